@@ -0,0 +1,107 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use kassandra::{cql::schema::Schema, session::SessionHandle, snapshot::DataSnapshots};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Deserialize;
+use stable_eyre::Result;
+use tokio_util::sync::CancellationToken;
+
+use crate::{dump, dump::DumpFormat, SessionSource};
+
+#[derive(Clone)]
+struct AdminState {
+    kassandra: SessionHandle,
+    source: Arc<SessionSource>,
+    metrics: PrometheusHandle,
+}
+
+/// Http endpoints to inspect and reset the node state without going through cql.
+pub async fn serve(
+    addr: SocketAddr,
+    kassandra: SessionHandle,
+    source: Arc<SessionSource>,
+    metrics: PrometheusHandle,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/health", get(health))
+        .route("/metrics", get(render_metrics))
+        .route("/snapshot", get(snapshot).post(export))
+        .route("/schema", get(schema))
+        .route("/reset", post(reset))
+        .with_state(AdminState {
+            kassandra,
+            source,
+            metrics,
+        });
+
+    tracing::info!(%addr, "Starting admin endpoint");
+    axum::Server::try_bind(&addr)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await?;
+
+    Ok(())
+}
+
+async fn health() -> &'static str {
+    "OK"
+}
+
+async fn render_metrics(State(state): State<AdminState>) -> String {
+    state.metrics.render()
+}
+
+async fn snapshot(State(state): State<AdminState>) -> Json<DataSnapshots> {
+    Json(state.kassandra.data_snapshot())
+}
+
+#[derive(Deserialize)]
+struct ExportRequest {
+    out: PathBuf,
+    #[serde(default)]
+    format: DumpFormat,
+}
+
+/// Dumps the live state the same way `kassandra-node export` does, responds with the files written
+async fn export(
+    State(state): State<AdminState>,
+    Json(request): Json<ExportRequest>,
+) -> (StatusCode, String) {
+    match dump::export(&state.kassandra, &request.out, request.format) {
+        Ok(written) => {
+            tracing::info!(output.path = %request.out.display(), keyspaces = written.len(), "Exported keyspaces");
+            let written = written.iter().map(|it| it.display().to_string());
+            (StatusCode::OK, written.collect::<Vec<_>>().join("\n"))
+        }
+        Err(error) => {
+            tracing::error!(?error, "Could not export keyspaces");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:#}"))
+        }
+    }
+}
+
+async fn schema(State(state): State<AdminState>) -> Json<Schema> {
+    Json(state.kassandra.schema_snapshot())
+}
+
+/// Replaces the state with the one node has started from
+async fn reset(State(state): State<AdminState>) -> (StatusCode, String) {
+    match state.source.load() {
+        Ok(kassandra) => {
+            state.kassandra.replace_with(kassandra);
+            tracing::info!("State was reset");
+            (StatusCode::OK, "OK".to_owned())
+        }
+        Err(error) => {
+            tracing::error!(?error, "Could not reset state");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{error:#}"))
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use kassandra::{cql::engine::kv::KeyspaceDump, session::SessionHandle};
+use serde::Deserialize;
+use stable_eyre::{eyre::Context, Result};
+
+/// Format of keyspace dumps, files are named `<keyspace>.<format>`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpFormat {
+    #[default]
+    Json,
+    Ron,
+}
+
+impl DumpFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            DumpFormat::Json => "json",
+            DumpFormat::Ron => "ron",
+        }
+    }
+}
+
+/// Writes every user keyspace into a file of its own in `out`, returns the files written
+pub fn export(kassandra: &SessionHandle, out: &Path, format: DumpFormat) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(out).with_context(|| format!("creating {}", out.display()))?;
+
+    let mut written = vec![];
+    for dump in kassandra.export_keyspaces() {
+        let path = out.join(format!("{}.{}", dump.schema.name, format.extension()));
+        let content = match format {
+            DumpFormat::Json => serde_json::to_string_pretty(&dump)?,
+            DumpFormat::Ron => ron::ser::to_string_pretty(&dump, Default::default())?,
+        };
+        std::fs::write(&path, content).with_context(|| format!("writing {}", path.display()))?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Loads every dump in `from`, the format of a file is told by its extension, other files are skipped
+pub fn import(kassandra: &SessionHandle, from: &Path) -> Result<Vec<String>> {
+    let mut paths = std::fs::read_dir(from)
+        .with_context(|| format!("reading {}", from.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    paths.sort();
+
+    let mut imported = vec![];
+    for path in paths {
+        let format = match path.extension().and_then(|it| it.to_str()) {
+            Some("json") => DumpFormat::Json,
+            Some("ron") => DumpFormat::Ron,
+            _ => continue,
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        let dump: KeyspaceDump = match format {
+            DumpFormat::Json => serde_json::from_str(&content)?,
+            DumpFormat::Ron => ron::from_str(&content)?,
+        };
+
+        let keyspace = dump.schema.name.clone();
+        kassandra
+            .import_keyspace(dump)
+            .with_context(|| format!("importing {}", path.display()))?;
+        imported.push(keyspace);
+    }
+
+    Ok(imported)
+}
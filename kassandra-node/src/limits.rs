@@ -0,0 +1,112 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use kassandra::{error::DbError, frame::response::error::Error};
+
+/// Limits of client requests, requests over them fail with `Overloaded` errors
+/// without being run, so backpressure handling of clients can be exercised.
+///
+/// Statements over the rate only fail for clients which started with `THROW_ON_OVERLOAD`,
+/// connections of the other ones stop reading requests until the rate allows them again.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    max_frame_size: Option<usize>,
+    max_in_flight: Option<usize>,
+    rate: Option<Arc<RateLimit>>,
+}
+
+/// What is done with a request, see [`Limits::check`]
+#[derive(Debug)]
+pub enum Verdict {
+    Run,
+    Fail(Error),
+    /// Check the request again after waiting
+    Wait(Duration),
+}
+
+/// Requests per second of the whole node, counted in windows of a second
+#[derive(Debug)]
+struct RateLimit {
+    per_second: u32,
+    window: Mutex<(Instant, u32)>,
+}
+
+impl Limits {
+    pub fn new(
+        max_frame_size: Option<usize>,
+        max_in_flight: Option<usize>,
+        max_requests_per_second: Option<u32>,
+    ) -> Self {
+        Self {
+            max_frame_size,
+            max_in_flight,
+            rate: max_requests_per_second.map(|per_second| {
+                Arc::new(RateLimit {
+                    per_second,
+                    window: Mutex::new((Instant::now(), 0)),
+                })
+            }),
+        }
+    }
+
+    /// Verdict of a request with a body of `size` bytes, received while a connection
+    /// has `in_flight` requests waiting for their responses.
+    ///
+    /// Only statements count towards the rate, so connections can still be established.
+    pub fn check(
+        &self,
+        size: usize,
+        in_flight: usize,
+        statement: bool,
+        throw_on_overload: bool,
+    ) -> Verdict {
+        if let Some(max) = self.max_frame_size.filter(|max| size > *max) {
+            return Verdict::Fail(Error::new(
+                DbError::Overloaded,
+                format!("Request is too big: length {size} exceeds maximum allowed length {max}"),
+            ));
+        }
+        if let Some(max) = self.max_in_flight.filter(|max| in_flight >= *max) {
+            return Verdict::Fail(Error::new(
+                DbError::Overloaded,
+                format!("Too many in-flight requests on the connection, at most {max} are allowed"),
+            ));
+        }
+        let Some(rate) = self.rate.as_ref().filter(|_| statement) else {
+            return Verdict::Run;
+        };
+        match rate.acquire() {
+            Ok(()) => Verdict::Run,
+            Err(_) if throw_on_overload => Verdict::Fail(Error::new(
+                DbError::Overloaded,
+                format!(
+                    "Request breached global limit of {} requests/second and triggered backpressure",
+                    rate.per_second
+                ),
+            )),
+            Err(wait) => Verdict::Wait(wait),
+        }
+    }
+}
+
+impl RateLimit {
+    /// Time until the next window, if the current one is full
+    fn acquire(&self) -> Result<(), Duration> {
+        const WINDOW: Duration = Duration::from_secs(1);
+
+        let mut window = self.window.lock().unwrap();
+        let (started, count) = &mut *window;
+        if started.elapsed() >= WINDOW {
+            *started = Instant::now();
+            *count = 0;
+        }
+        if *count >= self.per_second {
+            return Err(WINDOW.saturating_sub(started.elapsed()));
+        }
+        *count += 1;
+
+        Ok(())
+    }
+}
@@ -1,19 +1,69 @@
 use std::{
     io,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    net::SocketAddr,
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use clap::Parser;
-use futures_util::{SinkExt, StreamExt};
+use clap::{Parser, Subcommand};
+use dump::DumpFormat;
+use futures_util::{
+    future::BoxFuture, stream::FuturesUnordered, FutureExt, Sink, SinkExt, StreamExt,
+};
 use kassandra::{
-    frame::{request::Request, request_stream, response::Response, response_sink},
+    compat::CompatibilityProfile,
+    cql::{
+        engine::views::SystemViews, query::QueryString,
+        query_cache::DEFAULT_PREPARED_STATEMENTS_CAPACITY,
+    },
+    error::DbError,
+    frame::{
+        parse,
+        request::{Request, RequestOpcode},
+        request_stream,
+        response::{
+            authenticate::AuthSuccess,
+            error::{Error, ErrorRenderer},
+            supported::Features,
+            Response,
+        },
+        response_sink, FrameFlags,
+    },
+    policy::{BatchSizePolicy, LatencyPolicy, LatencyRule, LimitsPolicy, UnimplementedPolicy},
+    session::{ConnectionState, SessionConfig, SessionHandle, Topology},
     KassandraSession,
 };
-use stable_eyre::{eyre::Context, Result};
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use limits::{Limits, Verdict};
+use stable_eyre::{
+    eyre::{self, Context},
+    Result,
+};
+use tls::ClientAuth;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, ToSocketAddrs},
+    signal::unix::{signal, SignalKind},
+    task, time,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use uuid::Uuid;
 
+mod admin;
+mod dump;
+mod limits;
 mod logging;
+mod metrics;
+mod tls;
+
+const FRAME_HEADER_LEN: usize = 9;
+/// Requests a connection may have running or waiting for their responses, it isn't read while it has more
+const MAX_CONCURRENT_REQUESTS: usize = 1024;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -22,47 +72,504 @@ struct Args {
     #[arg(short, long, default_value_t = 9044)]
     port: u16,
 
+    /// Port of the http endpoint exposing `/health`, `/metrics`, `/snapshot`, `/schema` and `/reset`,
+    /// posting `{"out": "dir/", "format": "json"}` to `/snapshot` exports the live state the same as `export`
+    #[arg(long)]
+    admin_port: Option<u16>,
+
     /// Preload state from path
     #[arg(short, long, default_value = "./kass.data.ron")]
     data: PathBuf,
+
+    /// Cql script creating the initial state, used when there is no state file yet
+    #[arg(short, long)]
+    init: Option<PathBuf>,
+
+    /// Number of tokens advertised in `system.local`
+    #[arg(long, default_value_t = kassandra::session::DEFAULT_NUM_TOKENS)]
+    num_tokens: usize,
+
+    /// Number of virtual peers advertised in `system.peers`, peer `n` is at `127.0.0.{n + 1}`,
+    /// so drivers connect to this node again
+    #[arg(long, default_value_t = 0)]
+    peers: usize,
+
+    /// Server to pretend to be: `cassandra3`, `cassandra4` or `scylla5`,
+    /// sets the advertised versions, which `--release-version` overrides
+    #[arg(long)]
+    profile: Option<CompatibilityProfile>,
+
+    /// Shards advertised with the scylla extensions of `OPTIONS` responses, so shard-aware drivers
+    /// open a connection per shard, connections are assigned to the shards in turns
+    #[arg(long)]
+    scylla_shards: Option<NonZeroUsize>,
+
+    /// Port of shard-aware connections, which are served by the shard of their source port, `port % shards`,
+    /// requires `--scylla-shards`
+    #[arg(long, requires = "scylla_shards")]
+    scylla_shard_aware_port: Option<u16>,
+
+    /// Cluster name advertised in `system.local`
+    #[arg(long)]
+    cluster_name: Option<String>,
+
+    /// Datacenter every node is advertised in
+    #[arg(long)]
+    datacenter: Option<String>,
+
+    /// Rack every node is advertised in
+    #[arg(long)]
+    rack: Option<String>,
+
+    /// Release version advertised in `system.local` and `system.peers`
+    #[arg(long)]
+    release_version: Option<String>,
+
+    /// Tokens of this node, e.g. `-9223372036854775808,0`, instead of the ones dealt by `--num-tokens`
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+    tokens: Option<Vec<i64>>,
+
+    /// Host id of this node, peers get the following ones
+    #[arg(long)]
+    host_id: Option<Uuid>,
+
+    /// Number of prepared statements kept, the least recently used ones are evicted
+    #[arg(long, default_value_t = DEFAULT_PREPARED_STATEMENTS_CAPACITY)]
+    prepared_statements: NonZeroUsize,
+
+    /// Most rows a scan without paging may return, larger scans fail instead, `0` lets them return every row
+    #[arg(long, default_value_t = kassandra::session::DEFAULT_SCAN_LIMIT.get())]
+    scan_limit: usize,
+
+    /// What to do with statements using unimplemented features: `error`, `warn-and-ignore` or `panic`
+    #[arg(long, default_value_t = UnimplementedPolicy::Error)]
+    unimplemented: UnimplementedPolicy,
+
+    /// Reject writes missing parts of the primary key with the errors cassandra gives
+    #[arg(long)]
+    strict: bool,
+
+    /// Clients log in with a password and statements are checked against the permissions of their role,
+    /// the `cassandra` superuser has the password `cassandra`
+    #[arg(long)]
+    authentication: bool,
+
+    /// Messages of errors clients may match on: `kassandra`, or `cassandra4` for the ones of Apache Cassandra 4.x
+    #[arg(long, default_value_t = ErrorRenderer::Kassandra)]
+    error_messages: ErrorRenderer,
+
+    /// Delay responses to statements, e.g. `select=50ms`, `ks.users=1s` or `*=10ms`,
+    /// the first matching rule is applied
+    #[arg(long)]
+    latency: Vec<LatencyRule>,
+
+    /// Size in bytes of batches, which succeed with a warning
+    #[arg(long)]
+    batch_size_warn_threshold: Option<usize>,
+
+    /// Size in bytes of batches, which are rejected
+    #[arg(long)]
+    batch_size_fail_threshold: Option<usize>,
+
+    /// Reject names and values over the limits of cassandra, e.g. table names longer than 48 characters
+    #[arg(long)]
+    cassandra_limits: bool,
+
+    /// Largest request body accepted, larger requests fail with `Overloaded` errors
+    #[arg(long)]
+    max_frame_size: Option<usize>,
+
+    /// Responses a connection may have waiting to be sent, e.g. delayed by `--latency`,
+    /// requests over it fail with `Overloaded` errors
+    #[arg(long)]
+    max_in_flight: Option<usize>,
+
+    /// Statements per second the node runs, the ones over it fail with `Overloaded` errors
+    /// for clients started with `THROW_ON_OVERLOAD`, other clients wait for their turn
+    #[arg(long)]
+    max_requests_per_second: Option<u32>,
+
+    /// Seconds a connection may go without sending a request before it is closed,
+    /// `OPTIONS` heartbeats of drivers keep their connections open
+    #[arg(long)]
+    idle_timeout: Option<NonZeroU64>,
+
+    /// Certificate chain in pem format, enables tls
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key in pem format of the `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Certificate authorities in pem format client certificates are verified with
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Whether clients have to present a certificate
+    #[arg(long, value_enum, default_value_t = ClientAuth::None, requires = "tls_client_ca")]
+    tls_client_auth: ClientAuth,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Work with the `--data` state while the server is stopped, the server isn't started
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Dumps schema and data of every user keyspace, into a file per keyspace
+    Export {
+        #[arg(long, value_enum, default_value_t = DumpFormat::Json)]
+        format: DumpFormat,
+
+        /// Directory dumps are written to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Loads dumps written by `export` into the state, keyspaces of the same name are replaced
+    Import {
+        /// Directory with the dumps
+        #[arg(long)]
+        from: PathBuf,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     stable_eyre::install()?;
     logging::setup_telemetry("kassandra")?;
-    let Args { port, data } = Args::parse();
-
-    let state = std::fs::read(&data)
-        .map(Some)
-        .or_else(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                Ok(None)
-            } else {
-                Err(err)
-            }
-        })
-        .context("reading state")?;
+    let Args {
+        port,
+        admin_port,
+        data,
+        init,
+        num_tokens,
+        peers,
+        profile,
+        scylla_shards,
+        scylla_shard_aware_port,
+        cluster_name,
+        datacenter,
+        rack,
+        release_version,
+        tokens,
+        host_id,
+        prepared_statements,
+        scan_limit,
+        unimplemented,
+        strict,
+        authentication,
+        error_messages,
+        latency,
+        batch_size_warn_threshold,
+        batch_size_fail_threshold,
+        cassandra_limits,
+        max_frame_size,
+        max_in_flight,
+        max_requests_per_second,
+        idle_timeout,
+        tls_cert,
+        tls_key,
+        tls_client_ca,
+        tls_client_auth,
+        command,
+    } = Args::parse();
+    let idle_timeout = idle_timeout.map(|it| Duration::from_secs(it.get()));
+
+    let tls = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(
+            &cert,
+            &key,
+            tls_client_ca.as_deref(),
+            tls_client_auth,
+        )?),
+        _ => None,
+    };
+
+    let views = SystemViews::new();
+    views.set_setting("native_transport_port", port);
+    views.set_setting("num_tokens", num_tokens);
+    views.set_setting("peers", peers);
+    views.set_setting("prepared_statements_cache_size", prepared_statements);
+    views.set_setting("scan_limit", scan_limit);
+    views.set_setting("unimplemented", unimplemented);
+    views.set_setting("strict", strict);
+    views.set_setting(
+        "authenticator",
+        match authentication {
+            true => "PasswordAuthenticator",
+            false => "AllowAllAuthenticator",
+        },
+    );
+    views.set_setting("error_messages", error_messages);
+    views.set_setting("client_encryption_options_enabled", tls.is_some());
+    if let Some(threshold) = batch_size_warn_threshold {
+        views.set_setting("batch_size_warn_threshold", threshold);
+    }
+    if let Some(threshold) = batch_size_fail_threshold {
+        views.set_setting("batch_size_fail_threshold", threshold);
+    }
+    views.set_setting("cassandra_limits", cassandra_limits);
+    if let Some(max) = max_frame_size {
+        views.set_setting("native_transport_max_frame_size", max);
+    }
+    if let Some(max) = max_in_flight {
+        views.set_setting("native_transport_max_requests_per_connection", max);
+    }
+    if let Some(max) = max_requests_per_second {
+        views.set_setting("native_transport_max_requests_per_second", max);
+    }
+    if let Some(timeout) = idle_timeout {
+        views.set_setting(
+            "native_transport_idle_timeout",
+            format!("{}s", timeout.as_secs()),
+        );
+    }
+
+    let mut config = SessionConfig::new().with_topology(
+        Topology::new(num_tokens)
+            .with_peers(peers)
+            .with_native_port(port),
+    );
+    if let Some(profile) = profile {
+        config = config.with_profile(profile);
+    }
+
+    let source = Arc::new(SessionSource {
+        data,
+        init,
+        views,
+        features: Features {
+            profile,
+            throw_on_overload: true,
+            shards: scylla_shards,
+            shard_aware_port: scylla_shard_aware_port.filter(|_| tls.is_none()),
+            shard_aware_port_ssl: scylla_shard_aware_port.filter(|_| tls.is_some()),
+            ..Features::default()
+        },
+        config: session_config(
+            config,
+            cluster_name,
+            datacenter,
+            rack,
+            release_version,
+            tokens,
+            host_id,
+        ),
+        prepared_statements,
+        scan_limit: NonZeroUsize::new(scan_limit),
+        unimplemented,
+        strict,
+        authentication,
+        error_messages,
+        latency: latency
+            .into_iter()
+            .fold(LatencyPolicy::new(), LatencyPolicy::rule),
+        batch_size: BatchSizePolicy {
+            warn: batch_size_warn_threshold,
+            fail: batch_size_fail_threshold,
+        },
+        limits: if cassandra_limits {
+            LimitsPolicy::cassandra()
+        } else {
+            LimitsPolicy::new()
+        },
+    });
+
+    match command {
+        Some(Command::Export { format, out }) => {
+            let written = dump::export(&source.load()?.handle(), &out, format)?;
+            tracing::info!(output.path = %out.display(), keyspaces = written.len(), "Exported keyspaces");
+            return Ok(());
+        }
+        Some(Command::Import { from }) => {
+            let kassandra = source.load()?;
+            let imported = dump::import(&kassandra.handle(), &from)?;
+            std::fs::write(&source.data, kassandra.save_state()).context("saving state")?;
+            tracing::info!(input.path = %from.display(), ?imported, "Imported keyspaces");
+            return Ok(());
+        }
+        None => {}
+    }
 
-    let kassandra = state
-        .map(|it| KassandraSession::load_state(&it))
-        .transpose()?
-        .unwrap_or(KassandraSession::new());
     let addr = format!("0.0.0.0:{port}");
 
-    tracing::info!(%addr, "Starting kassandra node");
-    let server = Server::new(kassandra);
+    tracing::info!(%addr, tls = tls.is_some(), "Starting kassandra node");
+    let server = Server::new(
+        source.load()?.handle(),
+        tls,
+        source.views.clone(),
+        Limits::new(max_frame_size, max_in_flight, max_requests_per_second),
+        idle_timeout,
+    );
+    let mut serving = tokio::spawn(server.clone().serve(addr, Shards::InTurns));
+    let shard_aware = scylla_shard_aware_port.map(|port| {
+        tracing::info!(port, "Serving shard-aware connections");
+        tokio::spawn(
+            server
+                .clone()
+                .serve(format!("0.0.0.0:{port}"), Shards::BySourcePort),
+        )
+    });
+    let admin = match admin_port {
+        Some(port) => Some(tokio::spawn(admin::serve(
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            server.kassandra.clone(),
+            source.clone(),
+            metrics::install()?,
+            server.shutdown.clone(),
+        ))),
+        None => None,
+    };
 
-    tokio::select! {
-        _ = Server::serve(server.clone(), addr) => {},
-        _ = tokio::signal::ctrl_c() => {
-            tracing::info!(output.path = %data.display(), "Received SIG_TERM, saving state and closing server");
-            let kassandra = server.kassandra.lock().unwrap();
-            let state = kassandra.save_state();
-            std::fs::write(&data, state).context("saving state")?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    loop {
+        tokio::select! {
+            result = &mut serving => return result?,
+            _ = hangup.recv() => {
+                tracing::info!(input.path = %source.data.display(), "Received SIGHUP, reloading state");
+                match source.load() {
+                    Ok(kassandra) => server.kassandra.replace_with(kassandra),
+                    Err(error) => tracing::error!(?error, "Could not reload state, keeping the current one"),
+                }
+            }
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
         }
     }
 
+    tracing::info!("Shutting down, waiting for in-flight requests");
+    server.shutdown.cancel();
+    serving.await??;
+    if let Some(shard_aware) = shard_aware {
+        shard_aware.await??;
+    }
+    if let Some(admin) = admin {
+        admin.await??;
+    }
+
+    tracing::info!(output.path = %source.data.display(), "Saving state");
+    let state = server.kassandra.save_state();
+    std::fs::write(&source.data, state).context("saving state")?;
+
+    Ok(())
+}
+
+/// Values which aren't given are kept the way they are in `config`
+fn session_config(
+    mut config: SessionConfig,
+    cluster_name: Option<String>,
+    datacenter: Option<String>,
+    rack: Option<String>,
+    release_version: Option<String>,
+    tokens: Option<Vec<i64>>,
+    host_id: Option<Uuid>,
+) -> SessionConfig {
+    if let Some(cluster_name) = cluster_name {
+        config = config.with_cluster_name(cluster_name);
+    }
+    if let Some(datacenter) = datacenter {
+        config = config.with_datacenter(datacenter);
+    }
+    if let Some(rack) = rack {
+        config = config.with_rack(rack);
+    }
+    if let Some(release_version) = release_version {
+        config = config.with_release_version(release_version);
+    }
+    if let Some(tokens) = tokens {
+        config = config.with_tokens(tokens);
+    }
+    if let Some(host_id) = host_id {
+        config = config.with_host_id(host_id);
+    }
+
+    config
+}
+
+/// How sessions are created, on start and on `SIGHUP`
+struct SessionSource {
+    data: PathBuf,
+    init: Option<PathBuf>,
+    views: SystemViews,
+    config: SessionConfig,
+    features: Features,
+    prepared_statements: NonZeroUsize,
+    scan_limit: Option<NonZeroUsize>,
+    unimplemented: UnimplementedPolicy,
+    strict: bool,
+    authentication: bool,
+    error_messages: ErrorRenderer,
+    latency: LatencyPolicy,
+    batch_size: BatchSizePolicy,
+    limits: LimitsPolicy,
+}
+
+impl SessionSource {
+    /// State file takes precedence, init script is only run for fresh sessions
+    fn load(&self) -> Result<KassandraSession> {
+        let state = std::fs::read(&self.data)
+            .map(Some)
+            .or_else(|err| {
+                if err.kind() == io::ErrorKind::NotFound {
+                    Ok(None)
+                } else {
+                    Err(err)
+                }
+            })
+            .context("reading state")?;
+
+        if let Some(state) = state {
+            let kassandra =
+                KassandraSession::load_state(&state)?.with_unimplemented_policy(self.unimplemented);
+            kassandra.set_prepared_statements_capacity(self.prepared_statements);
+            kassandra.set_scan_limit(self.scan_limit);
+            kassandra.set_strict_mode(self.strict);
+            kassandra.set_authentication(self.authentication);
+            kassandra.set_error_renderer(self.error_messages);
+            kassandra.set_latency_policy(self.latency.clone());
+            kassandra.set_batch_size_policy(self.batch_size);
+            kassandra.set_limits_policy(self.limits);
+            kassandra.set_features(self.features.clone());
+            kassandra.set_system_views(self.views.clone());
+            return Ok(kassandra);
+        }
+
+        let mut kassandra = KassandraSession::with_config(self.config.clone())
+            .with_unimplemented_policy(self.unimplemented);
+        kassandra.set_prepared_statements_capacity(self.prepared_statements);
+        kassandra.set_scan_limit(self.scan_limit);
+        kassandra.set_strict_mode(self.strict);
+        kassandra.set_error_renderer(self.error_messages);
+        kassandra.set_latency_policy(self.latency.clone());
+        kassandra.set_batch_size_policy(self.batch_size);
+        kassandra.set_limits_policy(self.limits);
+        kassandra.set_features(self.features.clone());
+        kassandra.set_system_views(self.views.clone());
+        if let Some(init) = &self.init {
+            run_script(&mut kassandra, init)?;
+        }
+        // the init script runs as the session itself, before clients have to log in
+        kassandra.set_authentication(self.authentication);
+
+        Ok(kassandra)
+    }
+}
+
+fn run_script(kassandra: &mut KassandraSession, path: &Path) -> Result<()> {
+    let script = std::fs::read_to_string(path).context("reading init script")?;
+
+    for statement in script.split_inclusive(';') {
+        let statement = statement.trim();
+        if statement.is_empty() {
+            continue;
+        }
+        kassandra
+            .process_cql(statement)
+            .with_context(|| format!("running `{statement}`"))?;
+    }
+
     Ok(())
 }
 
@@ -77,48 +584,206 @@ macro_rules! span {
     };
 }
 
-#[derive(Clone, Debug)]
+/// How connections of a listener are assigned to the shards advertised by `--scylla-shards`
+#[derive(Debug, Clone, Copy)]
+enum Shards {
+    InTurns,
+    /// The way the shard-aware port of scylla does it
+    BySourcePort,
+}
+
+#[derive(Clone)]
 struct Server {
-    kassandra: Arc<Mutex<KassandraSession>>,
+    kassandra: SessionHandle,
+    tls: Option<TlsAcceptor>,
+    views: SystemViews,
+    limits: Limits,
+    /// Connections not sending requests for this long are closed
+    idle_timeout: Option<Duration>,
+    /// Connections accepted so far, they are assigned to the shards in turns
+    connections: Arc<AtomicUsize>,
+    shutdown: CancellationToken,
+    clients: TaskTracker,
 }
 
 impl Server {
-    fn new(kassandra: KassandraSession) -> Self {
+    fn new(
+        kassandra: SessionHandle,
+        tls: Option<TlsAcceptor>,
+        views: SystemViews,
+        limits: Limits,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
         Self {
-            kassandra: Arc::new(Mutex::new(kassandra)),
+            kassandra,
+            tls,
+            views,
+            limits,
+            idle_timeout,
+            connections: Arc::default(),
+            shutdown: CancellationToken::new(),
+            clients: TaskTracker::new(),
         }
     }
 
-    async fn serve(self, addr: impl ToSocketAddrs) -> Result<()> {
+    /// Accepts clients until shutdown is requested, then waits for them to finish in-flight requests.
+    async fn serve(self, addr: impl ToSocketAddrs, shards: Shards) -> Result<()> {
         let listen = TcpListener::bind(addr).await?;
 
         loop {
-            let Ok((stream, addr)) = listen.accept().await else {
+            let accepted = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                accepted = listen.accept() => accepted,
+            };
+            let Ok((stream, addr)) = accepted else {
                 continue;
             };
             tracing::info!(%addr, "New client");
+            let shard = match shards {
+                Shards::InTurns => self.connections.fetch_add(1, Ordering::Relaxed),
+                Shards::BySourcePort => addr.port().into(),
+            };
 
-            tokio::task::spawn(self.clone().client(stream));
+            let server = self.clone();
+            self.clients.spawn(async move {
+                match server.tls.clone() {
+                    Some(tls) => match tls.accept(stream).await {
+                        Ok(stream) => server.client(stream, addr, shard).await,
+                        Err(error) => {
+                            tracing::warn!(%addr, ?error, "Tls handshake failed");
+                            Ok(())
+                        }
+                    },
+                    None => server.client(stream, addr, shard).await,
+                }
+            });
         }
+
+        self.clients.close();
+        self.clients.wait().await;
+
+        Ok(())
     }
 
-    async fn client(mut self, mut stream: TcpStream) -> Result<()> {
-        let (mut read, mut write) = stream.split();
+    async fn client(
+        self,
+        stream: impl AsyncRead + AsyncWrite,
+        addr: SocketAddr,
+        shard: usize,
+    ) -> Result<()> {
+        let client = self.views.connect(addr, self.tls.is_some());
+        let (mut read, write) = tokio::io::split(stream);
+        let (mut write, written) = metrics::Counted::new(write);
         let mut stream = request_stream(&mut read);
         let mut sink = response_sink(&mut write);
-        while let Some(frame) = stream.next().await {
+        let mut connection = ConnectionState::new()
+            .with_streamed_rows()
+            .with_shard(shard);
+        // responses wait here for their requests to run and for the latency policy,
+        // they are sent as soon as they are due, in whatever order that is
+        let mut in_flight = FuturesUnordered::<BoxFuture<'static, Result<InFlight>>>::new();
+        let mut last_request = time::Instant::now();
+        loop {
+            // connections waiting for responses are not idle
+            let idle_until = self
+                .idle_timeout
+                .filter(|_| in_flight.is_empty())
+                .map(|timeout| last_request + timeout);
+            let frame = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                Some(response) = in_flight.next(), if !in_flight.is_empty() => {
+                    let mut response = response?;
+                    if let Some(keyspace) = response.keyspace.take() {
+                        connection.use_keyspace(keyspace);
+                    }
+                    response.send(&mut sink, &written).await?;
+                    continue;
+                }
+                _ = time::sleep_until(idle_until.unwrap_or_else(time::Instant::now)), if idle_until.is_some() => {
+                    tracing::info!(%addr, "Closing idle connection");
+                    break;
+                }
+                // backpressure, the connection isn't read while it has too many requests running
+                frame = stream.next(), if in_flight.len() < MAX_CONCURRENT_REQUESTS => frame,
+            };
+            last_request = time::Instant::now();
+            let Some(frame) = frame else {
+                break;
+            };
             match frame {
                 Ok((frame, opcode, data)) => {
                     tracing::debug!(?frame, ?opcode, data.len = data.len(), "New message");
+                    let started = Instant::now();
+                    metrics::request(opcode, FRAME_HEADER_LEN + data.len());
                     if frame.version.is_unsupported() {
                         sink.send((Response::unsupported_version(), frame.stream))
                             .await?;
                         continue;
                     }
 
-                    let request = Request::deserialize(opcode, &data, frame.flags)?;
-                    let response = self.request(request)?;
-                    sink.send((response, frame.stream)).await?;
+                    let statement = matches!(
+                        opcode,
+                        RequestOpcode::Query
+                            | RequestOpcode::Prepare
+                            | RequestOpcode::Execute
+                            | RequestOpcode::Batch
+                    );
+                    let rejected = loop {
+                        match self.limits.check(
+                            data.len(),
+                            in_flight.len(),
+                            statement,
+                            connection.throw_on_overload(),
+                        ) {
+                            Verdict::Run => break None,
+                            Verdict::Fail(error) => break Some(error),
+                            // backpressure, the connection isn't read meanwhile
+                            Verdict::Wait(wait) => time::sleep(wait).await,
+                        }
+                    };
+                    let response = if statement && !self.is_use(opcode, &data) {
+                        // statements run at once, each on a copy of the connection,
+                        // so a slow one doesn't hold up the others
+                        let server = self.clone();
+                        let mut statement_connection = connection.clone();
+                        let running = task::spawn_blocking(move || {
+                            let keyspace = statement_connection.keyspace().map(ToOwned::to_owned);
+                            let (response, latency) = server.run(
+                                &mut statement_connection,
+                                opcode,
+                                &data,
+                                frame.flags,
+                                rejected,
+                            )?;
+                            let mut response =
+                                InFlight::new(response, frame.stream, opcode, started);
+                            // executed `USE` applies to the connection once it is answered
+                            if statement_connection.keyspace() != keyspace.as_deref() {
+                                response.keyspace =
+                                    statement_connection.keyspace().map(ToOwned::to_owned);
+                            }
+
+                            eyre::Ok((response, latency))
+                        });
+                        async move {
+                            let (response, latency) = running.await??;
+                            response.after(latency).await
+                        }
+                        .boxed()
+                    } else {
+                        // requests changing the connection, like `STARTUP` or `USE`, run in the order they came,
+                        // so statements pipelined after them see the change
+                        let (response, latency) =
+                            self.run(&mut connection, opcode, &data, frame.flags, rejected)?;
+                        InFlight::new(response, frame.stream, opcode, started)
+                            .after(latency)
+                            .boxed()
+                    };
+                    if opcode == RequestOpcode::Startup {
+                        client.startup(frame.version.to_request().into(), connection.options());
+                    }
+                    client.request(connection.keyspace());
+                    in_flight.push(response);
                 }
                 Err(er) => {
                     tracing::error!(?er, "Could not read frame");
@@ -127,28 +792,96 @@ impl Server {
             }
         }
 
+        // requests already read still get their responses
+        while let Some(response) = in_flight.next().await {
+            response?.send(&mut sink, &written).await?;
+        }
+        // flushed and shut down, so clients see the connection closed rather than a dead socket
+        sink.close().await?;
+
         Ok(())
     }
 
-    fn request(&mut self, request: Request) -> Result<Response> {
+    /// Runs a request unless it was rejected by the limits, along with the latency its response is delayed by
+    fn run(
+        &self,
+        connection: &mut ConnectionState,
+        opcode: RequestOpcode,
+        data: &[u8],
+        flags: FrameFlags,
+        rejected: Option<Error>,
+    ) -> Result<(Response, Duration)> {
+        if let Some(error) = rejected {
+            return Ok((Response::Error(error), Duration::ZERO));
+        }
+
+        match Request::deserialize(opcode, data, flags) {
+            Ok(request) => {
+                let latency = self.kassandra.latency(connection, &request);
+                let response = self
+                    .request(connection, request)?
+                    .with_warnings(connection.take_warnings());
+                Ok((response, latency))
+            }
+            Err(error)
+                if opcode == RequestOpcode::Query && error.error == DbError::Unimplemented =>
+            {
+                // statement itself was read fine, it just can't be parsed
+                let statement = parse::long_string(data).map_or("", |(_, statement)| statement);
+                let response = match self.kassandra.handle_unimplemented(statement, error) {
+                    Ok(res) => Response::Result(res),
+                    Err(er) => Response::Error(er),
+                };
+                Ok((response, Duration::ZERO))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Whether the request runs a `USE` query, which changes the keyspace of the statements after it,
+    /// either directly or as a prepared statement.
+    ///
+    /// Only the statement of a query or the id of an execute is read, requests are parsed once they run.
+    fn is_use(&self, opcode: RequestOpcode, data: &[u8]) -> bool {
+        match opcode {
+            RequestOpcode::Query => {
+                parse::long_string(data).is_ok_and(|(_, statement)| starts_with_use(statement))
+            }
+            RequestOpcode::Execute => parse::short_bytes(data)
+                .ok()
+                .and_then(|(_, id)| self.kassandra.prepared_statement(id))
+                .is_some_and(|statement| matches!(statement, QueryString::Use { .. })),
+            _ => false,
+        }
+    }
+
+    fn request(&self, connection: &mut ConnectionState, request: Request) -> Result<Response> {
         use tracing::field::Empty;
         match request {
             Request::StartUp(options) => {
                 let span = span!("StartUp");
                 let _span = span.enter();
                 tracing::trace!(?options, "Starting client");
-                Ok(Response::Ready)
+                Ok(match self.kassandra.startup_in(connection, options) {
+                    Ok(()) => self.kassandra.startup_response(),
+                    Err(er) => {
+                        span.record("error", true);
+                        Response::Error(er)
+                    }
+                })
             }
             Request::Options => {
                 let span = span!("Options");
                 let _span = span.enter();
-                Ok(Response::options())
+                Ok(Response::supported(
+                    &self.kassandra.features(),
+                    connection.shard(),
+                ))
             }
             Request::Query(query) => {
                 let span = span!("Query");
                 let _span = span.enter();
-                let mut kass = self.kassandra.lock().unwrap();
-                Ok(match kass.process(query) {
+                Ok(match self.kassandra.process_in(connection, query) {
                     Ok(res) => Response::Result(res),
                     Err(er) => {
                         span.record("error", true);
@@ -159,8 +892,7 @@ impl Server {
             Request::Prepare(q) => {
                 let span = span!("Prepare");
                 let _span = span.enter();
-                let mut kass = self.kassandra.lock().unwrap();
-                Ok(match kass.prepare(q) {
+                Ok(match self.kassandra.prepare_in(connection, q) {
                     Ok(res) => Response::Result(res),
                     Err(er) => {
                         span.record("error", true);
@@ -171,8 +903,7 @@ impl Server {
             Request::Execute(e) => {
                 let span = span!("Execute");
                 let _span = span.enter();
-                let mut kass = self.kassandra.lock().unwrap();
-                Ok(match kass.execute(e) {
+                Ok(match self.kassandra.execute_in(connection, e) {
                     Ok(res) => Response::Result(res),
                     Err(er) => {
                         span.record("error", true);
@@ -190,8 +921,7 @@ impl Server {
             Request::Batch(b) => {
                 let span = span!("Batch");
                 let _span = span.enter();
-                let mut kass = self.kassandra.lock().unwrap();
-                Ok(match kass.process_batch(b) {
+                Ok(match self.kassandra.process_batch_in(connection, b) {
                     Ok(res) => Response::Result(res),
                     Err(er) => {
                         span.record("error", true);
@@ -199,7 +929,85 @@ impl Server {
                     }
                 })
             }
-            Request::AuthResponse => unimplemented!(),
+            Request::AuthResponse { token } => {
+                let span = span!("AuthResponse");
+                let _span = span.enter();
+                Ok(match self.kassandra.authenticate_in(connection, token) {
+                    Ok(()) => Response::AuthSuccess(AuthSuccess {
+                        success_message: None,
+                    }),
+                    Err(er) => {
+                        span.record("error", true);
+                        Response::Error(er)
+                    }
+                })
+            }
+            Request::Other { opcode, body } => {
+                let span = span!("Other");
+                let _span = span.enter();
+                tracing::debug!(opcode, "Request with an unknown opcode");
+                let response = self.kassandra.opcode_in(connection, opcode, body);
+                if matches!(response, Response::Error(_)) {
+                    span.record("error", true);
+                }
+
+                Ok(response)
+            }
         }
     }
 }
+
+/// `USE` keyword leading the statement, whatever its case, the way the parser reads it
+fn starts_with_use(statement: &str) -> bool {
+    let statement = statement.trim_start();
+    statement
+        .get(..3)
+        .is_some_and(|it| it.eq_ignore_ascii_case("use"))
+        && statement[3..].starts_with(|c: char| c.is_whitespace() || c == '"')
+}
+
+/// Response of a request, which was run but isn't sent yet
+struct InFlight {
+    response: Response,
+    stream: i16,
+    opcode: RequestOpcode,
+    started: Instant,
+    streamed_rows: Option<Arc<AtomicUsize>>,
+    /// Keyspace the request switched the connection to
+    keyspace: Option<String>,
+}
+
+impl InFlight {
+    fn new(response: Response, stream: i16, opcode: RequestOpcode, started: Instant) -> Self {
+        Self {
+            streamed_rows: metrics::response(&response),
+            response,
+            stream,
+            opcode,
+            started,
+            keyspace: None,
+        }
+    }
+
+    /// Waits until the response is due, `latency` after the request was received
+    async fn after(self, latency: Duration) -> Result<Self> {
+        time::sleep_until(time::Instant::from_std(self.started) + latency).await;
+        Ok(self)
+    }
+
+    async fn send(
+        self,
+        sink: &mut (impl Sink<(Response, i16), Error = eyre::Report> + Unpin),
+        written: &AtomicUsize,
+    ) -> Result<()> {
+        sink.send((self.response, self.stream)).await?;
+        metrics::response_sent(
+            self.opcode,
+            written.swap(0, Ordering::Relaxed),
+            self.started.elapsed(),
+            self.streamed_rows,
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use kassandra::frame::{
+    request::RequestOpcode,
+    response::{result::QueryResult, Response},
+};
+use metrics::{counter, describe_counter, describe_histogram, histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use stable_eyre::Result;
+use tokio::io::AsyncWrite;
+
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Installs the global prometheus recorder, its handle renders `/metrics` of the admin endpoint.
+pub fn install() -> Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("seconds".to_owned()),
+            &[
+                0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25,
+                0.5, 1.0,
+            ],
+        )?
+        .set_buckets_for_metric(
+            Matcher::Suffix("bytes".to_owned()),
+            &[
+                64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+            ],
+        )?
+        .set_buckets_for_metric(
+            Matcher::Full("kassandra_rows_returned".to_owned()),
+            &[0.0, 1.0, 10.0, 100.0, 1000.0, 10000.0],
+        )?
+        .install_recorder()?;
+
+    describe_counter!("kassandra_requests_total", "Requests received, by opcode");
+    describe_counter!("kassandra_errors_total", "Error responses, by error code");
+    describe_histogram!(
+        "kassandra_rows_returned",
+        "Number of rows in responses returning rows"
+    );
+    describe_histogram!(
+        "kassandra_request_size_bytes",
+        Unit::Bytes,
+        "Size of request frames"
+    );
+    describe_histogram!(
+        "kassandra_response_size_bytes",
+        Unit::Bytes,
+        "Size of response frames"
+    );
+    describe_histogram!(
+        "kassandra_request_duration_seconds",
+        Unit::Seconds,
+        "Time from reading a request to sending its response, by opcode"
+    );
+
+    // histograms are only drained on render otherwise, which may never be requested
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+
+    Ok(handle)
+}
+
+pub fn request(opcode: RequestOpcode, size: usize) {
+    let opcode = format!("{opcode:?}");
+    counter!("kassandra_requests_total", "opcode" => opcode.clone()).increment(1);
+    histogram!("kassandra_request_size_bytes", "opcode" => opcode).record(size as f64);
+}
+
+/// Streamed rows are only counted once they are sent, so their counter is returned for [`response_sent`]
+pub fn response(response: &Response) -> Option<Arc<AtomicUsize>> {
+    match response {
+        Response::Error(error) => {
+            let code = format!("{:#06x}", error.error.code());
+            counter!("kassandra_errors_total", "code" => code).increment(1);
+        }
+        Response::Result(QueryResult::Rows(rows)) => {
+            histogram!("kassandra_rows_returned").record(rows.rows.len() as f64);
+        }
+        Response::Result(QueryResult::RowStream(stream)) => return Some(stream.rows_written()),
+        _ => {}
+    }
+
+    None
+}
+
+pub fn response_sent(
+    opcode: RequestOpcode,
+    size: usize,
+    elapsed: Duration,
+    streamed_rows: Option<Arc<AtomicUsize>>,
+) {
+    if let Some(rows) = streamed_rows {
+        histogram!("kassandra_rows_returned").record(rows.load(Ordering::Relaxed) as f64);
+    }
+
+    let opcode = format!("{opcode:?}");
+    histogram!("kassandra_response_size_bytes", "opcode" => opcode.clone()).record(size as f64);
+    histogram!("kassandra_request_duration_seconds", "opcode" => opcode)
+        .record(elapsed.as_secs_f64());
+}
+
+/// Counts bytes written through it, so sizes of encoded frames can be recorded
+pub struct Counted<W> {
+    inner: W,
+    written: Arc<AtomicUsize>,
+}
+
+impl<W> Counted<W> {
+    pub fn new(inner: W) -> (Self, Arc<AtomicUsize>) {
+        let written = Arc::new(AtomicUsize::new(0));
+        let counted = Self {
+            inner,
+            written: written.clone(),
+        };
+
+        (counted, written)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Counted<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written.fetch_add(written, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
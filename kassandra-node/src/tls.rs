@@ -0,0 +1,75 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+use clap::ValueEnum;
+use stable_eyre::{
+    eyre::{eyre, Context},
+    Result,
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::CertificateDer, server::WebPkiClientVerifier, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+/// Whether clients have to present a certificate signed by `--tls-client-ca`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ClientAuth {
+    /// Client certificates are not requested
+    #[default]
+    None,
+    /// Client certificates are verified if presented
+    Optional,
+    /// Connections without a valid client certificate are rejected
+    Required,
+}
+
+pub fn acceptor(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+    client_auth: ClientAuth,
+) -> Result<TlsAcceptor> {
+    let certs = load_certs(cert)?;
+    let key = rustls_pemfile::private_key(&mut open(key)?)?
+        .ok_or_else(|| eyre!("No private key found in {}", key.display()))?;
+
+    let builder = ServerConfig::builder();
+    let builder = match (client_auth, client_ca) {
+        (ClientAuth::None, _) => builder.with_no_client_auth(),
+        (_, None) => return Err(eyre!("Client authentication requires `--tls-client-ca`")),
+        (auth, Some(ca)) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = match auth {
+                ClientAuth::Optional => verifier.allow_unauthenticated().build()?,
+                _ => verifier.build()?,
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+
+    let config = builder
+        .with_single_cert(certs, key)
+        .context("invalid tls certificate or key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(eyre!("No certificates found in {}", path.display()));
+    }
+
+    Ok(certs)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+
+    Ok(BufReader::new(file))
+}
@@ -0,0 +1,382 @@
+use std::{collections::HashMap, path::Path, str::FromStr, sync::Arc, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use kassandra::{
+    error::{DbError, WriteType},
+    frame::{
+        consistency::{Consistency, LegacyConsistency},
+        parse,
+        request::{
+            batch::{BatchStatement, BatchType},
+            Request, RequestOpcode,
+        },
+        response::{error::Error, ResponseOpcode},
+    },
+};
+use parking_lot::Mutex;
+use stable_eyre::eyre::{self, bail, eyre, Context};
+
+use super::{translator::PreparedQueryTranslator, CassandraRequest, CassandraResponse};
+
+/// Faults injected into responses before they are forwarded to the client.
+///
+/// Rules are checked in order, the first matched rule which passes the probability roll is applied.
+/// Only statements (`QUERY`, `EXECUTE` and `BATCH`) are affected, so connections can always be established.
+#[derive(Debug, Clone, Default)]
+pub struct Chaos {
+    rules: Vec<ChaosRule>,
+}
+
+/// Rule written as comma separated `key=value` pairs:
+/// `error=write-timeout,p=0.01,match=^(INSERT|UPDATE|DELETE)`.
+///
+/// The action is one of `delay=<duration>`, `drop` or `error=<kind>`, where kind is one of
+/// `write-timeout`, `read-timeout`, `unavailable` or `overloaded`.
+/// `p` is the probability the rule is applied with, defaults to 1.
+/// `match` is a pattern statements are matched against, it has to be the last key, so it can contain commas.
+#[derive(Debug, Clone)]
+pub struct ChaosRule {
+    action: Action,
+    probability: f64,
+    pattern: Option<regex::Regex>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Delay(Duration),
+    Drop,
+    Error(ErrorKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    WriteTimeout,
+    ReadTimeout,
+    Unavailable,
+    Overloaded,
+}
+
+/// Decided fault for an in-flight request
+#[derive(Debug, Clone)]
+enum Fault {
+    Delay(Duration),
+    Drop,
+    Replace(Bytes),
+}
+
+/// Faults of requests waiting for the response, keyed by stream id of a single connection.
+#[derive(Debug, Clone, Default)]
+pub struct PendingFaults(Arc<Mutex<HashMap<i16, Fault>>>);
+
+impl Chaos {
+    pub fn new(rules: Vec<ChaosRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Reads rules from json file, containing a list of rules in the same format as the cli accepts.
+    pub fn load_rules(path: &Path) -> eyre::Result<Vec<ChaosRule>> {
+        let content = std::fs::read(path).wrap_err("while reading chaos config")?;
+        let rules: Vec<String> = serde_json::from_slice(&content)?;
+
+        rules.iter().map(|rule| rule.parse()).collect()
+    }
+
+    /// Decides what happens with the response to the request.
+    pub fn intercept_request(
+        &self,
+        request: &CassandraRequest,
+        translator: &PreparedQueryTranslator,
+        pending: &PendingFaults,
+    ) {
+        if self.rules.is_empty() {
+            return;
+        }
+        let Some(statement) = Statement::describe(request, translator) else {
+            return;
+        };
+
+        let rule = self.rules.iter().find(|rule| {
+            rule.pattern
+                .as_ref()
+                .is_none_or(|it| it.is_match(&statement.text))
+                && rand::random::<f64>() < rule.probability
+        });
+
+        if let Some(rule) = rule {
+            tracing::info!(action = ?rule.action, statement = statement.text, "Injecting fault");
+            let fault = match rule.action {
+                Action::Delay(delay) => Fault::Delay(delay),
+                Action::Drop => Fault::Drop,
+                Action::Error(kind) => Fault::Replace(statement.error(kind)),
+            };
+            pending.0.lock().insert(request.0.stream, fault);
+        }
+    }
+
+    /// Applies decided fault to the response, `None` means response must not be forwarded.
+    ///
+    /// Delays hold back following responses of the same connection as well.
+    pub async fn intercept_response(
+        pending: &PendingFaults,
+        response: CassandraResponse,
+    ) -> Option<CassandraResponse> {
+        let fault = pending.0.lock().remove(&response.0.stream);
+
+        match fault {
+            None => Some(response),
+            Some(Fault::Delay(delay)) => {
+                tokio::time::sleep(delay).await;
+                Some(response)
+            }
+            Some(Fault::Drop) => None,
+            Some(Fault::Replace(body)) => Some((response.0, ResponseOpcode::Error, body)),
+        }
+    }
+}
+
+struct Statement {
+    text: String,
+    consistency: Consistency,
+    write_type: WriteType,
+}
+
+impl Statement {
+    fn describe(
+        (frame, opcode, body): &CassandraRequest,
+        translator: &PreparedQueryTranslator,
+    ) -> Option<Self> {
+        if !matches!(
+            opcode,
+            RequestOpcode::Query | RequestOpcode::Execute | RequestOpcode::Batch
+        ) {
+            return None;
+        }
+
+        match Request::deserialize(*opcode, body.as_ref(), frame.flags) {
+            Ok(Request::Query(query)) => Some(Self {
+                text: query.query.to_string(),
+                consistency: query.parameters.consistency,
+                write_type: WriteType::Simple,
+            }),
+            Ok(Request::Execute(execute)) => Some(Self {
+                text: translator.translate(execute.id).ok()?.to_string(),
+                consistency: execute.parameters.consistency,
+                write_type: WriteType::Simple,
+            }),
+            Ok(Request::Batch(batch)) => Some(Self {
+                text: batch
+                    .statements
+                    .iter()
+                    .filter_map(|statement| match statement {
+                        BatchStatement::Prepared { id, .. } => translator.translate(id).ok(),
+                        BatchStatement::Query { query, .. } => Some(*query.clone()),
+                    })
+                    .map(|it| it.to_string())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                consistency: batch.consistency,
+                write_type: match batch.batch_type {
+                    BatchType::Logged => WriteType::Batch,
+                    BatchType::Unlogged => WriteType::UnloggedBatch,
+                    BatchType::Counter => WriteType::Counter,
+                },
+            }),
+            // statements kassandra can't parse are still matched by their raw text
+            _ if *opcode == RequestOpcode::Query => Some(Self {
+                text: parse::long_string(body.as_ref()).ok()?.1.to_owned(),
+                consistency: Consistency::One,
+                write_type: WriteType::Simple,
+            }),
+            _ => None,
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> Bytes {
+        let consistency = LegacyConsistency::Regular(self.consistency);
+        let error = match kind {
+            ErrorKind::WriteTimeout => DbError::WriteTimeout {
+                consistency,
+                received: 0,
+                required: 1,
+                write_type: self.write_type.clone(),
+            },
+            ErrorKind::ReadTimeout => DbError::ReadTimeout {
+                consistency,
+                received: 0,
+                required: 1,
+                data_present: false,
+            },
+            ErrorKind::Unavailable => DbError::Unavailable {
+                consistency,
+                required: 1,
+                alive: 0,
+            },
+            ErrorKind::Overloaded => DbError::Overloaded,
+        };
+
+        let mut buf = BytesMut::new();
+        Error::new(error, "Fault injected by kassandra-proxy").serialize(&mut buf);
+        buf.freeze()
+    }
+}
+
+impl FromStr for ChaosRule {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (options, pattern) = match s.split_once("match=") {
+            Some((options, pattern)) => (options.trim_end_matches(','), Some(pattern)),
+            None => (s, None),
+        };
+
+        let mut action = None;
+        let mut probability = 1.0;
+        for option in options
+            .split(',')
+            .map(str::trim)
+            .filter(|it| !it.is_empty())
+        {
+            let (key, value) = option.split_once('=').unwrap_or((option, ""));
+            match key {
+                "delay" => action = Some(Action::Delay(parse_duration(value)?)),
+                "drop" => action = Some(Action::Drop),
+                "error" => action = Some(Action::Error(value.parse()?)),
+                "p" => {
+                    probability = value
+                        .parse()
+                        .wrap_err_with(|| format!("invalid probability `{value}`"))?
+                }
+                _ => bail!("unknown chaos rule option `{key}`"),
+            }
+        }
+
+        Ok(Self {
+            action: action.ok_or_else(|| eyre!("chaos rule `{s}` has no action"))?,
+            probability,
+            pattern: pattern.map(regex::Regex::new).transpose()?,
+        })
+    }
+}
+
+impl FromStr for ErrorKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "write-timeout" => Ok(Self::WriteTimeout),
+            "read-timeout" => Ok(Self::ReadTimeout),
+            "unavailable" => Ok(Self::Unavailable),
+            "overloaded" => Ok(Self::Overloaded),
+            _ => bail!("unknown error kind `{s}`"),
+        }
+    }
+}
+
+/// Accepts `<n>ms`, `<n>s` or just number of milliseconds
+fn parse_duration(value: &str) -> eyre::Result<Duration> {
+    let invalid = || eyre!("invalid duration `{value}`");
+
+    if let Some(ms) = value.strip_suffix("ms") {
+        Ok(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+    } else if let Some(s) = value.strip_suffix('s') {
+        let secs = s.parse().map_err(|_| invalid())?;
+        Duration::try_from_secs_f64(secs).map_err(|_| invalid())
+    } else {
+        Ok(Duration::from_millis(value.parse().map_err(|_| invalid())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_duration, Action, ChaosRule, ErrorKind};
+
+    fn rule(s: &str) -> ChaosRule {
+        s.parse().unwrap()
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<ChaosRule>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_actions() {
+        assert_eq!(rule("drop").action, Action::Drop);
+        assert_eq!(
+            rule("delay=250ms").action,
+            Action::Delay(Duration::from_millis(250))
+        );
+        for (kind, expected) in [
+            ("write-timeout", ErrorKind::WriteTimeout),
+            ("read-timeout", ErrorKind::ReadTimeout),
+            ("unavailable", ErrorKind::Unavailable),
+            ("overloaded", ErrorKind::Overloaded),
+        ] {
+            assert_eq!(
+                rule(&format!("error={kind}")).action,
+                Action::Error(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn probability_defaults_to_one() {
+        assert_eq!(rule("drop").probability, 1.0);
+        assert_eq!(rule("drop,p=0.25").probability, 0.25);
+        assert_eq!(rule(" p=0.5 , drop ").probability, 0.5);
+    }
+
+    #[test]
+    fn pattern_may_contain_commas() {
+        let rule = rule("error=write-timeout,p=0.01,match=^(INSERT|UPDATE|DELETE), x");
+        assert_eq!(rule.action, Action::Error(ErrorKind::WriteTimeout));
+        assert_eq!(rule.probability, 0.01);
+        let pattern = rule.pattern.unwrap();
+        assert_eq!(pattern.as_str(), "^(INSERT|UPDATE|DELETE), x");
+        assert!(pattern.is_match("UPDATE, x"));
+        assert!(!pattern.is_match("SELECT, x"));
+    }
+
+    #[test]
+    fn last_action_wins() {
+        assert_eq!(
+            rule("drop,delay=1s").action,
+            Action::Delay(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("15ms").unwrap(), Duration::from_millis(15));
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("100").unwrap(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for value in ["", "fast", "5m", "1.5ms", "-1s", "-10", "NaNs"] {
+            assert_eq!(
+                parse_duration(value).unwrap_err().to_string(),
+                format!("invalid duration `{value}`"),
+            );
+        }
+        assert_eq!(error("delay=1h"), "invalid duration `1h`");
+    }
+
+    #[test]
+    fn rejects_unknown_error_kinds() {
+        assert_eq!(error("error=timeout"), "unknown error kind `timeout`");
+        assert_eq!(error("error"), "unknown error kind ``");
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert_eq!(error("drop,retry=3"), "unknown chaos rule option `retry`");
+        assert_eq!(error("p=0.5"), "chaos rule `p=0.5` has no action");
+        assert_eq!(error(""), "chaos rule `` has no action");
+        assert_eq!(error("drop,p=often"), "invalid probability `often`");
+        assert!("drop,match=(".parse::<ChaosRule>().is_err());
+    }
+}
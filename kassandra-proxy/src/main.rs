@@ -1,14 +1,18 @@
 use std::{
     net::{SocketAddr, ToSocketAddrs},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
 use broadcast_sink::BroadcastSink;
 use bytes::Bytes;
-use clap::Parser;
-use futures::{Sink, Stream};
+use chaos::{Chaos, ChaosRule, PendingFaults};
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use futures::{Sink, Stream, StreamExt};
 use futures_util::SinkExt;
 use kassandra::{
+    cql::query::QueryString,
     frame::{
         raw_request_sink, raw_response_sink,
         request::{batch::BatchStatement, Request, RequestOpcode},
@@ -18,34 +22,135 @@ use kassandra::{
     },
     session::KassandraSession,
 };
+use query_log::QueryLog;
+use record::Recorder;
+use redact::{RedactRule, Redaction};
 use replay::ReplayInterceptor;
 use stable_eyre::eyre::{self, Context};
+use tls::{Connection, UpstreamTls};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    net::{TcpListener, TcpStream},
+    net::TcpListener,
     sync::broadcast::{self, Receiver},
 };
+use tokio_rustls::TlsAcceptor;
 use translator::PreparedQueryTranslator;
+use upstream::{Routing, Upstreams};
 
 mod broadcast_sink;
+mod chaos;
 mod logging;
+mod query_log;
+mod record;
+mod redact;
 mod replay;
+mod tls;
 mod translator;
+mod upstream;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(author, version, about, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    proxy: ProxyArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Replay recorded traffic into a fresh session and print the resulting data snapshot
+    Replay {
+        /// Traffic file written with `--record`
+        path: PathBuf,
+
+        /// Write the snapshot to the file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Also write the replay log of the traffic, a fixture `kassandra::replay::ReplayLog` can apply
+        #[arg(long)]
+        log: Option<PathBuf>,
+    },
+}
+
+#[derive(ClapArgs, Debug)]
+struct ProxyArgs {
     /// Port to listen connections for
     #[arg(short, long, default_value_t = 9044)]
     port: u16,
 
-    /// Port of upstream cassandra
-    #[arg(short, long, default_value_t = 9042)]
-    upstream: u16,
+    /// Upstream cassandra node, either `host:port` or a port of a local node.
+    /// Can be repeated to proxy to several nodes of a cluster
+    #[arg(short, long, default_value = "9042", value_parser = upstream::parse_upstream)]
+    upstream: Vec<SocketAddr>,
+
+    /// How client connections are routed between upstream nodes
+    #[arg(long, value_enum, default_value_t = Routing::RoundRobin)]
+    routing: Routing,
+
+    /// Seconds between health checks of upstream nodes
+    #[arg(long, default_value_t = 5)]
+    health_check_interval: u64,
+
+    /// Certificate chain in pem format, enables tls for client connections
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key in pem format of the `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Connect to upstream nodes over tls
+    #[arg(long)]
+    upstream_tls: bool,
+
+    /// Certificate authorities in pem format upstream certificates are verified with,
+    /// webpki roots are used by default
+    #[arg(long, requires = "upstream_tls")]
+    upstream_ca: Option<PathBuf>,
+
+    /// Name upstream certificates are verified against, ip address of the node by default
+    #[arg(long, requires = "upstream_tls")]
+    upstream_server_name: Option<String>,
 
     /// Preload state from path
     #[arg(short, long)]
     data: Option<PathBuf>,
+
+    /// Record every request and response pair to the file, so it can be replayed later
+    #[arg(short, long)]
+    record: Option<PathBuf>,
+
+    /// Preload ids of prepared statements from the file and keep it updated with statements prepared through the proxy,
+    /// so executions of statements prepared before a restart are still understood
+    #[arg(long)]
+    translations: Option<PathBuf>,
+
+    /// Write every statement going through the proxy to the file as a json line,
+    /// with its bound values decoded, consistency, latency and outcome
+    #[arg(long)]
+    query_log: Option<PathBuf>,
+
+    /// Keep values of the column out of recorded traffic and logs, e.g. `shop.users.password`,
+    /// `shop.users.email=hash` writes salted md5 digests of values instead.
+    /// Rows of results are not recorded while any column is redacted
+    #[arg(long)]
+    redact: Vec<RedactRule>,
+
+    /// Salt of hashed values of `--redact`
+    #[arg(long, default_value = "", requires = "redact")]
+    redact_salt: String,
+
+    /// Inject faults into responses, e.g. `error=write-timeout,p=0.01,match=^INSERT`,
+    /// actions are `delay=<duration>`, `drop` and `error=<write-timeout|read-timeout|unavailable|overloaded>`
+    #[arg(long)]
+    chaos: Vec<ChaosRule>,
+
+    /// Read fault injection rules from json file, containing a list of rules in `--chaos` format
+    #[arg(long)]
+    chaos_config: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -53,39 +158,169 @@ async fn main() -> eyre::Result<()> {
     stable_eyre::install()?;
     logging::setup_telemetry("kassandra-proxy")?;
 
-    let Args {
+    let args = Args::parse();
+
+    match args.command {
+        Some(Command::Replay { path, output, log }) => {
+            replay_file(&path, output.as_deref(), log.as_deref())
+        }
+        None => proxy(args.proxy).await,
+    }
+}
+
+async fn proxy(
+    ProxyArgs {
         port,
         upstream,
+        routing,
+        health_check_interval,
+        tls_cert,
+        tls_key,
+        upstream_tls,
+        upstream_ca,
+        upstream_server_name,
         data,
-    } = Args::parse();
+        record,
+        translations,
+        query_log,
+        redact,
+        redact_salt,
+        mut chaos,
+        chaos_config,
+    }: ProxyArgs,
+) -> eyre::Result<()> {
+    if let Some(config) = chaos_config {
+        chaos.extend(Chaos::load_rules(&config)?);
+    }
+
+    let acceptor = match (tls_cert, tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(&cert, &key)?),
+        _ => None,
+    };
+    let upstream_tls = upstream_tls
+        .then(|| UpstreamTls::new(upstream_ca.as_deref(), upstream_server_name))
+        .transpose()?;
+    let upstreams = Arc::new(Upstreams::new(upstream, routing, upstream_tls));
+    let translator = match translations {
+        Some(path) => PreparedQueryTranslator::persisted(&path)?,
+        None => PreparedQueryTranslator::new(),
+    };
+    tokio::spawn(
+        upstreams
+            .clone()
+            .health_check_loop(Duration::from_secs(health_check_interval.max(1))),
+    );
 
-    let CassandraSniffer {
-        mut requests,
-        mut responses,
-        translator,
-    } = CassandraSniffer::new(format!("127.0.0.1:{port}"), format!("127.0.0.1:{upstream}"))?;
     let session: KassandraSession = if let Some(data) = data {
         let content = std::fs::read(&data).wrap_err("while reading initial state file")?;
         KassandraSession::load_state(&content)?
     } else {
         KassandraSession::new()
     };
+    let redaction = Redaction::new(redact, redact_salt, session.handle());
+    let query_log = query_log
+        .map(|path| {
+            QueryLog::create(
+                &path,
+                translator.clone(),
+                session.handle(),
+                redaction.clone(),
+            )
+        })
+        .transpose()?
+        .map(Arc::new);
+
+    let CassandraSniffer {
+        mut requests,
+        mut responses,
+        translator,
+    } = CassandraSniffer::new(
+        format!("127.0.0.1:{port}"),
+        acceptor,
+        upstreams.clone(),
+        Chaos::new(chaos),
+        translator,
+        query_log,
+    )?;
     let mut replay = ReplayInterceptor::new(&session);
+    let mut recorder = record.as_deref().map(Recorder::create).transpose()?;
 
     loop {
         let (frame, op, payload) = requests.recv().await?;
-        tracing::info!(?frame, ?op, ?payload, "Request");
         let response = responses.recv().await?;
-        tracing::info!(frame = ?response.0, op = ?response.1, payload = ?response.2, "Response");
         if op == RequestOpcode::Prepare {
             replay.prepare_all(translator.read_all());
-            continue;
+        } else {
+            resolve_unknown_ids(&mut replay, &translator, &upstreams, (frame, op, &payload)).await;
         }
 
-        replay_request(&mut replay, &translator, (frame, op, payload));
+        let request = (frame, op, payload);
+        match redaction.request(&request, replay.keyspace(), &translator) {
+            Ok(redacted) => {
+                let response = redaction.response(&response);
+                tracing::info!(frame = ?redacted.0, op = ?redacted.1, payload = ?redacted.2, "Request");
+                tracing::info!(frame = ?response.0, op = ?response.1, payload = ?response.2, "Response");
+                if let Some(recorder) = &mut recorder {
+                    if let Err(error) = recorder.record(&redacted, &response) {
+                        tracing::error!(?error, "Could not record exchange");
+                    }
+                }
+            }
+            Err(error) => {
+                tracing::warn!(?error, ?op, "Could not redact request, it is not recorded")
+            }
+        }
+
+        if op != RequestOpcode::Prepare {
+            replay_request(&mut replay, &translator, Some(&redaction), request);
+        }
     }
 }
 
+/// Looks up statements of executions which were prepared before the proxy started
+async fn resolve_unknown_ids(
+    replay: &mut ReplayInterceptor,
+    translator: &PreparedQueryTranslator,
+    upstreams: &Upstreams,
+    (frame, op, payload): (FrameParams, RequestOpcode, &Bytes),
+) {
+    let unknown = Request::deserialize(op, payload.as_ref(), frame.flags)
+        .map(|request| translator.unknown_ids(&request))
+        .unwrap_or_default();
+    for id in &unknown {
+        match translator.resolve(upstreams, *id).await {
+            Ok(query) => {
+                tracing::info!(id, %query, "Resolved statement prepared before the proxy started")
+            }
+            Err(error) => tracing::warn!(?error, id, "Could not resolve prepared statement"),
+        }
+    }
+    if !unknown.is_empty() {
+        replay.prepare_all(translator.read_all());
+    }
+}
+
+fn replay_file(path: &Path, output: Option<&Path>, log: Option<&Path>) -> eyre::Result<()> {
+    let exchanges = record::read_exchanges(path)?;
+    if let Some(log) = log {
+        let content = serde_json::to_string_pretty(&record::replay_log(&exchanges)?)?;
+        std::fs::write(log, content).wrap_err("while writing replay log")?;
+    }
+    let session = KassandraSession::new();
+    let mut replay = ReplayInterceptor::new(&session);
+
+    record::replay_exchanges(&mut replay, &exchanges)?;
+    tracing::info!(exchanges = exchanges.len(), "Replayed recorded traffic");
+
+    let snapshot = serde_json::to_string_pretty(&replay.snapshot())?;
+    match output {
+        Some(output) => std::fs::write(output, snapshot).wrap_err("while writing snapshot")?,
+        None => println!("{snapshot}"),
+    }
+
+    Ok(())
+}
+
 type CassandraRequest = (FrameParams, RequestOpcode, Bytes);
 type CassandraResponse = (FrameParams, ResponseOpcode, Bytes);
 
@@ -97,23 +332,33 @@ struct CassandraSniffer {
 }
 
 impl CassandraSniffer {
-    fn new<S1, S2>(addr: S1, upstream: S2) -> eyre::Result<Self>
+    fn new<S>(
+        addr: S,
+        acceptor: Option<TlsAcceptor>,
+        upstreams: Arc<Upstreams>,
+        chaos: Chaos,
+        translator: PreparedQueryTranslator,
+        query_log: Option<Arc<QueryLog>>,
+    ) -> eyre::Result<Self>
     where
-        S1: ToSocketAddrs,
-        S2: ToSocketAddrs,
+        S: ToSocketAddrs,
     {
         let addr = addr.to_socket_addrs()?.next().unwrap();
-        let upstream = upstream.to_socket_addrs()?.next().unwrap();
 
         let (rq, requests) = broadcast::channel(256);
         let (rs, responses) = broadcast::channel(256);
         tokio::spawn(cassandra_proxy(
             addr,
-            upstream,
+            acceptor,
+            upstreams,
             BroadcastSink::new(rq),
             BroadcastSink::new(rs),
+            Interceptors {
+                chaos: Arc::new(chaos),
+                translator: translator.clone(),
+                query_log,
+            },
         ));
-        let translator = PreparedQueryTranslator::new();
 
         tokio::spawn(translator::translation_loop(
             translator.clone(),
@@ -129,32 +374,83 @@ impl CassandraSniffer {
     }
 }
 
+/// Everything looking into the traffic of client connections while it is proxied
+#[derive(Clone)]
+struct Interceptors {
+    chaos: Arc<Chaos>,
+    translator: PreparedQueryTranslator,
+    query_log: Option<Arc<QueryLog>>,
+}
+
 async fn cassandra_proxy(
     addr: SocketAddr,
-    upstream: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+    upstreams: Arc<Upstreams>,
     requests: impl Sink<CassandraRequest, Error = eyre::Report> + Unpin + Send + Clone + 'static,
     responses: impl Sink<CassandraResponse, Error = eyre::Report> + Unpin + Send + Clone + 'static,
+    interceptors: Interceptors,
 ) -> eyre::Result<()> {
     let tcp = TcpListener::bind(addr).await?;
     tracing::info!(addr = %tcp.local_addr().unwrap(), "Listening for cassandra clients");
     loop {
-        let Ok((mut client, a)) = tcp.accept().await else {
+        let Ok((client, a)) = tcp.accept().await else {
             continue;
         };
         tracing::info!(address = ?a, "Got a cassandra connection");
         let requests = requests.clone();
         let responses = responses.clone();
+        let Interceptors {
+            chaos,
+            translator,
+            query_log,
+        } = interceptors.clone();
+        let upstreams = upstreams.clone();
+        let acceptor = acceptor.clone();
+        let log = query_log.as_ref().map(QueryLog::connection);
         tokio::spawn(async move {
-            let mut service = TcpStream::connect(upstream).await?;
-            tracing::info!("Connected to upstream cassandra");
+            let client: Box<dyn Connection> = match acceptor {
+                Some(acceptor) => Box::new(acceptor.accept(client).await?),
+                None => Box::new(client),
+            };
+            let (upstream, service) = upstreams.connect(a).await?;
+            tracing::info!(%upstream, "Connected to upstream cassandra");
 
-            let (mut up_stream, up_sink) = cassandra_client_stream_sink(client.split());
-            let (mut down_stream, down_sink) = cassandra_server_stream_sink(service.split());
+            let pending = PendingFaults::default();
+            let (up_stream, mut up_sink) = cassandra_client_stream_sink(tokio::io::split(client));
+            let (down_stream, down_sink) = cassandra_server_stream_sink(tokio::io::split(service));
             let mut request_sink = down_sink.fanout(requests);
-            let mut response_sink = up_sink.fanout(responses);
+            let mut up_stream = up_stream.inspect(|request| {
+                if let Ok(request) = request {
+                    chaos.intercept_request(request, &translator, &pending);
+                    if let Some(log) = &log {
+                        log.request(request);
+                    }
+                }
+            });
+            // interceptors see responses as upstream sent them, faults only affect the client
+            let faults = pending.clone();
+            let log = log.clone();
+            let mut down_stream = Box::pin(down_stream.filter_map(move |response| {
+                let mut responses = responses.clone();
+                let pending = faults.clone();
+                let log = log.clone();
+                async move {
+                    let response = match response {
+                        Ok(response) => response,
+                        Err(er) => return Some(Err(er)),
+                    };
+                    if let Some(log) = &log {
+                        log.response(&response);
+                    }
+                    if let Err(er) = responses.send(response.clone()).await {
+                        return Some(Err(er));
+                    }
+                    Chaos::intercept_response(&pending, response).await.map(Ok)
+                }
+            }));
             let (x, y) = tokio::join!(
                 request_sink.send_all(&mut up_stream),
-                response_sink.send_all(&mut down_stream)
+                up_sink.send_all(&mut down_stream)
             );
             if let Err(er) = x {
                 tracing::error!(?er, "Error during proxying cassandra requests")
@@ -186,27 +482,34 @@ fn cassandra_server_stream_sink<'a>(
     (response_stream(read), raw_request_sink(write))
 }
 
+/// Statements are logged with the `redaction` applied, recorded traffic is replayed as it is
 fn replay_request(
     replay: &mut ReplayInterceptor,
     translator: &PreparedQueryTranslator,
+    redaction: Option<&Redaction>,
     request: CassandraRequest,
 ) {
     let (frame, opcode, b) = request;
     let request = Request::deserialize(opcode, b.as_ref(), frame.flags).unwrap();
+    let keyspace = replay.keyspace().map(str::to_owned);
+    let describe = |query: &QueryString| match redaction {
+        Some(redaction) => redaction.statement(query, keyspace.as_deref()),
+        None => query.to_string(),
+    };
 
     let mut queries = vec![];
     match request {
         Request::Query(q) => {
-            queries.push(q.query.to_string());
+            queries.push(describe(&q.query));
             replay.process(q);
         }
         Request::Execute(ex) => {
             let id = ex.id;
             let translated = translator.translate(id).ok();
             if let Some(q) = translated {
-                queries.push(q.to_string())
+                queries.push(describe(&q))
             } else {
-                tracing::warn!(query = ?ex, "Untranslated query")
+                tracing::warn!(id = ?ex.id, "Untranslated query")
             }
             replay.execute(ex);
         }
@@ -214,12 +517,12 @@ fn replay_request(
             for statement in &batch.statements {
                 let translated = match statement {
                     BatchStatement::Prepared { id, .. } => translator.translate(id).ok(),
-                    BatchStatement::Query { query, .. } => Some(query.clone()),
+                    BatchStatement::Query { query, .. } => Some(*query.clone()),
                 };
                 if let Some(q) = translated {
-                    queries.push(q.to_string())
+                    queries.push(describe(&q))
                 } else {
-                    tracing::warn!(query = ?statement, ?batch, "Untranslated query from the batch")
+                    tracing::warn!("Untranslated query from the batch")
                 }
             }
             replay.process_batch(batch);
@@ -0,0 +1,539 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use kassandra::{
+    cql::{query::QueryString, schema::ColumnType, value::deserialize_value},
+    frame::{
+        request::{batch::BatchStatement, Request, RequestOpcode},
+        response::{error::Error, ResponseOpcode},
+        value::FrameValue,
+    },
+    replay::ReplayValue,
+    session::SessionHandle,
+    snapshot::ValueSnapshot,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use stable_eyre::eyre::{self, Context};
+
+use super::{CassandraRequest, CassandraResponse};
+use crate::{redact::Redaction, translator::PreparedQueryTranslator};
+
+/// Statements going through the proxy, written as one json record per statement.
+///
+/// Bound values are decoded with the types of their bind markers, as planned against the schema
+/// of the replaying session, values which can't be decoded are written as hex.
+/// Statements and values are written after the [`Redaction`] is applied to them.
+pub struct QueryLog {
+    file: Mutex<BufWriter<File>>,
+    translator: PreparedQueryTranslator,
+    session: SessionHandle,
+    redaction: Redaction,
+}
+
+/// Statement record of the query log
+#[derive(Debug, Serialize)]
+struct QueryRecord {
+    /// Microseconds since unix epoch, when the response was intercepted
+    at: i64,
+    /// Statement rendered back from its parsed form, `None` for executions of unknown prepared ids
+    statement: Option<String>,
+    /// Id of an executed prepared statement
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    values: Vec<BoundValue>,
+    consistency: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    batch: bool,
+    latency_us: u64,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum BoundValue {
+    Decoded(ValueSnapshot),
+    Undecoded { hex: String },
+    Unset { unset: bool },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+enum Outcome {
+    Void,
+    Rows,
+    SetKeyspace,
+    Prepared,
+    SchemaChange,
+    Error { error: String, message: String },
+    Unexpected { opcode: String },
+}
+
+impl Outcome {
+    fn new((_, opcode, body): &CassandraResponse) -> Self {
+        match opcode {
+            ResponseOpcode::Result => match body.get(..4).map(|it| it.try_into().unwrap()) {
+                Some(kind) => match i32::from_be_bytes(kind) {
+                    0x0001 => Outcome::Void,
+                    0x0002 => Outcome::Rows,
+                    0x0003 => Outcome::SetKeyspace,
+                    0x0004 => Outcome::Prepared,
+                    0x0005 => Outcome::SchemaChange,
+                    kind => Outcome::Unexpected {
+                        opcode: format!("Result({kind})"),
+                    },
+                },
+                None => Outcome::Unexpected {
+                    opcode: "Result".to_owned(),
+                },
+            },
+            ResponseOpcode::Error => match Error::deserialize(body) {
+                Ok((_, error)) => Outcome::Error {
+                    error: error.error.to_string(),
+                    message: error.reason,
+                },
+                Err(_) => Outcome::Error {
+                    error: "Malformed error".to_owned(),
+                    message: String::new(),
+                },
+            },
+            opcode => Outcome::Unexpected {
+                opcode: format!("{opcode:?}"),
+            },
+        }
+    }
+}
+
+impl QueryLog {
+    pub fn create(
+        path: &Path,
+        translator: PreparedQueryTranslator,
+        session: SessionHandle,
+        redaction: Redaction,
+    ) -> eyre::Result<Self> {
+        let file = File::create(path).wrap_err("while creating query log")?;
+
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+            translator,
+            session,
+            redaction,
+        })
+    }
+
+    /// Log of a single client connection, its requests are matched with responses by stream ids
+    pub fn connection(self: &Arc<Self>) -> ConnectionLog {
+        ConnectionLog {
+            log: self.clone(),
+            state: Default::default(),
+        }
+    }
+
+    fn write(&self, records: &[QueryRecord]) -> eyre::Result<()> {
+        let mut file = self.file.lock();
+        for record in records {
+            serde_json::to_writer(&mut *file, record)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+
+        Ok(())
+    }
+
+    fn values(
+        &self,
+        statement: Option<&QueryString>,
+        keyspace: Option<&str>,
+        values: &[FrameValue<'_>],
+    ) -> Vec<BoundValue> {
+        let types = statement
+            .and_then(|it| self.session.prepared_metadata(it.clone(), keyspace).ok())
+            .map(|metadata| metadata.col_specs)
+            .unwrap_or_default();
+
+        self.redaction
+            .values(statement, keyspace, values)
+            .iter()
+            .enumerate()
+            .map(|(i, value)| match value {
+                ReplayValue::Value(bytes) => types
+                    .get(i)
+                    .filter(|spec| !matches!(spec.typ, ColumnType::Custom(_)))
+                    .and_then(|spec| deserialize_value(bytes, &spec.typ).ok())
+                    .map(|value| BoundValue::Decoded(value.into()))
+                    .unwrap_or_else(|| BoundValue::Undecoded { hex: hex(bytes) }),
+                ReplayValue::Null => BoundValue::Decoded(ValueSnapshot::Null),
+                ReplayValue::NotSet => BoundValue::Unset { unset: true },
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionLog {
+    log: Arc<QueryLog>,
+    state: Arc<Mutex<ConnectionState>>,
+}
+
+#[derive(Default)]
+struct ConnectionState {
+    keyspace: Option<String>,
+    pending: HashMap<i16, (Instant, CassandraRequest)>,
+}
+
+impl ConnectionLog {
+    pub fn request(&self, request: &CassandraRequest) {
+        if matches!(
+            request.1,
+            RequestOpcode::Query | RequestOpcode::Execute | RequestOpcode::Batch
+        ) {
+            self.state
+                .lock()
+                .pending
+                .insert(request.0.stream, (Instant::now(), request.clone()));
+        }
+    }
+
+    pub fn response(&self, response: &CassandraResponse) {
+        let mut state = self.state.lock();
+        let Some((started, (frame, opcode, body))) = state.pending.remove(&response.0.stream)
+        else {
+            return;
+        };
+        let latency_us = started.elapsed().as_micros() as u64;
+        let Ok(request) = Request::deserialize(opcode, body.as_ref(), frame.flags) else {
+            return;
+        };
+
+        let outcome = Outcome::new(response);
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        let record = |statement: Option<QueryString>,
+                      id: Option<&[u8]>,
+                      values: &[FrameValue<'_>],
+                      consistency: String,
+                      batch: bool| QueryRecord {
+            at,
+            values: self
+                .log
+                .values(statement.as_ref(), state.keyspace.as_deref(), values),
+            statement: statement
+                .map(|it| self.log.redaction.statement(&it, state.keyspace.as_deref())),
+            id: id.map(hex),
+            consistency,
+            batch,
+            latency_us,
+            outcome: outcome.clone(),
+        };
+
+        let records = match &request {
+            Request::Query(query) => vec![record(
+                Some(query.query.clone()),
+                None,
+                &query.parameters.data,
+                query.parameters.consistency.to_string(),
+                false,
+            )],
+            Request::Execute(execute) => vec![record(
+                self.log.translator.translate(execute.id).ok(),
+                Some(execute.id),
+                &execute.parameters.data,
+                execute.parameters.consistency.to_string(),
+                false,
+            )],
+            Request::Batch(batch) => batch
+                .statements
+                .iter()
+                .map(|statement| match statement {
+                    BatchStatement::Query { query, values, .. } => record(
+                        Some(*query.clone()),
+                        None,
+                        values,
+                        batch.consistency.to_string(),
+                        true,
+                    ),
+                    BatchStatement::Prepared { id, values } => record(
+                        self.log.translator.translate(id).ok(),
+                        Some(id),
+                        values,
+                        batch.consistency.to_string(),
+                        true,
+                    ),
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        if let (Request::Query(query), Outcome::SetKeyspace) = (&request, &outcome) {
+            if let QueryString::Use { keyspace } = &query.query {
+                state.keyspace = Some(keyspace.clone());
+            }
+        }
+        drop(state);
+
+        if let Err(error) = self.log.write(&records) {
+            tracing::error!(?error, "Could not write query log");
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use bytes::{BufMut, Bytes, BytesMut};
+    use kassandra::{
+        cql::parser,
+        error::DbError,
+        frame::{
+            consistency::{Consistency, SerialConsistency},
+            request::{
+                batch::{Batch, BatchStatement, BatchType},
+                execute::Execute,
+                query::Query,
+                QueryParameters, Request, RequestOpcode,
+            },
+            response::{error::Error, ResponseOpcode},
+            value::FrameValue,
+            FrameFlags, FrameParams, ProtocolVersion,
+        },
+        KassandraSession,
+    };
+    use serde_json::{json, Value};
+
+    use super::QueryLog;
+    use crate::{
+        redact::Redaction, translator::PreparedQueryTranslator, CassandraRequest, CassandraResponse,
+    };
+
+    /// Query log of a session with `shop.users`, which redacts emails
+    fn query_log(name: &str, translator: PreparedQueryTranslator) -> (Arc<QueryLog>, PathBuf) {
+        let mut session = KassandraSession::new();
+        for statement in [
+            "CREATE KEYSPACE shop WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1}",
+            "CREATE TABLE shop.users (id int PRIMARY KEY, email text, age int)",
+        ] {
+            session.process_cql(statement).unwrap();
+        }
+        let redaction = Redaction::new(
+            vec!["shop.users.email".parse().unwrap()],
+            String::new(),
+            session.handle(),
+        );
+        let path = std::env::temp_dir().join(format!(
+            "kassandra-proxy-{name}-{}.jsonl",
+            std::process::id()
+        ));
+        let log = QueryLog::create(&path, translator, session.handle(), redaction).unwrap();
+
+        (Arc::new(log), path)
+    }
+
+    /// Logged records without the fields depending on the time
+    fn records(path: &PathBuf) -> Vec<Value> {
+        let log = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        log.lines()
+            .map(|line| {
+                let mut record = serde_json::from_str::<Value>(line).unwrap();
+                let record_mut = record.as_object_mut().unwrap();
+                assert!(record_mut.remove("at").unwrap().is_i64());
+                assert!(record_mut.remove("latency_us").unwrap().is_u64());
+                record
+            })
+            .collect()
+    }
+
+    fn frame(stream: i16) -> FrameParams {
+        FrameParams {
+            version: ProtocolVersion::V4,
+            flags: FrameFlags::empty(),
+            stream,
+        }
+    }
+
+    fn request(stream: i16, opcode: RequestOpcode, request: Request<'_>) -> CassandraRequest {
+        let mut body = BytesMut::new();
+        request.serialize(&mut body).unwrap();
+
+        (frame(stream), opcode, body.freeze())
+    }
+
+    fn query(stream: i16, statement: &str, values: Vec<FrameValue<'_>>) -> CassandraRequest {
+        request(
+            stream,
+            RequestOpcode::Query,
+            Request::Query(Query {
+                query: parser::query(statement).unwrap(),
+                raw_query: statement,
+                parameters: QueryParameters {
+                    data: values,
+                    ..Default::default()
+                },
+            }),
+        )
+    }
+
+    fn result(stream: i16, kind: i32, rest: &[u8]) -> CassandraResponse {
+        let mut body = BytesMut::new();
+        body.put_i32(kind);
+        body.put_slice(rest);
+
+        (frame(stream), ResponseOpcode::Result, body.freeze())
+    }
+
+    #[test]
+    fn statements_are_matched_with_responses() {
+        let (log, path) = query_log("statements", PreparedQueryTranslator::new());
+        let connection = log.connection();
+
+        // responses without a logged request are skipped
+        connection.response(&result(9, 0x0001, &[]));
+        // as are requests other than statements
+        connection.request(&(frame(1), RequestOpcode::Options, Bytes::new()));
+        connection.response(&(frame(1), ResponseOpcode::Supported, Bytes::new()));
+
+        connection.request(&query(2, "USE shop", vec![]));
+        connection.response(&result(2, 0x0003, b"\0\x04shop"));
+
+        let insert = query(
+            3,
+            "INSERT INTO users (id, email, age) VALUES (?, ?, ?)",
+            vec![
+                FrameValue::Some(&[0, 0, 0, 1]),
+                FrameValue::Some(b"anna@shop"),
+                FrameValue::NotSet,
+            ],
+        );
+        let select = query(
+            4,
+            "SELECT * FROM users WHERE id = ?",
+            vec![FrameValue::Some(&[0, 1])],
+        );
+        // responses can come out of order
+        connection.request(&insert);
+        connection.request(&select);
+        let mut error = BytesMut::new();
+        Error::new(DbError::Invalid, "Expected 4 bytes for an int").serialize(&mut error);
+        connection.response(&(frame(4), ResponseOpcode::Error, error.freeze()));
+        connection.response(&result(3, 0x0001, &[]));
+
+        assert_eq!(
+            records(&path),
+            vec![
+                json!({
+                    "statement": "USE shop",
+                    "consistency": "LocalOne",
+                    "outcome": "set_keyspace",
+                }),
+                json!({
+                    "statement": "SELECT * FROM users WHERE id = ?",
+                    "values": [{"hex": "0001"}],
+                    "consistency": "LocalOne",
+                    "outcome": "error",
+                    "error": "The query is syntactically correct but invalid",
+                    "message": "Expected 4 bytes for an int",
+                }),
+                json!({
+                    "statement": "INSERT INTO users (id, email, age) VALUES (?, ?, ?)",
+                    "values": [1, null, {"unset": true}],
+                    "consistency": "LocalOne",
+                    "outcome": "void",
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn prepared_statements_and_batches_are_logged() {
+        let translator = PreparedQueryTranslator::new();
+        translator.insert(
+            1,
+            parser::query("UPDATE shop.users SET age = ? WHERE id = ?").unwrap(),
+        );
+        let (log, path) = query_log("prepared", translator);
+        let connection = log.connection();
+        let (known, unknown) = (1u128.to_be_bytes(), 2u128.to_be_bytes());
+
+        connection.request(&request(
+            1,
+            RequestOpcode::Execute,
+            Request::Execute(Execute {
+                id: &unknown,
+                parameters: QueryParameters {
+                    data: vec![FrameValue::Some(&[0, 0, 0, 1])],
+                    ..Default::default()
+                },
+            }),
+        ));
+        connection.response(&result(1, 0x0002, &[]));
+
+        let raw_query = "DELETE FROM shop.users WHERE id = ?";
+        connection.request(&request(
+            2,
+            RequestOpcode::Batch,
+            Request::Batch(Batch {
+                batch_type: BatchType::Logged,
+                consistency: Consistency::Quorum,
+                serial_consistency: SerialConsistency::Serial,
+                timestamp: None,
+                statements: vec![
+                    BatchStatement::Prepared {
+                        id: &known,
+                        values: vec![FrameValue::Some(&[0, 0, 0, 30]), FrameValue::Null],
+                    },
+                    BatchStatement::Query {
+                        query: Box::new(parser::query(raw_query).unwrap()),
+                        raw_query,
+                        values: vec![FrameValue::Some(&[0, 0, 0, 2])],
+                    },
+                ],
+            }),
+        ));
+        connection.response(&result(2, 0x0001, &[]));
+
+        assert_eq!(
+            records(&path),
+            vec![
+                json!({
+                    "statement": null,
+                    "id": "00000000000000000000000000000002",
+                    // values of unknown statements could hold anything
+                    "values": [null],
+                    "consistency": "LocalOne",
+                    "outcome": "rows",
+                }),
+                json!({
+                    "statement": "UPDATE shop.users SET age = ? WHERE id = ?",
+                    "id": "00000000000000000000000000000001",
+                    "values": [30, null],
+                    "consistency": "Quorum",
+                    "batch": true,
+                    "outcome": "void",
+                }),
+                json!({
+                    "statement": "DELETE FROM shop.users WHERE id = ?",
+                    "values": [2],
+                    "consistency": "Quorum",
+                    "batch": true,
+                    "outcome": "void",
+                }),
+            ]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, Bytes};
+use kassandra::{
+    frame::{
+        parse,
+        request::{prepare::Prepare, Request, RequestOpcode},
+        response::ResponseOpcode,
+        FrameFlags, FrameParams, ProtocolVersion,
+    },
+    replay::ReplayLog,
+};
+use serde::{Deserialize, Serialize};
+use stable_eyre::eyre::{self, eyre, Context};
+
+use super::{replay::ReplayInterceptor, replay_request, CassandraRequest, CassandraResponse};
+use crate::translator::PreparedQueryTranslator;
+
+/// Single intercepted request together with the response upstream replied with.
+///
+/// Traffic files contain one exchange per line, serialized as json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    /// Microseconds since unix epoch, when the response was intercepted
+    pub at: i64,
+    pub request: RecordedFrame,
+    pub response: RecordedFrame,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub stream: i16,
+    pub flags: u8,
+    pub opcode: u8,
+    pub body: Bytes,
+}
+
+impl RecordedFrame {
+    fn new(frame: &FrameParams, opcode: u8, body: &Bytes) -> Self {
+        Self {
+            stream: frame.stream,
+            flags: frame.flags.bits(),
+            opcode,
+            body: body.clone(),
+        }
+    }
+
+    fn params(&self) -> FrameParams {
+        FrameParams {
+            version: ProtocolVersion::V4,
+            flags: FrameFlags::from_bits_truncate(self.flags),
+            stream: self.stream,
+        }
+    }
+}
+
+impl Exchange {
+    pub fn new(request: &CassandraRequest, response: &CassandraResponse) -> Self {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+
+        Self {
+            at,
+            request: RecordedFrame::new(&request.0, u8::from(request.1), &request.2),
+            response: RecordedFrame::new(&response.0, response.1 as u8, &response.2),
+        }
+    }
+
+    pub fn request(&self) -> eyre::Result<CassandraRequest> {
+        let opcode = RequestOpcode::from(self.request.opcode);
+
+        Ok((self.request.params(), opcode, self.request.body.clone()))
+    }
+
+    pub fn response(&self) -> eyre::Result<CassandraResponse> {
+        let opcode = ResponseOpcode::try_from(self.response.opcode)
+            .map_err(|_| eyre!("Unknown response opcode {}", self.response.opcode))?;
+
+        Ok((self.response.params(), opcode, self.response.body.clone()))
+    }
+
+    /// Id upstream gave the prepared statement, `None` if the preparation failed
+    fn prepared_id(&self) -> eyre::Result<Option<u128>> {
+        let (_, _, mut body) = self.response()?;
+        if self.response.opcode != ResponseOpcode::Result as u8 {
+            return Ok(None);
+        }
+        if body.len() < 4 {
+            return Err(eyre!("Truncated result of a preparation"));
+        }
+        // only successful preparations are `Result::Prepared`
+        if body.get_u32() != 0x0004 {
+            return Ok(None);
+        }
+        let (_, id) =
+            parse::short_bytes(body.as_ref()).map_err(|_| eyre!("Malformed prepared id"))?;
+
+        Ok(Some(u128::from_be_bytes(id.try_into()?)))
+    }
+}
+
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> eyre::Result<Self> {
+        let file = File::create(path).wrap_err("while creating traffic file")?;
+
+        Ok(Self {
+            file: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        request: &CassandraRequest,
+        response: &CassandraResponse,
+    ) -> eyre::Result<()> {
+        serde_json::to_writer(&mut self.file, &Exchange::new(request, response))?;
+        self.file.write_all(b"\n")?;
+        // flushing every exchange, so the file is usable even if proxy is killed
+        self.file.flush()?;
+
+        Ok(())
+    }
+}
+
+pub fn read_exchanges(path: &Path) -> eyre::Result<Vec<Exchange>> {
+    let file = File::open(path).wrap_err("while opening traffic file")?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(n, line)| {
+            serde_json::from_str(&line?)
+                .wrap_err_with(|| format!("invalid exchange at line {}", n + 1))
+        })
+        .collect()
+}
+
+/// Feeds recorded requests into the interceptor the same way they would be while proxying.
+pub fn replay_exchanges(
+    replay: &mut ReplayInterceptor,
+    exchanges: &[Exchange],
+) -> eyre::Result<()> {
+    let translator = PreparedQueryTranslator::new();
+
+    for exchange in exchanges {
+        let request = exchange.request()?;
+
+        if request.1 != RequestOpcode::Prepare {
+            replay_request(replay, &translator, None, request);
+            continue;
+        }
+
+        let Some(id) = exchange.prepared_id()? else {
+            continue;
+        };
+        let Request::Prepare(Prepare { query, .. }) =
+            Request::deserialize(request.1, request.2.as_ref(), request.0.flags)?
+        else {
+            unreachable!("opcode was Prepare")
+        };
+
+        translator.insert(id, query);
+        replay.prepare_all(translator.read_all());
+    }
+
+    Ok(())
+}
+
+/// Replay log of the requests upstream handled successfully, with the ids it gave prepared statements.
+///
+/// Statements are timestamped with the time their responses were intercepted.
+pub fn replay_log(exchanges: &[Exchange]) -> eyre::Result<ReplayLog> {
+    let mut log = ReplayLog::new();
+
+    for exchange in exchanges {
+        if exchange.response.opcode == ResponseOpcode::Error as u8 {
+            continue;
+        }
+        let (frame, opcode, body) = exchange.request()?;
+        let request = Request::deserialize(opcode, body.as_ref(), frame.flags)?;
+
+        match request {
+            Request::Prepare(Prepare { raw_query, .. }) => {
+                if let Some(id) = exchange.prepared_id()? {
+                    log.record_prepared(id, raw_query, None, exchange.at);
+                }
+            }
+            request => log.record(&request, None, exchange.at),
+        }
+    }
+
+    Ok(log)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use kassandra::frame::{
+        request::{prepare::Prepare, Request, RequestOpcode},
+        response::ResponseOpcode,
+        FrameFlags, FrameParams, ProtocolVersion,
+    };
+
+    use super::{replay_log, Exchange};
+
+    fn prepare(response: &[u8]) -> Exchange {
+        let frame = FrameParams {
+            version: ProtocolVersion::V4,
+            flags: FrameFlags::empty(),
+            stream: 1,
+        };
+        let mut body = BytesMut::new();
+        Request::Prepare(Prepare::simple("SELECT * FROM shop.users").unwrap())
+            .serialize(&mut body)
+            .unwrap();
+
+        Exchange::new(
+            &(frame, RequestOpcode::Prepare, body.freeze()),
+            &(
+                frame,
+                ResponseOpcode::Result,
+                Bytes::copy_from_slice(response),
+            ),
+        )
+    }
+
+    fn prepared(id: &[u8]) -> Vec<u8> {
+        let mut body = vec![];
+        body.put_i32(0x0004);
+        body.put_u16(id.len() as u16);
+        body.put_slice(id);
+        body
+    }
+
+    #[test]
+    fn prepared_ids_are_read_from_results() {
+        let id = 42u128.to_be_bytes();
+
+        assert_eq!(prepare(&prepared(&id)).prepared_id().unwrap(), Some(42));
+        // other results are not preparations
+        assert_eq!(prepare(&[0, 0, 0, 1]).prepared_id().unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_results_are_rejected() {
+        for response in [
+            &[][..],
+            &[0, 0, 4],
+            &prepared(&[1; 16])[..10],
+            &prepared(&[1; 4]),
+        ] {
+            assert!(prepare(response).prepared_id().is_err());
+            assert!(replay_log(&[prepare(response)]).is_err());
+        }
+    }
+}
@@ -0,0 +1,927 @@
+use std::{borrow::Cow, str::FromStr};
+
+use bytes::{Bytes, BytesMut};
+use kassandra::{
+    cql::{
+        query::{InsertQuery, QueryString, QueryValue, WhereClosure},
+        schema::{ColumnType, Schema},
+        types::literal::Literal,
+    },
+    frame::{
+        request::{
+            batch::{Batch, BatchStatement},
+            execute::Execute,
+            prepare::Prepare,
+            query::Query,
+            QueryParameters, Request, RequestOpcode,
+        },
+        response::ResponseOpcode,
+        value::FrameValue,
+    },
+    replay::ReplayValue,
+    session::SessionHandle,
+};
+use stable_eyre::eyre::{self, bail};
+
+use super::{translator::PreparedQueryTranslator, CassandraRequest, CassandraResponse};
+
+/// Columns whose values are kept out of recorded traffic, query logs and proxy logs.
+///
+/// Values of redacted columns are replaced with nulls, while hashed columns get salted md5 digests of their values,
+/// so equal values stay equal and statements still relate to each other.
+/// Only `text`, `ascii` and `blob` values can be hashed, values of other types are replaced with nulls.
+/// Keyspace of unqualified statements isn't always known, such statements match rules of any keyspace.
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    rules: Vec<RedactRule>,
+    salt: String,
+    session: SessionHandle,
+}
+
+/// Rule written as `keyspace.table.column`, followed by `=hash` to hash values instead of dropping them:
+/// `shop.users.email=hash`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactRule {
+    keyspace: String,
+    table: String,
+    column: String,
+    mode: Mode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Redact,
+    Hash,
+}
+
+/// Column a value of a statement is written to or compared with
+#[derive(Debug, Clone, Copy)]
+struct Column<'a> {
+    keyspace: Option<&'a str>,
+    table: &'a str,
+    name: &'a str,
+}
+
+impl FromStr for RedactRule {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, mode) = match s.split_once('=') {
+            None => (s, Mode::Redact),
+            Some((path, "hash")) => (path, Mode::Hash),
+            Some((_, mode)) => bail!("Unknown redaction mode `{mode}`, expected `hash`"),
+        };
+        let [keyspace, table, column] = *path.split('.').collect::<Vec<_>>() else {
+            bail!("Expected `keyspace.table.column`, got `{path}`")
+        };
+        if [keyspace, table, column].iter().any(|it| it.is_empty()) {
+            bail!("Expected `keyspace.table.column`, got `{path}`")
+        }
+
+        Ok(Self {
+            keyspace: keyspace.to_owned(),
+            table: table.to_owned(),
+            column: column.to_owned(),
+            mode,
+        })
+    }
+}
+
+impl Redaction {
+    /// Column types of hashed values are looked up in the schema of the `session`
+    pub fn new(rules: Vec<RedactRule>, salt: String, session: SessionHandle) -> Self {
+        Self {
+            rules,
+            salt,
+            session,
+        }
+    }
+
+    /// Statement rendered with literals of redacted columns replaced, `keyspace` is the one in use
+    pub fn statement(&self, query: &QueryString, keyspace: Option<&str>) -> String {
+        match self.redacted(query, keyspace) {
+            Some(query) => query.to_string(),
+            None => query.to_string(),
+        }
+    }
+
+    /// Values bound to the statement with values of redacted columns replaced.
+    ///
+    /// Columns of values bound to unknown statements can't be told, all of them are replaced with nulls.
+    pub fn values(
+        &self,
+        query: Option<&QueryString>,
+        keyspace: Option<&str>,
+        values: &[FrameValue<'_>],
+    ) -> Vec<ReplayValue> {
+        let mut values = values.iter().map(ReplayValue::from).collect::<Vec<_>>();
+        if self.rules.is_empty() {
+            return values;
+        }
+        let Some(query) = query else {
+            for value in &mut values {
+                if let ReplayValue::Value(_) = value {
+                    *value = ReplayValue::Null;
+                }
+            }
+            return values;
+        };
+
+        let mut markers = vec![];
+        walk(&mut query.clone(), &mut |column, value| {
+            if value.is_bind_marker() {
+                let rule = column.and_then(|it| self.rule(it, keyspace));
+                markers.push(rule.map(|rule| (rule, matches!(value, QueryValue::FromJson(_)))));
+            }
+        });
+
+        let mut schema = None;
+        if let QueryString::Insert(insert @ InsertQuery { json: Some(_), .. }) = query {
+            if let (Some(QueryValue::Blankslate), Some(ReplayValue::Value(bytes))) =
+                (insert.values.first(), values.first())
+            {
+                let document = std::str::from_utf8(bytes)
+                    .ok()
+                    .map(|it| self.document(insert, keyspace, it, &mut schema));
+                match document {
+                    Some(None) => {}
+                    Some(Some(document)) => values[0] = ReplayValue::Value(document.into()),
+                    None => values[0] = ReplayValue::Null,
+                }
+            }
+        }
+
+        for (value, rule) in values.iter_mut().zip(markers) {
+            let (Some((rule, from_json)), ReplayValue::Value(bytes)) = (rule, &*value) else {
+                continue;
+            };
+            // json strings of `fromJson(?)` can't be hashed as values of the column
+            if from_json {
+                *value = ReplayValue::Null;
+                continue;
+            }
+            *value = match (rule.mode, self.column_type(rule, &mut schema)) {
+                (Mode::Hash, Some(ColumnType::Text | ColumnType::Ascii)) => {
+                    ReplayValue::Value(format!("{:x}", self.digest(bytes)).into())
+                }
+                (Mode::Hash, Some(ColumnType::Blob)) => {
+                    ReplayValue::Value(Bytes::copy_from_slice(&self.digest(bytes).0))
+                }
+                _ => ReplayValue::Null,
+            };
+        }
+
+        values
+    }
+
+    /// Request with its statements and bound values redacted, the way it is recorded.
+    ///
+    /// Statement requests which can't be parsed are rejected, as they could carry values of redacted columns.
+    pub fn request(
+        &self,
+        request: &CassandraRequest,
+        keyspace: Option<&str>,
+        translator: &PreparedQueryTranslator,
+    ) -> eyre::Result<CassandraRequest> {
+        let (frame, opcode, body) = request;
+        if self.rules.is_empty()
+            || !matches!(
+                opcode,
+                RequestOpcode::Query
+                    | RequestOpcode::Prepare
+                    | RequestOpcode::Execute
+                    | RequestOpcode::Batch
+            )
+        {
+            return Ok(request.clone());
+        }
+
+        let mut buf = BytesMut::new();
+        match Request::deserialize(*opcode, body.as_ref(), frame.flags)? {
+            Request::Query(query) => {
+                let raw_query = self.raw_query(&query.query, query.raw_query, keyspace);
+                let values = self.values(Some(&query.query), keyspace, &query.parameters.data);
+                Query {
+                    raw_query: &raw_query,
+                    parameters: with_values(&query.parameters, to_frame(&values)),
+                    ..query
+                }
+                .serialize(&mut buf);
+            }
+            Request::Prepare(prepare) => {
+                let raw_query = self.raw_query(&prepare.query, prepare.raw_query, keyspace);
+                Prepare {
+                    raw_query: &raw_query,
+                    ..prepare
+                }
+                .serialize(&mut buf);
+            }
+            Request::Execute(execute) => {
+                let statement = translator.translate(execute.id).ok();
+                let values = self.values(statement.as_ref(), keyspace, &execute.parameters.data);
+                Execute {
+                    id: execute.id,
+                    parameters: with_values(&execute.parameters, to_frame(&values)),
+                }
+                .serialize(&mut buf);
+            }
+            Request::Batch(batch) => {
+                let redacted = batch
+                    .statements
+                    .iter()
+                    .map(|statement| match statement {
+                        BatchStatement::Query {
+                            query,
+                            raw_query,
+                            values,
+                        } => (
+                            self.raw_query(query, raw_query, keyspace),
+                            self.values(Some(query), keyspace, values),
+                        ),
+                        BatchStatement::Prepared { id, values } => {
+                            let statement = translator.translate(id).ok();
+                            let values = self.values(statement.as_ref(), keyspace, values);
+                            (Cow::Borrowed(""), values)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let statements = batch
+                    .statements
+                    .iter()
+                    .zip(&redacted)
+                    .map(|(statement, (raw_query, values))| match statement {
+                        BatchStatement::Query { query, .. } => BatchStatement::Query {
+                            query: query.clone(),
+                            raw_query,
+                            values: to_frame(values),
+                        },
+                        BatchStatement::Prepared { id, .. } => BatchStatement::Prepared {
+                            id,
+                            values: to_frame(values),
+                        },
+                    })
+                    .collect();
+                Batch {
+                    statements,
+                    ..batch
+                }
+                .serialize(&mut buf);
+            }
+            Request::StartUp(_)
+            | Request::Options
+            | Request::Register { .. }
+            | Request::AuthResponse { .. }
+            | Request::Other { .. } => return Ok(request.clone()),
+        }
+
+        Ok((*frame, *opcode, buf.freeze()))
+    }
+
+    /// Rows can hold values of redacted columns, they are left out of results and recorded as void ones
+    pub fn response(&self, response: &CassandraResponse) -> CassandraResponse {
+        let (frame, opcode, body) = response;
+        let rows = *opcode == ResponseOpcode::Result && body.get(..4) == Some(&[0, 0, 0, 2]);
+        if self.rules.is_empty() || !rows {
+            return response.clone();
+        }
+
+        (*frame, *opcode, Bytes::from_static(&[0, 0, 0, 1]))
+    }
+
+    /// Statement with literals of redacted columns replaced, `None` if it has none of them
+    fn redacted(&self, query: &QueryString, keyspace: Option<&str>) -> Option<QueryString> {
+        if self.rules.is_empty() {
+            return None;
+        }
+
+        let mut query = query.clone();
+        let mut schema = None;
+        let mut changed = false;
+        walk(&mut query, &mut |column, value| {
+            let Some(rule) = column.and_then(|it| self.rule(it, keyspace)) else {
+                return;
+            };
+            if let QueryValue::FromJson(_) = value {
+                *value = QueryValue::Literal(Literal::Null);
+                changed = true;
+                return;
+            }
+            let QueryValue::Literal(literal) = value else {
+                return;
+            };
+            *literal = match (rule.mode, &*literal, self.column_type(rule, &mut schema)) {
+                (Mode::Hash, Literal::String(v), Some(ColumnType::Text | ColumnType::Ascii)) => {
+                    Literal::String(format!("{:x}", self.digest(v.as_bytes())))
+                }
+                _ => Literal::Null,
+            };
+            changed = true;
+        });
+
+        if let QueryString::Insert(insert @ InsertQuery { json: Some(_), .. }) = &mut query {
+            if let Some(QueryValue::Literal(Literal::String(document))) = insert.values.first() {
+                if let Some(document) = self.document(insert, keyspace, document, &mut schema) {
+                    insert.values[0] = QueryValue::Literal(Literal::String(document));
+                    changed = true;
+                }
+            }
+        }
+
+        changed.then_some(query)
+    }
+
+    /// Text of the statement the request is recorded with, the original one if nothing was redacted
+    fn raw_query<'a>(
+        &self,
+        query: &QueryString,
+        raw_query: &'a str,
+        keyspace: Option<&str>,
+    ) -> Cow<'a, str> {
+        match self.redacted(query, keyspace) {
+            Some(query) => Cow::Owned(query.to_string()),
+            None => Cow::Borrowed(raw_query),
+        }
+    }
+
+    /// Members of `INSERT .. JSON` documents are columns, the document with redacted ones replaced
+    /// or `None` if it has none of them. Documents which can't be parsed are dropped altogether.
+    fn document(
+        &self,
+        insert: &InsertQuery,
+        keyspace: Option<&str>,
+        document: &str,
+        schema: &mut Option<Schema>,
+    ) -> Option<String> {
+        let Ok(serde_json::Value::Object(mut members)) = serde_json::from_str(document) else {
+            return Some("null".to_owned());
+        };
+
+        let mut changed = false;
+        for (name, value) in &mut members {
+            let column = Column {
+                keyspace: insert.keyspace.as_deref(),
+                table: &insert.table,
+                name: &match name.strip_prefix('"').and_then(|it| it.strip_suffix('"')) {
+                    Some(quoted) => quoted.to_owned(),
+                    None => name.to_lowercase(),
+                },
+            };
+            let Some(rule) = self.rule(column, keyspace) else {
+                continue;
+            };
+            *value = match (rule.mode, &*value, self.column_type(rule, schema)) {
+                (
+                    Mode::Hash,
+                    serde_json::Value::String(v),
+                    Some(ColumnType::Text | ColumnType::Ascii),
+                ) => serde_json::Value::String(format!("{:x}", self.digest(v.as_bytes()))),
+                _ => serde_json::Value::Null,
+            };
+            changed = true;
+        }
+
+        changed.then(|| serde_json::Value::Object(members).to_string())
+    }
+
+    fn rule(&self, column: Column<'_>, keyspace: Option<&str>) -> Option<&RedactRule> {
+        let keyspace = column.keyspace.or(keyspace);
+
+        self.rules.iter().find(|rule| {
+            keyspace.is_none_or(|it| it == rule.keyspace)
+                && rule.table == column.table
+                && rule.column == column.name
+        })
+    }
+
+    /// Schema is only read once a value has to be hashed
+    fn column_type(&self, rule: &RedactRule, schema: &mut Option<Schema>) -> Option<ColumnType> {
+        if rule.mode != Mode::Hash {
+            return None;
+        }
+        let schema = schema.get_or_insert_with(|| self.session.schema_snapshot());
+        let table = schema.0.get(&rule.keyspace)?.tables.get(&rule.table)?;
+
+        Some(table.schema.columns.get(&rule.column)?.ty.clone())
+    }
+
+    fn digest(&self, value: &[u8]) -> md5::Digest {
+        let mut context = md5::Context::new();
+        context.consume(&self.salt);
+        context.consume(value);
+        context.compute()
+    }
+}
+
+fn with_values<'a>(
+    parameters: &QueryParameters<'a>,
+    data: Vec<FrameValue<'a>>,
+) -> QueryParameters<'a> {
+    QueryParameters {
+        data,
+        ..parameters.clone()
+    }
+}
+
+fn to_frame(values: &[ReplayValue]) -> Vec<FrameValue<'_>> {
+    values.iter().map(Into::into).collect()
+}
+
+/// Visits values of the statement in the order of its bind markers,
+/// values without a column, like `USING TIMESTAMP` or token relations, get `None`
+fn walk(query: &mut QueryString, f: &mut dyn FnMut(Option<Column<'_>>, &mut QueryValue)) {
+    match query {
+        QueryString::Select(s) => walk_where(s.keyspace.as_deref(), &s.table, &mut s.r#where, f),
+        QueryString::Insert(s) => {
+            // the document of `INSERT .. JSON` is a value without a column
+            for (index, value) in s.values.iter_mut().enumerate() {
+                let column = s.columns.get(index).map(|name| Column {
+                    keyspace: s.keyspace.as_deref(),
+                    table: &s.table,
+                    name,
+                });
+                f(column, value);
+            }
+            if let Some(using) = &mut s.using {
+                f(None, &mut using.value);
+            }
+        }
+        QueryString::Update(s) => {
+            if let Some(using) = &mut s.using {
+                f(None, &mut using.value);
+            }
+            for (name, value) in &mut s.assignments {
+                let column = Column {
+                    keyspace: s.keyspace.as_deref(),
+                    table: &s.table,
+                    name,
+                };
+                f(Some(column), value);
+            }
+            walk_where(s.keyspace.as_deref(), &s.table, &mut s.r#where, f);
+        }
+        QueryString::Delete(s) => {
+            if let Some(using) = &mut s.using {
+                f(None, &mut using.value);
+            }
+            walk_where(s.keyspace.as_deref(), &s.table, &mut s.r#where, f);
+        }
+        QueryString::Batch(s) => {
+            if let Some(using) = &mut s.using {
+                f(None, &mut using.value);
+            }
+            for statement in &mut s.statements {
+                walk(statement, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn walk_where(
+    keyspace: Option<&str>,
+    table: &str,
+    r#where: &mut WhereClosure,
+    f: &mut dyn FnMut(Option<Column<'_>>, &mut QueryValue),
+) {
+    for (name, value) in &mut r#where.statements {
+        f(
+            Some(Column {
+                keyspace,
+                table,
+                name,
+            }),
+            value,
+        );
+    }
+    for relation in &mut r#where.token {
+        f(None, &mut relation.value);
+    }
+    for relation in &mut r#where.clustering {
+        for (name, value) in relation.columns.iter().zip(&mut relation.values) {
+            f(
+                Some(Column {
+                    keyspace,
+                    table,
+                    name,
+                }),
+                value,
+            );
+        }
+    }
+    for (name, value) in &mut r#where.like {
+        f(
+            Some(Column {
+                keyspace,
+                table,
+                name,
+            }),
+            value,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+    use kassandra::{
+        cql::parser,
+        frame::{
+            consistency::{Consistency, SerialConsistency},
+            request::{
+                batch::{Batch, BatchStatement, BatchType},
+                execute::Execute,
+                QueryParameters, Request, RequestOpcode,
+            },
+            value::FrameValue,
+            FrameFlags, FrameParams, ProtocolVersion,
+        },
+        replay::ReplayValue,
+        KassandraSession,
+    };
+
+    use super::{Mode, RedactRule, Redaction};
+    use crate::translator::PreparedQueryTranslator;
+
+    const SALT: &str = "pepper";
+
+    fn redaction(rules: &[&str]) -> Redaction {
+        let mut session = KassandraSession::new();
+        for statement in [
+            "CREATE KEYSPACE shop WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1}",
+            "CREATE TABLE shop.users (id int PRIMARY KEY, email text, name text, avatar blob, age int)",
+            "CREATE TABLE shop.orders (user int, placed int, note text, PRIMARY KEY (user, placed))",
+        ] {
+            session.process_cql(statement).unwrap();
+        }
+        let rules = rules.iter().map(|rule| rule.parse().unwrap()).collect();
+
+        Redaction::new(rules, SALT.to_owned(), session.handle())
+    }
+
+    fn digest(value: &str) -> md5::Digest {
+        md5::compute(format!("{SALT}{value}"))
+    }
+
+    fn value(bytes: &[u8]) -> ReplayValue {
+        ReplayValue::Value(Bytes::copy_from_slice(bytes))
+    }
+
+    /// Values bound to the statement as a client would send them, redacted
+    fn values(redaction: &Redaction, statement: &str, values: &[&[u8]]) -> Vec<ReplayValue> {
+        let query = parser::query(statement).unwrap();
+        let values = values
+            .iter()
+            .map(|it| FrameValue::Some(it))
+            .collect::<Vec<_>>();
+
+        redaction.values(Some(&query), None, &values)
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        assert_eq!(
+            "shop.users.email".parse::<RedactRule>().unwrap(),
+            RedactRule {
+                keyspace: "shop".to_owned(),
+                table: "users".to_owned(),
+                column: "email".to_owned(),
+                mode: Mode::Redact,
+            }
+        );
+        assert_eq!(
+            "shop.users.email=hash".parse::<RedactRule>().unwrap().mode,
+            Mode::Hash
+        );
+
+        let error = |rule: &str| rule.parse::<RedactRule>().unwrap_err().to_string();
+        assert_eq!(
+            error("shop.users.email=drop"),
+            "Unknown redaction mode `drop`, expected `hash`"
+        );
+        for path in ["shop.users", "shop.users.email.domain", "shop..email", ""] {
+            assert_eq!(
+                error(path),
+                format!("Expected `keyspace.table.column`, got `{path}`")
+            );
+        }
+    }
+
+    #[test]
+    fn bind_markers_are_matched_to_columns() {
+        let redaction = redaction(&["shop.users.email", "shop.orders.placed", "shop.orders.note"]);
+        let (id, email, timestamp) = (&[0, 0, 0, 1][..], &b"anna@shop"[..], &[0; 8][..]);
+
+        // `USING` of an insert follows its values
+        assert_eq!(
+            values(
+                &redaction,
+                "INSERT INTO shop.users (id, email) VALUES (?, ?) USING TIMESTAMP ?",
+                &[id, email, timestamp]
+            ),
+            vec![value(id), ReplayValue::Null, value(timestamp)]
+        );
+        // while it comes before the assignments and restrictions of updates and deletes
+        assert_eq!(
+            values(
+                &redaction,
+                "UPDATE shop.users USING TIMESTAMP ? SET email = ? WHERE id = ?",
+                &[timestamp, email, id]
+            ),
+            vec![value(timestamp), ReplayValue::Null, value(id)]
+        );
+        assert_eq!(
+            values(
+                &redaction,
+                "DELETE FROM shop.users USING TIMESTAMP ? WHERE id = ? AND email = ?",
+                &[timestamp, id, email]
+            ),
+            vec![value(timestamp), value(id), ReplayValue::Null]
+        );
+
+        // token relations have no column, slices and patterns are matched to theirs
+        assert_eq!(
+            values(
+                &redaction,
+                "SELECT * FROM shop.orders WHERE user = ? AND token(user) > ? AND placed > ? AND note LIKE ?",
+                &[id, timestamp, id, b"gift%"]
+            ),
+            vec![value(id), value(timestamp), ReplayValue::Null, ReplayValue::Null]
+        );
+        assert_eq!(
+            redaction.statement(
+                &parser::query(
+                    "SELECT * FROM shop.orders WHERE user = 1 AND placed > 5 AND note LIKE 'gift%'"
+                )
+                .unwrap(),
+                None
+            ),
+            "SELECT * FROM shop.orders WHERE user = 1 AND placed > null AND note LIKE null"
+        );
+
+        // unqualified statements match rules of any keyspace
+        let query = parser::query("UPDATE users SET email = ? WHERE id = ?").unwrap();
+        assert_eq!(
+            redaction.values(
+                Some(&query),
+                Some("other"),
+                &[FrameValue::Some(email), FrameValue::Some(id)]
+            ),
+            vec![value(email), value(id)]
+        );
+        assert_eq!(
+            redaction.values(
+                Some(&query),
+                None,
+                &[FrameValue::Some(email), FrameValue::Some(id)]
+            ),
+            vec![ReplayValue::Null, value(id)]
+        );
+    }
+
+    #[test]
+    fn insert_json_documents_are_redacted() {
+        let redaction = redaction(&["shop.users.email", "shop.users.name=hash"]);
+        let document = r#"{"id": 1, "email": "anna@shop", "name": "Anna"}"#;
+        let redacted = serde_json::json!({
+            "id": 1,
+            "email": null,
+            "name": format!("{:x}", digest("Anna")),
+        });
+
+        let statement = redaction.statement(
+            &parser::query(&format!("INSERT INTO shop.users JSON '{document}'")).unwrap(),
+            None,
+        );
+        let literal = statement
+            .strip_prefix("INSERT INTO shop.users JSON '")
+            .and_then(|it| it.strip_suffix('\''))
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(literal).unwrap(),
+            redacted
+        );
+
+        let [ReplayValue::Value(bound)] = &values(
+            &redaction,
+            "INSERT INTO shop.users JSON ?",
+            &[document.as_bytes()],
+        )[..] else {
+            panic!("document is not bound");
+        };
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(bound).unwrap(),
+            redacted
+        );
+
+        // documents which can't be parsed could hold anything
+        assert_eq!(
+            values(
+                &redaction,
+                "INSERT INTO shop.users JSON ?",
+                &[b"{\"email\": "]
+            ),
+            vec![value(b"null")]
+        );
+        // json of a single column can't be hashed as its value
+        assert_eq!(
+            values(
+                &redaction,
+                "INSERT INTO shop.users (id, name) VALUES (?, fromJson(?))",
+                &[&[0, 0, 0, 1], b"\"Anna\""]
+            ),
+            vec![value(&[0, 0, 0, 1]), ReplayValue::Null]
+        );
+    }
+
+    #[test]
+    fn text_and_blob_values_are_hashed() {
+        let redaction = redaction(&[
+            "shop.users.name=hash",
+            "shop.users.avatar=hash",
+            "shop.users.age=hash",
+        ]);
+
+        let hashed = values(
+            &redaction,
+            "INSERT INTO shop.users (id, name, avatar, age) VALUES (?, ?, ?, ?)",
+            &[&[0, 0, 0, 1], b"Anna", b"Anna", &[0, 0, 0, 30]],
+        );
+        assert_eq!(
+            hashed,
+            vec![
+                value(&[0, 0, 0, 1]),
+                // text is hashed into hex digests, blobs into raw ones
+                value(format!("{:x}", digest("Anna")).as_bytes()),
+                value(&digest("Anna").0),
+                // other types can't hold a digest
+                ReplayValue::Null,
+            ]
+        );
+
+        // equal values stay equal
+        assert_eq!(
+            values(
+                &redaction,
+                "UPDATE shop.users SET name = ? WHERE id = ?",
+                &[b"Anna", &[0, 0, 0, 2]]
+            )[0],
+            hashed[1]
+        );
+
+        assert_eq!(
+            redaction.statement(
+                &parser::query("INSERT INTO shop.users (id, name, age) VALUES (1, 'Anna', 30)")
+                    .unwrap(),
+                None
+            ),
+            format!(
+                "INSERT INTO shop.users (id, name, age) VALUES (1, '{:x}', null)",
+                digest("Anna")
+            )
+        );
+    }
+
+    fn replayed(values: &[FrameValue<'_>]) -> Vec<ReplayValue> {
+        values.iter().map(ReplayValue::from).collect()
+    }
+
+    fn request(opcode: RequestOpcode, request: Request<'_>) -> (FrameParams, RequestOpcode, Bytes) {
+        let mut body = BytesMut::new();
+        request.serialize(&mut body).unwrap();
+        let frame = FrameParams {
+            version: ProtocolVersion::V4,
+            flags: FrameFlags::empty(),
+            stream: 1,
+        };
+
+        (frame, opcode, body.freeze())
+    }
+
+    #[test]
+    fn values_of_unknown_prepared_statements_are_dropped() {
+        let redaction = redaction(&["shop.users.email"]);
+        let translator = PreparedQueryTranslator::new();
+        let id = 7u128.to_be_bytes();
+        let execute = request(
+            RequestOpcode::Execute,
+            Request::Execute(Execute {
+                id: &id,
+                parameters: QueryParameters {
+                    data: vec![
+                        FrameValue::Some(&[0, 0, 0, 1]),
+                        FrameValue::Null,
+                        FrameValue::NotSet,
+                    ],
+                    ..Default::default()
+                },
+            }),
+        );
+
+        let (_, _, body) = redaction.request(&execute, None, &translator).unwrap();
+        let Request::Execute(redacted) =
+            Request::deserialize(RequestOpcode::Execute, &body, FrameFlags::empty()).unwrap()
+        else {
+            panic!("invalid request");
+        };
+        assert_eq!(
+            replayed(&redacted.parameters.data),
+            vec![ReplayValue::Null, ReplayValue::Null, ReplayValue::NotSet]
+        );
+
+        // once the statement is known only its redacted columns are dropped
+        translator.insert(
+            7,
+            parser::query("UPDATE shop.users SET email = ? WHERE id = ?").unwrap(),
+        );
+        let execute = request(
+            RequestOpcode::Execute,
+            Request::Execute(Execute {
+                id: &id,
+                parameters: QueryParameters {
+                    data: vec![
+                        FrameValue::Some(b"anna@shop"),
+                        FrameValue::Some(&[0, 0, 0, 1]),
+                    ],
+                    ..Default::default()
+                },
+            }),
+        );
+        let (_, _, body) = redaction.request(&execute, None, &translator).unwrap();
+        let Request::Execute(redacted) =
+            Request::deserialize(RequestOpcode::Execute, &body, FrameFlags::empty()).unwrap()
+        else {
+            panic!("invalid request");
+        };
+        assert_eq!(
+            replayed(&redacted.parameters.data),
+            vec![ReplayValue::Null, value(&[0, 0, 0, 1])]
+        );
+    }
+
+    #[test]
+    fn batches_are_redacted() {
+        let redaction = redaction(&["shop.users.email"]);
+        let translator = PreparedQueryTranslator::new();
+        translator.insert(
+            1,
+            parser::query("INSERT INTO shop.users (id, email) VALUES (?, ?)").unwrap(),
+        );
+        let (known, unknown) = (1u128.to_be_bytes(), 2u128.to_be_bytes());
+        let raw_query = "UPDATE shop.users SET email = 'anna@shop' WHERE id = ?";
+        let batch = request(
+            RequestOpcode::Batch,
+            Request::Batch(Batch {
+                batch_type: BatchType::Logged,
+                consistency: Consistency::One,
+                serial_consistency: SerialConsistency::Serial,
+                timestamp: None,
+                statements: vec![
+                    BatchStatement::Query {
+                        query: Box::new(parser::query(raw_query).unwrap()),
+                        raw_query,
+                        values: vec![FrameValue::Some(&[0, 0, 0, 1])],
+                    },
+                    BatchStatement::Prepared {
+                        id: &known,
+                        values: vec![
+                            FrameValue::Some(&[0, 0, 0, 2]),
+                            FrameValue::Some(b"lotte@shop"),
+                        ],
+                    },
+                    BatchStatement::Prepared {
+                        id: &unknown,
+                        values: vec![FrameValue::Some(b"marianne@shop")],
+                    },
+                ],
+            }),
+        );
+
+        let (_, _, body) = redaction.request(&batch, None, &translator).unwrap();
+        let Request::Batch(redacted) =
+            Request::deserialize(RequestOpcode::Batch, &body, FrameFlags::empty()).unwrap()
+        else {
+            panic!("invalid request");
+        };
+        let statements = redacted
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                BatchStatement::Query {
+                    raw_query, values, ..
+                } => (Some(raw_query.to_string()), replayed(values)),
+                BatchStatement::Prepared { values, .. } => (None, replayed(values)),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statements,
+            vec![
+                (
+                    Some("UPDATE shop.users SET email = null WHERE id = ?".to_owned()),
+                    vec![value(&[0, 0, 0, 1])]
+                ),
+                (None, vec![value(&[0, 0, 0, 2]), ReplayValue::Null]),
+                (None, vec![ReplayValue::Null]),
+            ]
+        );
+    }
+}
@@ -43,6 +43,11 @@ impl ReplayInterceptor {
         }
     }
 
+    /// Keyspace replayed `USE` statements selected last
+    pub fn keyspace(&self) -> Option<&str> {
+        self.session.keyspace()
+    }
+
     pub fn snapshot(&self) -> DataSnapshots {
         self.session.data_snapshot()
     }
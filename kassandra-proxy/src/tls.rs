@@ -0,0 +1,92 @@
+use std::{fs::File, io::BufReader, net::SocketAddr, path::Path, sync::Arc};
+
+use stable_eyre::eyre::{self, eyre, Context};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        pki_types::{CertificateDer, ServerName},
+        ClientConfig, RootCertStore, ServerConfig,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+/// Either plain tcp or tls stream
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Terminates tls of client connections
+pub fn acceptor(cert: &Path, key: &Path) -> eyre::Result<TlsAcceptor> {
+    let certs = load_certs(cert)?;
+    let key = rustls_pemfile::private_key(&mut open(key)?)?
+        .ok_or_else(|| eyre!("No private key found in {}", key.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .wrap_err("invalid tls certificate or key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Tls settings of connections to upstream nodes
+pub struct UpstreamTls {
+    connector: TlsConnector,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl UpstreamTls {
+    /// Without `ca` upstream certificates are verified against webpki roots,
+    /// without `server_name` against the ip address of the node.
+    pub fn new(ca: Option<&Path>, server_name: Option<String>) -> eyre::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        match ca {
+            Some(ca) => {
+                for cert in load_certs(ca)? {
+                    roots.add(cert)?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name: server_name.map(ServerName::try_from).transpose()?,
+        })
+    }
+
+    pub async fn connect(
+        &self,
+        addr: SocketAddr,
+        stream: TcpStream,
+    ) -> eyre::Result<impl Connection> {
+        let server_name = self
+            .server_name
+            .clone()
+            .unwrap_or_else(|| ServerName::IpAddress(addr.ip().into()));
+
+        Ok(self.connector.connect(server_name, stream).await?)
+    }
+}
+
+fn load_certs(path: &Path) -> eyre::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(eyre!("No certificates found in {}", path.display()));
+    }
+
+    Ok(certs)
+}
+
+fn open(path: &Path) -> eyre::Result<BufReader<File>> {
+    let file = File::open(path).wrap_err_with(|| format!("while opening {}", path.display()))?;
+
+    Ok(BufReader::new(file))
+}
@@ -1,33 +1,71 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
+use bytes::Bytes;
 use kassandra::{
-    cql::query::QueryString,
+    client::CqlConnection,
+    cql::{parser, query::QueryString},
     frame::{
         parse,
-        request::{Request, RequestOpcode},
+        request::{
+            batch::BatchStatement, prepare::Prepare, QueryParameters, Request, RequestOpcode,
+        },
         response::ResponseOpcode,
+        value::FrameValue,
     },
 };
 use parking_lot::Mutex;
-use stable_eyre::eyre::{self, eyre};
+use stable_eyre::eyre::{self, eyre, Context};
 use tokio::sync::broadcast::Receiver;
 
 use super::{CassandraRequest, CassandraResponse};
+use crate::upstream::Upstreams;
 
 #[derive(Clone)]
 pub struct PreparedQueryTranslator {
     cache: Arc<Mutex<HashMap<u128, QueryString>>>,
+    /// Ids upstream could not resolve, so they are not looked up again
+    unresolved: Arc<Mutex<HashSet<u128>>>,
+    /// File the translations are kept in, see [`PreparedQueryTranslator::persisted`]
+    path: Option<Arc<PathBuf>>,
 }
 
 impl PreparedQueryTranslator {
     pub fn new() -> Self {
         Self {
             cache: Arc::new(Mutex::new(HashMap::default())),
+            unresolved: Arc::new(Mutex::new(HashSet::default())),
+            path: None,
         }
     }
+
+    /// Translator preloaded from the file, if it exists, which is rewritten with every new translation,
+    /// so statements prepared before a restart of the proxy are still translated.
+    ///
+    /// Translations are kept as a json object of hex encoded ids and statements.
+    pub fn persisted(path: &Path) -> eyre::Result<Self> {
+        let mut translator = Self::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(path).wrap_err("while reading translations")?;
+            let translations: BTreeMap<String, String> =
+                serde_json::from_str(&content).wrap_err("invalid translations file")?;
+
+            let mut cache = translator.cache.lock();
+            for (id, query) in translations {
+                let id = u128::from_str_radix(&id, 16)
+                    .wrap_err_with(|| format!("invalid prepared id {id}"))?;
+                cache.insert(id, parser::query(&query)?);
+            }
+            tracing::info!(translations = cache.len(), "Preloaded prepared statements");
+        }
+        translator.path = Some(Arc::new(path.to_owned()));
+
+        Ok(translator)
+    }
+
     pub fn translate(&self, id: &[u8]) -> eyre::Result<QueryString> {
         let id = u128::from_be_bytes(id.try_into()?);
         let cache = self.cache.lock();
@@ -40,11 +78,172 @@ impl PreparedQueryTranslator {
 
     pub fn insert(&self, id: u128, query: QueryString) {
         self.cache.lock().insert(id, query);
+
+        if let Err(error) = self.save() {
+            tracing::error!(?error, "Could not save translations");
+        }
     }
 
     pub fn read_all(&self) -> impl Iterator<Item = (u128, QueryString)> {
         self.cache.lock().clone().into_iter()
     }
+
+    fn save(&self) -> eyre::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let translations = self
+            .cache
+            .lock()
+            .iter()
+            .map(|(id, query)| (format!("{id:032x}"), query.to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        std::fs::write(&**path, serde_json::to_string_pretty(&translations)?)?;
+
+        Ok(())
+    }
+
+    /// Ids of the request, which are not translated yet
+    pub fn unknown_ids(&self, request: &Request<'_>) -> Vec<u128> {
+        let ids = match request {
+            Request::Execute(execute) => vec![execute.id],
+            Request::Batch(batch) => batch
+                .statements
+                .iter()
+                .filter_map(|statement| match statement {
+                    BatchStatement::Prepared { id, .. } => Some(*id),
+                    BatchStatement::Query { .. } => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let cache = self.cache.lock();
+        let unresolved = self.unresolved.lock();
+
+        ids.into_iter()
+            .filter_map(|id| Some(u128::from_be_bytes(id.try_into().ok()?)))
+            .filter(|id| !cache.contains_key(id) && !unresolved.contains(id))
+            .collect()
+    }
+
+    /// Translates a statement prepared before the proxy started.
+    ///
+    /// Cassandra keeps prepared statements in `system.prepared_statements`, the statement found there
+    /// is prepared again, to make sure upstream still gives it the same id. Ids which can't be resolved
+    /// are remembered and not looked up again.
+    pub async fn resolve(&self, upstreams: &Upstreams, id: u128) -> eyre::Result<QueryString> {
+        let result = async {
+            let mut connection = upstreams.client().await?;
+            let (keyspace, query) = lookup(&mut connection, id)
+                .await?
+                .ok_or_else(|| eyre!("Statement is not in system.prepared_statements"))?;
+
+            if let Some(keyspace) = keyspace {
+                connection
+                    .query(&format!("USE \"{keyspace}\""), &QueryParameters::default())
+                    .await?;
+            }
+            let prepared = connection.prepare(&query).await?;
+            if prepared.as_ref() != id.to_be_bytes() {
+                return Err(eyre!("Upstream prepared the statement with another id"));
+            }
+
+            Ok(parser::query(&query)?)
+        }
+        .await;
+
+        match &result {
+            Ok(query) => self.insert(id, query.clone()),
+            Err(_) => {
+                self.unresolved.lock().insert(id);
+            }
+        }
+
+        result
+    }
+}
+
+/// Keyspace and text of the prepared statement
+async fn lookup(
+    connection: &mut CqlConnection<impl tokio::io::AsyncRead + tokio::io::AsyncWrite>,
+    id: u128,
+) -> eyre::Result<Option<(Option<String>, String)>> {
+    let id = id.to_be_bytes();
+    let parameters = QueryParameters {
+        data: vec![FrameValue::Some(&id)],
+        ..Default::default()
+    };
+    let body = connection
+        .query(
+            "SELECT logged_keyspace, query_string FROM system.prepared_statements WHERE prepared_id = ?",
+            &parameters,
+        )
+        .await?;
+
+    let mut row = text_rows(body)?.into_iter().next().into_iter().flatten();
+    let (Some(keyspace), Some(Some(query))) = (row.next(), row.next()) else {
+        return Ok(None);
+    };
+
+    Ok(Some((keyspace, query)))
+}
+
+/// Cells of `Result::Rows` with text columns only
+fn text_rows(body: Bytes) -> eyre::Result<Vec<Vec<Option<String>>>> {
+    const GLOBAL_TABLES_SPEC: i32 = 0x0001;
+    const HAS_MORE_PAGES: i32 = 0x0002;
+    const NO_METADATA: i32 = 0x0004;
+    let malformed = |_| eyre!("Malformed rows");
+
+    let (rest, kind) = int(&body)?;
+    if kind != 0x0002 {
+        return Err(eyre!("Unexpected result kind, expected rows"));
+    }
+    let (rest, flags) = int(rest)?;
+    let (mut rest, columns) = int(rest)?;
+    if flags & HAS_MORE_PAGES != 0 {
+        (rest, _) = parse::bytes_opt(rest).map_err(malformed)?;
+    }
+    if flags & NO_METADATA == 0 {
+        // keyspace and table, once for all columns or for each of them
+        let (global, specs) = match flags & GLOBAL_TABLES_SPEC {
+            0 => (0, 3),
+            _ => (2, 1),
+        };
+        for _ in 0..global {
+            (rest, _) = parse::short_string(rest).map_err(malformed)?;
+        }
+        for _ in 0..columns {
+            for _ in 0..specs {
+                (rest, _) = parse::short_string(rest).map_err(malformed)?;
+            }
+            // types of text columns have no nested types
+            rest = rest.get(2..).ok_or_else(|| eyre!("Malformed rows"))?;
+        }
+    }
+
+    let (mut rest, rows) = int(rest)?;
+    let mut result = vec![];
+    for _ in 0..rows {
+        let mut row = vec![];
+        for _ in 0..columns {
+            let cell;
+            (rest, cell) = parse::bytes_opt(rest).map_err(malformed)?;
+            row.push(cell.map(|it| String::from_utf8_lossy(it).into_owned()));
+        }
+        result.push(row);
+    }
+
+    Ok(result)
+}
+
+fn int(input: &[u8]) -> eyre::Result<(&[u8], i32)> {
+    let (int, rest) = input
+        .split_first_chunk()
+        .ok_or_else(|| eyre!("Unexpected end of rows"))?;
+
+    Ok((rest, i32::from_be_bytes(*int)))
 }
 
 pub async fn translation_loop(
@@ -62,7 +261,7 @@ pub async fn translation_loop(
                     continue;
                 }
 
-                let Request::Prepare(query) = Request::deserialize(opcode, body.as_ref(), frame.flags).unwrap() else {
+                let Request::Prepare(Prepare { query, .. }) = Request::deserialize(opcode, body.as_ref(), frame.flags).unwrap() else {
                     unreachable!("opcode was Prepare")
                 };
 
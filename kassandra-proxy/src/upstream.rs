@@ -0,0 +1,293 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use clap::ValueEnum;
+use futures::{SinkExt, StreamExt};
+use kassandra::{
+    client::CqlConnection,
+    frame::{
+        raw_request_sink, request::RequestOpcode, response::ResponseOpcode, response_stream,
+        FrameFlags, FrameParams, ProtocolVersion,
+    },
+};
+use stable_eyre::eyre::{self, eyre};
+use tokio::{net::TcpStream, time::timeout};
+
+use crate::tls::{Connection, UpstreamTls};
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How client connections are spread over upstream nodes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Routing {
+    /// Every new connection goes to the next node
+    #[default]
+    RoundRobin,
+    /// Connections of the same client address always go to the same node, while it is healthy
+    Consistent,
+}
+
+/// Contact points of the upstream cluster
+pub struct Upstreams {
+    nodes: Vec<Upstream>,
+    routing: Routing,
+    next: AtomicUsize,
+    tls: Option<UpstreamTls>,
+}
+
+#[derive(Debug)]
+struct Upstream {
+    addr: SocketAddr,
+    healthy: AtomicBool,
+}
+
+impl Upstreams {
+    pub fn new(addrs: Vec<SocketAddr>, routing: Routing, tls: Option<UpstreamTls>) -> Self {
+        Self {
+            nodes: addrs
+                .into_iter()
+                .map(|addr| Upstream {
+                    addr,
+                    healthy: AtomicBool::new(true),
+                })
+                .collect(),
+            routing,
+            next: AtomicUsize::new(0),
+            tls,
+        }
+    }
+
+    /// Connects to the node chosen for the client, falling back to the following nodes if it is down.
+    pub async fn connect(
+        &self,
+        client: SocketAddr,
+    ) -> eyre::Result<(SocketAddr, Box<dyn Connection>)> {
+        for node in self.candidates(client) {
+            match self.connect_node(node.addr).await {
+                Ok(stream) => {
+                    node.healthy.store(true, Ordering::Relaxed);
+                    return Ok((node.addr, stream));
+                }
+                Err(error) => {
+                    tracing::warn!(?error, upstream = %node.addr, "Could not connect to upstream");
+                    node.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+
+        Err(eyre!("None of upstream nodes is available"))
+    }
+
+    /// Client connection of the proxy itself to any available node, `STARTUP` is already sent
+    pub async fn client(&self) -> eyre::Result<CqlConnection<Box<dyn Connection>>> {
+        let client = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        let (_, stream) = self.connect(client).await?;
+        let mut connection = CqlConnection::new(stream);
+        connection.startup().await?;
+
+        Ok(connection)
+    }
+
+    async fn connect_node(&self, addr: SocketAddr) -> eyre::Result<Box<dyn Connection>> {
+        let stream = TcpStream::connect(addr).await?;
+
+        Ok(match &self.tls {
+            Some(tls) => Box::new(tls.connect(addr, stream).await?),
+            None => Box::new(stream),
+        })
+    }
+
+    /// Nodes in the order they should be tried, unhealthy ones go last,
+    /// so they are still tried when everything seems to be down.
+    fn candidates(&self, client: SocketAddr) -> Vec<&Upstream> {
+        let start = match self.routing {
+            Routing::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+            Routing::Consistent => {
+                let mut hasher = DefaultHasher::new();
+                client.ip().hash(&mut hasher);
+                hasher.finish() as usize
+            }
+        };
+
+        let mut nodes = (0..self.nodes.len())
+            .map(|i| &self.nodes[(start + i) % self.nodes.len()])
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|node| !node.healthy.load(Ordering::Relaxed));
+
+        nodes
+    }
+
+    /// Periodically sends `OPTIONS` to every node, nodes replying with `SUPPORTED` are considered healthy.
+    pub async fn health_check_loop(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            for node in &self.nodes {
+                let healthy = matches!(
+                    timeout(HEALTH_CHECK_TIMEOUT, self.check(node.addr)).await,
+                    Ok(Ok(()))
+                );
+                let was_healthy = node.healthy.swap(healthy, Ordering::Relaxed);
+                if healthy != was_healthy {
+                    tracing::info!(upstream = %node.addr, healthy, "Upstream health changed");
+                }
+            }
+        }
+    }
+
+    async fn check(&self, addr: SocketAddr) -> eyre::Result<()> {
+        let (read, write) = tokio::io::split(self.connect_node(addr).await?);
+        let mut requests = raw_request_sink(write);
+        let mut responses = Box::pin(response_stream(read));
+
+        let frame = FrameParams {
+            version: ProtocolVersion::V4,
+            flags: FrameFlags::empty(),
+            stream: 0,
+        };
+        requests
+            .send((frame, RequestOpcode::Options, Bytes::new()))
+            .await?;
+
+        match responses.next().await {
+            Some(Ok((_, ResponseOpcode::Supported, _))) => Ok(()),
+            Some(Ok((_, opcode, _))) => Err(eyre!("Unexpected response to OPTIONS: {opcode:?}")),
+            Some(Err(error)) => Err(error),
+            None => Err(eyre!("Connection closed")),
+        }
+    }
+}
+
+/// Accepts `host:port` or just a port of a local node
+pub fn parse_upstream(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = value.parse::<u16>() {
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port));
+    }
+
+    value
+        .to_socket_addrs()
+        .map_err(|error| error.to_string())?
+        .next()
+        .ok_or_else(|| format!("`{value}` does not resolve to any address"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::atomic::Ordering,
+    };
+
+    use tokio::net::TcpListener;
+
+    use super::{parse_upstream, Routing, Upstreams};
+
+    fn upstreams(routing: Routing) -> Upstreams {
+        Upstreams::new((9001..=9003).map(node).collect(), routing, None)
+    }
+
+    fn node(port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+    }
+
+    fn client(ip: [u8; 4], port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(ip.into()), port)
+    }
+
+    fn ports(upstreams: &Upstreams, client: SocketAddr) -> Vec<u16> {
+        upstreams
+            .candidates(client)
+            .iter()
+            .map(|node| node.addr.port())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_rotates_over_nodes() {
+        let upstreams = upstreams(Routing::RoundRobin);
+        let client = client([10, 0, 0, 1], 5000);
+
+        assert_eq!(ports(&upstreams, client), [9001, 9002, 9003]);
+        assert_eq!(ports(&upstreams, client), [9002, 9003, 9001]);
+        assert_eq!(ports(&upstreams, client), [9003, 9001, 9002]);
+        assert_eq!(ports(&upstreams, client), [9001, 9002, 9003]);
+    }
+
+    #[test]
+    fn consistent_routing_sticks_to_client_address() {
+        let upstreams = upstreams(Routing::Consistent);
+        let first = ports(&upstreams, client([10, 0, 0, 1], 5000));
+
+        assert_eq!(ports(&upstreams, client([10, 0, 0, 1], 5001)), first);
+        assert_eq!(ports(&upstreams, client([10, 0, 0, 1], 5000)), first);
+
+        let spread = (0..=255)
+            .map(|i| ports(&upstreams, client([10, 0, 0, i], 5000))[0])
+            .collect::<HashSet<_>>();
+        assert_eq!(spread.len(), 3);
+    }
+
+    #[test]
+    fn unhealthy_nodes_go_last() {
+        for routing in [Routing::RoundRobin, Routing::Consistent] {
+            let upstreams = upstreams(routing);
+            upstreams.nodes[0].healthy.store(false, Ordering::Relaxed);
+            upstreams.nodes[2].healthy.store(false, Ordering::Relaxed);
+
+            for i in 0..3 {
+                let ports = ports(&upstreams, client([10, 0, 0, i], 5000));
+                assert_eq!(ports[0], 9002);
+                assert_eq!(ports.len(), 3);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_falls_back_to_available_node() {
+        let down = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down_addr = down.local_addr().unwrap();
+        drop(down);
+        let up = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up_addr = up.local_addr().unwrap();
+
+        let upstreams = Upstreams::new(vec![down_addr, up_addr], Routing::RoundRobin, None);
+        let (addr, _) = upstreams
+            .connect(client([10, 0, 0, 1], 5000))
+            .await
+            .unwrap();
+
+        assert_eq!(addr, up_addr);
+        assert!(!upstreams.nodes[0].healthy.load(Ordering::Relaxed));
+        assert!(upstreams.nodes[1].healthy.load(Ordering::Relaxed));
+
+        drop(up);
+        assert!(upstreams
+            .connect(client([10, 0, 0, 1], 5000))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn parses_upstream_addresses() {
+        assert_eq!(parse_upstream("9042"), Ok(node(9042)));
+        assert_eq!(
+            parse_upstream("10.1.2.3:9142"),
+            Ok(client([10, 1, 2, 3], 9142))
+        );
+        assert_eq!(parse_upstream("localhost:9042").unwrap().port(), 9042);
+        assert!(parse_upstream("10.1.2.3").is_err());
+        assert!(parse_upstream("10.1.2.3:port").is_err());
+    }
+}
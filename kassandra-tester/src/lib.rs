@@ -1,39 +1,51 @@
-use std::{
-    future::Future,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-};
+use std::{future::Future, net::SocketAddr, time::Duration};
 
-use futures_util::{SinkExt, StreamExt};
+#[doc(hidden)]
+pub use eyre;
+use futures_util::{stream::FuturesUnordered, SinkExt, StreamExt};
 pub use kassandra;
 use kassandra::{
+    cql,
     error::DbError,
     frame::{
-        request::Request,
+        parse,
+        request::{Request, RequestOpcode},
         request_stream,
         response::{error::Error, Response},
         response_sink,
     },
-    session::KassandraSession,
+    session::{ConnectionState, KassandraSession, SessionHandle},
 };
 use tokio::{
     net::{TcpListener, TcpStream},
-    select, task,
+    select, task, time,
 };
 
+/// Serves a session to clients, its engine is the in-memory one unless another is given,
+/// e.g. a [`RemoteEngine`](kassandra::cql::engine::remote::RemoteEngine) to run the same test against a real cluster.
 #[derive(Debug, Clone)]
-pub struct KassandraTester {
-    kassandra: Arc<Mutex<KassandraSession>>,
+pub struct KassandraTester<
+    Engine: cql::Engine = cql::engine::kv::KvEngine<kassandra::storage::memory::Memory>,
+> {
+    kassandra: KassandraSession<Engine>,
+    /// Connections not sending requests for this long are closed, like by `kassandra-node --idle-timeout`
+    idle_timeout: Option<Duration>,
 }
 
-impl KassandraTester {
-    pub fn new(kassandra: KassandraSession) -> Self {
+impl<Engine: cql::Engine> KassandraTester<Engine> {
+    pub fn new(kassandra: KassandraSession<Engine>) -> Self {
         Self {
-            kassandra: Arc::new(Mutex::new(kassandra)),
+            kassandra,
+            idle_timeout: None,
         }
     }
 
-    pub async fn in_scope<F, Fut, E>(mut self, mut block: F) -> Result<KassandraSession, E>
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub async fn in_scope<F, Fut, E>(self, mut block: F) -> Result<KassandraSession<Engine>, E>
     where
         F: FnMut(SocketAddr) -> Fut,
         Fut: Future<Output = Result<(), E>>,
@@ -43,41 +55,159 @@ impl KassandraTester {
 
         select! {
             _ = self.serve(listener) => {},
-            _ = block(addr) => {}
+            result = block(addr) => result?,
         }
 
-        let kassandra = Arc::try_unwrap(self.kassandra)
-            .expect("Some clients still alive")
-            .into_inner()
-            .expect("Mutex is poisoned");
-
-        Ok(kassandra)
+        Ok(self.kassandra)
     }
 
-    async fn serve(&mut self, listener: TcpListener) {
+    async fn serve(&self, listener: TcpListener) {
         let tasks = task::LocalSet::new();
         tasks
             .run_until(async move {
+                // connections are assigned to the shards of the session in turns
+                let mut shard = 0;
                 loop {
                     let Ok((stream, _)) = listener.accept().await else {
                         continue;
                     };
-                    task::spawn_local(self.clone().client(stream));
+                    let client = Client {
+                        kassandra: self.kassandra.handle(),
+                        connection: ConnectionState::new()
+                            .with_streamed_rows()
+                            .with_shard(shard),
+                        idle_timeout: self.idle_timeout,
+                    };
+                    shard += 1;
+                    task::spawn_local(client.run(stream));
                 }
             })
             .await;
     }
+}
+
+/// Declares a `#[tokio::test]` which runs its body against a fresh tester
+/// with a scylla `Session` connected to it, and snapshot-asserts data left after it.
+///
+/// The test crate has to depend on `tokio`, `scylla` and `insta` itself.
+///
+/// ```ignore
+/// kassandra_test! {
+///     async fn insert(session) {
+///         session.query("CREATE KEYSPACE ks ...", ()).await?;
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// Initial state can be passed as a second argument: `async fn insert(session, KassandraSession::new()) { .. }`.
+///
+/// When `KASSANDRA_TEST_REMOTE` holds the address of a real node, the body is run once more
+/// against a [`RemoteEngine`](kassandra::cql::engine::remote::RemoteEngine) connected to it, without the initial state.
+/// Keyspaces created by the body are left in the cluster, so it has to drop them or tolerate existing ones.
+#[macro_export]
+macro_rules! kassandra_test {
+    ($(#[$meta:meta])* async fn $name:ident($session:ident) $body:block) => {
+        $crate::kassandra_test! {
+            $(#[$meta])*
+            async fn $name($session, $crate::kassandra::KassandraSession::new()) $body
+        }
+    };
+    ($(#[$meta:meta])* async fn $name:ident($session:ident, $kassandra:expr) $body:block) => {
+        $(#[$meta])*
+        #[tokio::test]
+        async fn $name() -> $crate::eyre::Result<()> {
+            let kassandra = $crate::KassandraTester::new($kassandra)
+                .in_scope(|addr| async move {
+                    let $session = scylla::SessionBuilder::new()
+                        .known_node(addr.to_string())
+                        .build()
+                        .await?;
+                    let result: $crate::eyre::Result<()> = async move $body.await;
+                    result
+                })
+                .await?;
+
+            insta::assert_yaml_snapshot!(kassandra.data_snapshot());
+
+            if let Ok(remote) = std::env::var("KASSANDRA_TEST_REMOTE") {
+                let engine = $crate::kassandra::cql::engine::remote::RemoteEngine::connect(remote.parse()?)?;
+                $crate::KassandraTester::new($crate::kassandra::KassandraSession::from_engine(
+                    engine,
+                    Default::default(),
+                ))
+                .in_scope(|addr| async move {
+                    let $session = scylla::SessionBuilder::new()
+                        .known_node(addr.to_string())
+                        .build()
+                        .await?;
+                    let result: $crate::eyre::Result<()> = async move $body.await;
+                    result
+                })
+                .await?;
+            }
+
+            Ok(())
+        }
+    };
+}
+
+/// Connection of a single client, sharing the session with the other ones
+struct Client<Engine: cql::Engine> {
+    kassandra: SessionHandle<Engine>,
+    connection: ConnectionState,
+    idle_timeout: Option<Duration>,
+}
 
-    async fn client(mut self, mut stream: TcpStream) {
+impl<Engine: cql::Engine> Client<Engine> {
+    async fn run(mut self, mut stream: TcpStream) {
         let (mut read, mut write) = stream.split();
         let mut stream = request_stream(&mut read);
         let mut sink = response_sink(&mut write);
+        // responses delayed by the latency policy, each one is sent once it is due,
+        // so a slow statement doesn't hold up the responses of the other streams
+        let mut delayed = FuturesUnordered::new();
+        let mut last_request = time::Instant::now();
 
-        while let Some(frame) = stream.next().await {
+        loop {
+            // connections waiting for responses are not idle
+            let idle_until = self
+                .idle_timeout
+                .filter(|_| delayed.is_empty())
+                .map(|timeout| last_request + timeout);
+            let frame = select! {
+                Some((response, stream)) = delayed.next(), if !delayed.is_empty() => {
+                    let _ = sink.send((response, stream)).await;
+                    continue;
+                }
+                _ = time::sleep_until(idle_until.unwrap_or_else(time::Instant::now)), if idle_until.is_some() => {
+                    tracing::info!("Closing idle connection");
+                    break;
+                }
+                frame = stream.next() => frame,
+            };
+            last_request = time::Instant::now();
+            let Some(frame) = frame else {
+                break;
+            };
             match frame {
                 Ok((frame, opcode, data)) => {
                     let request = match Request::deserialize(opcode, &data, frame.flags) {
                         Ok(req) => req,
+                        Err(er)
+                            if opcode == RequestOpcode::Query
+                                && er.error == DbError::Unimplemented =>
+                        {
+                            let statement =
+                                parse::long_string(&data).map_or("", |(_, statement)| statement);
+                            let response = match self.kassandra.handle_unimplemented(statement, er)
+                            {
+                                Ok(res) => Response::Result(res),
+                                Err(er) => Response::Error(er),
+                            };
+                            let _ = sink.send((response, frame.stream)).await;
+                            continue;
+                        }
                         Err(er) => {
                             tracing::error!(
                                 ?er,
@@ -99,8 +229,18 @@ impl KassandraTester {
                         }
                     };
 
-                    let response = self.request(request);
-                    let _ = sink.send((response, frame.stream)).await;
+                    let latency = self.kassandra.latency(&self.connection, &request);
+                    let response = self
+                        .request(request)
+                        .with_warnings(self.connection.take_warnings());
+                    if latency.is_zero() {
+                        let _ = sink.send((response, frame.stream)).await;
+                    } else {
+                        delayed.push(async move {
+                            time::sleep(latency).await;
+                            (response, frame.stream)
+                        });
+                    }
                 }
                 Err(er) => {
                     tracing::error!(?er, "Could not read frame");
@@ -108,42 +248,14 @@ impl KassandraTester {
                 }
             }
         }
+
+        while let Some((response, stream)) = delayed.next().await {
+            let _ = sink.send((response, stream)).await;
+        }
+        let _ = sink.close().await;
     }
 
     fn request(&mut self, request: Request) -> Response {
-        match request {
-            Request::StartUp(_options) => Response::Ready,
-            Request::Options => Response::options(),
-            Request::Query(query) => {
-                let mut kass = self.kassandra.lock().unwrap();
-                match kass.process(query) {
-                    Ok(res) => Response::Result(res),
-                    Err(er) => Response::Error(er),
-                }
-            }
-            Request::Prepare(prep) => {
-                let mut kass = self.kassandra.lock().unwrap();
-                match kass.prepare(prep) {
-                    Ok(res) => Response::Result(res),
-                    Err(er) => Response::Error(er),
-                }
-            }
-            Request::Execute(execute) => {
-                let mut kass = self.kassandra.lock().unwrap();
-                match kass.execute(execute) {
-                    Ok(res) => Response::Result(res),
-                    Err(er) => Response::Error(er),
-                }
-            }
-            Request::Register { events: _ } => Response::Ready,
-            Request::Batch(b) => {
-                let mut kass = self.kassandra.lock().unwrap();
-                match kass.process_batch(b) {
-                    Ok(res) => Response::Result(res),
-                    Err(er) => Response::Error(er),
-                }
-            }
-            Request::AuthResponse => unimplemented!(),
-        }
+        self.kassandra.request_in(&mut self.connection, request)
     }
 }
@@ -0,0 +1,347 @@
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use kassandra::{
+    client::CqlConnection,
+    cql::{
+        engine::{hybrid::HybridEngine, remote::RemoteEngine},
+        value::CqlValue,
+    },
+    error::DbError,
+    frame::{
+        request::{query::Query, QueryParameters, Request},
+        response::{error::Error, result::QueryResult, ResponseOpcode},
+        response_stream,
+        value::FrameValue,
+    },
+    policy::LatencyPolicy,
+    KassandraSession,
+};
+use kassandra_tester::KassandraTester;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+#[tokio::test]
+async fn query_prepare_and_execute() -> eyre::Result<()> {
+    let kassandra = KassandraTester::new(KassandraSession::new())
+        .in_scope(|addr| async move {
+            let mut connection = CqlConnection::connect(addr).await?;
+            let parameters = QueryParameters::default();
+
+            connection
+                .query("CREATE KEYSPACE ks WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 }", &parameters)
+                .await?;
+            connection
+                .query("CREATE TABLE ks.t (id int PRIMARY KEY, name text)", &parameters)
+                .await?;
+
+            let id = connection
+                .prepare("INSERT INTO ks.t (id, name) VALUES (?, ?)")
+                .await?;
+            let id_value = 1i32.to_be_bytes();
+            let parameters = QueryParameters {
+                data: vec![FrameValue::Some(&id_value), FrameValue::Some(b"name")],
+                ..QueryParameters::default()
+            };
+            let result = connection.execute(&id, &parameters).await?;
+            // Void
+            assert_eq!(result.as_ref(), &[0, 0, 0, 1]);
+
+            let error = connection
+                .query("SELECT * FROM ks.missing", &QueryParameters::default())
+                .await
+                .unwrap_err();
+            assert_eq!(error.downcast::<Error>()?.error, DbError::Invalid);
+
+            eyre::Ok(())
+        })
+        .await?;
+
+    let snapshot = kassandra.data_snapshot();
+    assert_eq!(snapshot.0["ks"].tables["t"].rows.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remote_engine() -> eyre::Result<()> {
+    let kassandra = KassandraTester::new(KassandraSession::new())
+        .in_scope(|addr| async move {
+            // engine calls block on the remote connection, so they can't share a thread with the tester
+            tokio::task::spawn_blocking(move || {
+                let engine = RemoteEngine::connect(addr)?;
+                let mut session = KassandraSession::from_engine(engine, Default::default());
+
+                session.process_cql("CREATE KEYSPACE ks WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 }")?;
+                session.process_cql("CREATE TABLE ks.t (id int, ck int, name text, PRIMARY KEY (id, ck))")?;
+                session.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (1, 1, 'one')")?;
+                session.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (1, 2, 'two')")?;
+                session.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (2, 1, 'three')")?;
+                session.process_cql("DELETE FROM ks.t WHERE id = 2 AND ck = 1")?;
+
+                let QueryResult::Rows(rows) =
+                    session.process_cql("SELECT name FROM ks.t WHERE id = 1 AND ck > 1")?
+                else {
+                    panic!("invalid return type");
+                };
+                assert_eq!(rows.rows.len(), 1);
+                assert_eq!(rows.rows[0].columns, vec![Some(CqlValue::Text("two".into()))]);
+
+                let QueryResult::Rows(rows) = session.process_cql("SELECT COUNT(*) FROM ks.t")?
+                else {
+                    panic!("invalid return type");
+                };
+                assert_eq!(rows.rows[0].columns, vec![Some(CqlValue::BigInt(2))]);
+
+                let error = session
+                    .process_cql("SELECT * FROM ks.missing")
+                    .unwrap_err();
+                assert_eq!(error.error, DbError::Invalid);
+
+                eyre::Ok(())
+            })
+            .await?
+        })
+        .await?;
+
+    let snapshot = kassandra.data_snapshot();
+    assert_eq!(snapshot.0["ks"].tables["t"].rows.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hybrid_engine() -> eyre::Result<()> {
+    let schema = [
+        "CREATE KEYSPACE ks WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 }",
+        "CREATE TABLE ks.t (id int, ck int, name text, PRIMARY KEY (id, ck))",
+    ];
+    let mut upstream = KassandraSession::new();
+    for statement in schema {
+        upstream.process_cql(statement)?;
+    }
+    upstream.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (1, 1, 'one')")?;
+    upstream.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (2, 1, 'two')")?;
+
+    let upstream = KassandraTester::new(upstream)
+        .in_scope(|addr| async move {
+            // engine calls block on the upstream connection, so they can't share a thread with the tester
+            tokio::task::spawn_blocking(move || {
+                let engine = HybridEngine::connect(addr)?;
+                let mut session = KassandraSession::from_engine(engine, Default::default());
+                for statement in schema {
+                    session.process_cql(statement)?;
+                }
+
+                let QueryResult::Rows(rows) =
+                    session.process_cql("SELECT name FROM ks.t WHERE id = 1")?
+                else {
+                    panic!("invalid return type");
+                };
+                assert_eq!(rows.rows.len(), 1);
+                assert_eq!(
+                    rows.rows[0].columns,
+                    vec![Some(CqlValue::Text("one".into()))]
+                );
+
+                session.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (1, 2, 'local')")?;
+                session.process_cql("INSERT INTO ks.t (id, ck, name) VALUES (3, 1, 'local')")?;
+                session.process_cql("DELETE FROM ks.t WHERE id = 2")?;
+
+                let QueryResult::Rows(rows) = session.process_cql("SELECT COUNT(*) FROM ks.t")?
+                else {
+                    panic!("invalid return type");
+                };
+                assert_eq!(rows.rows[0].columns, vec![Some(CqlValue::BigInt(3))]);
+
+                let QueryResult::Rows(rows) =
+                    session.process_cql("SELECT ck, name FROM ks.t WHERE id = 1")?
+                else {
+                    panic!("invalid return type");
+                };
+                let rows = rows
+                    .rows
+                    .into_iter()
+                    .map(|it| it.columns)
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    rows,
+                    vec![
+                        vec![Some(CqlValue::Int(1)), Some(CqlValue::Text("one".into()))],
+                        vec![Some(CqlValue::Int(2)), Some(CqlValue::Text("local".into()))],
+                    ]
+                );
+
+                eyre::Ok(())
+            })
+            .await?
+        })
+        .await?;
+
+    // writes stay local
+    let snapshot = upstream.data_snapshot();
+    assert_eq!(snapshot.0["ks"].tables["t"].rows.len(), 2);
+
+    Ok(())
+}
+
+/// Responses are sent once they are due, so a statement delayed by the latency policy
+/// doesn't hold up the ones sent after it on other streams of the connection
+#[tokio::test]
+async fn interleaved_streams() -> eyre::Result<()> {
+    let mut kassandra: KassandraSession = KassandraSession::new()
+        .with_latency_policy(LatencyPolicy::new().rule("ks.slow=200ms".parse().unwrap()));
+    for statement in [
+        "CREATE KEYSPACE ks WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 }",
+        "CREATE TABLE ks.slow (id int PRIMARY KEY)",
+        "CREATE TABLE ks.fast (id int PRIMARY KEY)",
+        "INSERT INTO ks.slow (id) VALUES (1)",
+        "INSERT INTO ks.fast (id) VALUES (2)",
+    ] {
+        kassandra.process_cql(statement)?;
+    }
+
+    KassandraTester::new(kassandra)
+        .in_scope(|addr| async move {
+            let (read, mut write) = TcpStream::connect(addr).await?.into_split();
+            // all of the requests are sent before any response is read
+            write
+                .write_all(&query_frames(&[
+                    (1, "SELECT id FROM ks.slow"),
+                    (2, "SELECT id FROM ks.fast"),
+                    (3, "SELECT id FROM ks.fast WHERE id = 2"),
+                ])?)
+                .await?;
+
+            let mut responses = response_stream(read);
+            let mut answered = vec![];
+            for _ in 0..3 {
+                let (frame, _, body) = responses.next().await.unwrap()?;
+                let QueryResult::Rows(rows) = QueryResult::deserialize(&body)? else {
+                    panic!("invalid return type");
+                };
+                answered.push((frame.stream, rows.rows[0].columns[0].clone()));
+            }
+            assert_eq!(
+                answered,
+                vec![
+                    (2, Some(CqlValue::Int(2))),
+                    (3, Some(CqlValue::Int(2))),
+                    (1, Some(CqlValue::Int(1))),
+                ]
+            );
+
+            eyre::Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// `USE` applies to the statements pipelined after it, even when its own response is delayed
+#[tokio::test]
+async fn pipelined_use() -> eyre::Result<()> {
+    let mut kassandra: KassandraSession = KassandraSession::new()
+        .with_latency_policy(LatencyPolicy::new().rule("use=200ms".parse().unwrap()));
+    for statement in [
+        "CREATE KEYSPACE ks WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 }",
+        "CREATE TABLE ks.t (id int PRIMARY KEY)",
+        "INSERT INTO ks.t (id) VALUES (1)",
+    ] {
+        kassandra.process_cql(statement)?;
+    }
+
+    KassandraTester::new(kassandra)
+        .in_scope(|addr| async move {
+            let (read, mut write) = TcpStream::connect(addr).await?.into_split();
+            write
+                .write_all(&query_frames(&[(1, "USE ks"), (2, "SELECT id FROM t")])?)
+                .await?;
+
+            let mut responses = response_stream(read);
+            let (frame, _, body) = responses.next().await.unwrap()?;
+            assert_eq!(frame.stream, 2);
+            let QueryResult::Rows(rows) = QueryResult::deserialize(&body)? else {
+                panic!("invalid return type");
+            };
+            assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Int(1)));
+
+            let (frame, _, body) = responses.next().await.unwrap()?;
+            assert_eq!(frame.stream, 1);
+            assert!(matches!(
+                QueryResult::deserialize(&body)?,
+                QueryResult::SetKeyspace(set) if set.keyspace_name == "ks"
+            ));
+
+            eyre::Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// `QUERY` frames of the statements, each on its own stream
+fn query_frames(queries: &[(i16, &str)]) -> eyre::Result<Vec<u8>> {
+    let mut frames = vec![];
+    for (stream, query) in queries {
+        let mut body = vec![];
+        Request::Query(Query::simple(query)?).serialize(&mut body)?;
+        frames.extend([0x04, 0]);
+        frames.extend(stream.to_be_bytes());
+        frames.push(0x07);
+        frames.extend((body.len() as u32).to_be_bytes());
+        frames.extend(body);
+    }
+
+    Ok(frames)
+}
+
+#[tokio::test]
+async fn idle_connection_is_closed() -> eyre::Result<()> {
+    let kassandra: KassandraSession = KassandraSession::new();
+    KassandraTester::new(kassandra)
+        .with_idle_timeout(Duration::from_millis(100))
+        .in_scope(|addr| async move {
+            let started = Instant::now();
+            let (read, _write) = TcpStream::connect(addr).await?.into_split();
+
+            let mut responses = response_stream(read);
+            assert!(responses.next().await.is_none());
+            assert!(started.elapsed() >= Duration::from_millis(100));
+
+            eyre::Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Drivers send `OPTIONS` as heartbeats, so connections they keep alive aren't idle
+#[tokio::test]
+async fn heartbeats_keep_connection_open() -> eyre::Result<()> {
+    let kassandra: KassandraSession = KassandraSession::new();
+    KassandraTester::new(kassandra)
+        .with_idle_timeout(Duration::from_millis(100))
+        .in_scope(|addr| async move {
+            let (read, mut write) = TcpStream::connect(addr).await?.into_split();
+            let mut responses = response_stream(read);
+
+            for stream in 0..6i16 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                write.write_all(&[0x04, 0]).await?;
+                write.write_all(&stream.to_be_bytes()).await?;
+                write.write_all(&[0x05, 0, 0, 0, 0]).await?;
+
+                let (frame, opcode, _) = responses.next().await.unwrap()?;
+                assert_eq!(frame.stream, stream);
+                assert_eq!(opcode, ResponseOpcode::Supported);
+            }
+
+            // once heartbeats stop, the connection is idle again
+            assert!(responses.next().await.is_none());
+
+            eyre::Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
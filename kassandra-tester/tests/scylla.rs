@@ -1,8 +1,8 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, num::NonZeroUsize};
 
 use insta::assert_yaml_snapshot;
-use kassandra::KassandraSession;
-use kassandra_tester::KassandraTester;
+use kassandra::{frame::response::supported::Features, KassandraSession};
+use kassandra_tester::{kassandra_test, KassandraTester};
 use scylla::{
     batch::{Batch, BatchType},
     FromRow, SessionBuilder, ValueList,
@@ -256,3 +256,57 @@ async fn test_prepared() -> eyre::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn shard_aware_driver() -> eyre::Result<()> {
+    let kassandra: KassandraSession = KassandraSession::new();
+    kassandra.set_features(Features {
+        shards: NonZeroUsize::new(2),
+        ..Features::default()
+    });
+
+    KassandraTester::new(kassandra)
+        .in_scope(|addr| async move {
+            let s = SessionBuilder::new()
+                .known_node(format!("{addr}"))
+                .build()
+                .await?;
+            s.query("SELECT cluster_name FROM system.local", ()).await?;
+
+            let nodes = s.get_cluster_data().get_nodes_info().to_vec();
+            let sharder = nodes[0].sharder().expect("node should be sharded");
+            assert_eq!(sharder.nr_shards.get(), 2);
+
+            eyre::Ok(())
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn batch_schema() -> KassandraSession {
+    let mut kassandra = KassandraSession::new();
+    for statement in [
+        "CREATE KEYSPACE test WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 };",
+        "CREATE TABLE test.t1 (key text, value text, PRIMARY KEY ((key)));",
+    ] {
+        kassandra.process_cql(statement).unwrap();
+    }
+
+    kassandra
+}
+
+kassandra_test! {
+    async fn test_macro_with_initial_state(s, batch_schema()) {
+        let insert = s.prepare("insert into test.t1 (key, value) values(?, ?)").await?;
+        s.execute(&insert, ("key", "value")).await?;
+
+        let row = s
+            .query("select key, value from test.t1 where key = 'key'", ())
+            .await?
+            .single_row_typed::<TestBatchData>()?;
+        assert_eq!(row.value, "value");
+
+        Ok(())
+    }
+}
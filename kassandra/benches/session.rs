@@ -0,0 +1,263 @@
+//! Throughput of the in-memory session on a table with collections.
+//!
+//! `cargo bench -p kassandra --bench session`
+
+use std::hint::black_box;
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use kassandra::{
+    frame::{
+        request::query::Query,
+        response::{Response, ResponseFrameCodec},
+        value::FrameValue,
+    },
+    session::ConnectionState,
+    KassandraSession,
+};
+use tokio_util::codec::Encoder;
+
+const PARTITIONS: i32 = 100;
+const ROWS_PER_PARTITION: i32 = 100;
+
+fn schema() -> KassandraSession {
+    let mut session = KassandraSession::new();
+    for statement in [
+        "CREATE KEYSPACE bench WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 };",
+        "CREATE TABLE bench.events (
+            user int,
+            seq int,
+            name text,
+            tags set<text>,
+            attributes map<text, text>,
+            PRIMARY KEY (user, seq)
+        );",
+    ] {
+        session.process_cql(statement).unwrap();
+    }
+
+    session
+}
+
+fn insert(user: i32, seq: i32) -> String {
+    format!(
+        "INSERT INTO bench.events (user, seq, name, tags, attributes) \
+         VALUES ({user}, {seq}, 'event {seq}', ['a', 'b{seq}'], {{'source': 'bench', 'user': '{user}'}});"
+    )
+}
+
+fn populated() -> KassandraSession {
+    let mut session = schema();
+    for user in 0..PARTITIONS {
+        for seq in 0..ROWS_PER_PARTITION {
+            session.process_cql(&insert(user, seq)).unwrap();
+        }
+    }
+
+    session
+}
+
+fn inserts(c: &mut Criterion) {
+    let statements = (0..ROWS_PER_PARTITION)
+        .flat_map(|seq| (0..10).map(move |user| insert(user, seq)))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("insert");
+    group.throughput(Throughput::Elements(statements.len() as u64));
+    group.bench_function("bulk", |b| {
+        b.iter_batched(
+            schema,
+            |mut session| {
+                for statement in &statements {
+                    session.process_cql(statement).unwrap();
+                }
+                session
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn selects(c: &mut Criterion) {
+    let mut session = populated();
+    let statements = (0..PARTITIONS)
+        .map(|user| {
+            let seq = user * 7 % ROWS_PER_PARTITION;
+            format!("SELECT * FROM bench.events WHERE user = {user} AND seq = {seq};")
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("select");
+    group.throughput(Throughput::Elements(statements.len() as u64));
+    group.bench_function("point", |b| {
+        b.iter(|| {
+            for statement in &statements {
+                black_box(session.process_cql(statement).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn scans(c: &mut Criterion) {
+    let mut session = populated();
+
+    let mut group = c.benchmark_group("scan");
+    group.throughput(Throughput::Elements(50));
+    group.bench_function("clustering_range", |b| {
+        b.iter(|| {
+            black_box(
+                session
+                    .process_cql(
+                        "SELECT * FROM bench.events WHERE user = 42 AND seq >= 10 AND seq < 60;",
+                    )
+                    .unwrap(),
+            )
+        })
+    });
+    group.throughput(Throughput::Elements(
+        (PARTITIONS * ROWS_PER_PARTITION) as u64,
+    ));
+    group.bench_function("table", |b| {
+        b.iter(|| black_box(session.process_cql("SELECT * FROM bench.events;").unwrap()))
+    });
+    group.finish();
+}
+
+/// Rows with 1KB text and 4KB blob cells bound to the statement, values are copied once out of
+/// the statement and shared by storage and results after that
+fn large_values(c: &mut Criterion) {
+    const INSERT: &str =
+        "INSERT INTO bench.documents (user, seq, body, attachment) VALUES (?, ?, ?, ?);";
+    let body = vec![b't'; 1024];
+    let attachment = vec![0xab; 4096];
+    let keys = (0..ROWS_PER_PARTITION)
+        .flat_map(|seq| (0..10i32).map(move |user| (user.to_be_bytes(), seq.to_be_bytes())))
+        .collect::<Vec<_>>();
+    let insert = |session: &mut KassandraSession, (user, seq): &([u8; 4], [u8; 4])| {
+        let mut query = Query::simple(INSERT).unwrap();
+        query.parameters.data = vec![
+            FrameValue::Some(user),
+            FrameValue::Some(seq),
+            FrameValue::Some(&body),
+            FrameValue::Some(&attachment),
+        ];
+        session.process(query).unwrap();
+    };
+    let schema = || {
+        let mut session = schema();
+        session
+            .process_cql(
+                "CREATE TABLE bench.documents (
+                    user int,
+                    seq int,
+                    body text,
+                    attachment blob,
+                    PRIMARY KEY (user, seq)
+                );",
+            )
+            .unwrap();
+        session
+    };
+
+    let mut group = c.benchmark_group("large_values");
+    group.throughput(Throughput::Elements(keys.len() as u64));
+    group.bench_function("insert", |b| {
+        b.iter_batched(
+            schema,
+            |mut session| {
+                for key in &keys {
+                    insert(&mut session, key);
+                }
+                session
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let mut session = schema();
+    for key in &keys {
+        insert(&mut session, key);
+    }
+    group.bench_function("select", |b| {
+        b.iter(|| {
+            black_box(
+                session
+                    .process_cql("SELECT * FROM bench.documents;")
+                    .unwrap(),
+            )
+        })
+    });
+    group.finish();
+}
+
+/// Pages streamed into a frame the way the node sends them, buffers of the codecs are reused between pages
+fn encoding(c: &mut Criterion) {
+    let session = populated();
+    let page = || {
+        let mut query = Query::simple("SELECT * FROM bench.events;").unwrap();
+        query.parameters.result_page_size = Some(5000);
+        query
+    };
+
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(5000));
+    group.bench_function("streamed_page", |b| {
+        let mut dst = BytesMut::new();
+        b.iter(|| {
+            let mut connection = ConnectionState::new().with_streamed_rows();
+            let result = session.process_in(&mut connection, page()).unwrap();
+            dst.clear();
+            ResponseFrameCodec
+                .encode((Response::Result(result), 0), &mut dst)
+                .unwrap();
+            black_box(dst.len())
+        })
+    });
+
+    // pages encoded by several connections at once, as a node serving them on its worker threads does
+    const ENCODERS: usize = 4;
+    group.throughput(Throughput::Elements(5000 * ENCODERS as u64));
+    group.bench_function("concurrent_streamed_pages", |b| {
+        b.iter(|| {
+            std::thread::scope(|scope| {
+                for _ in 0..ENCODERS {
+                    let handle = session.handle();
+                    scope.spawn(move || {
+                        let mut connection = ConnectionState::new().with_streamed_rows();
+                        let result = handle.process_in(&mut connection, page()).unwrap();
+                        let mut dst = BytesMut::new();
+                        ResponseFrameCodec
+                            .encode((Response::Result(result), 0), &mut dst)
+                            .unwrap();
+                        black_box(dst.len())
+                    });
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+fn snapshots(c: &mut Criterion) {
+    let session = populated();
+
+    let mut group = c.benchmark_group("snapshot");
+    group.throughput(Throughput::Elements(
+        (PARTITIONS * ROWS_PER_PARTITION) as u64,
+    ));
+    group.bench_function("data", |b| b.iter(|| black_box(session.data_snapshot())));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    inserts,
+    selects,
+    scans,
+    large_values,
+    encoding,
+    snapshots
+);
+criterion_main!(benches);
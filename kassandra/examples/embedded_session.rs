@@ -0,0 +1,35 @@
+//! Runs kassandra in-process, without any networking involved.
+//!
+//! `cargo run --example embedded_session`
+
+use kassandra::{
+    frame::{request::query::Query, response::result::QueryResult},
+    KassandraSession,
+};
+
+fn main() -> eyre::Result<()> {
+    let mut session: KassandraSession = KassandraSession::new();
+
+    for statement in [
+        "CREATE KEYSPACE cycling WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 };",
+        "CREATE TABLE cycling.cyclist_name (id int PRIMARY KEY, lastname text, firstname text);",
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (1, 'john', 'johnson');",
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (2, 'smith', 'smithson');",
+    ] {
+        session.process(Query::simple(statement)?)?;
+    }
+
+    session.use_keyspace("cycling");
+
+    let QueryResult::Rows(rows) =
+        session.process(Query::simple("SELECT id, lastname FROM cyclist_name;")?)?
+    else {
+        eyre::bail!("expected rows to be returned");
+    };
+
+    for row in rows.rows {
+        println!("{:?}", row.columns);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,74 @@
+//! Injects failures and latency into a session, the way tests check how a service
+//! copes with an unhealthy cluster: downgrading consistency, retrying, or timing out.
+//!
+//! `cargo run --example fault_injection`
+
+use std::{ops::ControlFlow, time::Duration};
+
+use kassandra::{
+    error::DbError,
+    frame::{
+        consistency::Consistency,
+        request::{query::Query, Request},
+        response::{error::Error, Response},
+    },
+    middleware::Context,
+    policy::{ConsistencyFailure, ConsistencyPolicy, LatencyPolicy},
+    session::ConnectionState,
+    KassandraSession,
+};
+
+fn main() -> eyre::Result<()> {
+    let mut session: KassandraSession = KassandraSession::new()
+        // QUORUM writes time out, so the service has to fall back to ONE
+        .with_consistency_policy(
+            ConsistencyPolicy::new().fail_writes(Consistency::Quorum, ConsistencyFailure::Timeout),
+        )
+        // reads of the table are slow, servers of the session wait the delay out before responding
+        .with_latency_policy(LatencyPolicy::new().table(
+            "shop",
+            "orders",
+            Duration::from_millis(200),
+        ))
+        // the node is overloaded for anything touching the audit table
+        .with_middleware(
+            |request: &mut Request<'_>, _: &mut Context<'_>| match request {
+                Request::Query(query) if query.raw_query.contains("shop.audit") => {
+                    ControlFlow::Break(Response::Error(Error::new(
+                        DbError::Overloaded,
+                        "Too many in flight requests",
+                    )))
+                }
+                _ => ControlFlow::Continue(()),
+            },
+        );
+
+    for statement in [
+        "CREATE KEYSPACE shop WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 3 };",
+        "CREATE TABLE shop.orders (customer text, id int, total int, PRIMARY KEY (customer, id));",
+        "CREATE TABLE shop.audit (id int PRIMARY KEY, entry text);",
+    ] {
+        session.process(Query::simple(statement)?)?;
+    }
+
+    let insert = "INSERT INTO shop.orders (customer, id, total) VALUES ('alice', 1, 100);";
+    for consistency in [Consistency::Quorum, Consistency::One] {
+        let mut query = Query::simple(insert)?;
+        query.parameters.consistency = consistency;
+        match session.request(Request::Query(query)) {
+            Response::Error(error) => println!("{consistency} insert failed: {error:?}"),
+            _ => println!("{consistency} insert succeeded"),
+        }
+    }
+
+    let select = Request::Query(Query::simple("SELECT * FROM shop.orders;")?);
+    let delay = session.handle().latency(&ConnectionState::new(), &select);
+    println!("select is delayed by {delay:?}");
+
+    let response = session.request(Request::Query(Query::simple(
+        "INSERT INTO shop.audit (id, entry) VALUES (1, 'order placed');",
+    )?));
+    println!("audit insert: {response:?}");
+
+    Ok(())
+}
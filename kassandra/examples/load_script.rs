@@ -0,0 +1,48 @@
+//! Seeds a session from a cql script and persists it,
+//! producing a state file that `kassandra-node --data` can start from.
+//! Statements kassandra does not support are skipped and reported.
+//!
+//! `cargo run --example load_script -- schema.cql kass.data.ron`
+
+use kassandra::{policy::UnimplementedPolicy, KassandraSession};
+
+const DEFAULT_SCRIPT: &str = "
+    CREATE KEYSPACE IF NOT EXISTS inventory
+        WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 };
+    CREATE TABLE IF NOT EXISTS inventory.items (sku text PRIMARY KEY, name text, amount int);
+    CREATE INDEX IF NOT EXISTS items_by_name ON inventory.items (name);
+    INSERT INTO inventory.items (sku, name, amount) VALUES ('a-1', 'hammer', 3);
+    INSERT INTO inventory.items (sku, name, amount) VALUES ('b-2', 'screwdriver', 12);
+";
+
+fn main() -> eyre::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let script = match args.next() {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => DEFAULT_SCRIPT.to_owned(),
+    };
+
+    let mut session: KassandraSession =
+        KassandraSession::new().with_unimplemented_policy(UnimplementedPolicy::WarnAndIgnore);
+    for statement in script.split_inclusive(';') {
+        let statement = statement.trim();
+        if statement.is_empty() {
+            continue;
+        }
+        session.process_cql(statement)?;
+    }
+    for skipped in session.skipped_statements() {
+        eprintln!("skipped `{}`: {}", skipped.statement, skipped.reason);
+    }
+
+    let state = session.save_state();
+    // make sure the state can be restored before handing it out
+    let restored = KassandraSession::load_state(&state)?;
+    println!("{:#?}", restored.data_snapshot());
+
+    if let Some(output) = args.next() {
+        std::fs::write(output, state)?;
+    }
+
+    Ok(())
+}
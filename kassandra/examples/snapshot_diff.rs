@@ -0,0 +1,48 @@
+//! Compares data snapshots taken before and after running some statements,
+//! the same way tests assert on the state a service left behind.
+//!
+//! `cargo run --example snapshot_diff`
+
+use kassandra::{frame::request::query::Query, KassandraSession};
+
+fn main() -> eyre::Result<()> {
+    let mut session: KassandraSession = KassandraSession::new();
+
+    session.process(Query::simple(
+        "CREATE KEYSPACE shop WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 };",
+    )?)?;
+    session.process(Query::simple(
+        "CREATE TABLE shop.orders (customer text, id int, total int, PRIMARY KEY (customer, id));",
+    )?)?;
+    session.process(Query::simple(
+        "INSERT INTO shop.orders (customer, id, total) VALUES ('alice', 1, 100);",
+    )?)?;
+
+    let before = serde_json::to_value(session.data_snapshot())?;
+
+    session.process(Query::simple(
+        "INSERT INTO shop.orders (customer, id, total) VALUES ('alice', 2, 250);",
+    )?)?;
+    session.process(Query::simple(
+        "UPDATE shop.orders SET total = 120 WHERE customer = 'alice' AND id = 1;",
+    )?)?;
+
+    let after = serde_json::to_value(session.data_snapshot())?;
+
+    let rows = |snapshot: &serde_json::Value| {
+        snapshot["shop"]["tables"]["orders"]["rows"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    };
+    let (before, after) = (rows(&before), rows(&after));
+
+    for row in before.iter().filter(|row| !after.contains(row)) {
+        println!("- {row}");
+    }
+    for row in after.iter().filter(|row| !before.contains(row)) {
+        println!("+ {row}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use kassandra::cql::parser;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|query: &str| {
+    let _ = parser::query(query);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use kassandra::frame::{request::execute::Execute, FrameFlags};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Execute::parse(data, FrameFlags::empty());
+});
@@ -0,0 +1,17 @@
+//! Arbitrary bytes read as a stream of request frames, each decoded frame is parsed as its request.
+
+#![no_main]
+
+use bytes::BytesMut;
+use kassandra::frame::request::{Request, RequestFrameCodec};
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = RequestFrameCodec;
+    let mut src = BytesMut::from(data);
+
+    while let Ok(Some((frame, opcode, body))) = codec.decode(&mut src) {
+        let _ = Request::deserialize(opcode, &body, frame.flags);
+    }
+});
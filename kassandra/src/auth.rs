@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use strum::IntoEnumIterator;
+
+use crate::{
+    cql::{
+        self,
+        query::{Permission, QueryString, Resource},
+        schema::system::is_system_keyspace,
+        value::{ClusteringKeyValueRange, CqlValue, PartitionKeyValue},
+    },
+    error::DbError,
+    frame::response::error::Error,
+    storage::Predicate,
+};
+
+/// Keyspace of the tables roles and their permissions are kept in
+pub const AUTH_KEYSPACE: &str = "system_auth";
+
+/// Superuser every session starts with, its password is `cassandra` as well
+pub const DEFAULT_ROLE: &str = "cassandra";
+
+/// Authenticator sessions with authentication announce, drivers answer it with `PLAIN` credentials
+pub const PASSWORD_AUTHENTICATOR: &str = "org.apache.cassandra.auth.PasswordAuthenticator";
+
+/// Row of `system_auth.roles` along with the permissions granted to the role
+#[derive(Debug, Clone)]
+pub struct Role {
+    pub name: String,
+    pub superuser: bool,
+    pub login: bool,
+    salted_hash: Option<String>,
+    /// Granted permissions by the name of their resource, like `data/ks/table`
+    pub permissions: BTreeMap<String, BTreeSet<Permission>>,
+}
+
+impl Role {
+    pub fn read(engine: &impl cql::Engine, name: &str) -> Result<Option<Role>, Error> {
+        let key = role_key(name);
+        let mut roles = engine.read(
+            AUTH_KEYSPACE,
+            "roles",
+            &key,
+            ClusteringKeyValueRange::Full,
+            Predicate::new(),
+        )?;
+        let Some(entry) = roles.next() else {
+            return Ok(None);
+        };
+        let flag = |column| matches!(entry.row.get(column), Some(CqlValue::Boolean(true)));
+        let salted_hash = match entry.row.get("salted_hash") {
+            Some(CqlValue::Text(hash)) => Some(hash.to_string()),
+            _ => None,
+        };
+
+        let permissions = engine
+            .read(
+                AUTH_KEYSPACE,
+                "role_permissions",
+                &key,
+                ClusteringKeyValueRange::Full,
+                Predicate::new(),
+            )?
+            .filter_map(|entry| {
+                let Some(CqlValue::Text(resource)) = entry.row.get("resource") else {
+                    return None;
+                };
+                let permissions = match entry.row.get("permissions") {
+                    Some(CqlValue::Set(values)) => values
+                        .iter()
+                        .filter_map(|value| match value {
+                            CqlValue::Text(permission) => permission.parse().ok(),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => BTreeSet::new(),
+                };
+                Some((resource.to_string(), permissions))
+            })
+            .collect();
+
+        Ok(Some(Role {
+            name: name.to_owned(),
+            superuser: flag("is_superuser"),
+            login: flag("can_login"),
+            salted_hash,
+            permissions,
+        }))
+    }
+
+    /// Roles log in only when they are allowed to and have a password
+    pub fn check_password(&self, password: &str) -> bool {
+        self.login && self.salted_hash.as_deref() == Some(hash_password(password).as_str())
+    }
+
+    /// Checks the role may run the statement, with the errors cassandra gives.
+    ///
+    /// Superusers may run anything, others need a permission on the resource of the statement or on a parent of it.
+    /// `system` and `system_schema` can be read by everyone, system keyspaces are written
+    /// and roles are managed only by superusers.
+    pub fn authorize(
+        &self,
+        statement: &QueryString,
+        use_keyspace: Option<&str>,
+    ) -> Result<(), Error> {
+        if self.superuser {
+            return Ok(());
+        }
+
+        let keyspace = |keyspace: &Option<String>| {
+            keyspace.clone().or_else(|| use_keyspace.map(str::to_owned))
+        };
+        let table = |ks: &Option<String>, table: &str| {
+            keyspace(ks).map(|keyspace| Resource::Table {
+                keyspace: Some(keyspace),
+                table: table.to_owned(),
+            })
+        };
+        let (permission, resource) = match statement {
+            QueryString::Select(s) if keyspace(&s.keyspace).is_some_and(|it| is_public_keyspace(&it)) => {
+                return Ok(())
+            }
+            QueryString::Select(s) => (Permission::Select, table(&s.keyspace, &s.table)),
+            QueryString::Insert(s) => (Permission::Modify, table(&s.keyspace, &s.table)),
+            QueryString::Update(s) => (Permission::Modify, table(&s.keyspace, &s.table)),
+            QueryString::Delete(s) => (Permission::Modify, table(&s.keyspace, &s.table)),
+            QueryString::Batch(s) => {
+                return s
+                    .statements
+                    .iter()
+                    .try_for_each(|statement| self.authorize(statement, use_keyspace))
+            }
+            QueryString::Explain(statement) => return self.authorize(statement, use_keyspace),
+            QueryString::CreateKeyspace(_) => (Permission::Create, Some(Resource::AllKeyspaces)),
+            QueryString::AlterKeyspace(s) => (
+                Permission::Alter,
+                Some(Resource::Keyspace(s.keyspace.clone())),
+            ),
+            QueryString::DropKeyspace(s) => (
+                Permission::Drop,
+                Some(Resource::Keyspace(s.keyspace.clone())),
+            ),
+            QueryString::CreateTable(s) => (
+                Permission::Create,
+                keyspace(&s.keyspace).map(Resource::Keyspace),
+            ),
+            QueryString::CreateType(s) => (
+                Permission::Create,
+                keyspace(&s.keyspace).map(Resource::Keyspace),
+            ),
+            QueryString::DropTable(s) => (Permission::Drop, table(&s.keyspace, &s.table)),
+            QueryString::DropType(s) => (
+                Permission::Drop,
+                keyspace(&s.keyspace).map(Resource::Keyspace),
+            ),
+            QueryString::Grant(s) | QueryString::Revoke(s) => match &s.resource {
+                Resource::Table {
+                    keyspace,
+                    table: name,
+                } => (Permission::Authorize, table(keyspace, name)),
+                resource => (Permission::Authorize, Some(resource.clone())),
+            },
+            QueryString::CreateRole(_) | QueryString::DropRole(_) => {
+                return Err(Error::new(
+                    DbError::Unauthorized,
+                    format!(
+                        "User {} does not have sufficient privileges to perform the requested operation",
+                        self.name
+                    ),
+                ))
+            }
+            QueryString::Use { .. } | QueryString::Describe(_) => return Ok(()),
+        };
+        // statements without a keyspace fail once they are planned
+        let Some(resource) = resource else {
+            return Ok(());
+        };
+        if let (
+            Permission::Modify,
+            Resource::Table {
+                keyspace: Some(keyspace),
+                ..
+            },
+        ) = (permission, &resource)
+        {
+            if is_system_keyspace(keyspace) {
+                return Err(Error::new(
+                    DbError::Unauthorized,
+                    format!("{keyspace} keyspace is not user-modifiable."),
+                ));
+            }
+        }
+
+        let granted = resource.lineage().iter().any(|resource| {
+            self.permissions
+                .get(&resource.name())
+                .is_some_and(|permissions| permissions.contains(&permission))
+        });
+        match granted {
+            true => Ok(()),
+            false => Err(Error::new(
+                DbError::Unauthorized,
+                format!(
+                    "User {} has no {permission} permission on {} or any of its parents",
+                    self.name,
+                    resource.label()
+                ),
+            )),
+        }
+    }
+}
+
+/// Keyspaces every role may read, drivers query them to discover the cluster and its schema
+fn is_public_keyspace(keyspace: &str) -> bool {
+    matches!(keyspace, "system" | "system_schema")
+}
+
+impl Resource {
+    /// Name permissions of the resource are stored by: `data`, `data/ks` or `data/ks/table`
+    pub fn name(&self) -> String {
+        match self {
+            Resource::AllKeyspaces => "data".to_owned(),
+            Resource::Keyspace(keyspace) => format!("data/{keyspace}"),
+            Resource::Table { keyspace, table } => {
+                format!("data/{}/{table}", keyspace.as_deref().unwrap_or_default())
+            }
+        }
+    }
+
+    /// Permissions which can be granted on the resource, tables can't hold `CREATE`
+    pub fn applicable_permissions(&self) -> BTreeSet<Permission> {
+        Permission::iter()
+            .filter(|it| *it != Permission::All)
+            .filter(|it| !matches!((self, it), (Resource::Table { .. }, Permission::Create)))
+            .collect()
+    }
+
+    /// The resource followed by the ones it belongs to, permissions of any of them apply to it
+    fn lineage(&self) -> Vec<Resource> {
+        match self {
+            Resource::AllKeyspaces => vec![Resource::AllKeyspaces],
+            Resource::Keyspace(_) => vec![self.clone(), Resource::AllKeyspaces],
+            Resource::Table { keyspace, .. } => vec![
+                self.clone(),
+                Resource::Keyspace(keyspace.clone().unwrap_or_default()),
+                Resource::AllKeyspaces,
+            ],
+        }
+    }
+
+    /// The resource the way cassandra names it in errors: `<table ks.table>`
+    pub fn label(&self) -> String {
+        match self {
+            Resource::AllKeyspaces => "<all keyspaces>".to_owned(),
+            Resource::Keyspace(keyspace) => format!("<keyspace {keyspace}>"),
+            Resource::Table { keyspace, table } => {
+                format!(
+                    "<table {}.{table}>",
+                    keyspace.as_deref().unwrap_or_default()
+                )
+            }
+        }
+    }
+}
+
+/// Kassandra doesn't keep secrets, passwords are only hashed so they are not stored as they are
+pub fn hash_password(password: &str) -> String {
+    format!("{:x}", md5::compute(password))
+}
+
+/// User and password of a `PLAIN` SASL token: `\0user\0password`
+pub fn plain_credentials(token: &[u8]) -> Option<(&str, &str)> {
+    let mut parts = token.split(|it| *it == 0).skip(1);
+    let user = std::str::from_utf8(parts.next()?).ok()?;
+    let password = std::str::from_utf8(parts.next()?).ok()?;
+
+    Some((user, password))
+}
+
+pub(crate) fn role_key(role: &str) -> PartitionKeyValue {
+    PartitionKeyValue::Simple(CqlValue::Text(role.into()))
+}
@@ -0,0 +1,127 @@
+use std::sync::Mutex;
+
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    Stream,
+};
+
+use crate::{
+    cql::{
+        plan::Plan,
+        value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+    },
+    frame::response::{event::SchemaChangeEvent, result::QueryResult},
+};
+
+/// Change applied by a statement of the session, see [`SessionHandle::subscribe`](crate::session::SessionHandle::subscribe).
+///
+/// Updates are reported as inserts, both of them write the cells of a single row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeEvent {
+    Insert {
+        keyspace: String,
+        table: String,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        /// Cells written, nulls delete the cell
+        values: Vec<(String, Option<CqlValue>)>,
+        /// Write timestamp in microseconds
+        timestamp: i64,
+    },
+    Delete {
+        keyspace: String,
+        table: String,
+        partition_key: PartitionKeyValue,
+        /// `Empty` when the whole partition is deleted
+        clustering_key: ClusteringKeyValue,
+        /// Write timestamp in microseconds
+        timestamp: i64,
+    },
+    Schema(SchemaChangeEvent),
+}
+
+impl ChangeEvent {
+    /// Data change the plan is going to apply, schema changes are known only from their results
+    pub(crate) fn from_plan(plan: &Plan) -> Option<Self> {
+        match plan {
+            Plan::Insert(node) => Some(Self::Insert {
+                keyspace: node.keyspace.clone(),
+                table: node.table.clone(),
+                partition_key: node.partition_key.clone(),
+                clustering_key: node.clustering_key.clone(),
+                values: node.values.clone(),
+                timestamp: node.timestamp,
+            }),
+            Plan::Update(node) => Some(Self::Insert {
+                keyspace: node.keyspace.clone(),
+                table: node.table.clone(),
+                partition_key: node.partition_key.clone(),
+                clustering_key: node.clustering_key.clone(),
+                values: node.values.clone(),
+                timestamp: node.timestamp,
+            }),
+            Plan::Delete(node) => Some(Self::Delete {
+                keyspace: node.keyspace.clone(),
+                table: node.table.clone(),
+                partition_key: node.partition_key.clone(),
+                clustering_key: node.clustering_key.clone(),
+                timestamp: node.timestamp,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Schema change reported by the result of the plan
+    pub(crate) fn from_result(result: &QueryResult) -> Option<Self> {
+        match result {
+            QueryResult::SchemaChange(change) => Some(Self::Schema(change.event.clone())),
+            _ => None,
+        }
+    }
+
+    fn keyspace_mut(&mut self) -> &mut String {
+        match self {
+            Self::Insert { keyspace, .. } | Self::Delete { keyspace, .. } => keyspace,
+            Self::Schema(
+                SchemaChangeEvent::KeyspaceChange { keyspace_name, .. }
+                | SchemaChangeEvent::TableChange { keyspace_name, .. }
+                | SchemaChangeEvent::TypeChange { keyspace_name, .. }
+                | SchemaChangeEvent::FunctionChange { keyspace_name, .. }
+                | SchemaChangeEvent::AggregateChange { keyspace_name, .. },
+            ) => keyspace_name,
+        }
+    }
+}
+
+/// Streams of the subscribers, the ones which were dropped are removed on the next event
+#[derive(Debug, Default)]
+pub(crate) struct Subscribers(Mutex<Vec<UnboundedSender<ChangeEvent>>>);
+
+impl Subscribers {
+    pub(crate) fn subscribe(&self) -> impl Stream<Item = ChangeEvent> {
+        let (sender, receiver) = unbounded();
+        self.0.lock().unwrap().push(sender);
+
+        receiver
+    }
+
+    /// Events are only built when somebody listens to them
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    /// Keyspaces of isolated sessions are reported without their `prefix`
+    pub(crate) fn send(&self, mut event: ChangeEvent, prefix: Option<&str>) {
+        if let Some(prefix) = prefix {
+            let keyspace = event.keyspace_mut();
+            if let Some(name) = keyspace.strip_prefix(prefix) {
+                *keyspace = name.to_owned();
+            }
+        }
+
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+}
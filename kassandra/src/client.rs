@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use bytes::{Buf, Bytes, BytesMut};
+use eyre::{eyre, Result};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::frame::{
+    parse,
+    request::{QueryParameters, RequestFrameCodec, RequestOpcode},
+    response::{error::Error, ResponseFrameCodec, ResponseOpcode},
+    write, FrameFlags, FrameParams, ProtocolVersion,
+};
+
+/// Minimal client connection to a CQL node speaking protocol v4,
+/// enough to re-issue statements against a real cluster and compare its replies with kassandra ones.
+///
+/// Requests are sent one at a time, so responses are matched to them by stream id
+/// only to skip server pushed events.
+pub struct CqlConnection<S> {
+    requests: FramedWrite<WriteHalf<S>, RequestFrameCodec>,
+    responses: FramedRead<ReadHalf<S>, ResponseFrameCodec>,
+    stream: i16,
+}
+
+impl CqlConnection<TcpStream> {
+    /// Connects to the node and sends `STARTUP`
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let mut connection = Self::new(stream);
+        connection.startup().await?;
+
+        Ok(connection)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> CqlConnection<S> {
+    /// Wraps an already established stream, `STARTUP` still has to be sent
+    pub fn new(stream: S) -> Self {
+        let (read, write) = tokio::io::split(stream);
+
+        Self {
+            requests: FramedWrite::new(write, RequestFrameCodec),
+            responses: FramedRead::new(read, ResponseFrameCodec),
+            stream: 0,
+        }
+    }
+
+    pub async fn startup(&mut self) -> Result<()> {
+        let options = HashMap::from([("CQL_VERSION".to_owned(), "3.0.0".to_owned())]);
+        let mut body = BytesMut::new();
+        write::string_map(&mut body, &options);
+
+        match self.request(RequestOpcode::Startup, body.freeze()).await? {
+            (ResponseOpcode::Ready, _) => Ok(()),
+            (ResponseOpcode::Authenticate, _) => Err(eyre!("Authentication is not supported")),
+            (opcode, _) => Err(eyre!("Unexpected response to STARTUP: {opcode:?}")),
+        }
+    }
+
+    /// Returns body of the `RESULT` response
+    pub async fn query(&mut self, query: &str, parameters: &QueryParameters<'_>) -> Result<Bytes> {
+        let mut body = BytesMut::new();
+        write::long_string(&mut body, query);
+        parameters.serialize(&mut body);
+
+        self.result(RequestOpcode::Query, body.freeze()).await
+    }
+
+    /// Returns id of the prepared statement
+    pub async fn prepare(&mut self, query: &str) -> Result<Bytes> {
+        let mut body = BytesMut::new();
+        write::long_string(&mut body, query);
+
+        let mut result = self.result(RequestOpcode::Prepare, body.freeze()).await?;
+        if result.get_i32() != 0x0004 {
+            return Err(eyre!("Unexpected result kind for PREPARE"));
+        }
+        let (_, id) = parse::short_bytes(&result).map_err(|_| eyre!("Malformed prepared id"))?;
+
+        Ok(Bytes::copy_from_slice(id))
+    }
+
+    /// Returns body of the `RESULT` response
+    pub async fn execute(&mut self, id: &[u8], parameters: &QueryParameters<'_>) -> Result<Bytes> {
+        let mut body = BytesMut::new();
+        write::short_bytes(&mut body, id);
+        parameters.serialize(&mut body);
+
+        self.result(RequestOpcode::Execute, body.freeze()).await
+    }
+
+    /// Sends a request with an already serialized body, e.g. a recorded one,
+    /// and returns the response to it as is
+    pub async fn request(
+        &mut self,
+        opcode: RequestOpcode,
+        body: Bytes,
+    ) -> Result<(ResponseOpcode, Bytes)> {
+        let stream = self.stream;
+        self.stream = self.stream.wrapping_add(1) & i16::MAX;
+
+        let frame = FrameParams {
+            version: ProtocolVersion::V4,
+            flags: FrameFlags::empty(),
+            stream,
+        };
+        self.requests.send((frame, opcode, body)).await?;
+
+        while let Some(response) = self.responses.next().await {
+            let (frame, opcode, body) = response?;
+            if frame.stream == stream {
+                return Ok((opcode, body));
+            }
+        }
+
+        Err(eyre!("Connection closed"))
+    }
+
+    /// `ERROR` responses are returned as [`Error`]
+    async fn result(&mut self, request: RequestOpcode, body: Bytes) -> Result<Bytes> {
+        match self.request(request, body).await? {
+            (ResponseOpcode::Result, body) => Ok(body),
+            (ResponseOpcode::Error, body) => {
+                let (_, error) =
+                    Error::deserialize(&body).map_err(|_| eyre!("Malformed error response"))?;
+                Err(error.into())
+            }
+            (opcode, _) => Err(eyre!("Unexpected response to {request:?}: {opcode:?}")),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Sources of time and ids of a session, tests replace them to get reproducible results.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{cql::query_cache, storage};
+
+/// Time of a session: write timestamps of statements without one and expiry of tombstones
+pub trait TimeProvider: Send + Sync {
+    /// Microseconds since unix epoch, same as cql write timestamps
+    fn now_micros(&self) -> i64;
+}
+
+impl fmt::Debug for dyn TimeProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TimeProvider")
+    }
+}
+
+/// Real time, sessions use it unless given another [`TimeProvider`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl TimeProvider for SystemClock {
+    fn now_micros(&self) -> i64 {
+        storage::now_micros()
+    }
+}
+
+/// Time which only moves when told to, clones share the same time.
+///
+/// Writes at the same time still get distinct timestamps, each one is a microsecond after the previous.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicI64>,
+}
+
+impl ManualClock {
+    pub fn new(now_micros: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now_micros)),
+        }
+    }
+
+    pub fn set(&self, now_micros: i64) {
+        self.now.store(now_micros, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.fetch_add(by.as_micros() as i64, Ordering::Relaxed);
+    }
+}
+
+impl TimeProvider for ManualClock {
+    fn now_micros(&self) -> i64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+/// Ids a session gives prepared statements
+pub trait IdProvider: Send + Sync {
+    /// Preparing the same statement from the same keyspace again has to return the same id
+    fn statement_id(&self, keyspace: Option<&str>, query: &str) -> u128;
+}
+
+impl fmt::Debug for dyn IdProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IdProvider")
+    }
+}
+
+/// Ids cassandra gives prepared statements, see [`query_cache::statement_id`], the default of sessions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Md5Ids;
+
+impl IdProvider for Md5Ids {
+    fn statement_id(&self, keyspace: Option<&str>, query: &str) -> u128 {
+        query_cache::statement_id(keyspace, query)
+    }
+}
+
+/// Statements are numbered from 1 in the order they are first prepared, which keeps snapshots readable
+#[derive(Debug, Default)]
+pub struct SequentialIds {
+    ids: Mutex<HashMap<(Option<String>, String), u128>>,
+}
+
+impl IdProvider for SequentialIds {
+    fn statement_id(&self, keyspace: Option<&str>, query: &str) -> u128 {
+        let mut ids = self.ids.lock().unwrap();
+        let next = ids.len() as u128 + 1;
+
+        *ids.entry((keyspace.map(str::to_owned), query.to_owned()))
+            .or_insert(next)
+    }
+}
@@ -0,0 +1,58 @@
+use strum::{Display, EnumString};
+
+use crate::frame::ProtocolVersion;
+
+/// Server a session pretends to be, drivers choose their code paths by the versions it advertises.
+///
+/// The profile sets the versions of `OPTIONS` responses and of `system.local`,
+/// and the few behaviors of the server kassandra can emulate, like the keyspaces of virtual tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum CompatibilityProfile {
+    /// Apache Cassandra 3.11
+    Cassandra3,
+    /// Apache Cassandra 4.1
+    Cassandra4,
+    /// ScyllaDB 5.x, which advertises itself as cassandra 3.0.8
+    Scylla5,
+}
+
+impl CompatibilityProfile {
+    pub fn release_version(self) -> &'static str {
+        match self {
+            Self::Cassandra3 => "3.11.16",
+            Self::Cassandra4 => "4.1.3",
+            Self::Scylla5 => "3.0.8",
+        }
+    }
+
+    pub fn cql_version(self) -> &'static str {
+        match self {
+            Self::Cassandra3 => "3.4.4",
+            Self::Cassandra4 => "3.4.6",
+            Self::Scylla5 => "3.3.1",
+        }
+    }
+
+    /// Versions of the native protocol the server supports, the ones kassandra doesn't implement are not advertised
+    pub fn protocol_versions(self) -> &'static [u8] {
+        match self {
+            Self::Cassandra3 | Self::Scylla5 => &[3, 4],
+            Self::Cassandra4 => &[3, 4, 5],
+        }
+    }
+
+    /// Virtual tables of `system_views` came with cassandra 4.0, scylla doesn't have them
+    pub fn has_system_views(self) -> bool {
+        matches!(self, Self::Cassandra4)
+    }
+
+    /// `PROTOCOL_VERSIONS` of `SUPPORTED` responses
+    pub(crate) fn supported_protocol_versions(self) -> Vec<String> {
+        self.protocol_versions()
+            .iter()
+            .filter(|version| !ProtocolVersion::from_request(**version).is_unsupported())
+            .map(|version| format!("{version}/v{version}"))
+            .collect()
+    }
+}
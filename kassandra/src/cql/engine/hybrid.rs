@@ -0,0 +1,484 @@
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+use super::{
+    kv::{in_clustering_order, owned_row, KvEngine},
+    remote::{restrictions, Remote},
+    Mutation, RowEntry, RowsIterator,
+};
+use crate::{
+    clock::TimeProvider,
+    cql::{
+        self,
+        partitioner::{Murmur3Partitioner, Partitioner},
+        query::QueryString,
+        schema::{
+            keyspace::{Keyspace, Strategy, UserDefinedType},
+            system::is_system_keyspace,
+            ColumnType, Table, TableOptions, TableSchema,
+        },
+        value::{
+            ClusteringKeyValue, ClusteringKeyValueRange, CqlValue, PartitionKeyValue,
+            PartitionKeyValueRange,
+        },
+    },
+    error::DbError,
+    frame::response::error::Error,
+    storage::{memory::Memory, Predicate, ReadStorage, WriteStorage},
+};
+
+/// Timestamp upstream cells are written with, so any local write is newer than them
+const UPSTREAM_TIMESTAMP: i64 = i64::MIN;
+
+/// Engine reading partitions missing locally from an upstream cluster, while every write stays local,
+/// so tests can run against a realistic dataset without mutating it.
+///
+/// Schema is local as well, tables have to be created the way they are defined upstream.
+/// Upstream partitions are cached once they are read, and copied into local data before their first local write.
+/// Tables which don't exist upstream, or were dropped locally, have local data only.
+#[derive(Debug)]
+pub struct HybridEngine {
+    local: KvEngine<Memory>,
+    upstream: Remote,
+    cache: RwLock<Cache>,
+    /// Upstream partitions copied into local data, which has all of their rows since then
+    materialized: BTreeSet<(String, String, PartitionKeyValue)>,
+    /// Keyspaces and tables dropped locally
+    detached: BTreeSet<(String, Option<String>)>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    data: Memory,
+    partitions: BTreeSet<(String, String, PartitionKeyValue)>,
+    /// Tables read upstream as a whole by scans
+    tables: BTreeSet<(String, String)>,
+}
+
+impl HybridEngine {
+    /// Connects to a node of the upstream cluster, see [`RemoteEngine`](super::remote::RemoteEngine)
+    pub fn connect(addr: SocketAddr) -> eyre::Result<Self> {
+        Ok(Self {
+            local: KvEngine::default(),
+            upstream: Remote::connect(addr)?,
+            cache: RwLock::default(),
+            materialized: BTreeSet::new(),
+            detached: BTreeSet::new(),
+        })
+    }
+
+    /// Local engine with the writes and the upstream partitions they were made to
+    pub fn local(&self) -> &KvEngine<Memory> {
+        &self.local
+    }
+
+    /// Whether rows of the table are read upstream
+    fn is_upstream(&self, keyspace: &str, table: &str) -> bool {
+        !is_system_keyspace(keyspace)
+            && !self.detached.contains(&(keyspace.to_owned(), None))
+            && !self
+                .detached
+                .contains(&(keyspace.to_owned(), Some(table.to_owned())))
+    }
+
+    fn is_materialized(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+    ) -> bool {
+        self.materialized
+            .contains(&(keyspace.to_owned(), table.to_owned(), partition_key.clone()))
+    }
+
+    /// Upstream rows selected by `restrictions`, tables missing upstream have none
+    fn select(
+        &self,
+        keyspace: &str,
+        table: &str,
+        restrictions: &str,
+    ) -> Result<Vec<RowEntry>, Error> {
+        let schema = cql::Catalog::get_table(&self.local, keyspace, table).ok_or_else(|| {
+            Error::new(
+                DbError::Invalid,
+                format!("table {keyspace}.{table} does not exist"),
+            )
+        })?;
+
+        match self.upstream.select(keyspace, table, schema, restrictions) {
+            Err(error) if error.error == DbError::Invalid => Ok(vec![]),
+            rows => rows,
+        }
+    }
+
+    fn fetch_partition(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+    ) -> Result<(), Error> {
+        let key = (keyspace.to_owned(), table.to_owned(), partition_key.clone());
+        {
+            let cache = self.cache.read().unwrap();
+            if cache.partitions.contains(&key)
+                || cache.tables.contains(&(key.0.clone(), key.1.clone()))
+            {
+                return Ok(());
+            }
+        }
+
+        let schema = cql::Catalog::get_table(&self.local, keyspace, table);
+        let restrictions = format!(
+            " WHERE {}",
+            restrictions(
+                schema.expect("table exists when its partition is read"),
+                partition_key,
+                &ClusteringKeyValue::Empty
+            )
+        );
+        let rows = self.select(keyspace, table, &restrictions)?;
+
+        let mut cache = self.cache.write().unwrap();
+        write_upstream(&mut cache.data, keyspace, table, rows)?;
+        cache.partitions.insert(key);
+
+        Ok(())
+    }
+
+    fn fetch_table(&self, keyspace: &str, table: &str) -> Result<(), Error> {
+        let key = (keyspace.to_owned(), table.to_owned());
+        if self.cache.read().unwrap().tables.contains(&key) {
+            return Ok(());
+        }
+
+        let rows = self.select(keyspace, table, "")?;
+
+        let mut cache = self.cache.write().unwrap();
+        // rows of partitions read before are written again, with the same timestamp they overwrite nothing
+        write_upstream(&mut cache.data, keyspace, table, rows)?;
+        cache.tables.insert(key);
+
+        Ok(())
+    }
+
+    /// Copies the upstream partition into local data, before the first local write to it
+    fn materialize(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+    ) -> Result<(), Error> {
+        if !self.is_upstream(keyspace, table)
+            || self.is_materialized(keyspace, table, partition_key)
+        {
+            return Ok(());
+        }
+        self.fetch_partition(keyspace, table, partition_key)?;
+
+        let rows = self
+            .cache
+            .get_mut()
+            .unwrap()
+            .data
+            .read(
+                keyspace,
+                table,
+                partition_key,
+                ClusteringKeyValueRange::Full,
+                Predicate::new(),
+            )?
+            .map(owned_row)
+            .collect::<Vec<_>>();
+        write_upstream(&mut self.local.data, keyspace, table, rows)?;
+
+        self.materialized
+            .insert((keyspace.to_owned(), table.to_owned(), partition_key.clone()));
+
+        Ok(())
+    }
+
+    /// Upstream rows of the table are neither cached, nor read anymore
+    fn detach(&mut self, keyspace: &str, table: Option<&str>) {
+        let cache = self.cache.get_mut().unwrap();
+        let _ = match table {
+            Some(table) => cache.data.drop_table(keyspace, table),
+            None => cache.data.drop_keyspace(keyspace),
+        };
+
+        self.detached
+            .insert((keyspace.to_owned(), table.map(str::to_owned)));
+    }
+}
+
+impl cql::Catalog for HybridEngine {
+    fn create_keyspace(
+        &mut self,
+        keyspace: String,
+        ignore_existence: bool,
+        replication: Strategy,
+        durable_writes: bool,
+    ) -> Result<&Keyspace, DbError> {
+        self.local
+            .create_keyspace(keyspace, ignore_existence, replication, durable_writes)
+    }
+
+    fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
+    ) -> Result<&Keyspace, DbError> {
+        self.local
+            .alter_keyspace(keyspace, replication, durable_writes)
+    }
+
+    fn create_table(
+        &mut self,
+        keyspace: String,
+        table: String,
+        ignore_existence: bool,
+        schema: TableSchema,
+        options: TableOptions,
+    ) -> Result<&Table, DbError> {
+        self.local
+            .create_table(keyspace, table, ignore_existence, schema, options)
+    }
+
+    fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError> {
+        let dropped = self.local.drop_keyspace(keyspace, ignore_existence)?;
+        if dropped.is_some() {
+            self.detach(keyspace, None);
+        }
+
+        Ok(dropped)
+    }
+
+    fn drop_table(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError> {
+        let dropped = self.local.drop_table(keyspace, table, ignore_existence)?;
+        if dropped.is_some() {
+            self.detach(keyspace, Some(table));
+        }
+
+        Ok(dropped)
+    }
+
+    fn create_type(
+        &mut self,
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError> {
+        self.local
+            .create_type(keyspace, name, ignore_existence, field_types)
+    }
+
+    fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError> {
+        self.local.drop_type(keyspace, name, ignore_existence)
+    }
+
+    fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace> {
+        self.local.get_keyspace(keyspace)
+    }
+
+    fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_> {
+        self.local.keyspaces()
+    }
+
+    fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
+        self.local.get_table(keyspace, table)
+    }
+}
+
+impl cql::QueryCache for HybridEngine {
+    fn store(&mut self, id: u128, query: QueryString) -> Result<(), DbError> {
+        self.local.store(id, query)
+    }
+
+    fn retrieve(&self, id: u128) -> Result<Option<QueryString>, DbError> {
+        self.local.retrieve(id)
+    }
+}
+
+impl cql::Engine for HybridEngine {
+    fn insert(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        values: Vec<(String, Option<CqlValue>)>,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        self.materialize(keyspace, table, &partition_key)?;
+        self.local.insert(
+            keyspace,
+            table,
+            partition_key,
+            clustering_key,
+            values,
+            timestamp,
+        )
+    }
+
+    fn delete(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        self.materialize(keyspace, table, &partition_key)?;
+        self.local
+            .delete(keyspace, table, partition_key, clustering_key, timestamp)
+    }
+
+    fn set_time_provider(&mut self, time: Arc<dyn TimeProvider>) {
+        self.local.set_time_provider(time);
+    }
+
+    fn read<'a>(
+        &'a self,
+        keyspace: &'a str,
+        table: &'a str,
+        partition_key: &'a PartitionKeyValue,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<RowsIterator<'a>, Error> {
+        if !self.is_upstream(keyspace, table)
+            || self.is_materialized(keyspace, table, partition_key)
+        {
+            return self
+                .local
+                .read(keyspace, table, partition_key, clustering_range, predicate);
+        }
+        self.fetch_partition(keyspace, table, partition_key)?;
+
+        let rows = self
+            .cache
+            .read()
+            .unwrap()
+            .data
+            .read(keyspace, table, partition_key, clustering_range, predicate)?
+            .map(owned_row)
+            .collect::<Vec<_>>();
+        let order = self.local.clustering_order(keyspace, table);
+
+        Ok(in_clustering_order(rows.into_iter(), order))
+    }
+
+    fn scan<'a>(
+        &'a self,
+        keyspace: &'a str,
+        table: &'a str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<RowsIterator<'a>, Error> {
+        if !self.is_upstream(keyspace, table) {
+            return self.local.scan(keyspace, table, range, predicate);
+        }
+        self.fetch_table(keyspace, table)?;
+
+        let mut rows = self
+            .cache
+            .read()
+            .unwrap()
+            .data
+            .scan(keyspace, table, range.clone(), predicate.clone())?
+            .filter(|row| !self.is_materialized(keyspace, table, row.partition))
+            .map(owned_row)
+            .collect::<Vec<_>>();
+        rows.extend(
+            self.local
+                .data
+                .scan(keyspace, table, range, predicate)?
+                .map(owned_row),
+        );
+        // sorting is stable, so rows of each partition stay in their order
+        rows.sort_by_cached_key(|row| {
+            (
+                Murmur3Partitioner.token(&row.partition),
+                row.partition.clone(),
+            )
+        });
+        let order = self.local.clustering_order(keyspace, table);
+
+        Ok(in_clustering_order(rows.into_iter(), order))
+    }
+
+    fn count(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error> {
+        Ok(self
+            .read(keyspace, table, partition_key, clustering_range, predicate)?
+            .count())
+    }
+
+    fn count_scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error> {
+        Ok(self.scan(keyspace, table, range, predicate)?.count())
+    }
+
+    /// Partitions are materialized before any of them is written, fetching them is what can fail
+    fn apply(&mut self, mutations: Vec<Mutation>) -> Result<(), Error> {
+        for mutation in &mutations {
+            let (keyspace, table) = mutation.table();
+            self.materialize(keyspace, table, mutation.partition_key())?;
+        }
+
+        self.local.apply(mutations)
+    }
+}
+
+fn write_upstream(
+    data: &mut Memory,
+    keyspace: &str,
+    table: &str,
+    rows: Vec<RowEntry>,
+) -> Result<(), Error> {
+    for RowEntry {
+        partition,
+        clustering,
+        row,
+    } in rows
+    {
+        data.write(
+            keyspace,
+            table,
+            partition,
+            clustering,
+            row.into_iter().map(|(name, value)| (name, Some(value))),
+            UPSTREAM_TIMESTAMP,
+        )?;
+    }
+
+    Ok(())
+}
@@ -1,45 +1,66 @@
-use std::ops::RangeBounds;
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroUsize,
+    sync::Arc,
+};
 
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 use super::RowEntry;
 use crate::{
+    clock::{SystemClock, TimeProvider},
     cql::{
         self,
-        engine::RowsIterator,
-        literal::Literal,
+        engine::{views::SystemViews, RowsIterator},
+        partitioner::{Murmur3Partitioner, Partitioner},
         query::QueryString,
         query_cache::PersistedQueryCache,
         schema::{
-            keyspace::{Keyspace, Strategy},
-            PersistedSchema, Table, TableSchema,
+            keyspace::{Keyspace, Strategy, UserDefinedType},
+            system::{is_system_keyspace, system_views_keyspace},
+            ClusteringOrder, ColumnType, PersistedSchema, Schema, Table, TableOptions, TableSchema,
+        },
+        value::{
+            ClusteringKeyValue, ClusteringKeyValueRange, CqlValue, PartitionKeyValue,
+            PartitionKeyValueRange,
         },
-        value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
     },
     error::DbError,
-    frame::response::{error::Error, event::SchemaChangeEvent},
-    storage::Storage,
+    frame::response::error::Error,
+    storage::{
+        self,
+        memory::{Memory, TableDump, UnversionedTable},
+        write_timestamp, Predicate, ReadStorage, Storage, WriteStorage,
+    },
 };
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct KvEngine<S: Storage> {
     pub data: S,
     schema: PersistedSchema,
-    #[serde(skip, default)]
+    #[serde(default)]
     query_cache: PersistedQueryCache,
+    #[serde(skip)]
+    views: SystemViews,
+    /// Local deletion time of tombstones is taken from it
+    #[serde(skip, default = "system_clock")]
+    time: Arc<dyn TimeProvider>,
+}
+
+fn system_clock() -> Arc<dyn TimeProvider> {
+    Arc::new(SystemClock)
 }
 
 impl<S: Storage + Default> Default for KvEngine<S> {
     fn default() -> Self {
-        let mut storage = Self {
+        Self {
             data: S::default(),
             schema: PersistedSchema::default(),
             query_cache: PersistedQueryCache::default(),
-        };
-
-        PersistedSchema::persist_system_schema(&mut storage.data);
-
-        storage
+            views: SystemViews::default(),
+            time: system_clock(),
+        }
     }
 }
 
@@ -49,9 +70,20 @@ impl<S: Storage> cql::Catalog for KvEngine<S> {
         keyspace: String,
         ignore_existence: bool,
         replication: Strategy,
+        durable_writes: bool,
     ) -> Result<&Keyspace, DbError> {
         self.schema
-            .create_keyspace(&mut self.data, keyspace, ignore_existence, replication)
+            .create_keyspace(keyspace, ignore_existence, replication, durable_writes)
+    }
+
+    fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
+    ) -> Result<&Keyspace, DbError> {
+        self.schema
+            .alter_keyspace(keyspace, replication, durable_writes)
     }
 
     fn create_table(
@@ -60,25 +92,65 @@ impl<S: Storage> cql::Catalog for KvEngine<S> {
         table: String,
         ignore_existence: bool,
         schema: TableSchema,
-        options: Vec<(String, Literal)>,
+        options: TableOptions,
     ) -> Result<&Table, DbError> {
-        self.schema.create_table(
-            &mut self.data,
-            keyspace,
-            table,
-            ignore_existence,
-            schema,
-            options,
-        )
+        self.schema
+            .create_table(keyspace, table, ignore_existence, schema, options)
+    }
+
+    fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError> {
+        let dropped = self.schema.drop_keyspace(keyspace, ignore_existence)?;
+        if dropped.is_some() {
+            self.data.drop_keyspace(keyspace)?;
+        }
+
+        Ok(dropped)
+    }
+
+    fn drop_table(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError> {
+        let dropped = self.schema.drop_table(keyspace, table, ignore_existence)?;
+        if dropped.is_some() {
+            self.data.drop_table(keyspace, table)?;
+        }
+
+        Ok(dropped)
     }
 
     fn create_type(
         &mut self,
-        _keyspace: Option<String>,
-        _table: String,
-        _columns: Vec<(String, String)>,
-    ) -> Result<SchemaChangeEvent, DbError> {
-        todo!()
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError> {
+        self.schema
+            .create_type(keyspace, name, ignore_existence, field_types)
+    }
+
+    fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError> {
+        self.schema.drop_type(keyspace, name, ignore_existence)
+    }
+
+    fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace> {
+        self.schema.get_keyspace(keyspace)
+    }
+
+    fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_> {
+        self.schema.keyspaces()
     }
 
     fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
@@ -91,19 +163,20 @@ impl<S: Storage> cql::QueryCache for KvEngine<S> {
         self.query_cache.store(id, query, &mut self.data)
     }
 
-    fn retrieve(&mut self, id: u128) -> Result<Option<QueryString>, DbError> {
+    fn retrieve(&self, id: u128) -> Result<Option<QueryString>, DbError> {
         self.query_cache.retrieve(id, &self.data)
     }
 }
 
-impl<S: Storage> cql::Engine for KvEngine<S> {
+impl<S: Storage + Send + Sync + 'static> cql::Engine for KvEngine<S> {
     fn insert(
         &mut self,
         keyspace: &str,
         table: &str,
         partition_key: PartitionKeyValue,
         clustering_key: ClusteringKeyValue,
-        values: Vec<(String, CqlValue)>,
+        values: Vec<(String, Option<CqlValue>)>,
+        timestamp: i64,
     ) -> Result<(), Error> {
         self.data
             .write(
@@ -112,8 +185,9 @@ impl<S: Storage> cql::Engine for KvEngine<S> {
                 partition_key,
                 clustering_key,
                 values.into_iter(),
+                timestamp,
             )
-            .map_err(|e| Error::new(DbError::Invalid, format!("{e}")))
+            .map_err(Error::from)
     }
 
     fn delete(
@@ -122,48 +196,414 @@ impl<S: Storage> cql::Engine for KvEngine<S> {
         table: &str,
         partition_key: PartitionKeyValue,
         clustering_key: ClusteringKeyValue,
+        timestamp: i64,
     ) -> Result<(), Error> {
+        let now = self.time.now_micros();
         self.data
-            .delete(keyspace, table, &partition_key, &clustering_key)
-            .map_err(|e| Error::new(DbError::Invalid, format!("{e}")))
+            .delete(
+                keyspace,
+                table,
+                &partition_key,
+                &clustering_key,
+                timestamp,
+                now,
+            )
+            .map_err(Error::from)
+    }
+
+    fn set_time_provider(&mut self, time: Arc<dyn TimeProvider>) {
+        self.time = time;
     }
 
     fn read<'a>(
-        &'a mut self,
+        &'a self,
         keyspace: &'a str,
         table: &'a str,
         partition_key: &'a PartitionKeyValue,
-        clustering_range: impl RangeBounds<ClusteringKeyValue> + Clone + 'static,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
     ) -> Result<RowsIterator<'a>, Error> {
+        let order = self.clustering_order(keyspace, table);
+        if let Some(data) = self.virtual_table(keyspace, table)? {
+            let rows = data
+                .read(keyspace, table, partition_key, clustering_range, predicate)?
+                .map(owned_row)
+                .collect::<Vec<_>>();
+            return Ok(in_clustering_order(rows.into_iter(), order));
+        }
+
         let scan = self
             .data
-            .read(keyspace, table, partition_key, clustering_range)
-            .map_err(|e| Error::new(DbError::Invalid, format!("{e}")))?;
-        let iter = scan.map(|row| RowEntry {
-            partition: partition_key.clone(),
-            clustering: row.clustering.clone(),
-            row: row.row.map(|(k, v)| (k.clone(), v.clone())).collect(),
-        });
-        Ok(Box::new(iter))
+            .read(keyspace, table, partition_key, clustering_range, predicate)
+            .map_err(Error::from)?;
+        Ok(in_clustering_order(scan.map(owned_row), order))
     }
 
     fn scan<'a>(
-        &'a mut self,
+        &'a self,
         keyspace: &'a str,
         table: &'a str,
-        range: impl RangeBounds<PartitionKeyValue> + Clone + 'static,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
     ) -> Result<RowsIterator<'a>, Error> {
+        let order = self.clustering_order(keyspace, table);
+        if let Some(data) = self.virtual_table(keyspace, table)? {
+            let rows = data
+                .scan(keyspace, table, range, predicate)?
+                .map(owned_row)
+                .collect::<Vec<_>>();
+            return Ok(in_clustering_order(rows.into_iter(), order));
+        }
+
         let scan = self
             .data
-            .scan(keyspace, table, range)
-            .map_err(|e| Error::new(DbError::Invalid, format!("{e}")))?;
+            .scan(keyspace, table, range, predicate)
+            .map_err(Error::from)?;
+        Ok(in_clustering_order(scan.map(owned_row), order))
+    }
+
+    fn count(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error> {
+        if let Some(data) = self.virtual_table(keyspace, table)? {
+            return Ok(data.count(keyspace, table, partition_key, clustering_range, predicate)?);
+        }
+
+        self.data
+            .count(keyspace, table, partition_key, clustering_range, predicate)
+            .map_err(Error::from)
+    }
+
+    fn count_scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error> {
+        if let Some(data) = self.virtual_table(keyspace, table)? {
+            return Ok(data.count_scan(keyspace, table, range, predicate)?);
+        }
+
+        self.data
+            .count_scan(keyspace, table, range, predicate)
+            .map_err(Error::from)
+    }
+}
+
+/// Schema and data of a single keyspace, the way `kassandra-node export` stores them
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KeyspaceDump {
+    pub schema: Keyspace,
+    #[serde(default)]
+    tables: BTreeMap<String, TableDump>,
+}
+
+/// Engine of a state saved before states had a layout version, see [`KvEngine::import_unversioned`]
+#[derive(Debug, Deserialize)]
+pub(crate) struct UnversionedEngine {
+    data: UnversionedMemory,
+    schema: PersistedSchema,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnversionedMemory {
+    data: HashMap<String, HashMap<String, UnversionedTable>>,
+}
+
+impl KvEngine<Memory> {
+    /// User keyspaces ordered by name
+    pub fn export_keyspaces(&self) -> Vec<KeyspaceDump> {
+        self.schema
+            .schema
+            .values()
+            .filter(|keyspace| !is_system_keyspace(&keyspace.name))
+            .map(|keyspace| KeyspaceDump {
+                schema: keyspace.clone(),
+                tables: self.data.dump_keyspace(&keyspace.name),
+            })
+            .collect()
+    }
+
+    /// Replaces the keyspace of the same name with the dumped one
+    pub fn import_keyspace(&mut self, dump: KeyspaceDump) -> Result<(), Error> {
+        let name = dump.schema.name.clone();
+        if is_system_keyspace(&name) {
+            return Err(Error::new(
+                DbError::Unauthorized,
+                format!("{name} keyspace is not user-modifiable."),
+            ));
+        }
+
+        self.data.restore_keyspace(&name, dump.tables);
+        self.schema.schema.insert(name, dump.schema);
 
-        let iter = scan.map(|row| RowEntry {
-            partition: row.partition.clone(),
-            clustering: row.clustering.clone(),
-            row: row.row.map(|(k, v)| (k.clone(), v.clone())).collect(),
-        });
+        Ok(())
+    }
+
+    /// Imports user keyspaces of an unversioned state, system keyspaces are kept as they are in this engine
+    pub(crate) fn import_unversioned(&mut self, state: UnversionedEngine) -> Result<(), Error> {
+        let UnversionedEngine {
+            data: UnversionedMemory { mut data },
+            schema,
+        } = state;
+        for (name, keyspace) in schema.schema.0 {
+            if is_system_keyspace(&name) {
+                continue;
+            }
+            let tables = data
+                .remove(&name)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(table, rows)| (table, TableDump::from_unversioned(rows)))
+                .collect();
+            self.import_keyspace(KeyspaceDump {
+                schema: keyspace,
+                tables,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Storage> KvEngine<S> {
+    pub fn schema(&self) -> &Schema {
+        &self.schema.schema
+    }
 
-        Ok(Box::new(iter))
+    pub fn set_prepared_statements_capacity(&mut self, capacity: NonZeroUsize) {
+        self.query_cache.set_capacity(capacity);
+    }
+
+    pub fn system_views(&self) -> &SystemViews {
+        &self.views
+    }
+
+    /// States saved before `system_views` existed get its tables defined as well
+    pub fn set_system_views(&mut self, views: SystemViews) {
+        let (name, keyspace) = system_views_keyspace();
+        self.schema.schema.entry(name).or_insert(keyspace);
+        self.views = views;
+    }
+
+    pub(super) fn clustering_order(&self, keyspace: &str, table: &str) -> Vec<ClusteringOrder> {
+        self.schema
+            .get_table(keyspace, table)
+            .map(|it| it.clustering_order.clone())
+            .unwrap_or_default()
     }
+
+    /// System tables which are not stored, but computed from the engine state every time they are queried
+    fn virtual_table(&self, keyspace: &str, table: &str) -> Result<Option<Memory>, Error> {
+        match (keyspace, table) {
+            ("system", "size_estimates" | "table_estimates") => self.size_estimates().map(Some),
+            ("system", "prepared_statements") => self.prepared_statements().map(Some),
+            ("system_schema", table @ ("keyspaces" | "tables" | "columns")) => {
+                let mut rows = Memory::default();
+                self.schema.system_schema_table(&mut rows, table)?;
+                Ok(Some(rows))
+            }
+            ("system_views", table) => {
+                let mut rows = Memory::default();
+                self.views.write_table(&mut rows, table)?;
+                Ok(Some(rows))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn prepared_statements(&self) -> Result<Memory, Error> {
+        let mut statements = Memory::default();
+        for (id, query) in self.query_cache.statements() {
+            let id = CqlValue::Blob(Bytes::copy_from_slice(&id.to_be_bytes()));
+            let keyspace = query.keyspace().map(|it| it.to_owned().into());
+            statements.write(
+                "system",
+                "prepared_statements",
+                PartitionKeyValue::Simple(id.clone()),
+                ClusteringKeyValue::Empty,
+                [
+                    ("prepared_id".to_owned(), Some(id)),
+                    ("logged_keyspace".to_owned(), keyspace),
+                    ("query_string".to_owned(), Some(query.to_string().into())),
+                ]
+                .into_iter(),
+                write_timestamp(),
+            )?;
+        }
+
+        Ok(statements)
+    }
+
+    /// Size estimates are not tracked on writes,
+    /// instead they are computed from the stored data into a scratch storage every time they are queried.
+    fn size_estimates(&self) -> Result<Memory, Error> {
+        let mut estimates = Memory::default();
+        let ranges = token_ranges(self.local_tokens()?);
+        let tables = self
+            .schema
+            .schema
+            .iter()
+            .filter(|(name, _)| !is_system_keyspace(name))
+            .flat_map(|(name, keyspace)| {
+                keyspace
+                    .tables
+                    .keys()
+                    .map(move |table| (name.clone(), table.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        for (keyspace, table) in tables {
+            // (partitions count, total size) per token range
+            let mut stats = vec![(0i64, 0i64); ranges.len()];
+            let mut last_partition = None;
+            for row in self
+                .data
+                .scan(&keyspace, &table, (..).into(), Predicate::new())?
+            {
+                let token = Murmur3Partitioner.token(row.partition);
+                let Some(range) = ranges.iter().position(|it| range_contains(*it, token)) else {
+                    continue;
+                };
+                if last_partition != Some(row.partition) {
+                    stats[range].0 += 1;
+                    last_partition = Some(row.partition);
+                }
+                stats[range].1 += row
+                    .row
+                    .map(|(_, value)| value.serialized_size() as i64)
+                    .sum::<i64>();
+            }
+
+            for ((start, end), (count, size)) in ranges.iter().zip(stats) {
+                let mean_partition_size = if count == 0 { 0 } else { size / count };
+                let values = [
+                    ("keyspace_name".to_owned(), keyspace.clone().into()),
+                    ("table_name".to_owned(), table.clone().into()),
+                    ("range_start".to_owned(), start.to_string().into()),
+                    ("range_end".to_owned(), end.to_string().into()),
+                    (
+                        "mean_partition_size".to_owned(),
+                        CqlValue::BigInt(mean_partition_size),
+                    ),
+                    ("partitions_count".to_owned(), CqlValue::BigInt(count)),
+                ];
+
+                estimates.write(
+                    "system",
+                    "size_estimates",
+                    PartitionKeyValue::Simple(keyspace.clone().into()),
+                    ClusteringKeyValue::Composite(vec![
+                        Some(table.clone().into()),
+                        Some(start.to_string().into()),
+                        Some(end.to_string().into()),
+                    ]),
+                    values.clone().into_iter(),
+                    write_timestamp(),
+                )?;
+                estimates.write(
+                    "system",
+                    "table_estimates",
+                    PartitionKeyValue::Simple(keyspace.clone().into()),
+                    ClusteringKeyValue::Composite(vec![
+                        Some(table.clone().into()),
+                        Some("primary".to_owned().into()),
+                        Some(start.to_string().into()),
+                        Some(end.to_string().into()),
+                    ]),
+                    values
+                        .into_iter()
+                        .chain([("range_type".to_owned(), "primary".to_owned().into())]),
+                    write_timestamp(),
+                )?;
+            }
+        }
+
+        Ok(estimates)
+    }
+
+    fn local_tokens(&self) -> Result<Vec<i64>, Error> {
+        let local = PartitionKeyValue::Simple("local".to_owned().into());
+        let mut tokens = vec![];
+        for row in self.data.read(
+            "system",
+            "local",
+            &local,
+            ClusteringKeyValueRange::Full,
+            Predicate::new(),
+        )? {
+            for (name, value) in row.row {
+                if let ("tokens", CqlValue::Set(values)) = (name.as_str(), value) {
+                    tokens.extend(values.iter().filter_map(|it| match it {
+                        CqlValue::Text(token) => token.parse::<i64>().ok(),
+                        _ => None,
+                    }));
+                }
+            }
+        }
+        tokens.sort();
+
+        Ok(tokens)
+    }
+}
+
+pub(super) fn owned_row<'a>(
+    row: storage::RowEntry<'a, impl Iterator<Item = (&'a String, &'a CqlValue)>>,
+) -> RowEntry {
+    RowEntry {
+        partition: row.partition.clone(),
+        clustering: row.clustering.clone(),
+        row: row.row.map(|(k, v)| (k.clone(), v.clone())).collect(),
+    }
+}
+
+/// Every local token owns the range `(previous token, token]`, the first one wraps around the ring.
+fn token_ranges(tokens: Vec<i64>) -> Vec<(i64, i64)> {
+    let Some(last) = tokens.last().copied() else {
+        return vec![(i64::MIN, i64::MIN)];
+    };
+
+    std::iter::once(last)
+        .chain(tokens.iter().copied())
+        .zip(tokens.iter().copied())
+        .collect()
+}
+
+fn range_contains((start, end): (i64, i64), token: i64) -> bool {
+    if start < end {
+        start < token && token <= end
+    } else {
+        token > start || token <= end
+    }
+}
+
+/// Storage keeps rows sorted by ascending clustering key,
+/// so partitions of tables with `DESC` columns are re-sorted on the way out.
+pub(super) fn in_clustering_order<'a>(
+    rows: impl Iterator<Item = RowEntry> + 'a,
+    order: Vec<ClusteringOrder>,
+) -> RowsIterator<'a> {
+    if order.iter().all(|it| *it == ClusteringOrder::Asc) {
+        return Box::new(rows);
+    }
+
+    let mut rows = rows.peekable();
+    let partitions = std::iter::from_fn(move || {
+        let first = rows.next()?;
+        let mut partition = vec![first];
+        while let Some(row) = rows.next_if(|it| it.partition == partition[0].partition) {
+            partition.push(row);
+        }
+        partition.sort_by(|a, b| a.clustering.cmp_ordered(&b.clustering, &order));
+
+        Some(partition)
+    });
+
+    Box::new(partitions.flatten())
 }
@@ -1,12 +1,19 @@
-use std::{collections::BTreeMap, ops::RangeBounds};
+use std::{collections::BTreeMap, sync::Arc};
 
-use super::value::{ClusteringKeyValue, PartitionKeyValue};
+use super::value::{
+    ClusteringKeyValue, ClusteringKeyValueRange, PartitionKeyValue, PartitionKeyValueRange,
+};
 use crate::{
+    clock::TimeProvider,
     cql::{query_cache::QueryCache, schema::Catalog, value::CqlValue},
     frame::response::error::Error,
+    storage::{Predicate, StorageError},
 };
 
+pub mod hybrid;
 pub mod kv;
+pub mod remote;
+pub mod views;
 
 pub type RowsIterator<'a> = Box<dyn Iterator<Item = RowEntry> + 'a>;
 
@@ -16,14 +23,60 @@ pub struct RowEntry {
     pub row: BTreeMap<String, CqlValue>,
 }
 
-pub trait Engine: Catalog + QueryCache + 'static {
+/// Write of a single row, which is staged by [`Engine::apply`] along with the other writes of a batch
+#[derive(Debug, Clone)]
+pub enum Mutation {
+    /// Cells to write, nulls delete the cell
+    Insert {
+        keyspace: String,
+        table: String,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        values: Vec<(String, Option<CqlValue>)>,
+        timestamp: i64,
+    },
+    /// Deletes the row, or the whole partition for an empty clustering key
+    Delete {
+        keyspace: String,
+        table: String,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        timestamp: i64,
+    },
+}
+
+impl Mutation {
+    /// Keyspace and table the mutation writes to
+    pub fn table(&self) -> (&str, &str) {
+        match self {
+            Mutation::Insert {
+                keyspace, table, ..
+            }
+            | Mutation::Delete {
+                keyspace, table, ..
+            } => (keyspace, table),
+        }
+    }
+
+    pub fn partition_key(&self) -> &PartitionKeyValue {
+        match self {
+            Mutation::Insert { partition_key, .. } | Mutation::Delete { partition_key, .. } => {
+                partition_key
+            }
+        }
+    }
+}
+
+/// Reads only need shared access, so sessions can run them concurrently, while writes are exclusive.
+pub trait Engine: Catalog + QueryCache + Send + Sync + 'static {
     fn insert(
         &mut self,
         keyspace: &str,
         table: &str,
         partition_key: PartitionKeyValue,
         clustering_key: ClusteringKeyValue,
-        values: Vec<(String, CqlValue)>,
+        values: Vec<(String, Option<CqlValue>)>,
+        timestamp: i64,
     ) -> Result<(), Error>;
 
     fn delete(
@@ -32,20 +85,87 @@ pub trait Engine: Catalog + QueryCache + 'static {
         table: &str,
         partition_key: PartitionKeyValue,
         clustering_key: ClusteringKeyValue,
+        timestamp: i64,
     ) -> Result<(), Error>;
 
     fn read<'a>(
-        &'a mut self,
+        &'a self,
         keyspace: &'a str,
         table: &'a str,
         partition_key: &'a PartitionKeyValue,
-        clustering_range: impl RangeBounds<ClusteringKeyValue> + Clone + 'static,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
     ) -> Result<RowsIterator<'a>, Error>;
 
     fn scan<'a>(
-        &'a mut self,
+        &'a self,
         keyspace: &'a str,
         table: &'a str,
-        range: impl RangeBounds<PartitionKeyValue> + Clone + 'static,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
     ) -> Result<RowsIterator<'a>, Error>;
+
+    /// Number of rows [`Engine::read`] returns, without materializing them
+    fn count(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error>;
+
+    /// Number of rows [`Engine::scan`] returns, without materializing them
+    fn count_scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error>;
+
+    /// Clock of the session, engines keeping tombstones record the local deletion time of them by it
+    fn set_time_provider(&mut self, _time: Arc<dyn TimeProvider>) {}
+
+    /// Applies all the mutations or none of them, the way a logged batch is applied.
+    ///
+    /// Tables of every mutation are checked before the first one is written,
+    /// engines with writes which can fail for other reasons stage them on their own.
+    fn apply(&mut self, mutations: Vec<Mutation>) -> Result<(), Error> {
+        for mutation in &mutations {
+            let (keyspace, table) = mutation.table();
+            if self.get_table(keyspace, table).is_none() {
+                return Err(StorageError::table_does_not_exist(keyspace, table).into());
+            }
+        }
+
+        for mutation in mutations {
+            match mutation {
+                Mutation::Insert {
+                    keyspace,
+                    table,
+                    partition_key,
+                    clustering_key,
+                    values,
+                    timestamp,
+                } => self.insert(
+                    &keyspace,
+                    &table,
+                    partition_key,
+                    clustering_key,
+                    values,
+                    timestamp,
+                )?,
+                Mutation::Delete {
+                    keyspace,
+                    table,
+                    partition_key,
+                    clustering_key,
+                    timestamp,
+                } => self.delete(&keyspace, &table, partition_key, clustering_key, timestamp)?,
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,762 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    ops::{Bound, RangeBounds},
+    sync::mpsc,
+    thread,
+};
+
+use bytes::Bytes;
+use nom::number::complete::be_i32;
+use tokio::net::TcpStream;
+
+use crate::{
+    client::CqlConnection,
+    cql::{
+        self,
+        engine::{kv::KvEngine, Mutation, RowEntry, RowsIterator},
+        partitioner::{Murmur3Partitioner, Partitioner},
+        query::QueryString,
+        schema::{
+            keyspace::{Keyspace, Strategy, UserDefinedType},
+            system::is_system_keyspace,
+            ColumnType, PrimaryKey, Table, TableOptions, TableSchema,
+        },
+        value::{
+            opt_deserialize_value, ClusteringKeyValue, ClusteringKeyValueRange, CqlValue,
+            PartitionKeyValue, PartitionKeyValueRange,
+        },
+    },
+    error::DbError,
+    export,
+    frame::{
+        request::{QueryFlags, QueryParameters},
+        response::error::Error,
+    },
+    storage::{memory::Memory, Predicate},
+};
+
+/// Engine running statements of user keyspaces against a real cluster,
+/// so the same test body can be checked against both kassandra and cassandra.
+///
+/// System keyspaces, prepared statements and the schema the planner works with are kept by a local engine,
+/// schema changes are applied to both of them. Partitions are read whole and sliced locally.
+/// Counter tables can't be written, since writes carry the final values of their cells.
+#[derive(Debug)]
+pub struct RemoteEngine {
+    local: KvEngine<Memory>,
+    remote: Remote,
+}
+
+impl RemoteEngine {
+    /// Connects to a node of the cluster, the connection is served by a thread of its own
+    pub fn connect(addr: SocketAddr) -> eyre::Result<Self> {
+        Ok(Self {
+            local: KvEngine::default(),
+            remote: Remote::connect(addr)?,
+        })
+    }
+
+    /// Schema changes of system keyspaces are only applied locally
+    fn change_schema(&self, keyspace: &str, statement: String) -> Result<(), DbError> {
+        if is_system_keyspace(keyspace) {
+            return Ok(());
+        }
+
+        self.remote
+            .run(Command::Query(statement))
+            .map(drop)
+            .map_err(|error| error.error)
+    }
+
+    /// `INSERT` writing the values to the remote cluster
+    fn insert_statement(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        clustering_key: &ClusteringKeyValue,
+        values: &[(String, Option<CqlValue>)],
+        timestamp: i64,
+    ) -> Result<String, Error> {
+        let schema = self.table_schema(keyspace, table)?;
+        let (mut columns, mut literals): (Vec<_>, Vec<_>) =
+            key_literals(schema, partition_key, clustering_key).unzip();
+        for (name, value) in values {
+            if columns.contains(name) {
+                continue;
+            }
+            let column = schema.columns.get(name).ok_or_else(|| {
+                Error::new(DbError::Invalid, format!("Undefined column name {name}"))
+            })?;
+            if column.ty == ColumnType::Counter {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    "Counters can't be written to a remote cluster",
+                ));
+            }
+
+            columns.push(name.clone());
+            literals.push(match value {
+                Some(value) => export::literal(value, &column.ty),
+                None => "null".to_owned(),
+            });
+        }
+
+        Ok(format!(
+            "INSERT INTO {keyspace}.{table} ({}) VALUES ({}) USING TIMESTAMP {timestamp}",
+            columns.join(", "),
+            literals.join(", ")
+        ))
+    }
+
+    fn delete_statement(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        clustering_key: &ClusteringKeyValue,
+        timestamp: i64,
+    ) -> Result<String, Error> {
+        let schema = self.table_schema(keyspace, table)?;
+        Ok(format!(
+            "DELETE FROM {keyspace}.{table} USING TIMESTAMP {timestamp} WHERE {}",
+            restrictions(schema, partition_key, clustering_key)
+        ))
+    }
+
+    fn table_schema(&self, keyspace: &str, table: &str) -> Result<&TableSchema, Error> {
+        cql::Catalog::get_table(&self.local, keyspace, table).ok_or_else(|| {
+            Error::new(
+                DbError::Invalid,
+                format!("table {keyspace}.{table} does not exist"),
+            )
+        })
+    }
+
+    fn select(
+        &self,
+        keyspace: &str,
+        table: &str,
+        restrictions: &str,
+    ) -> Result<Vec<RowEntry>, Error> {
+        let schema = self.table_schema(keyspace, table)?;
+        self.remote.select(keyspace, table, schema, restrictions)
+    }
+}
+
+impl cql::Catalog for RemoteEngine {
+    fn create_keyspace(
+        &mut self,
+        keyspace: String,
+        ignore_existence: bool,
+        replication: Strategy,
+        durable_writes: bool,
+    ) -> Result<&Keyspace, DbError> {
+        let statement = Keyspace {
+            name: keyspace.clone(),
+            strategy: replication.clone(),
+            durable_writes,
+            tables: Default::default(),
+            user_defined_types: Default::default(),
+        }
+        .create_statement();
+        self.change_schema(
+            &keyspace,
+            if_not_exists(statement, "CREATE KEYSPACE ", ignore_existence),
+        )?;
+
+        self.local
+            .create_keyspace(keyspace, ignore_existence, replication, durable_writes)
+    }
+
+    fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
+    ) -> Result<&Keyspace, DbError> {
+        let mut altered = self
+            .local
+            .get_keyspace(keyspace)
+            .ok_or(DbError::Invalid)?
+            .clone();
+        if let Some(strategy) = &replication {
+            altered.strategy = strategy.clone();
+        }
+        if let Some(durable_writes) = durable_writes {
+            altered.durable_writes = durable_writes;
+        }
+        let statement =
+            altered
+                .create_statement()
+                .replacen("CREATE KEYSPACE ", "ALTER KEYSPACE ", 1);
+        self.change_schema(keyspace, statement)?;
+
+        self.local
+            .alter_keyspace(keyspace, replication, durable_writes)
+    }
+
+    fn create_table(
+        &mut self,
+        keyspace: String,
+        table: String,
+        ignore_existence: bool,
+        schema: TableSchema,
+        options: TableOptions,
+    ) -> Result<&Table, DbError> {
+        let statement = Table {
+            keyspace: keyspace.clone(),
+            name: table.clone(),
+            schema: schema.clone(),
+            options: options.clone(),
+        }
+        .create_statement();
+        self.change_schema(
+            &keyspace,
+            if_not_exists(statement, "CREATE TABLE ", ignore_existence),
+        )?;
+
+        self.local
+            .create_table(keyspace, table, ignore_existence, schema, options)
+    }
+
+    fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError> {
+        let statement = format!("DROP KEYSPACE {}{keyspace}", if_exists(ignore_existence));
+        self.change_schema(keyspace, statement)?;
+
+        self.local.drop_keyspace(keyspace, ignore_existence)
+    }
+
+    fn drop_table(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError> {
+        let statement = format!(
+            "DROP TABLE {}{keyspace}.{table}",
+            if_exists(ignore_existence)
+        );
+        self.change_schema(keyspace, statement)?;
+
+        self.local.drop_table(keyspace, table, ignore_existence)
+    }
+
+    fn create_type(
+        &mut self,
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError> {
+        let statement = export::create_type(&UserDefinedType {
+            name: name.clone(),
+            keyspace: keyspace.clone(),
+            field_types: field_types.clone(),
+        });
+        self.change_schema(
+            &keyspace,
+            if_not_exists(statement, "CREATE TYPE ", ignore_existence),
+        )?;
+
+        self.local
+            .create_type(keyspace, name, ignore_existence, field_types)
+    }
+
+    fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError> {
+        let statement = format!("DROP TYPE {}{keyspace}.{name}", if_exists(ignore_existence));
+        self.change_schema(keyspace, statement)?;
+
+        self.local.drop_type(keyspace, name, ignore_existence)
+    }
+
+    fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace> {
+        self.local.get_keyspace(keyspace)
+    }
+
+    fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_> {
+        self.local.keyspaces()
+    }
+
+    fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
+        self.local.get_table(keyspace, table)
+    }
+}
+
+impl cql::QueryCache for RemoteEngine {
+    fn store(&mut self, id: u128, query: QueryString) -> Result<(), DbError> {
+        self.local.store(id, query)
+    }
+
+    fn retrieve(&self, id: u128) -> Result<Option<QueryString>, DbError> {
+        self.local.retrieve(id)
+    }
+}
+
+impl cql::Engine for RemoteEngine {
+    fn insert(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        values: Vec<(String, Option<CqlValue>)>,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        if is_system_keyspace(keyspace) {
+            return self.local.insert(
+                keyspace,
+                table,
+                partition_key,
+                clustering_key,
+                values,
+                timestamp,
+            );
+        }
+
+        let statement = self.insert_statement(
+            keyspace,
+            table,
+            &partition_key,
+            &clustering_key,
+            &values,
+            timestamp,
+        )?;
+        self.remote.run(Command::Query(statement)).map(drop)
+    }
+
+    fn delete(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        if is_system_keyspace(keyspace) {
+            return self
+                .local
+                .delete(keyspace, table, partition_key, clustering_key, timestamp);
+        }
+
+        let statement =
+            self.delete_statement(keyspace, table, &partition_key, &clustering_key, timestamp)?;
+        self.remote.run(Command::Query(statement)).map(drop)
+    }
+
+    fn read<'a>(
+        &'a self,
+        keyspace: &'a str,
+        table: &'a str,
+        partition_key: &'a PartitionKeyValue,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<RowsIterator<'a>, Error> {
+        if is_system_keyspace(keyspace) {
+            return self
+                .local
+                .read(keyspace, table, partition_key, clustering_range, predicate);
+        }
+
+        let schema = self.table_schema(keyspace, table)?;
+        let restrictions = format!(
+            " WHERE {}",
+            restrictions(schema, partition_key, &ClusteringKeyValue::Empty)
+        );
+        let rows = self
+            .select(keyspace, table, &restrictions)?
+            .into_iter()
+            .filter(move |row| {
+                clustering_range.contains(&row.clustering)
+                    && predicate.matches(|column| row.row.get(column))
+            });
+
+        Ok(Box::new(rows))
+    }
+
+    fn scan<'a>(
+        &'a self,
+        keyspace: &'a str,
+        table: &'a str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<RowsIterator<'a>, Error> {
+        if is_system_keyspace(keyspace) {
+            return self.local.scan(keyspace, table, range, predicate);
+        }
+
+        let schema = self.table_schema(keyspace, table)?;
+        let from = range
+            .from_key
+            .map(|key| (Murmur3Partitioner.token(&key), key));
+        let start = match &from {
+            Some((token, _)) => Bound::Included(*token),
+            None => range.start,
+        };
+        if PartitionKeyValueRange::tokens(start, range.end).is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
+
+        let token = format!(
+            "token({})",
+            schema
+                .partition_key
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let bounds = [
+            match start {
+                Bound::Included(start) => Some(format!("{token} >= {start}")),
+                Bound::Excluded(start) => Some(format!("{token} > {start}")),
+                Bound::Unbounded => None,
+            },
+            match range.end {
+                Bound::Included(end) => Some(format!("{token} <= {end}")),
+                Bound::Excluded(end) => Some(format!("{token} < {end}")),
+                Bound::Unbounded => None,
+            },
+        ];
+        let bounds = bounds.into_iter().flatten().collect::<Vec<_>>();
+        let restrictions = match bounds.is_empty() {
+            true => String::new(),
+            false => format!(" WHERE {}", bounds.join(" AND ")),
+        };
+
+        let rows = self
+            .select(keyspace, table, &restrictions)?
+            .into_iter()
+            // partitions sharing the token of the resumed one are ordered by their keys
+            .filter(move |row| match &from {
+                Some((token, key)) => {
+                    Murmur3Partitioner.token(&row.partition) != *token || row.partition >= *key
+                }
+                None => true,
+            })
+            .filter(move |row| predicate.matches(|column| row.row.get(column)));
+
+        Ok(Box::new(rows))
+    }
+
+    fn count(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        clustering_range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error> {
+        Ok(self
+            .read(keyspace, table, partition_key, clustering_range, predicate)?
+            .count())
+    }
+
+    fn count_scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize, Error> {
+        Ok(self.scan(keyspace, table, range, predicate)?.count())
+    }
+
+    /// Writes to the cluster are sent as a single logged batch, which is applied by it atomically.
+    /// Writes of system keyspaces are applied locally once the batch succeeded.
+    fn apply(&mut self, mutations: Vec<Mutation>) -> Result<(), Error> {
+        let (local, remote): (Vec<_>, Vec<_>) = mutations
+            .into_iter()
+            .partition(|mutation| is_system_keyspace(mutation.table().0));
+        let statements = remote
+            .iter()
+            .map(|mutation| match mutation {
+                Mutation::Insert {
+                    keyspace,
+                    table,
+                    partition_key,
+                    clustering_key,
+                    values,
+                    timestamp,
+                } => self.insert_statement(
+                    keyspace,
+                    table,
+                    partition_key,
+                    clustering_key,
+                    values,
+                    *timestamp,
+                ),
+                Mutation::Delete {
+                    keyspace,
+                    table,
+                    partition_key,
+                    clustering_key,
+                    timestamp,
+                } => self.delete_statement(
+                    keyspace,
+                    table,
+                    partition_key,
+                    clustering_key,
+                    *timestamp,
+                ),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if !statements.is_empty() {
+            let batch = format!("BEGIN BATCH {}; APPLY BATCH", statements.join("; "));
+            self.remote.run(Command::Query(batch))?;
+        }
+        self.local.apply(local)
+    }
+}
+
+/// Connection to the cluster, served by a thread with a runtime of its own,
+/// so engine methods can block on it while sessions run in any other runtime.
+#[derive(Debug)]
+pub(super) struct Remote {
+    commands: mpsc::Sender<(Command, mpsc::Sender<Result<Bytes, Error>>)>,
+}
+
+#[derive(Debug)]
+enum Command {
+    /// Statements without rows, like writes and schema changes
+    Query(String),
+    /// Statements with rows, they are prepared first, so rows of their executions come without metadata
+    Select(String),
+}
+
+impl Remote {
+    pub(super) fn connect(addr: SocketAddr) -> eyre::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        let (commands, received) = mpsc::channel::<(Command, mpsc::Sender<_>)>();
+        let (connected, connection) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("kassandra-remote".to_owned())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let mut connection = match CqlConnection::connect(addr).await {
+                        Ok(connection) => connection,
+                        Err(error) => {
+                            let _ = connected.send(Err(error));
+                            return;
+                        }
+                    };
+                    let _ = connected.send(Ok(()));
+
+                    // the thread serves nothing else, so it can block while it waits for commands
+                    while let Ok((command, reply)) = received.recv() {
+                        let _ = reply.send(command.run(&mut connection).await);
+                    }
+                })
+            })?;
+        connection.recv()??;
+
+        Ok(Self { commands })
+    }
+
+    /// Body of the `RESULT` response, `ERROR` responses of the cluster are returned as they are
+    fn run(&self, command: Command) -> Result<Bytes, Error> {
+        let disconnected = || Error::new(DbError::ServerError, "Remote cluster is disconnected");
+
+        let (reply, result) = mpsc::channel();
+        self.commands
+            .send((command, reply))
+            .map_err(|_| disconnected())?;
+        result.recv().map_err(|_| disconnected())?
+    }
+
+    /// Rows of the table with every column selected, `restrictions` follow the table name as they are
+    pub(super) fn select(
+        &self,
+        keyspace: &str,
+        table: &str,
+        schema: &TableSchema,
+        restrictions: &str,
+    ) -> Result<Vec<RowEntry>, Error> {
+        let columns = schema.columns.keys().cloned().collect::<Vec<_>>();
+        let statement = format!(
+            "SELECT {} FROM {keyspace}.{table}{restrictions}",
+            columns.join(", ")
+        );
+        let body = self.run(Command::Select(statement))?;
+        let types = schema.columns.values().map(|it| &it.ty).collect::<Vec<_>>();
+
+        let rows = rows(&body, &types)?
+            .into_iter()
+            .map(|cells| {
+                let row = columns
+                    .iter()
+                    .cloned()
+                    .zip(cells)
+                    .filter_map(|(name, value)| Some((name, value?)))
+                    .collect::<BTreeMap<_, _>>();
+
+                RowEntry {
+                    partition: partition_key(schema, &row),
+                    clustering: clustering_key(schema, &row),
+                    row,
+                }
+            })
+            .collect();
+
+        Ok(rows)
+    }
+}
+
+impl Command {
+    async fn run(self, connection: &mut CqlConnection<TcpStream>) -> Result<Bytes, Error> {
+        let result = match self {
+            Command::Query(statement) => {
+                connection
+                    .query(&statement, &QueryParameters::default())
+                    .await
+            }
+            Command::Select(statement) => match connection.prepare(&statement).await {
+                Ok(id) => {
+                    let parameters = QueryParameters {
+                        flags: QueryFlags::SKIP_METADATA,
+                        ..QueryParameters::default()
+                    };
+                    connection.execute(&id, &parameters).await
+                }
+                Err(error) => Err(error),
+            },
+        };
+
+        result.map_err(|report| {
+            report
+                .downcast::<Error>()
+                .unwrap_or_else(|report| Error::new(DbError::ServerError, report))
+        })
+    }
+}
+
+/// Cells of a `Rows` result without metadata, decoded as the types of the selected columns
+fn rows(body: &[u8], types: &[&ColumnType]) -> Result<Vec<Vec<Option<CqlValue>>>, Error> {
+    const HAS_MORE_PAGES: i32 = 0x0002;
+    const NO_METADATA: i32 = 0x0004;
+    let unexpected = |reason: &str| Error::new(DbError::ProtocolError, reason);
+
+    let (rest, kind) = be_i32::<_, nom::error::Error<_>>(body)?;
+    if kind != 0x0002 {
+        return Err(unexpected("Unexpected result kind, expected rows"));
+    }
+    let (rest, flags) = be_i32::<_, nom::error::Error<_>>(rest)?;
+    let (rest, _columns) = be_i32::<_, nom::error::Error<_>>(rest)?;
+    if flags & HAS_MORE_PAGES != 0 {
+        return Err(unexpected("Rows of the remote cluster are paged"));
+    }
+    if flags & NO_METADATA == 0 {
+        return Err(unexpected("Rows of the remote cluster came with metadata"));
+    }
+
+    let (mut rest, count) = be_i32::<_, nom::error::Error<_>>(rest)?;
+    let mut rows = vec![];
+    for _ in 0..count {
+        let mut row = vec![];
+        for ty in types {
+            let cell;
+            (rest, cell) = opt_deserialize_value(rest, ty)?;
+            row.push(cell);
+        }
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+fn partition_key(schema: &TableSchema, row: &BTreeMap<String, CqlValue>) -> PartitionKeyValue {
+    let value = |name: &String| row.get(name).cloned().unwrap_or(CqlValue::Empty);
+
+    match &schema.partition_key {
+        PrimaryKey::Empty => PartitionKeyValue::Empty,
+        PrimaryKey::Simple(name) => PartitionKeyValue::Simple(value(name)),
+        PrimaryKey::Composite(names) => {
+            PartitionKeyValue::Composite(names.iter().map(value).collect())
+        }
+    }
+}
+
+/// Rows of partitions with only static cells have no clustering values
+fn clustering_key(schema: &TableSchema, row: &BTreeMap<String, CqlValue>) -> ClusteringKeyValue {
+    let names = match &schema.clustering_key {
+        PrimaryKey::Empty => return ClusteringKeyValue::Empty,
+        PrimaryKey::Simple(name) => std::slice::from_ref(name),
+        PrimaryKey::Composite(names) => names.as_slice(),
+    };
+    let values = names
+        .iter()
+        .map(|name| row.get(name).cloned())
+        .collect::<Vec<_>>();
+
+    match (&schema.clustering_key, values.as_slice()) {
+        _ if values.iter().all(Option::is_none) => ClusteringKeyValue::Empty,
+        (PrimaryKey::Simple(_), [value]) => ClusteringKeyValue::Simple(value.clone()),
+        _ => ClusteringKeyValue::Composite(values),
+    }
+}
+
+/// Columns of the primary key with the literals of their values, clustering prefixes leave out the rest
+fn key_literals<'a>(
+    schema: &'a TableSchema,
+    partition_key: &'a PartitionKeyValue,
+    clustering_key: &'a ClusteringKeyValue,
+) -> impl Iterator<Item = (String, String)> + 'a {
+    let partition = schema
+        .partition_key
+        .into_iter()
+        .zip(partition_key.into_iter().map(Some));
+    let clustering = schema
+        .clustering_key
+        .into_iter()
+        .zip(clustering_key.into_iter().map(Option::as_ref));
+
+    partition
+        .chain(clustering)
+        .filter_map(|(name, value)| Some((name, value?)))
+        .map(|(name, value)| {
+            (
+                name.clone(),
+                export::literal(value, &schema.columns[name].ty),
+            )
+        })
+}
+
+pub(super) fn restrictions(
+    schema: &TableSchema,
+    partition_key: &PartitionKeyValue,
+    clustering_key: &ClusteringKeyValue,
+) -> String {
+    key_literals(schema, partition_key, clustering_key)
+        .map(|(name, value)| format!("{name} = {value}"))
+        .collect::<Vec<_>>()
+        .join(" AND ")
+}
+
+/// `ignore_existence` of schema changes is forwarded as `IF NOT EXISTS`
+fn if_not_exists(statement: String, create: &str, ignore_existence: bool) -> String {
+    match ignore_existence {
+        true => statement.replacen(create, &format!("{create}IF NOT EXISTS "), 1),
+        false => statement,
+    }
+}
+
+fn if_exists(ignore_existence: bool) -> &'static str {
+    match ignore_existence {
+        true => "IF EXISTS ",
+        false => "",
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use crate::{
+    cql::value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+    error::DbError,
+    storage::{self, Storage},
+};
+
+/// Live server state answering reads of `system_views` tables.
+///
+/// Handles are cheap to clone and share the same state, servers update it
+/// while the engine renders it into rows every time the tables are queried.
+#[derive(Debug, Clone, Default)]
+pub struct SystemViews {
+    state: Arc<RwLock<ViewsState>>,
+}
+
+#[derive(Debug)]
+struct ViewsState {
+    started: Instant,
+    settings: BTreeMap<String, String>,
+    clients: BTreeMap<SocketAddr, ClientInfo>,
+    requests: i64,
+}
+
+impl Default for ViewsState {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            settings: Default::default(),
+            clients: Default::default(),
+            requests: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClientInfo {
+    options: HashMap<String, String>,
+    keyspace: Option<String>,
+    protocol_version: Option<i32>,
+    requests: i64,
+    ssl_enabled: bool,
+}
+
+impl SystemViews {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exposed in `system_views.settings`
+    pub fn set_setting(&self, name: impl Into<String>, value: impl ToString) {
+        self.state
+            .write()
+            .unwrap()
+            .settings
+            .insert(name.into(), value.to_string());
+    }
+
+    /// Client is listed in `system_views.clients` until the returned guard is dropped
+    pub fn connect(&self, addr: SocketAddr, ssl_enabled: bool) -> ConnectedClient {
+        self.state.write().unwrap().clients.insert(
+            addr,
+            ClientInfo {
+                ssl_enabled,
+                ..Default::default()
+            },
+        );
+
+        ConnectedClient {
+            views: self.clone(),
+            addr,
+        }
+    }
+
+    pub(crate) fn write_table(
+        &self,
+        storage: &mut impl Storage,
+        table: &str,
+    ) -> Result<(), DbError> {
+        let state = self.state.read().unwrap();
+        let timestamp = storage::write_timestamp();
+
+        match table {
+            "clients" => {
+                for (addr, client) in &state.clients {
+                    let options = client
+                        .options
+                        .iter()
+                        .collect::<BTreeMap<_, _>>()
+                        .into_iter()
+                        .map(|(key, value)| (key.clone().into(), value.clone().into()))
+                        .collect();
+                    let option = |name: &str| client.options.get(name).cloned().map(CqlValue::from);
+                    let stage = match client.protocol_version {
+                        Some(_) => "ready",
+                        None => "established",
+                    };
+
+                    storage.write(
+                        "system_views",
+                        "clients",
+                        PartitionKeyValue::Simple(CqlValue::Inet(addr.ip())),
+                        ClusteringKeyValue::Simple(Some(CqlValue::Int(addr.port().into()))),
+                        [
+                            ("address".to_owned(), Some(CqlValue::Inet(addr.ip()))),
+                            ("port".to_owned(), Some(CqlValue::Int(addr.port().into()))),
+                            ("client_options".to_owned(), Some(CqlValue::Map(options))),
+                            ("connection_stage".to_owned(), Some(stage.to_owned().into())),
+                            ("driver_name".to_owned(), option("DRIVER_NAME")),
+                            ("driver_version".to_owned(), option("DRIVER_VERSION")),
+                            ("hostname".to_owned(), Some(addr.ip().to_string().into())),
+                            (
+                                "keyspace_name".to_owned(),
+                                client.keyspace.clone().map(CqlValue::from),
+                            ),
+                            (
+                                "protocol_version".to_owned(),
+                                client.protocol_version.map(CqlValue::Int),
+                            ),
+                            (
+                                "request_count".to_owned(),
+                                Some(CqlValue::BigInt(client.requests)),
+                            ),
+                            (
+                                "ssl_enabled".to_owned(),
+                                Some(CqlValue::Boolean(client.ssl_enabled)),
+                            ),
+                        ]
+                        .into_iter(),
+                        timestamp,
+                    )?;
+                }
+            }
+            "settings" => {
+                for (name, value) in &state.settings {
+                    storage.write(
+                        "system_views",
+                        "settings",
+                        PartitionKeyValue::Simple(name.clone().into()),
+                        ClusteringKeyValue::Empty,
+                        [
+                            ("name".to_owned(), CqlValue::from(name.clone())),
+                            ("value".to_owned(), value.clone().into()),
+                        ]
+                        .into_iter(),
+                        timestamp,
+                    )?;
+                }
+            }
+            "metrics" => {
+                let metrics = [
+                    (
+                        "uptime_ms",
+                        state
+                            .started
+                            .elapsed()
+                            .as_millis()
+                            .try_into()
+                            .unwrap_or(i64::MAX),
+                    ),
+                    ("requests", state.requests),
+                    ("connected_clients", state.clients.len() as i64),
+                ];
+                for (name, value) in metrics {
+                    storage.write(
+                        "system_views",
+                        "metrics",
+                        PartitionKeyValue::Simple(name.to_owned().into()),
+                        ClusteringKeyValue::Empty,
+                        [
+                            ("name".to_owned(), CqlValue::from(name.to_owned())),
+                            ("value".to_owned(), CqlValue::BigInt(value)),
+                        ]
+                        .into_iter(),
+                        timestamp,
+                    )?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Connection registered in [`SystemViews`], it is removed from `system_views.clients` on drop
+#[derive(Debug)]
+pub struct ConnectedClient {
+    views: SystemViews,
+    addr: SocketAddr,
+}
+
+impl ConnectedClient {
+    /// Records the protocol version and options the client sent in `STARTUP`
+    pub fn startup(&self, protocol_version: i32, options: &HashMap<String, String>) {
+        let mut state = self.views.state.write().unwrap();
+        if let Some(client) = state.clients.get_mut(&self.addr) {
+            client.protocol_version = Some(protocol_version);
+            client.options = options.clone();
+        }
+    }
+
+    /// Counts a request, `keyspace` is the one selected by the connection at the moment
+    pub fn request(&self, keyspace: Option<&str>) {
+        let mut state = self.views.state.write().unwrap();
+        state.requests += 1;
+        if let Some(client) = state.clients.get_mut(&self.addr) {
+            client.requests += 1;
+            client.keyspace = keyspace.map(str::to_owned);
+        }
+    }
+}
+
+impl Drop for ConnectedClient {
+    fn drop(&mut self) {
+        self.views.state.write().unwrap().clients.remove(&self.addr);
+    }
+}
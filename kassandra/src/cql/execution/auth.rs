@@ -0,0 +1,202 @@
+use std::collections::BTreeSet;
+
+use serde::Serialize;
+
+use crate::{
+    auth::{role_key, Role, AUTH_KEYSPACE},
+    cql::{
+        self,
+        execution::Executor,
+        query::Permission,
+        value::{ClusteringKeyValue, CqlValue},
+    },
+    error::DbError,
+    frame::response::{error::Error, result::QueryResult},
+};
+
+/// Changes of roles and of the permissions granted to them, which are rows of `system_auth` tables
+#[derive(Debug, Clone, Serialize)]
+pub enum AuthNode {
+    CreateRole {
+        role: String,
+        ignore_existence: bool,
+        login: bool,
+        superuser: bool,
+        salted_hash: Option<String>,
+        timestamp: i64,
+    },
+    DropRole {
+        role: String,
+        ignore_existence: bool,
+        timestamp: i64,
+    },
+    Grant {
+        role: String,
+        /// Name of the resource, like `data/ks/table`
+        resource: String,
+        permissions: BTreeSet<Permission>,
+        timestamp: i64,
+    },
+    Revoke {
+        role: String,
+        resource: String,
+        permissions: BTreeSet<Permission>,
+        timestamp: i64,
+    },
+}
+
+impl<E: cql::Engine> Executor<E> for AuthNode {
+    fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
+        match *self {
+            AuthNode::CreateRole {
+                role,
+                ignore_existence,
+                login,
+                superuser,
+                salted_hash,
+                timestamp,
+            } => {
+                if Role::read(engine, &role)?.is_some() {
+                    return match ignore_existence {
+                        true => Ok(QueryResult::Void),
+                        false => Err(Error::new(
+                            DbError::Invalid,
+                            format!("{role} already exists"),
+                        )),
+                    };
+                }
+
+                engine.insert(
+                    AUTH_KEYSPACE,
+                    "roles",
+                    role_key(&role),
+                    ClusteringKeyValue::Empty,
+                    vec![
+                        ("role".to_owned(), Some(CqlValue::Text(role.into()))),
+                        ("can_login".to_owned(), Some(CqlValue::Boolean(login))),
+                        (
+                            "is_superuser".to_owned(),
+                            Some(CqlValue::Boolean(superuser)),
+                        ),
+                        (
+                            "salted_hash".to_owned(),
+                            salted_hash.map(|it| CqlValue::Text(it.into())),
+                        ),
+                    ],
+                    timestamp,
+                )?;
+            }
+            AuthNode::DropRole {
+                role,
+                ignore_existence,
+                timestamp,
+            } => {
+                let Some(existing) = Role::read(engine, &role)? else {
+                    return match ignore_existence {
+                        true => Ok(QueryResult::Void),
+                        false => Err(doesnt_exist(&role)),
+                    };
+                };
+
+                for resource in existing.permissions.keys() {
+                    engine.delete(
+                        AUTH_KEYSPACE,
+                        "role_permissions",
+                        role_key(&role),
+                        resource_key(resource),
+                        timestamp,
+                    )?;
+                }
+                engine.delete(
+                    AUTH_KEYSPACE,
+                    "roles",
+                    role_key(&role),
+                    ClusteringKeyValue::Empty,
+                    timestamp,
+                )?;
+            }
+            AuthNode::Grant {
+                role,
+                resource,
+                permissions,
+                timestamp,
+            } => {
+                let existing = Role::read(engine, &role)?.ok_or_else(|| doesnt_exist(&role))?;
+                let mut granted = existing
+                    .permissions
+                    .get(&resource)
+                    .cloned()
+                    .unwrap_or_default();
+                granted.extend(permissions);
+
+                write_permissions(engine, role, resource, granted, timestamp)?;
+            }
+            AuthNode::Revoke {
+                role,
+                resource,
+                permissions,
+                timestamp,
+            } => {
+                let existing = Role::read(engine, &role)?.ok_or_else(|| doesnt_exist(&role))?;
+                let mut granted = existing
+                    .permissions
+                    .get(&resource)
+                    .cloned()
+                    .unwrap_or_default();
+                granted.retain(|it| !permissions.contains(it));
+
+                write_permissions(engine, role, resource, granted, timestamp)?;
+            }
+        }
+
+        Ok(QueryResult::Void)
+    }
+}
+
+/// Rows without permissions left are deleted
+fn write_permissions<E: cql::Engine>(
+    engine: &mut E,
+    role: String,
+    resource: String,
+    permissions: BTreeSet<Permission>,
+    timestamp: i64,
+) -> Result<(), Error> {
+    if permissions.is_empty() {
+        return engine.delete(
+            AUTH_KEYSPACE,
+            "role_permissions",
+            role_key(&role),
+            resource_key(&resource),
+            timestamp,
+        );
+    }
+
+    // elements of sets are ordered by their values
+    let permissions = permissions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|it| CqlValue::Text(it.into()))
+        .collect();
+    engine.insert(
+        AUTH_KEYSPACE,
+        "role_permissions",
+        role_key(&role),
+        resource_key(&resource),
+        vec![
+            ("role".to_owned(), Some(CqlValue::Text(role.into()))),
+            ("resource".to_owned(), Some(CqlValue::Text(resource.into()))),
+            ("permissions".to_owned(), Some(CqlValue::Set(permissions))),
+        ],
+        timestamp,
+    )
+}
+
+fn resource_key(resource: &str) -> ClusteringKeyValue {
+    ClusteringKeyValue::Simple(Some(CqlValue::Text(resource.into())))
+}
+
+fn doesnt_exist(role: &str) -> Error {
+    Error::new(DbError::Invalid, format!("{role} doesn't exist"))
+}
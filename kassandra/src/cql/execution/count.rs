@@ -0,0 +1,69 @@
+use serde::Serialize;
+use tracing::{instrument, Level};
+
+use crate::{
+    cql::{
+        self,
+        execution::{Executor, Reader},
+        plan::Plan,
+        value::CqlValue,
+    },
+    error::DbError,
+    frame::response::{
+        error::Error,
+        result::{QueryResult, Row, Rows},
+    },
+};
+
+/// Counts the rows its source would return, asking the engine for the count
+/// rather than materializing them, so it's a single row regardless of paging and limits.
+#[derive(Debug, Clone, Serialize)]
+pub struct CountNode(pub Box<Plan>);
+
+impl<E: cql::Engine> Executor<E> for CountNode {
+    fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
+        <Self as Reader<E>>::read(self, engine)
+    }
+}
+
+impl<E: cql::Engine> Reader<E> for CountNode {
+    #[instrument(level = Level::TRACE, skip(engine), err)]
+    fn read(self: Box<Self>, engine: &E) -> Result<QueryResult, Error> {
+        let (count, mut metadata) = match *self.0 {
+            Plan::Select(node) => (
+                engine.count(
+                    &node.keyspace,
+                    &node.table,
+                    &node.partition_key,
+                    node.clustering_range,
+                    node.predicate,
+                )?,
+                node.metadata,
+            ),
+            Plan::Scan(node) => (
+                engine.count_scan(
+                    &node.keyspace,
+                    &node.table,
+                    node.partition_range,
+                    node.predicate,
+                )?,
+                node.metadata,
+            ),
+            other => {
+                return Err(Error::new(
+                    DbError::ServerError,
+                    format!("{} plan can't be counted", other.name()),
+                ))
+            }
+        };
+        metadata.paging_state = None;
+
+        Ok(QueryResult::Rows(Rows {
+            metadata,
+            rows: vec![Row {
+                columns: vec![Some(CqlValue::BigInt(count as i64))],
+            }],
+            encoded: None,
+        }))
+    }
+}
@@ -15,6 +15,8 @@ pub struct DeleteNode {
     pub table: String,
     pub partition_key: PartitionKeyValue,
     pub clustering_key: ClusteringKeyValue,
+    /// Write timestamp in microseconds
+    pub timestamp: i64,
 }
 
 impl<E: cql::Engine> Executor<E> for DeleteNode {
@@ -24,6 +26,7 @@ impl<E: cql::Engine> Executor<E> for DeleteNode {
             &self.table,
             self.partition_key,
             self.clustering_key,
+            self.timestamp,
         )?;
 
         Ok(QueryResult::Void)
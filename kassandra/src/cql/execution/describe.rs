@@ -0,0 +1,154 @@
+use serde::Serialize;
+use tracing::{instrument, Level};
+
+use crate::{
+    cql,
+    cql::{
+        column::ColumnType,
+        execution::{Executor, Reader},
+        schema::{keyspace::Keyspace, system::is_system_keyspace},
+        value::CqlValue,
+    },
+    error::DbError,
+    frame::response::{
+        error::Error,
+        result::{ColumnSpec, QueryResult, ResultMetadata, Row, Rows, TableSpec},
+    },
+};
+
+/// Renders schema the way cassandra answers `DESCRIBE` statements,
+/// one row per keyspace or table, with statements recreating them unless only names are listed.
+#[derive(Debug, Clone, Serialize)]
+pub enum DescribeNode {
+    Keyspaces,
+    Tables,
+    /// All keyspaces, except system ones
+    Schema,
+    Keyspace(String),
+    Table {
+        keyspace: String,
+        table: String,
+    },
+}
+
+impl<E: cql::Engine> Executor<E> for DescribeNode {
+    fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
+        <Self as Reader<E>>::read(self, engine)
+    }
+}
+
+impl<E: cql::Engine> Reader<E> for DescribeNode {
+    #[instrument(level = Level::TRACE, skip(engine), err)]
+    fn read(self: Box<Self>, engine: &E) -> Result<QueryResult, Error> {
+        let mut entries = vec![];
+
+        let with_statements = match *self {
+            DescribeNode::Keyspaces => {
+                entries.extend(engine.keyspaces().map(keyspace_entry));
+                false
+            }
+            DescribeNode::Tables => {
+                entries.extend(engine.keyspaces().flat_map(table_entries));
+                false
+            }
+            DescribeNode::Schema => {
+                for keyspace in engine
+                    .keyspaces()
+                    .filter(|it| !is_system_keyspace(&it.name))
+                {
+                    entries.push(keyspace_entry(keyspace));
+                    entries.extend(table_entries(keyspace));
+                }
+                true
+            }
+            DescribeNode::Keyspace(name) => {
+                let keyspace = engine.get_keyspace(&name).ok_or_else(|| {
+                    Error::new(DbError::Invalid, format!("Keyspace '{name}' not found"))
+                })?;
+                entries.push(keyspace_entry(keyspace));
+                entries.extend(table_entries(keyspace));
+                true
+            }
+            DescribeNode::Table { keyspace, table } => {
+                let entry = engine
+                    .get_keyspace(&keyspace)
+                    .and_then(|it| it.tables.get(&table))
+                    .ok_or_else(|| {
+                        Error::new(
+                            DbError::Invalid,
+                            format!("Table '{table}' not found in keyspace '{keyspace}'"),
+                        )
+                    })?;
+                entries.push(Entry {
+                    keyspace,
+                    ty: "table",
+                    name: table,
+                    create_statement: entry.create_statement(),
+                });
+                true
+            }
+        };
+
+        let mut col_specs = ["keyspace_name", "type", "name"]
+            .into_iter()
+            .map(|name| ColumnSpec::new(name, ColumnType::Text))
+            .collect::<Vec<_>>();
+        if with_statements {
+            col_specs.push(ColumnSpec::new("create_statement", ColumnType::Text));
+        }
+
+        let rows = entries
+            .into_iter()
+            .map(|entry| {
+                let mut columns = vec![
+                    Some(CqlValue::Text(entry.keyspace.into())),
+                    Some(CqlValue::Text(entry.ty.into())),
+                    Some(CqlValue::Text(entry.name.into())),
+                ];
+                if with_statements {
+                    columns.push(Some(CqlValue::Text(entry.create_statement.into())));
+                }
+                Row { columns }
+            })
+            .collect();
+
+        Ok(QueryResult::Rows(Rows {
+            metadata: ResultMetadata {
+                global_spec: Some(TableSpec {
+                    ks_name: "system".to_owned(),
+                    table_name: "describe".to_owned(),
+                }),
+                paging_state: None,
+                col_specs,
+                no_metadata: false,
+            },
+            rows,
+            encoded: None,
+        }))
+    }
+}
+
+struct Entry {
+    keyspace: String,
+    ty: &'static str,
+    name: String,
+    create_statement: String,
+}
+
+fn keyspace_entry(keyspace: &Keyspace) -> Entry {
+    Entry {
+        keyspace: keyspace.name.clone(),
+        ty: "keyspace",
+        name: keyspace.name.clone(),
+        create_statement: keyspace.create_statement(),
+    }
+}
+
+fn table_entries(keyspace: &Keyspace) -> impl Iterator<Item = Entry> + '_ {
+    keyspace.tables.values().map(|table| Entry {
+        keyspace: table.keyspace.clone(),
+        ty: "table",
+        name: table.name.clone(),
+        create_statement: table.create_statement(),
+    })
+}
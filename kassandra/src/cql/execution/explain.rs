@@ -0,0 +1,257 @@
+use std::ops::{Bound, RangeBounds};
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    cql,
+    cql::{
+        column::ColumnType,
+        execution::{
+            selector::{ColumnSelector, Transform},
+            Executor, Reader,
+        },
+        json,
+        plan::Plan,
+        value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+    },
+    frame::response::{
+        error::Error,
+        result::{ColumnSpec, QueryResult, ResultMetadata, Row, Rows, TableSpec},
+    },
+    storage::{Like, Predicate},
+};
+
+/// Renders the plan of an `EXPLAIN` statement instead of running it,
+/// one row per node of the plan tree, with parameters of the node as json.
+///
+/// Parameters of reads and writes are the ones of the statement: keys, ranges, filters, columns and limits,
+/// unbounded ranges and limits are left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainNode(pub Box<Plan>);
+
+impl<E: cql::Engine> Executor<E> for ExplainNode {
+    fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
+        <Self as Reader<E>>::read(self, engine)
+    }
+}
+
+impl<E: cql::Engine> Reader<E> for ExplainNode {
+    fn read(self: Box<Self>, _: &E) -> Result<QueryResult, Error> {
+        let mut rows = vec![];
+        let mut plan = Some((0, &*self.0));
+        while let Some((depth, node)) = plan.take() {
+            let details = match node {
+                Plan::Aggregate { source, aggregate } => {
+                    plan = Some((depth + 1, &**source));
+                    json!({ "aggregate": aggregate })
+                }
+                Plan::Explain(ExplainNode(source)) => {
+                    plan = Some((depth + 1, &**source));
+                    Value::Null
+                }
+                Plan::Select(node) => {
+                    let mut details = table(&node.keyspace, &node.table);
+                    details.insert("partition_key".into(), partition_key(&node.partition_key));
+                    range(
+                        &mut details,
+                        "clustering",
+                        node.clustering_range.start_bound(),
+                        node.clustering_range.end_bound(),
+                        clustering_key,
+                    );
+                    reads(
+                        &mut details,
+                        &node.predicate,
+                        &node.selector.0,
+                        node.limit,
+                        node.result_page_size,
+                    );
+                    Value::Object(details)
+                }
+                Plan::Scan(node) => {
+                    let mut details = table(&node.keyspace, &node.table);
+                    range(
+                        &mut details,
+                        "token",
+                        node.partition_range.start.as_ref(),
+                        node.partition_range.end.as_ref(),
+                        |token| json!(token),
+                    );
+                    reads(
+                        &mut details,
+                        &node.predicate,
+                        &node.selector.0,
+                        node.limit,
+                        node.result_page_size,
+                    );
+                    Value::Object(details)
+                }
+                Plan::Insert(node) => write(
+                    &node.keyspace,
+                    &node.table,
+                    &node.partition_key,
+                    &node.clustering_key,
+                    &node.values,
+                ),
+                Plan::Update(node) => write(
+                    &node.keyspace,
+                    &node.table,
+                    &node.partition_key,
+                    &node.clustering_key,
+                    &node.values,
+                ),
+                Plan::Delete(node) => write(
+                    &node.keyspace,
+                    &node.table,
+                    &node.partition_key,
+                    &node.clustering_key,
+                    &[],
+                ),
+                Plan::AlterSchema(node) => json!(node),
+                Plan::Describe(node) => json!(node),
+                Plan::Auth(node) => json!(node),
+            };
+
+            rows.push(Row {
+                columns: vec![
+                    Some(CqlValue::Int(depth)),
+                    Some(CqlValue::Text(node.name().into())),
+                    Some(CqlValue::Text(details.to_string().into())),
+                ],
+            });
+        }
+
+        Ok(QueryResult::Rows(Rows {
+            metadata: ResultMetadata {
+                global_spec: Some(TableSpec {
+                    ks_name: "system".to_owned(),
+                    table_name: "explain".to_owned(),
+                }),
+                paging_state: None,
+                col_specs: vec![
+                    ColumnSpec::new("depth", ColumnType::Int),
+                    ColumnSpec::new("node", ColumnType::Text),
+                    ColumnSpec::new("details", ColumnType::Text),
+                ],
+                no_metadata: false,
+            },
+            rows,
+            encoded: None,
+        }))
+    }
+}
+
+fn table(keyspace: &str, table: &str) -> Map<String, Value> {
+    let mut details = Map::new();
+    details.insert("table".into(), json!(format!("{keyspace}.{table}")));
+    details
+}
+
+fn write(
+    keyspace: &str,
+    name: &str,
+    partition: &PartitionKeyValue,
+    clustering: &ClusteringKeyValue,
+    values: &[(String, Option<CqlValue>)],
+) -> Value {
+    let mut details = table(keyspace, name);
+    details.insert("partition_key".into(), partition_key(partition));
+    if clustering != &ClusteringKeyValue::Empty {
+        details.insert("clustering_key".into(), clustering_key(clustering));
+    }
+    if !values.is_empty() {
+        let values = values
+            .iter()
+            .map(|(column, v)| (column.clone(), v.as_ref().map_or(Value::Null, value)))
+            .collect();
+        details.insert("values".into(), Value::Object(values));
+    }
+
+    Value::Object(details)
+}
+
+/// Filter, selected columns and limits, limits are `usize::MAX` when the statement has none
+fn reads(
+    details: &mut Map<String, Value>,
+    predicate: &Predicate,
+    selector: &[ColumnSelector],
+    limit: usize,
+    page_size: usize,
+) {
+    let filter = predicate
+        .equals
+        .iter()
+        .map(|(column, v)| (column.clone(), json!({ "=": value(v) })))
+        .chain(predicate.likes.iter().map(|(column, like)| {
+            let pattern = match like {
+                Like::Exact(text) => text.clone(),
+                Like::Prefix(text) => format!("{text}%"),
+                Like::Suffix(text) => format!("%{text}"),
+                Like::Contains(text) => format!("%{text}%"),
+            };
+            (column.clone(), json!({ "LIKE": pattern }))
+        }))
+        .collect::<Map<_, _>>();
+    if !filter.is_empty() {
+        details.insert("filter".into(), Value::Object(filter));
+    }
+    if !selector.is_empty() {
+        let columns = selector
+            .iter()
+            .map(|column| match column.transform {
+                Transform::Identity => json!(column.name),
+                Transform::ToJson => json!(format!("toJson({})", column.name)),
+            })
+            .collect();
+        details.insert("columns".into(), Value::Array(columns));
+    }
+    if limit != usize::MAX {
+        details.insert("limit".into(), json!(limit));
+    }
+    if page_size != usize::MAX {
+        details.insert("page_size".into(), json!(page_size));
+    }
+}
+
+/// `<name>_start` and `<name>_end` of a bounded range, as the operator and the bound
+fn range<T>(
+    details: &mut Map<String, Value>,
+    name: &str,
+    start: Bound<&T>,
+    end: Bound<&T>,
+    render: impl Fn(&T) -> Value,
+) {
+    for (side, bound, inclusive, exclusive) in
+        [("start", start, ">=", ">"), ("end", end, "<=", "<")]
+    {
+        let (operator, bound) = match bound {
+            Bound::Included(bound) => (inclusive, bound),
+            Bound::Excluded(bound) => (exclusive, bound),
+            Bound::Unbounded => continue,
+        };
+        details.insert(format!("{name}_{side}"), json!({ operator: render(bound) }));
+    }
+}
+
+fn partition_key(key: &PartitionKeyValue) -> Value {
+    match key {
+        PartitionKeyValue::Simple(v) => json!([value(v)]),
+        PartitionKeyValue::Composite(values) => values.iter().map(value).collect(),
+        PartitionKeyValue::Empty => json!([]),
+    }
+}
+
+fn clustering_key(key: &ClusteringKeyValue) -> Value {
+    let cell = |v: &Option<CqlValue>| v.as_ref().map_or(Value::Null, value);
+    match key {
+        ClusteringKeyValue::Simple(v) => json!([cell(v)]),
+        ClusteringKeyValue::Composite(values) => values.iter().map(cell).collect(),
+        ClusteringKeyValue::Empty => json!([]),
+    }
+}
+
+/// Value as `toJson` renders it
+fn value(value: &CqlValue) -> Value {
+    serde_json::from_str(&json::to_json(value)).unwrap_or(Value::Null)
+}
@@ -15,7 +15,10 @@ pub struct InsertNode {
     pub table: String,
     pub partition_key: PartitionKeyValue,
     pub clustering_key: ClusteringKeyValue,
-    pub values: Vec<(String, CqlValue)>,
+    /// Cells to write, nulls delete the cell
+    pub values: Vec<(String, Option<CqlValue>)>,
+    /// Write timestamp in microseconds
+    pub timestamp: i64,
 }
 
 impl<E: cql::Engine> Executor<E> for InsertNode {
@@ -26,6 +29,7 @@ impl<E: cql::Engine> Executor<E> for InsertNode {
             self.partition_key,
             self.clustering_key,
             self.values,
+            self.timestamp,
         )?;
 
         Ok(QueryResult::Void)
@@ -1,16 +1,18 @@
-use std::collections::BTreeMap;
-
 use serde::Serialize;
 use tracing::{instrument, Level};
 
 use crate::{
     cql,
-    cql::{column::ColumnType, execution::Executor, value::CqlValue},
+    cql::{
+        column::ColumnType,
+        execution::{Executor, Reader},
+        json,
+        value::CqlValue,
+    },
     frame::response::{
         error::Error,
         result::{ColumnSpec, QueryResult, ResultMetadata, Row, Rows},
     },
-    snapshot::ValueSnapshot,
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -19,55 +21,61 @@ pub struct JsonNode<N: ?Sized>(pub Box<N>);
 impl<E: cql::Engine, N: Executor<E> + ?Sized> Executor<E> for JsonNode<N> {
     #[instrument(level = Level::TRACE, skip(engine), err)]
     fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
-        let Rows {
-            metadata:
-                ResultMetadata {
-                    global_spec,
-                    paging_state,
-                    col_specs,
-                    ..
-                },
-            rows,
-        } = match self.0.execute(engine)? {
-            QueryResult::Rows(rows) => rows,
-            other => return Ok(other),
-        };
-
-        let metadata = ResultMetadata {
-            global_spec,
-            paging_state,
-            col_specs: vec![ColumnSpec {
-                table_spec: None,
-                name: "json".to_string(),
-                typ: ColumnType::Text,
-            }],
-        };
-
-        let rows = rows
-            .into_iter()
-            .map(|row| {
-                let serialized =
-                    serialize_columns(col_specs.iter().map(|it| &it.name), row.columns.into_iter());
-
-                Row {
-                    columns: vec![Some(CqlValue::Text(serialized))],
-                }
-            })
-            .collect();
+        Ok(to_json(self.0.execute(engine)?))
+    }
+}
 
-        Ok(QueryResult::Rows(Rows { metadata, rows }))
+impl<E: cql::Engine, N: Reader<E> + ?Sized> Reader<E> for JsonNode<N> {
+    #[instrument(level = Level::TRACE, skip(engine), err)]
+    fn read(self: Box<Self>, engine: &E) -> Result<QueryResult, Error> {
+        Ok(to_json(self.0.read(engine)?))
     }
 }
 
-fn serialize_columns<'a>(
-    columns: impl Iterator<Item = &'a String>,
-    values: impl Iterator<Item = Option<CqlValue>>,
-) -> String {
-    let mut map = BTreeMap::new();
+fn to_json(result: QueryResult) -> QueryResult {
+    let Rows {
+        metadata:
+            ResultMetadata {
+                global_spec,
+                paging_state,
+                col_specs,
+                ..
+            },
+        rows,
+        ..
+    } = match result {
+        QueryResult::Rows(rows) => rows,
+        other => return other,
+    };
 
-    for (column, value) in columns.zip(values) {
-        map.insert(column, value.map(ValueSnapshot::from));
-    }
+    let metadata = ResultMetadata {
+        global_spec,
+        paging_state,
+        col_specs: vec![ColumnSpec {
+            table_spec: None,
+            name: "json".to_string(),
+            typ: ColumnType::Text,
+        }],
+        no_metadata: false,
+    };
+
+    let rows = rows
+        .into_iter()
+        .map(|row| {
+            let serialized = json::row_to_json(
+                col_specs.iter().map(|it| it.name.as_str()),
+                row.columns.iter().map(Option::as_ref),
+            );
+
+            Row {
+                columns: vec![Some(CqlValue::Text(serialized.into()))],
+            }
+        })
+        .collect();
 
-    serde_json::to_string(&map).expect("row to be serializable")
+    QueryResult::Rows(Rows {
+        metadata,
+        rows,
+        encoded: None,
+    })
 }
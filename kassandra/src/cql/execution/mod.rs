@@ -5,26 +5,57 @@ pub use selector::{ColumnSelector, ColumnsSelector, Transform};
 use crate::{
     cql,
     cql::plan::{Aggregate, Plan},
-    frame::response::{error::Error, result::QueryResult},
+    frame::response::{
+        error::Error,
+        result::{QueryResult, ResultMetadata, Row},
+    },
 };
 
+mod auth;
+mod count;
 mod delete;
+mod describe;
+mod explain;
 mod insert;
 mod json;
 mod scan;
 mod schema;
 mod select;
 pub(crate) mod selector;
+mod update;
 
 pub use self::{
-    delete::DeleteNode, insert::InsertNode, json::JsonNode, scan::ScanNode, schema::AlterSchema,
-    select::SelectNode,
+    auth::AuthNode, count::CountNode, delete::DeleteNode, describe::DescribeNode,
+    explain::ExplainNode, insert::InsertNode, json::JsonNode, scan::ScanNode, schema::AlterSchema,
+    select::SelectNode, update::UpdateNode,
 };
 
 pub trait Executor<E: cql::Engine>: fmt::Debug {
     fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error>;
 }
 
+/// Executors which only read data, so they can run with shared access to the engine
+pub trait Reader<E: cql::Engine>: fmt::Debug {
+    fn read(self: Box<Self>, engine: &E) -> Result<QueryResult, Error>;
+}
+
+/// Readers producing their result page in chunks, every chunk resumes where the previous one ended,
+/// so the engine only has to be locked while a chunk is read.
+pub trait ChunkedReader<E: cql::Engine>: fmt::Debug + Send {
+    /// Reads up to `max_rows` rows, an empty chunk means the page is complete
+    fn next_chunk(&mut self, engine: &E, max_rows: usize) -> Result<Vec<Row>, Error>;
+
+    /// Finds the row the page ends before by looking one row past it, so the paging state
+    /// is known before the rows of the page are read.
+    ///
+    /// Chunks read after it stop at that row rather than after the page size,
+    /// rows written between chunks can't move the end of the page away from its paging state.
+    fn end_page(&mut self, engine: &E) -> Result<(), Error>;
+
+    /// Metadata of the page, its paging state is only known once the page is complete or ended up front
+    fn metadata(&self) -> ResultMetadata;
+}
+
 impl<E: cql::Engine + 'static> dyn Executor<E> {
     pub fn build(plan: Plan) -> Box<dyn Executor<E>> {
         match plan {
@@ -33,10 +64,39 @@ impl<E: cql::Engine + 'static> dyn Executor<E> {
             Plan::Insert(i) => Box::new(i),
             Plan::Scan(s) => Box::new(s),
             Plan::Delete(d) => Box::new(d),
+            Plan::Update(u) => Box::new(u),
+            Plan::Describe(d) => Box::new(d),
+            Plan::Explain(e) => Box::new(e),
+            Plan::Auth(a) => Box::new(a),
             Plan::Aggregate {
                 aggregate: Aggregate::Json,
                 source,
             } => Box::new(JsonNode(Self::build(*source))),
+            Plan::Aggregate {
+                aggregate: Aggregate::Count,
+                source,
+            } => Box::new(CountNode(source)),
+        }
+    }
+}
+
+impl<E: cql::Engine + 'static> dyn Reader<E> {
+    /// `None` for plans modifying data or schema
+    pub fn build(plan: Plan) -> Option<Box<dyn Reader<E>>> {
+        match plan {
+            Plan::Select(s) => Some(Box::new(s)),
+            Plan::Scan(s) => Some(Box::new(s)),
+            Plan::Describe(d) => Some(Box::new(d)),
+            Plan::Explain(e) => Some(Box::new(e)),
+            Plan::Aggregate {
+                aggregate: Aggregate::Json,
+                source,
+            } => Some(Box::new(JsonNode(Self::build(*source)?))),
+            Plan::Aggregate {
+                aggregate: Aggregate::Count,
+                source,
+            } => Some(Box::new(CountNode(source))),
+            _ => None,
         }
     }
 }
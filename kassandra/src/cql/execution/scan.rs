@@ -1,5 +1,3 @@
-use std::ops::RangeBounds;
-
 use bytes::{Bytes, BytesMut};
 use serde::Serialize;
 use tracing::{instrument, Level};
@@ -7,13 +5,14 @@ use tracing::{instrument, Level};
 use crate::{
     cql::{
         self,
+        engine::RowEntry,
         execution::{
             selector::{self, ColumnsSelector},
-            Executor,
-        },
-        value::{
-            ClusteringKeyValue, ClusteringKeyValueRange, PartitionKeyValue, PartitionKeyValueRange,
+            ChunkedReader, Executor, Reader,
         },
+        partitioner::{Murmur3Partitioner, Partitioner},
+        schema::ClusteringOrder,
+        value::{ClusteringKeyValue, PartitionKeyValue, PartitionKeyValueRange},
     },
     frame::{
         response::{
@@ -22,6 +21,7 @@ use crate::{
         },
         value::PagingState,
     },
+    storage::Predicate,
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -30,38 +30,105 @@ pub struct ScanNode {
     pub table: String,
     pub selector: ColumnsSelector,
     pub metadata: ResultMetadata,
-    pub clustering_key_start: ClusteringKeyValueRange,
+    pub clustering_key_start: Option<ClusteringKeyValue>,
+    pub clustering_order: Vec<ClusteringOrder>,
     pub partition_range: PartitionKeyValueRange,
+    /// Restrictions of `ALLOW FILTERING` scans
+    pub predicate: Predicate,
     pub limit: usize,
     pub result_page_size: usize,
+    /// Position of the first row after the page, once it is found by [`ChunkedReader::end_page`]
+    #[serde(skip)]
+    pub page_end: Option<(PartitionKeyValue, ClusteringKeyValue)>,
 }
 
 impl<E: cql::Engine> Executor<E> for ScanNode {
-    #[instrument(level = Level::TRACE, skip(engine), err)]
     fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
-        let mut scan = engine
-            .scan(&self.keyspace, &self.table, self.partition_range)?
-            .take(self.limit);
+        <Self as Reader<E>>::read(self, engine)
+    }
+}
 
-        let mut rows = vec![];
+impl<E: cql::Engine> Reader<E> for ScanNode {
+    #[instrument(level = Level::TRACE, skip(engine), err)]
+    fn read(mut self: Box<Self>, engine: &E) -> Result<QueryResult, Error> {
+        let rows = self.next_chunk(engine, self.result_page_size)?;
+
+        Ok(QueryResult::Rows(Rows {
+            metadata: self.metadata,
+            rows,
+            encoded: None,
+        }))
+    }
+}
+
+impl ScanNode {
+    /// Rows the page is read from, starting where the previous chunk ended
+    fn rows<'a, E: cql::Engine>(
+        &'a self,
+        engine: &'a E,
+    ) -> Result<impl Iterator<Item = RowEntry> + 'a, Error> {
         let mut first_partition = None;
 
+        Ok(engine
+            .scan(
+                &self.keyspace,
+                &self.table,
+                self.partition_range.clone(),
+                self.predicate.clone(),
+            )?
+            .filter(move |entry| {
+                let first_partition =
+                    first_partition.get_or_insert_with(|| entry.partition.clone());
+
+                entry.partition != *first_partition
+                    || self.clustering_key_start.as_ref().is_none_or(|start| {
+                        entry
+                            .clustering
+                            .cmp_ordered(start, &self.clustering_order)
+                            .is_ge()
+                    })
+            })
+            .take(self.limit))
+    }
+
+    /// Whether the row is the first one after the page or comes after it
+    fn is_past_end(&self, entry: &RowEntry) -> bool {
+        let Some((partition, clustering)) = &self.page_end else {
+            return false;
+        };
+        let partition_order = (Murmur3Partitioner.token(&entry.partition), &entry.partition)
+            .cmp(&(Murmur3Partitioner.token(partition), partition));
+
+        partition_order
+            .then_with(|| {
+                entry
+                    .clustering
+                    .cmp_ordered(clustering, &self.clustering_order)
+            })
+            .is_ge()
+    }
+}
+
+impl<E: cql::Engine> ChunkedReader<E> for ScanNode {
+    fn next_chunk(&mut self, engine: &E, max_rows: usize) -> Result<Vec<Row>, Error> {
+        let chunk_size = max_rows.min(self.result_page_size);
+        if chunk_size == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut scan = self.rows(engine)?;
+        let mut rows = vec![];
+
         let last_row_entry = loop {
             let Some(next_entry) = scan.next() else {
                 break None;
             };
-            if rows.len() >= self.result_page_size {
+            if self.is_past_end(&next_entry) {
+                break None;
+            }
+            if rows.len() >= chunk_size {
                 break Some(next_entry);
             };
-            if first_partition.is_none() {
-                first_partition = Some(next_entry.partition.clone());
-            }
-
-            if Some(&next_entry.partition) == first_partition.as_ref()
-                && !self.clustering_key_start.contains(&next_entry.clustering)
-            {
-                continue;
-            }
 
             rows.push(Row {
                 columns: selector::filter(next_entry.row, &self.selector),
@@ -70,25 +137,56 @@ impl<E: cql::Engine> Executor<E> for ScanNode {
 
         drop(scan);
 
-        let metadata = if let Some(last_row_entry) = last_row_entry {
-            let state = PagingState::new(
-                Some(encode_partition_key(&last_row_entry.partition)),
-                Some(encode_row_marker(&last_row_entry.clustering)),
-                self.limit - rows.len(),
-                1,
-            );
-
-            ResultMetadata {
-                paging_state: Some(state),
-                ..self.metadata
+        self.limit -= rows.len();
+        self.result_page_size -= rows.len();
+        match last_row_entry {
+            None => self.result_page_size = 0,
+            Some(last_row_entry) if self.result_page_size == 0 => {
+                self.metadata.paging_state = Some(PagingState::new(
+                    Some(encode_partition_key(&last_row_entry.partition)),
+                    Some(encode_row_marker(&last_row_entry.clustering)),
+                    self.limit,
+                    1,
+                ));
             }
-        } else {
-            self.metadata
-        };
+            Some(last_row_entry) => {
+                self.partition_range = self
+                    .partition_range
+                    .clone()
+                    .from_key(last_row_entry.partition);
+                self.clustering_key_start = Some(last_row_entry.clustering);
+            }
+        }
+
+        Ok(rows)
+    }
+
+    fn end_page(&mut self, engine: &E) -> Result<(), Error> {
+        if self.result_page_size == 0 {
+            return Ok(());
+        }
+        let end = self
+            .rows(engine)?
+            .nth(self.result_page_size)
+            .map(|it| (it.partition, it.clustering));
+
+        if let Some((partition, clustering)) = &end {
+            self.metadata.paging_state = Some(PagingState::new(
+                Some(encode_partition_key(partition)),
+                Some(encode_row_marker(clustering)),
+                self.limit - self.result_page_size,
+                1,
+            ));
+        }
+        // the page is bounded by its end from now on, without an end it holds every remaining row
+        self.page_end = end;
+        self.result_page_size = usize::MAX;
 
-        let rows = Rows { metadata, rows };
+        Ok(())
+    }
 
-        Ok(QueryResult::Rows(rows))
+    fn metadata(&self) -> ResultMetadata {
+        self.metadata.clone()
     }
 }
 
@@ -4,8 +4,7 @@ use crate::{
     cql,
     cql::{
         execution::Executor,
-        literal::Literal,
-        schema::{keyspace::Strategy, TableSchema},
+        schema::{keyspace::Strategy, ColumnType, TableOptions, TableSchema},
     },
     frame::response::{
         error::Error,
@@ -20,13 +19,39 @@ pub enum AlterSchema {
         name: String,
         ignore_existence: bool,
         replication: Strategy,
+        durable_writes: bool,
+    },
+    AlterKeyspace {
+        name: String,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
     },
     Table {
         keyspace: String,
         name: String,
         ignore_existence: bool,
         schema: TableSchema,
-        options: Vec<(String, Literal)>,
+        options: Box<TableOptions>,
+    },
+    Type {
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    },
+    DropKeyspace {
+        name: String,
+        ignore_existence: bool,
+    },
+    DropTable {
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+    },
+    DropType {
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
     },
 }
 
@@ -37,8 +62,14 @@ impl<E: cql::Engine> Executor<E> for AlterSchema {
                 name,
                 replication,
                 ignore_existence,
+                durable_writes,
             } => {
-                let _ = engine.create_keyspace(name.clone(), ignore_existence, replication)?;
+                let _ = engine.create_keyspace(
+                    name.clone(),
+                    ignore_existence,
+                    replication,
+                    durable_writes,
+                )?;
 
                 SchemaChange {
                     event: SchemaChangeEvent::KeyspaceChange {
@@ -47,6 +78,20 @@ impl<E: cql::Engine> Executor<E> for AlterSchema {
                     },
                 }
             }
+            Self::AlterKeyspace {
+                name,
+                replication,
+                durable_writes,
+            } => {
+                let _ = engine.alter_keyspace(&name, replication, durable_writes)?;
+
+                SchemaChange {
+                    event: SchemaChangeEvent::KeyspaceChange {
+                        change_type: SchemaChangeType::Updated,
+                        keyspace_name: name,
+                    },
+                }
+            }
             AlterSchema::Table {
                 keyspace,
                 name,
@@ -59,7 +104,7 @@ impl<E: cql::Engine> Executor<E> for AlterSchema {
                     name.clone(),
                     ignore_existence,
                     schema,
-                    options,
+                    *options,
                 )?;
 
                 SchemaChange {
@@ -70,6 +115,83 @@ impl<E: cql::Engine> Executor<E> for AlterSchema {
                     },
                 }
             }
+            AlterSchema::Type {
+                keyspace,
+                name,
+                ignore_existence,
+                field_types,
+            } => {
+                let _ = engine.create_type(
+                    keyspace.clone(),
+                    name.clone(),
+                    ignore_existence,
+                    field_types,
+                )?;
+
+                SchemaChange {
+                    event: SchemaChangeEvent::TypeChange {
+                        change_type: SchemaChangeType::Created,
+                        keyspace_name: keyspace,
+                        type_name: name,
+                    },
+                }
+            }
+            // Nothing is dropped when `IF EXISTS` doesn't find the object, so there is no change to report
+            AlterSchema::DropKeyspace {
+                name,
+                ignore_existence,
+            } => {
+                if engine.drop_keyspace(&name, ignore_existence)?.is_none() {
+                    return Ok(QueryResult::Void);
+                }
+
+                SchemaChange {
+                    event: SchemaChangeEvent::KeyspaceChange {
+                        change_type: SchemaChangeType::Dropped,
+                        keyspace_name: name,
+                    },
+                }
+            }
+            AlterSchema::DropTable {
+                keyspace,
+                name,
+                ignore_existence,
+            } => {
+                if engine
+                    .drop_table(&keyspace, &name, ignore_existence)?
+                    .is_none()
+                {
+                    return Ok(QueryResult::Void);
+                }
+
+                SchemaChange {
+                    event: SchemaChangeEvent::TableChange {
+                        change_type: SchemaChangeType::Dropped,
+                        keyspace_name: keyspace,
+                        object_name: name,
+                    },
+                }
+            }
+            AlterSchema::DropType {
+                keyspace,
+                name,
+                ignore_existence,
+            } => {
+                if engine
+                    .drop_type(&keyspace, &name, ignore_existence)?
+                    .is_none()
+                {
+                    return Ok(QueryResult::Void);
+                }
+
+                SchemaChange {
+                    event: SchemaChangeEvent::TypeChange {
+                        change_type: SchemaChangeType::Dropped,
+                        keyspace_name: keyspace,
+                        type_name: name,
+                    },
+                }
+            }
         };
 
         Ok(QueryResult::SchemaChange(change))
@@ -5,7 +5,9 @@ use tracing::{instrument, Level};
 use crate::{
     cql::{
         self,
-        execution::{selector, ColumnsSelector, Executor},
+        engine::RowEntry,
+        execution::{selector, ChunkedReader, ColumnsSelector, Executor, Reader},
+        schema::ClusteringOrder,
         value::{ClusteringKeyValue, ClusteringKeyValueRange, PartitionKeyValue},
     },
     frame::{
@@ -15,6 +17,7 @@ use crate::{
         },
         value::PagingState,
     },
+    storage::Predicate,
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -23,31 +26,87 @@ pub struct SelectNode {
     pub table: String,
     pub partition_key: PartitionKeyValue,
     pub clustering_range: ClusteringKeyValueRange,
+    /// Restrictions of columns outside of the primary key, applied by `ALLOW FILTERING`
+    pub predicate: Predicate,
+    pub clustering_start: Option<ClusteringKeyValue>,
+    pub clustering_order: Vec<ClusteringOrder>,
     pub selector: ColumnsSelector,
     pub metadata: ResultMetadata,
     pub limit: usize,
     pub result_page_size: usize,
+    /// Clustering key of the first row after the page, once it is found by [`ChunkedReader::end_page`]
+    #[serde(skip)]
+    pub page_end: Option<ClusteringKeyValue>,
 }
 
 impl<E: cql::Engine> Executor<E> for SelectNode {
-    #[instrument(level = Level::TRACE, skip(engine), err)]
     fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
-        let mut scan = engine
+        <Self as Reader<E>>::read(self, engine)
+    }
+}
+
+impl<E: cql::Engine> Reader<E> for SelectNode {
+    #[instrument(level = Level::TRACE, skip(engine), err)]
+    fn read(mut self: Box<Self>, engine: &E) -> Result<QueryResult, Error> {
+        let rows = self.next_chunk(engine, self.result_page_size)?;
+
+        Ok(QueryResult::Rows(Rows {
+            metadata: self.metadata,
+            rows,
+            encoded: None,
+        }))
+    }
+}
+
+impl SelectNode {
+    /// Rows the page is read from, starting where the previous chunk ended
+    fn rows<'a, E: cql::Engine>(
+        &'a self,
+        engine: &'a E,
+    ) -> Result<impl Iterator<Item = RowEntry> + 'a, Error> {
+        Ok(engine
             .read(
                 &self.keyspace,
                 &self.table,
                 &self.partition_key,
-                self.clustering_range,
+                self.clustering_range.clone(),
+                self.predicate.clone(),
             )?
-            .take(self.limit);
+            .filter(|row| match &self.clustering_start {
+                Some(start) => row
+                    .clustering
+                    .cmp_ordered(start, &self.clustering_order)
+                    .is_ge(),
+                None => true,
+            })
+            .take(self.limit))
+    }
+}
+
+impl<E: cql::Engine> ChunkedReader<E> for SelectNode {
+    fn next_chunk(&mut self, engine: &E, max_rows: usize) -> Result<Vec<Row>, Error> {
+        let chunk_size = max_rows.min(self.result_page_size);
+        if chunk_size == 0 {
+            return Ok(vec![]);
+        }
 
+        let mut scan = self.rows(engine)?;
         let mut rows = vec![];
 
         let last_row = loop {
             let Some(next_entry) = scan.next() else {
                 break None;
             };
-            if rows.len() >= self.result_page_size {
+            let past_end = self.page_end.as_ref().is_some_and(|end| {
+                next_entry
+                    .clustering
+                    .cmp_ordered(end, &self.clustering_order)
+                    .is_ge()
+            });
+            if past_end {
+                break None;
+            }
+            if rows.len() >= chunk_size {
                 break Some(next_entry);
             };
             rows.push(Row {
@@ -57,25 +116,59 @@ impl<E: cql::Engine> Executor<E> for SelectNode {
 
         drop(scan);
 
-        let metadata = if let Some(row) = last_row {
-            let state = PagingState::new(
+        self.limit -= rows.len();
+        self.result_page_size -= rows.len();
+        match last_row {
+            None => self.result_page_size = 0,
+            Some(row) if self.result_page_size == 0 => {
+                self.metadata.paging_state = Some(PagingState::new(
+                    None,
+                    Some(encode_row_marker(&row.clustering)),
+                    self.limit,
+                    1,
+                ));
+            }
+            // storage returns rows in ascending order, the range can only be narrowed for those
+            Some(row)
+                if self
+                    .clustering_order
+                    .iter()
+                    .all(|it| *it == ClusteringOrder::Asc) =>
+            {
+                self.clustering_range = self.clustering_range.clone().from(row.clustering);
+            }
+            Some(row) => self.clustering_start = Some(row.clustering),
+        }
+
+        Ok(rows)
+    }
+
+    fn end_page(&mut self, engine: &E) -> Result<(), Error> {
+        if self.result_page_size == 0 {
+            return Ok(());
+        }
+        let end = self
+            .rows(engine)?
+            .nth(self.result_page_size)
+            .map(|it| it.clustering);
+
+        if let Some(end) = &end {
+            self.metadata.paging_state = Some(PagingState::new(
                 None,
-                Some(encode_row_marker(&row.clustering)),
-                self.limit - rows.len(),
+                Some(encode_row_marker(end)),
+                self.limit - self.result_page_size,
                 1,
-            );
-
-            ResultMetadata {
-                paging_state: Some(state),
-                ..self.metadata
-            }
-        } else {
-            self.metadata
-        };
+            ));
+        }
+        // the page is bounded by its end from now on, without an end it holds every remaining row
+        self.page_end = end;
+        self.result_page_size = usize::MAX;
 
-        let rows = Rows { metadata, rows };
+        Ok(())
+    }
 
-        Ok(QueryResult::Rows(rows))
+    fn metadata(&self) -> ResultMetadata {
+        self.metadata.clone()
     }
 }
 
@@ -2,13 +2,13 @@ use std::collections::BTreeMap;
 
 use serde::Serialize;
 
-use crate::{cql::value::CqlValue, snapshot::ValueSnapshot};
+use crate::cql::{json, value::CqlValue};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 #[serde(transparent)]
 pub struct ColumnsSelector(pub Vec<ColumnSelector>);
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub struct ColumnSelector {
     pub name: String,
     pub transform: Transform,
@@ -21,14 +21,20 @@ pub fn filter(
     selector
         .0
         .iter()
-        .map(|it| {
-            let column = row.remove(&it.name)?;
+        .enumerate()
+        .map(|(index, it)| {
+            // a column can be selected several times under different aliases
+            let selected_again = selector.0[index + 1..].iter().any(|s| s.name == it.name);
+            let column = match selected_again {
+                true => row.get(&it.name).cloned()?,
+                false => row.remove(&it.name)?,
+            };
             it.transform.transform(column)
         })
         .collect()
 }
 
-#[derive(Debug, Copy, Clone, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
 pub enum Transform {
     Identity,
     ToJson,
@@ -38,11 +44,7 @@ impl Transform {
     fn transform(&self, input: CqlValue) -> Option<CqlValue> {
         match self {
             Transform::Identity => Some(input),
-            Transform::ToJson => {
-                let t = ValueSnapshot::from(input);
-                let json = serde_json::to_string(&t).expect("to be serializable");
-                Some(CqlValue::Text(json))
-            }
+            Transform::ToJson => Some(CqlValue::Text(json::to_json(&input).into())),
         }
     }
 }
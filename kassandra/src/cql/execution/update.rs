@@ -0,0 +1,38 @@
+use serde::Serialize;
+
+use crate::{
+    cql::{
+        self,
+        execution::Executor,
+        value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+    },
+    frame::response::{error::Error, result::QueryResult},
+};
+
+/// Writes the assigned columns of a single row, the row is created when it does not exist yet
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateNode {
+    pub keyspace: String,
+    pub table: String,
+    pub partition_key: PartitionKeyValue,
+    pub clustering_key: ClusteringKeyValue,
+    /// Cells to write, nulls delete the cell
+    pub values: Vec<(String, Option<CqlValue>)>,
+    /// Write timestamp in microseconds
+    pub timestamp: i64,
+}
+
+impl<E: cql::Engine> Executor<E> for UpdateNode {
+    fn execute(self: Box<Self>, engine: &mut E) -> Result<QueryResult, Error> {
+        engine.insert(
+            &self.keyspace,
+            &self.table,
+            self.partition_key,
+            self.clustering_key,
+            self.values,
+            self.timestamp,
+        )?;
+
+        Ok(QueryResult::Void)
+    }
+}
@@ -2,15 +2,18 @@ pub mod engine;
 pub mod execution;
 pub mod functions;
 pub mod parser;
+pub mod partitioner;
 pub mod plan;
 pub mod query;
 pub mod query_cache;
+pub mod row_cache;
 pub mod schema;
 pub mod types;
+pub mod visit;
 
 pub use self::{
     engine::Engine,
     query_cache::QueryCache,
     schema::{column, Catalog},
-    types::{literal, value},
+    types::{json, literal, value},
 };
@@ -0,0 +1,147 @@
+//! Positions and expected tokens of statements the parser rejects.
+//!
+//! Parsers of this module fail with [`Failure`], which keeps the furthest position any alternative
+//! got to and the tokens expected there, [`Diagnostic`] points at it in the statement.
+
+use std::fmt;
+
+use nom::{
+    error::{ErrorKind, FromExternalError, ParseError},
+    InputLength,
+};
+
+pub(super) type IResult<I, O> = nom::IResult<I, O, Failure<I>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure<I> {
+    /// Input left at the position of the failure
+    pub(super) input: I,
+    pub(super) expected: Vec<&'static str>,
+}
+
+impl<I> Failure<I> {
+    pub(super) fn expected(input: I, token: &'static str) -> Self {
+        Self {
+            input,
+            expected: vec![token],
+        }
+    }
+}
+
+impl<I: InputLength> ParseError<I> for Failure<I> {
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        let expected = match kind {
+            ErrorKind::Alpha | ErrorKind::AlphaNumeric => vec!["<identifier>"],
+            ErrorKind::Digit => vec!["<number>"],
+            ErrorKind::MultiSpace => vec!["<whitespace>"],
+            _ => vec![],
+        };
+        Self { input, expected }
+    }
+
+    fn append(_: I, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    /// Failures of alternatives are merged when they happen at the same position
+    fn or(mut self, mut other: Self) -> Self {
+        match self.input.input_len().cmp(&other.input.input_len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                for token in other.expected.drain(..) {
+                    if !self.expected.contains(&token) {
+                        self.expected.push(token);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<I, E> FromExternalError<I, E> for Failure<I> {
+    fn from_external_error(input: I, _: ErrorKind, _: E) -> Self {
+        Self {
+            input,
+            expected: vec![],
+        }
+    }
+}
+
+/// Same as [`nom::bytes::complete::tag`], which reports the token when it doesn't match
+pub(super) fn tag<'a>(token: &'static str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    move |input| {
+        nom::bytes::complete::tag::<_, _, ()>(token)(input)
+            .map_err(|_| nom::Err::Error(Failure::expected(input, token)))
+    }
+}
+
+/// Same as [`nom::bytes::complete::tag_no_case`], which reports the token when it doesn't match
+pub(super) fn tag_no_case<'a>(
+    token: &'static str,
+) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    move |input| {
+        nom::bytes::complete::tag_no_case::<_, _, ()>(token)(input)
+            .map_err(|_| nom::Err::Error(Failure::expected(input, token)))
+    }
+}
+
+/// Where a statement could not be parsed, lines and columns start at 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    /// Line of the statement with the failure
+    pub snippet: String,
+    /// Tokens which would be accepted at the position, keywords are uppercase
+    pub expected: Vec<String>,
+}
+
+impl Diagnostic {
+    pub(super) fn new(statement: &str, failure: &Failure<&str>) -> Self {
+        let offset = statement.len().saturating_sub(failure.input.len());
+        let before = &statement[..offset];
+        let line_start = before.rfind('\n').map_or(0, |it| it + 1);
+        let line_end = statement[offset..]
+            .find('\n')
+            .map_or(statement.len(), |it| offset + it);
+
+        // keywords are matched in either case, so the same keyword can be expected twice
+        let mut expected = Vec::<String>::new();
+        for token in &failure.expected {
+            let token = match token.chars().all(|c| c.is_ascii_alphabetic() || c == ' ') {
+                true => token.to_uppercase(),
+                false => token.to_string(),
+            };
+            if !expected.contains(&token) {
+                expected.push(token);
+            }
+        }
+
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            snippet: statement[line_start..line_end].to_owned(),
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)?;
+        match self.expected.as_slice() {
+            [] => {}
+            [token] => write!(f, ", expected {token}")?,
+            tokens => write!(f, ", expected one of {}", tokens.join(", "))?,
+        }
+        write!(
+            f,
+            "\n{}\n{:>width$}",
+            self.snippet,
+            "^",
+            width = self.column
+        )
+    }
+}
@@ -3,17 +3,21 @@ use std::{borrow::Cow, str::FromStr};
 use eyre::Result;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
     character::complete::{alpha1, alphanumeric1, multispace0},
-    combinator::{map, opt, recognize},
+    combinator::{map, recognize},
     error::ParseError,
-    multi::{many0_count, separated_list1},
+    multi::many0_count,
     sequence::{delimited, pair},
-    IResult, Slice,
+    Slice,
 };
 
+use self::diagnostic::{tag, IResult};
 use crate::{cql::query::QueryString, error::DbError, frame::response::error::Error};
 
+mod diagnostic;
+
+pub use diagnostic::Diagnostic;
+
 pub fn query(query: &str) -> Result<QueryString, Error> {
     let query = if query.contains("/*") {
         Cow::Owned(filter_comments(query)?)
@@ -27,13 +31,79 @@ pub fn query(query: &str) -> Result<QueryString, Error> {
         queries::insert_query,
         queries::update_query,
         queries::delete_query,
+        queries::batch_query,
         queries::create_keyspace_query,
+        queries::alter_keyspace_query,
         queries::create_table_query,
         queries::create_udt_query,
+        queries::drop_query,
+        queries::describe_query,
+        queries::explain_query,
+        queries::create_role_query,
+        queries::permission_query,
     ))(query.as_ref())
-    .map(|(_, it)| it)?;
+    .map(|(_, it)| it);
+
+    match result {
+        Ok(result) => Ok(result),
+        Err(error) => match unsupported_statement(&query) {
+            Some(statement) => Err(Error::new(
+                DbError::Unimplemented,
+                format!("{statement} statements are not supported"),
+            )),
+            None => {
+                let diagnostic = match error {
+                    nom::Err::Error(failure) | nom::Err::Failure(failure) => {
+                        Diagnostic::new(&query, &failure)
+                    }
+                    nom::Err::Incomplete(_) => Diagnostic::new(
+                        &query,
+                        &diagnostic::Failure::expected(&query[query.len()..], "<end of statement>"),
+                    ),
+                };
+                Err(Error::syntax(diagnostic))
+            }
+        },
+    }
+}
 
-    Ok(result)
+/// Statements which are valid cql, but can't be parsed by kassandra yet.
+const UNSUPPORTED_STATEMENTS: &[&str] = &[
+    "CREATE INDEX",
+    "CREATE CUSTOM INDEX",
+    "CREATE MATERIALIZED VIEW",
+    "CREATE FUNCTION",
+    "CREATE OR REPLACE FUNCTION",
+    "CREATE AGGREGATE",
+    "CREATE OR REPLACE AGGREGATE",
+    "CREATE TRIGGER",
+    "CREATE USER",
+    "ALTER TABLE",
+    "ALTER TYPE",
+    "ALTER MATERIALIZED VIEW",
+    "ALTER ROLE",
+    "ALTER USER",
+    "DROP INDEX",
+    "DROP MATERIALIZED VIEW",
+    "DROP FUNCTION",
+    "DROP AGGREGATE",
+    "DROP TRIGGER",
+    "DROP USER",
+    "TRUNCATE",
+    "LIST",
+];
+
+fn unsupported_statement(query: &str) -> Option<&'static str> {
+    let words = query.split_whitespace().collect::<Vec<_>>();
+
+    UNSUPPORTED_STATEMENTS.iter().copied().find(|statement| {
+        let keywords = statement.split(' ').collect::<Vec<_>>();
+        keywords.len() <= words.len()
+            && keywords
+                .iter()
+                .zip(&words)
+                .all(|(keyword, word)| keyword.eq_ignore_ascii_case(word))
+    })
 }
 
 fn filter_comments(mut query: &str) -> Result<String, Error> {
@@ -76,26 +146,14 @@ pub fn identifier(input: &str) -> IResult<&str, String> {
     map(ident, |it: &str| it.to_lowercase())(input)
 }
 
+/// Type as it is written in the statement, with any nesting of generics
 pub fn cassandra_type(input: &str) -> IResult<&str, String> {
-    let ident = pair(
-        alt((alpha1, tag("_"))),
-        many0_count(alt((alphanumeric1, tag("_")))),
-    );
-    let generics = opt(delimited(
-        tag("<"),
-        separated_list1(ws(tag(",")), ident),
-        tag(">"),
-    ));
-    let ident = pair(
-        alt((alpha1, tag("_"))),
-        many0_count(alt((alphanumeric1, tag("_")))),
-    );
-    map(recognize(pair(ident, generics)), |it: &str| it.to_owned())(input)
+    map(recognize(types::parse), |it: &str| it.to_owned())(input)
 }
 
-pub fn ws<'a, F, O, E>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+pub fn ws<'a, F, O, E>(inner: F) -> impl FnMut(&'a str) -> nom::IResult<&'a str, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E> + 'a,
+    F: FnMut(&'a str) -> nom::IResult<&'a str, O, E> + 'a,
     E: ParseError<&'a str>,
 {
     delimited(multispace0, inner, multispace0)
@@ -104,23 +162,33 @@ where
 mod queries {
     use nom::{
         branch::alt,
-        bytes::complete::{tag, tag_no_case},
+        bytes::complete::take_until,
         character::complete::{multispace0, multispace1, u32},
         combinator::{map, opt, value},
         multi::{many_till, separated_list0, separated_list1},
         sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
-        IResult,
     };
 
-    use super::{cassandra_type, identifier, ws};
-    use crate::cql::{
-        functions::CqlFunction,
-        literal::Literal,
-        query::{
-            ColumnSelector, CreateKeyspaceQuery, CreateTableQuery, CreateTypeQuery, DeleteQuery,
-            InsertQuery, QueryString, QueryValue, SelectExpression, SelectQuery, WhereClosure,
+    use super::{
+        cassandra_type,
+        diagnostic::{tag, tag_no_case, Failure, IResult},
+        identifier, ws,
+    };
+    use crate::{
+        cql::{
+            functions::CqlFunction,
+            literal::Literal,
+            query::{
+                AlterKeyspaceQuery, BatchQuery, ClusteringRelation, ColumnSelector,
+                CreateKeyspaceQuery, CreateRoleQuery, CreateTableQuery, CreateTypeQuery,
+                DeleteQuery, DescribeQuery, DropKeyspaceQuery, DropRoleQuery, DropTableQuery,
+                DropTypeQuery, InsertQuery, JsonDefault, Operator, Permission, PermissionQuery,
+                QueryString, QueryValue, Resource, SelectExpression, SelectQuery, TokenRelation,
+                UpdateQuery, UsingTimestamp, WhereClosure,
+            },
+            types::PreCqlType,
         },
-        types::PreCqlType,
+        frame::request::batch::BatchType,
     };
 
     fn query_value(input: &str) -> IResult<&str, QueryValue> {
@@ -130,6 +198,37 @@ mod queries {
         alt((blank, literal, named_bind))(input)
     }
 
+    /// Value assigned to a column, which can also be decoded from json: `fromJson('[1, 2]')`
+    fn assigned_value(input: &str) -> IResult<&str, QueryValue> {
+        let from_json = preceded(
+            tag_no_case("fromJson"),
+            delimited(ws(tag("(")), query_value, preceded(multispace0, tag(")"))),
+        );
+        alt((
+            map(from_json, |value| QueryValue::FromJson(Box::new(value))),
+            query_value,
+        ))(input)
+    }
+
+    /// Quoted identifiers keep their case, quotes inside of them are doubled: `"Say ""hi"""`
+    fn quoted_identifier(input: &str) -> IResult<&str, String> {
+        let (mut rest, _) = tag("\"")(input)?;
+        let mut value = String::new();
+
+        loop {
+            let (r, chunk) = terminated(take_until("\""), tag("\""))(rest)?;
+            value.push_str(chunk);
+
+            match r.strip_prefix('"') {
+                Some(r) => {
+                    value.push('"');
+                    rest = r;
+                }
+                None => return Ok((r, value)),
+            }
+        }
+    }
+
     fn select_expression(input: &str) -> IResult<&str, SelectExpression> {
         let all = map(tag("*"), |_| SelectExpression::All);
 
@@ -153,10 +252,11 @@ mod queries {
             ..Default::default()
         });
 
-        let column = pair(
-            alt((column3, column1)),
-            opt(preceded(ws(tag_no_case("as")), identifier)),
+        let alias = preceded(
+            delimited(multispace1, tag_no_case("as"), multispace1),
+            alt((quoted_identifier, identifier)),
         );
+        let column = pair(alt((column3, column1)), opt(alias));
         let column = map(column, |(column, alias)| ColumnSelector { alias, ..column });
 
         let columns = map(
@@ -164,17 +264,159 @@ mod queries {
             SelectExpression::Columns,
         );
 
-        alt((all, columns))(input)
+        // `COUNT(1)` is the same as `COUNT(*)`
+        let count = preceded(
+            tag_no_case("count"),
+            delimited(
+                ws(tag("(")),
+                alt((tag("*"), tag("1"))),
+                preceded(multispace0, tag(")")),
+            ),
+        );
+        let alias = preceded(
+            delimited(multispace1, tag_no_case("as"), multispace1),
+            alt((quoted_identifier, identifier)),
+        );
+        let count = map(pair(count, opt(alias)), |(_, alias)| {
+            SelectExpression::Count { alias }
+        });
+
+        alt((all, count, columns))(input)
+    }
+
+    fn using_timestamp(leading: bool) -> impl FnMut(&str) -> IResult<&str, UsingTimestamp> {
+        move |input| {
+            let (rest, _) = tuple((
+                terminated(tag_no_case("using"), multispace1),
+                terminated(tag_no_case("timestamp"), multispace1),
+            ))(input)?;
+            let (rest, value) = terminated(query_value, multispace0)(rest)?;
+
+            Ok((rest, UsingTimestamp { value, leading }))
+        }
+    }
+
+    fn operator(input: &str) -> IResult<&str, Operator> {
+        alt((
+            value(Operator::Ge, tag(">=")),
+            value(Operator::Le, tag("<=")),
+            value(Operator::Gt, tag(">")),
+            value(Operator::Lt, tag("<")),
+            value(Operator::Eq, tag("=")),
+        ))(input)
+    }
+
+    fn token_relation(input: &str) -> IResult<&str, TokenRelation> {
+        let columns = preceded(
+            tag_no_case("token"),
+            delimited(
+                ws(tag("(")),
+                separated_list1(ws(tag(",")), identifier),
+                ws(tag(")")),
+            ),
+        );
+
+        map(
+            tuple((columns, ws(operator), query_value)),
+            |(columns, operator, value)| TokenRelation {
+                columns,
+                operator,
+                value,
+            },
+        )(input)
+    }
+
+    fn clustering_relation(input: &str) -> IResult<&str, ClusteringRelation> {
+        let columns = delimited(
+            ws(tag("(")),
+            separated_list1(ws(tag(",")), identifier),
+            ws(tag(")")),
+        );
+        let values = delimited(
+            ws(tag("(")),
+            separated_list1(ws(tag(",")), query_value),
+            ws(tag(")")),
+        );
+
+        map(
+            tuple((columns, ws(operator), values)),
+            |(columns, operator, values)| ClusteringRelation {
+                columns,
+                operator,
+                values,
+            },
+        )(input)
     }
 
     fn where_closure(input: &str) -> IResult<&str, WhereClosure> {
-        let (rest, _) = terminated(tag_no_case("where"), multispace1)(input)?;
+        enum Relation {
+            Column(String, QueryValue),
+            Token(TokenRelation),
+            Clustering(ClusteringRelation),
+            Like(String, QueryValue),
+        }
 
-        let statement = separated_pair(identifier, ws(tag("=")), query_value);
+        let (mut rest, _) = terminated(tag_no_case("where"), multispace1)(input)?;
+
+        let column = map(
+            tuple((identifier, ws(operator), query_value)),
+            |(column, operator, value)| match operator {
+                Operator::Eq => Relation::Column(column, value),
+                operator => Relation::Clustering(ClusteringRelation {
+                    columns: vec![column],
+                    operator,
+                    values: vec![value],
+                }),
+            },
+        );
+        let like = map(
+            separated_pair(identifier, ws(tag_no_case("like")), query_value),
+            |(column, value)| Relation::Like(column, value),
+        );
+        let mut relation = alt((
+            map(token_relation, Relation::Token),
+            map(clustering_relation, Relation::Clustering),
+            like,
+            column,
+        ));
+        let mut and = ws(tag_no_case("and"));
+
+        let is_bind = |value: &QueryValue| matches!(value, QueryValue::Blankslate);
+        let mut bound_kind = 0;
+
+        let mut closure = WhereClosure::default();
+        loop {
+            let (after, relation) = relation(rest)?;
+            // values are bound to the kinds of relations in turn, not in the order of the statement
+            let (kind, binds) = match &relation {
+                Relation::Column(_, value) => (0, is_bind(value)),
+                Relation::Token(token) => (1, is_bind(&token.value)),
+                Relation::Clustering(clustering) => (2, clustering.values.iter().any(is_bind)),
+                Relation::Like(_, value) => (3, is_bind(value)),
+            };
+            if binds && kind < bound_kind {
+                return Err(nom::Err::Failure(Failure::expected(
+                    rest,
+                    "<restriction without bind markers>",
+                )));
+            }
+            if binds {
+                bound_kind = kind;
+            }
 
-        let (rest, statements) = separated_list1(ws(tag("AND")), statement)(rest)?;
+            match relation {
+                Relation::Column(column, value) => closure.statements.push((column, value)),
+                Relation::Token(token) => closure.token.push(token),
+                Relation::Clustering(clustering) => closure.clustering.push(clustering),
+                Relation::Like(column, value) => closure.like.push((column, value)),
+            }
 
-        Ok((rest, WhereClosure { statements }))
+            match and(after) {
+                Ok((next, _)) => rest = next,
+                Err(nom::Err::Error(_)) => return Ok((after, closure)),
+                Err(error) => return Err(error),
+            }
+        }
     }
 
     pub fn select_query(input: &str) -> IResult<&str, QueryString> {
@@ -195,6 +437,11 @@ mod queries {
             terminated(map(u32, |it| it as usize), multispace0),
         );
         let (rest, limit) = opt(limit)(rest)?;
+        let allow_filtering = tuple((
+            terminated(tag_no_case("allow"), multispace1),
+            terminated(tag_no_case("filtering"), multispace0),
+        ));
+        let (rest, allow_filtering) = map(opt(allow_filtering), |it| it.is_some())(rest)?;
 
         Ok((
             rest,
@@ -205,6 +452,7 @@ mod queries {
                 r#where: closure.unwrap_or_default(),
                 limit,
                 json,
+                allow_filtering,
             }),
         ))
     }
@@ -214,23 +462,48 @@ mod queries {
         let (rest, _) = terminated(tag_no_case("into"), multispace1)(rest)?;
         let (rest, keyspace) = opt(terminated(identifier, tag(".")))(rest)?;
         let (rest, table) = terminated(identifier, multispace0)(rest)?;
-        let (rest, columns) = terminated(
+
+        let json = tuple((
+            terminated(tag_no_case("json"), multispace1),
+            terminated(query_value, multispace0),
+            opt(preceded(
+                pair(tag_no_case("default"), multispace1),
+                terminated(
+                    alt((
+                        value(JsonDefault::Null, tag_no_case("null")),
+                        value(JsonDefault::Unset, tag_no_case("unset")),
+                    )),
+                    multispace0,
+                ),
+            )),
+        ));
+        let json = map(json, |(_, document, default)| {
+            (vec![], vec![document], Some(default.unwrap_or_default()))
+        });
+        let columns = terminated(
             delimited(
                 ws(tag("(")),
                 separated_list0(ws(tag(",")), identifier),
                 ws(tag(")")),
             ),
             multispace0,
-        )(rest)?;
-        let (rest, _) = terminated(tag_no_case("values"), multispace0)(rest)?;
-        let (rest, values) = terminated(
-            delimited(
-                ws(tag("(")),
-                separated_list0(ws(tag(",")), query_value),
-                ws(tag(")")),
+        );
+        let values = preceded(
+            terminated(tag_no_case("values"), multispace0),
+            terminated(
+                delimited(
+                    ws(tag("(")),
+                    separated_list0(ws(tag(",")), assigned_value),
+                    ws(tag(")")),
+                ),
+                multispace0,
             ),
-            multispace0,
-        )(rest)?;
+        );
+        let values = map(pair(columns, values), |(columns, values)| {
+            (columns, values, None)
+        });
+        let (rest, (columns, values, json)) = alt((json, values))(rest)?;
+        let (rest, using) = opt(using_timestamp(false))(rest)?;
 
         Ok((
             rest,
@@ -239,6 +512,8 @@ mod queries {
                 keyspace,
                 columns,
                 values,
+                using,
+                json,
             }),
         ))
     }
@@ -260,10 +535,13 @@ mod queries {
 
         let (rest, keyspace) = terminated(identifier, multispace1)(rest)?;
         let (rest, _) = terminated(alt((tag("with"), tag("WITH"))), multispace1)(rest)?;
-        let replication = alt((tag("replication"), tag("REPLICATION")));
-
-        let (rest, (_, replication)) =
-            separated_pair(replication, ws(tag("=")), super::literal::parse)(rest)?;
+        let (rest, (replication, durable_writes)) = keyspace_options(rest)?;
+        let Some(replication) = replication else {
+            return Err(nom::Err::Error(nom::error::make_error(
+                rest,
+                nom::error::ErrorKind::Verify,
+            )));
+        };
 
         Ok((
             rest,
@@ -271,10 +549,60 @@ mod queries {
                 keyspace,
                 ignore_existence: if_not_exists.is_some(),
                 replication,
+                durable_writes,
             }),
         ))
     }
 
+    pub fn alter_keyspace_query(input: &str) -> IResult<&str, QueryString> {
+        let (rest, _) = terminated(tag_no_case("ALTER KEYSPACE"), multispace1)(input)?;
+        let (rest, keyspace) = terminated(identifier, multispace1)(rest)?;
+        let (rest, _) = terminated(tag_no_case("WITH"), multispace1)(rest)?;
+        let (rest, (replication, durable_writes)) = keyspace_options(rest)?;
+
+        Ok((
+            rest,
+            QueryString::AlterKeyspace(AlterKeyspaceQuery {
+                keyspace,
+                replication,
+                durable_writes,
+            }),
+        ))
+    }
+
+    /// `replication = {...} AND durable_writes = false`, in any order
+    fn keyspace_options(input: &str) -> IResult<&str, (Option<Literal>, Option<bool>)> {
+        let boolean = alt((
+            value(true, alt((tag_no_case("true"), tag_no_case("'true'")))),
+            value(false, alt((tag_no_case("false"), tag_no_case("'false'")))),
+        ));
+        let replication = map(
+            preceded(
+                pair(tag_no_case("replication"), ws(tag("="))),
+                super::literal::parse,
+            ),
+            |it| (Some(it), None),
+        );
+        let durable_writes = map(
+            preceded(pair(tag_no_case("durable_writes"), ws(tag("="))), boolean),
+            |it| (None, Some(it)),
+        );
+
+        let (rest, options) =
+            separated_list1(ws(tag_no_case("AND")), alt((replication, durable_writes)))(input)?;
+        let options = options.into_iter().fold(
+            (None, None),
+            |(replication, durable_writes), (new_replication, new_durable_writes)| {
+                (
+                    new_replication.or(replication),
+                    new_durable_writes.or(durable_writes),
+                )
+            },
+        );
+
+        Ok((rest, options))
+    }
+
     pub fn create_table_query(rest: &str) -> IResult<&str, QueryString> {
         fn table_options(rest: &str) -> IResult<&str, Vec<(String, Literal)>> {
             let ordering = map(
@@ -419,18 +747,19 @@ mod queries {
         let (rest, _) = terminated(tag_no_case("update"), multispace1)(rest)?;
         let (rest, keyspace) = opt(terminated(identifier, tag(".")))(rest)?;
         let (rest, table) = terminated(identifier, multispace1)(rest)?;
+        let (rest, using) = opt(using_timestamp(true))(rest)?;
         let (rest, _) = terminated(tag_no_case("set"), multispace1)(rest)?;
 
-        let (rest, columns_specification) = terminated(
+        let (rest, assignments) = terminated(
             separated_list1(
                 ws(tag(",")),
-                separated_pair(identifier, ws(tag("=")), query_value),
+                separated_pair(identifier, ws(tag("=")), assigned_value),
             ),
             multispace1,
         )(rest)?;
         let (rest, _) = terminated(tag_no_case("where"), multispace1)(rest)?;
 
-        let (rest, row_specification) = terminated(
+        let (rest, statements) = terminated(
             separated_list1(
                 ws(tag("AND")),
                 separated_pair(identifier, ws(tag("=")), query_value),
@@ -438,18 +767,21 @@ mod queries {
             multispace0,
         )(rest)?;
 
-        let (columns, values) = columns_specification
-            .into_iter()
-            .chain(row_specification)
-            .unzip();
+        let r#where = WhereClosure {
+            statements,
+            token: vec![],
+            clustering: vec![],
+            like: vec![],
+        };
 
         Ok((
             rest,
-            QueryString::Insert(InsertQuery {
+            QueryString::Update(UpdateQuery {
                 table,
                 keyspace,
-                columns,
-                values,
+                assignments,
+                r#where,
+                using,
             }),
         ))
     }
@@ -465,6 +797,7 @@ mod queries {
 
         let (rest, keyspace) = opt(terminated(identifier, tag(".")))(rest)?;
         let (rest, table) = terminated(identifier, multispace1)(rest)?;
+        let (rest, using) = opt(using_timestamp(true))(rest)?;
         let (rest, _) = terminated(tag_no_case("where"), multispace1)(rest)?;
 
         let (rest, statements) = terminated(
@@ -475,7 +808,12 @@ mod queries {
             multispace0,
         )(rest)?;
 
-        let r#where = WhereClosure { statements };
+        let r#where = WhereClosure {
+            statements,
+            token: vec![],
+            clustering: vec![],
+            like: vec![],
+        };
 
         Ok((
             rest,
@@ -484,13 +822,44 @@ mod queries {
                 keyspace,
                 columns,
                 r#where,
+                using,
+            }),
+        ))
+    }
+
+    /// Statements of the batch are separated with optional `;`
+    pub fn batch_query(input: &str) -> IResult<&str, QueryString> {
+        let (rest, _) = terminated(tag_no_case("BEGIN"), multispace1)(input)?;
+        let (rest, batch_type) = terminated(
+            alt((
+                value(BatchType::Unlogged, tag_no_case("UNLOGGED BATCH")),
+                value(BatchType::Counter, tag_no_case("COUNTER BATCH")),
+                value(BatchType::Logged, tag_no_case("BATCH")),
+            )),
+            multispace1,
+        )(rest)?;
+        let (rest, using) = opt(using_timestamp(true))(rest)?;
+
+        let statement = terminated(
+            alt((insert_query, update_query, delete_query)),
+            ws(opt(tag(";"))),
+        );
+        let (rest, (statements, _)) = many_till(statement, tag_no_case("APPLY BATCH"))(rest)?;
+
+        Ok((
+            rest,
+            QueryString::Batch(BatchQuery {
+                batch_type,
+                using,
+                statements,
             }),
         ))
     }
 
     pub fn create_udt_query(rest: &str) -> IResult<&str, QueryString> {
         let (rest, _) = terminated(tag_no_case("create type"), multispace1)(rest)?;
-        let (rest, _) = opt(terminated(tag_no_case("if not exists"), multispace1))(rest)?;
+        let (rest, if_not_exists) =
+            opt(terminated(tag_no_case("if not exists"), multispace1))(rest)?;
 
         let (rest, keyspace) = opt(terminated(identifier, tag(".")))(rest)?;
         let (rest, table) = terminated(identifier, multispace0)(rest)?;
@@ -510,11 +879,238 @@ mod queries {
             QueryString::CreateType(CreateTypeQuery {
                 keyspace,
                 name: table,
+                ignore_existence: if_not_exists.is_some(),
                 columns,
             }),
         ))
     }
 
+    /// `DROP KEYSPACE`, `DROP TABLE`, `DROP TYPE` and `DROP ROLE`, all of them accept `IF EXISTS`
+    pub fn drop_query(input: &str) -> IResult<&str, QueryString> {
+        let (rest, _) = terminated(tag_no_case("DROP"), multispace1)(input)?;
+
+        let if_exists = || {
+            map(
+                opt(terminated(tag_no_case("IF EXISTS"), multispace1)),
+                |it| it.is_some(),
+            )
+        };
+        let name = || pair(opt(terminated(identifier, tag("."))), identifier);
+
+        let keyspace = map(
+            preceded(
+                terminated(tag_no_case("KEYSPACE"), multispace1),
+                pair(if_exists(), identifier),
+            ),
+            |(ignore_existence, keyspace)| {
+                QueryString::DropKeyspace(DropKeyspaceQuery {
+                    keyspace,
+                    ignore_existence,
+                })
+            },
+        );
+        let table = map(
+            preceded(
+                terminated(tag_no_case("TABLE"), multispace1),
+                pair(if_exists(), name()),
+            ),
+            |(ignore_existence, (keyspace, table))| {
+                QueryString::DropTable(DropTableQuery {
+                    keyspace,
+                    table,
+                    ignore_existence,
+                })
+            },
+        );
+        let udt = map(
+            preceded(
+                terminated(tag_no_case("TYPE"), multispace1),
+                pair(if_exists(), name()),
+            ),
+            |(ignore_existence, (keyspace, name))| {
+                QueryString::DropType(DropTypeQuery {
+                    keyspace,
+                    name,
+                    ignore_existence,
+                })
+            },
+        );
+
+        let role = map(
+            preceded(
+                terminated(tag_no_case("ROLE"), multispace1),
+                pair(if_exists(), role_name),
+            ),
+            |(ignore_existence, role)| {
+                QueryString::DropRole(DropRoleQuery {
+                    role,
+                    ignore_existence,
+                })
+            },
+        );
+
+        alt((keyspace, table, udt, role))(rest)
+    }
+
+    /// Role names keep their case when quoted, either as identifiers or as strings
+    fn role_name(input: &str) -> IResult<&str, String> {
+        alt((super::literal::quoted_string, quoted_identifier, identifier))(input)
+    }
+
+    /// `CREATE ROLE name WITH PASSWORD = '..' AND LOGIN = true AND SUPERUSER = false`, in any order
+    pub fn create_role_query(input: &str) -> IResult<&str, QueryString> {
+        #[derive(Clone)]
+        enum RoleOption {
+            Password(String),
+            Login(bool),
+            Superuser(bool),
+        }
+
+        let (rest, _) = terminated(tag_no_case("CREATE ROLE"), multispace1)(input)?;
+        let (rest, if_not_exists) =
+            opt(terminated(tag_no_case("IF NOT EXISTS"), multispace1))(rest)?;
+        let (rest, role) = role_name(rest)?;
+
+        let boolean = || {
+            alt((
+                value(true, tag_no_case("true")),
+                value(false, tag_no_case("false")),
+            ))
+        };
+        let option = |name| pair(tag_no_case(name), ws(tag("=")));
+        let password = map(
+            preceded(option("PASSWORD"), super::literal::quoted_string),
+            RoleOption::Password,
+        );
+        let login = map(preceded(option("LOGIN"), boolean()), RoleOption::Login);
+        let superuser = map(
+            preceded(option("SUPERUSER"), boolean()),
+            RoleOption::Superuser,
+        );
+        let (rest, options) = opt(preceded(
+            ws(tag_no_case("WITH")),
+            separated_list1(ws(tag_no_case("AND")), alt((password, login, superuser))),
+        ))(rest)?;
+
+        let mut create = CreateRoleQuery {
+            role,
+            ignore_existence: if_not_exists.is_some(),
+            password: None,
+            login: false,
+            superuser: false,
+        };
+        for option in options.into_iter().flatten() {
+            match option {
+                RoleOption::Password(password) => create.password = Some(password),
+                RoleOption::Login(login) => create.login = login,
+                RoleOption::Superuser(superuser) => create.superuser = superuser,
+            }
+        }
+
+        Ok((rest, QueryString::CreateRole(create)))
+    }
+
+    /// `GRANT SELECT ON KEYSPACE ks TO role` and `REVOKE ALL PERMISSIONS ON TABLE t FROM role`
+    pub fn permission_query(input: &str) -> IResult<&str, QueryString> {
+        let (rest, grant) = terminated(
+            alt((
+                value(true, tag_no_case("GRANT")),
+                value(false, tag_no_case("REVOKE")),
+            )),
+            multispace1,
+        )(input)?;
+
+        let all = value(
+            Permission::All,
+            pair(
+                tag_no_case("ALL"),
+                opt(preceded(multispace1, tag_no_case("PERMISSIONS"))),
+            ),
+        );
+        let single = terminated(
+            alt((
+                value(Permission::Create, tag_no_case("CREATE")),
+                value(Permission::Alter, tag_no_case("ALTER")),
+                value(Permission::Drop, tag_no_case("DROP")),
+                value(Permission::Select, tag_no_case("SELECT")),
+                value(Permission::Modify, tag_no_case("MODIFY")),
+                value(Permission::Authorize, tag_no_case("AUTHORIZE")),
+            )),
+            opt(preceded(multispace1, tag_no_case("PERMISSION"))),
+        );
+        let (rest, permission) = terminated(alt((all, single)), ws(tag_no_case("ON")))(rest)?;
+
+        let all_keyspaces = value(
+            Resource::AllKeyspaces,
+            tuple((tag_no_case("ALL"), multispace1, tag_no_case("KEYSPACES"))),
+        );
+        let keyspace = map(
+            preceded(terminated(tag_no_case("KEYSPACE"), multispace1), identifier),
+            Resource::Keyspace,
+        );
+        let table = map(
+            preceded(
+                opt(terminated(tag_no_case("TABLE"), multispace1)),
+                pair(opt(terminated(identifier, tag("."))), identifier),
+            ),
+            |(keyspace, table)| Resource::Table { keyspace, table },
+        );
+        let (rest, resource) = alt((all_keyspaces, keyspace, table))(rest)?;
+
+        let (rest, _) = ws(tag_no_case(if grant { "TO" } else { "FROM" }))(rest)?;
+        let (rest, role) = role_name(rest)?;
+
+        let query = PermissionQuery {
+            permission,
+            resource,
+            role,
+        };
+        match grant {
+            true => Ok((rest, QueryString::Grant(query))),
+            false => Ok((rest, QueryString::Revoke(query))),
+        }
+    }
+
+    pub fn describe_query(rest: &str) -> IResult<&str, QueryString> {
+        let (rest, _) = terminated(
+            alt((tag_no_case("describe"), tag_no_case("desc"))),
+            multispace1,
+        )(rest)?;
+
+        let keyspaces = value(DescribeQuery::Keyspaces, tag_no_case("keyspaces"));
+        let tables = value(DescribeQuery::Tables, tag_no_case("tables"));
+        let schema = value(DescribeQuery::Schema, tag_no_case("schema"));
+        let keyspace = map(
+            preceded(
+                tag_no_case("keyspace"),
+                opt(preceded(multispace1, identifier)),
+            ),
+            DescribeQuery::Keyspace,
+        );
+        let table = map(
+            preceded(
+                terminated(tag_no_case("table"), multispace1),
+                pair(opt(terminated(identifier, tag("."))), identifier),
+            ),
+            |(keyspace, table)| DescribeQuery::Table { keyspace, table },
+        );
+
+        let (rest, describe) = terminated(
+            alt((keyspaces, tables, schema, keyspace, table)),
+            multispace0,
+        )(rest)?;
+
+        Ok((rest, QueryString::Describe(describe)))
+    }
+
+    pub fn explain_query(rest: &str) -> IResult<&str, QueryString> {
+        let (rest, _) = terminated(tag_no_case("explain"), multispace1)(rest)?;
+        let (rest, statement) =
+            alt((select_query, insert_query, update_query, delete_query))(rest)?;
+
+        Ok((rest, QueryString::Explain(Box::new(statement))))
+    }
+
     #[test]
     fn test_select_expression() {
         let (r, p) = select_expression("a, toJson(x) as y, toJson(z), b").unwrap();
@@ -523,29 +1119,29 @@ mod queries {
     }
 }
 
-mod types {
+pub(crate) mod types {
     use std::str::FromStr;
 
     use nom::{
-        bytes::{complete::tag, streaming::take_while},
-        character::is_alphanumeric,
-        error::ErrorKind,
-        multi::separated_list1,
-        sequence::terminated,
-        IResult,
+        bytes::complete::take_while, character::is_alphanumeric, error::ErrorKind,
+        multi::separated_list1, sequence::terminated,
     };
 
-    use super::{identifier, ws};
+    use super::{
+        diagnostic::{tag, Failure, IResult},
+        identifier, ws,
+    };
     use crate::cql::types::{NativeType, PreCqlType};
 
-    type ParseResult<'a, T> = IResult<&'a str, T, nom::error::Error<&'a str>>;
+    type ParseResult<'a, T> = IResult<&'a str, T>;
 
     pub fn parse(p: &str) -> ParseResult<PreCqlType> {
-        if let Ok((_rest, _)) = tag::<_, _, nom::error::Error<_>>("frozen<")(p) {
+        if let Ok((p, _)) = tag("frozen<")(p) {
             let (p, inner_type) = parse(p)?;
+            let (p, _) = tag(">")(p)?;
             let frozen_type = inner_type.freeze();
             Ok((p, frozen_type))
-        } else if let Ok((p, _)) = tag::<_, _, nom::error::Error<_>>("map<")(p) {
+        } else if let Ok((p, _)) = tag("map<")(p) {
             let (p, key) = terminated(parse, ws(tag(",")))(p)?;
             let (p, value) = parse(p)?;
             let (p, _) = tag(">")(p)?;
@@ -557,7 +1153,7 @@ mod types {
             };
 
             Ok((p, typ))
-        } else if let Ok((p, _)) = tag::<_, _, nom::error::Error<_>>("list<")(p) {
+        } else if let Ok((p, _)) = tag("list<")(p) {
             let (p, inner_type) = parse(p)?;
             let (p, _) = tag(">")(p)?;
 
@@ -567,7 +1163,7 @@ mod types {
             };
 
             Ok((p, typ))
-        } else if let Ok((p, _)) = tag::<_, _, nom::error::Error<_>>("set<")(p) {
+        } else if let Ok((p, _)) = tag("set<")(p) {
             let (p, inner_type) = parse(p)?;
             let (p, _) = tag(">")(p)?;
 
@@ -577,21 +1173,20 @@ mod types {
             };
 
             Ok((p, typ))
-        } else if let Ok((p, _)) = tag::<_, _, nom::error::Error<_>>("tuple<")(p) {
+        } else if let Ok((p, _)) = tag("tuple<")(p) {
             let (p, types) = separated_list1(ws(tag(",")), parse)(p)?;
             let (p, _) = tag(">")(p)?;
             Ok((p, PreCqlType::Tuple(types)))
         } else if let Ok((p, typ)) = parse_native_type(p) {
             Ok((p, PreCqlType::Native(typ)))
-        } else if let Ok((name, p)) = parse_user_defined_type(p) {
+        } else if let Ok((p, name)) = parse_user_defined_type(p) {
             let typ = PreCqlType::UserDefinedType {
                 frozen: false,
                 name: name.to_string(),
             };
             Ok((p, typ))
         } else {
-            // Err(p.error(ParseErrorCause::Other("invalid cql type")))
-            panic!("invalid cql type")
+            Err(nom::Err::Error(Failure::expected(p, "<type>")))
         }
     }
 
@@ -609,7 +1204,7 @@ mod types {
         let (p, tok) =
             take_while(|c| is_alphanumeric(c as u8) || c == '.' || c == '_' || c == '$')(p)?;
 
-        if tok.is_empty() {
+        if tok.is_empty() || tok.starts_with(|c: char| c.is_ascii_digit()) {
             return Err(nom::Err::Error(nom::error::make_error(p, ErrorKind::Tag)));
         }
         Ok((p, tok))
@@ -621,22 +1216,25 @@ mod literal {
 
     use nom::{
         branch::alt,
-        bytes::complete::{tag, tag_no_case, take_until, take_while_m_n},
-        character::complete::multispace0,
-        combinator::{map, recognize},
+        bytes::complete::{take_until, take_while_m_n},
+        character::complete::{multispace0, one_of, satisfy},
+        combinator::{map, not, recognize, value},
         multi::separated_list0,
         sequence::{delimited, separated_pair, terminated, tuple},
-        IResult,
     };
     use uuid::Uuid;
 
-    use super::ws;
+    use super::{
+        diagnostic::{tag, tag_no_case, IResult},
+        ws,
+    };
     use crate::cql::literal::Literal;
 
     pub fn parse(input: &str) -> IResult<&str, Literal> {
         alt((
             uuid_literal,
             null_literal,
+            bool_literal,
             map_literal,
             string_literal,
             number_literal,
@@ -646,14 +1244,45 @@ mod literal {
     }
 
     fn string_literal(input: &str) -> IResult<&str, Literal> {
+        map(quoted_string, Literal::String)(input)
+    }
+
+    /// Quotes inside of the string are escaped by doubling them: `'it''s'`
+    pub fn quoted_string(input: &str) -> IResult<&str, String> {
+        let (mut rest, _) = tag("'")(input)?;
+        let mut value = String::new();
+
+        loop {
+            let (r, chunk) = terminated(take_until("'"), tag("'"))(rest)?;
+            value.push_str(chunk);
+
+            match r.strip_prefix('\'') {
+                Some(r) => {
+                    value.push('\'');
+                    rest = r;
+                }
+                None => return Ok((r, value)),
+            }
+        }
+    }
+
+    fn bool_literal(input: &str) -> IResult<&str, Literal> {
+        let boolean = alt((
+            value(true, tag_no_case("true")),
+            value(false, tag_no_case("false")),
+        ));
         map(
-            delimited(tag("'"), take_until("'"), tag("'")),
-            |it: &str| Literal::String(it.to_owned()),
+            terminated(boolean, not(satisfy(|c| c.is_alphanumeric() || c == '_'))),
+            Literal::Bool,
         )(input)
     }
 
+    /// Numbers with a fraction or an exponent are left for [`float_literal`]
     fn number_literal(input: &str) -> IResult<&str, Literal> {
-        map(nom::character::complete::i64, Literal::Number)(input)
+        map(
+            terminated(nom::character::complete::i64, not(one_of(".eE"))),
+            Literal::Number,
+        )(input)
     }
 
     fn null_literal(input: &str) -> IResult<&str, Literal> {
@@ -666,25 +1295,17 @@ mod literal {
 
     fn list_literal(input: &str) -> IResult<&str, Literal> {
         let values = separated_list0(ws(tag(",")), ws(parse));
-        map(delimited(ws(tag("[")), values, ws(tag("]"))), Literal::List)(input)
+        map(delimited(tag("["), values, tag("]")), Literal::List)(input)
     }
 
     fn map_literal(input: &str) -> IResult<&str, Literal> {
-        let quoted_string = delimited(tag("'"), take_until("'"), tag("'"));
         let value = separated_pair(ws(quoted_string), tag(":"), ws(parse));
 
         let values = separated_list0(terminated(tag(","), multispace0), value);
 
-        map(
-            delimited(tag("{"), values, tag("}")),
-            |it: Vec<(&str, Literal)>| {
-                Literal::Map(
-                    it.into_iter()
-                        .map(|(key, value)| (key.to_owned(), value))
-                        .collect(),
-                )
-            },
-        )(input)
+        map(delimited(tag("{"), values, tag("}")), |it| {
+            Literal::Map(it.into_iter().collect())
+        })(input)
     }
 
     fn uuid_literal(input: &str) -> IResult<&str, Literal> {
@@ -712,7 +1333,9 @@ mod literal {
         ));
         let parser = alt((lower_hex, upper_hex));
         let (rest, lit) = recognize(parser)(input)?;
-        let uuid = Uuid::from_str(lit).expect("to be valid uuid");
+        let uuid = Uuid::from_str(lit).map_err(|_| {
+            nom::Err::Error(nom::error::make_error(input, nom::error::ErrorKind::Verify))
+        })?;
         Ok((rest, Literal::Uuid(uuid)))
     }
 
@@ -729,6 +1352,7 @@ mod literal {
     #[cfg(test)]
     mod tests {
         use super::{map_literal, parse};
+        use crate::cql::literal::Literal;
 
         #[test]
         fn test_map() {
@@ -737,6 +1361,13 @@ mod literal {
             println!("{m:?}");
         }
 
+        #[test]
+        fn numbers() {
+            assert!(matches!(parse("42").unwrap().1, Literal::Number(42)));
+            assert!(matches!(parse("0.25").unwrap().1, Literal::Float(v) if v == 0.25));
+            assert!(matches!(parse("1e3").unwrap().1, Literal::Float(v) if v == 1000.0));
+        }
+
         #[test]
         fn test_uuid() {
             let v = "6ab09bec-e68e-48d9-a5f8-97e6fb4c9b47";
@@ -749,10 +1380,20 @@ mod literal {
 #[cfg(test)]
 mod tests {
     use super::query;
-    use crate::cql::{
-        functions::CqlFunction,
-        parser::filter_comments,
-        query::{ColumnSelector, QueryString, SelectExpression, SelectQuery},
+    use crate::{
+        cql::{
+            functions::CqlFunction,
+            literal::Literal,
+            parser::filter_comments,
+            query::{
+                ColumnSelector, CreateRoleQuery, CreateTypeQuery, DescribeQuery, DropKeyspaceQuery,
+                DropRoleQuery, DropTableQuery, DropTypeQuery, InsertQuery, Permission,
+                PermissionQuery, QueryString, QueryValue, Resource, SelectExpression, SelectQuery,
+                UpdateQuery,
+            },
+        },
+        error::DbError,
+        frame::request::batch::BatchType,
     };
 
     #[test]
@@ -796,8 +1437,11 @@ mod tests {
     #[test]
     fn test_update_query() {
         let q = "UPDATE table SET field1=?,field2=?,field3=? WHERE field0=?";
-        let k = query(q).unwrap();
-        println!("{k:#?}");
+        let QueryString::Update(u) = query(q).unwrap() else {
+            panic!("was supposed to be parsed as update query")
+        };
+        assert_eq!(u.assignments.len(), 3);
+        assert_eq!(u.r#where.statements.len(), 1);
     }
 
     #[test]
@@ -851,6 +1495,26 @@ mod tests {
         println!("{k:#?}");
     }
 
+    #[test]
+    fn nested_user_types() {
+        let q =
+            "CREATE TYPE cycling.team (name text, riders frozen<map<text, frozen<cycling.rider>>>)";
+        let QueryString::CreateType(create) = query(q).unwrap() else {
+            panic!("was supposed to be parsed as create type query")
+        };
+        assert_eq!(
+            create.columns[1],
+            (
+                "riders".to_owned(),
+                "frozen<map<text, frozen<cycling.rider>>>".to_owned()
+            )
+        );
+
+        let (rest, ty) = super::types::parse("list<frozen<address>>").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(ty.to_string(), "list<frozen<address>>");
+    }
+
     #[test]
     fn test_named_bind() {
         let q = "INSERT INTO table (field1,field2,field3,field4,field5,field6) VALUES (:field1,:field2,:field3,:field4,:field5,:field6)";
@@ -883,6 +1547,38 @@ mod tests {
         assert!(s.json);
     }
 
+    #[test]
+    fn allow_filtering() {
+        let q = "SELECT field1 FROM table WHERE field0 = ? LIMIT 10 ALLOW FILTERING";
+        let QueryString::Select(s) = query(q).unwrap() else {
+            panic!("was supposed to be parsed as select query")
+        };
+        assert!(s.allow_filtering);
+        assert_eq!(s.limit, Some(10));
+    }
+
+    #[test]
+    fn using_timestamp() {
+        let q = "UPDATE ks.t USING TIMESTAMP ? SET a = ? WHERE id = ?";
+        let QueryString::Update(UpdateQuery {
+            using: Some(using), ..
+        }) = query(q).unwrap()
+        else {
+            panic!("was supposed to be parsed with timestamp")
+        };
+        assert!(using.leading);
+
+        let q = "INSERT INTO ks.t (id, a) VALUES (?, ?) USING TIMESTAMP 1000";
+        let QueryString::Insert(InsertQuery {
+            using: Some(using), ..
+        }) = query(q).unwrap()
+        else {
+            panic!("was supposed to be parsed with timestamp")
+        };
+        assert!(!using.leading);
+        assert!(matches!(using.value, QueryValue::Literal(_)));
+    }
+
     #[test]
     fn name_alias() {
         let q = "SELECT field1 as field2 FROM table";
@@ -900,7 +1596,49 @@ mod tests {
                 alias: Some("field2".to_string()),
                 function: None,
             }
-        )
+        );
+
+        let q = r#"SELECT field1 AS "Say ""hi""", toJson(field2) AS json FROM table"#;
+        let QueryString::Select(SelectQuery {
+            columns: SelectExpression::Columns(c),
+            ..
+        }) = query(q).unwrap()
+        else {
+            panic!("was supposed to be parsed as select query")
+        };
+        assert_eq!(c[0].alias.as_deref(), Some(r#"Say "hi""#));
+        assert_eq!(c[1].alias.as_deref(), Some("json"));
+
+        // `as` is a keyword only on its own
+        assert!(query("SELECT field1 asfield2 FROM table").is_err());
+    }
+
+    #[test]
+    fn count() {
+        for (q, expected) in [
+            ("SELECT COUNT(*) FROM table", None),
+            ("SELECT count( 1 ) FROM table", None),
+            ("SELECT COUNT(*) AS total FROM table", Some("total")),
+        ] {
+            let QueryString::Select(SelectQuery {
+                columns: SelectExpression::Count { alias },
+                ..
+            }) = query(q).unwrap()
+            else {
+                panic!("was supposed to be parsed as count: {q}")
+            };
+            assert_eq!(alias.as_deref(), expected);
+        }
+
+        // columns named `count` are still columns
+        let QueryString::Select(SelectQuery {
+            columns: SelectExpression::Columns(c),
+            ..
+        }) = query("SELECT count FROM table").unwrap()
+        else {
+            panic!("was supposed to be parsed as select query")
+        };
+        assert_eq!(c[0].name, "count");
     }
 
     #[test]
@@ -923,6 +1661,252 @@ mod tests {
         )
     }
 
+    #[test]
+    fn explain() {
+        assert!(matches!(
+            query("EXPLAIN SELECT * FROM cycling.cyclist_name WHERE id = 1;").unwrap(),
+            QueryString::Explain(statement) if matches!(*statement, QueryString::Select(_))
+        ));
+        assert!(matches!(
+            query("explain delete from cycling.cyclist_name where id = 1").unwrap(),
+            QueryString::Explain(statement) if matches!(*statement, QueryString::Delete(_))
+        ));
+        assert!(query("EXPLAIN DESCRIBE KEYSPACES").is_err());
+    }
+
+    #[test]
+    fn describe() {
+        assert!(matches!(
+            query("DESCRIBE KEYSPACES;").unwrap(),
+            QueryString::Describe(DescribeQuery::Keyspaces)
+        ));
+        assert!(matches!(
+            query("desc keyspace").unwrap(),
+            QueryString::Describe(DescribeQuery::Keyspace(None))
+        ));
+        assert!(matches!(
+            query("DESC TABLE cycling.cyclist_name").unwrap(),
+            QueryString::Describe(DescribeQuery::Table { keyspace: Some(ks), table })
+                if ks == "cycling" && table == "cyclist_name"
+        ));
+    }
+
+    #[test]
+    fn keyspace_options() {
+        let QueryString::AlterKeyspace(alter) = query(
+            "ALTER KEYSPACE cycling WITH durable_writes = false AND replication = {'class': 'SimpleStrategy', 'replication_factor': 2};",
+        )
+        .unwrap() else {
+            panic!("was supposed to be parsed as alter keyspace query")
+        };
+        assert_eq!(alter.keyspace, "cycling");
+        assert!(matches!(alter.replication, Some(Literal::Map(_))));
+        assert_eq!(alter.durable_writes, Some(false));
+
+        let QueryString::AlterKeyspace(alter) =
+            query("alter keyspace cycling with DURABLE_WRITES = 'true'").unwrap()
+        else {
+            panic!("was supposed to be parsed as alter keyspace query")
+        };
+        assert!(alter.replication.is_none());
+        assert_eq!(alter.durable_writes, Some(true));
+
+        let QueryString::CreateKeyspace(create) = query(
+            "CREATE KEYSPACE cycling WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1} AND DURABLE_WRITES = false",
+        )
+        .unwrap() else {
+            panic!("was supposed to be parsed as create keyspace query")
+        };
+        assert_eq!(create.durable_writes, Some(false));
+
+        assert!(query("CREATE KEYSPACE cycling WITH durable_writes = false").is_err());
+    }
+
+    #[test]
+    fn roles() {
+        let create =
+            query("CREATE ROLE IF NOT EXISTS alice WITH LOGIN = true AND PASSWORD = 'it''s'")
+                .unwrap();
+        assert!(matches!(
+            &create,
+            QueryString::CreateRole(CreateRoleQuery { role, ignore_existence: true, password: Some(password), login: true, superuser: false })
+                if role == "alice" && password == "it's"
+        ));
+        assert_eq!(
+            create.to_string(),
+            "CREATE ROLE IF NOT EXISTS alice WITH PASSWORD = 'it''s' AND LOGIN = true"
+        );
+        assert!(matches!(
+            query("DROP ROLE IF EXISTS 'Bob'").unwrap(),
+            QueryString::DropRole(DropRoleQuery { role, ignore_existence: true }) if role == "Bob"
+        ));
+
+        let grant = query("GRANT ALL PERMISSIONS ON KEYSPACE cycling TO alice").unwrap();
+        assert!(matches!(
+            &grant,
+            QueryString::Grant(PermissionQuery { permission: Permission::All, resource: Resource::Keyspace(keyspace), role })
+                if keyspace == "cycling" && role == "alice"
+        ));
+        assert_eq!(
+            grant.to_string(),
+            "GRANT ALL PERMISSIONS ON KEYSPACE cycling TO alice"
+        );
+        assert!(matches!(
+            query("revoke select permission on cyclist_name from alice;").unwrap(),
+            QueryString::Revoke(PermissionQuery { permission: Permission::Select, resource: Resource::Table { keyspace: None, table }, .. })
+                if table == "cyclist_name"
+        ));
+        assert!(matches!(
+            query("GRANT MODIFY ON ALL KEYSPACES TO alice").unwrap(),
+            QueryString::Grant(PermissionQuery {
+                resource: Resource::AllKeyspaces,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn existence_clauses() {
+        assert!(matches!(
+            query("DROP KEYSPACE IF EXISTS cycling").unwrap(),
+            QueryString::DropKeyspace(DropKeyspaceQuery { keyspace, ignore_existence: true })
+                if keyspace == "cycling"
+        ));
+        assert!(matches!(
+            query("drop table cycling.cyclist_name;").unwrap(),
+            QueryString::DropTable(DropTableQuery { keyspace: Some(ks), table, ignore_existence: false })
+                if ks == "cycling" && table == "cyclist_name"
+        ));
+        assert!(matches!(
+            query("DROP TYPE IF EXISTS basic_info").unwrap(),
+            QueryString::DropType(DropTypeQuery { keyspace: None, name, ignore_existence: true })
+                if name == "basic_info"
+        ));
+        assert!(matches!(
+            query("CREATE TYPE IF NOT EXISTS cycling.basic_info (birthday timestamp)").unwrap(),
+            QueryString::CreateType(CreateTypeQuery {
+                ignore_existence: true,
+                ..
+            })
+        ));
+
+        let error = query("DROP INDEX cycling.rank_idx").unwrap_err();
+        assert_eq!(error.error, DbError::Unimplemented);
+    }
+
+    #[test]
+    fn batch() {
+        let q = "BEGIN UNLOGGED BATCH USING TIMESTAMP 1000
+            INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS');
+            UPDATE cycling.cyclist_name SET lastname = ? WHERE id = ?
+            DELETE FROM cycling.cyclist_name WHERE id = 3;
+        APPLY BATCH;";
+        let QueryString::Batch(batch) = query(q).unwrap() else {
+            panic!("was supposed to be parsed as batch query")
+        };
+        assert_eq!(batch.batch_type, BatchType::Unlogged);
+        assert!(batch.using.is_some());
+        assert_eq!(batch.statements.len(), 3);
+        assert_eq!(QueryString::Batch(batch).bind_markers(), 2);
+
+        assert!(matches!(
+            query("begin batch apply batch").unwrap(),
+            QueryString::Batch(batch) if batch.statements.is_empty()
+        ));
+        let error =
+            query("BEGIN BATCH SELECT * FROM cycling.cyclist_name; APPLY BATCH").unwrap_err();
+        assert_eq!(error.error, DbError::SyntaxError);
+    }
+
+    #[test]
+    fn round_trip() {
+        let statements = [
+            "SELECT * FROM cycling.cyclist_name",
+            "SELECT JSON id, toJson(records) AS records FROM cyclist_name WHERE id = ? AND lastname = 'O''Grady' LIMIT 10 ALLOW FILTERING",
+            "SELECT id FROM cycling.cyclist_name WHERE token(id) > -100 AND token(id) <= ?",
+            r#"SELECT id AS "Id", lastname AS name, toJson(records) FROM cycling.cyclist_name"#,
+            "SELECT * FROM cycling.rank WHERE race = ? AND year = 2019 AND (rank, rider) >= (?, 'a') AND rank < 10",
+            "SELECT id FROM cycling.cyclist_name WHERE lastname LIKE 'VOS%' AND firstname LIKE ?",
+            "INSERT INTO cycling.cyclist_name (id, lastname, records, ratio, active) VALUES (6ab09bec-e68e-48d9-a5f8-97e6fb4c9b47, 'VOS', {'2019': 'first', '2020': null}, 1.0, true) USING TIMESTAMP 1000",
+            "INSERT INTO cycling.cyclist_name JSON '{\"id\": 1, \"lastname\": \"VOS\"}' DEFAULT UNSET USING TIMESTAMP ?",
+            "INSERT INTO cycling.cyclist_name JSON ?",
+            "INSERT INTO cycling.cyclist_name (id, tags) VALUES (1, fromJson('[\"a\"]'))",
+            "UPDATE cycling.cyclist_name USING TIMESTAMP ? SET lastname = ?, tags = ['a', 'b'] WHERE id = 1",
+            "UPDATE cycling.cyclist_name SET tags = fromJson(?) WHERE id = 1",
+            "DELETE FROM cycling.cyclist_name WHERE id = 1",
+            "DELETE lastname, firstname FROM cycling.cyclist_name USING TIMESTAMP 10 WHERE id = 1",
+            "BEGIN UNLOGGED BATCH USING TIMESTAMP 5 INSERT INTO t (id) VALUES (1); DELETE FROM t WHERE id = 2; APPLY BATCH",
+            "USE cycling",
+            "CREATE KEYSPACE IF NOT EXISTS cycling WITH replication = {'class': 'SimpleStrategy', 'replication_factor': 1} AND durable_writes = false",
+            "ALTER KEYSPACE cycling WITH replication = {'class': 'NetworkTopologyStrategy', 'datacenter1': 3}",
+            "ALTER KEYSPACE cycling WITH durable_writes = true",
+            "CREATE TABLE IF NOT EXISTS cycling.rank (race text, year int, rank int, riders frozen<list<text>>, scores map<text, int>, PRIMARY KEY ((race, year), rank)) WITH CLUSTERING ORDER BY (rank DESC) AND comment = 'it''s fast' AND bloom_filter_fp_chance = 0.1",
+            "CREATE TABLE cycling.cyclist_name (id uuid PRIMARY KEY, lastname text)",
+            "CREATE TYPE IF NOT EXISTS cycling.basic_info (birthday timestamp, teams list<text>)",
+            "DROP KEYSPACE IF EXISTS cycling",
+            "DROP TABLE cycling.cyclist_name",
+            "DROP TYPE IF EXISTS basic_info",
+            "DESCRIBE KEYSPACE",
+            "DESCRIBE TABLE cycling.cyclist_name",
+        ];
+
+        for statement in statements {
+            let printed = query(statement).unwrap().to_string();
+            let reparsed = query(&printed)
+                .unwrap_or_else(|error| panic!("`{printed}` can't be parsed: {error:?}"));
+            assert_eq!(reparsed.to_string(), printed);
+        }
+
+        assert_eq!(
+            query("create table t (id int, c int, primary key (id, c)) with clustering order by (c asc)")
+                .unwrap_err()
+                .error,
+            DbError::SyntaxError
+        );
+        assert_eq!(
+            query("CREATE TABLE t (a int, b int, c int, d int, PRIMARY KEY (a, c, b)) WITH CLUSTERING ORDER BY (c DESC, b ASC)")
+                .unwrap()
+                .to_string(),
+            "CREATE TABLE t (a int, b int, c int, d int, PRIMARY KEY (a, c, b)) WITH CLUSTERING ORDER BY (c DESC, b ASC)"
+        );
+    }
+
+    #[test]
+    fn unsupported_statements() {
+        let error = query("create index on ks.t (value)").unwrap_err();
+        assert_eq!(error.error, DbError::Unimplemented);
+
+        let error = query("select from where").unwrap_err();
+        assert_eq!(error.error, DbError::SyntaxError);
+    }
+
+    #[test]
+    fn syntax_error_diagnostics() {
+        let error = query("SELECT id\nFORM cycling.cyclist_name").unwrap_err();
+        let diagnostic = error.diagnostic().unwrap();
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 1));
+        assert_eq!(diagnostic.snippet, "FORM cycling.cyclist_name");
+        assert_eq!(diagnostic.expected, ["FROM"]);
+        assert_eq!(
+            error.reason,
+            "Invalid syntax at line 2, column 1, expected FROM\nFORM cycling.cyclist_name\n^"
+        );
+
+        let error = query("DELETE FROM t WHERE id = ").unwrap_err();
+        let diagnostic = error.diagnostic().unwrap();
+        assert_eq!(diagnostic.column, 26);
+        assert!(diagnostic.expected.iter().any(|it| it == "?"));
+
+        let error = query("create index on ks.t (value)").unwrap_err();
+        assert!(error.diagnostic().is_none());
+    }
+
+    #[test]
+    fn invalid_column_type() {
+        let error = query("CREATE TABLE ks.t (id int PRIMARY KEY, value 42)").unwrap_err();
+        assert_eq!(error.error, DbError::SyntaxError);
+    }
+
     #[test]
     fn test_filter_comments() {
         let s = "hello /* blabla */ world /* blabla */!";
@@ -0,0 +1,172 @@
+use std::fmt;
+
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    cql::value::{CqlValue, PartitionKeyValue},
+    frame::write,
+};
+
+/// Maps partition keys onto the token ring, which defines the order partitions are stored and scanned in.
+pub trait Partitioner: fmt::Debug + Clone + Default + Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    fn token(&self, key: &PartitionKeyValue) -> i64;
+}
+
+/// Cassandra compatible `Murmur3Partitioner`, so tokens computed here
+/// match the ones drivers compute for token aware routing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Murmur3Partitioner;
+
+impl Partitioner for Murmur3Partitioner {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn token(&self, key: &PartitionKeyValue) -> i64 {
+        Self::hash(&routing_key(key))
+    }
+}
+
+impl Murmur3Partitioner {
+    pub const NAME: &'static str = "org.apache.cassandra.dht.Murmur3Partitioner";
+
+    pub fn hash(data: &[u8]) -> i64 {
+        match murmur3_x64_128(data, 0).0 {
+            // `i64::MIN` is reserved for the minimum token
+            i64::MIN => i64::MAX,
+            token => token,
+        }
+    }
+
+    /// Evenly spreads `num_tokens` tokens over the whole ring.
+    pub fn generate_tokens(num_tokens: usize) -> Vec<i64> {
+        let num_tokens = num_tokens.max(1) as u64;
+        let step = u64::MAX / num_tokens;
+
+        (0..num_tokens)
+            .map(|i| i64::MIN.wrapping_add((step / 2 + step * i) as i64))
+            .collect()
+    }
+}
+
+/// Serializes partition key the same way drivers build routing keys:
+/// simple keys are written as is, composite ones are `<len><value>0x00` for every component.
+pub fn routing_key(key: &PartitionKeyValue) -> Vec<u8> {
+    match key {
+        PartitionKeyValue::Simple(value) => value_bytes(value).to_vec(),
+        PartitionKeyValue::Composite(values) => {
+            let mut buf = BytesMut::new();
+            for value in values {
+                let value = value_bytes(value);
+                buf.put_u16(value.len() as u16);
+                buf.put_slice(&value);
+                buf.put_u8(0);
+            }
+            buf.to_vec()
+        }
+        PartitionKeyValue::Empty => vec![],
+    }
+}
+
+fn value_bytes(value: &CqlValue) -> BytesMut {
+    let mut buf = BytesMut::new();
+    write::opt_cql_value(&mut buf, Some(value));
+
+    // drop `[int]` length prefix
+    buf.split_off(4)
+}
+
+fn murmur3_x64_128(data: &[u8], seed: u64) -> (i64, i64) {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    let mut h1 = seed;
+    let mut h2 = seed;
+
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let k2 = u64::from_le_bytes(block[8..].try_into().unwrap());
+
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    // Cassandra reads tail bytes as signed, so they are sign extended here as well
+    let tail = blocks.remainder();
+    let byte = |i: usize| tail[i] as i8 as i64 as u64;
+
+    let mut k1 = 0u64;
+    let mut k2 = 0u64;
+    for i in (8..tail.len()).rev() {
+        k2 ^= byte(i) << ((i - 8) * 8);
+    }
+    if tail.len() > 8 {
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    for i in (0..tail.len().min(8)).rev() {
+        k1 ^= byte(i) << (i * 8);
+    }
+    if !tail.is_empty() {
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    h1 = fmix(h1);
+    h2 = fmix(h2);
+
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    (h1 as i64, h2 as i64)
+}
+
+fn fmix(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Murmur3Partitioner, Partitioner};
+    use crate::cql::value::{CqlValue, PartitionKeyValue};
+
+    #[test]
+    fn int_tokens_match_cassandra() {
+        let token = |v| Murmur3Partitioner.token(&PartitionKeyValue::Simple(CqlValue::Int(v)));
+
+        assert_eq!(token(1), -4069959284402364209);
+        assert_eq!(token(2), -3248873570005575792);
+    }
+
+    #[test]
+    fn generated_tokens_are_sorted() {
+        let tokens = Murmur3Partitioner::generate_tokens(16);
+
+        assert_eq!(tokens.len(), 16);
+        assert!(tokens.windows(2).all(|it| it[0] < it[1]));
+    }
+}
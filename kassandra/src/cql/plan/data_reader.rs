@@ -1,18 +1,24 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Bound};
+
+use nom::number::complete::be_i32;
 
 use crate::{
     cql::{
-        query::QueryValue,
-        schema::{PrimaryKey, TableSchema},
+        json,
+        literal::Literal,
+        query::{ClusteringRelation, JsonDefault, Operator, QueryValue},
+        schema::{Column, ColumnType, PrimaryKey, TableSchema},
         value::{
             deserialize_value, map_lit, ClusteringKeyValue, ClusteringKeyValueRange, CqlValue,
             PartitionKeyValue,
         },
     },
     error::DbError,
-    frame::{response::error::Error, value::FrameValue},
+    frame::{parse, response::error::Error, value::FrameValue},
+    storage::{Like, Predicate},
 };
 
+/// Values of a statement by column, `None` for nulls, columns bound to unset values are absent.
 pub struct DataPayload<'a> {
     schema: &'a TableSchema,
     pub raw: HashMap<String, Option<CqlValue>>,
@@ -30,6 +36,87 @@ impl<'a> DataPayload<'a> {
         })
     }
 
+    /// Cells written by the statement, nulls delete the cell and unset columns are left untouched
+    pub fn into_cells(self) -> Vec<(String, Option<CqlValue>)> {
+        self.raw.into_iter().collect()
+    }
+
+    /// Restrictions of the columns `filtered` accepts, rows are matched against them by storage
+    pub fn predicate(&self, filtered: impl Fn(&Column) -> bool) -> Result<Predicate, Error> {
+        let mut predicate = Predicate::new();
+        for (name, column) in &self.schema.columns {
+            let Some(value) = self.raw.get(name).filter(|_| filtered(column)) else {
+                continue;
+            };
+            let value = value.clone().ok_or_else(|| {
+                Error::new(
+                    DbError::Invalid,
+                    format!("Unsupported null value for column {name}"),
+                )
+            })?;
+            predicate = predicate.eq(name, value);
+        }
+
+        Ok(predicate)
+    }
+
+    /// Values of the columns of an `INSERT .. JSON` document, `document` is either a string literal
+    /// or bound to the only marker. Names of columns are case-insensitive unless they are quoted.
+    pub fn from_json(
+        schema: &'a TableSchema,
+        document: QueryValue,
+        data: Vec<FrameValue<'_>>,
+        default: JsonDefault,
+    ) -> Result<Self, Error> {
+        let document = match (document, data.first()) {
+            (QueryValue::Literal(Literal::String(document)), _) => document,
+            (QueryValue::Blankslate, Some(FrameValue::Some(value))) => {
+                match read_bound_value("[json]", value, &ColumnType::Text)? {
+                    CqlValue::Text(document) => document.to_string(),
+                    _ => unreachable!("text value is always read as text"),
+                }
+            }
+            (QueryValue::Blankslate, _) => {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    "Got null or unset for INSERT JSON values",
+                ))
+            }
+            (document, _) => {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!("INSERT JSON only accepts a json string, but got {document}"),
+                ))
+            }
+        };
+
+        let mut raw = HashMap::new();
+        for (name, value) in json::parse_object(&document)? {
+            let name = match name.strip_prefix('"').and_then(|it| it.strip_suffix('"')) {
+                Some(quoted) => quoted.to_owned(),
+                None => name.to_lowercase(),
+            };
+            let column = schema_column(schema, &name)?;
+            let value = json::from_json(&column.ty, value)?;
+            if raw.insert(name.clone(), value).is_some() {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!("Multiple definitions found for column {name}"),
+                ));
+            }
+        }
+
+        if default == JsonDefault::Null {
+            for name in schema.columns.keys() {
+                if !is_primary_key(schema, name) {
+                    raw.entry(name.clone()).or_insert(None);
+                }
+            }
+        }
+
+        Ok(Self { schema, raw })
+    }
+
     pub fn get_partition_key(&self) -> Result<PartitionKeyValue, Error> {
         Ok(match &self.schema.partition_key {
             PrimaryKey::Empty => unreachable!("Can't have empty primary key"),
@@ -93,7 +180,8 @@ impl<'a> DataPayload<'a> {
             PrimaryKey::Empty => (..).into(),
             PrimaryKey::Simple(key) => {
                 if let Some(value) = self.raw.get(key).and_then(Clone::clone) {
-                    (ClusteringKeyValue::Simple(Some(value))..).into()
+                    let key = ClusteringKeyValue::Simple(Some(value));
+                    ClusteringKeyValueRange::Range(key.clone(), key)
                 } else {
                     (..).into()
                 }
@@ -129,6 +217,129 @@ impl<'a> DataPayload<'a> {
 
         Ok(range)
     }
+
+    /// Narrows the range of clustering key equalities with slices of the columns following them,
+    /// `data` are the values bound to the slices
+    pub fn get_clustering_key_slice(
+        &self,
+        relations: Vec<ClusteringRelation>,
+        data: impl IntoIterator<Item = FrameValue<'a>> + 'a,
+    ) -> Result<ClusteringKeyValueRange, Error> {
+        let mut range = self.get_clustering_key_range()?;
+        if relations.is_empty() {
+            return Ok(range);
+        }
+
+        let keys = self.schema.clustering_key.into_iter().collect::<Vec<_>>();
+        let prefix = keys
+            .iter()
+            .map_while(|key| self.raw.get(*key).cloned())
+            .collect::<Vec<_>>();
+
+        for ClusteringRelation {
+            columns, values, ..
+        } in &relations
+        {
+            let following = keys.iter().skip(prefix.len()).take(columns.len());
+            if !columns.iter().eq(following.copied()) {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!(
+                        "Slice restrictions must be on clustering columns following the ones \
+                         restricted by equalities, got ({})",
+                        columns.join(", ")
+                    ),
+                ));
+            }
+            if values.len() != columns.len() {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!(
+                        "Expected {} elements in value tuple, but got {}",
+                        columns.len(),
+                        values.len()
+                    ),
+                ));
+            }
+        }
+
+        let pairs = relations
+            .iter()
+            .flat_map(|it| it.columns.iter().cloned().zip(it.values.iter().cloned()))
+            .collect::<Vec<_>>();
+        let mut bound = parse_values(self.schema, pairs.into_iter(), data);
+
+        let key = |values: &[Option<CqlValue>]| match &self.schema.clustering_key {
+            PrimaryKey::Simple(_) => ClusteringKeyValue::Simple(values[0].clone()),
+            _ => ClusteringKeyValue::Composite(values.to_vec()),
+        };
+        for relation in relations {
+            let mut values = prefix.clone();
+            for _ in &relation.columns {
+                let (column, value) = bound.next().ok_or(Error::new(
+                    DbError::Invalid,
+                    "Missing required blankslate value",
+                ))??;
+                let value = value.ok_or_else(|| {
+                    Error::new(
+                        DbError::Invalid,
+                        format!("Invalid null value in condition for column {column}"),
+                    )
+                })?;
+                values.push(Some(value));
+            }
+
+            // sorts after every key starting with `values`
+            let mut after = values.clone();
+            after.push(Some(CqlValue::Empty));
+
+            let (start, end) = match relation.operator {
+                Operator::Eq => (Bound::Included(key(&values)), Bound::Included(key(&after))),
+                Operator::Ge => (Bound::Included(key(&values)), Bound::Unbounded),
+                Operator::Gt => (Bound::Excluded(key(&after)), Bound::Unbounded),
+                Operator::Le => (Bound::Unbounded, Bound::Included(key(&after))),
+                Operator::Lt => (Bound::Unbounded, Bound::Excluded(key(&values))),
+            };
+            range = range.narrow(start, end);
+        }
+
+        Ok(range)
+    }
+}
+
+/// Adds `LIKE` restrictions of text columns to `predicate`, `data` are the values bound to them
+pub fn like_predicate<'a>(
+    schema: &'a TableSchema,
+    relations: Vec<(String, QueryValue)>,
+    data: impl IntoIterator<Item = FrameValue<'a>> + 'a,
+    mut predicate: Predicate,
+) -> Result<Predicate, Error> {
+    for (column, _) in &relations {
+        let ty = &schema_column(schema, column)?.ty;
+        if !matches!(ty, ColumnType::Text | ColumnType::Ascii) {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("LIKE restriction is only supported on text columns, {column} is {ty:?}"),
+            ));
+        }
+    }
+
+    for value in parse_values(schema, relations.into_iter(), data) {
+        let (column, value) = value?;
+        let pattern = match value {
+            Some(CqlValue::Text(pattern) | CqlValue::Ascii(pattern)) => Like::new(&pattern),
+            _ => None,
+        };
+        let pattern = pattern.ok_or_else(|| {
+            Error::new(
+                DbError::Invalid,
+                format!("LIKE value of column {column} can't be null or empty"),
+            )
+        })?;
+        predicate = predicate.like(column, pattern);
+    }
+
+    Ok(predicate)
 }
 
 fn parse_values<'a>(
@@ -160,15 +371,33 @@ where
         loop {
             let (column, value) = self.inputs.next()?;
 
-            let Some(schema) = self.schema.columns.get(&column) else {
-                return Some(Err(Error::new(
-                    DbError::Invalid,
-                    format!("unknown column `{column}`"),
-                )));
+            let schema = match schema_column(self.schema, &column) {
+                Ok(schema) => schema,
+                Err(error) => return Some(Err(error)),
+            };
+
+            // `fromJson()` takes a json string, which is then decoded as the type of the column
+            let (value, from_json) = match value {
+                QueryValue::FromJson(value) => (*value, true),
+                value => (value, false),
+            };
+            let ty = if from_json {
+                &ColumnType::Text
+            } else {
+                &schema.ty
             };
 
             let value = match value {
-                QueryValue::Literal(lit) => map_lit(&schema.ty, lit).map(Some),
+                QueryValue::Literal(Literal::Null) => Ok(None),
+                QueryValue::Literal(Literal::String(json)) if from_json => {
+                    Ok(Some(CqlValue::Text(json.into())))
+                }
+                QueryValue::Literal(_) | QueryValue::FromJson(_) if from_json => Err(Error::new(
+                    DbError::Invalid,
+                    format!("fromJson() of column {column} only accepts json strings"),
+                )),
+                QueryValue::Literal(lit) => map_lit(ty, lit).map(Some),
+                QueryValue::FromJson(_) => unreachable!("fromJson() is unwrapped above"),
                 QueryValue::Blankslate => {
                     let Some(next_value) = self.data.next() else {
                         return Some(Err(Error::new(
@@ -178,12 +407,22 @@ where
                     };
 
                     match next_value {
+                        FrameValue::NotSet if is_primary_key(self.schema, &column) => {
+                            Err(Error::new(
+                                DbError::Invalid,
+                                format!("Invalid unset value for column {column}"),
+                            ))
+                        }
                         FrameValue::NotSet => continue,
                         FrameValue::Null => Ok(None),
-                        FrameValue::Some(value) => deserialize_value(value, &schema.ty).map(Some),
+                        FrameValue::Some(value) => read_bound_value(&column, value, ty).map(Some),
                     }
                 }
             };
+            let value = match value {
+                Ok(Some(CqlValue::Text(json))) if from_json => json::parse(&schema.ty, &json),
+                value => value,
+            };
 
             return match value {
                 Ok(value) => Some(Ok((column, value))),
@@ -196,3 +435,172 @@ where
         self.inputs.size_hint()
     }
 }
+
+pub fn is_primary_key(schema: &TableSchema, column: &str) -> bool {
+    schema
+        .partition_key
+        .into_iter()
+        .chain(&schema.clustering_key)
+        .any(|key| key == column)
+}
+
+pub fn schema_column<'s>(schema: &'s TableSchema, column: &str) -> Result<&'s Column, Error> {
+    schema
+        .columns
+        .get(column)
+        .ok_or_else(|| Error::new(DbError::Invalid, format!("Undefined column name {column}")))
+}
+
+/// Rejects writes which don't name every part of the primary key, as cassandra does
+pub fn check_primary_key<'c>(
+    schema: &TableSchema,
+    columns: impl Iterator<Item = &'c String> + Clone,
+) -> Result<(), Error> {
+    let missing = |key: &PrimaryKey| {
+        key.into_iter()
+            .filter(|part| !columns.clone().any(|column| column == *part))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    let partition_key = missing(&schema.partition_key);
+    if !partition_key.is_empty() {
+        return Err(Error::new(
+            DbError::Invalid,
+            format!(
+                "Some partition key parts are missing: {}",
+                partition_key.join(", ")
+            ),
+        ));
+    }
+
+    let clustering_key = missing(&schema.clustering_key);
+    if !clustering_key.is_empty() {
+        return Err(Error::new(
+            DbError::Invalid,
+            format!(
+                "Some clustering keys are missing: {}",
+                clustering_key.join(", ")
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Deserializes a value bound to `target` (a column, or a clause like `USING TIMESTAMP`),
+/// rejecting bytes which don't have the shape of its type
+pub fn read_bound_value(target: &str, data: &[u8], ty: &ColumnType) -> Result<CqlValue, Error> {
+    check_shape(data, ty).map_err(|reason| {
+        Error::new(
+            DbError::Invalid,
+            format!("Invalid value bound to `{target}`: {reason}"),
+        )
+    })?;
+
+    deserialize_value(data, ty)
+}
+
+fn check_shape(data: &[u8], ty: &ColumnType) -> Result<(), String> {
+    let fixed = |name: &str, len: usize| {
+        if data.len() == len {
+            Ok(())
+        } else {
+            Err(format!(
+                "expected {len} bytes for {name}, got {}",
+                data.len()
+            ))
+        }
+    };
+
+    match ty {
+        ColumnType::Blob => Ok(()),
+        ColumnType::Ascii => match data.iter().position(|b| !b.is_ascii()) {
+            None => Ok(()),
+            Some(at) => Err(format!("non-ascii byte at position {at} for ascii")),
+        },
+        ColumnType::Text => std::str::from_utf8(data)
+            .map(|_| ())
+            .map_err(|err| format!("invalid utf-8 for text: {err}")),
+        ColumnType::Boolean => fixed("boolean", 1),
+        ColumnType::Int => fixed("int", 4),
+        ColumnType::Date => fixed("date", 4),
+        ColumnType::Float => fixed("float", 4),
+        ColumnType::BigInt => fixed("bigint", 8),
+        ColumnType::Counter => fixed("counter", 8),
+        ColumnType::Timestamp => fixed("timestamp", 8),
+        ColumnType::Double => fixed("double", 8),
+        ColumnType::Uuid => fixed("uuid", 16),
+        ColumnType::Inet => match data.len() {
+            4 | 16 => Ok(()),
+            n => Err(format!("expected 4 or 16 bytes for inet, got {n}")),
+        },
+        ColumnType::List(inner) | ColumnType::Set(inner) => check_collection(data, &[inner]),
+        ColumnType::Map(key, value) => check_collection(data, &[key, value]),
+        ColumnType::Tuple(types) => {
+            let rest = types
+                .iter()
+                .try_fold(data, |rest, ty| check_element(rest, ty))?;
+            check_consumed(rest)
+        }
+        // values can leave out trailing fields, the ones added to the type after they were written
+        ColumnType::UserDefinedType { field_types, .. } => {
+            let mut rest = data;
+            for (_, ty) in field_types {
+                if rest.is_empty() {
+                    break;
+                }
+                rest = check_element(rest, ty)?;
+            }
+            check_consumed(rest)
+        }
+        ColumnType::Decimal if data.len() < 4 => Err(format!(
+            "expected at least 4 bytes for decimal, got {}",
+            data.len()
+        )),
+        ColumnType::Decimal | ColumnType::Varint => Ok(()),
+        ColumnType::Duration => {
+            let rest = (0..3).try_fold(data, |rest, _| {
+                parse::signed_vint(rest)
+                    .map(|(rest, _)| rest)
+                    .map_err(|_| "truncated duration".to_owned())
+            })?;
+            check_consumed(rest)
+        }
+        other => Err(format!("values of type {other:?} are not supported")),
+    }
+}
+
+/// Collections are prefixed with the count of their elements (or key-value pairs for maps)
+fn check_collection(data: &[u8], types: &[&ColumnType]) -> Result<(), String> {
+    let (mut rest, count) =
+        be_i32::<_, nom::error::Error<_>>(data).map_err(|_| "truncated collection".to_owned())?;
+    if count < 0 {
+        return Err(format!("negative collection size {count}"));
+    }
+
+    for _ in 0..count {
+        for ty in types {
+            rest = check_element(rest, ty)?;
+        }
+    }
+
+    check_consumed(rest)
+}
+
+fn check_element<'a>(data: &'a [u8], ty: &ColumnType) -> Result<&'a [u8], String> {
+    let (rest, element) =
+        parse::bytes_opt(data).map_err(|_| "truncated collection or tuple element".to_owned())?;
+    if let Some(element) = element {
+        check_shape(element, ty)?;
+    }
+
+    Ok(rest)
+}
+
+fn check_consumed(rest: &[u8]) -> Result<(), String> {
+    match rest.len() {
+        0 => Ok(()),
+        n => Err(format!("{n} unexpected trailing bytes")),
+    }
+}
@@ -5,10 +5,15 @@ use serde::Serialize;
 use crate::{
     cql,
     cql::{
-        execution::{AlterSchema, DeleteNode, Executor, InsertNode, ScanNode, SelectNode},
+        engine::Mutation,
+        execution::{
+            AlterSchema, AuthNode, DeleteNode, DescribeNode, Executor, ExplainNode, InsertNode,
+            Reader, ScanNode, SelectNode, UpdateNode,
+        },
         query::QueryString,
         schema::Catalog,
     },
+    error::DbError,
     frame::{
         request::query_params::QueryParameters,
         response::{
@@ -25,6 +30,9 @@ mod planner;
 pub enum Aggregate {
     #[display(fmt = "JSON")]
     Json,
+    /// `COUNT(*)` of the rows of a select or a scan, counted by the storage without reading them
+    #[display(fmt = "COUNT")]
+    Count,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -36,29 +44,97 @@ pub enum Plan {
     Select(SelectNode),
     Scan(ScanNode),
     Insert(InsertNode),
+    Update(UpdateNode),
     Delete(DeleteNode),
     AlterSchema(AlterSchema),
+    Describe(DescribeNode),
+    Explain(ExplainNode),
+    Auth(AuthNode),
 }
 
 impl Plan {
+    /// `strict` planning rejects writes missing parts of the primary key with the errors cassandra gives
     pub fn build(
         statement: QueryString,
         parameters: QueryParameters<'_>,
         use_keyspace: Option<String>,
-        catalog: &mut impl Catalog,
+        catalog: &impl Catalog,
+        strict: bool,
     ) -> Result<Plan, Error> {
-        Planner::new(catalog, use_keyspace).build(statement, parameters)
+        Planner::new(catalog, use_keyspace, strict).build(statement, parameters)
     }
 
     pub fn prepare(
         statement: QueryString,
         use_keyspace: Option<String>,
-        catalog: &mut impl Catalog,
+        catalog: &impl Catalog,
+        strict: bool,
     ) -> Result<(PreparedMetadata, ResultMetadata), Error> {
-        Planner::new(catalog, use_keyspace).prepare(statement)
+        Planner::new(catalog, use_keyspace, strict).prepare(statement)
     }
 
     pub fn execute<E: cql::Engine + 'static>(self, engine: &mut E) -> Result<QueryResult, Error> {
         <dyn Executor<E>>::build(self).execute(engine)
     }
+
+    /// Executes the plan with shared access to the engine, fails for plans modifying data or schema
+    pub fn read<E: cql::Engine + 'static>(self, engine: &E) -> Result<QueryResult, Error> {
+        let name = self.name();
+        match <dyn Reader<E>>::build(self) {
+            Some(reader) => reader.read(engine),
+            None => Err(Error::new(
+                DbError::ServerError,
+                format!("{name} plan can't be executed as a read"),
+            )),
+        }
+    }
+
+    /// Row write of the plan, which batches stage until all of their statements are planned.
+    /// Only `INSERT`, `UPDATE` and `DELETE` can be part of a batch.
+    pub fn mutation(self) -> Result<Mutation, Error> {
+        Ok(match self {
+            Plan::Insert(node) => Mutation::Insert {
+                keyspace: node.keyspace,
+                table: node.table,
+                partition_key: node.partition_key,
+                clustering_key: node.clustering_key,
+                values: node.values,
+                timestamp: node.timestamp,
+            },
+            Plan::Update(node) => Mutation::Insert {
+                keyspace: node.keyspace,
+                table: node.table,
+                partition_key: node.partition_key,
+                clustering_key: node.clustering_key,
+                values: node.values,
+                timestamp: node.timestamp,
+            },
+            Plan::Delete(node) => Mutation::Delete {
+                keyspace: node.keyspace,
+                table: node.table,
+                partition_key: node.partition_key,
+                clustering_key: node.clustering_key,
+                timestamp: node.timestamp,
+            },
+            _ => return Err(Error::new(
+                DbError::Invalid,
+                "Invalid statement in batch: only UPDATE, INSERT and DELETE statements are allowed",
+            )),
+        })
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Plan::Aggregate { .. } => "Aggregate",
+            Plan::Select(_) => "Select",
+            Plan::Scan(_) => "Scan",
+            Plan::Insert(_) => "Insert",
+            Plan::Update(_) => "Update",
+            Plan::Delete(_) => "Delete",
+            Plan::AlterSchema(_) => "AlterSchema",
+            Plan::Describe(_) => "Describe",
+            Plan::Explain(_) => "Explain",
+            Plan::Auth(_) => "Auth",
+        }
+    }
 }
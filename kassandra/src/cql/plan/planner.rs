@@ -1,22 +1,36 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
+};
+
 use tracing::{instrument, Level};
 
 use crate::{
+    auth,
     cql::{
-        column::{self, Column, ColumnKind},
+        column::{self, Column, ColumnKind, ColumnType},
         execution::{
             self,
             selector::{ColumnsSelector, Transform},
-            AlterSchema, DeleteNode, InsertNode, ScanNode, SelectNode,
+            AlterSchema, AuthNode, DeleteNode, DescribeNode, ExplainNode, InsertNode, ScanNode,
+            SelectNode, UpdateNode,
         },
         functions::CqlFunction,
+        literal::Literal,
+        parser,
         plan::{data_reader, Aggregate, Plan},
         query::{
-            self, CreateKeyspaceQuery, CreateTableQuery, DeleteQuery, InsertQuery, QueryString,
-            QueryValue, SelectExpression, SelectQuery,
+            self, AlterKeyspaceQuery, CreateKeyspaceQuery, CreateRoleQuery, CreateTableQuery,
+            CreateTypeQuery, DeleteQuery, DescribeQuery, DropKeyspaceQuery, DropRoleQuery,
+            DropTableQuery, DropTypeQuery, InsertQuery, Operator, Permission, PermissionQuery,
+            QueryString, QueryValue, Resource, SelectExpression, SelectQuery, TokenRelation,
+            UpdateQuery, UsingTimestamp, WhereClosure,
         },
-        schema::{keyspace::Strategy, PrimaryKey, PrimaryKeyColumn, TableSchema},
-        types::PreCqlType,
-        value::{ClusteringKeyValue, ClusteringKeyValueRange, CqlValue, PartitionKeyValue},
+        schema::{
+            keyspace::Strategy, system::is_system_keyspace, ClusteringOrder, PrimaryKey,
+            PrimaryKeyColumn, TableOptions, TableSchema,
+        },
+        value::{map_lit, ClusteringKeyValue, CqlValue, PartitionKeyValue, PartitionKeyValueRange},
         Catalog,
     },
     error::DbError,
@@ -24,24 +38,61 @@ use crate::{
         parse,
         request::QueryParameters,
         response::{
-            error::Error,
+            error::{Error, KnownError},
             result::{ColumnSpec, PartitionKeyIndex, PreparedMetadata, ResultMetadata, TableSpec},
         },
-        value::PagingState,
+        value::{FrameValue, PagingState},
     },
+    session::DATACENTER,
+    storage::{self, Predicate},
 };
 
-pub struct Planner<C: Catalog> {
-    catalog: C,
+/// Cassandra caps `default_time_to_live` at 20 years
+const MAX_TTL: i64 = 20 * 365 * 24 * 60 * 60;
+
+const FILTERING_REQUIRED: &str = "Cannot execute this query as it might involve data filtering \
+    and thus may have unpredictable performance. If you want to execute this query despite \
+    the performance unpredictability, use ALLOW FILTERING";
+
+pub struct Planner<'a, C: Catalog + ?Sized> {
+    catalog: &'a C,
     use_keyspace: Option<String>,
+    strict: bool,
 }
 
-impl<C: Catalog> Planner<C> {
-    pub fn new(catalog: C, use_keyspace: Option<String>) -> Self {
+impl<'a, C: Catalog + ?Sized> Planner<'a, C> {
+    pub fn new(catalog: &'a C, use_keyspace: Option<String>, strict: bool) -> Self {
         Self {
             catalog,
             use_keyspace,
+            strict,
+        }
+    }
+
+    fn table(&self, keyspace: &str, table: &str) -> Result<&'a TableSchema, Error> {
+        if let Some(schema) = self.catalog.get_table(keyspace, table) {
+            return Ok(schema);
+        }
+
+        Err(match self.catalog.get_keyspace(keyspace) {
+            Some(_) => KnownError::UnconfiguredTable {
+                keyspace: keyspace.to_owned(),
+                table: table.to_owned(),
+            },
+            None => KnownError::UnknownKeyspace(keyspace.to_owned()),
+        }
+        .into())
+    }
+
+    fn check_primary_key<'c>(
+        &self,
+        schema: &TableSchema,
+        columns: impl Iterator<Item = &'c String> + Clone,
+    ) -> Result<(), Error> {
+        if self.strict {
+            data_reader::check_primary_key(schema, columns)?;
         }
+        Ok(())
     }
 
     #[instrument(level = Level::TRACE, skip(self), err)]
@@ -51,20 +102,42 @@ impl<C: Catalog> Planner<C> {
         parameters: QueryParameters<'_>,
     ) -> Result<Plan, Error> {
         match statement {
-            QueryString::Select(select) if !select.r#where.is_empty() => {
-                self.select(select, parameters)
+            QueryString::Select(select)
+                if select.r#where.statements.is_empty() || self.is_filtered_scan(&select) =>
+            {
+                self.scan(select, parameters)
             }
-
-            QueryString::Select(select) => self.scan(select, parameters),
+            QueryString::Select(select) => self.select(select, parameters),
             QueryString::Insert(insert) => self.insert(insert, parameters),
+            QueryString::Update(update) => self.update(update, parameters),
             QueryString::Delete(delete) if delete.columns.is_empty() => {
                 self.delete(delete, parameters)
             }
             QueryString::Delete(delete) => self.delete_columns(delete, parameters),
-            QueryString::Use { .. } => unimplemented!(),
+            // `USE` and batches are handled by the session itself
+            QueryString::Use { .. } => Err(Error::new(
+                DbError::Unimplemented,
+                "USE statements can't be planned",
+            )),
+            QueryString::Batch(_) => Err(Error::new(
+                DbError::Unimplemented,
+                "BATCH statements can't be planned",
+            )),
             QueryString::CreateKeyspace(create) => self.create_keyspace(create),
+            QueryString::AlterKeyspace(alter) => self.alter_keyspace(alter),
             QueryString::CreateTable(create) => self.create_table(create),
-            QueryString::CreateType { .. } => unimplemented!(),
+            QueryString::CreateType(create) => self.create_type(create),
+            QueryString::DropKeyspace(drop) => self.drop_keyspace(drop),
+            QueryString::DropTable(drop) => self.drop_table(drop),
+            QueryString::DropType(drop) => self.drop_type(drop),
+            QueryString::Describe(describe) => self.describe(describe),
+            QueryString::Explain(statement) => Ok(Plan::Explain(ExplainNode(Box::new(
+                self.build(*statement, parameters)?,
+            )))),
+            QueryString::CreateRole(create) => self.create_role(create, parameters),
+            QueryString::DropRole(drop) => self.drop_role(drop, parameters),
+            QueryString::Grant(grant) => self.permissions(grant, true, parameters),
+            QueryString::Revoke(revoke) => self.permissions(revoke, false, parameters),
         }
     }
 
@@ -76,11 +149,12 @@ impl<C: Catalog> Planner<C> {
         match statement {
             QueryString::Select(select) => self.prepare_select(select),
             QueryString::Insert(insert) => self.prepare_insert(insert),
+            QueryString::Update(update) => self.prepare_update(update),
             QueryString::Delete(delete) if delete.columns.is_empty() => self.prepare_delete(delete),
 
-            _ => Err(Error::new(
-                DbError::Invalid,
-                "Can't prepare this type of query",
+            other => Err(Error::new(
+                DbError::Unimplemented,
+                format!("Preparing {} statements is not supported", other.name()),
             )),
         }
     }
@@ -91,37 +165,50 @@ impl<C: Catalog> Planner<C> {
             table,
             columns,
             values,
+            using,
+            json,
         } = insert;
         let keyspace = keyspace
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
-        if values.len() != columns.len() {
+        if values.len() != columns.len() && json.is_none() {
             return Err(Error::new(
                 DbError::SyntaxError,
                 "Missmatch the amount of columns and values",
             ));
         }
 
-        let schema = self.catalog.get_table(&keyspace, &table).ok_or(Error::new(
-            DbError::Invalid,
-            "Keyspace or table does nor exist",
-        ))?;
+        let schema = self.table(&keyspace, &table)?;
 
-        let values = data_reader::DataPayload::read(
-            schema,
-            columns.into_iter().zip(values),
-            parameters.data,
+        if json.is_none() {
+            self.check_primary_key(schema, columns.iter())?;
+        }
+
+        let mut data = parameters.data;
+        let timestamp = write_timestamp(
+            using,
+            bind_markers(&values),
+            &mut data,
+            parameters.default_timestamp,
         )?;
+        let values = match json {
+            Some(default) => {
+                let document = values
+                    .into_iter()
+                    .next()
+                    .expect("json document to be parsed");
+                let values = data_reader::DataPayload::from_json(schema, document, data, default)?;
+                self.check_primary_key(schema, values.raw.keys())?;
+                values
+            }
+            None => data_reader::DataPayload::read(schema, columns.into_iter().zip(values), data)?,
+        };
 
         let partition_key = values.get_partition_key()?;
         let clustering_key = values.get_clustering_key()?;
 
-        let values = values
-            .raw
-            .into_iter()
-            .filter_map(|(k, v)| Some((k, v?)))
-            .collect();
+        let values = values.into_cells();
 
         let insert = InsertNode {
             keyspace,
@@ -129,6 +216,7 @@ impl<C: Catalog> Planner<C> {
             partition_key,
             clustering_key,
             values,
+            timestamp,
         };
 
         Ok(Plan::Insert(insert))
@@ -143,29 +231,113 @@ impl<C: Catalog> Planner<C> {
             table,
             columns,
             values,
+            using,
+            json,
         } = insert;
         let keyspace = keyspace
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
-        if values.len() != columns.len() {
+        if values.len() != columns.len() && json.is_none() {
             return Err(Error::new(
                 DbError::SyntaxError,
                 "Mismatch the amount of columns and values",
             ));
         }
 
-        let schema = self.catalog.get_table(&keyspace, &table).ok_or(Error::new(
-            DbError::Invalid,
-            "Keyspace or table does nor exist",
-        ))?;
+        let schema = self.table(&keyspace, &table)?;
+
+        let prepared_metadata = match json {
+            // columns of the document are only known once it is bound
+            Some(_) => PreparedMetadata {
+                pk_indexes: vec![],
+                global_spec: Some(TableSpec {
+                    ks_name: keyspace.clone(),
+                    table_name: table.clone(),
+                }),
+                col_specs: bind_markers(&values)
+                    .gt(&0)
+                    .then(|| ColumnSpec::new("[json]".to_owned(), ColumnType::Text))
+                    .into_iter()
+                    .collect(),
+            },
+            None => {
+                self.check_primary_key(schema, columns.iter())?;
+                prepared_metadata(&keyspace, &table, schema, columns.into_iter().zip(values))?
+            }
+        };
+        let prepared_metadata = timestamp_metadata(prepared_metadata, using.as_ref());
+
+        let result_metadata = ResultMetadata::empty();
+
+        Ok((prepared_metadata, result_metadata))
+    }
+
+    fn update(&mut self, update: UpdateQuery, parameters: QueryParameters) -> Result<Plan, Error> {
+        let UpdateQuery {
+            keyspace,
+            table,
+            assignments,
+            r#where,
+            using,
+        } = update;
+        let keyspace = keyspace
+            .or(self.use_keyspace.clone())
+            .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
+
+        let schema = self.table(&keyspace, &table)?;
+        check_assignments(schema, &assignments)?;
+        self.check_primary_key(schema, r#where.statements.iter().map(|(column, _)| column))?;
+
+        let mut data = parameters.data;
+        let timestamp = write_timestamp(using, 0, &mut data, parameters.default_timestamp)?;
+        let values = data_reader::DataPayload::read(
+            schema,
+            assignments.into_iter().chain(r#where.statements),
+            data,
+        )?;
+
+        let partition_key = values.get_partition_key()?;
+        let clustering_key = values.get_clustering_key()?;
+
+        let values = values.into_cells();
+
+        Ok(Plan::Update(UpdateNode {
+            keyspace,
+            table,
+            partition_key,
+            clustering_key,
+            values,
+            timestamp,
+        }))
+    }
+
+    fn prepare_update(
+        &mut self,
+        update: UpdateQuery,
+    ) -> Result<(PreparedMetadata, ResultMetadata), Error> {
+        let UpdateQuery {
+            keyspace,
+            table,
+            assignments,
+            r#where,
+            using,
+        } = update;
+        let keyspace = keyspace
+            .or(self.use_keyspace.clone())
+            .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
+
+        let schema = self.table(&keyspace, &table)?;
+        check_assignments(schema, &assignments)?;
+        self.check_primary_key(schema, r#where.statements.iter().map(|(column, _)| column))?;
 
         let prepared_metadata = prepared_metadata(
             &keyspace,
             &table,
             schema,
-            columns.into_iter().zip(values.into_iter()),
+            assignments.into_iter().chain(r#where.statements),
         )?;
+        let prepared_metadata = timestamp_metadata(prepared_metadata, using.as_ref());
 
         let result_metadata = ResultMetadata::empty();
 
@@ -186,33 +358,26 @@ impl<C: Catalog> Planner<C> {
             .keyspace
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
-        let schema = self
-            .catalog
-            .get_table(&keyspace, &delete.table)
-            .ok_or(Error::new(
-                DbError::Invalid,
-                "Keyspace or table does nor exist",
-            ))?;
+        let schema = self.table(&keyspace, &delete.table)?;
 
-        let values = data_reader::DataPayload::read(
+        self.check_primary_key(
             schema,
-            delete.r#where.statements.into_iter(),
-            parameters.data,
+            delete.r#where.statements.iter().map(|(column, _)| column),
         )?;
 
+        let mut data = parameters.data;
+        let timestamp = write_timestamp(delete.using, 0, &mut data, parameters.default_timestamp)?;
+        let values =
+            data_reader::DataPayload::read(schema, delete.r#where.statements.into_iter(), data)?;
+
         let partition_key = values.get_partition_key()?;
         let clustering_key = values
             .get_clustering_key()
             .unwrap_or(ClusteringKeyValue::Empty);
         let mut values = vec![];
         for column in delete.columns {
-            if schema.columns.get(&column).is_none() {
-                return Err(Error::new(
-                    DbError::Invalid,
-                    format!("Unknown column `{column}`"),
-                ));
-            }
-            values.push((column, CqlValue::Empty));
+            data_reader::schema_column(schema, &column)?;
+            values.push((column, None));
         }
 
         Ok(Plan::Insert(InsertNode {
@@ -221,6 +386,7 @@ impl<C: Catalog> Planner<C> {
             partition_key,
             clustering_key,
             values,
+            timestamp,
         }))
     }
 
@@ -234,19 +400,12 @@ impl<C: Catalog> Planner<C> {
             .keyspace
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
-        let schema = self
-            .catalog
-            .get_table(&keyspace, &delete.table)
-            .ok_or(Error::new(
-                DbError::Invalid,
-                "Keyspace or table does nor exist",
-            ))?;
+        let schema = self.table(&keyspace, &delete.table)?;
 
-        let values = data_reader::DataPayload::read(
-            schema,
-            delete.r#where.statements.into_iter(),
-            parameters.data,
-        )?;
+        let mut data = parameters.data;
+        let timestamp = write_timestamp(delete.using, 0, &mut data, parameters.default_timestamp)?;
+        let values =
+            data_reader::DataPayload::read(schema, delete.r#where.statements.into_iter(), data)?;
 
         let partition_key = values.get_partition_key()?;
         let clustering_key = values
@@ -258,6 +417,7 @@ impl<C: Catalog> Planner<C> {
             table: delete.table,
             partition_key,
             clustering_key,
+            timestamp,
         }))
     }
 
@@ -270,6 +430,7 @@ impl<C: Catalog> Planner<C> {
             keyspace,
             table,
             r#where,
+            using,
             ..
         } = delete;
 
@@ -277,13 +438,11 @@ impl<C: Catalog> Planner<C> {
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
-        let schema = self.catalog.get_table(&keyspace, &table).ok_or(Error::new(
-            DbError::Invalid,
-            "Keyspace or table does nor exist",
-        ))?;
+        let schema = self.table(&keyspace, &table)?;
 
         let prepared_metadata =
             prepared_metadata(&keyspace, &table, schema, r#where.statements.into_iter())?;
+        let prepared_metadata = timestamp_metadata(prepared_metadata, using.as_ref());
 
         let result_metadata = ResultMetadata::empty();
 
@@ -291,12 +450,313 @@ impl<C: Catalog> Planner<C> {
     }
 
     fn create_keyspace(&mut self, create: CreateKeyspaceQuery) -> Result<Plan, Error> {
-        // todo parse replication literal
+        let replication = replication_strategy(&create.keyspace, create.replication)?;
 
         Ok(Plan::AlterSchema(AlterSchema::Keyspace {
             name: create.keyspace,
             ignore_existence: create.ignore_existence,
-            replication: Strategy::LocalStrategy,
+            replication,
+            durable_writes: create.durable_writes.unwrap_or(true),
+        }))
+    }
+
+    fn alter_keyspace(&mut self, alter: AlterKeyspaceQuery) -> Result<Plan, Error> {
+        let AlterKeyspaceQuery {
+            keyspace,
+            replication,
+            durable_writes,
+        } = alter;
+
+        self.check_modifiable(&keyspace)?;
+        if self.catalog.get_keyspace(&keyspace).is_none() {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("Keyspace '{keyspace}' doesn't exist"),
+            ));
+        }
+        let replication = replication
+            .map(|replication| replication_strategy(&keyspace, replication))
+            .transpose()?;
+
+        Ok(Plan::AlterSchema(AlterSchema::AlterKeyspace {
+            name: keyspace,
+            replication,
+            durable_writes,
+        }))
+    }
+
+    fn drop_keyspace(&mut self, drop: DropKeyspaceQuery) -> Result<Plan, Error> {
+        let DropKeyspaceQuery {
+            keyspace,
+            ignore_existence,
+        } = drop;
+
+        self.check_modifiable(&keyspace)?;
+        if !ignore_existence && self.catalog.get_keyspace(&keyspace).is_none() {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("Keyspace '{keyspace}' doesn't exist"),
+            ));
+        }
+
+        Ok(Plan::AlterSchema(AlterSchema::DropKeyspace {
+            name: keyspace,
+            ignore_existence,
+        }))
+    }
+
+    fn drop_table(&mut self, drop: DropTableQuery) -> Result<Plan, Error> {
+        let DropTableQuery {
+            keyspace,
+            table,
+            ignore_existence,
+        } = drop;
+        let keyspace = keyspace
+            .or(self.use_keyspace.clone())
+            .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
+
+        self.check_modifiable(&keyspace)?;
+        if !ignore_existence && self.catalog.get_table(&keyspace, &table).is_none() {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("Table '{keyspace}.{table}' doesn't exist"),
+            ));
+        }
+
+        Ok(Plan::AlterSchema(AlterSchema::DropTable {
+            keyspace,
+            name: table,
+            ignore_existence,
+        }))
+    }
+
+    fn create_type(&mut self, create: CreateTypeQuery) -> Result<Plan, Error> {
+        let CreateTypeQuery {
+            keyspace,
+            name,
+            ignore_existence,
+            columns,
+        } = create;
+        let keyspace = keyspace
+            .or(self.use_keyspace.clone())
+            .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
+
+        self.check_modifiable(&keyspace)?;
+        if self.catalog.get_keyspace(&keyspace).is_none() {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("Keyspace '{keyspace}' doesn't exist"),
+            ));
+        }
+
+        let mut field_types: Vec<(String, ColumnType)> = Vec::with_capacity(columns.len());
+        for (field, ty) in columns {
+            if field_types.iter().any(|(existing, _)| *existing == field) {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!("Duplicate field name {field} in type {name}"),
+                ));
+            }
+            let ty = match parser::types::parse(&ty) {
+                Ok(("", ty)) => column::resolve_field_type(ty, self.catalog, &keyspace)?,
+                _ => {
+                    return Err(Error::new(
+                        DbError::Invalid,
+                        format!("Invalid type {ty} of field {field}"),
+                    ))
+                }
+            };
+            field_types.push((field, ty));
+        }
+
+        Ok(Plan::AlterSchema(AlterSchema::Type {
+            keyspace,
+            name,
+            ignore_existence,
+            field_types,
+        }))
+    }
+
+    fn drop_type(&mut self, drop: DropTypeQuery) -> Result<Plan, Error> {
+        let DropTypeQuery {
+            keyspace,
+            name,
+            ignore_existence,
+        } = drop;
+        let keyspace = keyspace
+            .or(self.use_keyspace.clone())
+            .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
+
+        self.check_modifiable(&keyspace)?;
+        let ks = self.catalog.get_keyspace(&keyspace);
+        let exists = ks.is_some_and(|ks| ks.user_defined_types.contains_key(&name));
+        if !ignore_existence && !exists {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("Type '{keyspace}.{name}' doesn't exist"),
+            ));
+        }
+
+        let users = ks
+            .into_iter()
+            .flat_map(|ks| ks.tables.values())
+            .filter(|table| {
+                table
+                    .schema
+                    .columns
+                    .values()
+                    .any(|column| references_type(&column.ty, &keyspace, &name))
+            })
+            .map(|table| table.name.as_str())
+            .collect::<Vec<_>>();
+        if !users.is_empty() {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!(
+                    "Cannot drop user type '{keyspace}.{name}' as it is still used by tables {}",
+                    users.join(", ")
+                ),
+            ));
+        }
+
+        Ok(Plan::AlterSchema(AlterSchema::DropType {
+            keyspace,
+            name,
+            ignore_existence,
+        }))
+    }
+
+    fn check_modifiable(&self, keyspace: &str) -> Result<(), Error> {
+        if is_system_keyspace(keyspace) {
+            return Err(Error::new(
+                DbError::Unauthorized,
+                format!("{keyspace} keyspace is not user-modifiable."),
+            ));
+        }
+        Ok(())
+    }
+
+    fn describe(&mut self, describe: DescribeQuery) -> Result<Plan, Error> {
+        let keyspace = |keyspace: Option<String>| {
+            keyspace
+                .or(self.use_keyspace.clone())
+                .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))
+        };
+
+        let node = match describe {
+            DescribeQuery::Keyspaces => DescribeNode::Keyspaces,
+            DescribeQuery::Tables => DescribeNode::Tables,
+            DescribeQuery::Schema => DescribeNode::Schema,
+            DescribeQuery::Keyspace(name) => DescribeNode::Keyspace(keyspace(name)?),
+            DescribeQuery::Table {
+                keyspace: name,
+                table,
+            } => DescribeNode::Table {
+                keyspace: keyspace(name)?,
+                table,
+            },
+        };
+
+        Ok(Plan::Describe(node))
+    }
+
+    fn create_role(
+        &mut self,
+        create: CreateRoleQuery,
+        parameters: QueryParameters,
+    ) -> Result<Plan, Error> {
+        Ok(Plan::Auth(AuthNode::CreateRole {
+            role: create.role,
+            ignore_existence: create.ignore_existence,
+            login: create.login,
+            superuser: create.superuser,
+            salted_hash: create.password.as_deref().map(auth::hash_password),
+            timestamp: parameters
+                .default_timestamp
+                .unwrap_or_else(storage::write_timestamp),
+        }))
+    }
+
+    fn drop_role(
+        &mut self,
+        drop: DropRoleQuery,
+        parameters: QueryParameters,
+    ) -> Result<Plan, Error> {
+        Ok(Plan::Auth(AuthNode::DropRole {
+            role: drop.role,
+            ignore_existence: drop.ignore_existence,
+            timestamp: parameters
+                .default_timestamp
+                .unwrap_or_else(storage::write_timestamp),
+        }))
+    }
+
+    /// `GRANT` and `REVOKE` of permissions on an existing resource, `ALL PERMISSIONS` are the ones it supports
+    fn permissions(
+        &mut self,
+        query: PermissionQuery,
+        grant: bool,
+        parameters: QueryParameters,
+    ) -> Result<Plan, Error> {
+        let PermissionQuery {
+            permission,
+            resource,
+            role,
+        } = query;
+        let resource = match resource {
+            Resource::Table { keyspace, table } => Resource::Table {
+                keyspace: Some(
+                    keyspace
+                        .or(self.use_keyspace.clone())
+                        .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?,
+                ),
+                table,
+            },
+            resource => resource,
+        };
+        let exists = match &resource {
+            Resource::AllKeyspaces => true,
+            Resource::Keyspace(keyspace) => self.catalog.get_keyspace(keyspace).is_some(),
+            Resource::Table { keyspace, table } => self
+                .catalog
+                .get_table(keyspace.as_deref().unwrap_or_default(), table)
+                .is_some(),
+        };
+        if !exists {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!("Resource {} doesn't exist", resource.label()),
+            ));
+        }
+
+        let applicable = resource.applicable_permissions();
+        let permissions =
+            match permission {
+                Permission::All => applicable,
+                permission if applicable.contains(&permission) => [permission].into(),
+                _ => return Err(Error::new(
+                    DbError::Invalid,
+                    "Resource type DataResource does not support any of the requested permissions",
+                )),
+            };
+        let timestamp = parameters
+            .default_timestamp
+            .unwrap_or_else(storage::write_timestamp);
+        let resource = resource.name();
+
+        Ok(Plan::Auth(match grant {
+            true => AuthNode::Grant {
+                role,
+                resource,
+                permissions,
+                timestamp,
+            },
+            false => AuthNode::Revoke {
+                role,
+                resource,
+                permissions,
+                timestamp,
+            },
         }))
     }
 
@@ -314,15 +774,39 @@ impl<C: Catalog> Planner<C> {
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
+        let columns = columns
+            .into_iter()
+            .map(|(name, ty)| Ok((name, column::resolve_type(ty, self.catalog, &keyspace)?)))
+            .collect::<Result<_, Error>>()?;
+
         Ok(Plan::AlterSchema(AlterSchema::Table {
             keyspace,
             name: table,
             ignore_existence,
-            schema: create_table_schema(columns, partition_keys, clustering_keys),
-            options,
+            schema: create_table_schema(columns, partition_keys, clustering_keys, &options)?,
+            options: Box::new(table_options(&options)?),
         }))
     }
 
+    /// Restrictions without the whole partition key can only be served by scanning every partition
+    fn is_filtered_scan(&self, select: &SelectQuery) -> bool {
+        let Some(keyspace) = select.keyspace.as_ref().or(self.use_keyspace.as_ref()) else {
+            return false;
+        };
+        let restricted = |key: &String| {
+            select
+                .r#where
+                .statements
+                .iter()
+                .any(|(column, _)| column == key)
+        };
+
+        select.allow_filtering
+            && self
+                .table(keyspace, &select.table)
+                .is_ok_and(|schema| !schema.partition_key.into_iter().all(restricted))
+    }
+
     fn select(&mut self, select: SelectQuery, parameters: QueryParameters) -> Result<Plan, Error> {
         let SelectQuery {
             keyspace,
@@ -330,6 +814,7 @@ impl<C: Catalog> Planner<C> {
             columns,
             r#where,
             limit,
+            allow_filtering,
             ..
         } = select;
 
@@ -337,31 +822,51 @@ impl<C: Catalog> Planner<C> {
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
-        let schema = self.catalog.get_table(&keyspace, &table).ok_or(Error::new(
-            DbError::Invalid,
-            "Keyspace or table does nor exist",
-        ))?;
+        let schema = self.table(&keyspace, &table)?;
+        check_aliases(schema, &columns, &r#where)?;
 
-        let values = data_reader::DataPayload::read(
-            schema,
-            r#where.statements.into_iter(),
-            parameters.data,
-        )?;
+        if !r#where.token.is_empty() {
+            return Err(Error::new(
+                DbError::Invalid,
+                "Token restrictions can't be combined with other restrictions",
+            ));
+        }
+
+        let mut data = parameters.data;
+        let equalities = take_bound(&mut data, r#where.statements.iter().map(|(_, value)| value));
+        let slices = take_bound(
+            &mut data,
+            r#where.clustering.iter().flat_map(|it| &it.values),
+        );
+        let values =
+            data_reader::DataPayload::read(schema, r#where.statements.into_iter(), equalities)?;
 
         let partition_key = values.get_partition_key()?;
-        let clustering_key = values.get_clustering_key_range()?;
+        let clustering_key = values.get_clustering_key_slice(r#where.clustering, slices)?;
+        let predicate = values
+            .predicate(|column| matches!(column.kind, ColumnKind::Regular | ColumnKind::Static))?;
+        if !predicate.is_empty() && !allow_filtering {
+            return Err(Error::new(DbError::Invalid, FILTERING_REQUIRED));
+        }
+        // `LIKE` is served by an index in cassandra, so it doesn't need `ALLOW FILTERING`
+        let predicate = data_reader::like_predicate(schema, r#where.like, data, predicate)?;
 
+        let count = matches!(columns, SelectExpression::Count { .. });
         let metadata = metadata(&keyspace, &table, schema, &columns)?;
         let selector = columns_selector(schema, columns)?;
-        let clustering_range = match parameters.paging_state {
+        let (clustering_range, clustering_start) = match parameters.paging_state {
             Some(PagingState {
                 row_mark: Some(ref row_mark),
                 ..
             }) => {
                 let marker = decode_row_marker(row_mark, &schema.clustering_key_column())?;
-                clustering_key.from(marker)
+                if schema.is_clustering_ascending() {
+                    (clustering_key.from(marker), None)
+                } else {
+                    (clustering_key, Some(marker))
+                }
             }
-            _ => clustering_key,
+            _ => (clustering_key, None),
         };
 
         let limit = match (limit, parameters.paging_state) {
@@ -376,18 +881,15 @@ impl<C: Catalog> Planner<C> {
             partition_key,
             selector,
             clustering_range,
+            predicate,
+            clustering_start,
+            clustering_order: schema.clustering_order.clone(),
             metadata,
             limit,
-            result_page_size: parameters.result_page_size.unwrap_or(100),
+            result_page_size: parameters.result_page_size.unwrap_or(usize::MAX),
+            page_end: None,
         };
-        if select.json {
-            Ok(Plan::Aggregate {
-                source: Box::new(Plan::Select(node)),
-                aggregate: Aggregate::Json,
-            })
-        } else {
-            Ok(Plan::Select(node))
-        }
+        Ok(aggregate(Plan::Select(node), count, select.json))
     }
 
     fn prepare_select(
@@ -406,14 +908,36 @@ impl<C: Catalog> Planner<C> {
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
-        let schema = self.catalog.get_table(&keyspace, &table).ok_or(Error::new(
-            DbError::Invalid,
-            "Keyspace or table does nor exist",
-        ))?;
+        let schema = self.table(&keyspace, &table)?;
+        check_aliases(schema, &columns, &r#where)?;
 
         let metadata = metadata(&keyspace, &table, schema, &columns)?;
-        let prepared_metadata =
-            prepared_metadata(&keyspace, &table, schema, r#where.statements.into_iter())?;
+        let slices = r#where
+            .clustering
+            .into_iter()
+            .flat_map(|it| it.columns.into_iter().zip(it.values));
+        let mut prepared_metadata = prepared_metadata(
+            &keyspace,
+            &table,
+            schema,
+            r#where.statements.into_iter().chain(slices),
+        )?;
+        for relation in r#where.token {
+            if let QueryValue::Blankslate = relation.value {
+                prepared_metadata.col_specs.push(ColumnSpec::new(
+                    "partition key token".to_owned(),
+                    ColumnType::BigInt,
+                ));
+            }
+        }
+        for (column, value) in r#where.like {
+            if let QueryValue::Blankslate = value {
+                let spec = data_reader::schema_column(schema, &column)?;
+                prepared_metadata
+                    .col_specs
+                    .push(ColumnSpec::new(column, spec.ty.clone()));
+            }
+        }
 
         Ok((prepared_metadata, metadata))
     }
@@ -424,6 +948,7 @@ impl<C: Catalog> Planner<C> {
             table,
             columns,
             limit,
+            r#where,
             ..
         } = select;
 
@@ -431,23 +956,54 @@ impl<C: Catalog> Planner<C> {
             .or(self.use_keyspace.clone())
             .ok_or(Error::new(DbError::Invalid, "Keyspace is not specified"))?;
 
-        let schema = self.catalog.get_table(&keyspace, &table).ok_or(Error::new(
-            DbError::Invalid,
-            "Keyspace or table does nor exist",
-        ))?;
+        let schema = self.table(&keyspace, &table)?;
+        check_aliases(schema, &columns, &r#where)?;
+
+        if !r#where.clustering.is_empty() {
+            return Err(Error::new(
+                DbError::Invalid,
+                "Slices of clustering columns can only be selected within a partition",
+            ));
+        }
 
+        let count = matches!(columns, SelectExpression::Count { .. });
         let metadata = metadata(&keyspace, &table, schema, &columns)?;
         let selector = columns_selector(schema, columns)?;
+        let mut data = parameters.data;
+        let equalities = take_bound(&mut data, r#where.statements.iter().map(|(_, value)| value));
+        let tokens = take_bound(&mut data, r#where.token.iter().map(|it| &it.value));
+        let (token_range, predicate) = match (r#where.statements.is_empty(), r#where.token) {
+            (true, token) => (token_range(schema, token, tokens)?, Predicate::new()),
+            (false, token) if token.is_empty() => {
+                let values = data_reader::DataPayload::read(
+                    schema,
+                    r#where.statements.into_iter(),
+                    equalities,
+                )?;
+                (
+                    token_range(schema, vec![], vec![])?,
+                    values.predicate(|_| true)?,
+                )
+            }
+            (false, _) => {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    "Token restrictions can't be combined with other restrictions",
+                ))
+            }
+        };
+
+        let predicate = data_reader::like_predicate(schema, r#where.like, data, predicate)?;
 
         let clustering_key_start = match parameters.paging_state {
             Some(PagingState {
                 row_mark: Some(ref row_mark),
                 ..
-            }) => {
-                let marker = decode_row_marker(row_mark, &schema.clustering_key_column())?;
-                ClusteringKeyValueRange::From(marker)
-            }
-            _ => ClusteringKeyValueRange::Full,
+            }) => Some(decode_row_marker(
+                row_mark,
+                &schema.clustering_key_column(),
+            )?),
+            _ => None,
         };
         let partition_range = match parameters.paging_state {
             Some(PagingState {
@@ -456,9 +1012,9 @@ impl<C: Catalog> Planner<C> {
             }) => {
                 let partition =
                     decode_partition_start(partition_key, &schema.partition_key_column())?;
-                (partition..).into()
+                token_range.from_key(partition)
             }
-            _ => (..).into(),
+            _ => token_range,
         };
 
         let limit = match (limit, parameters.paging_state) {
@@ -473,19 +1029,34 @@ impl<C: Catalog> Planner<C> {
             metadata,
             selector,
             partition_range,
+            predicate,
             clustering_key_start,
+            clustering_order: schema.clustering_order.clone(),
             limit,
-            result_page_size: parameters.result_page_size.unwrap_or(500),
+            // requests without a page size get every row in a single page
+            result_page_size: parameters.result_page_size.unwrap_or(usize::MAX),
+            page_end: None,
         };
 
-        if select.json {
-            Ok(Plan::Aggregate {
-                source: Box::new(Plan::Scan(node)),
-                aggregate: Aggregate::Json,
-            })
-        } else {
-            Ok(Plan::Scan(node))
-        }
+        Ok(aggregate(Plan::Scan(node), count, select.json))
+    }
+}
+
+/// Counting happens before the rows are rendered as json: `SELECT JSON COUNT(*)` gives `{"count": 1}`
+fn aggregate(plan: Plan, count: bool, json: bool) -> Plan {
+    let plan = match count {
+        true => Plan::Aggregate {
+            source: Box::new(plan),
+            aggregate: Aggregate::Count,
+        },
+        false => plan,
+    };
+    match json {
+        true => Plan::Aggregate {
+            source: Box::new(plan),
+            aggregate: Aggregate::Json,
+        },
+        false => plan,
     }
 }
 
@@ -494,7 +1065,7 @@ fn metadata(
     table: &str,
     schema: &TableSchema,
     columns: &SelectExpression,
-) -> Result<ResultMetadata, DbError> {
+) -> Result<ResultMetadata, Error> {
     let global_spec = Some(TableSpec {
         ks_name: keyspace.to_owned(),
         table_name: table.to_owned(),
@@ -509,25 +1080,78 @@ fn metadata(
             .iter()
             .map(|it| resolve_column_spec(schema, it))
             .collect::<Result<Vec<_>, _>>()?,
+        SelectExpression::Count { alias } => vec![ColumnSpec::new(
+            alias.clone().unwrap_or_else(|| "count".to_owned()),
+            ColumnType::BigInt,
+        )],
     };
 
     Ok(ResultMetadata {
         global_spec,
         paging_state: None,
         col_specs,
+        no_metadata: false,
     })
 }
 
+/// Aliases only name the results, relations have to use the columns of the table
+fn check_aliases(
+    schema: &TableSchema,
+    columns: &SelectExpression,
+    r#where: &WhereClosure,
+) -> Result<(), Error> {
+    let SelectExpression::Columns(columns) = columns else {
+        return Ok(());
+    };
+    let is_alias = |name: &String| {
+        !schema.columns.contains_key(name)
+            && columns.iter().any(|it| it.alias.as_ref() == Some(name))
+    };
+
+    let relation = r#where
+        .statements
+        .iter()
+        .find(|(column, _)| is_alias(column))
+        .map(|(column, value)| format!("{column} = {value}"))
+        .or_else(|| {
+            let (column, value) = r#where.like.iter().find(|(column, _)| is_alias(column))?;
+            Some(format!("{column} LIKE {value}"))
+        })
+        .or_else(|| {
+            let relation = r#where
+                .clustering
+                .iter()
+                .find(|it| it.columns.iter().any(is_alias))?;
+            Some(relation.to_string())
+        });
+
+    match relation {
+        Some(relation) => Err(Error::new(
+            DbError::Invalid,
+            format!("Aliases aren't allowed in the where clause ('{relation}')"),
+        )),
+        None => Ok(()),
+    }
+}
+
 #[instrument(level = Level::TRACE, skip(schema), err)]
 fn resolve_column_spec(
     schema: &TableSchema,
     selector: &query::ColumnSelector,
-) -> Result<ColumnSpec, DbError> {
-    let Some(column) = schema.columns.get(&selector.name) else {
-        // Unknown column
-        return Err(DbError::Invalid);
+) -> Result<ColumnSpec, Error> {
+    let column = data_reader::schema_column(schema, &selector.name)?;
+    // results of functions are named after the call, as cassandra does: `system.tojson(records)`
+    let name = match (&selector.alias, selector.function) {
+        (Some(alias), _) => alias.clone(),
+        (None, Some(function)) => {
+            format!(
+                "system.{}({})",
+                function.to_string().to_lowercase(),
+                selector.name
+            )
+        }
+        (None, None) => selector.name.clone(),
     };
-    let name = selector.alias.as_ref().unwrap_or(&selector.name).clone();
     let ty = selector
         .function
         .map(|it| it.return_type(&column.ty))
@@ -546,29 +1170,33 @@ fn prepared_metadata(
     let mut pk_indexes = vec![];
     let mut col_specs = vec![];
 
-    for (seq, (column, value)) in r#where.enumerate() {
-        match value {
-            QueryValue::Blankslate => {
-                if let Some(index) = schema.partition_key.into_iter().position(|p| p == &column) {
-                    pk_indexes.push(PartitionKeyIndex {
-                        index: seq as _,
-                        sequence: index as _,
-                    });
-                }
-            }
-            QueryValue::Literal(_) => {}
+    for (column, value) in r#where {
+        let column_spec = data_reader::schema_column(schema, &column)?;
+        // literals are part of the statement, only bind markers are described
+        if !value.is_bind_marker() {
+            continue;
         }
 
-        let Some(column_spec) = schema.columns.get(&column) else {
-            return Err(Error::new(
-                DbError::Invalid,
-                format!("unknown column `{column}`"),
-            ));
-        };
+        // `fromJson(?)` is bound to a json string, which drivers can't route by
+        if let QueryValue::FromJson(_) = value {
+            col_specs.push(ColumnSpec::new(column, ColumnType::Text));
+            continue;
+        }
 
+        if let Some(index) = schema.partition_key.into_iter().position(|p| p == &column) {
+            pk_indexes.push(PartitionKeyIndex {
+                index: col_specs.len() as _,
+                sequence: index as _,
+            });
+        }
         col_specs.push(ColumnSpec::new(column, column_spec.ty.clone()));
     }
 
+    // drivers can only route a statement when they have every partition key component
+    if pk_indexes.len() != schema.partition_key.count() {
+        pk_indexes.clear();
+    }
+
     Ok(PreparedMetadata {
         pk_indexes,
         global_spec: Some(TableSpec {
@@ -579,14 +1207,187 @@ fn prepared_metadata(
     })
 }
 
+/// Primary key identifies the updated row, so it can only be restricted in `WHERE`
+fn check_assignments(
+    schema: &TableSchema,
+    assignments: &[(String, QueryValue)],
+) -> Result<(), Error> {
+    match assignments
+        .iter()
+        .find(|(column, _)| data_reader::is_primary_key(schema, column))
+    {
+        Some((column, _)) => Err(Error::new(
+            DbError::Invalid,
+            format!("PRIMARY KEY part {column} found in SET part"),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Takes the values bound to the markers among `values` off the front of `data`
+fn take_bound<'a, 'v>(
+    data: &mut Vec<FrameValue<'a>>,
+    values: impl IntoIterator<Item = &'v QueryValue>,
+) -> Vec<FrameValue<'a>> {
+    let markers = values.into_iter().filter(|it| it.is_bind_marker()).count();
+
+    data.drain(..markers.min(data.len())).collect()
+}
+
+fn bind_markers(values: &[QueryValue]) -> usize {
+    values.iter().filter(|it| it.is_bind_marker()).count()
+}
+
+/// Resolves the timestamp of a write: `USING TIMESTAMP` takes precedence over
+/// the default timestamp of the request, and the server assigns one when neither is set.
+///
+/// Bound `USING TIMESTAMP` value is taken out of `data`, so the rest can be read as usual.
+fn write_timestamp(
+    using: Option<UsingTimestamp>,
+    bind_markers: usize,
+    data: &mut Vec<FrameValue<'_>>,
+    default_timestamp: Option<i64>,
+) -> Result<i64, Error> {
+    let timestamp = match using {
+        None => None,
+        Some(UsingTimestamp {
+            value: QueryValue::Literal(literal),
+            ..
+        }) => Some(map_lit(&ColumnType::BigInt, literal)?),
+        Some(UsingTimestamp {
+            value: QueryValue::Blankslate,
+            leading,
+        }) => {
+            let index = if leading { 0 } else { bind_markers };
+            match (index < data.len()).then(|| data.remove(index)) {
+                Some(FrameValue::Some(value)) => Some(data_reader::read_bound_value(
+                    "USING TIMESTAMP",
+                    value,
+                    &ColumnType::BigInt,
+                )?),
+                // unset timestamp is the same as no `USING TIMESTAMP` at all
+                Some(FrameValue::NotSet) => None,
+                _ => {
+                    return Err(Error::new(
+                        DbError::Invalid,
+                        "Invalid null value of timestamp",
+                    ))
+                }
+            }
+        }
+        Some(UsingTimestamp {
+            value: QueryValue::FromJson(_),
+            ..
+        }) => return Err(Error::new(DbError::Invalid, "Timestamp must be a bigint")),
+    };
+
+    match timestamp {
+        Some(CqlValue::BigInt(timestamp)) => Ok(timestamp),
+        Some(_) => Err(Error::new(DbError::Invalid, "Timestamp must be a bigint")),
+        None => Ok(default_timestamp.unwrap_or_else(storage::write_timestamp)),
+    }
+}
+
+fn timestamp_metadata(
+    mut metadata: PreparedMetadata,
+    using: Option<&UsingTimestamp>,
+) -> PreparedMetadata {
+    let Some(UsingTimestamp {
+        value: QueryValue::Blankslate,
+        leading,
+    }) = using
+    else {
+        return metadata;
+    };
+
+    let spec = ColumnSpec::new("[timestamp]".to_owned(), ColumnType::BigInt);
+    if *leading {
+        metadata.col_specs.insert(0, spec);
+        for pk in &mut metadata.pk_indexes {
+            pk.index += 1;
+        }
+    } else {
+        metadata.col_specs.push(spec);
+    }
+
+    metadata
+}
+
+fn token_range(
+    schema: &TableSchema,
+    relations: Vec<TokenRelation>,
+    data: Vec<FrameValue<'_>>,
+) -> Result<PartitionKeyValueRange, Error> {
+    let mut data = data.into_iter();
+    let (mut start, mut end) = (Bound::Unbounded, Bound::Unbounded);
+
+    for TokenRelation {
+        columns,
+        operator,
+        value,
+    } in relations
+    {
+        if !columns.iter().eq(&schema.partition_key) {
+            return Err(Error::new(
+                DbError::Invalid,
+                "The token function arguments must be in the partition key order",
+            ));
+        }
+
+        let token = match value {
+            QueryValue::Literal(literal) => map_lit(&ColumnType::BigInt, literal)?,
+            QueryValue::Blankslate => match data.next() {
+                Some(FrameValue::Some(value)) => {
+                    data_reader::read_bound_value("token", value, &ColumnType::BigInt)?
+                }
+                _ => {
+                    return Err(Error::new(
+                        DbError::Invalid,
+                        "Invalid null or unset value for partition key token",
+                    ))
+                }
+            },
+            QueryValue::FromJson(_) => {
+                return Err(Error::new(DbError::Invalid, "Token must be a bigint"))
+            }
+        };
+        let CqlValue::BigInt(token) = token else {
+            return Err(Error::new(DbError::Invalid, "Token must be a bigint"));
+        };
+
+        let bounds = match operator {
+            Operator::Eq => vec![
+                (&mut start, Bound::Included(token)),
+                (&mut end, Bound::Included(token)),
+            ],
+            Operator::Gt => vec![(&mut start, Bound::Excluded(token))],
+            Operator::Ge => vec![(&mut start, Bound::Included(token))],
+            Operator::Lt => vec![(&mut end, Bound::Excluded(token))],
+            Operator::Le => vec![(&mut end, Bound::Included(token))],
+        };
+        for (bound, value) in bounds {
+            if !matches!(bound, Bound::Unbounded) {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    "More than one restriction was found for the token bound",
+                ));
+            }
+            *bound = value;
+        }
+    }
+
+    Ok(PartitionKeyValueRange::tokens(start, end))
+}
+
 fn create_table_schema(
-    columns: Vec<(String, PreCqlType)>,
+    columns: Vec<(String, ColumnType)>,
     partition_keys: Vec<String>,
     clustering_keys: Vec<String>,
-) -> TableSchema {
+    options: &[(String, Literal)],
+) -> Result<TableSchema, Error> {
     let mut columns_res = Vec::new();
 
-    for (column_name, column_type) in columns {
+    for (column_name, ty) in columns {
         let kind = if partition_keys.contains(&column_name) {
             ColumnKind::PartitionKey
         } else if clustering_keys.contains(&column_name) {
@@ -594,17 +1395,248 @@ fn create_table_schema(
         } else {
             ColumnKind::Regular
         };
-        let ty = column::map_pre_type(column_type);
-
         columns_res.push((column_name, Column { ty, kind }));
     }
 
-    TableSchema {
+    let clustering_order = clustering_order(&clustering_keys, options)?;
+
+    Ok(TableSchema {
         columns: columns_res.into_iter().collect(),
         partition_key: PrimaryKey::from_definition(partition_keys),
         clustering_key: PrimaryKey::from_definition(clustering_keys),
         partitioner: None,
+        clustering_order,
+    })
+}
+
+/// Whether values of the type contain the user type, at any depth
+fn references_type(ty: &ColumnType, keyspace: &str, name: &str) -> bool {
+    match ty {
+        ColumnType::UserDefinedType {
+            type_name,
+            keyspace: ks,
+            field_types,
+        } => {
+            (type_name == name && ks == keyspace)
+                || field_types
+                    .iter()
+                    .any(|(_, ty)| references_type(ty, keyspace, name))
+        }
+        ColumnType::List(item) | ColumnType::Set(item) => references_type(item, keyspace, name),
+        ColumnType::Map(key, value) => {
+            references_type(key, keyspace, name) || references_type(value, keyspace, name)
+        }
+        ColumnType::Tuple(items) => items.iter().any(|ty| references_type(ty, keyspace, name)),
+        _ => false,
+    }
+}
+
+/// Options kassandra has no use for, like `caching`, are accepted and dropped
+fn table_options(options: &[(String, Literal)]) -> Result<TableOptions, Error> {
+    let invalid = |name: &str, value: &Literal| {
+        Error::new(
+            DbError::ConfigError,
+            format!("Invalid value {value} for property '{name}'"),
+        )
+    };
+    let seconds = |name: &str, value: &Literal, max: i64| match value {
+        Literal::Number(seconds) if *seconds < 0 => Err(Error::new(
+            DbError::ConfigError,
+            format!("{name} must be greater than or equal to 0 (got {seconds})"),
+        )),
+        Literal::Number(seconds) if *seconds > max => Err(Error::new(
+            DbError::ConfigError,
+            format!("{name} must be less than or equal to {max} (got {seconds})"),
+        )),
+        Literal::Number(seconds) => Ok(*seconds as i32),
+        other => Err(invalid(name, other)),
+    };
+    let class_options = |name: &str, value: &Literal, prefix: &str| {
+        let Literal::Map(entries) = value else {
+            return Err(invalid(name, value));
+        };
+        let mut entries = entries
+            .iter()
+            .map(|(key, value)| match value {
+                Literal::String(value) => (key.clone(), value.clone()),
+                other => (key.clone(), other.to_string()),
+            })
+            .collect::<BTreeMap<_, _>>();
+        if let Some(class) = entries.get_mut("class") {
+            if !class.contains('.') {
+                *class = format!("{prefix}{class}");
+            }
+        }
+
+        Ok(entries)
+    };
+
+    let mut table_options = TableOptions::default();
+    for (name, value) in options {
+        match name.as_str() {
+            "bloom_filter_fp_chance" => {
+                let chance = match value {
+                    Literal::Float(chance) => *chance,
+                    Literal::Number(chance) => *chance as f64,
+                    other => return Err(invalid(name, other)),
+                };
+                if chance <= 0.0 || chance > 1.0 {
+                    return Err(Error::new(
+                        DbError::ConfigError,
+                        format!("bloom_filter_fp_chance must be larger than 0 and less than or equal to 1.0 (got {chance})"),
+                    ));
+                }
+                table_options.bloom_filter_fp_chance = chance;
+            }
+            "comment" => match value {
+                Literal::String(comment) => table_options.comment.clone_from(comment),
+                other => return Err(invalid(name, other)),
+            },
+            "default_time_to_live" => {
+                table_options.default_time_to_live = seconds(name, value, MAX_TTL)?;
+            }
+            "gc_grace_seconds" => {
+                table_options.gc_grace_seconds = seconds(name, value, i32::MAX.into())?;
+            }
+            "compaction" => {
+                let compaction = class_options(name, value, TableOptions::COMPACTION_PREFIX)?;
+                if !compaction.contains_key("class") {
+                    return Err(Error::new(
+                        DbError::ConfigError,
+                        "Missing sub-option 'class' for the 'compaction' option.",
+                    ));
+                }
+                table_options.compaction = compaction;
+            }
+            "compression" => {
+                table_options.compression =
+                    class_options(name, value, TableOptions::COMPRESSION_PREFIX)?;
+            }
+            _ => {}
+        }
     }
+
+    Ok(table_options)
+}
+
+/// Validates replication options the way cassandra does,
+/// factors of `NetworkTopologyStrategy` are given per datacenter or with `replication_factor` for all of them.
+fn replication_strategy(keyspace: &str, replication: Literal) -> Result<Strategy, Error> {
+    let config_error = |message: String| Error::new(DbError::ConfigError, message);
+
+    let Literal::Map(mut options) = replication else {
+        return Err(config_error(format!(
+            "Replication of keyspace {keyspace} must be a map, found {replication}"
+        )));
+    };
+    let class = match options.remove("class") {
+        Some(Literal::String(class)) => class,
+        _ => {
+            return Err(config_error(
+                "Missing replication strategy class".to_owned(),
+            ))
+        }
+    };
+    let class = class
+        .strip_prefix(Strategy::CLASS_PREFIX)
+        .unwrap_or(&class)
+        .to_owned();
+
+    let factor = |value: Literal| {
+        let factor = match &value {
+            Literal::Number(factor) => Some(*factor),
+            Literal::String(factor) => factor.parse().ok(),
+            _ => None,
+        };
+        match factor {
+            Some(factor) if factor >= 0 => Ok(factor as usize),
+            Some(factor) => Err(config_error(format!(
+                "Replication factor must be non-negative; found {factor}"
+            ))),
+            None => Err(config_error(format!(
+                "Replication factor must be numeric; found {value}"
+            ))),
+        }
+    };
+
+    match class.as_str() {
+        "SimpleStrategy" => {
+            let replication_factor = options.remove("replication_factor").ok_or_else(|| {
+                config_error(
+                    "SimpleStrategy requires a replication_factor strategy option.".to_owned(),
+                )
+            })?;
+            if let Some(option) = options.keys().next() {
+                return Err(config_error(format!(
+                    "Unrecognized strategy option {{{option}}} passed to SimpleStrategy for keyspace {keyspace}"
+                )));
+            }
+
+            Ok(Strategy::SimpleStrategy {
+                replication_factor: factor(replication_factor)?,
+            })
+        }
+        "NetworkTopologyStrategy" => {
+            let default = options
+                .remove("replication_factor")
+                .map(factor)
+                .transpose()?;
+            let mut datacenter_repfactors = options
+                .into_iter()
+                .map(|(datacenter, value)| Ok((datacenter, factor(value)?)))
+                .collect::<Result<HashMap<_, _>, Error>>()?;
+            if let Some(default) = default {
+                datacenter_repfactors
+                    .entry(DATACENTER.to_owned())
+                    .or_insert(default);
+            }
+
+            Ok(Strategy::NetworkTopologyStrategy {
+                datacenter_repfactors,
+            })
+        }
+        "LocalStrategy" => Err(config_error(
+            "Unable to use given strategy class: LocalStrategy is reserved for internal use."
+                .to_owned(),
+        )),
+        _ => Ok(Strategy::Other {
+            name: class,
+            data: options
+                .into_iter()
+                .map(|(key, value)| match value {
+                    Literal::String(value) => (key, value),
+                    other => (key, other.to_string()),
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn clustering_order(
+    clustering_keys: &[String],
+    options: &[(String, Literal)],
+) -> Result<Vec<ClusteringOrder>, Error> {
+    let Some((_, Literal::Map(ordering))) = options
+        .iter()
+        .find(|(name, _)| name == "clustering order by")
+    else {
+        return Ok(vec![]);
+    };
+
+    if let Some(column) = ordering.keys().find(|it| !clustering_keys.contains(it)) {
+        return Err(Error::new(
+            DbError::Invalid,
+            format!("Only clustering key columns can be defined in CLUSTERING ORDER directive: {column}"),
+        ));
+    }
+
+    Ok(clustering_keys
+        .iter()
+        .map(|column| match ordering.get(column) {
+            Some(Literal::Bool(false)) => ClusteringOrder::Desc,
+            _ => ClusteringOrder::Asc,
+        })
+        .collect())
 }
 
 #[instrument(level = Level::TRACE, skip(schema), err)]
@@ -635,6 +1667,7 @@ fn columns_selector(
                 })
             })
             .collect::<Result<_, _>>()?,
+        SelectExpression::Count { .. } => vec![],
     }))
 }
 
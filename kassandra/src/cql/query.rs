@@ -3,7 +3,10 @@ use std::fmt;
 use derive_more::{Display, From};
 use serde::{Deserialize, Serialize};
 
-use crate::cql::{functions::CqlFunction, literal::Literal, types::PreCqlType};
+use crate::{
+    cql::{functions::CqlFunction, literal::Literal, types::PreCqlType},
+    frame::request::batch::BatchType,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Display, From)]
 pub enum QueryString {
@@ -12,15 +15,52 @@ pub enum QueryString {
     #[display(fmt = "{}", "_0")]
     Insert(InsertQuery),
     #[display(fmt = "{}", "_0")]
+    Update(UpdateQuery),
+    #[display(fmt = "{}", "_0")]
     Delete(DeleteQuery),
+    #[display(fmt = "{}", "_0")]
+    Batch(BatchQuery),
     #[display(fmt = "USE {}", "keyspace")]
     Use { keyspace: String },
     #[display(fmt = "{}", "_0")]
     CreateKeyspace(CreateKeyspaceQuery),
     #[display(fmt = "{}", "_0")]
+    AlterKeyspace(AlterKeyspaceQuery),
+    #[display(fmt = "{}", "_0")]
     CreateTable(CreateTableQuery),
     #[display(fmt = "{}", "_0")]
     CreateType(CreateTypeQuery),
+    #[display(fmt = "{}", "_0")]
+    DropKeyspace(DropKeyspaceQuery),
+    #[display(fmt = "{}", "_0")]
+    DropTable(DropTableQuery),
+    #[display(fmt = "{}", "_0")]
+    DropType(DropTypeQuery),
+    #[display(fmt = "{}", "_0")]
+    Describe(DescribeQuery),
+    /// Plan of the statement instead of its result
+    #[display(fmt = "EXPLAIN {}", "_0")]
+    Explain(Box<QueryString>),
+    #[display(fmt = "{}", "_0")]
+    CreateRole(CreateRoleQuery),
+    #[display(fmt = "{}", "_0")]
+    DropRole(DropRoleQuery),
+    #[from(ignore)]
+    #[display(
+        fmt = "GRANT {} ON {} TO {}",
+        "_0.permission",
+        "_0.resource",
+        "_0.role"
+    )]
+    Grant(PermissionQuery),
+    #[from(ignore)]
+    #[display(
+        fmt = "REVOKE {} ON {} FROM {}",
+        "_0.permission",
+        "_0.resource",
+        "_0.role"
+    )]
+    Revoke(PermissionQuery),
 }
 
 impl QueryString {
@@ -28,11 +68,86 @@ impl QueryString {
         match self {
             QueryString::Select(_) => "select",
             QueryString::Insert(_) => "insert",
+            QueryString::Update(_) => "update",
             QueryString::Delete(_) => "delete",
+            QueryString::Batch(_) => "batch",
             QueryString::Use { .. } => "use",
             QueryString::CreateKeyspace(_) => "create keyspace",
+            QueryString::AlterKeyspace(_) => "alter keyspace",
             QueryString::CreateTable(_) => "create table",
             QueryString::CreateType(_) => "create type",
+            QueryString::DropKeyspace(_) => "drop keyspace",
+            QueryString::DropTable(_) => "drop table",
+            QueryString::DropType(_) => "drop type",
+            QueryString::Describe(_) => "describe",
+            QueryString::Explain(_) => "explain",
+            QueryString::CreateRole(_) => "create role",
+            QueryString::DropRole(_) => "drop role",
+            QueryString::Grant(_) => "grant",
+            QueryString::Revoke(_) => "revoke",
+        }
+    }
+
+    /// Binds the statement to `keyspace` unless it names one explicitly
+    pub fn qualify(&mut self, keyspace: &str) {
+        let target = match self {
+            QueryString::Select(s) => &mut s.keyspace,
+            QueryString::Insert(s) => &mut s.keyspace,
+            QueryString::Update(s) => &mut s.keyspace,
+            QueryString::Delete(s) => &mut s.keyspace,
+            QueryString::Batch(s) => {
+                for statement in &mut s.statements {
+                    statement.qualify(keyspace);
+                }
+                return;
+            }
+            QueryString::Explain(statement) => return statement.qualify(keyspace),
+            QueryString::CreateTable(s) => &mut s.keyspace,
+            QueryString::CreateType(s) => &mut s.keyspace,
+            QueryString::DropTable(s) => &mut s.keyspace,
+            QueryString::DropType(s) => &mut s.keyspace,
+            QueryString::Describe(
+                DescribeQuery::Keyspace(keyspace) | DescribeQuery::Table { keyspace, .. },
+            ) => keyspace,
+            QueryString::Grant(s) | QueryString::Revoke(s) => match &mut s.resource {
+                Resource::Table { keyspace, .. } => keyspace,
+                Resource::AllKeyspaces | Resource::Keyspace(_) => return,
+            },
+            QueryString::Use { .. }
+            | QueryString::CreateKeyspace(_)
+            | QueryString::AlterKeyspace(_)
+            | QueryString::DropKeyspace(_)
+            | QueryString::Describe(_)
+            | QueryString::CreateRole(_)
+            | QueryString::DropRole(_) => return,
+        };
+        target.get_or_insert_with(|| keyspace.to_owned());
+    }
+
+    /// Keyspace the statement targets, if it names one
+    pub fn keyspace(&self) -> Option<&str> {
+        match self {
+            QueryString::Select(s) => s.keyspace.as_deref(),
+            QueryString::Insert(s) => s.keyspace.as_deref(),
+            QueryString::Update(s) => s.keyspace.as_deref(),
+            QueryString::Delete(s) => s.keyspace.as_deref(),
+            // statements of a batch may target different keyspaces
+            QueryString::Batch(_) => None,
+            QueryString::CreateTable(s) => s.keyspace.as_deref(),
+            QueryString::CreateType(s) => s.keyspace.as_deref(),
+            QueryString::DropTable(s) => s.keyspace.as_deref(),
+            QueryString::DropType(s) => s.keyspace.as_deref(),
+            QueryString::Use { keyspace } => Some(keyspace),
+            QueryString::CreateKeyspace(s) => Some(&s.keyspace),
+            QueryString::AlterKeyspace(s) => Some(&s.keyspace),
+            QueryString::DropKeyspace(s) => Some(&s.keyspace),
+            QueryString::Describe(
+                DescribeQuery::Keyspace(keyspace) | DescribeQuery::Table { keyspace, .. },
+            ) => keyspace.as_deref(),
+            QueryString::Describe(_) => None,
+            QueryString::Explain(statement) => statement.keyspace(),
+            QueryString::Grant(s) | QueryString::Revoke(s) => s.resource.keyspace(),
+            QueryString::CreateRole(_) | QueryString::DropRole(_) => None,
         }
     }
 
@@ -44,27 +159,86 @@ impl QueryString {
             QueryString::Insert(s) => {
                 format!("{}.{}", s.keyspace.as_deref().unwrap_or(""), s.table)
             }
+            QueryString::Update(s) => {
+                format!("{}.{}", s.keyspace.as_deref().unwrap_or(""), s.table)
+            }
             QueryString::Delete(s) => {
                 format!("{}.{}", s.keyspace.as_deref().unwrap_or(""), s.table)
             }
+            QueryString::Batch(s) => s
+                .statements
+                .iter()
+                .map(QueryString::target)
+                .collect::<Vec<_>>()
+                .join(", "),
             QueryString::Use { keyspace, .. } => keyspace.to_string(),
             QueryString::CreateKeyspace(s) => s.keyspace.to_string(),
+            QueryString::AlterKeyspace(s) => s.keyspace.to_string(),
             QueryString::CreateTable(s) => {
                 format!("{}.{}", s.keyspace.as_deref().unwrap_or(""), s.table)
             }
             QueryString::CreateType(s) => s.keyspace.as_deref().unwrap_or("").to_string(),
+            QueryString::DropKeyspace(s) => s.keyspace.to_string(),
+            QueryString::DropTable(s) => {
+                format!("{}.{}", s.keyspace.as_deref().unwrap_or(""), s.table)
+            }
+            QueryString::DropType(s) => s.keyspace.as_deref().unwrap_or("").to_string(),
+            QueryString::Describe(DescribeQuery::Keyspace(keyspace)) => {
+                keyspace.as_deref().unwrap_or("").to_string()
+            }
+            QueryString::Describe(DescribeQuery::Table { keyspace, table }) => {
+                format!("{}.{}", keyspace.as_deref().unwrap_or(""), table)
+            }
+            QueryString::Describe(_) => "".to_string(),
+            QueryString::Explain(statement) => statement.target(),
+            QueryString::CreateRole(s) => s.role.to_string(),
+            QueryString::DropRole(s) => s.role.to_string(),
+            QueryString::Grant(s) | QueryString::Revoke(s) => s.resource.to_string(),
+        }
+    }
+
+    /// Number of `?` markers in the statement, bound values are matched with them in order
+    pub fn bind_markers(&self) -> usize {
+        let markers = |values: &mut dyn Iterator<Item = &QueryValue>| {
+            values.filter(|it| it.is_bind_marker()).count()
+        };
+        let using = |using: &Option<UsingTimestamp>| markers(&mut using.iter().map(|it| &it.value));
+
+        match self {
+            QueryString::Select(s) => s.r#where.bind_markers(),
+            QueryString::Insert(s) => markers(&mut s.values.iter()) + using(&s.using),
+            QueryString::Update(s) => {
+                using(&s.using)
+                    + markers(&mut s.assignments.iter().map(|(_, value)| value))
+                    + s.r#where.bind_markers()
+            }
+            QueryString::Delete(s) => using(&s.using) + s.r#where.bind_markers(),
+            QueryString::Batch(s) => {
+                using(&s.using)
+                    + s.statements
+                        .iter()
+                        .map(QueryString::bind_markers)
+                        .sum::<usize>()
+            }
+            QueryString::Use { .. }
+            | QueryString::CreateKeyspace(_)
+            | QueryString::AlterKeyspace(_)
+            | QueryString::CreateTable(_)
+            | QueryString::CreateType(_)
+            | QueryString::DropKeyspace(_)
+            | QueryString::DropTable(_)
+            | QueryString::DropType(_)
+            | QueryString::Describe(_)
+            | QueryString::CreateRole(_)
+            | QueryString::DropRole(_)
+            | QueryString::Grant(_)
+            | QueryString::Revoke(_) => 0,
+            QueryString::Explain(statement) => statement.bind_markers(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Display)]
-#[display(
-    fmt = "SELECT {} FROM {}.{} WHERE {}",
-    "columns",
-    "keyspace.as_deref().unwrap_or_default()",
-    "table",
-    "r#where"
-)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SelectQuery {
     pub keyspace: Option<String>,
     pub table: String,
@@ -72,29 +246,57 @@ pub struct SelectQuery {
     pub r#where: WhereClosure,
     pub limit: Option<usize>,
     pub json: bool,
+    #[serde(default)]
+    pub allow_filtering: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Display)]
-#[display(
-    fmt = "INSERT INTO {}.{} ({}) VALUES({})",
-    "keyspace.as_deref().unwrap_or_default()",
-    "table",
-    "columns.join(\", \")",
-    "values.iter().map(|it| it.to_string()).collect::<Vec<_>>().join(\", \")"
-)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InsertQuery {
     pub keyspace: Option<String>,
     pub table: String,
     pub columns: Vec<String>,
     pub values: Vec<QueryValue>,
+    #[serde(default)]
+    pub using: Option<UsingTimestamp>,
+    /// `INSERT INTO t JSON '{..}'`, the document is the only value and there are no columns
+    #[serde(default)]
+    pub json: Option<JsonDefault>,
+}
+
+/// Value of the columns missing from the document of `INSERT .. JSON`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum JsonDefault {
+    #[default]
+    #[display(fmt = "NULL")]
+    Null,
+    #[display(fmt = "UNSET")]
+    Unset,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Display)]
 #[display(
-    fmt = "DELETE {} FROM {}.{} WHERE {}",
-    "columns.join(\", \")",
-    "keyspace.as_deref().unwrap_or_default()",
-    "table",
+    fmt = "UPDATE {}{} SET {} WHERE {}",
+    "qualified(keyspace, table)",
+    "using.as_ref().map(|it| format!(\" {it}\")).unwrap_or_default()",
+    "assignments.iter().map(|(column, value)| format!(\"{column} = {value}\")).collect::<Vec<_>>().join(\", \")",
+    "r#where"
+)]
+pub struct UpdateQuery {
+    pub keyspace: Option<String>,
+    pub table: String,
+    /// Columns of the `SET` clause with their new values
+    pub assignments: Vec<(String, QueryValue)>,
+    pub r#where: WhereClosure,
+    #[serde(default)]
+    pub using: Option<UsingTimestamp>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(
+    fmt = "DELETE {}FROM {}{} WHERE {}",
+    "if columns.is_empty() { String::new() } else { format!(\"{} \", columns.join(\", \")) }",
+    "qualified(keyspace, table)",
+    "using.as_ref().map(|it| format!(\" {it}\")).unwrap_or_default()",
     "r#where"
 )]
 pub struct DeleteQuery {
@@ -102,22 +304,141 @@ pub struct DeleteQuery {
     pub table: String,
     pub columns: Vec<String>,
     pub r#where: WhereClosure,
+    #[serde(default)]
+    pub using: Option<UsingTimestamp>,
+}
+
+impl fmt::Display for InsertQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "INSERT INTO {} ", qualified(&self.keyspace, &self.table))?;
+        match self.json {
+            Some(default) => {
+                write!(f, "JSON {}", join(&self.values))?;
+                if default != JsonDefault::Null {
+                    write!(f, " DEFAULT {default}")?;
+                }
+            }
+            None => write!(
+                f,
+                "({}) VALUES ({})",
+                self.columns.join(", "),
+                join(&self.values)
+            )?,
+        }
+        if let Some(using) = &self.using {
+            write!(f, " {using}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SelectQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SELECT ")?;
+        if self.json {
+            write!(f, "JSON ")?;
+        }
+        write!(
+            f,
+            "{} FROM {}",
+            self.columns,
+            qualified(&self.keyspace, &self.table)
+        )?;
+        if !self.r#where.is_empty() {
+            write!(f, " WHERE {}", self.r#where)?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {limit}")?;
+        }
+        if self.allow_filtering {
+            write!(f, " ALLOW FILTERING")?;
+        }
+        Ok(())
+    }
+}
+
+/// `BEGIN [UNLOGGED | COUNTER] BATCH ... APPLY BATCH`, the textual form of a batch request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchQuery {
+    pub batch_type: BatchType,
+    /// Timestamp of every statement of the batch, it is always a leading one
+    pub using: Option<UsingTimestamp>,
+    /// Only `INSERT`, `UPDATE` and `DELETE` statements
+    pub statements: Vec<QueryString>,
+}
+
+impl fmt::Display for BatchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.batch_type {
+            BatchType::Logged => write!(f, "BEGIN BATCH ")?,
+            BatchType::Unlogged => write!(f, "BEGIN UNLOGGED BATCH ")?,
+            BatchType::Counter => write!(f, "BEGIN COUNTER BATCH ")?,
+        }
+        if let Some(using) = &self.using {
+            write!(f, "{using} ")?;
+        }
+        for statement in &self.statements {
+            write!(f, "{statement}; ")?;
+        }
+        write!(f, "APPLY BATCH")
+    }
+}
+
+/// `USING TIMESTAMP` clause of modification statements.
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(fmt = "USING TIMESTAMP {}", "value")]
+pub struct UsingTimestamp {
+    pub value: QueryValue,
+    /// Whether the clause goes before other bind markers, as in `UPDATE` and `DELETE`, or after them as in `INSERT`
+    pub leading: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Display)]
-#[display(fmt = "CREATE KEYSPACE {}", "keyspace")]
+#[display(
+    fmt = "CREATE KEYSPACE {}{} WITH replication = {}{}",
+    "if_not_exists(*ignore_existence)",
+    "keyspace",
+    "replication",
+    "durable_writes.map(|it| format!(\" AND durable_writes = {it}\")).unwrap_or_default()"
+)]
 pub struct CreateKeyspaceQuery {
     pub keyspace: String,
     pub ignore_existence: bool,
     pub replication: Literal,
+    #[serde(default)]
+    pub durable_writes: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Display)]
-#[display(
-    fmt = "CREATE TABLE {}.{}",
-    "keyspace.as_deref().unwrap_or_default()",
-    "table"
-)]
+/// Options which are not given are kept as they are
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlterKeyspaceQuery {
+    pub keyspace: String,
+    pub replication: Option<Literal>,
+    pub durable_writes: Option<bool>,
+}
+
+impl fmt::Display for AlterKeyspaceQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let replication = self
+            .replication
+            .as_ref()
+            .map(|it| format!("replication = {it}"));
+        let durable_writes = self
+            .durable_writes
+            .map(|it| format!("durable_writes = {it}"));
+        let options = replication.into_iter().chain(durable_writes);
+
+        write!(
+            f,
+            "ALTER KEYSPACE {} WITH {}",
+            self.keyspace,
+            options.collect::<Vec<_>>().join(" AND ")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateTableQuery {
     pub keyspace: Option<String>,
     pub table: String,
@@ -128,41 +449,249 @@ pub struct CreateTableQuery {
     pub options: Vec<(String, Literal)>,
 }
 
+impl fmt::Display for CreateTableQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE TABLE {}{} (",
+            if_not_exists(self.ignore_existence),
+            qualified(&self.keyspace, &self.table)
+        )?;
+        for (name, ty) in &self.columns {
+            write!(f, "{name} {ty}, ")?;
+        }
+        let partition_key = match self.partition_keys.as_slice() {
+            [column] => column.clone(),
+            columns => format!("({})", columns.join(", ")),
+        };
+        let key = std::iter::once(partition_key).chain(self.clustering_keys.iter().cloned());
+        write!(f, "PRIMARY KEY ({}))", key.collect::<Vec<_>>().join(", "))?;
+
+        let options = self
+            .options
+            .iter()
+            .map(|(name, value)| match (name.as_str(), value) {
+                // the map of directions is sorted by column names, the clauses goes in the key order
+                ("clustering order by", Literal::Map(directions)) => {
+                    let columns = self.clustering_keys.iter().filter_map(|column| {
+                        let direction = match directions.get(column)? {
+                            Literal::Bool(false) => "DESC",
+                            _ => "ASC",
+                        };
+                        Some(format!("{column} {direction}"))
+                    });
+                    format!(
+                        "CLUSTERING ORDER BY ({})",
+                        columns.collect::<Vec<_>>().join(", ")
+                    )
+                }
+                ("compact storage", _) => "COMPACT STORAGE".to_owned(),
+                (name, value) => format!("{name} = {value}"),
+            });
+        let options = options.collect::<Vec<_>>();
+        if !options.is_empty() {
+            write!(f, " WITH {}", options.join(" AND "))?;
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Display)]
 #[display(
-    fmt = "CREATE TYPE {}.{}",
-    "keyspace.as_deref().unwrap_or_default()",
-    "name"
+    fmt = "CREATE TYPE {}{} ({})",
+    "if_not_exists(*ignore_existence)",
+    "qualified(keyspace, name)",
+    "columns.iter().map(|(name, ty)| format!(\"{name} {ty}\")).collect::<Vec<_>>().join(\", \")"
 )]
 pub struct CreateTypeQuery {
     pub keyspace: Option<String>,
     pub name: String,
+    #[serde(default)]
+    pub ignore_existence: bool,
     pub columns: Vec<(String, String)>,
 }
 
+/// `ignore_existence` is set by `IF EXISTS`, the same as `IF NOT EXISTS` of `CREATE` statements
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(fmt = "DROP KEYSPACE {}{}", "if_exists(*ignore_existence)", "keyspace")]
+pub struct DropKeyspaceQuery {
+    pub keyspace: String,
+    pub ignore_existence: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(
+    fmt = "DROP TABLE {}{}",
+    "if_exists(*ignore_existence)",
+    "qualified(keyspace, table)"
+)]
+pub struct DropTableQuery {
+    pub keyspace: Option<String>,
+    pub table: String,
+    pub ignore_existence: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(
+    fmt = "DROP TYPE {}{}",
+    "if_exists(*ignore_existence)",
+    "qualified(keyspace, name)"
+)]
+pub struct DropTypeQuery {
+    pub keyspace: Option<String>,
+    pub name: String,
+    pub ignore_existence: bool,
+}
+
+/// `DESCRIBE` statements, as cqlsh issues them
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+pub enum DescribeQuery {
+    #[display(fmt = "DESCRIBE KEYSPACES")]
+    Keyspaces,
+    #[display(fmt = "DESCRIBE TABLES")]
+    Tables,
+    #[display(fmt = "DESCRIBE SCHEMA")]
+    Schema,
+    /// Keyspace in use, unless named
+    #[display(
+        fmt = "DESCRIBE KEYSPACE{}",
+        "_0.as_ref().map(|it| format!(\" {it}\")).unwrap_or_default()"
+    )]
+    Keyspace(Option<String>),
+    #[display(fmt = "DESCRIBE TABLE {}", "qualified(keyspace, table)")]
+    Table {
+        keyspace: Option<String>,
+        table: String,
+    },
+}
+
+/// Options which are not given are `false`, roles without a password can't log in with one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateRoleQuery {
+    pub role: String,
+    pub ignore_existence: bool,
+    pub password: Option<String>,
+    pub login: bool,
+    pub superuser: bool,
+}
+
+impl fmt::Display for CreateRoleQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CREATE ROLE {}{}",
+            if_not_exists(self.ignore_existence),
+            quoted_identifier(&self.role)
+        )?;
+        let password = self
+            .password
+            .as_ref()
+            .map(|it| format!("PASSWORD = {}", Literal::String(it.clone())));
+        let options = password
+            .into_iter()
+            .chain(self.login.then(|| "LOGIN = true".to_owned()))
+            .chain(self.superuser.then(|| "SUPERUSER = true".to_owned()))
+            .collect::<Vec<_>>();
+        if !options.is_empty() {
+            write!(f, " WITH {}", options.join(" AND "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(
+    fmt = "DROP ROLE {}{}",
+    "if_exists(*ignore_existence)",
+    "quoted_identifier(role)"
+)]
+pub struct DropRoleQuery {
+    pub role: String,
+    pub ignore_existence: bool,
+}
+
+/// `GRANT permission ON resource TO role` and `REVOKE permission ON resource FROM role`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionQuery {
+    pub permission: Permission,
+    pub resource: Resource,
+    pub role: String,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+    strum::EnumIter,
+)]
+#[strum(serialize_all = "UPPERCASE", ascii_case_insensitive)]
+pub enum Permission {
+    /// Every permission the resource supports
+    #[strum(serialize = "ALL PERMISSIONS")]
+    All,
+    Create,
+    Alter,
+    Drop,
+    Select,
+    Modify,
+    Authorize,
+}
+
+/// Data resources permissions are granted on, a permission of a resource applies to its children as well
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum Resource {
+    #[display(fmt = "ALL KEYSPACES")]
+    AllKeyspaces,
+    #[display(fmt = "KEYSPACE {}", "_0")]
+    Keyspace(String),
+    #[display(fmt = "TABLE {}", "qualified(keyspace, table)")]
+    Table {
+        keyspace: Option<String>,
+        table: String,
+    },
+}
+
+impl Resource {
+    pub fn keyspace(&self) -> Option<&str> {
+        match self {
+            Resource::AllKeyspaces => None,
+            Resource::Keyspace(keyspace) => Some(keyspace),
+            Resource::Table { keyspace, .. } => keyspace.as_deref(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SelectExpression {
     All,
     Columns(Vec<ColumnSelector>),
+    /// `COUNT(*)`, the number of rows rather than the rows themselves
+    Count {
+        alias: Option<String>,
+    },
 }
 
 impl fmt::Display for SelectExpression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let columns = match self {
-            SelectExpression::All => return write!(f, "*"),
-            SelectExpression::Columns(c) => c,
-        };
-        for (column, last) in columns.iter().zip(
-            std::iter::repeat(false)
-                .take(columns.len() - 1)
-                .chain(Some(true)),
-        ) {
-            write!(f, "{}", column)?;
-            if !last {
-                write!(f, ",")?
+        match self {
+            SelectExpression::All => write!(f, "*"),
+            SelectExpression::Columns(columns) => write!(f, "{}", join(columns)),
+            SelectExpression::Count { alias: None } => write!(f, "COUNT(*)"),
+            SelectExpression::Count { alias: Some(alias) } => {
+                write!(f, "COUNT(*) AS {}", quoted_identifier(alias))
             }
         }
-        Ok(())
     }
 }
 
@@ -181,7 +710,7 @@ impl fmt::Display for ColumnSelector {
             write!(f, "{}", self.name)?;
         }
         if let Some(alias) = &self.alias {
-            write!(f, " AS {alias}")?;
+            write!(f, " AS {}", quoted_identifier(alias))?;
         }
         Ok(())
     }
@@ -190,19 +719,56 @@ impl fmt::Display for ColumnSelector {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct WhereClosure {
     pub statements: Vec<(String, QueryValue)>,
+    #[serde(default)]
+    pub token: Vec<TokenRelation>,
+    /// Slices of clustering columns, their bind markers follow the ones of `statements` and `token`
+    #[serde(default)]
+    pub clustering: Vec<ClusteringRelation>,
+    /// `column LIKE 'pattern%'` restrictions, their bind markers come last
+    #[serde(default)]
+    pub like: Vec<(String, QueryValue)>,
 }
 
 impl WhereClosure {
     pub fn is_empty(&self) -> bool {
         self.statements.is_empty()
+            && self.token.is_empty()
+            && self.clustering.is_empty()
+            && self.like.is_empty()
+    }
+
+    fn bind_markers(&self) -> usize {
+        self.statements
+            .iter()
+            .map(|(_, value)| value)
+            .chain(self.token.iter().map(|it| &it.value))
+            .chain(self.clustering.iter().flat_map(|it| &it.values))
+            .chain(self.like.iter().map(|(_, value)| value))
+            .filter(|it| it.is_bind_marker())
+            .count()
     }
 }
 
 impl fmt::Display for WhereClosure {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut iter = self.statements.iter().peekable();
-        while let Some((name, value)) = iter.next() {
-            write!(f, "{name} = {value}")?;
+        let statements = self
+            .statements
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}"));
+        let token = self.token.iter().map(ToString::to_string);
+        let clustering = self.clustering.iter().map(ToString::to_string);
+        let like = self
+            .like
+            .iter()
+            .map(|(name, value)| format!("{name} LIKE {value}"));
+
+        let mut iter = statements
+            .chain(token)
+            .chain(clustering)
+            .chain(like)
+            .peekable();
+        while let Some(relation) = iter.next() {
+            write!(f, "{relation}")?;
             if iter.peek().is_some() {
                 write!(f, " AND ")?;
             }
@@ -211,10 +777,117 @@ impl fmt::Display for WhereClosure {
     }
 }
 
+/// `token(pk1, pk2) > ?` restriction
+#[derive(Debug, Clone, Serialize, Deserialize, Display)]
+#[display(fmt = "token({}) {} {}", "columns.join(\", \")", "operator", "value")]
+pub struct TokenRelation {
+    pub columns: Vec<String>,
+    pub operator: Operator,
+    pub value: QueryValue,
+}
+
+/// `ck > ?` restriction of a clustering column, or `(ck1, ck2) >= (?, ?)` of several of them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusteringRelation {
+    pub columns: Vec<String>,
+    pub operator: Operator,
+    /// A value for each of `columns`
+    pub values: Vec<QueryValue>,
+}
+
+impl fmt::Display for ClusteringRelation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.columns.as_slice(), self.values.as_slice()) {
+            ([column], [value]) => write!(f, "{column} {} {value}", self.operator),
+            (columns, values) => write!(
+                f,
+                "({}) {} ({})",
+                columns.join(", "),
+                self.operator,
+                join(values)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum Operator {
+    #[display(fmt = "=")]
+    Eq,
+    #[display(fmt = "<")]
+    Lt,
+    #[display(fmt = "<=")]
+    Le,
+    #[display(fmt = ">")]
+    Gt,
+    #[display(fmt = ">=")]
+    Ge,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Display)]
 pub enum QueryValue {
     #[display(fmt = "{}", "_0")]
     Literal(Literal),
     #[display(fmt = "?")]
     Blankslate,
+    /// `fromJson(..)` of a json string, decoded as the type of the assigned column
+    #[display(fmt = "fromJson({})", "_0")]
+    FromJson(Box<QueryValue>),
+}
+
+impl QueryValue {
+    /// Bind markers take their values from the parameters of a statement, also as `fromJson(?)`
+    pub fn is_bind_marker(&self) -> bool {
+        match self {
+            QueryValue::Literal(_) => false,
+            QueryValue::Blankslate => true,
+            QueryValue::FromJson(value) => value.is_bind_marker(),
+        }
+    }
+}
+
+/// Identifiers keep their case only when quoted, the rest are written as they are
+fn quoted_identifier(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|it| it.is_ascii_lowercase() || it == '_')
+        && chars.all(|it| it.is_ascii_lowercase() || it.is_ascii_digit() || it == '_');
+
+    match plain {
+        true => name.to_owned(),
+        false => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+/// `keyspace.name`, or just `name` for statements relying on `USE`
+fn qualified(keyspace: &Option<String>, name: &str) -> String {
+    match keyspace {
+        Some(keyspace) => format!("{keyspace}.{name}"),
+        None => name.to_owned(),
+    }
+}
+
+fn if_not_exists(ignore_existence: bool) -> &'static str {
+    if ignore_existence {
+        "IF NOT EXISTS "
+    } else {
+        ""
+    }
+}
+
+fn if_exists(ignore_existence: bool) -> &'static str {
+    if ignore_existence {
+        "IF EXISTS "
+    } else {
+        ""
+    }
+}
+
+fn join(items: &[impl fmt::Display]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
 }
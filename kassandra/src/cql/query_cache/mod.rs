@@ -2,10 +2,22 @@ use crate::{cql::query::QueryString, error::DbError};
 
 mod persisted;
 
-pub use persisted::PersistedQueryCache;
+pub use persisted::{PersistedQueryCache, DEFAULT_PREPARED_STATEMENTS_CAPACITY};
 
 pub trait QueryCache {
     fn store(&mut self, id: u128, query: QueryString) -> Result<(), DbError>;
 
-    fn retrieve(&mut self, id: u128) -> Result<Option<QueryString>, DbError>;
+    fn retrieve(&self, id: u128) -> Result<Option<QueryString>, DbError>;
+}
+
+/// Id of a prepared statement: md5 of the query prefixed with the keyspace of the preparing connection,
+/// same as cassandra computes it, so preparing the same statement again returns the same id.
+pub fn statement_id(keyspace: Option<&str>, query: &str) -> u128 {
+    let mut context = md5::Context::new();
+    if let Some(keyspace) = keyspace {
+        context.consume(keyspace);
+    }
+    context.consume(query);
+
+    u128::from_be_bytes(context.compute().0)
 }
@@ -1,31 +1,108 @@
-use std::collections::HashMap;
+use std::{num::NonZeroUsize, sync::Mutex};
 
-use serde::{Deserialize, Serialize};
+use lru::LruCache;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{cql::query::QueryString, error::DbError, storage};
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub const DEFAULT_PREPARED_STATEMENTS_CAPACITY: NonZeroUsize = NonZeroUsize::new(5000).unwrap();
+
+/// Prepared statements, the least recently used ones are evicted once the capacity is reached
+/// and executing them returns `Unprepared`, so clients prepare them again.
+#[derive(Debug)]
 pub struct PersistedQueryCache {
-    local: HashMap<u128, QueryString>,
+    // retrieving marks the statement as used, while the cache is only borrowed for reading
+    local: Mutex<LruCache<u128, QueryString>>,
+}
+
+impl Default for PersistedQueryCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_PREPARED_STATEMENTS_CAPACITY)
+    }
+}
+
+impl Clone for PersistedQueryCache {
+    fn clone(&self) -> Self {
+        Self {
+            local: Mutex::new(self.local.lock().unwrap().clone()),
+        }
+    }
 }
 
 impl PersistedQueryCache {
+    pub fn with_capacity(capacity: NonZeroUsize) -> Self {
+        Self {
+            local: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn set_capacity(&mut self, capacity: NonZeroUsize) {
+        self.local.get_mut().unwrap().resize(capacity);
+    }
+
     pub fn store(
         &mut self,
         id: u128,
         query: QueryString,
         _storage: &mut impl storage::Storage,
     ) -> Result<(), DbError> {
-        self.local.insert(id, query);
-        // todo: insert in storage
+        if let Some((evicted, _)) = self.local.get_mut().unwrap().push(id, query) {
+            if evicted != id {
+                tracing::debug!(evicted, "Evicted prepared statement");
+            }
+        }
+
         Ok(())
     }
 
     pub fn retrieve(
-        &mut self,
+        &self,
         id: u128,
-        _storage: &impl storage::Storage,
+        _storage: &impl storage::ReadStorage,
     ) -> Result<Option<QueryString>, DbError> {
-        Ok(self.local.get(&id).cloned())
+        Ok(self.local.lock().unwrap().get(&id).cloned())
+    }
+
+    /// Statements from the most to the least recently used one
+    pub fn statements(&self) -> Vec<(u128, QueryString)> {
+        self.local
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, query)| (*id, query.clone()))
+            .collect()
+    }
+}
+
+/// Statements are written from the least to the most recently used one, so loading them back
+/// keeps the order of eviction. Ids are hex encoded, the way drivers log them.
+impl Serialize for PersistedQueryCache {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let local = self.local.lock().unwrap();
+        serializer.collect_seq(
+            local
+                .iter()
+                .rev()
+                .map(|(id, query)| (format!("{id:032x}"), query)),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for PersistedQueryCache {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let statements = Vec::<(String, QueryString)>::deserialize(deserializer)?;
+        let capacity = NonZeroUsize::new(statements.len())
+            .map_or(DEFAULT_PREPARED_STATEMENTS_CAPACITY, |it| {
+                it.max(DEFAULT_PREPARED_STATEMENTS_CAPACITY)
+            });
+        let mut local = LruCache::new(capacity);
+        for (id, query) in statements {
+            let id = u128::from_str_radix(&id, 16).map_err(de::Error::custom)?;
+            local.push(id, query);
+        }
+
+        Ok(Self {
+            local: Mutex::new(local),
+        })
     }
 }
@@ -0,0 +1,234 @@
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use lru::LruCache;
+
+use crate::{
+    cql::{
+        execution::selector::ColumnsSelector,
+        plan::Plan,
+        schema::ClusteringOrder,
+        value::{
+            ClusteringKeyValue, ClusteringKeyValueRange, PartitionKeyValue, PartitionKeyValueRange,
+        },
+    },
+    frame::response::result::{QueryResult, Rows},
+    storage::Predicate,
+};
+
+pub const DEFAULT_ROW_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(1024).unwrap();
+
+/// Rows of repeated `SELECT`s from system tables along with their serialized form,
+/// so drivers polling `system.local` or the schema tables don't get them read and encoded every time.
+///
+/// Only system tables are cached: they are changed by statements of the session alone,
+/// while rows of user tables can expire without any write.
+/// Writes invalidate the rows of their table, schema changes clear the whole cache.
+#[derive(Debug)]
+pub struct RowCache {
+    rows: Mutex<LruCache<RowKey, Rows>>,
+}
+
+/// Everything of a read plan which decides the rows it returns
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RowKey {
+    keyspace: String,
+    table: String,
+    rows: RowRange,
+    predicate: Predicate,
+    selector: ColumnsSelector,
+    clustering_order: Vec<ClusteringOrder>,
+    limit: usize,
+    result_page_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RowRange {
+    /// Rows of a partition, `start` is where a paged select resumes
+    Partition {
+        key: PartitionKeyValue,
+        range: ClusteringKeyValueRange,
+        start: Option<ClusteringKeyValue>,
+    },
+    /// Rows of a range of partitions, `start` is where a paged scan resumes in the first partition
+    Scan {
+        range: PartitionKeyValueRange,
+        start: Option<ClusteringKeyValue>,
+    },
+}
+
+impl Default for RowCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_ROW_CACHE_CAPACITY)
+    }
+}
+
+impl RowCache {
+    pub fn with_capacity(capacity: NonZeroUsize) -> Self {
+        Self {
+            rows: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Key of the rows the plan reads, `None` when they are not cached
+    pub fn key(plan: &Plan) -> Option<RowKey> {
+        match plan {
+            Plan::Select(node) if is_cached_table(&node.keyspace, &node.table) => Some(RowKey {
+                keyspace: node.keyspace.clone(),
+                table: node.table.clone(),
+                rows: RowRange::Partition {
+                    key: node.partition_key.clone(),
+                    range: node.clustering_range.clone(),
+                    start: node.clustering_start.clone(),
+                },
+                predicate: node.predicate.clone(),
+                selector: node.selector.clone(),
+                clustering_order: node.clustering_order.clone(),
+                limit: node.limit,
+                result_page_size: node.result_page_size,
+            }),
+            Plan::Scan(node) if is_cached_table(&node.keyspace, &node.table) => Some(RowKey {
+                keyspace: node.keyspace.clone(),
+                table: node.table.clone(),
+                rows: RowRange::Scan {
+                    range: node.partition_range.clone(),
+                    start: node.clustering_key_start.clone(),
+                },
+                predicate: node.predicate.clone(),
+                selector: node.selector.clone(),
+                clustering_order: node.clustering_order.clone(),
+                limit: node.limit,
+                result_page_size: node.result_page_size,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: &RowKey) -> Option<QueryResult> {
+        let rows = self.rows.lock().unwrap().get(key).cloned()?;
+        Some(QueryResult::Rows(rows))
+    }
+
+    /// Caches the rows of a result and returns them encoded, pages which are followed by others are not cached
+    pub fn put(&self, key: RowKey, result: QueryResult) -> QueryResult {
+        match result {
+            QueryResult::Rows(rows) if rows.metadata.paging_state.is_none() => {
+                let rows = rows.encode();
+                self.rows.lock().unwrap().put(key, rows.clone());
+                QueryResult::Rows(rows)
+            }
+            other => other,
+        }
+    }
+
+    pub fn invalidate(&self, keyspace: &str, table: &str) {
+        let mut rows = self.rows.lock().unwrap();
+        let stale = rows
+            .iter()
+            .filter(|(key, _)| key.keyspace == keyspace && key.table == table)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in stale {
+            rows.pop(&key);
+        }
+    }
+
+    /// Drops the rows a plan is about to change, the whole cache for schema changes
+    pub fn invalidate_plan(&self, plan: &Plan) {
+        match plan {
+            Plan::Insert(node) => self.invalidate(&node.keyspace, &node.table),
+            Plan::Update(node) => self.invalidate(&node.keyspace, &node.table),
+            Plan::Delete(node) => self.invalidate(&node.keyspace, &node.table),
+            Plan::Select(_) | Plan::Scan(_) | Plan::Describe(_) | Plan::Explain(_) => {}
+            _ => self.clear(),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.rows.lock().unwrap().clear();
+    }
+}
+
+/// System tables whose rows only change with statements of the session,
+/// estimates follow the data of user tables and prepared statements change by preparing them
+fn is_cached_table(keyspace: &str, table: &str) -> bool {
+    match keyspace {
+        "system" => !matches!(
+            table,
+            "size_estimates" | "table_estimates" | "prepared_statements"
+        ),
+        "system_schema" => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{
+        response::result::{ResultMetadata, Row},
+        value::PagingState,
+    };
+
+    fn key(table: &str) -> RowKey {
+        RowKey {
+            keyspace: "system".to_owned(),
+            table: table.to_owned(),
+            rows: RowRange::Scan {
+                range: PartitionKeyValueRange::from(..),
+                start: None,
+            },
+            predicate: Predicate::default(),
+            selector: ColumnsSelector(vec![]),
+            clustering_order: vec![],
+            limit: usize::MAX,
+            result_page_size: usize::MAX,
+        }
+    }
+
+    fn rows(more_pages: bool) -> QueryResult {
+        QueryResult::Rows(Rows {
+            metadata: ResultMetadata {
+                paging_state: more_pages.then(PagingState::default),
+                ..ResultMetadata::empty()
+            },
+            rows: vec![Row::default()],
+            encoded: None,
+        })
+    }
+
+    #[test]
+    fn invalidates_rows_of_the_table() {
+        let cache = RowCache::default();
+        cache.put(key("local"), rows(false));
+        cache.put(key("peers"), rows(false));
+
+        cache.invalidate("system", "local");
+
+        assert!(cache.get(&key("local")).is_none());
+        let Some(QueryResult::Rows(cached)) = cache.get(&key("peers")) else {
+            panic!("rows of other tables are kept");
+        };
+        assert!(cached.encoded.is_some());
+    }
+
+    #[test]
+    fn pages_are_not_cached() {
+        let cache = RowCache::default();
+        cache.put(key("local"), rows(true));
+
+        assert!(cache.get(&key("local")).is_none());
+    }
+
+    #[test]
+    fn keys_differ_by_limit() {
+        let cache = RowCache::default();
+        cache.put(key("local"), rows(false));
+
+        let limited = RowKey {
+            limit: 1,
+            ..key("local")
+        };
+        assert!(cache.get(&limited).is_none());
+        assert!(cache.get(&key("local")).is_some());
+    }
+}
@@ -1,7 +1,16 @@
+use std::fmt;
+
 use derive_more::Display;
 use serde::{Deserialize, Serialize};
 
-use crate::cql::types::{NativeType, PreCqlType};
+use crate::{
+    cql::{
+        schema::Catalog,
+        types::{NativeType, PreCqlType},
+    },
+    error::DbError,
+    frame::response::error::Error,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ColumnType {
@@ -37,36 +46,40 @@ pub enum ColumnType {
     Varint,
 }
 
-impl ColumnType {
-    pub fn into_cql(&self) -> Option<String> {
-        Some(match self {
-            ColumnType::Custom(_) => unimplemented!(),
-            ColumnType::Ascii => "ascii".to_owned(),
-            ColumnType::Boolean => "bool".to_owned(),
-            ColumnType::Blob => "blob".to_owned(),
-            ColumnType::Counter => unimplemented!(),
-            ColumnType::Date => "date".to_owned(),
-            ColumnType::Decimal => "bigint".to_owned(),
-            ColumnType::Double => "double".to_owned(),
-            ColumnType::Duration => "timestamp".to_owned(),
-            ColumnType::Float => "float".to_owned(),
-            ColumnType::Int => "int".to_owned(),
-            ColumnType::BigInt => "bigint".to_owned(),
-            ColumnType::Text => "text".to_owned(),
-            ColumnType::Timestamp => "timestamp".to_owned(),
-            ColumnType::Inet => "inet".to_owned(),
-            ColumnType::List(l) => format!("list<{}>", l.into_cql()?),
-            ColumnType::Map(k, v) => format!("map<{}, {}>", k.into_cql()?, v.into_cql()?),
-            ColumnType::Set(i) => format!("set<{}>", i.into_cql()?),
-            ColumnType::UserDefinedType { .. } => unimplemented!(),
-            ColumnType::SmallInt => "smallint".to_owned(),
-            ColumnType::TinyInt => "timyint".to_owned(),
-            ColumnType::Time => "time".to_owned(),
-            ColumnType::Timeuuid => "timeuuid".to_owned(),
-            ColumnType::Tuple(_i) => unimplemented!(),
-            ColumnType::Uuid => "uuid".to_owned(),
-            ColumnType::Varint => "varint".to_owned(),
-        })
+/// Renders the type the way it is written in cql statements
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnType::Custom(class) => write!(f, "'{class}'"),
+            ColumnType::Ascii => write!(f, "ascii"),
+            ColumnType::Boolean => write!(f, "boolean"),
+            ColumnType::Blob => write!(f, "blob"),
+            ColumnType::Counter => write!(f, "counter"),
+            ColumnType::Date => write!(f, "date"),
+            ColumnType::Decimal => write!(f, "decimal"),
+            ColumnType::Double => write!(f, "double"),
+            ColumnType::Duration => write!(f, "duration"),
+            ColumnType::Float => write!(f, "float"),
+            ColumnType::Int => write!(f, "int"),
+            ColumnType::BigInt => write!(f, "bigint"),
+            ColumnType::Text => write!(f, "text"),
+            ColumnType::Timestamp => write!(f, "timestamp"),
+            ColumnType::Inet => write!(f, "inet"),
+            ColumnType::List(item) => write!(f, "list<{item}>"),
+            ColumnType::Map(key, value) => write!(f, "map<{key}, {value}>"),
+            ColumnType::Set(item) => write!(f, "set<{item}>"),
+            ColumnType::UserDefinedType { type_name, .. } => write!(f, "frozen<{type_name}>"),
+            ColumnType::SmallInt => write!(f, "smallint"),
+            ColumnType::TinyInt => write!(f, "tinyint"),
+            ColumnType::Time => write!(f, "time"),
+            ColumnType::Timeuuid => write!(f, "timeuuid"),
+            ColumnType::Tuple(items) => {
+                let items = items.iter().map(ToString::to_string).collect::<Vec<_>>();
+                write!(f, "frozen<tuple<{}>>", items.join(", "))
+            }
+            ColumnType::Uuid => write!(f, "uuid"),
+            ColumnType::Varint => write!(f, "varint"),
+        }
     }
 }
 
@@ -88,36 +101,150 @@ pub enum ColumnKind {
     PartitionKey,
 }
 
-pub fn map_pre_type(pre: PreCqlType) -> ColumnType {
-    match pre {
-        PreCqlType::Native(NativeType::Ascii) => ColumnType::Ascii,
-        PreCqlType::Native(NativeType::Boolean) => ColumnType::Boolean,
-        PreCqlType::Native(NativeType::Blob) => ColumnType::Blob,
-        PreCqlType::Native(NativeType::Counter) => ColumnType::Counter,
-        PreCqlType::Native(NativeType::Date) => ColumnType::Date,
-        PreCqlType::Native(NativeType::Decimal) => ColumnType::Decimal,
-        PreCqlType::Native(NativeType::Double) => ColumnType::Double,
-        PreCqlType::Native(NativeType::Duration) => ColumnType::Duration,
-        PreCqlType::Native(NativeType::Float) => ColumnType::Float,
-        PreCqlType::Native(NativeType::Int) => ColumnType::Int,
-        PreCqlType::Native(NativeType::BigInt) => ColumnType::BigInt,
-        PreCqlType::Native(NativeType::Text) => ColumnType::Text,
-        PreCqlType::Native(NativeType::Timestamp) => ColumnType::Timestamp,
-        PreCqlType::Native(NativeType::Inet) => ColumnType::Inet,
-        PreCqlType::Native(NativeType::SmallInt) => ColumnType::SmallInt,
-        PreCqlType::Native(NativeType::TinyInt) => ColumnType::TinyInt,
-        PreCqlType::Native(NativeType::Time) => ColumnType::Time,
-        PreCqlType::Native(NativeType::Timeuuid) => ColumnType::Timeuuid,
-        PreCqlType::Native(NativeType::Uuid) => ColumnType::Uuid,
-        PreCqlType::Native(NativeType::Varint) => ColumnType::Varint,
-        PreCqlType::List { frozen: _, item } => ColumnType::List(Box::new(map_pre_type(*item))),
-        PreCqlType::Set { frozen: _, item } => ColumnType::Set(Box::new(map_pre_type(*item))),
-        PreCqlType::Map {
-            frozen: _,
-            key,
-            value,
-        } => ColumnType::Map(Box::new(map_pre_type(*key)), Box::new(map_pre_type(*value))),
-        PreCqlType::Tuple(_) => unimplemented!(),
-        PreCqlType::UserDefinedType { .. } => unimplemented!(),
+/// Where a type is used, types nested in collections and user types are restricted
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Nesting {
+    Column,
+    /// Field of a user type being created
+    Field,
+    Collection,
+    Tuple,
+    /// Anything inside of `frozen<..>` is frozen as well
+    Frozen,
+}
+
+/// Resolves the type of a table column, user types (`name` or `keyspace.name`) are looked up
+/// in the `catalog` and have to be defined in the `keyspace` of the table
+pub fn resolve_type<C: Catalog + ?Sized>(
+    pre: PreCqlType,
+    catalog: &C,
+    keyspace: &str,
+) -> Result<ColumnType, Error> {
+    resolve(pre, catalog, keyspace, Nesting::Column)
+}
+
+/// Same as [`resolve_type`] for a field of a user type, which can't be a counter or an unfrozen user type
+pub fn resolve_field_type<C: Catalog + ?Sized>(
+    pre: PreCqlType,
+    catalog: &C,
+    keyspace: &str,
+) -> Result<ColumnType, Error> {
+    resolve(pre, catalog, keyspace, Nesting::Field)
+}
+
+fn resolve<C: Catalog + ?Sized>(
+    pre: PreCqlType,
+    catalog: &C,
+    keyspace: &str,
+    nesting: Nesting,
+) -> Result<ColumnType, Error> {
+    let invalid = |message: String| Err(Error::new(DbError::Invalid, message));
+    let frozen = match &pre {
+        PreCqlType::List { frozen, .. }
+        | PreCqlType::Set { frozen, .. }
+        | PreCqlType::Map { frozen, .. }
+        | PreCqlType::UserDefinedType { frozen, .. } => *frozen || nesting == Nesting::Frozen,
+        PreCqlType::Native(_) | PreCqlType::Tuple(_) => true,
+    };
+    let inner = match frozen {
+        true => Nesting::Frozen,
+        false => Nesting::Collection,
+    };
+
+    match (&pre, nesting) {
+        (PreCqlType::Native(NativeType::Counter), Nesting::Collection | Nesting::Frozen) => {
+            return invalid(format!(
+                "Counters are not allowed inside collections: {pre}"
+            ));
+        }
+        (PreCqlType::Native(NativeType::Counter), Nesting::Tuple) => {
+            return invalid(format!("Counters are not allowed inside tuples: {pre}"));
+        }
+        (PreCqlType::Native(NativeType::Counter), Nesting::Field) => {
+            return invalid("A user type cannot contain counters".to_owned());
+        }
+        (
+            PreCqlType::List { .. } | PreCqlType::Set { .. } | PreCqlType::Map { .. },
+            Nesting::Collection,
+        ) if !frozen => {
+            return invalid(format!(
+                "Non-frozen collections are not allowed inside collections: {pre}"
+            ));
+        }
+        (PreCqlType::UserDefinedType { .. }, Nesting::Collection) if !frozen => {
+            return invalid(format!(
+                "Non-frozen UDTs are not allowed inside collections: {pre}"
+            ));
+        }
+        (PreCqlType::UserDefinedType { .. }, Nesting::Field) if !frozen => {
+            return invalid("A user type cannot contain non-frozen UDTs".to_owned());
+        }
+        _ => {}
+    }
+
+    let resolve = |pre: PreCqlType, nesting: Nesting| resolve(pre, catalog, keyspace, nesting);
+    Ok(match pre {
+        PreCqlType::Native(native) => native_type(native),
+        PreCqlType::List { item, .. } => ColumnType::List(Box::new(resolve(*item, inner)?)),
+        PreCqlType::Set { item, .. } => ColumnType::Set(Box::new(resolve(*item, inner)?)),
+        PreCqlType::Map { key, value, .. } => ColumnType::Map(
+            Box::new(resolve(*key, inner)?),
+            Box::new(resolve(*value, inner)?),
+        ),
+        // tuples are always frozen, so are the collections in them
+        PreCqlType::Tuple(items) => ColumnType::Tuple(
+            items
+                .into_iter()
+                .map(|item| resolve(item.freeze(), Nesting::Tuple))
+                .collect::<Result<_, _>>()?,
+        ),
+        PreCqlType::UserDefinedType { name, .. } => {
+            let (type_keyspace, name) = name.split_once('.').unwrap_or((keyspace, &name));
+            if type_keyspace != keyspace {
+                return invalid(format!(
+                    "Statement on keyspace {keyspace} cannot refer to a user type in keyspace \
+                     {type_keyspace}; user types can only be used in the keyspace they are \
+                     defined in"
+                ));
+            }
+
+            let Some(ty) = catalog
+                .get_keyspace(keyspace)
+                .and_then(|ks| ks.user_defined_types.get(name))
+            else {
+                return invalid(format!("Unknown type {keyspace}.{name}"));
+            };
+
+            ColumnType::UserDefinedType {
+                type_name: ty.name.clone(),
+                keyspace: ty.keyspace.clone(),
+                field_types: ty.field_types.clone(),
+            }
+        }
+    })
+}
+
+fn native_type(native: NativeType) -> ColumnType {
+    match native {
+        NativeType::Ascii => ColumnType::Ascii,
+        NativeType::Boolean => ColumnType::Boolean,
+        NativeType::Blob => ColumnType::Blob,
+        NativeType::Counter => ColumnType::Counter,
+        NativeType::Date => ColumnType::Date,
+        NativeType::Decimal => ColumnType::Decimal,
+        NativeType::Double => ColumnType::Double,
+        NativeType::Duration => ColumnType::Duration,
+        NativeType::Float => ColumnType::Float,
+        NativeType::Int => ColumnType::Int,
+        NativeType::BigInt => ColumnType::BigInt,
+        NativeType::Text => ColumnType::Text,
+        NativeType::Timestamp => ColumnType::Timestamp,
+        NativeType::Inet => ColumnType::Inet,
+        NativeType::SmallInt => ColumnType::SmallInt,
+        NativeType::TinyInt => ColumnType::TinyInt,
+        NativeType::Time => ColumnType::Time,
+        NativeType::Timeuuid => ColumnType::Timeuuid,
+        NativeType::Uuid => ColumnType::Uuid,
+        NativeType::Varint => ColumnType::Varint,
     }
 }
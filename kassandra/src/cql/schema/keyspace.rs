@@ -9,6 +9,8 @@ use crate::cql::schema::{ColumnType, Table};
 pub struct Keyspace {
     pub name: String,
     pub strategy: Strategy,
+    #[serde(default = "durable_writes_default")]
+    pub durable_writes: bool,
     pub tables: BTreeMap<String, Table>,
     pub user_defined_types: BTreeMap<String, UserDefinedType>,
 }
@@ -30,6 +32,70 @@ pub enum Strategy {
     },
 }
 
+impl Keyspace {
+    /// `CREATE KEYSPACE` statement recreating the keyspace, as `DESCRIBE` renders it
+    pub fn create_statement(&self) -> String {
+        let replication = self
+            .strategy
+            .replication()
+            .into_iter()
+            .map(|(key, value)| format!("'{key}': '{value}'"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "CREATE KEYSPACE {} WITH replication = {{{replication}}}  AND durable_writes = {};",
+            self.name, self.durable_writes
+        )
+    }
+}
+
+fn durable_writes_default() -> bool {
+    true
+}
+
+impl Strategy {
+    /// Package of the strategies shipped with cassandra, their classes are reported qualified with it
+    pub const CLASS_PREFIX: &'static str = "org.apache.cassandra.locator.";
+
+    /// Replication options of the strategy, starting with its `class`
+    pub fn replication(&self) -> Vec<(String, String)> {
+        let class: &'static str = self.into();
+        let mut options = vec![("class".to_owned(), format!("{}{class}", Self::CLASS_PREFIX))];
+
+        match self {
+            Strategy::SimpleStrategy { replication_factor } => {
+                options.push((
+                    "replication_factor".to_owned(),
+                    replication_factor.to_string(),
+                ));
+            }
+            Strategy::NetworkTopologyStrategy {
+                datacenter_repfactors,
+            } => {
+                let mut datacenters = datacenter_repfactors
+                    .iter()
+                    .map(|(dc, factor)| (dc.clone(), factor.to_string()))
+                    .collect::<Vec<_>>();
+                datacenters.sort();
+                options.extend(datacenters);
+            }
+            Strategy::LocalStrategy => {}
+            Strategy::Other { name, data } => {
+                options[0].1.clone_from(name);
+                let mut data = data
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect::<Vec<_>>();
+                data.sort();
+                options.extend(data);
+            }
+        }
+
+        options
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UserDefinedType {
     pub name: String,
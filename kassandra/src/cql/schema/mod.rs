@@ -12,18 +12,16 @@ use serde::{Deserialize, Serialize};
 pub use self::{
     column::{Column, ColumnKind, ColumnType},
     persisted::PersistedSchema,
-    table::{PrimaryKey, PrimaryKeyColumn, Table, TableSchema},
+    table::{ClusteringOrder, PrimaryKey, PrimaryKeyColumn, Table, TableOptions, TableSchema},
 };
 use crate::{
-    cql::{
-        literal::Literal,
-        schema::{
-            keyspace::{Keyspace, Strategy},
-            system::{system_keyspace, system_schema_keyspace},
+    cql::schema::{
+        keyspace::{Keyspace, Strategy, UserDefinedType},
+        system::{
+            system_auth_keyspace, system_keyspace, system_schema_keyspace, system_views_keyspace,
         },
     },
     error::DbError,
-    frame::response::event::SchemaChangeEvent,
 };
 
 pub trait Catalog {
@@ -32,6 +30,15 @@ pub trait Catalog {
         keyspace: String,
         ignore_existence: bool,
         replication: Strategy,
+        durable_writes: bool,
+    ) -> Result<&Keyspace, DbError>;
+
+    /// Options which are `None` are kept as they are
+    fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
     ) -> Result<&Keyspace, DbError>;
 
     fn create_table(
@@ -40,15 +47,43 @@ pub trait Catalog {
         table: String,
         ignore_existence: bool,
         schema: TableSchema,
-        options: Vec<(String, Literal)>,
+        options: TableOptions,
     ) -> Result<&Table, DbError>;
 
+    /// Tables and types of the keyspace are dropped along with it,
+    /// `None` is returned when there was nothing to drop
+    fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError>;
+
+    fn drop_table(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError>;
+
     fn create_type(
         &mut self,
-        keyspace: Option<String>,
-        table: String,
-        columns: Vec<(String, String)>,
-    ) -> Result<SchemaChangeEvent, DbError>;
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError>;
+
+    fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError>;
+
+    fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace>;
+
+    /// All keyspaces, system ones included, ordered by name
+    fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_>;
 
     fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema>;
 }
@@ -60,9 +95,14 @@ pub struct Schema(pub BTreeMap<String, Keyspace>);
 impl Default for Schema {
     fn default() -> Self {
         Self(
-            [system_keyspace(), system_schema_keyspace()]
-                .into_iter()
-                .collect(),
+            [
+                system_keyspace(),
+                system_schema_keyspace(),
+                system_views_keyspace(),
+                system_auth_keyspace(),
+            ]
+            .into_iter()
+            .collect(),
         )
     }
 }
@@ -73,6 +113,7 @@ impl Catalog for Schema {
         keyspace: String,
         ignore_existence: bool,
         strategy: Strategy,
+        durable_writes: bool,
     ) -> Result<&Keyspace, DbError> {
         match self.0.entry(keyspace) {
             Entry::Occupied(occupied) if ignore_existence => Ok(&*occupied.into_mut()),
@@ -85,6 +126,7 @@ impl Catalog for Schema {
                 let ks = vacant.insert(Keyspace {
                     name,
                     strategy,
+                    durable_writes,
                     tables: Default::default(),
                     user_defined_types: Default::default(),
                 });
@@ -94,13 +136,30 @@ impl Catalog for Schema {
         }
     }
 
+    fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
+    ) -> Result<&Keyspace, DbError> {
+        let ks = self.0.get_mut(keyspace).ok_or(DbError::Invalid)?;
+        if let Some(strategy) = replication {
+            ks.strategy = strategy;
+        }
+        if let Some(durable_writes) = durable_writes {
+            ks.durable_writes = durable_writes;
+        }
+
+        Ok(&*ks)
+    }
+
     fn create_table(
         &mut self,
         keyspace: String,
         table: String,
         ignore_existence: bool,
         schema: TableSchema,
-        _options: Vec<(String, Literal)>,
+        options: TableOptions,
     ) -> Result<&Table, DbError> {
         let ks = self.0.get_mut(&keyspace).ok_or(DbError::Invalid)?;
 
@@ -112,6 +171,7 @@ impl Catalog for Schema {
                     keyspace,
                     name: table,
                     schema,
+                    options,
                 });
 
                 Ok(&*table)
@@ -119,13 +179,82 @@ impl Catalog for Schema {
         }
     }
 
+    fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError> {
+        match self.0.remove(keyspace) {
+            None if !ignore_existence => Err(DbError::Invalid),
+            dropped => Ok(dropped),
+        }
+    }
+
+    fn drop_table(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError> {
+        match self
+            .0
+            .get_mut(keyspace)
+            .and_then(|ks| ks.tables.remove(table))
+        {
+            None if !ignore_existence => Err(DbError::Invalid),
+            dropped => Ok(dropped),
+        }
+    }
+
     fn create_type(
         &mut self,
-        _keyspace: Option<String>,
-        _table: String,
-        _columns: Vec<(String, String)>,
-    ) -> Result<SchemaChangeEvent, DbError> {
-        todo!()
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError> {
+        let ks = self.0.get_mut(&keyspace).ok_or(DbError::Invalid)?;
+
+        match ks.user_defined_types.entry(name.clone()) {
+            Entry::Occupied(occupied) if ignore_existence => Ok(&*occupied.into_mut()),
+            Entry::Occupied(_) => Err(DbError::AlreadyExists {
+                keyspace,
+                table: name,
+            }),
+            Entry::Vacant(vacant) => {
+                let udt = vacant.insert(UserDefinedType {
+                    name,
+                    keyspace,
+                    field_types,
+                });
+
+                Ok(&*udt)
+            }
+        }
+    }
+
+    fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError> {
+        match self
+            .0
+            .get_mut(keyspace)
+            .and_then(|ks| ks.user_defined_types.remove(name))
+        {
+            None if !ignore_existence => Err(DbError::Invalid),
+            dropped => Ok(dropped),
+        }
+    }
+
+    fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace> {
+        self.0.get(keyspace)
+    }
+
+    fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_> {
+        Box::new(self.0.values())
     }
 
     fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
@@ -139,8 +268,18 @@ impl<'a, C: Catalog> Catalog for &'a mut C {
         keyspace: String,
         ignore_existence: bool,
         replication: Strategy,
+        durable_writes: bool,
     ) -> Result<&Keyspace, DbError> {
-        (*self).create_keyspace(keyspace, ignore_existence, replication)
+        (*self).create_keyspace(keyspace, ignore_existence, replication, durable_writes)
+    }
+
+    fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
+    ) -> Result<&Keyspace, DbError> {
+        (*self).alter_keyspace(keyspace, replication, durable_writes)
     }
 
     fn create_table(
@@ -149,18 +288,53 @@ impl<'a, C: Catalog> Catalog for &'a mut C {
         table: String,
         ignore_existence: bool,
         schema: TableSchema,
-        options: Vec<(String, Literal)>,
+        options: TableOptions,
     ) -> Result<&Table, DbError> {
         (*self).create_table(keyspace, table, ignore_existence, schema, options)
     }
 
+    fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError> {
+        (*self).drop_keyspace(keyspace, ignore_existence)
+    }
+
+    fn drop_table(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError> {
+        (*self).drop_table(keyspace, table, ignore_existence)
+    }
+
     fn create_type(
         &mut self,
-        _keyspace: Option<String>,
-        _table: String,
-        _columns: Vec<(String, String)>,
-    ) -> Result<SchemaChangeEvent, DbError> {
-        todo!()
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError> {
+        (*self).create_type(keyspace, name, ignore_existence, field_types)
+    }
+
+    fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError> {
+        (*self).drop_type(keyspace, name, ignore_existence)
+    }
+
+    fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace> {
+        (**self).get_keyspace(keyspace)
+    }
+
+    fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_> {
+        (**self).keyspaces()
     }
 
     fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
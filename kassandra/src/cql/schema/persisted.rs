@@ -1,19 +1,20 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     cql::{
-        column::ColumnKind,
-        literal::Literal,
+        column::{ColumnKind, ColumnType},
         schema::{
-            keyspace::{Keyspace, Strategy},
-            system::{system_keyspace, system_schema_keyspace},
-            Schema, Table, TableSchema,
+            keyspace::{Keyspace, Strategy, UserDefinedType},
+            table::PrimaryKey,
+            Schema, Table, TableOptions, TableSchema,
         },
         value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
         Catalog,
     },
     error::DbError,
-    frame::response::event::SchemaChangeEvent,
     storage,
 };
 
@@ -23,171 +24,246 @@ pub struct PersistedSchema {
 }
 
 impl PersistedSchema {
+    /// Reflects the live catalog into rows of `system_schema.{keyspaces,tables,columns}`,
+    /// so driver metadata refreshes see the same schema the session uses.
+    pub(crate) fn system_schema_table(
+        &self,
+        storage: &mut impl storage::Storage,
+        table: &str,
+    ) -> Result<(), DbError> {
+        for keyspace in self.schema.keyspaces() {
+            match table {
+                "keyspaces" => Self::insert_keyspace(storage, keyspace)?,
+                "tables" => {
+                    for table in keyspace.tables.values() {
+                        Self::insert_table(storage, table)?;
+                    }
+                }
+                "columns" => {
+                    for table in keyspace.tables.values() {
+                        Self::insert_columns(storage, table)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
     fn insert_keyspace(
         storage: &mut impl storage::Storage,
         keyspace: &Keyspace,
     ) -> Result<(), DbError> {
         let pk: CqlValue = keyspace.name.clone().into();
-        let class: &'static str = keyspace.strategy.clone().into();
-        let replication = [
-            ("class".to_owned().into(), class.to_owned().into()),
-            (
-                "replication_factor".to_owned().into(),
-                "1".to_owned().into(),
-            ),
-        ]
-        .into_iter()
-        .collect::<Vec<(CqlValue, CqlValue)>>();
-
-        storage
-            .write(
-                "system_schema",
-                "keyspaces",
-                pk.clone().into(),
-                ClusteringKeyValue::Empty,
-                [
-                    ("keyspace_name".to_owned(), pk),
-                    ("durable_writes".to_owned(), CqlValue::Boolean(true)),
-                    ("replication".to_owned(), CqlValue::Map(replication)),
-                ]
-                .into_iter(),
-            )
-            .map_err(|_| DbError::Invalid)?;
+        let replication = keyspace
+            .strategy
+            .replication()
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect::<Vec<(CqlValue, CqlValue)>>();
+
+        storage.write(
+            "system_schema",
+            "keyspaces",
+            pk.clone().into(),
+            ClusteringKeyValue::Empty,
+            [
+                ("keyspace_name".to_owned(), pk),
+                (
+                    "durable_writes".to_owned(),
+                    CqlValue::Boolean(keyspace.durable_writes),
+                ),
+                ("replication".to_owned(), CqlValue::Map(replication)),
+            ]
+            .into_iter(),
+            storage::write_timestamp(),
+        )?;
 
         Ok(())
     }
+
     fn insert_table(storage: &mut impl storage::Storage, table: &Table) -> Result<(), DbError> {
         let pk: CqlValue = table.keyspace.clone().into();
         let ck: CqlValue = table.name.clone().into();
-
-        storage
-            .write(
-                "system_schema",
-                "tables",
-                PartitionKeyValue::Simple(pk.clone()),
-                ClusteringKeyValue::Simple(Some(ck.clone())),
-                [
-                    ("keyspace_name".to_owned(), pk),
-                    ("table_name".to_owned(), ck),
-                    ("allow_auto_snapshot".to_owned(), CqlValue::Boolean(false)),
-                    ("incremental_backups".to_owned(), CqlValue::Boolean(false)),
-                    ("cdc".to_owned(), CqlValue::Boolean(false)),
-                ]
-                .into_iter(),
+        let options = &table.options;
+        let map = |entries: &BTreeMap<String, String>| {
+            CqlValue::Map(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone().into(), value.clone().into()))
+                    .collect(),
             )
-            .map_err(|_| DbError::Invalid)?;
+        };
+
+        storage.write(
+            "system_schema",
+            "tables",
+            PartitionKeyValue::Simple(pk.clone()),
+            ClusteringKeyValue::Simple(Some(ck.clone())),
+            [
+                ("keyspace_name".to_owned(), pk),
+                ("table_name".to_owned(), ck),
+                ("cdc".to_owned(), CqlValue::Boolean(false)),
+                (
+                    "bloom_filter_fp_chance".to_owned(),
+                    CqlValue::Double(options.bloom_filter_fp_chance.to_bits()),
+                ),
+                ("comment".to_owned(), options.comment.clone().into()),
+                ("compaction".to_owned(), map(&options.compaction)),
+                ("compression".to_owned(), map(&options.compression)),
+                (
+                    "default_time_to_live".to_owned(),
+                    CqlValue::Int(options.default_time_to_live),
+                ),
+                (
+                    "gc_grace_seconds".to_owned(),
+                    CqlValue::Int(options.gc_grace_seconds),
+                ),
+            ]
+            .into_iter(),
+            storage::write_timestamp(),
+        )?;
 
         Ok(())
     }
 
     fn insert_columns(storage: &mut impl storage::Storage, table: &Table) -> Result<(), DbError> {
         let pk: CqlValue = table.keyspace.clone().into();
+        let schema = &table.schema;
 
-        let mut partition_order = -1;
-        let mut clustering_order = -1;
-        for (column_name, column_spec) in table.schema.columns.iter() {
+        for (column_name, column_spec) in schema.columns.iter() {
             let name: CqlValue = column_name.clone().into();
-            let ck: CqlValue = CqlValue::Tuple(vec![table.name.clone().into(), name.clone()]);
+            let ck = ClusteringKeyValue::Composite(vec![
+                Some(table.name.clone().into()),
+                Some(name.clone()),
+            ]);
 
-            let order = match column_spec.kind {
-                ColumnKind::Regular => -1,
-                ColumnKind::Static => -1,
+            let position_in = |key: &PrimaryKey| {
+                key.into_iter()
+                    .position(|it| it == column_name)
+                    .expect("key column to be part of the key")
+            };
+            let (position, direction) = match column_spec.kind {
+                ColumnKind::Regular | ColumnKind::Static => (-1, "none".to_owned()),
                 ColumnKind::Clustering => {
-                    clustering_order += 1;
+                    let position = position_in(&schema.clustering_key);
+                    let direction = schema.clustering_column_order(position);
 
-                    clustering_order
+                    (position as i32, direction.to_string())
                 }
                 ColumnKind::PartitionKey => {
-                    partition_order += 1;
-
-                    partition_order
+                    (position_in(&schema.partition_key) as i32, "none".to_owned())
                 }
             };
 
-            storage
-                .write(
-                    "system_schema",
-                    "columns",
-                    pk.clone().into(),
-                    ClusteringKeyValue::Simple(Some(ck.clone())),
-                    [
-                        ("keyspace_name".to_owned(), pk.clone()),
-                        ("table_name".to_owned(), table.name.clone().into()),
-                        ("column_name".to_owned(), name),
-                        ("clustering_order".to_owned(), "none".to_owned().into()),
-                        (
-                            "column_name_bytes".to_owned(),
-                            CqlValue::Blob(column_name.as_bytes().to_owned()),
-                        ),
-                        (
-                            "kind".to_owned(),
-                            CqlValue::Text(column_spec.kind.to_string()),
-                        ),
-                        ("position".to_owned(), CqlValue::Int(order as _)),
-                        ("type".to_owned(), column_spec.ty.into_cql().unwrap().into()),
-                    ]
-                    .into_iter(),
-                )
-                .map_err(|_| DbError::Invalid)?;
+            storage.write(
+                "system_schema",
+                "columns",
+                pk.clone().into(),
+                ck,
+                [
+                    ("keyspace_name".to_owned(), pk.clone()),
+                    ("table_name".to_owned(), table.name.clone().into()),
+                    ("column_name".to_owned(), name),
+                    ("clustering_order".to_owned(), direction.into()),
+                    (
+                        "column_name_bytes".to_owned(),
+                        CqlValue::Blob(Bytes::copy_from_slice(column_name.as_bytes())),
+                    ),
+                    (
+                        "kind".to_owned(),
+                        CqlValue::Text(column_spec.kind.to_string().into()),
+                    ),
+                    ("position".to_owned(), CqlValue::Int(position)),
+                    ("type".to_owned(), column_spec.ty.to_string().into()),
+                ]
+                .into_iter(),
+                storage::write_timestamp(),
+            )?;
         }
 
         Ok(())
     }
-
-    pub(crate) fn persist_system_schema(storage: &mut impl storage::Storage) {
-        for (_, keyspace) in [system_keyspace(), system_schema_keyspace()] {
-            Self::insert_keyspace(storage, &keyspace).expect("system keyspace not to fail");
-            for table in keyspace.tables.values() {
-                Self::insert_table(storage, table).expect("system tables not to fail");
-                Self::insert_columns(storage, table).expect("system table not to fail");
-            }
-        }
-    }
 }
 
 impl PersistedSchema {
     pub(crate) fn create_keyspace(
         &mut self,
-        storage: &mut impl storage::Storage,
         keyspace: String,
         ignore_existence: bool,
         replication: Strategy,
+        durable_writes: bool,
     ) -> Result<&Keyspace, DbError> {
-        let ks = self
-            .schema
-            .create_keyspace(keyspace, ignore_existence, replication)?;
-        Self::insert_keyspace(storage, ks)?;
+        self.schema
+            .create_keyspace(keyspace, ignore_existence, replication, durable_writes)
+    }
 
-        Ok(ks)
+    pub(crate) fn alter_keyspace(
+        &mut self,
+        keyspace: &str,
+        replication: Option<Strategy>,
+        durable_writes: Option<bool>,
+    ) -> Result<&Keyspace, DbError> {
+        self.schema
+            .alter_keyspace(keyspace, replication, durable_writes)
     }
 
     pub(crate) fn create_table(
         &mut self,
-        storage: &mut impl storage::Storage,
         keyspace: String,
         table: String,
         ignore_existence: bool,
         schema: TableSchema,
-        options: Vec<(String, Literal)>,
+        options: TableOptions,
     ) -> Result<&Table, DbError> {
-        let table = self
-            .schema
-            .create_table(keyspace, table, ignore_existence, schema, options)?;
-        Self::insert_table(storage, table)?;
-        Self::insert_columns(storage, table)?;
+        self.schema
+            .create_table(keyspace, table, ignore_existence, schema, options)
+    }
 
-        Ok(table)
+    pub(crate) fn drop_keyspace(
+        &mut self,
+        keyspace: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Keyspace>, DbError> {
+        self.schema.drop_keyspace(keyspace, ignore_existence)
     }
 
-    #[allow(dead_code)]
-    fn create_type(
+    pub(crate) fn drop_table(
         &mut self,
-        _storage: &mut impl storage::Storage,
-        _keyspace: Option<String>,
-        _table: String,
-        _columns: Vec<(String, String)>,
-    ) -> Result<SchemaChangeEvent, DbError> {
-        todo!()
+        keyspace: &str,
+        table: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<Table>, DbError> {
+        self.schema.drop_table(keyspace, table, ignore_existence)
+    }
+
+    pub(crate) fn create_type(
+        &mut self,
+        keyspace: String,
+        name: String,
+        ignore_existence: bool,
+        field_types: Vec<(String, ColumnType)>,
+    ) -> Result<&UserDefinedType, DbError> {
+        self.schema
+            .create_type(keyspace, name, ignore_existence, field_types)
+    }
+
+    pub(crate) fn drop_type(
+        &mut self,
+        keyspace: &str,
+        name: &str,
+        ignore_existence: bool,
+    ) -> Result<Option<UserDefinedType>, DbError> {
+        self.schema.drop_type(keyspace, name, ignore_existence)
+    }
+
+    pub fn get_keyspace(&self, keyspace: &str) -> Option<&Keyspace> {
+        self.schema.get_keyspace(keyspace)
+    }
+
+    pub fn keyspaces(&self) -> Box<dyn Iterator<Item = &Keyspace> + '_> {
+        self.schema.keyspaces()
     }
 
     pub fn get_table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
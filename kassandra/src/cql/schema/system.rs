@@ -12,6 +12,7 @@ pub fn system_keyspace() -> (String, Keyspace) {
     let keyspace = Keyspace {
         name: "system".to_string(),
         strategy: Strategy::LocalStrategy,
+        durable_writes: true,
         tables: [
             local(),
             available_ranges(),
@@ -48,6 +49,7 @@ pub fn system_schema_keyspace() -> (String, Keyspace) {
         Keyspace {
             name: "system_schema".to_string(),
             strategy: Strategy::LocalStrategy,
+            durable_writes: true,
             tables: [
                 types(),
                 columns(),
@@ -66,6 +68,42 @@ pub fn system_schema_keyspace() -> (String, Keyspace) {
     )
 }
 
+/// Virtual tables exposing live server state, their rows are never stored
+pub fn system_views_keyspace() -> (String, Keyspace) {
+    (
+        "system_views".to_string(),
+        Keyspace {
+            name: "system_views".to_string(),
+            strategy: Strategy::LocalStrategy,
+            durable_writes: true,
+            tables: [clients(), settings(), metrics()].into_iter().collect(),
+            user_defined_types: Default::default(),
+        },
+    )
+}
+
+/// Roles and the permissions granted to them, sessions with authentication check statements against them
+pub fn system_auth_keyspace() -> (String, Keyspace) {
+    (
+        "system_auth".to_string(),
+        Keyspace {
+            name: "system_auth".to_string(),
+            strategy: Strategy::LocalStrategy,
+            durable_writes: true,
+            tables: [roles(), role_permissions()].into_iter().collect(),
+            user_defined_types: Default::default(),
+        },
+    )
+}
+
+/// Keyspaces defined by kassandra itself rather than by users
+pub fn is_system_keyspace(name: &str) -> bool {
+    matches!(
+        name,
+        "system" | "system_schema" | "system_views" | "system_auth"
+    )
+}
+
 macro_rules! system_table {
     (
         $keyspace:ident . $table:ident;
@@ -89,12 +127,14 @@ macro_rules! system_table {
                     $( stringify!($clustering_name).to_string(), )*
                 ].into_iter().collect()),
                 partitioner: None,
+                clustering_order: vec![],
             };
 
             let table = Table {
                 keyspace: stringify!($keyspace).to_string(),
                 name: stringify!($table).to_string(),
                 schema,
+                options: Default::default(),
             };
 
             (stringify!($table).to_string(), table)
@@ -102,6 +142,24 @@ macro_rules! system_table {
     };
 }
 
+system_table! {
+    system_auth.roles;
+    [role: ColumnType::Text],
+    [],
+    [
+        can_login: ColumnType::Boolean,
+        is_superuser: ColumnType::Boolean,
+        salted_hash: ColumnType::Text
+    ]
+}
+
+system_table! {
+    system_auth.role_permissions;
+    [role: ColumnType::Text],
+    [resource: ColumnType::Text],
+    [permissions: ColumnType::Set(Box::new(ColumnType::Text))]
+}
+
 system_table! {
     system.peers;
     [peer: ColumnType::Inet],
@@ -516,3 +574,39 @@ system_table!(
         options: ColumnType::Map(Box::new(ColumnType::Text), Box::new(ColumnType::Text))
     ]
 );
+
+system_table!(
+    system_views.clients;
+    [address: ColumnType::Inet],
+    [port: ColumnType::Int],
+    [
+        client_options: ColumnType::Map(Box::new(ColumnType::Text), Box::new(ColumnType::Text)),
+        connection_stage: ColumnType::Text,
+        driver_name: ColumnType::Text,
+        driver_version: ColumnType::Text,
+        hostname: ColumnType::Text,
+        keyspace_name: ColumnType::Text,
+        protocol_version: ColumnType::Int,
+        request_count: ColumnType::BigInt,
+        ssl_enabled: ColumnType::Boolean,
+        username: ColumnType::Text
+    ]
+);
+
+system_table!(
+    system_views.settings;
+    [name: ColumnType::Text],
+    [],
+    [
+        value: ColumnType::Text
+    ]
+);
+
+system_table!(
+    system_views.metrics;
+    [name: ColumnType::Text],
+    [],
+    [
+        value: ColumnType::BigInt
+    ]
+);
@@ -1,16 +1,130 @@
-use std::slice;
+use std::{collections::BTreeMap, slice};
 
+use derive_more::Display;
 use indexmap::map::IndexMap;
 use serde::{Deserialize, Serialize};
 
 use super::ColumnType;
-use crate::cql::schema::Column;
+use crate::cql::schema::{Column, ColumnKind};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Table {
     pub keyspace: String,
     pub name: String,
     pub schema: TableSchema,
+    #[serde(default)]
+    pub options: TableOptions,
+}
+
+impl Table {
+    /// `CREATE TABLE` statement recreating the table, as `DESCRIBE` renders it
+    pub fn create_statement(&self) -> String {
+        let schema = &self.schema;
+        let simple_key = schema.partition_key.count() == 1 && schema.clustering_key.count() == 0;
+
+        let mut definitions = schema
+            .columns
+            .iter()
+            .map(|(name, column)| match column.kind {
+                ColumnKind::PartitionKey if simple_key => {
+                    format!("{name} {} PRIMARY KEY", column.ty)
+                }
+                ColumnKind::Static => format!("{name} {} static", column.ty),
+                _ => format!("{name} {}", column.ty),
+            })
+            .collect::<Vec<_>>();
+
+        if !simple_key {
+            let partition_key = schema
+                .partition_key
+                .into_iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            let partition_key = match schema.partition_key.count() {
+                1 => partition_key,
+                _ => format!("({partition_key})"),
+            };
+            let primary_key = std::iter::once(partition_key)
+                .chain(schema.clustering_key.into_iter().cloned())
+                .collect::<Vec<_>>()
+                .join(", ");
+            definitions.push(format!("PRIMARY KEY ({primary_key})"));
+        }
+
+        let mut statement = format!(
+            "CREATE TABLE {}.{} (\n    {}\n)",
+            self.keyspace,
+            self.name,
+            definitions.join(",\n    ")
+        );
+
+        if schema.clustering_key.count() > 0 {
+            let order = schema
+                .clustering_key
+                .into_iter()
+                .enumerate()
+                .map(|(position, column)| {
+                    let order = schema.clustering_column_order(position);
+                    format!("{column} {}", order.to_string().to_uppercase())
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            statement += &format!(" WITH CLUSTERING ORDER BY ({order})");
+        }
+
+        statement + ";"
+    }
+}
+
+/// Options given in `CREATE TABLE ... WITH`, defaults are the ones of cassandra.
+///
+/// They are only reported back, `default_time_to_live` included, as kassandra has no expiring data.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TableOptions {
+    pub bloom_filter_fp_chance: f64,
+    pub comment: String,
+    pub compaction: BTreeMap<String, String>,
+    pub compression: BTreeMap<String, String>,
+    pub default_time_to_live: i32,
+    pub gc_grace_seconds: i32,
+}
+
+impl TableOptions {
+    /// Package of the compaction strategies shipped with cassandra
+    pub const COMPACTION_PREFIX: &'static str = "org.apache.cassandra.db.compaction.";
+    /// Package of the compressors shipped with cassandra
+    pub const COMPRESSION_PREFIX: &'static str = "org.apache.cassandra.io.compress.";
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        let map = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        Self {
+            bloom_filter_fp_chance: 0.01,
+            comment: String::new(),
+            compaction: map(&[
+                (
+                    "class",
+                    "org.apache.cassandra.db.compaction.SizeTieredCompactionStrategy",
+                ),
+                ("max_threshold", "32"),
+                ("min_threshold", "4"),
+            ]),
+            compression: map(&[
+                ("chunk_length_in_kb", "16"),
+                ("class", "org.apache.cassandra.io.compress.LZ4Compressor"),
+            ]),
+            default_time_to_live: 0,
+            gc_grace_seconds: 864000,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -19,6 +133,8 @@ pub struct TableSchema {
     pub partition_key: PrimaryKey,
     pub clustering_key: PrimaryKey,
     pub partitioner: Option<String>,
+    #[serde(default)]
+    pub clustering_order: Vec<ClusteringOrder>,
 }
 
 impl TableSchema {
@@ -28,6 +144,37 @@ impl TableSchema {
     pub fn partition_key_column(&self) -> PrimaryKeyColumn {
         PrimaryKeyColumn::new(self.partition_key.into_iter(), &self.columns)
     }
+
+    pub fn clustering_column_order(&self, position: usize) -> ClusteringOrder {
+        self.clustering_order
+            .get(position)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn is_clustering_ascending(&self) -> bool {
+        self.clustering_order
+            .iter()
+            .all(|it| *it == ClusteringOrder::Asc)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Display)]
+pub enum ClusteringOrder {
+    #[default]
+    #[display(fmt = "asc")]
+    Asc,
+    #[display(fmt = "desc")]
+    Desc,
+}
+
+impl ClusteringOrder {
+    pub fn apply(self, ordering: std::cmp::Ordering) -> std::cmp::Ordering {
+        match self {
+            ClusteringOrder::Asc => ordering,
+            ClusteringOrder::Desc => ordering.reverse(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
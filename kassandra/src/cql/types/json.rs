@@ -0,0 +1,731 @@
+//! Json of values the way cassandra has it.
+//!
+//! Values of `INSERT .. JSON` documents and `fromJson()` arguments are decoded as the type of their
+//! column: besides the matching json type, scalars are also accepted in their string form and keys
+//! of maps, which are always strings in json, are decoded as the key type.
+//!
+//! `toJson()` and `SELECT JSON` render values the way cassandra does: blobs as `0x` hex strings,
+//! timestamps, dates and times as strings and floats in the java notation.
+
+use std::{fmt::Write, net::IpAddr, str::FromStr};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use crate::{
+    cql::{
+        column::ColumnType,
+        value::{CqlDuration, CqlValue},
+    },
+    error::DbError,
+    frame::response::error::Error,
+};
+
+/// Decodes a json document, `None` is the json `null`
+pub fn parse(ty: &ColumnType, json: &str) -> Result<Option<CqlValue>, Error> {
+    let value = serde_json::from_str(json)
+        .map_err(|err| invalid(format!("Could not decode JSON string '{json}': {err}")))?;
+
+    from_json(ty, value)
+}
+
+/// Parses the members of an `INSERT .. JSON` document
+pub fn parse_object(json: &str) -> Result<Map<String, Value>, Error> {
+    match serde_json::from_str(json) {
+        Ok(Value::Object(members)) => Ok(members),
+        Ok(_) => Err(invalid(format!(
+            "Could not decode JSON string as a map: {json}"
+        ))),
+        Err(err) => Err(invalid(format!(
+            "Could not decode JSON string '{json}': {err}"
+        ))),
+    }
+}
+
+pub fn from_json(ty: &ColumnType, value: Value) -> Result<Option<CqlValue>, Error> {
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    let mismatch = |value: &Value| {
+        invalid(format!(
+            "Unable to make {ty} from {value}, expected {}",
+            expected(ty)
+        ))
+    };
+
+    let value = match (ty, value) {
+        (ColumnType::Ascii, Value::String(v)) if v.is_ascii() => CqlValue::Ascii(v.into()),
+        (ColumnType::Text, Value::String(v)) => CqlValue::Text(v.into()),
+        (ColumnType::Boolean, Value::Bool(v)) => CqlValue::Boolean(v),
+        (ColumnType::Boolean, Value::String(v)) if v.eq_ignore_ascii_case("true") => {
+            CqlValue::Boolean(true)
+        }
+        (ColumnType::Boolean, Value::String(v)) if v.eq_ignore_ascii_case("false") => {
+            CqlValue::Boolean(false)
+        }
+        (ColumnType::TinyInt, value) => CqlValue::TinyInt(number(ty, &value)?),
+        (ColumnType::SmallInt, value) => CqlValue::SmallInt(number(ty, &value)?),
+        (ColumnType::Int, value) => CqlValue::Int(number(ty, &value)?),
+        (ColumnType::BigInt, value) => CqlValue::BigInt(number(ty, &value)?),
+        (ColumnType::Counter, value) => CqlValue::Counter(number(ty, &value)?),
+        (ColumnType::Varint, value) => CqlValue::Varint(number(ty, &value)?),
+        (ColumnType::Decimal, value) => CqlValue::Decimal(number(ty, &value)?),
+        (ColumnType::Float, value) => {
+            let v: f32 = number(ty, &value)?;
+            CqlValue::Float(v.to_bits())
+        }
+        (ColumnType::Double, value) => {
+            let v: f64 = number(ty, &value)?;
+            CqlValue::Double(v.to_bits())
+        }
+        (ColumnType::Blob, Value::String(v)) => match v.strip_prefix("0x").map(hex) {
+            Some(Some(bytes)) => CqlValue::Blob(bytes.into()),
+            _ => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Uuid, Value::String(v)) => match Uuid::from_str(&v) {
+            Ok(uuid) => CqlValue::Uuid(uuid),
+            Err(_) => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Timeuuid, Value::String(v)) => match Uuid::from_str(&v) {
+            Ok(uuid) if uuid.get_version_num() == 1 => CqlValue::Timeuuid(uuid),
+            _ => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Inet, Value::String(v)) => match IpAddr::from_str(&v) {
+            Ok(addr) => CqlValue::Inet(addr),
+            Err(_) => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Timestamp, Value::Number(v)) => match v.as_i64() {
+            Some(millis) => CqlValue::Timestamp(millis),
+            None => return Err(mismatch(&Value::Number(v))),
+        },
+        (ColumnType::Timestamp, Value::String(v)) => match timestamp(&v) {
+            Some(millis) => CqlValue::Timestamp(millis),
+            None => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Date, Value::Number(v)) => match v.as_u64().map(u32::try_from) {
+            Some(Ok(days)) => CqlValue::Date(days),
+            _ => return Err(mismatch(&Value::Number(v))),
+        },
+        (ColumnType::Date, Value::String(v)) => match NaiveDate::from_str(&v) {
+            Ok(date) => {
+                let days = date.signed_duration_since(NaiveDate::default()).num_days();
+                CqlValue::Date((days + (1 << 31)) as u32)
+            }
+            Err(_) => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Time, Value::Number(v)) => match v.as_i64() {
+            Some(nanos) if (0..86_400_000_000_000).contains(&nanos) => CqlValue::Time(nanos),
+            _ => return Err(mismatch(&Value::Number(v))),
+        },
+        (ColumnType::Time, Value::String(v)) => match NaiveTime::from_str(&v) {
+            Ok(time) => CqlValue::Time(
+                i64::from(time.num_seconds_from_midnight()) * 1_000_000_000
+                    + i64::from(time.nanosecond()),
+            ),
+            Err(_) => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::Duration, Value::String(v)) => match duration(&v) {
+            Some(duration) => CqlValue::Duration(duration),
+            None => return Err(mismatch(&Value::String(v))),
+        },
+        (ColumnType::List(item), Value::Array(items)) => CqlValue::List(elements(item, items)?),
+        (ColumnType::Set(item), Value::Array(items)) => {
+            let mut items = elements(item, items)?;
+            items.sort();
+            items.dedup();
+            CqlValue::Set(items)
+        }
+        (ColumnType::Map(key, value), Value::Object(entries)) => {
+            let mut entries = entries
+                .into_iter()
+                .map(|(k, v)| Ok((element(key, map_key(key, k)?)?, element(value, v)?)))
+                .collect::<Result<Vec<_>, Error>>()?;
+            // json keys are ordered as strings, entries of maps are ordered by the key type
+            entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+            CqlValue::Map(entries)
+        }
+        (ColumnType::Tuple(types), Value::Array(items)) if types.len() == items.len() => {
+            CqlValue::Tuple(
+                types
+                    .iter()
+                    .zip(items)
+                    // null elements of tuples are empty values
+                    .map(|(ty, item)| Ok(from_json(ty, item)?.unwrap_or(CqlValue::Empty)))
+                    .collect::<Result<_, Error>>()?,
+            )
+        }
+        (
+            ColumnType::UserDefinedType {
+                type_name,
+                keyspace,
+                field_types,
+            },
+            Value::Object(mut members),
+        ) => {
+            let fields = field_types
+                .iter()
+                .map(|(name, ty)| {
+                    let value = members.remove(name).unwrap_or(Value::Null);
+                    Ok((name.clone(), from_json(ty, value)?))
+                })
+                .collect::<Result<_, Error>>()?;
+
+            if let Some(unknown) = members.keys().next() {
+                return Err(invalid(format!(
+                    "Unknown field '{unknown}' in value of user defined type {type_name}"
+                )));
+            }
+
+            CqlValue::UserDefinedType {
+                keyspace: keyspace.clone(),
+                type_name: type_name.clone(),
+                fields,
+            }
+        }
+        (_, value) => return Err(mismatch(&value)),
+    };
+
+    Ok(Some(value))
+}
+
+/// Json document of a value
+pub fn to_json(value: &CqlValue) -> String {
+    let mut json = String::new();
+    write_json(&mut json, value);
+    json
+}
+
+/// Json object of a row, members are in the order of `columns`
+pub fn row_to_json<'a>(
+    columns: impl Iterator<Item = &'a str>,
+    values: impl Iterator<Item = Option<&'a CqlValue>>,
+) -> String {
+    let mut json = String::from("{");
+    for (index, (column, value)) in columns.zip(values).enumerate() {
+        if index > 0 {
+            json.push_str(", ");
+        }
+        write_string(&mut json, &column_name(column));
+        json.push_str(": ");
+        match value {
+            Some(value) => write_json(&mut json, value),
+            None => json.push_str("null"),
+        }
+    }
+    json.push('}');
+    json
+}
+
+fn write_json(out: &mut String, value: &CqlValue) {
+    let join = |out: &mut String, values: &[CqlValue]| {
+        for (index, value) in values.iter().enumerate() {
+            if index > 0 {
+                out.push_str(", ");
+            }
+            match value {
+                // empty elements of tuples are nulls
+                CqlValue::Empty => out.push_str("null"),
+                value => write_json(out, value),
+            }
+        }
+    };
+
+    match value {
+        CqlValue::Ascii(v) => write_string(out, v),
+        CqlValue::Text(v) => write_string(out, v),
+        CqlValue::Boolean(v) => write!(out, "{v}").unwrap(),
+        CqlValue::Blob(v) => {
+            out.push_str("\"0x");
+            for byte in v.iter() {
+                write!(out, "{byte:02x}").unwrap();
+            }
+            out.push('"');
+        }
+        CqlValue::Counter(v) | CqlValue::BigInt(v) => write!(out, "{v}").unwrap(),
+        CqlValue::Int(v) => write!(out, "{v}").unwrap(),
+        CqlValue::SmallInt(v) => write!(out, "{v}").unwrap(),
+        CqlValue::TinyInt(v) => write!(out, "{v}").unwrap(),
+        CqlValue::Varint(v) => write!(out, "{v}").unwrap(),
+        CqlValue::Decimal(v) => write!(out, "{v}").unwrap(),
+        CqlValue::Double(v) => out.push_str(&java_float(f64::from_bits(*v))),
+        CqlValue::Float(v) => out.push_str(&java_float(f32::from_bits(*v))),
+        CqlValue::Timestamp(v) => match DateTime::from_timestamp_millis(*v) {
+            Some(timestamp) => {
+                write!(out, "\"{}\"", timestamp.format("%Y-%m-%d %H:%M:%S%.3fZ")).unwrap()
+            }
+            None => write!(out, "{v}").unwrap(),
+        },
+        CqlValue::Date(v) => {
+            let days = i64::from(*v) - (1 << 31);
+            match NaiveDate::default().checked_add_signed(Duration::days(days)) {
+                Some(date) => write!(out, "\"{date}\"").unwrap(),
+                None => write!(out, "{v}").unwrap(),
+            }
+        }
+        CqlValue::Time(v) => {
+            let (seconds, nanoseconds) = (v / 1_000_000_000, v % 1_000_000_000);
+            write!(
+                out,
+                "\"{:02}:{:02}:{:02}.{nanoseconds:09}\"",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            )
+            .unwrap()
+        }
+        CqlValue::Duration(v) => write_string(out, &duration_string(v)),
+        CqlValue::Inet(IpAddr::V4(v)) => write!(out, "\"{v}\"").unwrap(),
+        // java doesn't compress zeros of ipv6 addresses
+        CqlValue::Inet(IpAddr::V6(v)) => {
+            let segments = v.segments().map(|it| format!("{it:x}"));
+            write!(out, "\"{}\"", segments.join(":")).unwrap()
+        }
+        CqlValue::Uuid(v) | CqlValue::Timeuuid(v) => write!(out, "\"{v}\"").unwrap(),
+        CqlValue::List(values) | CqlValue::Set(values) | CqlValue::Tuple(values) => {
+            out.push('[');
+            join(out, values);
+            out.push(']');
+        }
+        CqlValue::Map(entries) => {
+            out.push('{');
+            for (index, (key, value)) in entries.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                // keys which aren't json strings on their own are quoted
+                let key = to_json(key);
+                match key.starts_with('"') {
+                    true => out.push_str(&key),
+                    false => write_string(out, &key),
+                }
+                out.push_str(": ");
+                write_json(out, value);
+            }
+            out.push('}');
+        }
+        CqlValue::UserDefinedType { fields, .. } => {
+            out.push('{');
+            for (index, (name, value)) in fields.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                write_string(out, &column_name(name));
+                out.push_str(": ");
+                match value {
+                    Some(value) => write_json(out, value),
+                    None => out.push_str("null"),
+                }
+            }
+            out.push('}');
+        }
+        // zero-length values stay apart from nulls
+        CqlValue::Empty => out.push_str("\"\""),
+    }
+}
+
+fn write_string(out: &mut String, value: &str) {
+    out.push_str(&serde_json::to_string(value).expect("strings to be serializable"));
+}
+
+/// Names which are not lowercase identifiers keep their quotes, like `"\"firstName\""`
+fn column_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars.next().is_some_and(|it| it.is_ascii_lowercase())
+        && chars.all(|it| it.is_ascii_lowercase() || it.is_ascii_digit() || it == '_');
+
+    match plain {
+        true => name.to_owned(),
+        false => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+/// `Double.toString()` of java: scientific notation out of `[10^-3, 10^7)` and always a fraction
+fn java_float<F: Into<f64> + Copy + std::fmt::Display + std::fmt::LowerExp>(value: F) -> String {
+    let float: f64 = value.into();
+    if float.is_nan() {
+        return "NaN".to_owned();
+    }
+    if float.is_infinite() {
+        return if float > 0.0 { "Infinity" } else { "-Infinity" }.to_owned();
+    }
+
+    let magnitude = float.abs();
+    if magnitude != 0.0 && !(1e-3..1e7).contains(&magnitude) {
+        let formatted = format!("{value:e}");
+        let (mantissa, exponent) = formatted.split_once('e').expect("exponent to be written");
+        let fraction = if mantissa.contains('.') { "" } else { ".0" };
+        return format!("{mantissa}{fraction}E{exponent}");
+    }
+
+    let formatted = value.to_string();
+    match formatted.contains('.') {
+        true => formatted,
+        false => format!("{formatted}.0"),
+    }
+}
+
+/// Durations the way cassandra writes them: `1y2mo3d4h5m6s7ms8us9ns`, without zero units
+fn duration_string(duration: &CqlDuration) -> String {
+    let CqlDuration {
+        months,
+        days,
+        nanoseconds,
+    } = *duration;
+    let mut out = String::new();
+    if months < 0 || days < 0 || nanoseconds < 0 {
+        out.push('-');
+    }
+
+    let months = u64::from(months.unsigned_abs());
+    let mut nanoseconds = nanoseconds.unsigned_abs();
+    let units = [
+        (months / 12, "y"),
+        (months % 12, "mo"),
+        (u64::from(days.unsigned_abs()), "d"),
+    ];
+    for (amount, unit) in units {
+        if amount > 0 {
+            write!(out, "{amount}{unit}").unwrap();
+        }
+    }
+    for (scale, unit) in [
+        (3_600_000_000_000, "h"),
+        (60_000_000_000, "m"),
+        (1_000_000_000, "s"),
+        (1_000_000, "ms"),
+        (1_000, "us"),
+        (1, "ns"),
+    ] {
+        if nanoseconds >= scale {
+            write!(out, "{}{unit}", nanoseconds / scale).unwrap();
+            nanoseconds %= scale;
+        }
+    }
+
+    out
+}
+
+/// Elements of collections can't be null
+fn element(ty: &ColumnType, value: Value) -> Result<CqlValue, Error> {
+    from_json(ty, value)?
+        .ok_or_else(|| invalid(format!("Invalid null element in a collection of {ty}")))
+}
+
+fn elements(ty: &ColumnType, items: Vec<Value>) -> Result<Vec<CqlValue>, Error> {
+    items.into_iter().map(|item| element(ty, item)).collect()
+}
+
+/// Keys of collection and composite types are json documents of their own
+fn map_key(ty: &ColumnType, key: String) -> Result<Value, Error> {
+    match ty {
+        ColumnType::List(_)
+        | ColumnType::Set(_)
+        | ColumnType::Map(..)
+        | ColumnType::Tuple(_)
+        | ColumnType::UserDefinedType { .. } => serde_json::from_str(&key)
+            .map_err(|err| invalid(format!("Could not decode JSON string '{key}': {err}"))),
+        _ => Ok(Value::String(key)),
+    }
+}
+
+/// Numbers are accepted both as json numbers and strings
+fn number<T: FromStr>(ty: &ColumnType, value: &Value) -> Result<T, Error> {
+    let parsed = match value {
+        Value::Number(number) => number.to_string().parse().ok(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    };
+
+    parsed.ok_or_else(|| {
+        invalid(format!(
+            "Unable to make {ty} from {value}, expected {}",
+            expected(ty)
+        ))
+    })
+}
+
+fn expected(ty: &ColumnType) -> &'static str {
+    match ty {
+        ColumnType::Boolean => "a boolean",
+        ColumnType::TinyInt
+        | ColumnType::SmallInt
+        | ColumnType::Int
+        | ColumnType::BigInt
+        | ColumnType::Counter
+        | ColumnType::Varint
+        | ColumnType::Decimal
+        | ColumnType::Float
+        | ColumnType::Double => "a number or a numeric string",
+        ColumnType::Blob => "a hex string starting with 0x",
+        ColumnType::Timestamp | ColumnType::Date | ColumnType::Time => {
+            "a number or a date/time string"
+        }
+        ColumnType::List(_) | ColumnType::Set(_) => "a list",
+        ColumnType::Tuple(_) => "a list with an element for each component of the tuple",
+        ColumnType::Map(..) | ColumnType::UserDefinedType { .. } => "a map",
+        ColumnType::Custom(_) => "a supported type",
+        _ => "a string",
+    }
+}
+
+fn hex(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Milliseconds since unix epoch of `yyyy-mm-dd[( |T)hh:mm[:ss[.fff]]][zone]`, utc without a zone
+fn timestamp(value: &str) -> Option<i64> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.timestamp_millis());
+    }
+
+    let value = value.strip_suffix('Z').unwrap_or(value);
+    for format in [
+        "%Y-%m-%d %H:%M:%S%.f%z",
+        "%Y-%m-%dT%H:%M:%S%.f%z",
+        "%Y-%m-%d %H:%M%z",
+        "%Y-%m-%dT%H:%M%z",
+    ] {
+        if let Ok(date) = DateTime::parse_from_str(value, format) {
+            return Some(date.timestamp_millis());
+        }
+    }
+    for format in [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(date.and_utc().timestamp_millis());
+        }
+    }
+
+    let date = NaiveDate::from_str(value).ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis())
+}
+
+/// Durations in the `[-]1y2mo3w4d5h6m7s8ms9us10ns` format, units can be given in any order
+fn duration(value: &str) -> Option<CqlDuration> {
+    let (sign, mut rest) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+
+    let (mut months, mut days, mut nanoseconds) = (0_i64, 0_i64, 0_i64);
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let amount: i64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (target, scale) = match rest[..unit].to_ascii_lowercase().as_str() {
+            "y" => (&mut months, 12),
+            "mo" => (&mut months, 1),
+            "w" => (&mut days, 7),
+            "d" => (&mut days, 1),
+            "h" => (&mut nanoseconds, 3_600_000_000_000),
+            "m" => (&mut nanoseconds, 60_000_000_000),
+            "s" => (&mut nanoseconds, 1_000_000_000),
+            "ms" => (&mut nanoseconds, 1_000_000),
+            "us" | "µs" => (&mut nanoseconds, 1_000),
+            "ns" => (&mut nanoseconds, 1),
+            _ => return None,
+        };
+        *target = target.checked_add(amount.checked_mul(scale)?)?;
+        rest = &rest[unit..];
+    }
+
+    Some(CqlDuration {
+        months: i32::try_from(months).ok()? * sign,
+        days: i32::try_from(days).ok()? * sign,
+        nanoseconds: nanoseconds * i64::from(sign),
+    })
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(DbError::Invalid, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn scalars_accept_strings() {
+        assert_eq!(
+            from_json(&ColumnType::Int, json!("42")).unwrap(),
+            Some(CqlValue::Int(42))
+        );
+        assert_eq!(
+            from_json(&ColumnType::Boolean, json!("TRUE")).unwrap(),
+            Some(CqlValue::Boolean(true))
+        );
+        assert_eq!(
+            from_json(&ColumnType::Blob, json!("0x00ff")).unwrap(),
+            Some(CqlValue::Blob(vec![0, 255].into()))
+        );
+        assert_eq!(
+            from_json(&ColumnType::Timestamp, json!("2017-04-01 10:15:00+0000")).unwrap(),
+            Some(CqlValue::Timestamp(1491041700000))
+        );
+        assert_eq!(
+            from_json(&ColumnType::Date, json!("1970-01-02")).unwrap(),
+            Some(CqlValue::Date((1 << 31) + 1))
+        );
+        assert_eq!(
+            from_json(&ColumnType::Duration, json!("1y2d3h")).unwrap(),
+            Some(CqlValue::Duration(CqlDuration {
+                months: 12,
+                days: 2,
+                nanoseconds: 3 * 3_600_000_000_000,
+            }))
+        );
+        assert!(from_json(&ColumnType::TinyInt, json!(300)).is_err());
+        assert!(from_json(&ColumnType::Text, json!(1)).is_err());
+        assert_eq!(from_json(&ColumnType::Text, json!(null)).unwrap(), None);
+    }
+
+    /// Outputs of `toJson()` of cassandra 4.1
+    #[test]
+    fn values_are_rendered_like_cassandra() {
+        let text = |v: &str| CqlValue::Text(v.into());
+        let cases = [
+            (CqlValue::Blob(vec![0, 255].into()), r#""0x00ff""#),
+            (
+                CqlValue::Timestamp(1491041700123),
+                r#""2017-04-01 10:15:00.123Z""#,
+            ),
+            (CqlValue::Date((1 << 31) + 17257), r#""2017-04-01""#),
+            (
+                CqlValue::Time(36_900_123_000_000),
+                r#""10:15:00.123000000""#,
+            ),
+            (CqlValue::Double(1.0_f64.to_bits()), "1.0"),
+            (CqlValue::Double(1e20_f64.to_bits()), "1.0E20"),
+            (CqlValue::Double(0.0001_f64.to_bits()), "1.0E-4"),
+            (CqlValue::Float(1.1_f32.to_bits()), "1.1"),
+            (CqlValue::Double(f64::NAN.to_bits()), "NaN"),
+            (
+                CqlValue::Inet("::1".parse().unwrap()),
+                r#""0:0:0:0:0:0:0:1""#,
+            ),
+            (
+                CqlValue::Uuid("6ab09bec-e68e-48d9-a5f8-97e6fb4c9b47".parse().unwrap()),
+                r#""6ab09bec-e68e-48d9-a5f8-97e6fb4c9b47""#,
+            ),
+            (
+                CqlValue::Duration(CqlDuration {
+                    months: 14,
+                    days: 3,
+                    nanoseconds: 4 * 3_600_000_000_000 + 5_000,
+                }),
+                r#""1y2mo3d4h5us""#,
+            ),
+            (text("it's \"quoted\""), r#""it's \"quoted\"""#),
+            (CqlValue::List(vec![text("a"), text("b")]), r#"["a", "b"]"#),
+            (
+                CqlValue::Map(vec![(CqlValue::Int(1), text("a"))]),
+                r#"{"1": "a"}"#,
+            ),
+            (
+                CqlValue::Map(vec![(
+                    CqlValue::List(vec![CqlValue::Int(1), CqlValue::Int(2)]),
+                    CqlValue::Int(3),
+                )]),
+                r#"{"[1, 2]": 3}"#,
+            ),
+            (
+                CqlValue::Tuple(vec![CqlValue::Int(1), CqlValue::Empty]),
+                "[1, null]",
+            ),
+            (
+                CqlValue::UserDefinedType {
+                    keyspace: "cycling".to_owned(),
+                    type_name: "address".to_owned(),
+                    fields: vec![
+                        ("street".to_owned(), Some(text("Main"))),
+                        ("zipCode".to_owned(), None),
+                    ],
+                },
+                r#"{"street": "Main", "\"zipCode\"": null}"#,
+            ),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(to_json(&value), expected, "{value:?}");
+        }
+
+        let row = row_to_json(
+            ["id", "lastName"].into_iter(),
+            [Some(&CqlValue::Int(1)), None].into_iter(),
+        );
+        assert_eq!(row, r#"{"id": 1, "\"lastName\"": null}"#);
+    }
+
+    #[test]
+    fn rendered_values_are_decoded_back() {
+        let cases = [
+            (ColumnType::Blob, CqlValue::Blob(vec![1, 2].into())),
+            (ColumnType::Timestamp, CqlValue::Timestamp(1491041700123)),
+            (ColumnType::Date, CqlValue::Date((1 << 31) - 3)),
+            (ColumnType::Time, CqlValue::Time(36_900_123_000_001)),
+            (ColumnType::Double, CqlValue::Double(1.5e-9_f64.to_bits())),
+            (ColumnType::Inet, CqlValue::Inet("fe80::1".parse().unwrap())),
+            (
+                ColumnType::Duration,
+                CqlValue::Duration(CqlDuration {
+                    months: -1,
+                    days: -2,
+                    nanoseconds: -3,
+                }),
+            ),
+            (
+                ColumnType::Map(Box::new(ColumnType::Date), Box::new(ColumnType::Int)),
+                CqlValue::Map(vec![(CqlValue::Date(1 << 31), CqlValue::Int(1))]),
+            ),
+        ];
+
+        for (ty, value) in cases {
+            assert_eq!(parse(&ty, &to_json(&value)).unwrap(), Some(value));
+        }
+    }
+
+    #[test]
+    fn map_keys_are_decoded_as_key_type() {
+        let ty = ColumnType::Map(Box::new(ColumnType::Int), Box::new(ColumnType::Text));
+        assert_eq!(
+            from_json(&ty, json!({"2": "b", "1": "a"})).unwrap(),
+            Some(CqlValue::Map(vec![
+                (CqlValue::Int(1), CqlValue::Text("a".into())),
+                (CqlValue::Int(2), CqlValue::Text("b".into())),
+            ]))
+        );
+
+        let ty = ColumnType::Map(
+            Box::new(ColumnType::Tuple(vec![ColumnType::Int, ColumnType::Int])),
+            Box::new(ColumnType::Int),
+        );
+        assert_eq!(
+            from_json(&ty, json!({"[1, 2]": 3})).unwrap(),
+            Some(CqlValue::Map(vec![(
+                CqlValue::Tuple(vec![CqlValue::Int(1), CqlValue::Int(2)]),
+                CqlValue::Int(3)
+            )]))
+        );
+    }
+}
@@ -18,24 +18,20 @@ pub enum Literal {
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Literal::String(v) => write!(f, "'{v}'"),
+            Literal::String(v) => write!(f, "'{}'", v.replace('\'', "''")),
             Literal::Number(n) => n.fmt(f),
-            Literal::Float(v) => v.fmt(f),
+            // debug formatting keeps the fraction of whole numbers, so they are parsed back as floats
+            Literal::Float(v) => write!(f, "{v:?}"),
             Literal::List(values) => {
-                write!(f, "[")?;
-                for value in values {
-                    write!(f, "{}, ", value)?;
-                }
-                write!(f, "]")?;
-                Ok(())
+                let values = values.iter().map(ToString::to_string).collect::<Vec<_>>();
+                write!(f, "[{}]", values.join(", "))
             }
             Literal::Map(m) => {
-                write!(f, "{{")?;
-                for (k, v) in m {
-                    write!(f, "{}: {}, ", k, v)?;
-                }
-                write!(f, "}}")?;
-                Ok(())
+                let entries = m
+                    .iter()
+                    .map(|(key, value)| format!("{}: {value}", Literal::String(key.clone())))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", entries.join(", "))
             }
             Literal::Bool(b) => b.fmt(f),
             Literal::Null => write!(f, "null"),
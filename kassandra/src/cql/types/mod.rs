@@ -1,10 +1,13 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
-use strum::EnumString;
+use strum::{Display, EnumString};
 
+pub mod json;
 pub mod literal;
 pub mod value;
 
-#[derive(Clone, Debug, PartialEq, Eq, EnumString, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, EnumString, Display, Serialize, Deserialize)]
 #[strum(serialize_all = "lowercase")]
 pub enum NativeType {
     Ascii,
@@ -52,6 +55,28 @@ pub enum PreCqlType {
     },
 }
 
+/// Renders the type the way it is written in cql statements
+impl fmt::Display for PreCqlType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (ty, frozen) = match self {
+            PreCqlType::Native(native) => return write!(f, "{native}"),
+            PreCqlType::Tuple(items) => {
+                let items = items.iter().map(ToString::to_string).collect::<Vec<_>>();
+                return write!(f, "tuple<{}>", items.join(", "));
+            }
+            PreCqlType::List { item, frozen } => (format!("list<{item}>"), frozen),
+            PreCqlType::Set { item, frozen } => (format!("set<{item}>"), frozen),
+            PreCqlType::Map { key, value, frozen } => (format!("map<{key}, {value}>"), frozen),
+            PreCqlType::UserDefinedType { name, frozen } => (name.clone(), frozen),
+        };
+
+        match frozen {
+            true => write!(f, "frozen<{ty}>"),
+            false => write!(f, "{ty}"),
+        }
+    }
+}
+
 impl PreCqlType {
     pub fn freeze(mut self) -> PreCqlType {
         match self {
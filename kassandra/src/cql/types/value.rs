@@ -1,22 +1,28 @@
 use std::{
+    borrow::Cow,
+    cmp::Ordering,
     hash::{Hash, Hasher},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    ops::RangeBounds,
+    ops::{Bound, RangeBounds},
     str::FromStr,
 };
 
 use bigdecimal::BigDecimal;
+use bytes::Bytes;
+use bytestring::ByteString;
 use derive_more::From;
 use eyre::Result;
-use nom::number::complete::{be_f32, be_f64, be_i32, be_i64, be_u128, be_u32};
+use nom::number::complete::{
+    be_f32, be_f64, be_i16, be_i32, be_i64, be_i8, be_u128, be_u32, be_u8,
+};
 use num_bigint::BigInt;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    cql::{column::ColumnType, literal::Literal},
+    cql::{column::ColumnType, literal::Literal, schema::ClusteringOrder},
     error::DbError,
-    frame::{parse, response::error::Error},
+    frame::{parse, response::error::Error, write},
 };
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord, From)]
@@ -24,9 +30,11 @@ pub enum CqlValue {
     #[from(ignore)]
     Tuple(Vec<CqlValue>),
     #[from(ignore)]
-    Ascii(String),
+    Ascii(ByteString),
     Boolean(bool),
-    Blob(Vec<u8>),
+    #[from(types(Vec<u8>))]
+    #[serde(with = "blob")]
+    Blob(Bytes),
     #[from(ignore)]
     Counter(i64),
     Decimal(BigDecimal),
@@ -42,7 +50,8 @@ pub enum CqlValue {
     Float(u32),
     Int(i32),
     BigInt(i64),
-    Text(String),
+    #[from(types(String))]
+    Text(ByteString),
     /// Milliseconds since unix epoch
     #[from(ignore)]
     Timestamp(i64),
@@ -73,13 +82,34 @@ pub enum CqlValue {
     Empty,
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub struct CqlDuration {
     pub months: i32,
     pub days: i32,
     pub nanoseconds: i64,
 }
 
+/// Blobs keep the layout of `Vec<u8>` in persisted state
+mod blob {
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(blob: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(blob)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Bytes::from)
+    }
+}
+
+impl CqlValue {
+    /// Size of the value as it is written in protocol frames, including the length prefix.
+    pub fn serialized_size(&self) -> usize {
+        write::opt_cql_value_size(Some(self))
+    }
+}
+
 impl Hash for CqlValue {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
@@ -104,8 +134,8 @@ impl Hash for CqlValue {
             CqlValue::Double(value) => {
                 value.hash(state);
             }
-            CqlValue::Duration(_value) => {
-                unimplemented!()
+            CqlValue::Duration(value) => {
+                value.hash(state);
             }
             CqlValue::Empty => {}
             CqlValue::Float(value) => {
@@ -173,18 +203,19 @@ pub fn opt_deserialize_value<'a>(
 
 pub fn deserialize_value(data: &[u8], col: &ColumnType) -> Result<CqlValue, Error> {
     match col {
-        ColumnType::Custom(_) => {
-            unimplemented!()
-        }
+        ColumnType::Custom(_) => Err(unsupported_value(col)),
         ColumnType::Ascii => {
-            let ascii_str = std::str::from_utf8(data).unwrap().to_string();
-            Ok(CqlValue::Ascii(ascii_str))
+            let ascii_str = std::str::from_utf8(data)
+                .ok()
+                .filter(|it| it.is_ascii())
+                .ok_or_else(|| Error::new(DbError::ProtocolError, "Invalid ascii value"))?;
+            Ok(CqlValue::Ascii(ascii_str.into()))
         }
         ColumnType::Boolean => {
-            let boolean = data[0] != 0;
-            Ok(CqlValue::Boolean(boolean))
+            let (_, boolean) = be_u8::<_, nom::error::Error<_>>(data)?;
+            Ok(CqlValue::Boolean(boolean != 0))
         }
-        ColumnType::Blob => Ok(CqlValue::Blob(data.to_vec())),
+        ColumnType::Blob => Ok(CqlValue::Blob(Bytes::copy_from_slice(data))),
         ColumnType::Counter => {
             let (_, counter) = be_i64::<_, nom::error::Error<_>>(data)?;
             Ok(CqlValue::Counter(counter))
@@ -194,14 +225,29 @@ pub fn deserialize_value(data: &[u8], col: &ColumnType) -> Result<CqlValue, Erro
             Ok(CqlValue::Date(date))
         }
         ColumnType::Decimal => {
-            todo!()
+            let (unscaled, scale) = be_i32::<_, nom::error::Error<_>>(data)?;
+            Ok(CqlValue::Decimal(BigDecimal::new(
+                BigInt::from_signed_bytes_be(unscaled),
+                scale.into(),
+            )))
         }
         ColumnType::Double => {
             let (_, double) = be_f64::<_, nom::error::Error<_>>(data)?;
             Ok(CqlValue::Double(double.to_bits()))
         }
         ColumnType::Duration => {
-            todo!()
+            let (rest, months) = parse::signed_vint(data)?;
+            let (rest, days) = parse::signed_vint(rest)?;
+            let (_, nanoseconds) = parse::signed_vint(rest)?;
+            let (Ok(months), Ok(days)) = (months.try_into(), days.try_into()) else {
+                return Err(Error::new(DbError::ProtocolError, "Invalid duration value"));
+            };
+
+            Ok(CqlValue::Duration(CqlDuration {
+                months,
+                days,
+                nanoseconds,
+            }))
         }
         ColumnType::Float => {
             let (_, float) = be_f32::<_, nom::error::Error<_>>(data)?;
@@ -216,7 +262,10 @@ pub fn deserialize_value(data: &[u8], col: &ColumnType) -> Result<CqlValue, Erro
             Ok(CqlValue::BigInt(v))
         }
         ColumnType::Text => {
-            let s = String::from_utf8_lossy(data).into();
+            let s = match String::from_utf8_lossy(data) {
+                Cow::Borrowed(s) => s.into(),
+                Cow::Owned(s) => s.into(),
+            };
             Ok(CqlValue::Text(s))
         }
         ColumnType::Timestamp => {
@@ -288,20 +337,44 @@ pub fn deserialize_value(data: &[u8], col: &ColumnType) -> Result<CqlValue, Erro
 
             Ok(CqlValue::Set(set))
         }
-        ColumnType::UserDefinedType { .. } => {
-            todo!()
+        ColumnType::UserDefinedType {
+            type_name,
+            keyspace,
+            field_types,
+        } => {
+            // values can leave out trailing fields, the ones added to the type after they were written
+            let mut fields = vec![];
+            let mut rest = data;
+            for (name, ty) in field_types {
+                if rest.is_empty() {
+                    break;
+                }
+                let (r, value) = opt_deserialize_value(rest, ty)?;
+                fields.push((name.clone(), value));
+                rest = r;
+            }
+
+            Ok(CqlValue::UserDefinedType {
+                keyspace: keyspace.clone(),
+                type_name: type_name.clone(),
+                fields,
+            })
         }
         ColumnType::SmallInt => {
-            todo!()
+            let (_, v) = be_i16::<_, nom::error::Error<_>>(data)?;
+            Ok(CqlValue::SmallInt(v))
         }
         ColumnType::TinyInt => {
-            todo!()
+            let (_, v) = be_i8::<_, nom::error::Error<_>>(data)?;
+            Ok(CqlValue::TinyInt(v))
         }
         ColumnType::Time => {
-            todo!()
+            let (_, v) = be_i64::<_, nom::error::Error<_>>(data)?;
+            Ok(CqlValue::Time(v))
         }
         ColumnType::Timeuuid => {
-            todo!()
+            let (_, v) = be_u128::<_, nom::error::Error<_>>(data)?;
+            Ok(CqlValue::Timeuuid(Uuid::from_u128(v)))
         }
         ColumnType::Tuple(types) => {
             let mut result = vec![];
@@ -313,8 +386,7 @@ pub fn deserialize_value(data: &[u8], col: &ColumnType) -> Result<CqlValue, Erro
                     result.push(CqlValue::Empty);
                     continue;
                 };
-                let (_, value) = opt_deserialize_value(value, ty)?;
-                result.push(value.unwrap_or_default());
+                result.push(deserialize_value(value, ty)?);
             }
 
             Ok(CqlValue::Tuple(result))
@@ -324,18 +396,34 @@ pub fn deserialize_value(data: &[u8], col: &ColumnType) -> Result<CqlValue, Erro
             let v = Uuid::from_u128(v);
             Ok(CqlValue::Uuid(v))
         }
-        ColumnType::Varint => {
-            todo!()
-        }
+        ColumnType::Varint => Ok(CqlValue::Varint(BigInt::from_signed_bytes_be(data))),
     }
 }
 
+fn unsupported_value(col: &ColumnType) -> Error {
+    Error::new(
+        DbError::ProtocolError,
+        format!("Values of type {col} can't be deserialized yet"),
+    )
+}
+
 pub fn map_lit(col: &ColumnType, lit: Literal) -> Result<CqlValue, Error> {
     match (col, lit) {
         (_, Literal::Null) => Ok(CqlValue::Empty),
-        (ColumnType::Text, Literal::String(v)) => Ok(CqlValue::Text(v)),
+        (ColumnType::Text, Literal::String(v)) => Ok(CqlValue::Text(v.into())),
         (ColumnType::BigInt, Literal::Number(n)) => Ok(CqlValue::BigInt(n)),
         (ColumnType::Int, Literal::Number(n)) => Ok(CqlValue::Int(n as _)),
+        (ColumnType::Boolean, Literal::Bool(v)) => Ok(CqlValue::Boolean(v)),
+        (ColumnType::Double, Literal::Float(v)) => Ok(CqlValue::Double(v.to_bits())),
+        (ColumnType::Double, Literal::Number(n)) => Ok(CqlValue::Double((n as f64).to_bits())),
+        // milliseconds since unix epoch
+        (ColumnType::Timestamp, Literal::Number(n)) => Ok(CqlValue::Timestamp(n)),
+        (ColumnType::Varint, Literal::Number(n)) => Ok(CqlValue::Varint(n.into())),
+        (ColumnType::Decimal, Literal::Number(n)) => Ok(CqlValue::Decimal(n.into())),
+        // debug formatting is the shortest form which reads back as the same float, as it was written
+        (ColumnType::Decimal, Literal::Float(v)) => BigDecimal::from_str(&format!("{v:?}"))
+            .map(CqlValue::Decimal)
+            .map_err(|_| Error::new(DbError::Invalid, "invalid literal for decimal")),
         (ColumnType::Inet, Literal::String(v)) => {
             let addr = IpAddr::from_str(&v).map_err(|err| {
                 tracing::error!(value = ?v, ?err, "Could not parse inet literal");
@@ -353,6 +441,12 @@ pub fn map_lit(col: &ColumnType, lit: Literal) -> Result<CqlValue, Error> {
             Ok(CqlValue::Uuid(uuid))
         }
         (ColumnType::Uuid, Literal::Uuid(uuid)) => Ok(CqlValue::Uuid(uuid)),
+        (ColumnType::List(item_ty), Literal::List(literals)) => Ok(CqlValue::List(
+            literals
+                .into_iter()
+                .map(|item| map_lit(item_ty, item))
+                .collect::<Result<_, _>>()?,
+        )),
         (ColumnType::Set(item_ty), Literal::List(literals)) => Ok(CqlValue::Set(
             literals
                 .into_iter()
@@ -364,7 +458,7 @@ pub fn map_lit(col: &ColumnType, lit: Literal) -> Result<CqlValue, Error> {
                 map.into_iter()
                     .map(|(k, value)| {
                         let value = map_lit(value_ty, value)?;
-                        Ok((CqlValue::Text(k), value))
+                        Ok((CqlValue::Text(k.into()), value))
                     })
                     .collect::<Result<_, Error>>()?,
             ))
@@ -379,7 +473,9 @@ pub fn map_lit(col: &ColumnType, lit: Literal) -> Result<CqlValue, Error> {
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord, From)]
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord, From,
+)]
 pub enum ClusteringKeyValue {
     Simple(Option<CqlValue>),
     Composite(Vec<Option<CqlValue>>),
@@ -389,6 +485,24 @@ pub enum ClusteringKeyValue {
     Empty,
 }
 
+impl ClusteringKeyValue {
+    /// Compares clustering keys the way rows are laid out in a table with the given clustering order.
+    pub fn cmp_ordered(&self, other: &Self, order: &[ClusteringOrder]) -> std::cmp::Ordering {
+        self.into_iter()
+            .zip(other)
+            .enumerate()
+            .map(|(idx, (left, right))| {
+                order
+                    .get(idx)
+                    .copied()
+                    .unwrap_or_default()
+                    .apply(left.cmp(right))
+            })
+            .find(|it| it.is_ne())
+            .unwrap_or_else(|| self.cmp(other))
+    }
+}
+
 impl From<CqlValue> for ClusteringKeyValue {
     fn from(value: CqlValue) -> Self {
         match value {
@@ -413,12 +527,14 @@ impl<'a> IntoIterator for &'a ClusteringKeyValue {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ClusteringKeyValueRange {
     Full,
     From(ClusteringKeyValue),
     To(ClusteringKeyValue),
     Range(ClusteringKeyValue, ClusteringKeyValue),
+    /// Range of slice restrictions, which can exclude their bounds
+    Slice(Bound<ClusteringKeyValue>, Bound<ClusteringKeyValue>),
 }
 
 impl ClusteringKeyValueRange {
@@ -430,6 +546,31 @@ impl ClusteringKeyValueRange {
             Self::To(right) => Self::Range(left, right),
             Self::Range(old, right) if old < left => Self::Range(left, right),
             Self::Range(_, _) => self,
+            Self::Slice(..) => self.narrow(Bound::Included(left), Bound::Unbounded),
+        }
+    }
+
+    /// Intersection of the range with `start..end`
+    pub fn narrow(self, start: Bound<ClusteringKeyValue>, end: Bound<ClusteringKeyValue>) -> Self {
+        let start = tighter(self.start_bound().cloned(), start, Ordering::Greater);
+        let end = tighter(self.end_bound().cloned(), end, Ordering::Less);
+
+        Self::Slice(start, end)
+    }
+}
+
+/// Bound which excludes more, `further` is the direction it excludes values in
+fn tighter<T: Ord>(left: Bound<T>, right: Bound<T>, further: Ordering) -> Bound<T> {
+    match (&left, &right) {
+        (Bound::Unbounded, _) => right,
+        (_, Bound::Unbounded) => left,
+        (Bound::Included(l) | Bound::Excluded(l), Bound::Included(r) | Bound::Excluded(r)) => {
+            match l.cmp(r) {
+                Ordering::Equal if matches!(right, Bound::Excluded(_)) => right,
+                Ordering::Equal => left,
+                ordering if ordering == further => left,
+                _ => right,
+            }
         }
     }
 }
@@ -441,6 +582,7 @@ impl RangeBounds<ClusteringKeyValue> for ClusteringKeyValueRange {
             ClusteringKeyValueRange::From(v) => std::ops::Bound::Included(v),
             ClusteringKeyValueRange::To(_) => std::ops::Bound::Unbounded,
             ClusteringKeyValueRange::Range(v, _) => std::ops::Bound::Included(v),
+            ClusteringKeyValueRange::Slice(start, _) => start.as_ref(),
         }
     }
 
@@ -450,6 +592,7 @@ impl RangeBounds<ClusteringKeyValue> for ClusteringKeyValueRange {
             ClusteringKeyValueRange::From(_) => std::ops::Bound::Unbounded,
             ClusteringKeyValueRange::To(v) => std::ops::Bound::Included(v),
             ClusteringKeyValueRange::Range(_, v) => std::ops::Bound::Included(v),
+            ClusteringKeyValueRange::Slice(_, end) => end.as_ref(),
         }
     }
 }
@@ -478,7 +621,7 @@ impl From<std::ops::RangeToInclusive<ClusteringKeyValue>> for ClusteringKeyValue
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, PartialOrd, Ord)]
 pub enum PartitionKeyValue {
     Simple(CqlValue),
     Composite(Vec<CqlValue>),
@@ -510,62 +653,112 @@ impl<'a> IntoIterator for &'a PartitionKeyValue {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum PartitionKeyValueRange {
-    Full,
-    From(PartitionKeyValue),
-    To(PartitionKeyValue),
-    Range(PartitionKeyValue, PartitionKeyValue),
+/// Range of partitions in token order.
+/// Scans can additionally be resumed from a specific partition, which is how paging works.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PartitionKeyValueRange {
+    pub start: Bound<i64>,
+    pub end: Bound<i64>,
+    pub from_key: Option<PartitionKeyValue>,
 }
 
-impl RangeBounds<PartitionKeyValue> for PartitionKeyValueRange {
-    fn start_bound(&self) -> std::ops::Bound<&PartitionKeyValue> {
-        match self {
-            PartitionKeyValueRange::Full => std::ops::Bound::Unbounded,
-            PartitionKeyValueRange::From(v) => std::ops::Bound::Included(v),
-            PartitionKeyValueRange::To(_) => std::ops::Bound::Unbounded,
-            PartitionKeyValueRange::Range(v, _) => std::ops::Bound::Included(v),
+impl PartitionKeyValueRange {
+    pub fn tokens(start: Bound<i64>, end: Bound<i64>) -> Self {
+        Self {
+            start,
+            end,
+            from_key: None,
         }
     }
 
-    fn end_bound(&self) -> std::ops::Bound<&PartitionKeyValue> {
-        match self {
-            PartitionKeyValueRange::Full => std::ops::Bound::Unbounded,
-            PartitionKeyValueRange::From(_) => std::ops::Bound::Unbounded,
-            PartitionKeyValueRange::To(v) => std::ops::Bound::Included(v),
-            PartitionKeyValueRange::Range(_, v) => std::ops::Bound::Included(v),
+    pub fn from_key(self, key: PartitionKeyValue) -> Self {
+        Self {
+            from_key: Some(key),
+            ..self
         }
     }
-}
 
-impl From<std::ops::Range<PartitionKeyValue>> for PartitionKeyValueRange {
-    fn from(value: std::ops::Range<PartitionKeyValue>) -> Self {
-        PartitionKeyValueRange::Range(value.start, value.end)
+    /// `BTreeMap::range` panics on inverted ranges, so those have to be checked beforehand
+    pub fn is_empty(&self) -> bool {
+        match (self.start, self.end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+            (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            _ => false,
+        }
     }
 }
 
 impl From<std::ops::RangeFull> for PartitionKeyValueRange {
     fn from(_: std::ops::RangeFull) -> Self {
-        PartitionKeyValueRange::Full
+        Self::tokens(Bound::Unbounded, Bound::Unbounded)
     }
 }
 
 impl From<std::ops::RangeFrom<PartitionKeyValue>> for PartitionKeyValueRange {
     fn from(value: std::ops::RangeFrom<PartitionKeyValue>) -> Self {
-        Self::From(value.start)
-    }
-}
-
-impl From<std::ops::RangeToInclusive<PartitionKeyValue>> for PartitionKeyValueRange {
-    fn from(value: std::ops::RangeToInclusive<PartitionKeyValue>) -> Self {
-        Self::To(value.end)
+        Self::from(..).from_key(value.start)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::CqlValue;
-    use crate::cql::value::PartitionKeyValue;
+    use super::{deserialize_value, map_lit, CqlValue};
+    use crate::{
+        cql::{column::ColumnType, literal::Literal, value::PartitionKeyValue},
+        error::DbError,
+    };
+
+    #[test]
+    fn literals_of_native_types() {
+        for (ty, literal, value) in [
+            (
+                ColumnType::Boolean,
+                Literal::Bool(true),
+                CqlValue::Boolean(true),
+            ),
+            (
+                ColumnType::Double,
+                Literal::Float(0.25),
+                CqlValue::Double(0.25f64.to_bits()),
+            ),
+            (
+                ColumnType::Double,
+                Literal::Number(-2),
+                CqlValue::Double((-2f64).to_bits()),
+            ),
+            (
+                ColumnType::Timestamp,
+                Literal::Number(1_700_000_000_000),
+                CqlValue::Timestamp(1_700_000_000_000),
+            ),
+            (
+                ColumnType::List(Box::new(ColumnType::Int)),
+                Literal::List(vec![Literal::Number(1), Literal::Number(2)]),
+                CqlValue::List(vec![CqlValue::Int(1), CqlValue::Int(2)]),
+            ),
+        ] {
+            assert_eq!(map_lit(&ty, literal).unwrap(), value, "{ty}");
+        }
+    }
+
+    #[test]
+    fn malformed_values_are_protocol_errors() {
+        for (data, ty) in [
+            (&b""[..], ColumnType::Boolean),
+            (&[0xff, 0xfe][..], ColumnType::Ascii),
+            (&[0, 0, 1][..], ColumnType::Decimal),
+            (&[0xf0][..], ColumnType::Duration),
+            (
+                &[0, 0, 0, 1][..],
+                ColumnType::List(Box::new(ColumnType::Int)),
+            ),
+        ] {
+            let error = deserialize_value(data, &ty).unwrap_err();
+            assert_eq!(error.error, DbError::ProtocolError, "{ty}");
+        }
+    }
 
     #[test]
     fn test_composite_value_ranges() {
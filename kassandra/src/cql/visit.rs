@@ -0,0 +1,146 @@
+//! Traversal of parsed statements, used to rewrite them before they are executed.
+
+use std::fmt;
+
+use crate::cql::query::{DescribeQuery, QueryString, QueryValue, Resource, WhereClosure};
+
+/// Mutable traversal of a [`QueryString`].
+///
+/// Every method walks into the nested parts of the statement by default,
+/// implementations override only the parts they rewrite and call the `walk_*` function to keep going.
+pub trait VisitMut {
+    fn visit_query(&mut self, query: &mut QueryString) {
+        walk_query(self, query)
+    }
+
+    /// Every keyspace a statement names, either on its own or qualifying a table or a type
+    fn visit_keyspace(&mut self, _keyspace: &mut String) {}
+
+    /// Table of a statement, `keyspace` is `None` when the statement relies on the keyspace in use
+    fn visit_table(&mut self, keyspace: &mut Option<String>, _table: &mut String) {
+        if let Some(keyspace) = keyspace {
+            self.visit_keyspace(keyspace);
+        }
+    }
+
+    /// User defined type of `CREATE TYPE` and `DROP TYPE`
+    fn visit_type(&mut self, keyspace: &mut Option<String>, _name: &mut String) {
+        if let Some(keyspace) = keyspace {
+            self.visit_keyspace(keyspace);
+        }
+    }
+
+    fn visit_where(&mut self, r#where: &mut WhereClosure) {
+        walk_where(self, r#where)
+    }
+
+    /// Values of `VALUES`, `SET`, `WHERE` and `USING TIMESTAMP` clauses
+    fn visit_value(&mut self, _value: &mut QueryValue) {}
+}
+
+pub fn walk_query<V: VisitMut + ?Sized>(visitor: &mut V, query: &mut QueryString) {
+    match query {
+        QueryString::Select(s) => {
+            visitor.visit_table(&mut s.keyspace, &mut s.table);
+            visitor.visit_where(&mut s.r#where);
+        }
+        QueryString::Insert(s) => {
+            visitor.visit_table(&mut s.keyspace, &mut s.table);
+            for value in &mut s.values {
+                visitor.visit_value(value);
+            }
+            if let Some(using) = &mut s.using {
+                visitor.visit_value(&mut using.value);
+            }
+        }
+        QueryString::Update(s) => {
+            visitor.visit_table(&mut s.keyspace, &mut s.table);
+            if let Some(using) = &mut s.using {
+                visitor.visit_value(&mut using.value);
+            }
+            for (_, value) in &mut s.assignments {
+                visitor.visit_value(value);
+            }
+            visitor.visit_where(&mut s.r#where);
+        }
+        QueryString::Delete(s) => {
+            visitor.visit_table(&mut s.keyspace, &mut s.table);
+            if let Some(using) = &mut s.using {
+                visitor.visit_value(&mut using.value);
+            }
+            visitor.visit_where(&mut s.r#where);
+        }
+        QueryString::Batch(s) => {
+            if let Some(using) = &mut s.using {
+                visitor.visit_value(&mut using.value);
+            }
+            for statement in &mut s.statements {
+                visitor.visit_query(statement);
+            }
+        }
+        QueryString::Use { keyspace } => visitor.visit_keyspace(keyspace),
+        QueryString::CreateKeyspace(s) => visitor.visit_keyspace(&mut s.keyspace),
+        QueryString::AlterKeyspace(s) => visitor.visit_keyspace(&mut s.keyspace),
+        QueryString::CreateTable(s) => visitor.visit_table(&mut s.keyspace, &mut s.table),
+        QueryString::CreateType(s) => visitor.visit_type(&mut s.keyspace, &mut s.name),
+        QueryString::DropKeyspace(s) => visitor.visit_keyspace(&mut s.keyspace),
+        QueryString::DropTable(s) => visitor.visit_table(&mut s.keyspace, &mut s.table),
+        QueryString::DropType(s) => visitor.visit_type(&mut s.keyspace, &mut s.name),
+        QueryString::Describe(DescribeQuery::Keyspace(Some(keyspace))) => {
+            visitor.visit_keyspace(keyspace)
+        }
+        QueryString::Describe(DescribeQuery::Table { keyspace, table }) => {
+            visitor.visit_table(keyspace, table)
+        }
+        QueryString::Describe(
+            DescribeQuery::Keyspace(None)
+            | DescribeQuery::Keyspaces
+            | DescribeQuery::Tables
+            | DescribeQuery::Schema,
+        ) => {}
+        QueryString::Explain(statement) => visitor.visit_query(statement),
+        QueryString::Grant(s) | QueryString::Revoke(s) => match &mut s.resource {
+            Resource::Keyspace(keyspace) => visitor.visit_keyspace(keyspace),
+            Resource::Table { keyspace, table } => visitor.visit_table(keyspace, table),
+            Resource::AllKeyspaces => {}
+        },
+        QueryString::CreateRole(_) | QueryString::DropRole(_) => {}
+    }
+}
+
+pub fn walk_where<V: VisitMut + ?Sized>(visitor: &mut V, r#where: &mut WhereClosure) {
+    for (_, value) in &mut r#where.statements {
+        visitor.visit_value(value);
+    }
+    for relation in &mut r#where.token {
+        visitor.visit_value(&mut relation.value);
+    }
+    for (_, value) in &mut r#where.like {
+        visitor.visit_value(value);
+    }
+    for relation in &mut r#where.clustering {
+        for value in &mut relation.values {
+            visitor.visit_value(value);
+        }
+    }
+}
+
+/// Rewrite a session applies to the statements it receives, before they are prepared or executed.
+///
+/// Closures taking `&mut QueryString` are rewrites, so are [`VisitMut`] implementations wrapped into one:
+/// `move |query: &mut QueryString| visitor.clone().visit_query(query)`.
+pub trait Rewrite: Send + Sync {
+    fn rewrite(&self, query: &mut QueryString);
+}
+
+impl<F: Fn(&mut QueryString) + Send + Sync> Rewrite for F {
+    fn rewrite(&self, query: &mut QueryString) {
+        self(query)
+    }
+}
+
+impl fmt::Debug for dyn Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Rewrite")
+    }
+}
@@ -165,6 +165,10 @@ pub enum DbError {
     #[error("Invalid protocol message received from the driver")]
     ProtocolError,
 
+    /// The query uses a feature kassandra does not implement, sent to drivers as `Invalid`
+    #[error("The query uses a feature kassandra does not implement")]
+    Unimplemented,
+
     /// Other error code not specified in the specification
     #[error("Other error not specified in the specification. Error code: {0}")]
     Other(i32),
@@ -217,7 +221,7 @@ impl DbError {
             } => 0x1500,
             DbError::SyntaxError => 0x2000,
             DbError::Unauthorized => 0x2100,
-            DbError::Invalid => 0x2200,
+            DbError::Invalid | DbError::Unimplemented => 0x2200,
             DbError::ConfigError => 0x2300,
             DbError::AlreadyExists {
                 keyspace: _,
@@ -272,8 +276,18 @@ impl WriteType {
 impl FromStr for WriteType {
     type Err = Infallible;
 
-    fn from_str(_s: &str) -> Result<Self, Self::Err> {
-        todo!()
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "SIMPLE" => WriteType::Simple,
+            "BATCH" => WriteType::Batch,
+            "UNLOGGED_BATCH" => WriteType::UnloggedBatch,
+            "COUNTER" => WriteType::Counter,
+            "BATCH_LOG" => WriteType::BatchLog,
+            "CAS" => WriteType::Cas,
+            "VIEW" => WriteType::View,
+            "CDC" => WriteType::Cdc,
+            other => WriteType::Other(other.to_owned()),
+        })
     }
 }
 
@@ -0,0 +1,248 @@
+//! Export of a session state as cql statements, which recreate it on any cassandra cluster.
+
+use chrono::{Duration, NaiveDate};
+
+use crate::{
+    cql::{
+        column::ColumnType,
+        schema::{keyspace::UserDefinedType, system::is_system_keyspace, ColumnKind, Table},
+        value::{ClusteringKeyValue, CqlDuration, CqlValue, PartitionKeyValue},
+    },
+    session::SessionHandle,
+    storage::memory::{self, RowValues},
+};
+
+/// `CREATE KEYSPACE`, `CREATE TYPE`, `CREATE TABLE` statements of user keyspaces
+/// followed by a statement per row, a statement per line.
+///
+/// Rows are written with `INSERT`, or `UPDATE` for counter tables, without timestamps
+/// and deleted rows are left out, so only the live data is reproduced.
+pub fn to_cql_script(session: &SessionHandle) -> String {
+    let engine = session.engine();
+    let mut script = vec![];
+
+    for keyspace in engine.schema().values() {
+        if is_system_keyspace(&keyspace.name) {
+            continue;
+        }
+
+        script.push(keyspace.create_statement());
+        script.extend(keyspace.user_defined_types.values().map(create_type));
+        for table in keyspace.tables.values() {
+            script.push(table.create_statement());
+
+            let data = engine
+                .data
+                .data
+                .get(&keyspace.name)
+                .and_then(|it| it.get(&table.name));
+            if let Some(data) = data {
+                for (partition_key, clustering_key, row) in data.rows() {
+                    script.push(write_statement(
+                        table,
+                        data,
+                        partition_key,
+                        clustering_key,
+                        row,
+                    ));
+                }
+            }
+        }
+    }
+
+    script.into_iter().map(|it| it + "\n").collect()
+}
+
+pub(crate) fn create_type(ty: &UserDefinedType) -> String {
+    let fields = ty
+        .field_types
+        .iter()
+        .map(|(name, ty)| format!("{name} {ty}"))
+        .collect::<Vec<_>>();
+
+    format!(
+        "CREATE TYPE {}.{} ({});",
+        ty.keyspace,
+        ty.name,
+        fields.join(", ")
+    )
+}
+
+fn write_statement(
+    table: &Table,
+    data: &memory::Table,
+    partition_key: &PartitionKeyValue,
+    clustering_key: &ClusteringKeyValue,
+    row: &RowValues,
+) -> String {
+    let schema = &table.schema;
+    let partition_values = match partition_key {
+        PartitionKeyValue::Simple(value) => vec![Some(value)],
+        PartitionKeyValue::Composite(values) => values.iter().map(Some).collect(),
+        PartitionKeyValue::Empty => vec![],
+    };
+    let clustering_values = match clustering_key {
+        ClusteringKeyValue::Simple(value) => vec![value.as_ref()],
+        ClusteringKeyValue::Composite(values) => values.iter().map(Option::as_ref).collect(),
+        ClusteringKeyValue::Empty => vec![],
+    };
+    let key = schema
+        .partition_key
+        .into_iter()
+        .zip(partition_values)
+        .chain(schema.clustering_key.into_iter().zip(clustering_values))
+        .filter_map(|(name, value)| Some((name, literal(value?, &schema.columns[name].ty))))
+        .collect::<Vec<_>>();
+
+    let values = schema
+        .columns
+        .iter()
+        .filter(|(_, column)| matches!(column.kind, ColumnKind::Regular | ColumnKind::Static))
+        .filter_map(|(name, column)| {
+            let value = data.cell(row, name)?.value.as_ref()?;
+            Some((name, column, literal(value, &column.ty)))
+        })
+        .collect::<Vec<_>>();
+
+    if values
+        .iter()
+        .any(|(_, column, _)| column.ty == ColumnType::Counter)
+    {
+        let assignments = values
+            .iter()
+            .map(|(name, _, value)| format!("{name} = {name} + {value}"));
+        let restrictions = key.iter().map(|(name, value)| format!("{name} = {value}"));
+
+        return format!(
+            "UPDATE {}.{} SET {} WHERE {};",
+            table.keyspace,
+            table.name,
+            assignments.collect::<Vec<_>>().join(", "),
+            restrictions.collect::<Vec<_>>().join(" AND ")
+        );
+    }
+
+    let (columns, values): (Vec<_>, Vec<_>) = key
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .chain(
+            values
+                .iter()
+                .map(|(name, _, value)| (name.as_str(), value.as_str())),
+        )
+        .unzip();
+
+    format!(
+        "INSERT INTO {}.{} ({}) VALUES ({});",
+        table.keyspace,
+        table.name,
+        columns.join(", "),
+        values.join(", ")
+    )
+}
+
+/// Empty values have no literal of their own, they are written as empty blobs converted to the column type
+pub(crate) fn literal(value: &CqlValue, ty: &ColumnType) -> String {
+    match (value, ty) {
+        (CqlValue::Empty, ColumnType::Text | ColumnType::Ascii) => "''".to_owned(),
+        (CqlValue::Empty, ty) => {
+            let ty = ty.to_string();
+            let (first, rest) = ty.split_at(1);
+            format!("blobAs{}{rest}(0x)", first.to_uppercase())
+        }
+        (value, _) => nested_literal(value),
+    }
+}
+
+fn nested_literal(value: &CqlValue) -> String {
+    let join = |values: &mut dyn Iterator<Item = String>| values.collect::<Vec<_>>().join(", ");
+
+    match value {
+        CqlValue::Ascii(v) => quoted(v),
+        CqlValue::Text(v) => quoted(v),
+        CqlValue::Boolean(v) => v.to_string(),
+        CqlValue::Blob(v) => {
+            let hex = v.iter().map(|byte| format!("{byte:02x}"));
+            format!("0x{}", hex.collect::<String>())
+        }
+        CqlValue::Counter(v) | CqlValue::BigInt(v) | CqlValue::Timestamp(v) => v.to_string(),
+        CqlValue::Decimal(v) => v.to_string(),
+        CqlValue::Varint(v) => v.to_string(),
+        CqlValue::Int(v) => v.to_string(),
+        CqlValue::SmallInt(v) => v.to_string(),
+        CqlValue::TinyInt(v) => v.to_string(),
+        CqlValue::Double(v) => float(f64::from_bits(*v)),
+        CqlValue::Float(v) => float(f32::from_bits(*v).into()),
+        CqlValue::Date(v) => {
+            let days = i64::from(*v) - (1 << 31);
+            match NaiveDate::default().checked_add_signed(Duration::days(days)) {
+                Some(date) => format!("'{date}'"),
+                None => v.to_string(),
+            }
+        }
+        CqlValue::Time(v) => {
+            let (seconds, nanoseconds) = (v / 1_000_000_000, v % 1_000_000_000);
+            format!(
+                "'{:02}:{:02}:{:02}.{nanoseconds:09}'",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            )
+        }
+        CqlValue::Duration(v) => duration(v),
+        CqlValue::Inet(v) => quoted(&v.to_string()),
+        CqlValue::Uuid(v) | CqlValue::Timeuuid(v) => v.to_string(),
+        CqlValue::List(values) => format!("[{}]", join(&mut values.iter().map(nested_literal))),
+        CqlValue::Set(values) => format!("{{{}}}", join(&mut values.iter().map(nested_literal))),
+        CqlValue::Map(entries) => {
+            let mut entries = entries
+                .iter()
+                .map(|(key, value)| format!("{}: {}", nested_literal(key), nested_literal(value)));
+            format!("{{{}}}", join(&mut entries))
+        }
+        CqlValue::Tuple(values) => format!("({})", join(&mut values.iter().map(nested_literal))),
+        CqlValue::UserDefinedType { fields, .. } => {
+            let mut fields = fields.iter().map(|(name, value)| {
+                let value = value.as_ref().map(nested_literal);
+                format!("{name}: {}", value.as_deref().unwrap_or("null"))
+            });
+            format!("{{{}}}", join(&mut fields))
+        }
+        // empty elements of tuples are nulls
+        CqlValue::Empty => "null".to_owned(),
+    }
+}
+
+fn quoted(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn float(value: f64) -> String {
+    match value {
+        v if v.is_nan() => "NaN".to_owned(),
+        v if v.is_infinite() && v > 0.0 => "Infinity".to_owned(),
+        v if v.is_infinite() => "-Infinity".to_owned(),
+        v => format!("{v:?}"),
+    }
+}
+
+/// Components of a duration have the same sign, it is written once in front of them
+fn duration(duration: &CqlDuration) -> String {
+    let CqlDuration {
+        months,
+        days,
+        nanoseconds,
+    } = *duration;
+    let sign = if months < 0 || days < 0 || nanoseconds < 0 {
+        "-"
+    } else {
+        ""
+    };
+
+    format!(
+        "{sign}{}mo{}d{}ns",
+        months.unsigned_abs(),
+        days.unsigned_abs(),
+        nanoseconds.unsigned_abs()
+    )
+}
@@ -0,0 +1,351 @@
+//! Frames of the native protocol v4 the way Cassandra and its drivers put them on the wire,
+//! and [`assert_roundtrip`] to check the codecs read and write them back byte for byte.
+//!
+//! Every fixture states its [`Origin`]: most are written by hand after the spec,
+//! the `conformance` keyspace and its tables they refer to don't exist anywhere.
+//!
+//! Frames are kept as raw bytes, so changes of the codecs (new protocol versions, compression)
+//! can't change the fixtures along with them.
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::frame::{
+    parse,
+    request::{Request, RequestFrameCodec, RequestOpcode},
+    response::{Response, ResponseFrameCodec, ResponseOpcode},
+};
+
+/// Whole frame, header included
+#[derive(Debug, Clone, Copy)]
+pub struct Fixture {
+    pub name: &'static str,
+    pub origin: Origin,
+    pub bytes: &'static [u8],
+}
+
+/// Where the bytes of a fixture come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Written by hand after the native protocol v4 spec, messages of errors are modelled on Cassandra's
+    Handcrafted,
+    /// Sent by the driver to a `KassandraTester` session and captured by a TCP relay between them
+    Captured { driver: &'static str },
+}
+
+/// Requests of drivers connecting and querying, and responses of every kind of result
+/// and of the errors drivers react to. Rows cover every type kassandra can write back,
+/// including nulls and left out trailing fields of user defined types.
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "startup",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\0\x01\0\0\0Q\0\x03\0\x0bCQL_VERSION\0\x053.0.0\0\x0bDRIVER_NAME\0\x14DataStax J\
+        ava Driver\0\x0eDRIVER_VERSION\0\x064.17.0",
+    },
+    Fixture {
+        name: "options",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\0\x05\0\0\0\0",
+    },
+    Fixture {
+        name: "ready",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\0\x02\0\0\0\0",
+    },
+    Fixture {
+        name: "supported",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\0\x06\0\0\0`\0\x03\0\x0bCOMPRESSION\0\x02\0\x06snappy\0\x03lz4\0\x0bCQL_VERSION\
+        \0\x01\0\x053.4.6\0\x11PROTOCOL_VERSIONS\0\x03\0\x043/v3\0\x044/v4\0\x095/v5-beta",
+    },
+    Fixture {
+        name: "authenticate",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\0\x03\0\0\x001\0/org.apache.cassandra.auth.PasswordAuthenticator",
+    },
+    Fixture {
+        name: "auth_response",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\x01\x0f\0\0\0\x18\0\0\0\x14\0cassandra\0cassandra",
+    },
+    Fixture {
+        name: "auth_success",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x01\x10\0\0\0\x04\xff\xff\xff\xff",
+    },
+    Fixture {
+        name: "register",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\x02\x0b\0\0\x001\0\x03\0\x0fTOPOLOGY_CHANGE\0\x0dSTATUS_CHANGE\0\x0dSCHEMA_CHANGE",
+    },
+    Fixture {
+        name: "query",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\x03\x07\0\0\0?\0\0\0,SELECT * FROM system.local WHERE key='local'\0\x01$\0\0\
+        \x13\x88\0\x06\x07r\xea^\xa0\0",
+    },
+    Fixture {
+        name: "rows_of_every_type",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x04\x08\0\0\x02:\0\0\0\x02\0\0\0\x01\0\0\0\x14\0\x0bconformance\0\x05types\0\
+        \x05ascii\0\x01\0\x06bigint\0\x02\0\x04blob\0\x03\0\x07boolean\0\x04\0\x04date\0\x11\0\
+        \x06double\0\x07\0\x05float\0\x08\0\x04inet\0\x10\0\x05inet6\0\x10\0\x03int\0\x09\0\x08s\
+        mallint\0\x13\0\x04text\0\x0d\0\x04time\0\x12\0\x09timestamp\0\x0b\0\x08timeuuid\0\x0f\0\
+        \x07tinyint\0\x14\0\x04uuid\0\x0c\0\x04list\0 \0\x09\0\x03map\0!\0\x0d\0\x09\0\x03set\0\
+        \x22\0\x0d\0\0\0\x02\0\0\0\x05ascii\0\0\0\x08\xff\xff\xff\xfd\xe7\x8e\xe6\0\0\0\0\x02\
+        \xca\xfe\0\0\0\x01\x01\0\0\0\x04\x80\0J8\0\0\0\x08@\x0c\0\0\0\0\0\0\0\0\0\x04\xbf\xa0\0\
+        \0\0\0\0\x04\x7f\0\0\x01\0\0\0\x10\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\0\0\x04\0\0\0*\0\
+        \0\0\x02\xff\xf9\0\0\0\x09tekst \xe2\x9c\x93\0\0\0\x08\0\0\x03b\xd4\x17\xae\0\0\0\0\x08\
+        \0\0\x01\x8b\x1fwd\0\0\0\0\x10\xf0\xb6-@h`\x11\xee\x8c\x99\x02B\xac\x12\0\x02\0\0\0\x01\
+        \xfd\0\0\0\x10Kz\x1aT\x0b\xbaN\x8c\x9c\x1a/]\x0c>\x8a\x11\0\0\0\x14\0\0\0\x02\0\0\0\x04\
+        \0\0\0\x01\0\0\0\x04\0\0\0\x02\0\0\0\x1e\0\0\0\x02\0\0\0\x01a\0\0\0\x04\0\0\0\x01\0\0\0\
+        \x01b\0\0\0\x04\0\0\0\x02\0\0\0\x0e\0\0\0\x02\0\0\0\x01x\0\0\0\x01y\0\0\0\x03key\xff\xff\
+        \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+        \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+        \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+        \xff\xff\xff\xff\xff\xff\xff\xff",
+    },
+    Fixture {
+        name: "rows_of_udt_tuple_decimal_varint_duration",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x05\x08\0\0\0\xf8\0\0\0\x02\0\0\0\x01\0\0\0\x05\0\x0bconformance\0\x06exo\
+        tic\0\x05price\0\x06\0\x05count\0\x0e\0\x06period\0\x15\0\x07address\0\x30\0\x0bconformance\
+        \0\x07address\0\x02\0\x06street\0\x0d\0\x03zip\0\x09\0\x05point\0\x31\0\x02\0\x09\0\x0d\0\0\
+        \0\x02\0\0\0\x06\0\0\0\x02\x01:\0\0\0\x09\x01\0\0\0\0\0\0\0\0\0\0\0\x08\x02\x03\xf8)\xe8\
+        \xd6\x08\0\0\0\0\x13\0\0\0\x07Main St\0\0\0\x04\0\0'\x83\0\0\0\x10\0\0\0\x04\xff\xff\xff\
+        \xf9\0\0\0\x04east\0\0\0\x05\0\0\0\x01\xfb\0\0\0\x02\xff\x7f\0\0\0\x03\0\0\x01\0\0\0\x08\0\
+        \0\0\0\xff\xff\xff\xff\xff\xff\xff\xff",
+    },
+    Fixture {
+        name: "rows_without_global_spec",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x03\x08\0\0\0^\0\0\0\x02\0\0\0\0\0\0\0\x02\0\x06system\0\x05local\0\x03key\0\
+        \x0d\0\x06system\0\x05local\0\x0ccluster_name\0\x0d\0\0\0\x01\0\0\0\x05local\0\0\0\x0cTe\
+        st Cluster",
+    },
+    Fixture {
+        name: "rows_without_metadata",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x05\x08\0\0\0\x10\0\0\0\x02\0\0\0\x04\0\0\0\0\0\0\0\0",
+    },
+    Fixture {
+        name: "void",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x06\x08\0\0\0\x04\0\0\0\x01",
+    },
+    Fixture {
+        name: "set_keyspace",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x07\x08\0\0\0\x11\0\0\0\x03\0\x0bconformance",
+    },
+    Fixture {
+        name: "schema_change",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x08\x08\0\0\0(\0\0\0\x05\0\x07CREATED\0\x05TABLE\0\x0bconformance\0\x05types",
+    },
+    Fixture {
+        name: "warned_void",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\x08\0\x09\x08\0\0\0\xad\0\x01\0\xa5Unlogged batch covering 2 partitions detected ag\
+        ainst table [conformance.types]. You should use a logged batch for atomicity, or asynchr\
+        onous writes for performance.\0\0\0\x01",
+    },
+    Fixture {
+        name: "event",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\xff\xff\x0c\0\0\0/\0\x0dSCHEMA_CHANGE\0\x07DROPPED\0\x08KEYSPACE\0\x0bconformance",
+    },
+    Fixture {
+        name: "syntax_error",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x0a\0\0\0\0B\0\0 \0\0<line 1:0 no viable alternative at input 'SELEC' ([SELEC].\
+        ..)",
+    },
+    Fixture {
+        name: "invalid",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x0b\0\0\0\0 \0\0\x22\0\0\x1aunconfigured table missing",
+    },
+    Fixture {
+        name: "unavailable",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x0c\0\0\0\x007\0\0\x10\0\0'Cannot achieve consistency level QUORUM\0\x04\0\0\0\
+        \x02\0\0\0\x01",
+    },
+    Fixture {
+        name: "read_timeout",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x0d\0\0\0\0A\0\0\x12\0\x000Operation timed out - received only 0 responses.\0\x01\
+        \0\0\0\0\0\0\0\x01\0",
+    },
+    Fixture {
+        name: "write_timeout",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x0e\0\0\0\0H\0\0\x11\0\x000Operation timed out - received only 1 responses.\0\x06\
+        \0\0\0\x01\0\0\0\x02\0\x06SIMPLE",
+    },
+    Fixture {
+        name: "already_exists",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x0f\0\0\0\0?\0\0$\0\0*Cannot add existing keyspace \x22conformance\x22\0\x0bcon\
+        formance\0\0",
+    },
+    Fixture {
+        name: "unprepared",
+        origin: Origin::Handcrafted,
+        bytes: b"\x84\0\0\x10\0\0\0\0Y\0\0%\0\0APrepared query with ID 0123456789abcdef0123456789abcdef n\
+        ot found\0\x10\x01#Eg\x89\xab\xcd\xef\x01#Eg\x89\xab\xcd\xef",
+    },
+    Fixture {
+        name: "prepare",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\x11\x09\0\0\0<\0\0\x008INSERT INTO conformance.types (ascii, int) VALUES (?, ?)",
+    },
+    Fixture {
+        name: "execute",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\x12\x0a\0\0\0&\0\x10\x01#Eg\x89\xab\xcd\xef\x01#Eg\x89\xab\xcd\xef\0\x01\x01\0\
+        \x02\0\0\0\x03key\0\0\0\x04\0\0\0*",
+    },
+    Fixture {
+        name: "batch",
+        origin: Origin::Handcrafted,
+        bytes: b"\x04\0\0\x13\x0d\0\0\0i\0\0\x02\0\0\0\0:INSERT INTO conformance.types (ascii, int) VALUE\
+        S ('a', 1)\0\0\x01\0\x10\x01#Eg\x89\xab\xcd\xef\x01#Eg\x89\xab\xcd\xef\0\x02\0\0\0\x01b\
+        \0\0\0\x04\0\0\0\x02\0\x01\0",
+    },
+    Fixture {
+        name: "scylla_startup",
+        origin: Origin::Captured {
+            driver: "scylla-rust-driver 0.10.2",
+        },
+        bytes: b"\x04\0\0\0\x01\0\0\0O\0\x03\0\x0eDRIVER_VERSION\0\x060.10.2\0\x0bCQL_VERSION\0\x054.0.0\0\x0bDRIV\
+        ER_NAME\0\x12scylla-rust-driver",
+    },
+    Fixture {
+        name: "scylla_query",
+        origin: Origin::Captured {
+            driver: "scylla-rust-driver 0.10.2",
+        },
+        bytes: b"\x04\0\0\0\x07\0\0\0:\0\0\0\x31CREATE TABLE ks.t (id int PRIMARY KEY, name text)\0\x06\x10\0\x09",
+    },
+    Fixture {
+        name: "scylla_prepare",
+        origin: Origin::Captured {
+            driver: "scylla-rust-driver 0.10.2",
+        },
+        bytes: b"\x04\0\0\0\x09\0\0\0-\0\0\0)insert into ks.t (id, name) values (?, ?)",
+    },
+    Fixture {
+        name: "scylla_logged_batch",
+        origin: Origin::Captured {
+            driver: "scylla-rust-driver 0.10.2",
+        },
+        bytes: b"\x04\0\0\0\x0d\0\0\0r\0\0\x02\0\0\0\0)insert into ks.t (id, name) values (?, ?)\0\x02\0\0\
+        \0\x04\0\0\0\x01\0\0\0\x05first\x01\0\x10\xccY\x85+\xfak\xdc\x12\x83\x09\xfc\xaf\xe7\x22\xe9\x8b\0\x02\0\
+        \0\0\x04\0\0\0\x02\xff\xff\xff\xff\0\x060\0\x09\0\x06\x0a$\x18\x1e@\0",
+    },
+];
+
+/// Decodes the frame, request or response by the direction bit of its version,
+/// and checks encoding it again gives the same bytes.
+///
+/// Maps of `STARTUP` and `SUPPORTED` are compared by their entries, they have no order on the wire.
+pub fn assert_roundtrip(bytes: &[u8]) {
+    if let Err(er) = roundtrip(bytes) {
+        panic!("{er}");
+    }
+}
+
+/// [`assert_roundtrip`] reporting how the frame differs instead of panicking
+pub fn roundtrip(bytes: &[u8]) -> Result<(), String> {
+    let mut src = BytesMut::from(bytes);
+    let mut dst = BytesMut::new();
+
+    let unordered = match bytes.first() {
+        Some(version) if version & 0x80 != 0 => {
+            let (frame, opcode, body) = ResponseFrameCodec
+                .decode(&mut src)
+                .map_err(|er| format!("invalid response frame: {er}"))?
+                .ok_or("incomplete response frame")?;
+            let response = Response::deserialize(opcode, &body, frame.flags)
+                .map_err(|er| format!("could not read {opcode:?} response: {er}"))?;
+            ResponseFrameCodec
+                .encode((response, frame.stream), &mut dst)
+                .map_err(|er| format!("response is not encoded: {er}"))?;
+
+            opcode == ResponseOpcode::Supported
+        }
+        Some(_) => {
+            let (frame, opcode, body) = RequestFrameCodec
+                .decode(&mut src)
+                .map_err(|er| format!("invalid request frame: {er}"))?
+                .ok_or("incomplete request frame")?;
+            let request = Request::deserialize(opcode, &body, frame.flags)
+                .map_err(|er| format!("could not read {opcode:?} request: {er}"))?;
+            RequestFrameCodec
+                .encode((request, frame), &mut dst)
+                .map_err(|er| format!("request is not encoded: {er}"))?;
+
+            opcode == RequestOpcode::Startup
+        }
+        None => return Err("empty frame".to_owned()),
+    };
+    if !src.is_empty() {
+        return Err(format!("{} bytes left after the frame", src.len()));
+    }
+
+    if unordered {
+        if dst[..9] != bytes[..9] {
+            return Err(format!(
+                "headers differ\n encoded: {}\nexpected: {}",
+                hex(&dst[..9]),
+                hex(&bytes[..9])
+            ));
+        }
+        if parse::string_multimap(&dst[9..]).ok() != parse::string_multimap(&bytes[9..]).ok()
+            || parse::string_map(&dst[9..]).ok() != parse::string_map(&bytes[9..]).ok()
+        {
+            return Err("maps differ".to_owned());
+        }
+        return Ok(());
+    }
+
+    if dst[..] != *bytes {
+        let at = dst.iter().zip(bytes).take_while(|(a, b)| a == b).count();
+        return Err(format!(
+            "frame differs at byte {at}\n encoded: {}\nexpected: {}",
+            hex(&dst),
+            hex(bytes)
+        ));
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|it| format!("{it:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_roundtrip, roundtrip, FIXTURES};
+
+    #[test]
+    fn fixtures_roundtrip() {
+        for fixture in FIXTURES {
+            if let Err(er) = roundtrip(fixture.bytes) {
+                panic!("fixture {}: {er}", fixture.name);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "frame differs at byte 41")]
+    fn changed_bytes_are_reported() {
+        // a boolean sent as 2 is read as true, which is written back as 1
+        assert_roundtrip(
+            b"\x84\0\0\0\x08\0\0\0!\0\0\0\x02\0\0\0\x01\0\0\0\x01\0\x02ks\0\x01t\0\x01b\0\x04\0\0\0\x01\0\0\0\x01\x02",
+        );
+    }
+}
@@ -9,8 +9,11 @@ use crate::frame::{
     response::{Response, ResponseFrameCodec, ResponseOpcode},
 };
 
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
 pub mod consistency;
 pub mod parse;
+mod pool;
 pub mod request;
 pub mod response;
 pub mod value;
@@ -1,8 +1,10 @@
 use std::{
     collections::HashMap,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
 };
 
+use bigdecimal::BigDecimal;
+use bytes::Bytes;
 use integer_encoding::VarIntReader;
 use nom::{
     bytes::complete::take,
@@ -12,12 +14,13 @@ use nom::{
     sequence::pair,
     IResult,
 };
+use num_bigint::BigInt;
 use uuid::Uuid;
 
 use crate::{
     cql::{
         schema::{ColumnType, PrimaryKeyColumn},
-        value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+        value::{deserialize_value, ClusteringKeyValue, CqlDuration, CqlValue, PartitionKeyValue},
     },
     frame::{
         consistency::{Consistency, LegacyConsistency, SerialConsistency},
@@ -28,14 +31,16 @@ use crate::{
 pub fn short_string(input: &[u8]) -> IResult<&[u8], &str> {
     let (rest, n) = complete::be_u16(input)?;
     let (rest, bytes) = take(n as usize)(rest)?;
-    let s = std::str::from_utf8(bytes).unwrap();
+    let s = std::str::from_utf8(bytes)
+        .map_err(|_| nom::Err::Failure(error::Error::new(input, ErrorKind::Char)))?;
     Ok((rest, s))
 }
 
 pub fn long_string(input: &[u8]) -> IResult<&[u8], &str> {
     let (rest, n) = complete::be_u32(input)?;
     let (rest, bytes) = take(n as usize)(rest)?;
-    let s = std::str::from_utf8(bytes).unwrap();
+    let s = std::str::from_utf8(bytes)
+        .map_err(|_| nom::Err::Failure(error::Error::new(input, ErrorKind::Char)))?;
     Ok((rest, s))
 }
 
@@ -71,6 +76,23 @@ pub fn bytes_opt(input: &[u8]) -> IResult<&[u8], Option<&[u8]>> {
     Ok((rest, Some(bytes)))
 }
 
+pub fn inet(input: &[u8]) -> IResult<&[u8], SocketAddr> {
+    let (rest, size) = complete::be_u8(input)?;
+    let (rest, ip) = match size {
+        4 => map(be_u32, |it| IpAddr::V4(Ipv4Addr::from(it)))(rest)?,
+        16 => map(be_u128, |it| IpAddr::V6(Ipv6Addr::from(it)))(rest)?,
+        _ => {
+            return Err(nom::Err::Failure(error::Error::new(
+                input,
+                ErrorKind::LengthValue,
+            )))
+        }
+    };
+    let (rest, port) = be_i32(rest)?;
+
+    Ok((rest, SocketAddr::new(ip, port as u16)))
+}
+
 pub fn value(input: &[u8]) -> IResult<&[u8], FrameValue> {
     let (rest, len) = complete::be_i32(input)?;
     match len {
@@ -108,8 +130,8 @@ pub fn consistency(input: &[u8]) -> IResult<&[u8], LegacyConsistency> {
     let parsed = match Consistency::try_from(raw) {
         Ok(c) => LegacyConsistency::Regular(c),
         Err(_) => {
-            let parsed_serial =
-                SerialConsistency::try_from(raw).expect("could not parsed serial consistency");
+            let parsed_serial = SerialConsistency::try_from(raw)
+                .map_err(|_| nom::Err::Failure(Error::new(input, ErrorKind::Verify)))?;
             LegacyConsistency::Serial(parsed_serial)
         }
     };
@@ -124,16 +146,105 @@ pub fn unsigned_vint(mut input: &[u8]) -> IResult<&[u8], u64> {
     Ok((input, int))
 }
 
+/// Zigzag encoded variable length integer of durations, the reverse of `write::signed_vint`:
+/// the number of leading ones of the first byte is the number of bytes following it.
+pub fn signed_vint(input: &[u8]) -> IResult<&[u8], i64> {
+    let (rest, first) = complete::be_u8(input)?;
+    let extra = first.leading_ones() as usize;
+    let (rest, bytes) = take(extra)(rest)?;
+    // all the bits of the first byte are taken by the length of 8 bytes long values
+    let high = if extra < 8 {
+        first & (0xff >> extra)
+    } else {
+        0
+    };
+    let value = bytes
+        .iter()
+        .fold(u64::from(high), |value, &byte| value << 8 | u64::from(byte));
+
+    Ok((rest, (value >> 1) as i64 ^ -((value & 1) as i64)))
+}
+
+/// `[option]` of a column type in result metadata, the reverse of `write::r#type`
+pub fn column_type(input: &[u8]) -> IResult<&[u8], ColumnType> {
+    let (rest, id) = complete::be_u16(input)?;
+    let ty = match id {
+        0x0000 => {
+            let (rest, class) = short_string(rest)?;
+            return Ok((rest, ColumnType::Custom(class.to_owned())));
+        }
+        0x0001 => ColumnType::Ascii,
+        0x0002 => ColumnType::BigInt,
+        0x0003 => ColumnType::Blob,
+        0x0004 => ColumnType::Boolean,
+        0x0005 => ColumnType::Counter,
+        0x0006 => ColumnType::Decimal,
+        0x0007 => ColumnType::Double,
+        0x0008 => ColumnType::Float,
+        0x0009 => ColumnType::Int,
+        0x000B => ColumnType::Timestamp,
+        0x000C => ColumnType::Uuid,
+        0x000D => ColumnType::Text,
+        0x000E => ColumnType::Varint,
+        0x000F => ColumnType::Timeuuid,
+        0x0010 => ColumnType::Inet,
+        0x0011 => ColumnType::Date,
+        0x0012 => ColumnType::Time,
+        0x0013 => ColumnType::SmallInt,
+        0x0014 => ColumnType::TinyInt,
+        0x0015 => ColumnType::Duration,
+        0x0020 => {
+            let (rest, element) = column_type(rest)?;
+            return Ok((rest, ColumnType::List(Box::new(element))));
+        }
+        0x0021 => {
+            let (rest, key) = column_type(rest)?;
+            let (rest, value) = column_type(rest)?;
+            return Ok((rest, ColumnType::Map(Box::new(key), Box::new(value))));
+        }
+        0x0022 => {
+            let (rest, element) = column_type(rest)?;
+            return Ok((rest, ColumnType::Set(Box::new(element))));
+        }
+        0x0030 => {
+            let (rest, keyspace) = short_string(rest)?;
+            let (rest, type_name) = short_string(rest)?;
+            let (rest, n) = complete::be_u16(rest)?;
+            let (rest, field_types) =
+                nom::multi::count(pair(short_string, column_type), n as usize)(rest)?;
+            let field_types = field_types
+                .into_iter()
+                .map(|(name, ty)| (name.to_owned(), ty))
+                .collect();
+            return Ok((
+                rest,
+                ColumnType::UserDefinedType {
+                    type_name: type_name.to_owned(),
+                    keyspace: keyspace.to_owned(),
+                    field_types,
+                },
+            ));
+        }
+        0x0031 => {
+            let (rest, n) = complete::be_u16(rest)?;
+            let (rest, types) = nom::multi::count(column_type, n as usize)(rest)?;
+            return Ok((rest, ColumnType::Tuple(types)));
+        }
+        _ => return unsupported(input),
+    };
+
+    Ok((rest, ty))
+}
+
 fn cql_value_without_size<'a>(data: &'a [u8], col: &ColumnType) -> IResult<&'a [u8], CqlValue> {
     match col {
-        ColumnType::Custom(_) => {
-            unimplemented!()
-        }
+        ColumnType::Custom(_) => unsupported(data),
         ColumnType::Ascii => {
             let (rest, size) = unsigned_vint(data)?;
             let (rest, slice) = take(size as usize)(rest)?;
-            let ascii_str = std::str::from_utf8(slice).unwrap().to_string();
-            Ok((rest, CqlValue::Ascii(ascii_str)))
+            let ascii_str = std::str::from_utf8(slice)
+                .map_err(|_| nom::Err::Failure(Error::new(data, ErrorKind::Char)))?;
+            Ok((rest, CqlValue::Ascii(ascii_str.into())))
         }
         ColumnType::Boolean => {
             let (rest, b) = take(1_usize)(data)?;
@@ -143,7 +254,7 @@ fn cql_value_without_size<'a>(data: &'a [u8], col: &ColumnType) -> IResult<&'a [
             let (rest, size) = unsigned_vint(data)?;
             let (rest, slice) = take(size as usize)(rest)?;
 
-            Ok((rest, CqlValue::Blob(slice.to_owned())))
+            Ok((rest, CqlValue::Blob(Bytes::copy_from_slice(slice))))
         }
         ColumnType::Counter => {
             let (rest, counter) = be_i64::<_, nom::error::Error<_>>(data)?;
@@ -154,14 +265,36 @@ fn cql_value_without_size<'a>(data: &'a [u8], col: &ColumnType) -> IResult<&'a [
             Ok((rest, CqlValue::Date(date)))
         }
         ColumnType::Decimal => {
-            todo!()
+            let (rest, size) = unsigned_vint(data)?;
+            let (rest, slice) = take(size as usize)(rest)?;
+            let (unscaled, scale) = be_i32(slice)?;
+            let unscaled = BigInt::from_signed_bytes_be(unscaled);
+
+            Ok((
+                rest,
+                CqlValue::Decimal(BigDecimal::new(unscaled, scale.into())),
+            ))
         }
         ColumnType::Double => {
             let (rest, double) = be_f64::<_, nom::error::Error<_>>(data)?;
             Ok((rest, CqlValue::Double(double.to_bits())))
         }
         ColumnType::Duration => {
-            todo!()
+            let (rest, months) = signed_vint(data)?;
+            let (rest, days) = signed_vint(rest)?;
+            let (rest, nanoseconds) = signed_vint(rest)?;
+            let (Ok(months), Ok(days)) = (months.try_into(), days.try_into()) else {
+                return Err(nom::Err::Failure(Error::new(data, ErrorKind::Verify)));
+            };
+
+            Ok((
+                rest,
+                CqlValue::Duration(CqlDuration {
+                    months,
+                    days,
+                    nanoseconds,
+                }),
+            ))
         }
         ColumnType::Float => {
             let (rest, float) = be_f32::<_, nom::error::Error<_>>(data)?;
@@ -178,8 +311,9 @@ fn cql_value_without_size<'a>(data: &'a [u8], col: &ColumnType) -> IResult<&'a [
         ColumnType::Text => {
             let (rest, size) = unsigned_vint(data)?;
             let (rest, slice) = take(size as usize)(rest)?;
-            let s = std::str::from_utf8(slice).unwrap().to_string();
-            Ok((rest, CqlValue::Text(s)))
+            let s = std::str::from_utf8(slice)
+                .map_err(|_| nom::Err::Failure(Error::new(data, ErrorKind::Char)))?;
+            Ok((rest, CqlValue::Text(s.into())))
         }
         ColumnType::Timestamp => {
             let (rest, timestamp) = be_i64::<_, nom::error::Error<_>>(data)?;
@@ -206,44 +340,59 @@ fn cql_value_without_size<'a>(data: &'a [u8], col: &ColumnType) -> IResult<&'a [
             };
             Ok((rest, CqlValue::Inet(ip)))
         }
-        ColumnType::List(_) => {
-            todo!()
-        }
-        ColumnType::Map(_, _) => {
-            todo!()
-        }
-        ColumnType::Set(_) => {
-            todo!()
-        }
-        ColumnType::UserDefinedType { .. } => {
-            todo!()
-        }
-        ColumnType::SmallInt => {
-            todo!()
-        }
-        ColumnType::TinyInt => {
-            todo!()
-        }
-        ColumnType::Time => {
-            todo!()
-        }
-        ColumnType::Timeuuid => {
-            todo!()
-        }
-        ColumnType::Tuple(_) => {
-            todo!()
+        ColumnType::List(_) => unsupported(data),
+        ColumnType::Map(_, _) => unsupported(data),
+        ColumnType::Set(_) => unsupported(data),
+        ColumnType::UserDefinedType {
+            type_name,
+            keyspace,
+            field_types,
+        } => {
+            let (mut rest, count) = unsigned_vint(data)?;
+            let mut fields = vec![];
+            for (name, ty) in field_types.iter().take(count as usize) {
+                let (r, value) = bytes_opt(rest)?;
+                let value = value
+                    .map(|it| deserialize_value(it, ty))
+                    .transpose()
+                    .map_err(|_| nom::Err::Failure(Error::new(rest, ErrorKind::Verify)))?;
+                fields.push((name.clone(), value));
+                rest = r;
+            }
+
+            Ok((
+                rest,
+                CqlValue::UserDefinedType {
+                    keyspace: keyspace.clone(),
+                    type_name: type_name.clone(),
+                    fields,
+                },
+            ))
         }
+        ColumnType::SmallInt => unsupported(data),
+        ColumnType::TinyInt => unsupported(data),
+        ColumnType::Time => unsupported(data),
+        ColumnType::Timeuuid => unsupported(data),
+        ColumnType::Tuple(_) => unsupported(data),
         ColumnType::Uuid => {
             let (rest, v) = be_u128::<_, nom::error::Error<_>>(data)?;
             let v = Uuid::from_u128(v);
             Ok((rest, CqlValue::Uuid(v)))
         }
         ColumnType::Varint => {
-            todo!()
+            let (rest, size) = unsigned_vint(data)?;
+            let (rest, slice) = take(size as usize)(rest)?;
+
+            Ok((rest, CqlValue::Varint(BigInt::from_signed_bytes_be(slice))))
         }
     }
 }
 
+/// Key values of types kassandra can't decode from their serialized form
+fn unsupported<T>(data: &[u8]) -> IResult<&[u8], T> {
+    Err(nom::Err::Failure(Error::new(data, ErrorKind::Fail)))
+}
+
 pub fn clustering_key<'a>(
     input: &'a [u8],
     ty: &PrimaryKeyColumn,
@@ -269,11 +418,12 @@ pub fn clustering_key<'a>(
         rest = r;
         for column in (&mut columns).take(32) {
             offset += 1;
-            if is_null(header, offset - 1) {
+            // every header covers the next 32 columns
+            if is_null(header, (offset - 1) % 32) {
                 values.push(None);
                 continue;
             }
-            if is_empty(header, offset - 1) {
+            if is_empty(header, (offset - 1) % 32) {
                 values.push(Some(CqlValue::Empty));
                 continue;
             }
@@ -298,9 +448,9 @@ pub fn partition_key<'a>(
 ) -> IResult<&'a [u8], PartitionKeyValue> {
     let mut types = ty.into_iter();
 
-    let first = types
-        .next()
-        .expect("PrimaryKeyColumn can't be composite of 0 values");
+    let Some(first) = types.next() else {
+        return Ok((input, PartitionKeyValue::Empty));
+    };
     let (rest, first) = cql_value_without_size(input, first)?;
 
     if types.as_slice().is_empty() {
@@ -336,8 +486,8 @@ mod tests {
         assert_eq!(
             v,
             ClusteringKeyValue::Composite(vec![
-                Some(CqlValue::Ascii("998".to_owned())),
-                Some(CqlValue::Ascii("1".to_owned())),
+                Some(CqlValue::Ascii("998".into())),
+                Some(CqlValue::Ascii("1".into())),
             ])
         );
 
@@ -0,0 +1,56 @@
+use std::cell::RefCell;
+
+use bytes::BytesMut;
+
+/// Buffers kept for reuse by each thread, enough for collections nested a few levels deep
+const MAX_BUFFERS: usize = 8;
+/// Buffers grown past it by a large result are dropped rather than kept around
+const MAX_CAPACITY: usize = 1 << 20;
+
+thread_local! {
+    /// Every thread keeps its own buffers, so connections encoding at once don't contend on them
+    static POOL: RefCell<Vec<BytesMut>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Empty scratch buffer for serialization, to be given back once its content is copied out
+pub(crate) fn take() -> BytesMut {
+    POOL.with_borrow_mut(|pool| pool.pop()).unwrap_or_default()
+}
+
+pub(crate) fn give(mut buf: BytesMut) {
+    if buf.capacity() > MAX_CAPACITY {
+        return;
+    }
+    buf.clear();
+
+    POOL.with_borrow_mut(|pool| {
+        if pool.len() < MAX_BUFFERS {
+            pool.push(buf);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused_empty() {
+        let mut buf = take();
+        buf.put_slice(b"serialized");
+        give(buf);
+
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn large_buffers_are_dropped() {
+        let mut buf = BytesMut::with_capacity(MAX_CAPACITY + 1);
+        buf.put_u8(1);
+        give(buf);
+
+        POOL.with_borrow(|pool| assert!(pool.iter().all(|it| it.capacity() <= MAX_CAPACITY)));
+    }
+}
@@ -1,20 +1,31 @@
+use bitflags::bitflags;
+use bytes::BufMut;
 use nom::{
     branch::alt,
     bytes::complete::tag,
     combinator::{map, recognize},
     multi::count,
     number::complete::{be_i16, be_i64, be_u16, be_u8},
+    sequence::pair,
+    IResult,
 };
 use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    cql::{parser, query::QueryString},
+    cql::{
+        literal::Literal,
+        parser,
+        query::{BatchQuery, QueryString, QueryValue, UsingTimestamp},
+    },
     error::DbError,
     frame::{
         consistency::{Consistency, SerialConsistency},
         parse,
+        request::QueryParameters,
         response::error::Error,
         value::FrameValue,
+        write,
     },
 };
 
@@ -28,7 +39,7 @@ pub struct Batch<'a> {
 }
 
 /// The type of a batch.
-#[derive(Debug, Clone, Copy, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum BatchType {
     Logged = 0,
@@ -39,7 +50,7 @@ pub enum BatchType {
 #[derive(Debug, Clone)]
 pub enum BatchStatement<'a> {
     Query {
-        query: QueryString,
+        query: Box<QueryString>,
         raw_query: &'a str,
         values: Vec<FrameValue<'a>>,
     },
@@ -49,30 +60,197 @@ pub enum BatchStatement<'a> {
     },
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct BatchFlags: u8 {
+        const WITH_SERIAL_CONSISTENCY   = 0x10;
+        const WITH_DEFAULT_TIMESTAMP    = 0x20;
+        const WITH_NAMES_FOR_VALUES     = 0x40;
+    }
+}
+
 impl<'a> Batch<'a> {
+    /// Batch sent as `BEGIN BATCH ... APPLY BATCH` text of a regular query,
+    /// values of the query are handed out to the statements by their bind markers.
+    pub fn from_query(query: BatchQuery, parameters: QueryParameters<'a>) -> Result<Self, Error> {
+        let BatchQuery {
+            batch_type,
+            using,
+            statements,
+        } = query;
+
+        let markers = statements
+            .iter()
+            .map(QueryString::bind_markers)
+            .sum::<usize>()
+            + usize::from(matches!(
+                using,
+                Some(UsingTimestamp {
+                    value: QueryValue::Blankslate,
+                    ..
+                })
+            ));
+        if !parameters.data.is_empty() && parameters.data.len() != markers {
+            return Err(Error::new(
+                DbError::Invalid,
+                format!(
+                    "There were {markers} markers(?) in CQL but {} bound variables",
+                    parameters.data.len()
+                ),
+            ));
+        }
+
+        let mut data = parameters.data.into_iter();
+        let timestamp = match using {
+            None => parameters.default_timestamp,
+            Some(_) if statements.iter().any(has_timestamp) => {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    "Timestamp must be set either on BATCH or individual statements",
+                ))
+            }
+            Some(UsingTimestamp {
+                value: QueryValue::Literal(Literal::Number(timestamp)),
+                ..
+            }) => Some(timestamp),
+            Some(UsingTimestamp {
+                value: QueryValue::Blankslate,
+                ..
+            }) => match data.next() {
+                Some(FrameValue::Some(value)) => Some(
+                    value
+                        .try_into()
+                        .map(i64::from_be_bytes)
+                        .map_err(|_| Error::new(DbError::Invalid, "Timestamp must be a bigint"))?,
+                ),
+                Some(FrameValue::NotSet) | None => parameters.default_timestamp,
+                Some(FrameValue::Null) => {
+                    return Err(Error::new(
+                        DbError::Invalid,
+                        "Invalid null value of timestamp",
+                    ))
+                }
+            },
+            Some(_) => return Err(Error::new(DbError::Invalid, "Timestamp must be a bigint")),
+        };
+
+        let statements = statements
+            .into_iter()
+            .map(|query| {
+                let values = data.by_ref().take(query.bind_markers()).collect();
+                BatchStatement::Query {
+                    query: Box::new(query),
+                    raw_query: "",
+                    values,
+                }
+            })
+            .collect();
+
+        Ok(Batch {
+            batch_type,
+            consistency: parameters.consistency,
+            serial_consistency: parameters.serial_consistency,
+            timestamp,
+            statements,
+        })
+    }
+
+    /// Values are written positionally, the same way they are kept after parsing.
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        buf.put_u8(self.batch_type as u8);
+        buf.put_u16(self.statements.len() as u16);
+
+        for statement in &self.statements {
+            let values = match statement {
+                BatchStatement::Query {
+                    raw_query, values, ..
+                } => {
+                    buf.put_u8(0);
+                    write::long_string(buf, raw_query);
+                    values
+                }
+                BatchStatement::Prepared { id, values } => {
+                    buf.put_u8(1);
+                    write::short_bytes(buf, id);
+                    values
+                }
+            };
+
+            buf.put_u16(values.len() as u16);
+            for value in values {
+                write::value(buf, value);
+            }
+        }
+
+        let mut flags = BatchFlags::empty();
+        flags.set(
+            BatchFlags::WITH_SERIAL_CONSISTENCY,
+            self.serial_consistency != SerialConsistency::Serial,
+        );
+        flags.set(BatchFlags::WITH_DEFAULT_TIMESTAMP, self.timestamp.is_some());
+
+        buf.put_i16(self.consistency.into());
+        buf.put_u8(flags.bits());
+
+        if flags.contains(BatchFlags::WITH_SERIAL_CONSISTENCY) {
+            buf.put_i16(self.serial_consistency.into());
+        }
+        if let Some(timestamp) = self.timestamp {
+            buf.put_i64(timestamp);
+        }
+    }
+
+    /// `<type><n><query_1>...<query_n><consistency><flags>[<serial_consistency>][<timestamp>]`
+    ///
+    /// Whether statement values are preceded by names is only known from `<flags>`,
+    /// which come after all the statements, so a batch is parsed as positional first
+    /// and re-parsed if it turns out to carry named values.
+    ///
+    /// Named values are parsed, so they don't corrupt the statements following them,
+    /// but such batches are rejected: the names can't be matched to the bind markers
+    /// of the statements, as the spec notes for this flag (CASSANDRA-10246),
+    /// and binding them by position would write values into the wrong columns.
     pub fn deserialize(input: &'a [u8]) -> Result<Self, Error> {
+        let positional = Self::parse(input, false);
+        match positional {
+            Ok((batch, flags)) if !flags.contains(BatchFlags::WITH_NAMES_FOR_VALUES) => {
+                return Ok(batch)
+            }
+            _ => {}
+        }
+
+        match Self::parse(input, true) {
+            Ok((_, flags)) if flags.contains(BatchFlags::WITH_NAMES_FOR_VALUES) => Err(Error::new(
+                DbError::ProtocolError,
+                "Names for values are not supported in batches",
+            )),
+            Ok(_) => Err(positional
+                .err()
+                .unwrap_or_else(|| Error::new(DbError::ProtocolError, "Malformed batch values"))),
+            Err(er) => Err(er),
+        }
+    }
+
+    fn parse(input: &'a [u8], with_names: bool) -> Result<(Self, BatchFlags), Error> {
         let (rest, ty) = be_u8::<_, nom::error::Error<_>>(input)?;
-        let batch_type = BatchType::try_from(ty).map_err(|_| DbError::ProtocolError)?;
+        let batch_type = BatchType::try_from(ty)
+            .map_err(|_| Error::new(DbError::ProtocolError, format!("Unknown batch type: {ty}")))?;
         let (mut rest, queries_count) = be_u16::<_, nom::error::Error<_>>(rest)?;
 
         let mut statements = vec![];
         for _ in 0..queries_count {
             let (r, kind) = be_u8::<_, nom::error::Error<_>>(rest)?;
 
-            let values_parser = |r: &'a [u8]| {
-                let (r, values_count) = be_u16::<_, nom::error::Error<_>>(r)?;
-                count(parse::value, values_count as usize)(r)
-            };
             match kind {
                 0 => {
                     let (r, query_string) = parse::long_string(r)?;
                     let query = parser::query(query_string)?;
 
-                    let (r, values) = values_parser(r)?;
+                    let (r, values) = values(r, with_names)?;
                     rest = r;
 
                     let query = BatchStatement::Query {
-                        query,
+                        query: Box::new(query),
                         raw_query: query_string,
                         values,
                     };
@@ -80,30 +258,38 @@ impl<'a> Batch<'a> {
                 }
                 1 => {
                     let (r, id) = parse::short_bytes(r)?;
-                    let (r, values) = values_parser(r)?;
+                    let (r, values) = values(r, with_names)?;
                     rest = r;
 
                     let execute = BatchStatement::Prepared { id, values };
 
                     statements.push(execute)
                 }
-                _ => unreachable!(),
+                other => {
+                    return Err(Error::new(
+                        DbError::ProtocolError,
+                        format!("Unknown batch statement kind: {other}"),
+                    ))
+                }
             }
         }
 
-        let (rest, consistency) = map(be_i16::<_, nom::error::Error<_>>, |it| {
-            Consistency::try_from(it).unwrap()
-        })(rest)?;
+        let (rest, consistency) = be_i16::<_, nom::error::Error<_>>(rest)?;
+        let consistency = Consistency::try_from(consistency).map_err(|_| {
+            Error::new(
+                DbError::ProtocolError,
+                format!("Unknown consistency: {consistency}"),
+            )
+        })?;
         let (rest, flags) = be_u8::<_, nom::error::Error<_>>(rest)?;
-
-        if flags & 0x40 != 0 {
-            return Err(Error::new(
+        let flags = BatchFlags::from_bits(flags).ok_or_else(|| {
+            Error::new(
                 DbError::ProtocolError,
-                "Batch query with NAMES_FOR_VALUES flag is un-implementable",
-            ));
-        }
+                format!("Unknown batch flags: {flags}"),
+            )
+        })?;
 
-        let (rest, serial_consistency) = if flags & 0x10 != 0 {
+        let (rest, serial_consistency) = if flags.contains(BatchFlags::WITH_SERIAL_CONSISTENCY) {
             let (rest, raw) = recognize(alt((
                 tag::<_, _, nom::error::Error<_>>(0x0008i16.to_be_bytes()),
                 tag::<_, _, nom::error::Error<_>>(0x0009i16.to_be_bytes()),
@@ -115,22 +301,163 @@ impl<'a> Batch<'a> {
             (rest, SerialConsistency::Serial)
         };
 
-        let (rest, timestamp) = if flags & 0x20 != 0 {
+        let (rest, timestamp) = if flags.contains(BatchFlags::WITH_DEFAULT_TIMESTAMP) {
             map(be_i64::<_, nom::error::Error<_>>, Some)(rest)?
         } else {
             (rest, None)
         };
 
         if !rest.is_empty() {
-            tracing::warn!(?rest, "Batch wasn't parsed till the end");
+            return Err(Error::new(
+                DbError::ProtocolError,
+                format!("Batch has {} unexpected trailing bytes", rest.len()),
+            ));
         }
 
-        Ok(Batch {
+        let batch = Batch {
             batch_type,
             consistency,
             serial_consistency,
             timestamp,
             statements,
-        })
+        };
+
+        Ok((batch, flags))
+    }
+}
+
+fn has_timestamp(query: &QueryString) -> bool {
+    match query {
+        QueryString::Insert(s) => s.using.is_some(),
+        QueryString::Update(s) => s.using.is_some(),
+        QueryString::Delete(s) => s.using.is_some(),
+        _ => false,
+    }
+}
+
+/// `<n><value_1>...<value_n>` or `<n><name_1><value_1>...<name_n><value_n>`,
+/// names are only read to find the flags after the statements.
+fn values(input: &[u8], with_names: bool) -> IResult<&[u8], Vec<FrameValue<'_>>> {
+    let (rest, values_count) = be_u16(input)?;
+    if with_names {
+        count(
+            map(pair(parse::short_string, parse::value), |(_, value)| value),
+            values_count as usize,
+        )(rest)
+    } else {
+        count(parse::value, values_count as usize)(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+
+    use super::{Batch, BatchStatement};
+    use crate::{
+        error::DbError,
+        frame::{consistency::SerialConsistency, value::FrameValue},
+    };
+
+    /// Logged batch sent by the scylla rust driver 0.10.2 to a `KassandraTester` session, captured
+    /// by a TCP relay between them: a query and a prepared statement binding a null,
+    /// with `LOCAL_SERIAL` serial consistency and a timestamp. The prepared id is kassandra's.
+    const LOGGED_BATCH: &[u8] =
+        b"\x00\x00\x02\x00\x00\x00\x00\x29insert into ks.t (id, name) values (?, ?)\
+        \x00\x02\x00\x00\x00\x04\x00\x00\x00\x01\x00\x00\x00\x05first\
+        \x01\x00\x10\xcc\x59\x85\x2b\xfa\x6b\xdc\x12\x83\x09\xfc\xaf\xe7\x22\xe9\x8b\
+        \x00\x02\x00\x00\x00\x04\x00\x00\x00\x02\xff\xff\xff\xff\
+        \x00\x06\x30\x00\x09\x00\x06\x0a\x24\x18\x1e\x40\x00";
+
+    /// The fixture with names put in front of its values the way the spec lays them out
+    fn with_names() -> BytesMut {
+        let mut buf = BytesMut::new();
+        buf.put_slice(&LOGGED_BATCH[..51]);
+        for (name, value) in [
+            ("id", &b"\x00\x00\x00\x04\x00\x00\x00\x01"[..]),
+            ("name", b"\x00\x00\x00\x05first"),
+        ] {
+            buf.put_u16(name.len() as u16);
+            buf.put_slice(name.as_bytes());
+            buf.put_slice(value);
+        }
+        buf.put_slice(&LOGGED_BATCH[68..89]);
+        for (name, value) in [
+            ("id", &b"\x00\x00\x00\x04\x00\x00\x00\x02"[..]),
+            ("name", b"\xff\xff\xff\xff"),
+        ] {
+            buf.put_u16(name.len() as u16);
+            buf.put_slice(name.as_bytes());
+            buf.put_slice(value);
+        }
+        buf.put_slice(&LOGGED_BATCH[101..]);
+        let flags = buf.len() - 11;
+        buf[flags] |= 0x40;
+
+        buf
+    }
+
+    fn values(statement: &BatchStatement<'_>) -> Vec<Option<Vec<u8>>> {
+        let (BatchStatement::Query { values, .. } | BatchStatement::Prepared { values, .. }) =
+            statement;
+
+        values
+            .iter()
+            .map(|it| match it {
+                FrameValue::Some(value) => Some(value.to_vec()),
+                FrameValue::Null => None,
+                FrameValue::NotSet => panic!("unexpected unset value"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn logged_batch() {
+        let batch = Batch::deserialize(LOGGED_BATCH).unwrap();
+
+        assert_eq!(batch.statements.len(), 2);
+        assert_eq!(batch.serial_consistency, SerialConsistency::LocalSerial);
+        assert_eq!(batch.timestamp, Some(1_700_000_000_000_000));
+        assert_eq!(
+            values(&batch.statements[0]),
+            vec![Some(1i32.to_be_bytes().to_vec()), Some(b"first".to_vec())]
+        );
+        assert!(matches!(
+            batch.statements[1],
+            BatchStatement::Prepared { id, .. } if id.len() == 16
+        ));
+        assert_eq!(
+            values(&batch.statements[1]),
+            vec![Some(2i32.to_be_bytes().to_vec()), None]
+        );
+    }
+
+    #[test]
+    fn logged_batch_roundtrip() {
+        let batch = Batch::deserialize(LOGGED_BATCH).unwrap();
+        let mut buf = BytesMut::new();
+        batch.serialize(&mut buf);
+
+        assert_eq!(&buf[..], LOGGED_BATCH);
+    }
+
+    #[test]
+    fn named_values_are_rejected() {
+        let data = with_names();
+        let error = Batch::deserialize(&data).unwrap_err();
+
+        assert_eq!(error.error, DbError::ProtocolError);
+        assert_eq!(
+            error.reason,
+            "Names for values are not supported in batches"
+        );
+    }
+
+    #[test]
+    fn unknown_statement_kind() {
+        let mut data = LOGGED_BATCH.to_vec();
+        data[3] = 7;
+
+        assert!(Batch::deserialize(&data).is_err());
     }
 }
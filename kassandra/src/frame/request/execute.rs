@@ -1,5 +1,7 @@
+use bytes::BufMut;
+
 use crate::frame::{
-    parse, request::query_params::QueryParameters, response::error::Error, FrameFlags,
+    parse, request::query_params::QueryParameters, response::error::Error, write, FrameFlags,
 };
 
 #[derive(Debug, Clone)]
@@ -9,6 +11,11 @@ pub struct Execute<'a> {
 }
 
 impl<'a> Execute<'a> {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        write::short_bytes(buf, self.id);
+        self.parameters.serialize(buf);
+    }
+
     pub fn parse(data: &'a [u8], flags: FrameFlags) -> Result<Execute<'a>, Error> {
         let (rest, id) = parse::short_bytes(data)?;
 
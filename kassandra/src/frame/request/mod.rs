@@ -3,17 +3,16 @@ use std::collections::HashMap;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use eyre::eyre;
 use nom::AsBytes;
-use num_enum::TryFromPrimitive;
+use num_enum::{FromPrimitive, IntoPrimitive};
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::{
-    cql::query::QueryString,
-    frame::{parse, request::batch::Batch, response::error::Error, write, FrameFlags, FrameParams},
+use crate::frame::{
+    parse, request::batch::Batch, response::error::Error, write, FrameFlags, FrameParams,
 };
 
 pub mod batch;
 pub mod execute;
-mod prepare;
+pub mod prepare;
 pub mod query;
 mod startup;
 
@@ -23,7 +22,7 @@ pub use query_params::{QueryFlags, QueryParameters};
 
 use crate::frame::ProtocolVersion;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, FromPrimitive, IntoPrimitive)]
 #[repr(u8)]
 pub enum RequestOpcode {
     Startup = 0x01,
@@ -34,6 +33,9 @@ pub enum RequestOpcode {
     Register = 0x0B,
     Batch = 0x0D,
     AuthResponse = 0x0F,
+    /// Opcode of a newer protocol version or an extension, read as [`Request::Other`]
+    #[num_enum(catch_all)]
+    Other(u8),
 }
 
 #[derive(Debug)]
@@ -42,10 +44,22 @@ pub enum Request<'a> {
     Options,
     Query(query::Query<'a>),
     Batch(Batch<'a>),
-    Prepare(QueryString),
+    Prepare(prepare::Prepare<'a>),
     Execute(execute::Execute<'a>),
-    Register { events: Vec<String> },
-    AuthResponse,
+    Register {
+        events: Vec<String>,
+    },
+    /// SASL token of the authenticator, `PLAIN` credentials for password authentication
+    AuthResponse {
+        token: Option<&'a [u8]>,
+    },
+    /// Request with an opcode kassandra doesn't know, answered by the [`OpcodeHandler`] registered for it
+    ///
+    /// [`OpcodeHandler`]: crate::middleware::OpcodeHandler
+    Other {
+        opcode: u8,
+        body: &'a [u8],
+    },
 }
 
 impl<'a> Request<'a> {
@@ -59,6 +73,7 @@ impl<'a> Request<'a> {
             Self::Register { .. } => 0x0B,
             Self::Batch { .. } => 0x0D,
             Self::AuthResponse { .. } => 0x0F,
+            Self::Other { opcode, .. } => *opcode,
         }
     }
 
@@ -68,7 +83,13 @@ impl<'a> Request<'a> {
             Self::StartUp(opts) => {
                 write::string_map(buf, opts);
             }
-            _ => unimplemented!(),
+            Self::Query(query) => query.serialize(buf),
+            Self::Batch(batch) => batch.serialize(buf),
+            Self::Prepare(prepare) => prepare.serialize(buf),
+            Self::Execute(execute) => execute.serialize(buf),
+            Self::Register { events } => write::string_list(buf, events),
+            Self::AuthResponse { token } => write::bytes_opt(buf, *token),
+            Self::Other { body, .. } => buf.put_slice(body),
         }
         Ok(())
     }
@@ -82,7 +103,7 @@ impl<'a> Request<'a> {
             RequestOpcode::Startup => Request::StartUp(startup::deserialize(data)?),
             RequestOpcode::Options => Request::Options,
             RequestOpcode::Query => Request::Query(query::Query::parse(data, flags)?),
-            RequestOpcode::Prepare => Request::Prepare(prepare::parse(data)?),
+            RequestOpcode::Prepare => Request::Prepare(prepare::Prepare::parse(data)?),
             RequestOpcode::Execute => Request::Execute(execute::Execute::parse(data, flags)?),
             RequestOpcode::Register => {
                 let (_, events) = parse::short_string_list(data)?;
@@ -92,7 +113,12 @@ impl<'a> Request<'a> {
                 }
             }
             RequestOpcode::Batch => Request::Batch(Batch::deserialize(data)?),
-            RequestOpcode::AuthResponse => unimplemented!(),
+            RequestOpcode::AuthResponse => {
+                let (_, token) = parse::bytes_opt(data)?;
+
+                Request::AuthResponse { token }
+            }
+            RequestOpcode::Other(opcode) => Request::Other { opcode, body: data },
         };
 
         Ok(request)
@@ -138,7 +164,7 @@ impl Encoder<(FrameParams, RequestOpcode, Bytes)> for RequestFrameCodec {
         dst.put_u8(ProtocolVersion::V4.to_request()); // version
         dst.put_u8(frame.flags.bits());
         dst.put_i16(frame.stream);
-        dst.put_u8(opcode as _);
+        dst.put_u8(opcode.into());
         dst.put_u32(data.len() as _);
         dst.put_slice(data.as_bytes());
         tracing::trace!(?frame, ?opcode, "Sent request frame");
@@ -181,7 +207,7 @@ impl Decoder for RequestFrameCodec {
             Err(eyre!("Compression is not supported"))?;
         }
 
-        let opcode = RequestOpcode::try_from(src.get_u8())?;
+        let opcode = RequestOpcode::from(src.get_u8());
         let _ = src.get_u32() as usize;
         let body = src.split_to(length);
 
@@ -190,3 +216,76 @@ impl Decoder for RequestFrameCodec {
         Ok(Some((frame, opcode, Bytes::from(body))))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::Decoder;
+
+    use super::{Request, RequestFrameCodec, RequestOpcode};
+    use crate::frame::{value::FrameValue, FrameFlags};
+
+    fn round_trip(opcode: RequestOpcode, data: &[u8]) -> Vec<u8> {
+        let request = Request::deserialize(opcode, data, FrameFlags::empty()).unwrap();
+        let mut buf = vec![];
+        request.serialize(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn query() {
+        let data: &[u8] = b"\0\0\0.select * from system.local where key = 'local'\0\x01$\0\0\x13\x88\0\x06\x08\xd3\xa0\xc0K\xe9";
+
+        assert_eq!(round_trip(RequestOpcode::Query, data), data);
+    }
+
+    #[test]
+    fn prepare_and_register() {
+        let data: &[u8] = b"\0\0\0\x1aselect * from system.local";
+        assert_eq!(round_trip(RequestOpcode::Prepare, data), data);
+
+        let data: &[u8] = b"\0\x02\0\x0dSCHEMA_CHANGE\0\x0dSTATUS_CHANGE";
+        assert_eq!(round_trip(RequestOpcode::Register, data), data);
+    }
+
+    #[test]
+    fn execute_with_unset_and_null_values() {
+        let data: &[u8] =
+            b"\0\x02\xab\xcd\0\x04\x11\0\x03\0\0\0\x02hi\xff\xff\xff\xff\xff\xff\xff\xfe\0\x09";
+
+        let Request::Execute(execute) =
+            Request::deserialize(RequestOpcode::Execute, data, FrameFlags::empty()).unwrap()
+        else {
+            panic!("expected execute");
+        };
+        assert!(matches!(
+            execute.parameters.data[..],
+            [
+                FrameValue::Some(b"hi"),
+                FrameValue::Null,
+                FrameValue::NotSet
+            ]
+        ));
+        assert_eq!(round_trip(RequestOpcode::Execute, data), data);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_read() {
+        let mut src = BytesMut::from(&b"\x04\0\0\x07\x20\0\0\0\x02hi\x04\0\0\x08\x05\0\0\0\0"[..]);
+
+        let (frame, opcode, body) = RequestFrameCodec.decode(&mut src).unwrap().unwrap();
+        assert_eq!((frame.stream, opcode), (7, RequestOpcode::Other(0x20)));
+        assert!(matches!(
+            Request::deserialize(opcode, &body, FrameFlags::empty()).unwrap(),
+            Request::Other {
+                opcode: 0x20,
+                body: b"hi"
+            }
+        ));
+        assert_eq!(round_trip(opcode, &body), b"hi");
+
+        // the frame is consumed, so the following one is read as usual
+        let (_, opcode, _) = RequestFrameCodec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(opcode, RequestOpcode::Options);
+    }
+}
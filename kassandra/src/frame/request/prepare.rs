@@ -1,20 +1,42 @@
+use bytes::BufMut;
+
 use crate::{
     cql::{parser, query::QueryString},
     error::DbError,
-    frame::{parse, response::error::Error},
+    frame::{parse, response::error::Error, write},
 };
 
-pub fn parse(data: &[u8]) -> Result<QueryString, Error> {
-    let (rest, raw_query) = parse::long_string(data)?;
-    let query = parser::query(raw_query).map_err(|_| {
-        Error::new(
-            DbError::SyntaxError,
-            format!("Could not parse query: {raw_query}"),
-        )
-    })?;
-    if !rest.is_empty() {
-        return Err(Error::new(DbError::Invalid, "Data contains ".to_string()));
+#[derive(Debug, Clone)]
+pub struct Prepare<'a> {
+    pub query: QueryString,
+    pub raw_query: &'a str,
+}
+
+impl<'a> Prepare<'a> {
+    pub fn simple(input: &'a str) -> Result<Self, Error> {
+        Ok(Self {
+            query: parser::query(input)?,
+            raw_query: input,
+        })
     }
 
-    Ok(query)
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        write::long_string(buf, self.raw_query);
+    }
+
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let (rest, raw_query) = parse::long_string(data)?;
+        let query = parser::query(raw_query).map_err(|error| match error.error {
+            DbError::Unimplemented | DbError::SyntaxError => error,
+            _ => Error::new(
+                DbError::SyntaxError,
+                format!("Could not parse query: {raw_query}"),
+            ),
+        })?;
+        if !rest.is_empty() {
+            return Err(Error::new(DbError::Invalid, "Data contains ".to_string()));
+        }
+
+        Ok(Self { query, raw_query })
+    }
 }
@@ -1,9 +1,12 @@
+use bytes::BufMut;
 use eyre::Result;
 
 use crate::{
     cql::{parser, query::QueryString},
     error::DbError,
-    frame::{parse, request::query_params::QueryParameters, response::error::Error, FrameFlags},
+    frame::{
+        parse, request::query_params::QueryParameters, response::error::Error, write, FrameFlags,
+    },
 };
 
 #[derive(Debug, Clone)]
@@ -22,13 +25,19 @@ impl<'a> Query<'a> {
         })
     }
 
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        write::long_string(buf, self.raw_query);
+        self.parameters.serialize(buf);
+    }
+
     pub fn parse(input: &'a [u8], flags: FrameFlags) -> Result<Self, Error> {
         let (rest, raw_query) = parse::long_string(input)?;
-        let query = parser::query(raw_query).map_err(|_| {
-            Error::new(
+        let query = parser::query(raw_query).map_err(|error| match error.error {
+            DbError::Unimplemented | DbError::SyntaxError => error,
+            _ => Error::new(
                 DbError::SyntaxError,
                 format!("Could not parse query: {raw_query}"),
-            )
+            ),
         })?;
 
         let parameters = QueryParameters::parse(rest, flags)?;
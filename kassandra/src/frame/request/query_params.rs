@@ -1,4 +1,5 @@
 use bitflags::bitflags;
+use bytes::BufMut;
 
 use crate::{
     error::DbError,
@@ -6,7 +7,7 @@ use crate::{
         consistency::{Consistency, SerialConsistency},
         response::error::Error,
         value::{FrameValue, PagingState},
-        FrameFlags,
+        write, FrameFlags,
     },
 };
 
@@ -61,6 +62,47 @@ impl<'a> QueryParameters<'a> {
     }
 }
 
+impl QueryParameters<'_> {
+    /// Flags are derived from the parameters that are set, values are always written
+    /// positionally, since their names are not kept while parsing.
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        let mut flags = self.flags & QueryFlags::SKIP_METADATA;
+        flags.set(QueryFlags::VALUES, !self.data.is_empty());
+        flags.set(QueryFlags::PAGE_SIZE, self.result_page_size.is_some());
+        flags.set(QueryFlags::WITH_PAGING_STATE, self.paging_state.is_some());
+        flags.set(
+            QueryFlags::WITH_SERIAL_CONSISTENCY,
+            self.serial_consistency != SerialConsistency::Serial,
+        );
+        flags.set(
+            QueryFlags::WITH_DEFAULT_TIMESTAMP,
+            self.default_timestamp.is_some(),
+        );
+
+        buf.put_i16(self.consistency.into());
+        buf.put_u8(flags.bits());
+
+        if !self.data.is_empty() {
+            buf.put_u16(self.data.len() as u16);
+            for value in &self.data {
+                write::value(buf, value);
+            }
+        }
+        if let Some(page_size) = self.result_page_size {
+            buf.put_i32(page_size as i32);
+        }
+        if let Some(state) = &self.paging_state {
+            state.encode(buf);
+        }
+        if flags.contains(QueryFlags::WITH_SERIAL_CONSISTENCY) {
+            buf.put_i16(self.serial_consistency.into());
+        }
+        if let Some(timestamp) = self.default_timestamp {
+            buf.put_i64(timestamp);
+        }
+    }
+}
+
 mod parse {
     use bytes::Bytes;
     use nom::{
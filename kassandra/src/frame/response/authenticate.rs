@@ -1,14 +1,19 @@
+use bytes::BufMut;
 use nom::IResult;
 
-use crate::frame::parse;
+use crate::frame::{parse, write};
 
 // Implements Authenticate message.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Authenticate {
     pub authenticator_name: String,
 }
 
 impl Authenticate {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        write::string(buf, &self.authenticator_name);
+    }
+
     pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
         let (rest, authenticator_name) = parse::short_string(buf)?;
         let authenticator_name = authenticator_name.to_string();
@@ -17,12 +22,16 @@ impl Authenticate {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AuthSuccess {
     pub success_message: Option<Vec<u8>>,
 }
 
 impl AuthSuccess {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        write::bytes_opt(buf, self.success_message.as_deref());
+    }
+
     pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
         let (rest, success_message) = parse::bytes_opt(buf)?;
         let success_message = success_message.map(|it| it.to_owned());
@@ -31,12 +40,16 @@ impl AuthSuccess {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AuthChallenge {
     pub authenticate_message: Option<Vec<u8>>,
 }
 
 impl AuthChallenge {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        write::bytes_opt(buf, self.authenticate_message.as_deref());
+    }
+
     pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
         let (rest, authenticate_message) = parse::bytes_opt(buf)?;
         let authenticate_message = authenticate_message.map(|it| it.to_owned());
@@ -3,11 +3,13 @@ use nom::{
     number::complete::{be_i32, be_u8},
     AsBytes, IResult,
 };
+use strum::{Display, EnumString};
 use thiserror::Error;
 
 use crate::{
+    cql::parser::Diagnostic,
     error::DbError,
-    frame::{parse, write},
+    frame::{consistency::Consistency, parse, write},
 };
 
 #[derive(Error, Debug, Clone)]
@@ -15,6 +17,8 @@ use crate::{
 pub struct Error {
     pub error: DbError,
     pub reason: String,
+    known: Option<Box<KnownError>>,
+    diagnostic: Option<Box<Diagnostic>>,
 }
 
 impl Error {
@@ -22,9 +26,26 @@ impl Error {
         Self {
             error,
             reason: msg.to_string(),
+            known: None,
+            diagnostic: None,
         }
     }
 
+    /// Syntax error of a statement the parser rejected, the reason points at the failure
+    pub fn syntax(diagnostic: Diagnostic) -> Self {
+        Self {
+            error: DbError::SyntaxError,
+            reason: format!("Invalid syntax at {diagnostic}"),
+            known: None,
+            diagnostic: Some(Box::new(diagnostic)),
+        }
+    }
+
+    /// Where the statement could not be parsed, for syntax errors of the parser
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        self.diagnostic.as_deref()
+    }
+
     pub fn serialize(&self, buf: &mut impl BufMut) {
         buf.put_i32(self.error.code());
         write::string(buf, &self.reason);
@@ -244,7 +265,7 @@ impl Error {
             _ => (buf, DbError::Other(code)),
         };
 
-        Ok((buf, Error { error, reason }))
+        Ok((buf, Error::new(error, reason)))
     }
 }
 
@@ -255,6 +276,89 @@ impl From<DbError> for Error {
     }
 }
 
+/// Errors drivers and applications are known to match on by their message,
+/// the message is rendered by [`ErrorRenderer`] of the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KnownError {
+    UnconfiguredTable { keyspace: String, table: String },
+    UnknownKeyspace(String),
+    WriteOnlyConsistency(Consistency),
+}
+
+impl From<KnownError> for Error {
+    fn from(known: KnownError) -> Self {
+        ErrorRenderer::default().render(known)
+    }
+}
+
+/// Which messages are sent for [`KnownError`]s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ErrorRenderer {
+    /// Messages of kassandra itself
+    #[default]
+    Kassandra,
+    /// Messages byte-compatible with Apache Cassandra 4.x
+    Cassandra4,
+}
+
+impl ErrorRenderer {
+    pub fn render(self, known: KnownError) -> Error {
+        let reason = match (self, &known) {
+            (Self::Kassandra, KnownError::UnconfiguredTable { keyspace, table }) => {
+                format!("Table {keyspace}.{table} does not exist")
+            }
+            (Self::Cassandra4, KnownError::UnconfiguredTable { table, .. }) => {
+                format!("unconfigured table {table}")
+            }
+            (Self::Kassandra, KnownError::UnknownKeyspace(keyspace)) => {
+                format!("Keyspace {keyspace} does not exist")
+            }
+            (Self::Cassandra4, KnownError::UnknownKeyspace(keyspace)) => {
+                format!("keyspace {keyspace} does not exist")
+            }
+            (Self::Kassandra, KnownError::WriteOnlyConsistency(consistency)) => {
+                format!("{consistency} consistency can only be used for writes")
+            }
+            (Self::Cassandra4, KnownError::WriteOnlyConsistency(consistency)) => {
+                format!(
+                    "{} ConsistencyLevel is only supported for writes",
+                    consistency_name(*consistency)
+                )
+            }
+        };
+
+        Error {
+            error: DbError::Invalid,
+            reason,
+            known: Some(Box::new(known)),
+            diagnostic: None,
+        }
+    }
+
+    /// Renders the message again if the error is a [`KnownError`], other errors are returned as is
+    pub fn apply(self, error: Error) -> Error {
+        match error.known {
+            Some(known) => self.render(*known),
+            None => error,
+        }
+    }
+}
+
+fn consistency_name(consistency: Consistency) -> &'static str {
+    match consistency {
+        Consistency::Any => "ANY",
+        Consistency::One => "ONE",
+        Consistency::Two => "TWO",
+        Consistency::Three => "THREE",
+        Consistency::Quorum => "QUORUM",
+        Consistency::All => "ALL",
+        Consistency::LocalQuorum => "LOCAL_QUORUM",
+        Consistency::EachQuorum => "EACH_QUORUM",
+        Consistency::LocalOne => "LOCAL_ONE",
+    }
+}
+
 impl From<nom::Err<nom::error::Error<&str>>> for Error {
     fn from(value: nom::Err<nom::error::Error<&str>>) -> Self {
         tracing::error!(error = ?value, "Parsing error");
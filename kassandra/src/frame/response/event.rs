@@ -1,29 +1,33 @@
 use std::net::SocketAddr;
 
 use bytes::BufMut;
+use nom::{
+    error::{Error, ErrorKind},
+    IResult,
+};
 
-use crate::frame::write;
+use crate::frame::{parse, write};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Event {
     TopologyChange(TopologyChangeEvent),
     StatusChange(StatusChangeEvent),
     SchemaChange(SchemaChangeEvent),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TopologyChangeEvent {
     NewNode(SocketAddr),
     RemovedNode(SocketAddr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StatusChangeEvent {
     Up(SocketAddr),
     Down(SocketAddr),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SchemaChangeEvent {
     KeyspaceChange {
         change_type: SchemaChangeType,
@@ -53,7 +57,7 @@ pub enum SchemaChangeEvent {
     },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SchemaChangeType {
     Created,
     Updated,
@@ -61,6 +65,210 @@ pub enum SchemaChangeType {
     Invalid,
 }
 
+impl Event {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        match self {
+            Event::TopologyChange(event) => {
+                write::string(buf, "TOPOLOGY_CHANGE");
+                event.serialize(buf);
+            }
+            Event::StatusChange(event) => {
+                write::string(buf, "STATUS_CHANGE");
+                event.serialize(buf);
+            }
+            Event::SchemaChange(event) => {
+                write::string(buf, "SCHEMA_CHANGE");
+                event.serialize(buf);
+            }
+        }
+    }
+
+    pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, ty) = parse::short_string(buf)?;
+
+        match ty {
+            "TOPOLOGY_CHANGE" => {
+                let (rest, event) = TopologyChangeEvent::deserialize(rest)?;
+                Ok((rest, Event::TopologyChange(event)))
+            }
+            "STATUS_CHANGE" => {
+                let (rest, event) = StatusChangeEvent::deserialize(rest)?;
+                Ok((rest, Event::StatusChange(event)))
+            }
+            "SCHEMA_CHANGE" => {
+                let (rest, event) = SchemaChangeEvent::deserialize(rest)?;
+                Ok((rest, Event::SchemaChange(event)))
+            }
+            _ => Err(unknown(buf)),
+        }
+    }
+}
+
+impl TopologyChangeEvent {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        let (change, address) = match self {
+            TopologyChangeEvent::NewNode(address) => ("NEW_NODE", address),
+            TopologyChangeEvent::RemovedNode(address) => ("REMOVED_NODE", address),
+        };
+        write::string(buf, change);
+        write::inet(buf, address);
+    }
+
+    pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, change) = parse::short_string(buf)?;
+        let (rest, address) = parse::inet(rest)?;
+
+        match change {
+            "NEW_NODE" => Ok((rest, TopologyChangeEvent::NewNode(address))),
+            "REMOVED_NODE" => Ok((rest, TopologyChangeEvent::RemovedNode(address))),
+            _ => Err(unknown(buf)),
+        }
+    }
+}
+
+impl StatusChangeEvent {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        let (change, address) = match self {
+            StatusChangeEvent::Up(address) => ("UP", address),
+            StatusChangeEvent::Down(address) => ("DOWN", address),
+        };
+        write::string(buf, change);
+        write::inet(buf, address);
+    }
+
+    pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, change) = parse::short_string(buf)?;
+        let (rest, address) = parse::inet(rest)?;
+
+        match change {
+            "UP" => Ok((rest, StatusChangeEvent::Up(address))),
+            "DOWN" => Ok((rest, StatusChangeEvent::Down(address))),
+            _ => Err(unknown(buf)),
+        }
+    }
+}
+
+impl SchemaChangeEvent {
+    pub fn serialize(&self, buf: &mut impl BufMut) {
+        match self {
+            SchemaChangeEvent::KeyspaceChange {
+                change_type,
+                keyspace_name,
+            } => {
+                change_type.write(buf);
+                write::string(buf, "KEYSPACE");
+                write::string(buf, keyspace_name);
+            }
+            SchemaChangeEvent::TableChange {
+                change_type,
+                keyspace_name,
+                object_name,
+            } => {
+                change_type.write(buf);
+                write::string(buf, "TABLE");
+                write::string(buf, keyspace_name);
+                write::string(buf, object_name);
+            }
+            SchemaChangeEvent::TypeChange {
+                change_type,
+                keyspace_name,
+                type_name,
+            } => {
+                change_type.write(buf);
+                write::string(buf, "TYPE");
+                write::string(buf, keyspace_name);
+                write::string(buf, type_name);
+            }
+            SchemaChangeEvent::FunctionChange {
+                change_type,
+                keyspace_name,
+                function_name,
+                arguments,
+            } => {
+                change_type.write(buf);
+                write::string(buf, "FUNCTION");
+                write::string(buf, keyspace_name);
+                write::string(buf, function_name);
+                write::string_list(buf, arguments);
+            }
+            SchemaChangeEvent::AggregateChange {
+                change_type,
+                keyspace_name,
+                aggregate_name,
+                arguments,
+            } => {
+                change_type.write(buf);
+                write::string(buf, "AGGREGATE");
+                write::string(buf, keyspace_name);
+                write::string(buf, aggregate_name);
+                write::string_list(buf, arguments);
+            }
+        }
+    }
+
+    pub fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, change_type) = SchemaChangeType::deserialize(buf)?;
+        let (rest, target) = parse::short_string(rest)?;
+        let (rest, keyspace_name) = parse::short_string(rest)?;
+        let keyspace_name = keyspace_name.to_owned();
+
+        if target == "KEYSPACE" {
+            return Ok((
+                rest,
+                SchemaChangeEvent::KeyspaceChange {
+                    change_type,
+                    keyspace_name,
+                },
+            ));
+        }
+
+        let (rest, name) = parse::short_string(rest)?;
+        let name = name.to_owned();
+
+        match target {
+            "TABLE" => Ok((
+                rest,
+                SchemaChangeEvent::TableChange {
+                    change_type,
+                    keyspace_name,
+                    object_name: name,
+                },
+            )),
+            "TYPE" => Ok((
+                rest,
+                SchemaChangeEvent::TypeChange {
+                    change_type,
+                    keyspace_name,
+                    type_name: name,
+                },
+            )),
+            "FUNCTION" | "AGGREGATE" => {
+                let (rest, arguments) = parse::short_string_list(rest)?;
+                let arguments = arguments.into_iter().map(|it| it.to_owned()).collect();
+
+                let event = if target == "FUNCTION" {
+                    SchemaChangeEvent::FunctionChange {
+                        change_type,
+                        keyspace_name,
+                        function_name: name,
+                        arguments,
+                    }
+                } else {
+                    SchemaChangeEvent::AggregateChange {
+                        change_type,
+                        keyspace_name,
+                        aggregate_name: name,
+                        arguments,
+                    }
+                };
+
+                Ok((rest, event))
+            }
+            _ => Err(unknown(buf)),
+        }
+    }
+}
+
 impl SchemaChangeType {
     pub(crate) fn write(&self, buf: &mut impl BufMut) {
         match self {
@@ -70,4 +278,19 @@ impl SchemaChangeType {
             SchemaChangeType::Invalid => write::string(buf, "INVALID"),
         }
     }
+
+    pub(crate) fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, change_type) = parse::short_string(buf)?;
+
+        match change_type {
+            "CREATED" => Ok((rest, SchemaChangeType::Created)),
+            "UPDATED" => Ok((rest, SchemaChangeType::Updated)),
+            "DROPPED" => Ok((rest, SchemaChangeType::Dropped)),
+            _ => Err(unknown(buf)),
+        }
+    }
+}
+
+fn unknown(buf: &[u8]) -> nom::Err<Error<&[u8]>> {
+    nom::Err::Failure(Error::new(buf, ErrorKind::Tag))
 }
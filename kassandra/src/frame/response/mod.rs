@@ -6,7 +6,7 @@ use tokio_util::codec::{Decoder, Encoder};
 
 use crate::{
     error::DbError,
-    frame::{FrameFlags, FrameParams, ProtocolVersion},
+    frame::{parse, write, FrameFlags, FrameParams, ProtocolVersion},
 };
 
 pub mod authenticate;
@@ -38,6 +38,11 @@ pub enum Response {
     Event(event::Event),
     AuthChallenge(authenticate::AuthChallenge),
     AuthSuccess(authenticate::AuthSuccess),
+    /// Response sent with the `WARNING` flag, its warnings come before the body
+    Warned {
+        warnings: Vec<String>,
+        response: Box<Response>,
+    },
 }
 
 impl Response {
@@ -51,19 +56,29 @@ impl Response {
             Self::Event { .. } => 0x0C,
             Self::AuthChallenge { .. } => 0x0E,
             Self::AuthSuccess { .. } => 0x10,
+            Self::Warned { response, .. } => response.opcode(),
+        }
+    }
+
+    /// The response itself when there is no warning
+    pub fn with_warnings(self, warnings: Vec<String>) -> Self {
+        if warnings.is_empty() {
+            return self;
+        }
+
+        Self::Warned {
+            warnings,
+            response: Box::new(self),
         }
     }
 
     pub fn options() -> Self {
-        Response::Supported(supported::Supported {
-            options: vec![
-                ("CQL_VERSION".to_owned(), vec!["3.0.0".to_owned()]),
-                ("COMPRESSION".to_owned(), vec![]),
-                ("PROTOCOL_VERSIONS".to_owned(), vec!["4/v4".to_owned()]),
-            ]
-            .into_iter()
-            .collect(),
-        })
+        Self::supported(&supported::Features::default(), 0)
+    }
+
+    /// Options a server with the features advertises to a connection served by the `shard`
+    pub fn supported(features: &supported::Features, shard: usize) -> Self {
+        Response::Supported(supported::Supported::of(features, shard))
     }
 
     /// Sends ProtocolError response with a message that most drivers expect to receive
@@ -75,7 +90,11 @@ impl Response {
         ))
     }
 
-    pub fn serialize(&self, buf: &mut impl BufMut, _flags: &mut FrameFlags) -> Result<()> {
+    pub fn serialize(
+        &self,
+        buf: &mut (impl BufMut + AsMut<[u8]>),
+        flags: &mut FrameFlags,
+    ) -> Result<()> {
         match self {
             Response::Supported(supported) => {
                 supported.serialize(buf)?;
@@ -86,24 +105,82 @@ impl Response {
                 er.serialize(buf);
                 Ok(())
             }
-            Response::Authenticate(_) => {
-                unimplemented!()
+            Response::Authenticate(authenticate) => {
+                authenticate.serialize(buf);
+                Ok(())
             }
             Response::Result(res) => {
                 res.serialize(buf)?;
                 Ok(())
             }
-            Response::Event(_) => {
-                unimplemented!()
+            Response::Event(event) => {
+                event.serialize(buf);
+                Ok(())
+            }
+            Response::AuthChallenge(challenge) => {
+                challenge.serialize(buf);
+                Ok(())
             }
-            Response::AuthChallenge(_) => {
-                unimplemented!()
+            Response::AuthSuccess(success) => {
+                success.serialize(buf);
+                Ok(())
             }
-            Response::AuthSuccess(_) => {
-                unimplemented!()
+            Response::Warned { warnings, response } => {
+                flags.insert(FrameFlags::WARNING);
+                write::string_list(buf, warnings);
+                response.serialize(buf, flags)
             }
         }
     }
+
+    /// Reads the body of a response frame, warnings of the `WARNING` flag go before it
+    pub fn deserialize(opcode: ResponseOpcode, data: &[u8], flags: FrameFlags) -> Result<Self> {
+        if flags.intersects(FrameFlags::TRACING | FrameFlags::CUSTOM_PAYLOAD) {
+            return Err(eyre!("Tracing and custom payloads are not supported"));
+        }
+        if flags.contains(FrameFlags::WARNING) {
+            let (rest, warnings) = parse::short_string_list(data)
+                .map_err(|_| eyre!("Could not parse warnings of the response"))?;
+            let warnings = warnings.into_iter().map(str::to_owned).collect();
+            let response = Self::deserialize(opcode, rest, flags - FrameFlags::WARNING)?;
+
+            return Ok(response.with_warnings(warnings));
+        }
+
+        let invalid = |name| move |_| eyre!("Could not parse {name} response");
+        let response = match opcode {
+            ResponseOpcode::Error => {
+                let (_, error) = error::Error::deserialize(data).map_err(invalid("Error"))?;
+                Response::Error(error)
+            }
+            ResponseOpcode::Ready => Response::Ready,
+            ResponseOpcode::Authenticate => {
+                let (_, authenticate) = authenticate::Authenticate::deserialize(data)
+                    .map_err(invalid("Authenticate"))?;
+                Response::Authenticate(authenticate)
+            }
+            ResponseOpcode::Supported => {
+                Response::Supported(supported::Supported::deserialize(data)?)
+            }
+            ResponseOpcode::Result => Response::Result(result::QueryResult::deserialize(data)?),
+            ResponseOpcode::Event => {
+                let (_, event) = event::Event::deserialize(data).map_err(invalid("Event"))?;
+                Response::Event(event)
+            }
+            ResponseOpcode::AuthChallenge => {
+                let (_, challenge) = authenticate::AuthChallenge::deserialize(data)
+                    .map_err(invalid("AuthChallenge"))?;
+                Response::AuthChallenge(challenge)
+            }
+            ResponseOpcode::AuthSuccess => {
+                let (_, success) =
+                    authenticate::AuthSuccess::deserialize(data).map_err(invalid("AuthSuccess"))?;
+                Response::AuthSuccess(success)
+            }
+        };
+
+        Ok(response)
+    }
 }
 
 #[derive(Debug, Copy, Clone, Default)]
@@ -117,11 +194,13 @@ impl Encoder<(Response, i16)> for ResponseFrameCodec {
         (response, stream_id): (Response, i16),
         dst: &mut BytesMut,
     ) -> std::result::Result<(), Self::Error> {
+        // frames queued before this one stay in front of it, the header is filled in once the body is written
+        let start = dst.len();
         let mut flags = FrameFlags::empty();
-        dst.resize(9, 0);
+        dst.put_bytes(0, 9);
         response.serialize(dst, &mut flags)?;
 
-        let (mut header, data) = dst.split_at_mut(9);
+        let (mut header, data) = dst[start..].split_at_mut(9);
 
         header.put_u8(0x84); // version
         header.put_u8(flags.bits());
@@ -207,3 +286,119 @@ impl Decoder for ResponseFrameCodec {
         Ok(Some((frame, opcode, Bytes::from(body))))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::codec::Encoder;
+
+    use super::{
+        authenticate::{AuthChallenge, AuthSuccess, Authenticate},
+        event::{
+            Event, SchemaChangeEvent, SchemaChangeType, StatusChangeEvent, TopologyChangeEvent,
+        },
+        Response, ResponseFrameCodec,
+    };
+    use crate::frame::FrameFlags;
+
+    fn serialize(response: &Response) -> Vec<u8> {
+        let mut buf = vec![];
+        response
+            .serialize(&mut buf, &mut FrameFlags::empty())
+            .unwrap();
+        buf
+    }
+
+    #[test]
+    fn frames_are_encoded_after_queued_ones() {
+        let mut dst = bytes::BytesMut::new();
+        ResponseFrameCodec
+            .encode((Response::Ready, 1), &mut dst)
+            .unwrap();
+        ResponseFrameCodec
+            .encode((Response::Ready, 2), &mut dst)
+            .unwrap();
+
+        assert_eq!(
+            &dst[..],
+            b"\x84\0\0\x01\x02\0\0\0\0\x84\0\0\x02\x02\0\0\0\0"
+        );
+    }
+
+    #[test]
+    fn authenticate() {
+        let data: &[u8] = b"\0\x2forg.apache.cassandra.auth.PasswordAuthenticator";
+        let authenticate = Authenticate {
+            authenticator_name: "org.apache.cassandra.auth.PasswordAuthenticator".to_owned(),
+        };
+
+        assert_eq!(
+            Authenticate::deserialize(data).unwrap(),
+            (&[][..], authenticate.clone())
+        );
+        assert_eq!(serialize(&Response::Authenticate(authenticate)), data);
+    }
+
+    #[test]
+    fn auth_challenge_and_success() {
+        let data: &[u8] = b"\0\0\0\x03abc";
+        let challenge = AuthChallenge {
+            authenticate_message: Some(b"abc".to_vec()),
+        };
+        assert_eq!(
+            AuthChallenge::deserialize(data).unwrap(),
+            (&[][..], challenge.clone())
+        );
+        assert_eq!(serialize(&Response::AuthChallenge(challenge)), data);
+
+        let data: &[u8] = b"\xff\xff\xff\xff";
+        let success = AuthSuccess {
+            success_message: None,
+        };
+        assert_eq!(
+            AuthSuccess::deserialize(data).unwrap(),
+            (&[][..], success.clone())
+        );
+        assert_eq!(serialize(&Response::AuthSuccess(success)), data);
+    }
+
+    #[test]
+    fn events() {
+        let fixtures: Vec<(&[u8], Event)> = vec![
+            (
+                b"\0\x0fTOPOLOGY_CHANGE\0\x08NEW_NODE\x10\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\0\x23\x52",
+                Event::TopologyChange(TopologyChangeEvent::NewNode("[::1]:9042".parse().unwrap())),
+            ),
+            (
+                b"\0\x0dSTATUS_CHANGE\0\x04DOWN\x04\x7f\0\0\x01\0\0\x23\x52",
+                Event::StatusChange(StatusChangeEvent::Down("127.0.0.1:9042".parse().unwrap())),
+            ),
+            (
+                b"\0\x0dSCHEMA_CHANGE\0\x07CREATED\0\x05TABLE\0\x07cycling\0\x0ccyclist_name",
+                Event::SchemaChange(SchemaChangeEvent::TableChange {
+                    change_type: SchemaChangeType::Created,
+                    keyspace_name: "cycling".to_owned(),
+                    object_name: "cyclist_name".to_owned(),
+                }),
+            ),
+            (
+                b"\0\x0dSCHEMA_CHANGE\0\x07DROPPED\0\x08FUNCTION\0\x02ks\0\x03avg\0\x02\0\x03int\0\x04text",
+                Event::SchemaChange(SchemaChangeEvent::FunctionChange {
+                    change_type: SchemaChangeType::Dropped,
+                    keyspace_name: "ks".to_owned(),
+                    function_name: "avg".to_owned(),
+                    arguments: vec!["int".to_owned(), "text".to_owned()],
+                }),
+            ),
+        ];
+
+        for (data, event) in fixtures {
+            assert_eq!(Event::deserialize(data).unwrap(), (&[][..], event.clone()));
+            assert_eq!(serialize(&Response::Event(event)), data);
+        }
+    }
+
+    #[test]
+    fn unknown_event_type() {
+        assert!(Event::deserialize(b"\0\x07UNKNOWN").is_err());
+    }
+}
@@ -1,23 +1,45 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
 use bitflags::bitflags;
-use bytes::BufMut;
+use bytes::{BufMut, Bytes, BytesMut};
+use nom::{
+    number::complete::{be_i32, be_u32},
+    IResult,
+};
 use serde::Serialize;
 
 use crate::{
-    cql::{column::ColumnType, value::CqlValue},
-    frame::{response::event::SchemaChangeEvent, value::PagingState, write},
+    cql::{
+        column::ColumnType,
+        value::{opt_deserialize_value, CqlValue},
+    },
+    error::DbError,
+    frame::{
+        parse,
+        response::{error::Error, event::SchemaChangeEvent},
+        value::PagingState,
+        write,
+    },
 };
 
 #[derive(Debug)]
 pub enum QueryResult {
     Void,
     Rows(Rows),
+    RowStream(RowStream),
     SetKeyspace(SetKeyspace),
     Prepared(Prepared),
     SchemaChange(SchemaChange),
 }
 
 impl QueryResult {
-    pub fn serialize(&self, buf: &mut impl BufMut) -> eyre::Result<()> {
+    pub fn serialize(&self, buf: &mut (impl BufMut + AsMut<[u8]>)) -> eyre::Result<()> {
         match self {
             QueryResult::Void => {
                 buf.put_i32(0x0001);
@@ -26,6 +48,10 @@ impl QueryResult {
                 buf.put_i32(0x0002);
                 rows.serialize(buf);
             }
+            QueryResult::RowStream(stream) => {
+                buf.put_i32(0x0002);
+                stream.serialize(buf)?;
+            }
             QueryResult::SetKeyspace(set) => {
                 buf.put_i32(0x0003);
                 write::string(buf, &set.keyspace_name);
@@ -42,6 +68,46 @@ impl QueryResult {
 
         Ok(())
     }
+
+    /// Reads results the way servers send them: void, rows, `USE` and schema changes.
+    /// Prepared results and paged rows are not read yet.
+    pub fn deserialize(buf: &[u8]) -> Result<Self, Error> {
+        let (rest, kind) = be_i32::<_, nom::error::Error<_>>(buf)?;
+        let result = match kind {
+            0x0001 => QueryResult::Void,
+            0x0002 => QueryResult::Rows(Rows::deserialize(rest)?),
+            0x0003 => {
+                let (_, keyspace_name) = parse::short_string(rest)?;
+                QueryResult::SetKeyspace(SetKeyspace {
+                    keyspace_name: keyspace_name.to_owned(),
+                })
+            }
+            0x0005 => {
+                let (_, event) = SchemaChangeEvent::deserialize(rest)?;
+                QueryResult::SchemaChange(SchemaChange { event })
+            }
+            kind => {
+                return Err(Error::new(
+                    DbError::ProtocolError,
+                    format!("Results of kind {kind:#06x} can't be deserialized yet"),
+                ))
+            }
+        };
+
+        Ok(result)
+    }
+
+    /// Leaves column specs out of rows, the way `SKIP_METADATA` asks for,
+    /// but only when they are still the `prepared` ones clients read the rows by
+    pub fn skip_metadata(&mut self, prepared: &[ColumnSpec]) {
+        match self {
+            QueryResult::Rows(rows) => {
+                rows.metadata.no_metadata = rows.metadata.col_specs == prepared;
+            }
+            QueryResult::RowStream(stream) => stream.skip_metadata(prepared),
+            _ => {}
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -71,35 +137,7 @@ pub struct SchemaChange {
 
 impl SchemaChange {
     pub fn serialize(&self, buf: &mut impl BufMut) -> eyre::Result<()> {
-        match self.event {
-            SchemaChangeEvent::KeyspaceChange {
-                change_type,
-                ref keyspace_name,
-            } => {
-                change_type.write(buf);
-                write::string(buf, "KEYSPACE");
-                write::string(buf, keyspace_name);
-            }
-            SchemaChangeEvent::TableChange {
-                change_type,
-                ref keyspace_name,
-                ref object_name,
-            } => {
-                change_type.write(buf);
-                write::string(buf, "TABLE");
-                write::string(buf, keyspace_name);
-                write::string(buf, object_name);
-            }
-            SchemaChangeEvent::TypeChange { .. } => {
-                unimplemented!()
-            }
-            SchemaChangeEvent::FunctionChange { .. } => {
-                unimplemented!()
-            }
-            SchemaChangeEvent::AggregateChange { .. } => {
-                unimplemented!()
-            }
-        }
+        self.event.serialize(buf);
         Ok(())
     }
 }
@@ -115,9 +153,22 @@ impl TableSpec {
         write::string(buf, &self.ks_name);
         write::string(buf, &self.table_name);
     }
+
+    fn deserialize(buf: &[u8]) -> IResult<&[u8], Self> {
+        let (rest, ks_name) = parse::short_string(buf)?;
+        let (rest, table_name) = parse::short_string(rest)?;
+
+        Ok((
+            rest,
+            Self {
+                ks_name: ks_name.to_owned(),
+                table_name: table_name.to_owned(),
+            },
+        ))
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct ColumnSpec {
     pub table_spec: Option<TableSpec>,
     pub name: String,
@@ -148,6 +199,10 @@ pub struct ResultMetadata {
     pub global_spec: Option<TableSpec>,
     pub paging_state: Option<PagingState>,
     pub col_specs: Vec<ColumnSpec>,
+    /// Column specs are left out and only counted, clients read the rows by the specs
+    /// they got when preparing the statement
+    #[serde(skip)]
+    pub no_metadata: bool,
 }
 
 impl ResultMetadata {
@@ -162,11 +217,11 @@ impl ResultMetadata {
             flags |= ResultMetadataFlags::HAS_MORE_PAGES
         }
 
-        if self.col_specs.is_empty() {
+        if self.col_specs.is_empty() || self.no_metadata {
             flags |= ResultMetadataFlags::NO_METADATA;
 
             buf.put_u32(flags.bits());
-            buf.put_u32(0);
+            buf.put_u32(self.col_specs.len() as u32);
 
             if let Some(state) = &self.paging_state {
                 PagingState::encode(state, buf);
@@ -202,6 +257,57 @@ impl ResultMetadata {
             write::r#type(buf, &spec.typ);
         }
     }
+
+    fn deserialize(buf: &[u8]) -> Result<(&[u8], Self), Error> {
+        let (rest, flags) = be_u32::<_, nom::error::Error<_>>(buf)?;
+        let flags = ResultMetadataFlags::from_bits_truncate(flags);
+        let (mut rest, count) = be_u32::<_, nom::error::Error<_>>(rest)?;
+
+        if flags.contains(ResultMetadataFlags::HAS_MORE_PAGES) {
+            return Err(Error::new(
+                DbError::ProtocolError,
+                "Paging states of results can't be deserialized yet",
+            ));
+        }
+        if flags.contains(ResultMetadataFlags::NO_METADATA) {
+            return Ok((rest, ResultMetadata::empty()));
+        }
+
+        let mut global_spec = None;
+        if flags.contains(ResultMetadataFlags::GLOBAL_TABLES_SPEC) {
+            let (r, spec) = TableSpec::deserialize(rest)?;
+            rest = r;
+            global_spec = Some(spec);
+        }
+
+        let mut col_specs = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut table_spec = None;
+            if global_spec.is_none() {
+                let (r, spec) = TableSpec::deserialize(rest)?;
+                rest = r;
+                table_spec = Some(spec);
+            }
+            let (r, name) = parse::short_string(rest)?;
+            let (r, typ) = parse::column_type(r)?;
+            rest = r;
+            col_specs.push(ColumnSpec {
+                table_spec,
+                name: name.to_owned(),
+                typ,
+            });
+        }
+
+        Ok((
+            rest,
+            Self {
+                global_spec,
+                paging_state: None,
+                col_specs,
+                no_metadata: false,
+            },
+        ))
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -256,7 +362,7 @@ impl PreparedMetadata {
     }
 }
 
-#[derive(Debug, Default, PartialEq, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
 pub struct Row {
     pub columns: Vec<Option<CqlValue>>,
 }
@@ -277,20 +383,158 @@ impl Row {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Rows {
     pub metadata: ResultMetadata,
     pub rows: Vec<Row>,
+    /// Count and rows as they are serialized, written instead of `rows` by cached results
+    #[serde(skip)]
+    pub encoded: Option<Bytes>,
 }
 
 impl Rows {
     pub fn serialize(&self, buf: &mut impl BufMut) {
         self.metadata.serialize(buf);
 
+        match &self.encoded {
+            Some(encoded) => buf.put_slice(encoded),
+            None => self.serialize_rows(buf),
+        }
+    }
+
+    /// Rows along with their serialized form, so they are not serialized again
+    pub fn encode(mut self) -> Self {
+        let mut buf = BytesMut::new();
+        self.serialize_rows(&mut buf);
+        self.encoded = Some(buf.freeze());
+        self
+    }
+
+    fn serialize_rows(&self, buf: &mut impl BufMut) {
         // rows_count serialization
         buf.put_u32(self.rows.len() as _);
         for row in &self.rows {
             row.serialize(buf);
         }
     }
+
+    pub fn deserialize(buf: &[u8]) -> Result<Self, Error> {
+        let (rest, metadata) = ResultMetadata::deserialize(buf)?;
+        let (mut rest, count) = be_u32::<_, nom::error::Error<_>>(rest)?;
+
+        let mut rows = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut row = Row::new();
+            for spec in &metadata.col_specs {
+                let (r, value) = opt_deserialize_value(rest, &spec.typ)?;
+                rest = r;
+                row.push(value);
+            }
+            rows.push(row);
+        }
+
+        Ok(Rows {
+            metadata,
+            rows,
+            encoded: None,
+        })
+    }
+}
+
+/// Source of the rows of a [`RowStream`]
+pub trait RowChunks: Send {
+    /// Next rows of the result, an empty chunk ends the stream
+    fn next_chunk(&mut self) -> Result<Vec<Row>, Error>;
+
+    /// Fixes where the result ends, so its paging state is known before the first chunk
+    fn end_page(&mut self) -> Result<(), Error>;
+
+    /// Metadata of the result, its paging state is only known once the stream ended or its end is fixed
+    fn metadata(&self) -> ResultMetadata;
+}
+
+/// Rows result pulled chunk by chunk while the response is serialized,
+/// so rows of a large page are never kept in memory as a whole, only their encoded form is.
+pub struct RowStream {
+    chunks: Mutex<Box<dyn RowChunks>>,
+    rows_written: Arc<AtomicUsize>,
+    no_metadata: bool,
+}
+
+impl fmt::Debug for RowStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RowStream")
+            .field("rows_written", &self.rows_written)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RowStream {
+    pub fn new(chunks: impl RowChunks + 'static) -> Self {
+        Self {
+            chunks: Mutex::new(Box::new(chunks)),
+            rows_written: Arc::default(),
+            no_metadata: false,
+        }
+    }
+
+    fn skip_metadata(&mut self, prepared: &[ColumnSpec]) {
+        self.no_metadata = self.chunks.get_mut().unwrap().metadata().col_specs == prepared;
+    }
+
+    /// Counter of rows serialized so far, it is still available after the stream is consumed
+    pub fn rows_written(&self) -> Arc<AtomicUsize> {
+        self.rows_written.clone()
+    }
+
+    /// Pulls every chunk at once
+    pub fn collect(self) -> Result<Rows, Error> {
+        let mut chunks = self.chunks.into_inner().unwrap();
+        let mut rows = vec![];
+        loop {
+            let chunk = chunks.next_chunk()?;
+            if chunk.is_empty() {
+                break;
+            }
+            rows.extend(chunk);
+        }
+
+        let mut metadata = chunks.metadata();
+        metadata.no_metadata = self.no_metadata;
+
+        Ok(Rows {
+            metadata,
+            rows,
+            encoded: None,
+        })
+    }
+
+    /// Metadata goes before the rows, so the end of the page is fixed first to know its paging state.
+    /// Chunks are then serialized straight after it, the row count is written once the last one is.
+    fn serialize(&self, buf: &mut (impl BufMut + AsMut<[u8]>)) -> Result<(), Error> {
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks.end_page()?;
+
+        let mut metadata = chunks.metadata();
+        metadata.no_metadata = self.no_metadata;
+        metadata.serialize(buf);
+
+        let count_at = buf.as_mut().len();
+        buf.put_u32(0);
+        let mut count = 0;
+        loop {
+            let chunk = chunks.next_chunk()?;
+            if chunk.is_empty() {
+                break;
+            }
+            for row in &chunk {
+                row.serialize(buf);
+            }
+            count += chunk.len();
+            self.rows_written.store(count, Ordering::Relaxed);
+        }
+        buf.as_mut()[count_at..count_at + 4].copy_from_slice(&(count as u32).to_be_bytes());
+
+        Ok(())
+    }
 }
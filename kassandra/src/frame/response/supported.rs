@@ -1,16 +1,91 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, num::NonZeroUsize};
 
 use bytes::BufMut;
 use eyre::{eyre, Result};
 
-use crate::{frame, frame::parse};
+use crate::{
+    compat::CompatibilityProfile, cql::partitioner::Murmur3Partitioner, frame, frame::parse,
+};
+
+/// Bits of tokens scylla ignores when mapping them to shards
+const SHARDING_IGNORE_MSB: u8 = 12;
 
 #[derive(Debug)]
 pub struct Supported {
     pub options: HashMap<String, Vec<String>>,
 }
 
+/// What a server supports, `SUPPORTED` responses advertise it to drivers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Server whose versions are advertised, the ones of kassandra itself if there is none
+    pub profile: Option<CompatibilityProfile>,
+    /// Algorithms frame bodies can be compressed with
+    pub compression: Vec<String>,
+    /// `THROW_ON_OVERLOAD` option of `STARTUP` is honored
+    pub throw_on_overload: bool,
+    /// Shards advertised with the `SCYLLA_*` extensions, so shard-aware drivers open a connection per shard.
+    /// Every shard serves the same data.
+    pub shards: Option<NonZeroUsize>,
+    /// Port connections are pinned to the shard of their source port at, `port % shards`
+    pub shard_aware_port: Option<u16>,
+    /// The same as `shard_aware_port`, for clients using tls
+    pub shard_aware_port_ssl: Option<u16>,
+}
+
 impl Supported {
+    /// Options of a connection served by the `shard`, which is ignored without [`Features::shards`]
+    pub fn of(features: &Features, shard: usize) -> Self {
+        let (cql_version, protocol_versions) = match features.profile {
+            Some(profile) => (
+                profile.cql_version().to_owned(),
+                profile.supported_protocol_versions(),
+            ),
+            None => ("3.0.0".to_owned(), vec!["4/v4".to_owned()]),
+        };
+
+        let mut options = HashMap::from([
+            ("CQL_VERSION".to_owned(), vec![cql_version]),
+            ("COMPRESSION".to_owned(), features.compression.clone()),
+            ("PROTOCOL_VERSIONS".to_owned(), protocol_versions),
+        ]);
+        if features.throw_on_overload {
+            options.insert("THROW_ON_OVERLOAD".to_owned(), vec!["1".to_owned()]);
+        }
+        if let Some(shards) = features.shards {
+            options.extend([
+                (
+                    "SCYLLA_SHARD".to_owned(),
+                    vec![(shard % shards).to_string()],
+                ),
+                ("SCYLLA_NR_SHARDS".to_owned(), vec![shards.to_string()]),
+                (
+                    "SCYLLA_PARTITIONER".to_owned(),
+                    vec![Murmur3Partitioner::NAME.to_owned()],
+                ),
+                (
+                    "SCYLLA_SHARDING_ALGORITHM".to_owned(),
+                    vec!["biased-token-round-robin".to_owned()],
+                ),
+                (
+                    "SCYLLA_SHARDING_IGNORE_MSB".to_owned(),
+                    vec![SHARDING_IGNORE_MSB.to_string()],
+                ),
+            ]);
+            if let Some(port) = features.shard_aware_port {
+                options.insert("SCYLLA_SHARD_AWARE_PORT".to_owned(), vec![port.to_string()]);
+            }
+            if let Some(port) = features.shard_aware_port_ssl {
+                options.insert(
+                    "SCYLLA_SHARD_AWARE_PORT_SSL".to_owned(),
+                    vec![port.to_string()],
+                );
+            }
+        }
+
+        Self { options }
+    }
+
     pub fn deserialize(buf: &[u8]) -> Result<Self> {
         let (_, map) =
             parse::string_multimap(buf).map_err(|_| eyre!("Could not parse Supported response"))?;
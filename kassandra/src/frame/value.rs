@@ -1,8 +1,8 @@
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{BufMut, Bytes};
 use nom::AsBytes;
 use serde::Serialize;
 
-use crate::frame::write;
+use crate::frame::{pool, write};
 
 #[derive(Debug, Clone)]
 pub enum FrameValue<'a> {
@@ -35,12 +35,13 @@ impl PagingState {
     }
 
     pub fn encode(&self, dst: &mut impl BufMut) {
-        let mut b = BytesMut::new();
+        let mut b = pool::take();
         write::opt_buffer_varint(&mut b, self.partition_key.as_ref());
         write::opt_buffer_varint(&mut b, self.row_mark.as_ref());
         write::unsigned_varint(&mut b, self.remaining as _);
         write::unsigned_varint(&mut b, self.remaining_in_partition as _);
 
         write::bytes(dst, b.as_bytes());
+        pool::give(b);
     }
 }
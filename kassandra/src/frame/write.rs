@@ -1,14 +1,17 @@
-use std::{collections::HashMap, net::IpAddr};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+};
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{BufMut, Bytes};
 use nom::AsBytes;
 
 use crate::{
     cql::{
         column::ColumnType,
-        value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+        value::{ClusteringKeyValue, CqlDuration, CqlValue, PartitionKeyValue},
     },
-    frame::consistency::LegacyConsistency,
+    frame::{consistency::LegacyConsistency, pool, value::FrameValue},
 };
 
 pub(crate) fn string_multimap(buf: &mut impl BufMut, value: &HashMap<String, Vec<String>>) {
@@ -56,6 +59,35 @@ pub(crate) fn bytes(buf: &mut impl BufMut, value: &[u8]) {
     buf.put_slice(value.as_bytes());
 }
 
+pub(crate) fn bytes_opt(buf: &mut impl BufMut, value: Option<&[u8]>) {
+    match value {
+        Some(value) => bytes(buf, value),
+        None => buf.put_i32(-1),
+    }
+}
+
+pub(crate) fn value(buf: &mut impl BufMut, value: &FrameValue) {
+    match value {
+        FrameValue::Some(value) => bytes(buf, value),
+        FrameValue::Null => buf.put_i32(-1),
+        FrameValue::NotSet => buf.put_i32(-2),
+    }
+}
+
+pub(crate) fn inet(buf: &mut impl BufMut, value: &SocketAddr) {
+    match value.ip() {
+        IpAddr::V4(ip) => {
+            buf.put_u8(4);
+            buf.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.put_u8(16);
+            buf.put_slice(&ip.octets());
+        }
+    }
+    buf.put_i32(value.port() as i32);
+}
+
 pub(crate) fn r#type(buf: &mut impl BufMut, value: &ColumnType) {
     match value {
         ColumnType::Custom(n) => {
@@ -135,13 +167,26 @@ pub(crate) fn r#type(buf: &mut impl BufMut, value: &ColumnType) {
             buf.put_u16(0x0022);
             r#type(buf, i);
         }
-        ColumnType::UserDefinedType { .. } => {
+        ColumnType::UserDefinedType {
+            type_name,
+            keyspace,
+            field_types,
+        } => {
             buf.put_u16(0x0030);
-            unimplemented!()
+            string(buf, keyspace);
+            string(buf, type_name);
+            buf.put_u16(field_types.len() as u16);
+            for (name, ty) in field_types {
+                string(buf, name);
+                r#type(buf, ty);
+            }
         }
-        ColumnType::Tuple(_) => {
+        ColumnType::Tuple(types) => {
             buf.put_u16(0x0031);
-            unimplemented!()
+            buf.put_u16(types.len() as u16);
+            for ty in types {
+                r#type(buf, ty);
+            }
         }
     }
 }
@@ -161,13 +206,17 @@ pub(crate) fn opt_cql_value(buf: &mut impl BufMut, value: Option<&CqlValue>) {
             buf.put_u8(*b as u8);
         }
         CqlValue::Blob(v) => {
-            bytes(buf, v.as_slice());
+            bytes(buf, v);
         }
         CqlValue::Counter(i) => {
             bytes(buf, &i.to_be_bytes());
         }
-        CqlValue::Decimal(_i) => {
-            unimplemented!()
+        CqlValue::Decimal(v) => {
+            let (unscaled, scale) = v.as_bigint_and_exponent();
+            let unscaled = unscaled.to_signed_bytes_be();
+            buf.put_u32(4 + unscaled.len() as u32);
+            buf.put_i32(scale as i32);
+            buf.put_slice(&unscaled);
         }
         CqlValue::Date(i) => {
             bytes(buf, &i.to_be_bytes());
@@ -175,8 +224,11 @@ pub(crate) fn opt_cql_value(buf: &mut impl BufMut, value: Option<&CqlValue>) {
         CqlValue::Double(f) => {
             bytes(buf, &f.to_be_bytes());
         }
-        CqlValue::Duration(_i) => {
-            unimplemented!()
+        CqlValue::Duration(v) => {
+            buf.put_u32(duration_size(v) as u32);
+            signed_vint(buf, v.months as i64);
+            signed_vint(buf, v.days as i64);
+            signed_vint(buf, v.nanoseconds);
         }
         CqlValue::Empty => {
             buf.put_u32(0);
@@ -206,36 +258,26 @@ pub(crate) fn opt_cql_value(buf: &mut impl BufMut, value: Option<&CqlValue>) {
                 buf.put_slice(&ip.octets());
             }
         },
-        CqlValue::List(list) => {
-            let mut bytes = vec![];
+        CqlValue::List(list) | CqlValue::Set(list) => {
+            buf.put_u32(opt_cql_value_size(Some(value)) as u32 - 4);
+            buf.put_u32(list.len() as _);
             for v in list {
-                opt_cql_value(&mut bytes, Some(v));
+                opt_cql_value(buf, Some(v));
             }
-            buf.put_u32(4 + bytes.len() as u32);
-            buf.put_u32(list.len() as _);
-            buf.put_slice(bytes.as_slice());
         }
         CqlValue::Map(map) => {
-            let mut bytes = vec![];
+            buf.put_u32(opt_cql_value_size(Some(value)) as u32 - 4);
+            buf.put_u32(map.len() as _);
             for (k, v) in map {
-                opt_cql_value(&mut bytes, Some(k));
-                opt_cql_value(&mut bytes, Some(v));
+                opt_cql_value(buf, Some(k));
+                opt_cql_value(buf, Some(v));
             }
-            buf.put_u32(4 + bytes.len() as u32);
-            buf.put_u32(map.len() as _);
-            buf.put_slice(bytes.as_slice());
         }
-        CqlValue::Set(list) => {
-            let mut bytes = vec![];
-            for v in list {
-                opt_cql_value(&mut bytes, Some(v));
+        CqlValue::UserDefinedType { fields, .. } => {
+            buf.put_u32(opt_cql_value_size(Some(value)) as u32 - 4);
+            for (_, v) in fields {
+                opt_cql_value(buf, v.as_ref());
             }
-            buf.put_u32(4 + bytes.len() as u32);
-            buf.put_u32(list.len() as _);
-            buf.put_slice(bytes.as_slice());
-        }
-        CqlValue::UserDefinedType { .. } => {
-            unimplemented!()
         }
         CqlValue::SmallInt(i) => {
             bytes(buf, &i.to_be_bytes());
@@ -250,23 +292,100 @@ pub(crate) fn opt_cql_value(buf: &mut impl BufMut, value: Option<&CqlValue>) {
             bytes(buf, &u.as_u128().to_be_bytes());
         }
         CqlValue::Tuple(values) => {
+            buf.put_u32(opt_cql_value_size(Some(value)) as u32 - 4);
             for v in values {
-                if v == &CqlValue::Empty {
-                    buf.put_i32(-1);
-                    continue;
-                }
-                let mut value = BytesMut::new();
-                opt_cql_value(&mut value, Some(v));
-                bytes(buf, value.as_bytes());
+                // missing elements are nulls
+                let v = Some(v).filter(|it| **it != CqlValue::Empty);
+                opt_cql_value(buf, v);
             }
         }
         CqlValue::Uuid(u) => {
             bytes(buf, &u.as_u128().to_be_bytes());
         }
-        CqlValue::Varint(_) => {
-            unimplemented!()
+        CqlValue::Varint(v) => {
+            bytes(buf, &v.to_signed_bytes_be());
+        }
+    }
+}
+
+/// Number of bytes [`opt_cql_value`] writes for the value,
+/// collections are prefixed with their size without being serialized twice.
+pub(crate) fn opt_cql_value_size(value: Option<&CqlValue>) -> usize {
+    let Some(value) = value else {
+        return 4;
+    };
+
+    4 + match value {
+        CqlValue::Ascii(v) | CqlValue::Text(v) => v.len(),
+        CqlValue::Blob(v) => v.len(),
+        CqlValue::Boolean(_) | CqlValue::TinyInt(_) => 1,
+        CqlValue::SmallInt(_) => 2,
+        CqlValue::Date(_) | CqlValue::Float(_) | CqlValue::Int(_) => 4,
+        CqlValue::Counter(_)
+        | CqlValue::Double(_)
+        | CqlValue::BigInt(_)
+        | CqlValue::Timestamp(_)
+        | CqlValue::Time(_) => 8,
+        CqlValue::Timeuuid(_) | CqlValue::Uuid(_) => 16,
+        CqlValue::Empty => 0,
+        CqlValue::Inet(IpAddr::V4(_)) => 4,
+        CqlValue::Inet(IpAddr::V6(_)) => 16,
+        CqlValue::List(list) | CqlValue::Set(list) => {
+            4 + list
+                .iter()
+                .map(|v| opt_cql_value_size(Some(v)))
+                .sum::<usize>()
         }
+        CqlValue::Map(map) => {
+            4 + map
+                .iter()
+                .map(|(k, v)| opt_cql_value_size(Some(k)) + opt_cql_value_size(Some(v)))
+                .sum::<usize>()
+        }
+        CqlValue::Tuple(values) => values
+            .iter()
+            .map(|v| opt_cql_value_size(Some(v).filter(|it| **it != CqlValue::Empty)))
+            .sum(),
+        CqlValue::UserDefinedType { fields, .. } => fields
+            .iter()
+            .map(|(_, v)| opt_cql_value_size(v.as_ref()))
+            .sum(),
+        CqlValue::Decimal(v) => 4 + v.as_bigint_and_exponent().0.to_signed_bytes_be().len(),
+        CqlValue::Varint(v) => v.to_signed_bytes_be().len(),
+        CqlValue::Duration(v) => duration_size(v),
+    }
+}
+
+fn duration_size(value: &CqlDuration) -> usize {
+    vint_size(zigzag(value.months as i64))
+        + vint_size(zigzag(value.days as i64))
+        + vint_size(zigzag(value.nanoseconds))
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Size of the variable length integer of durations, which keeps the number of extra bytes
+/// in the leading bits of the first one, unlike the varints of [`unsigned_varint`]
+fn vint_size(value: u64) -> usize {
+    let magnitude = (value | 1).leading_zeros() as usize;
+    (639 - magnitude * 9) >> 6
+}
+
+fn signed_vint(buf: &mut impl BufMut, value: i64) {
+    let value = zigzag(value);
+    let size = vint_size(value);
+    if size == 9 {
+        buf.put_u8(0xff);
+        buf.put_u64(value);
+        return;
     }
+
+    let mut bytes = value.to_be_bytes();
+    let first = 8 - size;
+    bytes[first] |= !(0xffu8 >> (size - 1));
+    buf.put_slice(&bytes[first..]);
 }
 
 fn cql_value_without_size(buf: &mut impl BufMut, value: &CqlValue) {
@@ -280,13 +399,17 @@ fn cql_value_without_size(buf: &mut impl BufMut, value: &CqlValue) {
         }
         CqlValue::Blob(v) => {
             unsigned_varint(buf, v.len() as _);
-            buf.put_slice(v.as_bytes());
+            buf.put_slice(v);
         }
         CqlValue::Counter(i) => {
             buf.put_slice(&i.to_be_bytes());
         }
-        CqlValue::Decimal(_i) => {
-            unimplemented!()
+        CqlValue::Decimal(v) => {
+            let (unscaled, scale) = v.as_bigint_and_exponent();
+            let unscaled = unscaled.to_signed_bytes_be();
+            unsigned_varint(buf, 4 + unscaled.len() as u64);
+            buf.put_i32(scale as i32);
+            buf.put_slice(&unscaled);
         }
         CqlValue::Date(i) => {
             buf.put_slice(&i.to_be_bytes());
@@ -294,8 +417,10 @@ fn cql_value_without_size(buf: &mut impl BufMut, value: &CqlValue) {
         CqlValue::Double(f) => {
             buf.put_slice(&f.to_be_bytes());
         }
-        CqlValue::Duration(_i) => {
-            unimplemented!()
+        CqlValue::Duration(v) => {
+            signed_vint(buf, v.months as i64);
+            signed_vint(buf, v.days as i64);
+            signed_vint(buf, v.nanoseconds);
         }
         CqlValue::Empty => {}
         CqlValue::Float(v) => {
@@ -325,23 +450,25 @@ fn cql_value_without_size(buf: &mut impl BufMut, value: &CqlValue) {
             }
         },
         CqlValue::List(list) | CqlValue::Set(list) => {
-            let mut bytes = vec![];
+            let mut bytes = pool::take();
             for v in list {
                 cql_value_without_size(&mut bytes, v);
             }
             unsigned_varint(buf, 4 + bytes.len() as u64);
             unsigned_varint(buf, list.len() as _);
-            buf.put_slice(bytes.as_slice());
+            buf.put_slice(&bytes);
+            pool::give(bytes);
         }
         CqlValue::Map(map) => {
-            let mut bytes = vec![];
+            let mut bytes = pool::take();
             for (k, v) in map {
                 cql_value_without_size(&mut bytes, k);
                 cql_value_without_size(&mut bytes, v);
             }
             unsigned_varint(buf, 4 + bytes.len() as u64);
             unsigned_varint(buf, map.len() as _);
-            buf.put_slice(bytes.as_slice());
+            buf.put_slice(&bytes);
+            pool::give(bytes);
         }
 
         CqlValue::SmallInt(i) => {
@@ -365,11 +492,17 @@ fn cql_value_without_size(buf: &mut impl BufMut, value: &CqlValue) {
                 cql_value_without_size(buf, v);
             }
         }
-        CqlValue::UserDefinedType { .. } => {
-            unimplemented!()
+        // fields keep their `[bytes]` form, so missing and null ones can be told apart when read back
+        CqlValue::UserDefinedType { fields, .. } => {
+            unsigned_varint(buf, fields.len() as u64);
+            for (_, value) in fields {
+                opt_cql_value(buf, value.as_ref());
+            }
         }
-        CqlValue::Varint(_) => {
-            unimplemented!()
+        CqlValue::Varint(v) => {
+            let v = v.to_signed_bytes_be();
+            unsigned_varint(buf, v.len() as u64);
+            buf.put_slice(&v);
         }
     }
 }
@@ -455,12 +588,77 @@ mod tests {
     #[test]
     fn serialize_clustering_value() {
         let value = ClusteringKeyValue::Composite(vec![
-            Some(CqlValue::Ascii("998".to_owned())),
-            Some(CqlValue::Ascii("1".to_owned())),
+            Some(CqlValue::Ascii("998".into())),
+            Some(CqlValue::Ascii("1".into())),
         ]);
         let mut buf = vec![];
         super::clustering_value(&mut buf, &value);
 
         assert_eq!(buf, b"\0\x03998\x011");
     }
+
+    #[test]
+    fn serialized_size_of_collections() {
+        let value = CqlValue::Map(vec![
+            (
+                CqlValue::Text("key".into()),
+                CqlValue::List(vec![CqlValue::Int(1), CqlValue::Blob(vec![1, 2, 3].into())]),
+            ),
+            (CqlValue::Text("empty".into()), CqlValue::Set(vec![])),
+        ]);
+        let mut buf = vec![];
+        super::opt_cql_value(&mut buf, Some(&value));
+
+        assert_eq!(buf.len(), super::opt_cql_value_size(Some(&value)));
+        assert_eq!(&buf[..8], &[0, 0, 0, 51, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn serialize_numbers_durations_and_udts() {
+        use std::str::FromStr;
+
+        use bigdecimal::BigDecimal;
+        use num_bigint::BigInt;
+
+        use crate::cql::value::CqlDuration;
+
+        let udt = CqlValue::UserDefinedType {
+            keyspace: "ks".to_owned(),
+            type_name: "address".to_owned(),
+            fields: vec![
+                ("street".to_owned(), Some(CqlValue::Text("Main".into()))),
+                ("zip".to_owned(), None),
+            ],
+        };
+        let cases: [(CqlValue, &[u8]); 5] = [
+            (
+                CqlValue::Decimal(BigDecimal::from_str("12.34").unwrap()),
+                &[0, 0, 0, 6, 0, 0, 0, 2, 0x04, 0xd2],
+            ),
+            (CqlValue::Varint(BigInt::from(-128)), &[0, 0, 0, 1, 0x80]),
+            (CqlValue::Varint(BigInt::from(128)), &[0, 0, 0, 2, 0, 0x80]),
+            (
+                CqlValue::Duration(CqlDuration {
+                    months: 1,
+                    days: -2,
+                    nanoseconds: 1_000_000_000,
+                }),
+                &[0, 0, 0, 7, 2, 3, 0xf0, 0x77, 0x35, 0x94, 0],
+            ),
+            (
+                udt,
+                &[
+                    0, 0, 0, 12, 0, 0, 0, 4, b'M', b'a', b'i', b'n', 0xff, 0xff, 0xff, 0xff,
+                ],
+            ),
+        ];
+
+        for (value, expected) in cases {
+            let mut buf = vec![];
+            super::opt_cql_value(&mut buf, Some(&value));
+
+            assert_eq!(buf, expected, "{value:?}");
+            assert_eq!(super::opt_cql_value_size(Some(&value)), expected.len());
+        }
+    }
 }
@@ -1,8 +1,20 @@
+pub mod auth;
+pub mod changes;
+pub mod client;
+pub mod clock;
+pub mod compat;
 pub mod cql;
 pub mod error;
+pub mod export;
 pub mod frame;
+pub mod middleware;
+pub mod policy;
+pub mod query_stats;
+pub mod replay;
 pub mod session;
 pub mod snapshot;
 pub mod storage;
+#[cfg(feature = "testgen")]
+pub mod testgen;
 
 pub use session::KassandraSession;
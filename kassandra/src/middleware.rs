@@ -0,0 +1,68 @@
+use std::{fmt, ops::ControlFlow};
+
+use crate::{
+    frame::{request::Request, response::Response},
+    session::ConnectionState,
+};
+
+/// Step a session runs every request through before handling it, see [`SessionHandle::add_middleware`].
+///
+/// Middleware can observe or change the request, or answer it itself with [`ControlFlow::Break`],
+/// then the response is sent as is, and neither the following middleware nor the session see the request.
+/// Closures taking `(&mut Request, &mut Context)` are middleware as well.
+///
+/// [`SessionHandle::add_middleware`]: crate::session::SessionHandle::add_middleware
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut Request<'_>, context: &mut Context<'_>)
+        -> ControlFlow<Response>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut Request<'_>, &mut Context<'_>) -> ControlFlow<Response> + Send + Sync,
+{
+    fn handle(
+        &self,
+        request: &mut Request<'_>,
+        context: &mut Context<'_>,
+    ) -> ControlFlow<Response> {
+        self(request, context)
+    }
+}
+
+impl fmt::Debug for dyn Middleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Middleware")
+    }
+}
+
+/// Answers requests with an opcode kassandra doesn't know, like ones of experimental protocol extensions,
+/// see [`SessionHandle::register_opcode`]. Closures taking `(&[u8], &mut Context)` are handlers as well.
+///
+/// Handlers get the body of the request, opcodes kassandra handles itself are never passed to them.
+///
+/// [`SessionHandle::register_opcode`]: crate::session::SessionHandle::register_opcode
+pub trait OpcodeHandler: Send + Sync {
+    fn handle(&self, body: &[u8], context: &mut Context<'_>) -> Response;
+}
+
+impl<F> OpcodeHandler for F
+where
+    F: Fn(&[u8], &mut Context<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, body: &[u8], context: &mut Context<'_>) -> Response {
+        self(body, context)
+    }
+}
+
+impl fmt::Debug for dyn OpcodeHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OpcodeHandler")
+    }
+}
+
+/// State of the connection the request came from
+#[derive(Debug)]
+pub struct Context<'a> {
+    pub connection: &'a mut ConnectionState,
+}
@@ -0,0 +1,852 @@
+use std::{str::FromStr, time::Duration};
+
+pub use regex::Regex;
+use serde::Serialize;
+use strum::{Display, EnumString};
+
+use crate::{
+    cql::{
+        query::{QueryString, Resource},
+        value::CqlValue,
+    },
+    error::{DbError, WriteType},
+    frame::{
+        consistency::{Consistency, LegacyConsistency},
+        request::batch::BatchType,
+        response::error::Error,
+    },
+};
+
+/// Statements hygiene rules enforced by a session, useful when kassandra is used as a gate in tests.
+///
+/// Rules are checked in the order they were added, first violated rule rejects the statement.
+#[derive(Debug, Clone, Default)]
+pub struct StatementPolicy {
+    rules: Vec<Rule>,
+    setup_finished: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Rejects `SELECT ... ALLOW FILTERING`
+    DenyAllowFiltering,
+    /// Rejects statements relying on `USE` instead of `keyspace.table`
+    DenyUnqualifiedTables,
+    /// Rejects schema changes once [`StatementPolicy::finish_setup`] is called
+    DenySchemaChanges,
+    /// Rejects statements matching the pattern
+    Deny(Regex),
+    /// If there is any allow rule, only statements matching one of them are accepted
+    Allow(Regex),
+}
+
+impl StatementPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn deny_allow_filtering(self) -> Self {
+        self.rule(Rule::DenyAllowFiltering)
+    }
+
+    pub fn deny_unqualified_tables(self) -> Self {
+        self.rule(Rule::DenyUnqualifiedTables)
+    }
+
+    pub fn deny_schema_changes_after_setup(self) -> Self {
+        self.rule(Rule::DenySchemaChanges)
+    }
+
+    pub fn deny(self, pattern: Regex) -> Self {
+        self.rule(Rule::Deny(pattern))
+    }
+
+    pub fn allow(self, pattern: Regex) -> Self {
+        self.rule(Rule::Allow(pattern))
+    }
+
+    /// Ends the setup phase, after which schema changes can be denied.
+    pub fn finish_setup(&mut self) {
+        self.setup_finished = true;
+    }
+
+    /// Patterns are matched against the normalized statement, as it is displayed by [`QueryString`].
+    pub fn check(&self, query: &QueryString) -> Result<(), Error> {
+        if self.rules.is_empty() {
+            return Ok(());
+        }
+
+        let statement = query.to_string();
+        let mut allowed = None;
+
+        for rule in &self.rules {
+            let violation = match rule {
+                Rule::DenyAllowFiltering => matches!(
+                    query,
+                    QueryString::Select(select) if select.allow_filtering
+                )
+                .then(|| "ALLOW FILTERING is not allowed".to_owned()),
+                Rule::DenyUnqualifiedTables => is_unqualified(query).then(|| {
+                    format!(
+                        "Table `{}` must be qualified with a keyspace",
+                        query.target().trim_start_matches('.')
+                    )
+                }),
+                Rule::DenySchemaChanges => (self.setup_finished && is_schema_change(query))
+                    .then(|| "Schema changes are not allowed after setup".to_owned()),
+                Rule::Deny(pattern) => pattern
+                    .is_match(&statement)
+                    .then(|| format!("Statement matches denied pattern `{pattern}`")),
+                Rule::Allow(pattern) => {
+                    allowed = Some(allowed.unwrap_or(false) || pattern.is_match(&statement));
+                    None
+                }
+            };
+
+            if let Some(violation) = violation {
+                return Err(rejected(query, violation));
+            }
+        }
+
+        match allowed {
+            Some(false) => Err(rejected(
+                query,
+                "Statement does not match any allowed pattern".to_owned(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What to do with statements which use cql features kassandra does not implement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum UnimplementedPolicy {
+    /// Reply with an error
+    #[default]
+    Error,
+    /// Log a warning, record the statement as skipped and reply as if it succeeded,
+    /// so large schema dumps can be loaded on the best effort basis
+    WarnAndIgnore,
+    /// Panic, so unsupported statements can't go unnoticed in tests
+    Panic,
+}
+
+/// Failures a session simulates for statements of given consistency levels,
+/// so consistency downgrade logic of applications can be tested deterministically.
+///
+/// Rules are checked in the order they were added, first matched rule fails the statement.
+/// Only reads and writes are failed, schema changes and `USE` statements always succeed.
+#[derive(Debug, Clone, Default)]
+pub struct ConsistencyPolicy {
+    rules: Vec<ConsistencyRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsistencyRule {
+    pub consistency: Consistency,
+    /// `None` fails both reads and writes
+    pub operation: Option<Operation>,
+    pub failure: ConsistencyFailure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Operation {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ConsistencyFailure {
+    /// Replicas are known to be down before the statement is sent to them
+    Unavailable,
+    /// Replicas didn't respond in time, reads fail with `ReadTimeout` and writes with `WriteTimeout`
+    Timeout,
+}
+
+impl ConsistencyPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, rule: ConsistencyRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn fail_reads(self, consistency: Consistency, failure: ConsistencyFailure) -> Self {
+        self.rule(ConsistencyRule {
+            consistency,
+            operation: Some(Operation::Read),
+            failure,
+        })
+    }
+
+    pub fn fail_writes(self, consistency: Consistency, failure: ConsistencyFailure) -> Self {
+        self.rule(ConsistencyRule {
+            consistency,
+            operation: Some(Operation::Write),
+            failure,
+        })
+    }
+
+    pub fn fail_all(self, consistency: Consistency, failure: ConsistencyFailure) -> Self {
+        self.rule(ConsistencyRule {
+            consistency,
+            operation: None,
+            failure,
+        })
+    }
+
+    /// Replicas the errors report are told by `replication_factor` of the keyspace the statement targets
+    pub fn check(
+        &self,
+        query: &QueryString,
+        consistency: Consistency,
+        replication_factor: usize,
+    ) -> Result<(), Error> {
+        let operation = match query {
+            QueryString::Select(_) => Operation::Read,
+            QueryString::Insert(_) | QueryString::Update(_) | QueryString::Delete(_) => {
+                Operation::Write
+            }
+            QueryString::Batch(batch) => {
+                return self.check_batch(batch.batch_type, consistency, replication_factor)
+            }
+            _ => return Ok(()),
+        };
+
+        self.check_operation(
+            operation,
+            WriteType::Simple,
+            consistency,
+            replication_factor,
+        )
+    }
+
+    /// Batches are failed as a whole, with the write type of the batch
+    pub fn check_batch(
+        &self,
+        batch_type: BatchType,
+        consistency: Consistency,
+        replication_factor: usize,
+    ) -> Result<(), Error> {
+        let write_type = match batch_type {
+            BatchType::Logged => WriteType::Batch,
+            BatchType::Unlogged => WriteType::UnloggedBatch,
+            BatchType::Counter => WriteType::Counter,
+        };
+
+        self.check_operation(
+            Operation::Write,
+            write_type,
+            consistency,
+            replication_factor,
+        )
+    }
+
+    fn check_operation(
+        &self,
+        operation: Operation,
+        write_type: WriteType,
+        consistency: Consistency,
+        replication_factor: usize,
+    ) -> Result<(), Error> {
+        let Some(rule) = self.rules.iter().find(|rule| {
+            rule.consistency == consistency && rule.operation.is_none_or(|it| it == operation)
+        }) else {
+            return Ok(());
+        };
+
+        let required = required_replicas(consistency, replication_factor);
+        let responded = required - 1;
+        let legacy = LegacyConsistency::Regular(consistency);
+        let error = match (rule.failure, operation) {
+            (ConsistencyFailure::Unavailable, _) => Error::new(
+                DbError::Unavailable {
+                    consistency: legacy,
+                    required,
+                    alive: responded,
+                },
+                format!("Cannot achieve consistency level {consistency}"),
+            ),
+            (ConsistencyFailure::Timeout, Operation::Read) => Error::new(
+                DbError::ReadTimeout {
+                    consistency: legacy,
+                    received: responded,
+                    required,
+                    data_present: false,
+                },
+                format!("Operation timed out - received only {responded} responses."),
+            ),
+            (ConsistencyFailure::Timeout, Operation::Write) => Error::new(
+                DbError::WriteTimeout {
+                    consistency: legacy,
+                    received: responded,
+                    required,
+                    write_type,
+                },
+                format!("Operation timed out - received only {responded} responses."),
+            ),
+        };
+
+        Err(error)
+    }
+}
+
+/// Replicas which have to respond for the consistency level to be achieved
+fn required_replicas(consistency: Consistency, replication_factor: usize) -> i32 {
+    let replicas = match consistency {
+        Consistency::Any | Consistency::One | Consistency::LocalOne => 1,
+        Consistency::Two => 2,
+        Consistency::Three => 3,
+        Consistency::Quorum | Consistency::LocalQuorum | Consistency::EachQuorum => {
+            replication_factor / 2 + 1
+        }
+        Consistency::All => replication_factor,
+    };
+
+    replicas.max(1) as i32
+}
+
+/// Artificial latency of statements, servers of the session wait it out before responding,
+/// so client timeouts and speculative executions can be tested without a proxy.
+///
+/// Rules are checked in the order they were added, first matched rule gives the delay.
+/// Batches without a rule of their own take the longest delay of their statements.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyPolicy {
+    rules: Vec<LatencyRule>,
+}
+
+/// Rule written as `<target>=<delay>`: `select=50ms`, `ks.users=1s` or `*=10ms`,
+/// delays without a unit are milliseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyRule {
+    pub target: LatencyTarget,
+    pub delay: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LatencyTarget {
+    /// Every statement, written as `*`
+    All,
+    /// Statements of the kind, as named by [`QueryString::name`], like `select` or `insert`
+    Kind(String),
+    /// Statements reading or writing the table, written as `keyspace.table`
+    Table { keyspace: String, table: String },
+}
+
+impl LatencyPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, rule: LatencyRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn all(self, delay: Duration) -> Self {
+        self.rule(LatencyRule {
+            target: LatencyTarget::All,
+            delay,
+        })
+    }
+
+    pub fn kind(self, kind: impl Into<String>, delay: Duration) -> Self {
+        self.rule(LatencyRule {
+            target: LatencyTarget::Kind(kind.into()),
+            delay,
+        })
+    }
+
+    pub fn table(
+        self,
+        keyspace: impl Into<String>,
+        table: impl Into<String>,
+        delay: Duration,
+    ) -> Self {
+        self.rule(LatencyRule {
+            target: LatencyTarget::Table {
+                keyspace: keyspace.into(),
+                table: table.into(),
+            },
+            delay,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Delay of the statement, `keyspace` is the one in use, unqualified tables belong to it
+    pub fn delay(&self, query: &QueryString, keyspace: Option<&str>) -> Duration {
+        let rule = self.rules.iter().find(|rule| match &rule.target {
+            LatencyTarget::All => true,
+            LatencyTarget::Kind(kind) => kind.eq_ignore_ascii_case(query.name()),
+            LatencyTarget::Table {
+                keyspace: rule_keyspace,
+                table,
+            } => {
+                query.keyspace().or(keyspace) == Some(rule_keyspace.as_str())
+                    && table_of(query) == Some(table.as_str())
+            }
+        });
+
+        match (rule, query) {
+            (Some(rule), _) => rule.delay,
+            (None, QueryString::Batch(batch)) => batch
+                .statements
+                .iter()
+                .map(|statement| self.delay(statement, keyspace))
+                .max()
+                .unwrap_or_default(),
+            (None, _) => Duration::ZERO,
+        }
+    }
+}
+
+impl FromStr for LatencyRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, delay) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected `<target>=<delay>`, got `{s}`"))?;
+        let target = match target.trim() {
+            "*" => LatencyTarget::All,
+            target => match target.split_once('.') {
+                Some((keyspace, table)) => LatencyTarget::Table {
+                    keyspace: keyspace.to_owned(),
+                    table: table.to_owned(),
+                },
+                None => LatencyTarget::Kind(target.to_owned()),
+            },
+        };
+
+        Ok(Self {
+            target,
+            delay: parse_delay(delay.trim())?,
+        })
+    }
+}
+
+/// Batch size thresholds, like `batch_size_warn_threshold` and `batch_size_fail_threshold` of cassandra.
+///
+/// Size of a batch is the number of bytes of its bound values and of the text of its statements,
+/// which is close enough to the size of mutations cassandra checks to catch oversized batches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchSizePolicy {
+    /// Larger batches succeed with a warning
+    pub warn: Option<usize>,
+    /// Larger batches are rejected
+    pub fail: Option<usize>,
+}
+
+impl BatchSizePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn warn_above(mut self, bytes: usize) -> Self {
+        self.warn = Some(bytes);
+        self
+    }
+
+    pub fn fail_above(mut self, bytes: usize) -> Self {
+        self.fail = Some(bytes);
+        self
+    }
+
+    /// Warning of a batch of `size` bytes, `keyspace` is the one in use, unqualified tables belong to it
+    pub fn check(
+        &self,
+        statements: &[QueryString],
+        keyspace: Option<&str>,
+        size: usize,
+    ) -> Result<Option<String>, Error> {
+        if self.fail.is_some_and(|fail| size > fail) {
+            return Err(Error::new(DbError::Invalid, "Batch too large"));
+        }
+        let Some(warn) = self.warn.filter(|warn| size > *warn) else {
+            return Ok(None);
+        };
+
+        let mut tables = statements
+            .iter()
+            .filter_map(|query| {
+                let keyspace = query.keyspace().or(keyspace).unwrap_or_default();
+                Some(format!("{keyspace}.{}", table_of(query)?))
+            })
+            .collect::<Vec<_>>();
+        tables.sort();
+        tables.dedup();
+        let kib = |bytes: usize| format!("{:.1}KiB", bytes as f64 / 1024.0);
+
+        Ok(Some(format!(
+            "Batch for [{}] is of size {}, exceeding specified threshold of {} by {}.",
+            tables.join(", "),
+            kib(size),
+            kib(warn),
+            kib(size - warn)
+        )))
+    }
+}
+
+/// Limits of identifiers and values, like the ones of cassandra,
+/// so schemas and writes which would fail on a real cluster fail in tests as well.
+///
+/// Exceeding any of them rejects the statement with an `Invalid` error, nothing is limited by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitsPolicy {
+    /// Characters of keyspace, table and type names
+    pub max_name_length: Option<usize>,
+    /// Columns of a table
+    pub max_columns: Option<usize>,
+    /// Elements of a list, set or map value
+    pub max_collection_size: Option<usize>,
+    /// Bytes of a blob or text value of a single cell
+    pub max_cell_size: Option<usize>,
+}
+
+impl LimitsPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits of cassandra with the default `max_value_size` of 256MiB
+    pub fn cassandra() -> Self {
+        Self {
+            max_name_length: Some(48),
+            max_columns: Some(u16::MAX as usize),
+            max_collection_size: Some(u16::MAX as usize),
+            max_cell_size: Some(256 * 1024 * 1024),
+        }
+    }
+
+    pub fn max_name_length(mut self, characters: usize) -> Self {
+        self.max_name_length = Some(characters);
+        self
+    }
+
+    pub fn max_columns(mut self, columns: usize) -> Self {
+        self.max_columns = Some(columns);
+        self
+    }
+
+    pub fn max_collection_size(mut self, elements: usize) -> Self {
+        self.max_collection_size = Some(elements);
+        self
+    }
+
+    pub fn max_cell_size(mut self, bytes: usize) -> Self {
+        self.max_cell_size = Some(bytes);
+        self
+    }
+
+    /// Names and columns of schema changes, keyspaces of isolated sessions are checked without their `prefix`
+    pub fn check_schema(&self, query: &QueryString, prefix: Option<&str>) -> Result<(), Error> {
+        let keyspace = |keyspace: &str| {
+            let keyspace = prefix
+                .and_then(|prefix| keyspace.strip_prefix(prefix))
+                .unwrap_or(keyspace);
+            self.check_name("Keyspace", keyspace)
+        };
+
+        match query {
+            QueryString::CreateKeyspace(create) => keyspace(&create.keyspace),
+            QueryString::CreateTable(create) => {
+                self.check_name("Table", &create.table)?;
+                match self.max_columns {
+                    Some(max) if create.columns.len() > max => Err(Error::new(
+                        DbError::Invalid,
+                        format!(
+                            "Table {} has {} columns, more than the {max} allowed",
+                            create.table,
+                            create.columns.len()
+                        ),
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            QueryString::CreateType(create) => self.check_name("Type", &create.name),
+            _ => Ok(()),
+        }
+    }
+
+    /// Cells written by a statement
+    pub fn check_values(&self, values: &[(String, Option<CqlValue>)]) -> Result<(), Error> {
+        if *self == Self::default() {
+            return Ok(());
+        }
+
+        values
+            .iter()
+            .filter_map(|(column, value)| Some((column, value.as_ref()?)))
+            .try_for_each(|(column, value)| self.check_value(column, value))
+    }
+
+    fn check_name(&self, kind: &str, name: &str) -> Result<(), Error> {
+        let length = name.chars().count();
+        match self.max_name_length {
+            Some(max) if length > max => Err(Error::new(
+                DbError::Invalid,
+                format!(
+                    "{kind} name must not be more than {max} characters long (got {length} characters for \"{name}\")"
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_value(&self, column: &str, value: &CqlValue) -> Result<(), Error> {
+        let (size, elements) = match value {
+            CqlValue::Blob(bytes) => (Some(bytes.len()), None),
+            CqlValue::Text(text) | CqlValue::Ascii(text) => (Some(text.len()), None),
+            CqlValue::List(elements) | CqlValue::Set(elements) | CqlValue::Tuple(elements) => {
+                for element in elements {
+                    self.check_value(column, element)?;
+                }
+                (
+                    None,
+                    (!matches!(value, CqlValue::Tuple(_))).then_some(elements.len()),
+                )
+            }
+            CqlValue::Map(entries) => {
+                for (key, value) in entries {
+                    self.check_value(column, key)?;
+                    self.check_value(column, value)?;
+                }
+                (None, Some(entries.len()))
+            }
+            CqlValue::UserDefinedType { fields, .. } => {
+                for value in fields.iter().filter_map(|(_, value)| value.as_ref()) {
+                    self.check_value(column, value)?;
+                }
+                (None, None)
+            }
+            _ => (None, None),
+        };
+
+        if let (Some(size), Some(max)) = (size, self.max_cell_size) {
+            if size > max {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!(
+                        "Value of column {column} is {size} bytes, more than the {max} allowed"
+                    ),
+                ));
+            }
+        }
+        if let (Some(elements), Some(max)) = (elements, self.max_collection_size) {
+            if elements > max {
+                return Err(Error::new(
+                    DbError::Invalid,
+                    format!(
+                        "Collection of column {column} has {elements} elements, more than the {max} allowed"
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Accepts `<n>ms`, `<n>s` or just number of milliseconds
+fn parse_delay(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid delay `{value}`");
+
+    if let Some(ms) = value.strip_suffix("ms") {
+        Ok(Duration::from_millis(ms.parse().map_err(|_| invalid())?))
+    } else if let Some(s) = value.strip_suffix('s') {
+        Duration::try_from_secs_f64(s.parse().map_err(|_| invalid())?).map_err(|_| invalid())
+    } else {
+        Ok(Duration::from_millis(value.parse().map_err(|_| invalid())?))
+    }
+}
+
+fn table_of(query: &QueryString) -> Option<&str> {
+    match query {
+        QueryString::Select(s) => Some(&s.table),
+        QueryString::Insert(s) => Some(&s.table),
+        QueryString::Update(s) => Some(&s.table),
+        QueryString::Delete(s) => Some(&s.table),
+        QueryString::CreateTable(s) => Some(&s.table),
+        QueryString::DropTable(s) => Some(&s.table),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedStatement {
+    pub statement: String,
+    pub reason: String,
+}
+
+fn rejected(query: &QueryString, reason: String) -> Error {
+    Error::new(
+        DbError::Unauthorized,
+        format!("Statement `{query}` rejected by policy: {reason}"),
+    )
+}
+
+fn is_unqualified(query: &QueryString) -> bool {
+    match query {
+        QueryString::Select(s) => s.keyspace.is_none(),
+        QueryString::Insert(s) => s.keyspace.is_none(),
+        QueryString::Update(s) => s.keyspace.is_none(),
+        QueryString::Delete(s) => s.keyspace.is_none(),
+        QueryString::Batch(s) => s.statements.iter().any(is_unqualified),
+        QueryString::Explain(statement) => is_unqualified(statement),
+        QueryString::CreateTable(s) => s.keyspace.is_none(),
+        QueryString::CreateType(s) => s.keyspace.is_none(),
+        QueryString::DropTable(s) => s.keyspace.is_none(),
+        QueryString::DropType(s) => s.keyspace.is_none(),
+        QueryString::Grant(s) | QueryString::Revoke(s) => {
+            matches!(s.resource, Resource::Table { keyspace: None, .. })
+        }
+        QueryString::Use { .. }
+        | QueryString::CreateKeyspace(_)
+        | QueryString::AlterKeyspace(_)
+        | QueryString::DropKeyspace(_)
+        | QueryString::Describe(_)
+        | QueryString::CreateRole(_)
+        | QueryString::DropRole(_) => false,
+    }
+}
+
+fn is_schema_change(query: &QueryString) -> bool {
+    match query {
+        QueryString::CreateKeyspace(_)
+        | QueryString::AlterKeyspace(_)
+        | QueryString::CreateTable(_)
+        | QueryString::CreateType(_)
+        | QueryString::DropKeyspace(_)
+        | QueryString::DropTable(_)
+        | QueryString::DropType(_) => true,
+        QueryString::Select(_)
+        | QueryString::Insert(_)
+        | QueryString::Update(_)
+        | QueryString::Delete(_)
+        | QueryString::Batch(_)
+        | QueryString::Use { .. }
+        | QueryString::Describe(_)
+        | QueryString::Explain(_)
+        | QueryString::CreateRole(_)
+        | QueryString::DropRole(_)
+        | QueryString::Grant(_)
+        | QueryString::Revoke(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        ConsistencyFailure, ConsistencyPolicy, LatencyPolicy, LatencyRule, Regex, StatementPolicy,
+    };
+    use crate::{cql::parser::query, error::DbError, frame::consistency::Consistency};
+
+    #[test]
+    fn deny_allow_filtering() {
+        let policy = StatementPolicy::new().deny_allow_filtering();
+
+        assert!(policy
+            .check(&query("SELECT * FROM ks.t WHERE a = 1 ALLOW FILTERING").unwrap())
+            .is_err());
+        assert!(policy
+            .check(&query("SELECT * FROM ks.t WHERE a = 1").unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    fn schema_changes_after_setup() {
+        let mut policy = StatementPolicy::new().deny_schema_changes_after_setup();
+        let create = query("CREATE TABLE ks.t (id int PRIMARY KEY)").unwrap();
+
+        assert!(policy.check(&create).is_ok());
+        policy.finish_setup();
+        assert!(policy.check(&create).is_err());
+    }
+
+    #[test]
+    fn allow_patterns() {
+        let policy = StatementPolicy::new()
+            .allow(Regex::new("^SELECT").unwrap())
+            .deny(Regex::new("system").unwrap());
+
+        assert!(policy.check(&query("SELECT * FROM ks.t").unwrap()).is_ok());
+        assert!(policy
+            .check(&query("SELECT * FROM system.local").unwrap())
+            .is_err());
+        assert!(policy
+            .check(&query("INSERT INTO ks.t (id) VALUES (1)").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn consistency_failures_report_required_replicas() {
+        let policy = ConsistencyPolicy::new()
+            .fail_all(Consistency::Quorum, ConsistencyFailure::Timeout)
+            .fail_writes(Consistency::One, ConsistencyFailure::Unavailable);
+        let select = query("SELECT * FROM ks.t").unwrap();
+        let batch =
+            query("BEGIN UNLOGGED BATCH INSERT INTO ks.t (a) VALUES (1) APPLY BATCH").unwrap();
+
+        let error = policy.check(&select, Consistency::Quorum, 5).unwrap_err();
+        assert!(matches!(
+            error.error,
+            DbError::ReadTimeout {
+                received: 2,
+                required: 3,
+                ..
+            }
+        ));
+        assert!(policy.check(&select, Consistency::One, 5).is_ok());
+        assert!(matches!(
+            policy.check(&batch, Consistency::One, 3).unwrap_err().error,
+            DbError::Unavailable { required: 1, .. }
+        ));
+        assert!(policy
+            .check(&query("CREATE KEYSPACE ks WITH replication = {'class': 'SimpleStrategy', 'replication_factor': 1}").unwrap(), Consistency::Quorum, 1)
+            .is_ok());
+    }
+
+    #[test]
+    fn latency_of_tables_and_kinds() {
+        let policy = ["ks.slow=1s", "insert=20ms"]
+            .into_iter()
+            .map(|rule| rule.parse::<LatencyRule>().unwrap())
+            .fold(LatencyPolicy::new(), LatencyPolicy::rule);
+        let delay = |statement: &str, keyspace| policy.delay(&query(statement).unwrap(), keyspace);
+
+        assert_eq!(
+            delay("SELECT * FROM slow", Some("ks")),
+            Duration::from_secs(1)
+        );
+        assert_eq!(delay("SELECT * FROM slow", Some("other")), Duration::ZERO);
+        assert_eq!(
+            delay("INSERT INTO ks.fast (a) VALUES (1)", None),
+            Duration::from_millis(20)
+        );
+        assert_eq!(
+            delay(
+                "BEGIN BATCH INSERT INTO ks.fast (a) VALUES (1); DELETE FROM ks.slow WHERE a = 1; APPLY BATCH",
+                None
+            ),
+            Duration::from_secs(1)
+        );
+        assert!("slow".parse::<LatencyRule>().is_err());
+        assert!("*=soon".parse::<LatencyRule>().is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, RwLock},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    cql::{
+        query::{QueryString, QueryValue},
+        visit::VisitMut,
+    },
+    frame::response::{error::Error, result::QueryResult},
+};
+
+/// Statistics of the statements of a single shape, see [`SessionHandle::query_stats`].
+///
+/// [`SessionHandle::query_stats`]: crate::session::SessionHandle::query_stats
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct QueryStats {
+    pub count: u64,
+    /// Statements which failed, they are counted in `count` as well
+    pub errors: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+    /// Rows of streamed results are sent after the statement completes, so they aren't counted
+    pub rows: u64,
+}
+
+impl QueryStats {
+    pub fn mean_latency(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total_latency / count as u32,
+        }
+    }
+}
+
+/// Statement with every value replaced by a bind marker, so statements differing only in values share statistics.
+///
+/// Keyspaces of isolated sessions are shaped without their `prefix`.
+pub fn statement_shape(query: &QueryString, prefix: Option<&str>) -> String {
+    let mut shape = query.clone();
+    Shape { prefix }.visit_query(&mut shape);
+
+    shape.to_string()
+}
+
+struct Shape<'a> {
+    prefix: Option<&'a str>,
+}
+
+impl VisitMut for Shape<'_> {
+    fn visit_keyspace(&mut self, keyspace: &mut String) {
+        if let Some(name) = self.prefix.and_then(|prefix| keyspace.strip_prefix(prefix)) {
+            *keyspace = name.to_owned();
+        }
+    }
+
+    fn visit_value(&mut self, value: &mut QueryValue) {
+        *value = QueryValue::Blankslate;
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct QueryStatsRecorder {
+    stats: Mutex<BTreeMap<String, QueryStats>>,
+    slow_query_threshold: RwLock<Option<Duration>>,
+}
+
+impl QueryStatsRecorder {
+    pub(crate) fn record(
+        &self,
+        shape: String,
+        latency: Duration,
+        result: &Result<QueryResult, Error>,
+    ) {
+        let rows = match result {
+            Ok(QueryResult::Rows(rows)) => rows.rows.len() as u64,
+            _ => 0,
+        };
+        if self
+            .slow_query_threshold()
+            .is_some_and(|threshold| latency >= threshold)
+        {
+            tracing::warn!(statement = shape, ?latency, rows, "Slow query");
+        }
+
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(shape).or_default();
+        stats.count += 1;
+        stats.errors += result.is_err() as u64;
+        stats.total_latency += latency;
+        stats.max_latency = stats.max_latency.max(latency);
+        stats.rows += rows;
+    }
+
+    pub(crate) fn stats(&self) -> BTreeMap<String, QueryStats> {
+        self.stats.lock().unwrap().clone()
+    }
+
+    pub(crate) fn reset(&self) {
+        self.stats.lock().unwrap().clear();
+    }
+
+    pub(crate) fn slow_query_threshold(&self) -> Option<Duration> {
+        *self.slow_query_threshold.read().unwrap()
+    }
+
+    pub(crate) fn set_slow_query_threshold(&self, threshold: Option<Duration>) {
+        *self.slow_query_threshold.write().unwrap() = threshold;
+    }
+}
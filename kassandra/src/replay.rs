@@ -0,0 +1,360 @@
+//! Recorded statements, which replay into the same state on any session.
+//!
+//! A [`ReplayLog`] keeps statements in the order they were received, together with their bound values,
+//! the write timestamps they were applied with and the ids of prepared statements,
+//! so executions refer to the same statements when replayed. Logs are plain serde data, stable between releases:
+//! readers check [`ReplayLog::version`] and ids and values are written as hex strings to stay readable in json.
+
+use bytes::Bytes;
+use eyre::{eyre, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cql::{self, parser, query_cache},
+    frame::{
+        request::{
+            batch::{Batch, BatchStatement, BatchType},
+            execute::Execute,
+            query::Query,
+            QueryFlags, QueryParameters, Request,
+        },
+        value::FrameValue,
+    },
+    session::KassandraSession,
+};
+
+/// Version of the format logs are written in, logs of other versions are rejected by [`ReplayLog::apply`]
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayLog {
+    pub version: u32,
+    pub entries: Vec<ReplayEntry>,
+}
+
+impl Default for ReplayLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    /// Write timestamp the statement was applied with, microseconds since unix epoch
+    pub timestamp: i64,
+    /// Keyspace in use when the statement was received, `None` keeps the one selected by earlier `USE` statements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyspace: Option<String>,
+    pub statement: ReplayStatement,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReplayStatement {
+    Prepare {
+        #[serde(with = "hex_id")]
+        id: u128,
+        query: String,
+    },
+    Query {
+        query: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<ReplayValue>,
+    },
+    Execute {
+        #[serde(with = "hex_id")]
+        id: u128,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<ReplayValue>,
+    },
+    Batch {
+        batch_type: BatchType,
+        statements: Vec<ReplayBatchStatement>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReplayBatchStatement {
+    Query {
+        query: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<ReplayValue>,
+    },
+    Prepared {
+        #[serde(with = "hex_id")]
+        id: u128,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        values: Vec<ReplayValue>,
+    },
+}
+
+/// Bound value of a statement, the same as [`FrameValue`] but owning its bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayValue {
+    Value(#[serde(with = "hex_bytes")] Bytes),
+    Null,
+    NotSet,
+}
+
+impl From<&FrameValue<'_>> for ReplayValue {
+    fn from(value: &FrameValue<'_>) -> Self {
+        match value {
+            FrameValue::Some(bytes) => ReplayValue::Value(Bytes::copy_from_slice(bytes)),
+            FrameValue::Null => ReplayValue::Null,
+            FrameValue::NotSet => ReplayValue::NotSet,
+        }
+    }
+}
+
+impl<'a> From<&'a ReplayValue> for FrameValue<'a> {
+    fn from(value: &'a ReplayValue) -> Self {
+        match value {
+            ReplayValue::Value(bytes) => FrameValue::Some(bytes),
+            ReplayValue::Null => FrameValue::Null,
+            ReplayValue::NotSet => FrameValue::NotSet,
+        }
+    }
+}
+
+impl ReplayLog {
+    pub fn new() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            entries: vec![],
+        }
+    }
+
+    /// Records a request received by a connection with `keyspace` in use,
+    /// `timestamp` is the write timestamp it was applied with.
+    ///
+    /// Prepared statements get the ids cassandra gives them, [`ReplayLog::record_prepared`] keeps other ones.
+    /// Requests which don't change state, like `STARTUP` or `OPTIONS`, are left out.
+    pub fn record(&mut self, request: &Request<'_>, keyspace: Option<&str>, timestamp: i64) {
+        let statement = match request {
+            Request::Query(query) => ReplayStatement::Query {
+                query: raw_query(query.raw_query, &query.query),
+                values: values(&query.parameters.data),
+            },
+            Request::Prepare(prepare) => ReplayStatement::Prepare {
+                id: query_cache::statement_id(keyspace, prepare.raw_query),
+                query: raw_query(prepare.raw_query, &prepare.query),
+            },
+            Request::Execute(execute) => {
+                let Ok(id) = execute.id.try_into() else {
+                    tracing::warn!(id = ?execute.id, "Execution of a malformed id is not recorded");
+                    return;
+                };
+                ReplayStatement::Execute {
+                    id: u128::from_be_bytes(id),
+                    values: values(&execute.parameters.data),
+                }
+            }
+            Request::Batch(batch) => ReplayStatement::Batch {
+                batch_type: batch.batch_type,
+                statements: batch
+                    .statements
+                    .iter()
+                    .filter_map(|statement| match statement {
+                        BatchStatement::Query {
+                            query,
+                            raw_query: raw,
+                            values: bound,
+                        } => Some(ReplayBatchStatement::Query {
+                            query: raw_query(raw, query),
+                            values: values(bound),
+                        }),
+                        BatchStatement::Prepared { id, values: bound } => {
+                            Some(ReplayBatchStatement::Prepared {
+                                id: u128::from_be_bytes((*id).try_into().ok()?),
+                                values: values(bound),
+                            })
+                        }
+                    })
+                    .collect(),
+            },
+            Request::StartUp(_)
+            | Request::Options
+            | Request::Register { .. }
+            | Request::AuthResponse { .. }
+            | Request::Other { .. } => return,
+        };
+
+        self.push(statement, keyspace, timestamp);
+    }
+
+    /// Records a statement prepared with the given id
+    pub fn record_prepared(
+        &mut self,
+        id: u128,
+        query: &str,
+        keyspace: Option<&str>,
+        timestamp: i64,
+    ) {
+        let statement = ReplayStatement::Prepare {
+            id,
+            query: query.to_owned(),
+        };
+        self.push(statement, keyspace, timestamp);
+    }
+
+    fn push(&mut self, statement: ReplayStatement, keyspace: Option<&str>, timestamp: i64) {
+        self.entries.push(ReplayEntry {
+            timestamp,
+            keyspace: keyspace.map(str::to_owned),
+            statement,
+        });
+    }
+
+    /// Runs the recorded statements on the session in order, stopping at the first one which fails.
+    ///
+    /// Writes are applied with their recorded timestamps, so replaying a log gives the same state every time.
+    pub fn apply<E: cql::Engine>(&self, session: &mut KassandraSession<E>) -> eyre::Result<()> {
+        if self.version != FORMAT_VERSION {
+            return Err(eyre!(
+                "Unsupported replay log version {}, expected {FORMAT_VERSION}",
+                self.version
+            ));
+        }
+
+        for (n, entry) in self.entries.iter().enumerate() {
+            entry
+                .apply(session)
+                .wrap_err_with(|| format!("while replaying entry {n}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ReplayEntry {
+    fn apply<E: cql::Engine>(&self, session: &mut KassandraSession<E>) -> eyre::Result<()> {
+        if let Some(keyspace) = &self.keyspace {
+            session.use_keyspace(keyspace.clone());
+        }
+
+        match &self.statement {
+            ReplayStatement::Prepare { id, query } => {
+                session.prepare_with_id(parser::query(query)?, *id)?;
+            }
+            ReplayStatement::Query { query, values } => {
+                session.process(Query {
+                    query: parser::query(query)?,
+                    raw_query: query,
+                    parameters: self.parameters(values),
+                })?;
+            }
+            ReplayStatement::Execute { id, values } => {
+                session.execute(Execute {
+                    id: &id.to_be_bytes(),
+                    parameters: self.parameters(values),
+                })?;
+            }
+            ReplayStatement::Batch {
+                batch_type,
+                statements,
+            } => {
+                let ids = statements
+                    .iter()
+                    .map(|statement| match statement {
+                        ReplayBatchStatement::Prepared { id, .. } => id.to_be_bytes(),
+                        ReplayBatchStatement::Query { .. } => Default::default(),
+                    })
+                    .collect::<Vec<_>>();
+                let statements = statements
+                    .iter()
+                    .zip(&ids)
+                    .map(|(statement, id)| {
+                        Ok(match statement {
+                            ReplayBatchStatement::Query { query, values } => {
+                                BatchStatement::Query {
+                                    query: Box::new(parser::query(query)?),
+                                    raw_query: query,
+                                    values: values.iter().map(Into::into).collect(),
+                                }
+                            }
+                            ReplayBatchStatement::Prepared { values, .. } => {
+                                BatchStatement::Prepared {
+                                    id,
+                                    values: values.iter().map(Into::into).collect(),
+                                }
+                            }
+                        })
+                    })
+                    .collect::<eyre::Result<Vec<_>>>()?;
+                let QueryParameters {
+                    consistency,
+                    serial_consistency,
+                    ..
+                } = QueryParameters::default();
+
+                session.process_batch(Batch {
+                    batch_type: *batch_type,
+                    consistency,
+                    serial_consistency,
+                    timestamp: Some(self.timestamp),
+                    statements,
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parameters<'a>(&self, values: &'a [ReplayValue]) -> QueryParameters<'a> {
+        QueryParameters {
+            flags: QueryFlags::VALUES,
+            data: values.iter().map(Into::into).collect(),
+            default_timestamp: Some(self.timestamp),
+            ..Default::default()
+        }
+    }
+}
+
+/// Statements rewritten by the session don't keep their text, they are rendered back instead
+fn raw_query(raw: &str, query: &cql::query::QueryString) -> String {
+    match raw {
+        "" => query.to_string(),
+        raw => raw.to_owned(),
+    }
+}
+
+fn values(values: &[FrameValue<'_>]) -> Vec<ReplayValue> {
+    values.iter().map(Into::into).collect()
+}
+
+mod hex_id {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(id: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{id:032x}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        u128::from_str_radix(&id, 16).map_err(D::Error::custom)
+    }
+}
+
+mod hex_bytes {
+    use bytes::Bytes;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        let hex = bytes.iter().map(|byte| format!("{byte:02x}"));
+        serializer.serialize_str(&hex.collect::<String>())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(D::Error::custom("expected pairs of hex digits"));
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(D::Error::custom))
+            .collect()
+    }
+}
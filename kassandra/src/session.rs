@@ -1,234 +1,2055 @@
-use std::net::IpAddr;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv4Addr},
+    num::NonZeroUsize,
+    ops::{ControlFlow, Deref},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant},
+};
 
 use bytes::Bytes;
+use futures::Stream;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
 use tracing::{instrument, Level};
-use uuid::uuid;
+use uuid::{uuid, Uuid};
 
 use crate::{
+    auth::{self, Role, DEFAULT_ROLE, PASSWORD_AUTHENTICATOR},
+    changes::{ChangeEvent, Subscribers},
+    clock::{IdProvider, Md5Ids, SystemClock, TimeProvider},
+    compat::CompatibilityProfile,
     cql::{
         self,
-        engine::kv::KvEngine,
-        execution::InsertNode,
+        engine::{
+            kv::{KeyspaceDump, KvEngine, UnversionedEngine},
+            views::SystemViews,
+        },
+        execution::{AuthNode, ChunkedReader, InsertNode, ScanNode},
+        partitioner::Murmur3Partitioner,
         plan::Plan,
-        query::QueryString,
+        query::{BatchQuery, QueryString},
+        query_cache::DEFAULT_PREPARED_STATEMENTS_CAPACITY,
+        row_cache::RowCache,
+        schema::{keyspace::Strategy, system::is_system_keyspace, Schema},
         value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
+        visit::{Rewrite, VisitMut},
     },
     error::DbError,
     frame::{
+        consistency::Consistency,
         request::{
             batch::{Batch, BatchStatement},
             execute::Execute,
+            prepare::Prepare,
             query::Query,
-            QueryFlags, QueryParameters,
+            QueryFlags, QueryParameters, Request,
         },
         response::{
-            error::Error,
-            result::{Prepared, QueryResult, SetKeyspace},
+            authenticate::{AuthSuccess, Authenticate},
+            error::{Error, ErrorRenderer, KnownError},
+            result::{
+                ColumnSpec, Prepared, PreparedMetadata, QueryResult, ResultMetadata, Row,
+                RowChunks, RowStream, SetKeyspace,
+            },
+            supported::Features,
+            Response,
         },
+        value::FrameValue,
+    },
+    middleware::{Context, Middleware, OpcodeHandler},
+    policy::{
+        BatchSizePolicy, ConsistencyPolicy, LatencyPolicy, LimitsPolicy, SkippedStatement,
+        StatementPolicy, UnimplementedPolicy,
+    },
+    query_stats::{statement_shape, QueryStats, QueryStatsRecorder},
+    snapshot::{state_digest, DataSnapshots, StatsSnapshot},
+    storage::{
+        memory::{self, Memory},
+        write_timestamp, Timestamps, WriteStorage,
     },
-    snapshot::DataSnapshots,
-    storage::memory::{self, Memory},
 };
 
-#[derive(Debug, Clone)]
-pub struct KassandraSession<E: cql::Engine = KvEngine<Memory>> {
-    use_keyspace: Option<String>,
-    engine: E,
-}
+pub const DEFAULT_NUM_TOKENS: usize = 16;
+
+/// Most rows a scan without paging may return, see [`SessionHandle::set_scan_limit`]
+pub const DEFAULT_SCAN_LIMIT: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+
+/// Datacenter nodes of the session are advertised in, unless [`SessionConfig::with_datacenter`] is given,
+/// `replication_factor` of `NetworkTopologyStrategy` keyspaces always expands to it
+pub const DATACENTER: &str = "datacenter1";
+
+const ROWS_PER_CHUNK: usize = 1000;
+
+const LOCAL_HOST_ID: Uuid = uuid! {"aa1f1ae0-469d-4abf-ae3f-ecb7a17132fe"};
+const SCHEMA_VERSION: Uuid = uuid! {"0b1c3252-f787-4099-8594-157323b71789"};
+
+/// Cluster advertised by the session in `system.local` and `system.peers`.
+///
+/// Every peer is virtual, node `n` is advertised at `127.0.0.{n + 1}`, which is
+/// routed to the same host on linux, so drivers connect back to the same process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Topology {
+    pub num_tokens: usize,
+    pub peers: usize,
+    /// Native port advertised in `system.peers_v2`
+    pub native_port: u16,
+}
+
+impl Default for Topology {
+    fn default() -> Self {
+        Self::new(DEFAULT_NUM_TOKENS)
+    }
+}
+
+impl Topology {
+    pub fn new(num_tokens: usize) -> Self {
+        Self {
+            num_tokens,
+            peers: 0,
+            native_port: 9042,
+        }
+    }
+
+    pub fn with_peers(mut self, peers: usize) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    pub fn with_native_port(mut self, native_port: u16) -> Self {
+        self.native_port = native_port;
+        self
+    }
+
+    /// Node `0` is the local one
+    pub fn address(&self, node: usize) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(u32::from(Ipv4Addr::LOCALHOST) + node as u32))
+    }
+
+    pub fn host_id(&self, node: usize) -> Uuid {
+        Uuid::from_u128(LOCAL_HOST_ID.as_u128() + node as u128)
+    }
+
+    /// Tokens of the whole ring are dealt to the nodes in turns, so every node owns `num_tokens` ranges.
+    pub fn tokens(&self, node: usize) -> Vec<i64> {
+        let nodes = self.peers + 1;
+
+        Murmur3Partitioner::generate_tokens(self.num_tokens.max(1) * nodes)
+            .into_iter()
+            .skip(node)
+            .step_by(nodes)
+            .collect()
+    }
+}
+
+/// Values the session advertises in `system.local` and `system.peers`,
+/// so applications reading them at startup see the names of the cluster they run against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+    pub topology: Topology,
+    pub cluster_name: String,
+    /// Datacenter of every node
+    pub datacenter: String,
+    /// Rack of every node
+    pub rack: String,
+    pub release_version: String,
+    /// Tokens of the local node instead of the ones dealt by the topology, peers don't get them
+    pub tokens: Option<Vec<i64>>,
+    /// Host id of the local node, peer `n` gets the `n`-th following one
+    pub host_id: Uuid,
+    /// Server the session pretends to be, see [`SessionHandle::set_compatibility_profile`]
+    pub profile: Option<CompatibilityProfile>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Topology> for SessionConfig {
+    fn from(topology: Topology) -> Self {
+        Self::new().with_topology(topology)
+    }
+}
+
+impl SessionConfig {
+    pub fn new() -> Self {
+        Self {
+            topology: Topology::default(),
+            cluster_name: "Test Cluster".to_owned(),
+            datacenter: DATACENTER.to_owned(),
+            rack: "rack".to_owned(),
+            release_version: "3.0.0".to_owned(),
+            tokens: None,
+            host_id: LOCAL_HOST_ID,
+            profile: None,
+        }
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_cluster_name(mut self, cluster_name: impl Into<String>) -> Self {
+        self.cluster_name = cluster_name.into();
+        self
+    }
+
+    pub fn with_datacenter(mut self, datacenter: impl Into<String>) -> Self {
+        self.datacenter = datacenter.into();
+        self
+    }
+
+    pub fn with_rack(mut self, rack: impl Into<String>) -> Self {
+        self.rack = rack.into();
+        self
+    }
+
+    pub fn with_release_version(mut self, release_version: impl Into<String>) -> Self {
+        self.release_version = release_version.into();
+        self
+    }
+
+    pub fn with_tokens(mut self, tokens: Vec<i64>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    pub fn with_host_id(mut self, host_id: Uuid) -> Self {
+        self.host_id = host_id;
+        self
+    }
+
+    /// Takes the release version of the profile as well, later [`SessionConfig::with_release_version`] overrides it
+    pub fn with_profile(mut self, profile: CompatibilityProfile) -> Self {
+        self.release_version = profile.release_version().to_owned();
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Node `0` is the local one
+    pub fn host_id(&self, node: usize) -> Uuid {
+        Uuid::from_u128(self.host_id.as_u128().wrapping_add(node as u128))
+    }
+
+    pub fn tokens(&self, node: usize) -> Vec<i64> {
+        match (&self.tokens, node) {
+            (Some(tokens), 0) => tokens.clone(),
+            (Some(tokens), _) => self
+                .topology
+                .tokens(node)
+                .into_iter()
+                .filter(|token| !tokens.contains(token))
+                .collect(),
+            (None, _) => self.topology.tokens(node),
+        }
+    }
+}
+
+/// State scoped to a single client connection, like the keyspace selected with `USE`.
+///
+/// Servers keep one per connection and pass it to the `*_in` methods of [`SessionHandle`],
+/// methods of [`KassandraSession`] use a connection owned by the session itself.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionState {
+    keyspace: Option<String>,
+    options: HashMap<String, String>,
+    stream_rows: bool,
+    shard: usize,
+    role: Option<String>,
+    warnings: Vec<String>,
+}
+
+impl ConnectionState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pages of `SELECT` results larger than a single chunk are returned as [`QueryResult::RowStream`],
+    /// for servers serializing results right away.
+    pub fn with_streamed_rows(mut self) -> Self {
+        self.stream_rows = true;
+        self
+    }
+
+    /// Shard advertised to the connection, when the session has [`Features::shards`]
+    pub fn with_shard(mut self, shard: usize) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    pub fn keyspace(&self) -> Option<&str> {
+        self.keyspace.as_deref()
+    }
+
+    pub fn use_keyspace(&mut self, ks: impl Into<String>) {
+        self.keyspace = Some(ks.into());
+    }
+
+    /// Role the connection logged in with, see [`SessionHandle::login_in`]
+    pub fn role(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+
+    /// Records options the client sent in `STARTUP`, like `CQL_VERSION` or `COMPRESSION`
+    pub fn startup(&mut self, options: HashMap<String, String>) {
+        self.options = options;
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    pub fn options(&self) -> &HashMap<String, String> {
+        &self.options
+    }
+
+    /// Client asked for `Overloaded` errors instead of backpressure when the server is overloaded
+    pub fn throw_on_overload(&self) -> bool {
+        self.option("THROW_ON_OVERLOAD")
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+
+    /// Warnings of the last statements, servers send them along with the response
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn warn(&mut self, warning: String) {
+        tracing::warn!(%warning, "Statement warning");
+        self.warnings.push(warning);
+    }
+}
+
+/// Embedded session, it owns a connection, so its methods run statements the way a single client would.
+///
+/// Servers give each client connection a [`SessionHandle`] from [`KassandraSession::handle`] instead,
+/// every method of the handle is available on the session as well.
+#[derive(Debug)]
+pub struct KassandraSession<E: cql::Engine = KvEngine<Memory>> {
+    connection: ConnectionState,
+    handle: SessionHandle<E>,
+}
+
+/// Cheap to clone handle to the state of a session, it can be used from many threads at once.
+///
+/// `SELECT`s of all handles run concurrently, other statements take exclusive access to the engine.
+#[derive(Debug)]
+pub struct SessionHandle<E: cql::Engine = KvEngine<Memory>> {
+    shared: Arc<Shared<E>>,
+}
+
+#[derive(Debug)]
+struct Shared<E> {
+    engine: Arc<RwLock<E>>,
+    /// Prefix of the keyspaces of an isolated session, see [`KassandraSession::isolated`]
+    isolation: Option<String>,
+    policy: RwLock<StatementPolicy>,
+    unimplemented: RwLock<UnimplementedPolicy>,
+    consistency: RwLock<ConsistencyPolicy>,
+    latency: RwLock<LatencyPolicy>,
+    batch_size: RwLock<BatchSizePolicy>,
+    features: RwLock<Features>,
+    limits: RwLock<LimitsPolicy>,
+    scan_limit: RwLock<Option<NonZeroUsize>>,
+    strict: AtomicBool,
+    authentication: AtomicBool,
+    errors: RwLock<ErrorRenderer>,
+    rewrite: RwLock<Option<Arc<dyn Rewrite>>>,
+    middleware: RwLock<Vec<Arc<dyn Middleware>>>,
+    opcodes: RwLock<HashMap<u8, Arc<dyn OpcodeHandler>>>,
+    time: RwLock<Arc<dyn TimeProvider>>,
+    ids: RwLock<Arc<dyn IdProvider>>,
+    timestamps: Timestamps,
+    skipped: Mutex<Vec<SkippedStatement>>,
+    /// Column specs of the rows prepared statements return, as clients got them when preparing,
+    /// evicted like the statements themselves
+    result_metadata: Mutex<LruCache<u128, Vec<ColumnSpec>>>,
+    /// Shared by sessions sharing the engine, as writes of any of them invalidate it
+    row_cache: Arc<RowCache>,
+    subscribers: Subscribers,
+    stats: QueryStatsRecorder,
+}
+
+impl<E: cql::Engine + Default> Default for KassandraSession<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: cql::Engine + Default> KassandraSession<E> {
+    pub fn new() -> Self {
+        Self::with_num_tokens(DEFAULT_NUM_TOKENS)
+    }
+
+    pub fn with_num_tokens(num_tokens: usize) -> Self {
+        Self::with_topology(Topology::new(num_tokens))
+    }
+
+    pub fn with_topology(topology: Topology) -> Self {
+        Self::with_config(topology.into())
+    }
+
+    pub fn with_config(config: SessionConfig) -> Self {
+        Self::from_engine(Default::default(), config)
+    }
+}
+
+/// Sessions are cloned with all of their data, a clone doesn't share anything with the original.
+impl<E: cql::Engine + Clone> Clone for KassandraSession<E> {
+    fn clone(&self) -> Self {
+        let session = Self::with_shared_engine(
+            Arc::new(RwLock::new(self.engine().clone())),
+            Arc::default(),
+            self.shared.isolation.clone(),
+        )
+        .with_policy(self.policy().clone())
+        .with_unimplemented_policy(self.unimplemented_policy())
+        .with_consistency_policy(self.consistency_policy())
+        .with_latency_policy(self.latency_policy())
+        .with_batch_size_policy(self.batch_size_policy())
+        .with_limits_policy(self.limits_policy());
+        session.set_strict_mode(self.strict_mode());
+        session.set_authentication(self.authentication());
+        session.set_scan_limit(self.scan_limit());
+        session.set_error_renderer(self.error_renderer());
+        session.set_rewrite(self.rewrite());
+        session.set_middleware(self.middleware());
+        session.set_opcode_handlers(self.opcode_handlers());
+        session.set_features(self.features());
+        session.set_slow_query_threshold(self.slow_query_threshold());
+        session.set_time_provider(self.time_provider());
+        session.set_id_provider(self.id_provider());
+        *session.shared.skipped.lock().unwrap() = self.skipped_statements();
+
+        Self {
+            connection: self.connection.clone(),
+            ..session
+        }
+    }
+}
+
+/// Data and schema of a session at the time it was forked, see [`SessionHandle::fork`]
+#[derive(Debug, Clone)]
+pub struct Savepoint<E>(E);
+
+impl<E: cql::Engine + Clone> SessionHandle<E> {
+    /// Savepoint of the current data and schema, the session can be restored to it any number of times.
+    ///
+    /// Memory storage shares its tables with the savepoint until they are written to,
+    /// so seeded data isn't copied by forking it, nor by restoring it.
+    pub fn fork(&self) -> Savepoint<E> {
+        Savepoint(self.engine().clone())
+    }
+
+    /// Brings data and schema back to the savepoint, settings of the session are kept.
+    ///
+    /// Sessions sharing the data, like isolated ones, are restored along with this one.
+    pub fn restore(&self, savepoint: &Savepoint<E>) {
+        *self.engine_mut() = savepoint.0.clone();
+    }
+}
+
+impl<E: cql::Engine> Deref for KassandraSession<E> {
+    type Target = SessionHandle<E>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.handle
+    }
+}
+
+impl<E: cql::Engine> KassandraSession<E> {
+    /// Session over an engine built elsewhere, e.g. a [`RemoteEngine`](cql::engine::remote::RemoteEngine),
+    /// system keyspaces of the config are created in it
+    pub fn from_engine(mut engine: E, config: SessionConfig) -> Self {
+        for plan in init_session(&config) {
+            plan.execute(&mut engine).expect("Could not init session");
+        }
+        let session = Self::with_engine(engine);
+        session.set_compatibility_profile(config.profile);
+
+        session
+    }
+
+    fn with_engine(engine: E) -> Self {
+        Self::with_shared_engine(Arc::new(RwLock::new(engine)), Arc::default(), None)
+    }
+
+    fn with_shared_engine(
+        engine: Arc<RwLock<E>>,
+        row_cache: Arc<RowCache>,
+        isolation: Option<String>,
+    ) -> Self {
+        Self {
+            connection: ConnectionState::default(),
+            handle: SessionHandle {
+                shared: Arc::new(Shared {
+                    engine,
+                    isolation,
+                    policy: RwLock::default(),
+                    unimplemented: RwLock::default(),
+                    consistency: RwLock::default(),
+                    latency: RwLock::default(),
+                    batch_size: RwLock::default(),
+                    features: RwLock::default(),
+                    limits: RwLock::default(),
+                    scan_limit: RwLock::new(Some(DEFAULT_SCAN_LIMIT)),
+                    strict: AtomicBool::default(),
+                    authentication: AtomicBool::default(),
+                    errors: RwLock::default(),
+                    rewrite: RwLock::default(),
+                    middleware: RwLock::default(),
+                    opcodes: RwLock::default(),
+                    time: RwLock::new(Arc::new(SystemClock)),
+                    ids: RwLock::new(Arc::new(Md5Ids)),
+                    timestamps: Timestamps::new(),
+                    skipped: Mutex::default(),
+                    result_metadata: Mutex::new(LruCache::new(
+                        DEFAULT_PREPARED_STATEMENTS_CAPACITY,
+                    )),
+                    row_cache,
+                    subscribers: Subscribers::default(),
+                    stats: QueryStatsRecorder::default(),
+                }),
+            },
+        }
+    }
+
+    /// Session sharing the data of this one, which keeps the keyspaces it uses apart from other sessions.
+    ///
+    /// Keyspaces named by its statements get a prefix, which is unique to the returned session,
+    /// snapshots of the session only include its keyspaces with the prefix stripped back out.
+    /// System keyspaces are shared as they are.
+    pub fn isolated(&self, prefix: &str) -> Self {
+        static ISOLATED: AtomicUsize = AtomicUsize::new(0);
+
+        let prefix = format!("{prefix}_{}_", ISOLATED.fetch_add(1, Ordering::Relaxed));
+        let session = Self::with_shared_engine(
+            self.shared.engine.clone(),
+            self.shared.row_cache.clone(),
+            Some(prefix.clone()),
+        )
+        .with_policy(self.policy().clone())
+        .with_unimplemented_policy(self.unimplemented_policy())
+        .with_consistency_policy(self.consistency_policy())
+        .with_latency_policy(self.latency_policy())
+        .with_batch_size_policy(self.batch_size_policy())
+        .with_limits_policy(self.limits_policy());
+        session.set_strict_mode(self.strict_mode());
+        session.set_authentication(self.authentication());
+        session.set_scan_limit(self.scan_limit());
+        session.set_error_renderer(self.error_renderer());
+        session.set_middleware(self.middleware());
+        session.set_opcode_handlers(self.opcode_handlers());
+        session.set_features(self.features());
+        session.set_slow_query_threshold(self.slow_query_threshold());
+        session.set_time_provider(self.time_provider());
+        session.set_id_provider(self.id_provider());
+
+        let rewrite = self.rewrite();
+        session.set_rewrite(Some(Arc::new(move |query: &mut QueryString| {
+            if let Some(rewrite) = &rewrite {
+                rewrite.rewrite(query);
+            }
+            Isolation { prefix: &prefix }.visit_query(query);
+        })));
+
+        session
+    }
+
+    /// Handle sharing the state of this session, with its own connection state kept by the caller
+    pub fn handle(&self) -> SessionHandle<E> {
+        self.handle.clone()
+    }
+
+    pub fn process(&mut self, query: Query) -> Result<QueryResult, Error> {
+        self.handle.process_in(&mut self.connection, query)
+    }
+
+    pub fn explain(&self, query: Query) -> Result<Plan, Error> {
+        self.handle.explain_in(&self.connection, query)
+    }
+
+    /// Handles a protocol request the way a server does, middleware included
+    pub fn request(&mut self, request: Request<'_>) -> Response {
+        self.handle.request_in(&mut self.connection, request)
+    }
+
+    /// Parses and processes a single cql statement,
+    /// statements kassandra can't parse are handled according to [`UnimplementedPolicy`].
+    pub fn process_cql(&mut self, statement: &str) -> Result<QueryResult, Error> {
+        match Query::simple(statement) {
+            Ok(query) => self.process(query),
+            Err(error) if error.error == DbError::Unimplemented => {
+                self.handle_unimplemented(statement, error)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn execute(&mut self, execute: Execute<'_>) -> Result<QueryResult, Error> {
+        self.handle.execute_in(&mut self.connection, execute)
+    }
+
+    pub fn process_batch(&mut self, batch: Batch<'_>) -> Result<QueryResult, Error> {
+        self.handle.process_batch_in(&mut self.connection, batch)
+    }
+
+    /// Warnings of the statements run since the last call, like the ones of oversized batches
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        self.connection.take_warnings()
+    }
+
+    pub fn prepare(&mut self, prepare: Prepare<'_>) -> Result<QueryResult, Error> {
+        self.handle.prepare_in(&self.connection, prepare)
+    }
+
+    pub fn prepare_with_id(&mut self, query: QueryString, id: u128) -> Result<QueryResult, Error> {
+        self.handle.prepare_with_id_in(&self.connection, query, id)
+    }
+
+    pub fn use_keyspace(&mut self, ks: impl Into<String>) {
+        self.connection.use_keyspace(ks);
+    }
+
+    /// Logs the session connection in, statements of sessions with authentication run with the permissions of the role
+    pub fn login(&mut self, role: &str, password: &str) -> Result<(), Error> {
+        self.handle.login_in(&mut self.connection, role, password)
+    }
+
+    /// Keyspace selected with the last `USE` statement of the session connection
+    pub fn keyspace(&self) -> Option<&str> {
+        self.connection.keyspace()
+    }
+
+    pub fn with_policy(self, policy: StatementPolicy) -> Self {
+        *self.policy_mut() = policy;
+        self
+    }
+
+    pub fn with_unimplemented_policy(self, policy: UnimplementedPolicy) -> Self {
+        self.set_unimplemented_policy(policy);
+        self
+    }
+
+    pub fn with_consistency_policy(self, policy: ConsistencyPolicy) -> Self {
+        self.set_consistency_policy(policy);
+        self
+    }
+
+    pub fn with_latency_policy(self, policy: LatencyPolicy) -> Self {
+        self.set_latency_policy(policy);
+        self
+    }
+
+    pub fn with_batch_size_policy(self, policy: BatchSizePolicy) -> Self {
+        self.set_batch_size_policy(policy);
+        self
+    }
+
+    pub fn with_limits_policy(self, policy: LimitsPolicy) -> Self {
+        self.set_limits_policy(policy);
+        self
+    }
+
+    pub fn with_strict_mode(self) -> Self {
+        self.set_strict_mode(true);
+        self
+    }
+
+    pub fn with_authentication(self) -> Self {
+        self.set_authentication(true);
+        self
+    }
+
+    pub fn with_error_renderer(self, renderer: ErrorRenderer) -> Self {
+        self.set_error_renderer(renderer);
+        self
+    }
+
+    pub fn with_rewrite(self, rewrite: impl Rewrite + 'static) -> Self {
+        self.set_rewrite(Some(Arc::new(rewrite)));
+        self
+    }
+
+    pub fn with_slow_query_threshold(self, threshold: Duration) -> Self {
+        self.set_slow_query_threshold(Some(threshold));
+        self
+    }
+
+    pub fn with_middleware(self, middleware: impl Middleware + 'static) -> Self {
+        self.add_middleware(middleware);
+        self
+    }
+
+    pub fn with_opcode(self, opcode: u8, handler: impl OpcodeHandler + 'static) -> Self {
+        self.register_opcode(opcode, handler);
+        self
+    }
+
+    pub fn with_time_provider(self, time: impl TimeProvider + 'static) -> Self {
+        self.set_time_provider(Arc::new(time));
+        self
+    }
+
+    pub fn with_id_provider(self, ids: impl IdProvider + 'static) -> Self {
+        self.set_id_provider(Arc::new(ids));
+        self
+    }
+}
+
+impl<E: cql::Engine> Clone for SessionHandle<E> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<E: cql::Engine> SessionHandle<E> {
+    /// Runs the request through middleware, then handles it, unless middleware answered it already
+    pub fn request_in(
+        &self,
+        connection: &mut ConnectionState,
+        mut request: Request<'_>,
+    ) -> Response {
+        if let ControlFlow::Break(response) = self.intercept(connection, &mut request) {
+            return response;
+        }
+
+        let result = match request {
+            Request::StartUp(options) => {
+                return match self.startup_in(connection, options) {
+                    Ok(()) => self.startup_response(),
+                    Err(error) => Response::Error(error),
+                };
+            }
+            Request::AuthResponse { token } => {
+                return match self.authenticate_in(connection, token) {
+                    Ok(()) => Response::AuthSuccess(AuthSuccess {
+                        success_message: None,
+                    }),
+                    Err(error) => Response::Error(error),
+                };
+            }
+            Request::Options => return Response::supported(&self.features(), connection.shard()),
+            Request::Register { events: _ } => return Response::Ready,
+            Request::Query(query) => self.process_in(connection, query),
+            Request::Prepare(prepare) => self.prepare_in(connection, prepare),
+            Request::Execute(execute) => self.execute_in(connection, execute),
+            Request::Batch(batch) => self.process_batch_in(connection, batch),
+            Request::Other { opcode, body } => return self.opcode_in(connection, opcode, body),
+        };
+
+        match result {
+            Ok(result) => Response::Result(result),
+            Err(error) => Response::Error(error),
+        }
+    }
+
+    /// Answers a request with an opcode kassandra doesn't know by the handler registered for it,
+    /// without one the request fails with a `ProtocolError`, and the connection is kept.
+    pub fn opcode_in(&self, connection: &mut ConnectionState, opcode: u8, body: &[u8]) -> Response {
+        let handler = self.shared.opcodes.read().unwrap().get(&opcode).cloned();
+        match handler {
+            Some(handler) => handler.handle(body, &mut Context { connection }),
+            None => Response::Error(Error::new(
+                DbError::ProtocolError,
+                format!("Unknown opcode {opcode:#04x}"),
+            )),
+        }
+    }
+
+    /// Checks options of `STARTUP` the way cassandra does, then records them for the connection
+    pub fn startup_in(
+        &self,
+        connection: &mut ConnectionState,
+        options: HashMap<String, String>,
+    ) -> Result<(), Error> {
+        let protocol_error = |reason: String| Error::new(DbError::ProtocolError, reason);
+
+        let version = options
+            .get("CQL_VERSION")
+            .ok_or_else(|| protocol_error("Missing value CQL_VERSION in STARTUP message".into()))?;
+        let valid_version = version
+            .split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|it| it.is_ascii_digit()));
+        if !valid_version {
+            return Err(protocol_error(format!("Invalid CQL version: {version}")));
+        }
+
+        if let Some(compression) = options.get("COMPRESSION") {
+            let supported = self.features().compression;
+            if !supported
+                .iter()
+                .any(|it| it.eq_ignore_ascii_case(compression))
+            {
+                return Err(protocol_error(format!(
+                    "Unknown compression algorithm: {compression}, supported: [{}]",
+                    supported.join(", ")
+                )));
+            }
+        }
+
+        for option in ["NO_COMPACT", "THROW_ON_OVERLOAD"] {
+            match options.get(option) {
+                Some(value)
+                    if !value.eq_ignore_ascii_case("true")
+                        && !value.eq_ignore_ascii_case("false") =>
+                {
+                    return Err(protocol_error(format!(
+                        "Invalid value for {option}: {value}, expected true or false"
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        connection.startup(options);
+
+        Ok(())
+    }
+
+    /// Answer to a valid `STARTUP`, sessions with authentication ask clients to authenticate first
+    pub fn startup_response(&self) -> Response {
+        match self.authentication() {
+            true => Response::Authenticate(Authenticate {
+                authenticator_name: PASSWORD_AUTHENTICATOR.to_owned(),
+            }),
+            false => Response::Ready,
+        }
+    }
+
+    /// Logs the connection in with the `PLAIN` credentials of an `AUTH_RESPONSE` token
+    pub fn authenticate_in(
+        &self,
+        connection: &mut ConnectionState,
+        token: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let (role, password) = token.and_then(auth::plain_credentials).ok_or_else(|| {
+            Error::new(
+                DbError::AuthenticationError,
+                "Authentication failed: expected PLAIN credentials",
+            )
+        })?;
+
+        self.login_in(connection, role, password)
+    }
+
+    /// Logs the connection in as the role, which has to be allowed to log in with the password
+    pub fn login_in(
+        &self,
+        connection: &mut ConnectionState,
+        role: &str,
+        password: &str,
+    ) -> Result<(), Error> {
+        match Role::read(&*self.engine(), role)? {
+            Some(found) if found.check_password(password) => {
+                connection.role = Some(role.to_owned());
+                Ok(())
+            }
+            _ => Err(Error::new(
+                DbError::AuthenticationError,
+                format!("Provided username {role} and/or password are incorrect"),
+            )),
+        }
+    }
+
+    /// Runs the request through middleware in the order it was added, the first response given ends it.
+    ///
+    /// Servers handling requests themselves, instead of with [`SessionHandle::request_in`], call it first.
+    pub fn intercept(
+        &self,
+        connection: &mut ConnectionState,
+        request: &mut Request<'_>,
+    ) -> ControlFlow<Response> {
+        let mut context = Context { connection };
+        for middleware in self.middleware() {
+            middleware.handle(request, &mut context)?;
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    #[instrument(level = Level::TRACE, skip(self, connection), fields(operation = query.query.name(), target = query.query.target()) err, ret)]
+    pub fn process_in(
+        &self,
+        connection: &mut ConnectionState,
+        mut query: Query,
+    ) -> Result<QueryResult, Error> {
+        if let Some(rewrite) = self.rewrite() {
+            rewrite.rewrite(&mut query.query);
+            // the statement is reported the way it was executed
+            query.raw_query = "";
+        }
+
+        self.run_in(connection, query)
+    }
+
+    /// Runs a statement, which was already rewritten
+    fn run_in(
+        &self,
+        connection: &mut ConnectionState,
+        mut query: Query,
+    ) -> Result<QueryResult, Error> {
+        self.check_in(connection, &mut query)?;
+
+        let statement = match query.raw_query {
+            "" => Cow::Owned(query.query.to_string()),
+            raw => Cow::Borrowed(raw),
+        };
+        let shape = statement_shape(&query.query, self.shared.isolation.as_deref());
+        let started = Instant::now();
+        let result = self
+            .process_statement(connection, query.query, query.parameters)
+            .map_err(|error| self.error_renderer().apply(error));
+        self.shared.stats.record(shape, started.elapsed(), &result);
+        match result {
+            Err(error) if error.error == DbError::Unimplemented => {
+                self.handle_unimplemented(&statement, error)
+            }
+            other => other,
+        }
+    }
+
+    /// Checks the statement is allowed to run, and assigns its write timestamp unless it has one
+    fn check_in(&self, connection: &ConnectionState, query: &mut Query) -> Result<(), Error> {
+        self.policy().check(&query.query)?;
+        self.authorize(connection, &query.query)?;
+        self.limits_policy()
+            .check_schema(&query.query, self.shared.isolation.as_deref())?;
+        if let Some(profile) = self.compatibility_profile() {
+            let keyspace = query.query.keyspace().or(connection.keyspace());
+            if keyspace == Some("system_views") && !profile.has_system_views() {
+                return Err(self
+                    .error_renderer()
+                    .render(KnownError::UnknownKeyspace("system_views".to_owned())));
+            }
+        }
+        self.shared.consistency.read().unwrap().check(
+            &query.query,
+            query.parameters.consistency,
+            self.replication_factor(connection, query.query.keyspace()),
+        )?;
+        query
+            .parameters
+            .default_timestamp
+            .get_or_insert_with(|| self.write_timestamp());
+
+        Ok(())
+    }
+
+    /// Checks the role of the connection may run the statement, unless authentication is disabled
+    fn authorize(
+        &self,
+        connection: &ConnectionState,
+        statement: &QueryString,
+    ) -> Result<(), Error> {
+        if !self.authentication() {
+            return Ok(());
+        }
+        let unauthorized = |reason: String| Error::new(DbError::Unauthorized, reason);
+
+        let name = connection
+            .role()
+            .ok_or_else(|| unauthorized("You have not logged in".to_owned()))?;
+        let role = Role::read(&*self.engine(), name)?
+            .ok_or_else(|| unauthorized(format!("Role {name} doesn't exist")))?;
+
+        role.authorize(statement, connection.keyspace())
+    }
+
+    /// Applies [`UnimplementedPolicy`] to an error, which isn't an [`DbError::Unimplemented`] is returned as is.
+    pub fn handle_unimplemented(
+        &self,
+        statement: &str,
+        error: Error,
+    ) -> Result<QueryResult, Error> {
+        if error.error != DbError::Unimplemented {
+            return Err(error);
+        }
+
+        match self.unimplemented_policy() {
+            UnimplementedPolicy::Error => Err(error),
+            UnimplementedPolicy::WarnAndIgnore => {
+                tracing::warn!(
+                    statement,
+                    reason = error.reason,
+                    "Skipping unimplemented statement"
+                );
+                self.shared.skipped.lock().unwrap().push(SkippedStatement {
+                    statement: statement.to_owned(),
+                    reason: error.reason,
+                });
+                Ok(QueryResult::Void)
+            }
+            UnimplementedPolicy::Panic => {
+                panic!("Unimplemented statement `{statement}`: {}", error.reason)
+            }
+        }
+    }
+
+    fn process_statement(
+        &self,
+        connection: &mut ConnectionState,
+        query: QueryString,
+        parameters: QueryParameters<'_>,
+    ) -> Result<QueryResult, Error> {
+        match query {
+            QueryString::Use { keyspace } => {
+                connection.use_keyspace(&keyspace);
+                Ok(QueryResult::SetKeyspace(SetKeyspace {
+                    keyspace_name: keyspace.to_owned(),
+                }))
+            }
+            select @ QueryString::Select(_) => {
+                if parameters.consistency == Consistency::Any {
+                    return Err(KnownError::WriteOnlyConsistency(Consistency::Any).into());
+                }
+
+                let engine = self.engine();
+                let plan = Plan::build(
+                    select,
+                    parameters,
+                    connection.keyspace.clone(),
+                    &*engine,
+                    self.strict_mode(),
+                )?;
+                tracing::trace!(?plan, "Built a plan");
+
+                let plan = match (plan, self.scan_limit()) {
+                    (Plan::Scan(node), Some(max)) if node.result_page_size == usize::MAX => {
+                        return limited_scan(node, max, &*engine);
+                    }
+                    (plan, _) => plan,
+                };
+                if let Some(key) = RowCache::key(&plan) {
+                    if let Some(result) = self.shared.row_cache.get(&key) {
+                        return Ok(result);
+                    }
+                    // cached while the engine is still locked, so no write can slip in between
+                    let result = plan.read(&*engine)?;
+                    return Ok(self.shared.row_cache.put(key, result));
+                }
+                let reader: Box<dyn ChunkedReader<E>> = match plan {
+                    Plan::Select(node)
+                        if connection.stream_rows && node.result_page_size > ROWS_PER_CHUNK =>
+                    {
+                        Box::new(node)
+                    }
+                    Plan::Scan(node)
+                        if connection.stream_rows && node.result_page_size > ROWS_PER_CHUNK =>
+                    {
+                        Box::new(node)
+                    }
+                    plan => return plan.read(&*engine),
+                };
+
+                Ok(QueryResult::RowStream(RowStream::new(PageChunks {
+                    handle: self.clone(),
+                    reader,
+                })))
+            }
+            QueryString::Batch(batch) => {
+                let batch = Batch::from_query(batch, parameters)?;
+                self.run_batch_in(connection, batch)
+            }
+            statement @ (QueryString::Describe(_) | QueryString::Explain(_)) => {
+                let engine = self.engine();
+                let plan = Plan::build(
+                    statement,
+                    parameters,
+                    connection.keyspace.clone(),
+                    &*engine,
+                    self.strict_mode(),
+                )?;
+
+                plan.read(&*engine)
+            }
+            other => {
+                let mut engine = self.statement_engine_mut();
+                let plan = Plan::build(
+                    other,
+                    parameters,
+                    connection.keyspace.clone(),
+                    &*engine,
+                    self.strict_mode(),
+                )?;
+                tracing::trace!(?plan, "Built a plan");
+                match &plan {
+                    Plan::Insert(node) => self.limits_policy().check_values(&node.values)?,
+                    Plan::Update(node) => self.limits_policy().check_values(&node.values)?,
+                    _ => {}
+                }
+
+                let subscribed = !self.shared.subscribers.is_empty();
+                let change = subscribed.then(|| ChangeEvent::from_plan(&plan)).flatten();
+                self.shared.row_cache.invalidate_plan(&plan);
+                let result = plan.execute(&mut *engine)?;
+                if subscribed {
+                    // sent while the engine is still locked, so events are ordered the way changes were applied
+                    let change = change.or_else(|| ChangeEvent::from_result(&result));
+                    if let Some(change) = change {
+                        self.shared
+                            .subscribers
+                            .send(change, self.shared.isolation.as_deref());
+                    }
+                }
+
+                Ok(result)
+            }
+        }
+    }
+
+    #[instrument(level = Level::TRACE, skip(self, connection), err, ret)]
+    pub fn execute_in(
+        &self,
+        connection: &mut ConnectionState,
+        execute: Execute<'_>,
+    ) -> Result<QueryResult, Error> {
+        let query = self.retrieve(execute.id)?;
+        let skip_metadata = execute.parameters.flags.contains(QueryFlags::SKIP_METADATA);
+
+        let mut result = self.run_in(
+            connection,
+            Query {
+                query,
+                raw_query: "",
+                parameters: execute.parameters,
+            },
+        )?;
+        if skip_metadata {
+            self.skip_metadata(execute.id, &mut result);
+        }
+
+        Ok(result)
+    }
 
-impl<E: cql::Engine + Default> Default for KassandraSession<E> {
-    fn default() -> Self {
-        Self::new()
+    /// Rows keep their column specs when they are not the ones the statement was prepared with,
+    /// like after the table was altered, clients would read them by outdated specs otherwise.
+    fn skip_metadata(&self, id: &[u8], result: &mut QueryResult) {
+        let Ok(id) = id.try_into().map(u128::from_be_bytes) else {
+            return;
+        };
+        let mut prepared = self.shared.result_metadata.lock().unwrap();
+        if let Some(col_specs) = prepared.get(&id) {
+            result.skip_metadata(col_specs);
+        }
     }
-}
 
-impl<E: cql::Engine + Default> KassandraSession<E> {
-    pub fn new() -> Self {
-        let mut engine = Default::default();
-        init_session()
-            .execute(&mut engine)
-            .expect("Could not init session");
-        Self {
-            engine,
-            use_keyspace: None,
+    #[instrument(level = Level::TRACE, skip(self, connection), err, ret)]
+    pub fn process_batch_in(
+        &self,
+        connection: &mut ConnectionState,
+        mut batch: Batch<'_>,
+    ) -> Result<QueryResult, Error> {
+        if let Some(rewrite) = self.rewrite() {
+            for statement in &mut batch.statements {
+                if let BatchStatement::Query { query, .. } = statement {
+                    rewrite.rewrite(query);
+                }
+            }
         }
+
+        self.run_batch_in(connection, batch)
     }
-}
 
-impl<E: cql::Engine> KassandraSession<E> {
-    #[instrument(level = Level::TRACE, skip(self), fields(operation = query.query.name(), target = query.query.target()) err, ret)]
-    pub fn process(&mut self, query: Query) -> Result<QueryResult, Error> {
-        match query.query {
-            QueryString::Use { keyspace } => {
-                self.use_keyspace(&keyspace);
-                Ok(QueryResult::SetKeyspace(SetKeyspace {
-                    keyspace_name: keyspace.to_owned(),
+    fn run_batch_in(
+        &self,
+        connection: &mut ConnectionState,
+        batch: Batch<'_>,
+    ) -> Result<QueryResult, Error> {
+        let keyspace = batch
+            .statements
+            .iter()
+            .find_map(|statement| match statement {
+                BatchStatement::Query { query, .. } => query.keyspace().map(str::to_owned),
+                BatchStatement::Prepared { .. } => None,
+            });
+        self.shared.consistency.read().unwrap().check_batch(
+            batch.batch_type,
+            batch.consistency,
+            self.replication_factor(connection, keyspace.as_deref()),
+        )?;
+
+        let (queries, values): (Vec<_>, Vec<_>) = batch
+            .statements
+            .into_iter()
+            .map(|statement| match statement {
+                BatchStatement::Query { query, values, .. } => Ok((*query, values)),
+                BatchStatement::Prepared { id, values, .. } => Ok((self.retrieve(id)?, values)),
+            })
+            .collect::<Result<Vec<_>, Error>>()?
+            .into_iter()
+            .unzip();
+
+        let policy = self.batch_size_policy();
+        if policy != BatchSizePolicy::default() {
+            let size = queries
+                .iter()
+                .map(|query| query.to_string().len())
+                .chain(values.iter().flatten().map(|value| match value {
+                    FrameValue::Some(bytes) => bytes.len(),
+                    FrameValue::Null | FrameValue::NotSet => 0,
                 }))
+                .sum();
+            if let Some(warning) = policy.check(&queries, connection.keyspace(), size)? {
+                connection.warn(warning);
             }
-            other => {
-                let plan = Plan::build(
-                    other,
-                    query.parameters,
-                    self.use_keyspace.clone(),
-                    &mut self.engine,
-                )?;
-                tracing::trace!(?plan, "Built a plan");
+        }
+
+        // statements of a batch share its timestamp, so a delete and an insert of the same row tie
+        let timestamp = batch.timestamp.unwrap_or_else(|| self.write_timestamp());
+        let queries = queries
+            .into_iter()
+            .zip(values)
+            .map(|(query, values)| {
+                let mut query = Query {
+                    query,
+                    raw_query: "",
+                    parameters: QueryParameters {
+                        consistency: batch.consistency,
+                        flags: QueryFlags::VALUES,
+                        data: values,
+                        result_page_size: None,
+                        paging_state: None,
+                        serial_consistency: batch.serial_consistency,
+                        default_timestamp: Some(timestamp),
+                    },
+                };
+                self.check_in(connection, &mut query)?;
+                Ok(query)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
 
-                plan.execute(&mut self.engine)
+        let shapes = queries
+            .iter()
+            .map(|query| statement_shape(&query.query, self.shared.isolation.as_deref()))
+            .collect::<Vec<_>>();
+        let started = Instant::now();
+        let result = self
+            .apply_batch_in(connection, queries)
+            .map_err(|error| self.error_renderer().apply(error));
+        for shape in shapes {
+            self.shared.stats.record(shape, started.elapsed(), &result);
+        }
+
+        result
+    }
+
+    /// Every statement of the batch is planned before any of them is applied,
+    /// so a failing statement leaves nothing of the batch written, like a logged batch does.
+    fn apply_batch_in(
+        &self,
+        connection: &ConnectionState,
+        queries: Vec<Query>,
+    ) -> Result<QueryResult, Error> {
+        let mut engine = self.statement_engine_mut();
+        let mut plans = Vec::with_capacity(queries.len());
+        for query in queries {
+            let statement = query.query.to_string();
+            let plan = match Plan::build(
+                query.query,
+                query.parameters,
+                connection.keyspace.clone(),
+                &*engine,
+                self.strict_mode(),
+            ) {
+                Ok(plan) => plan,
+                Err(error) => {
+                    self.handle_unimplemented(&statement, error)?;
+                    continue;
+                }
+            };
+            match &plan {
+                Plan::Insert(node) => self.limits_policy().check_values(&node.values)?,
+                Plan::Update(node) => self.limits_policy().check_values(&node.values)?,
+                _ => {}
             }
+            plans.push(plan);
+        }
+        tracing::trace!(?plans, "Built plans of the batch");
+
+        let changes = match self.shared.subscribers.is_empty() {
+            true => Vec::new(),
+            false => plans.iter().filter_map(ChangeEvent::from_plan).collect(),
+        };
+        for plan in &plans {
+            self.shared.row_cache.invalidate_plan(plan);
+        }
+        let mutations = plans
+            .into_iter()
+            .map(Plan::mutation)
+            .collect::<Result<Vec<_>, Error>>()?;
+        engine.apply(mutations)?;
+        // sent while the engine is still locked, so events are ordered the way changes were applied
+        for change in changes {
+            self.shared
+                .subscribers
+                .send(change, self.shared.isolation.as_deref());
         }
+
+        Ok(QueryResult::Void)
     }
 
-    #[instrument(level = Level::TRACE, skip(self), err, ret)]
-    pub fn execute(&mut self, execute: Execute<'_>) -> Result<QueryResult, Error> {
-        let id = u128::from_be_bytes(
-            execute
-                .id
-                .try_into()
+    fn retrieve(&self, id: &[u8]) -> Result<QueryString, Error> {
+        let parsed_id = u128::from_be_bytes(
+            id.try_into()
                 .map_err(|_| Error::new(DbError::Invalid, "Invalid id for prepared query"))?,
         );
-        let Some(query) = self.engine.retrieve(id)? else {
-            return Err(Error::new(
+        let query = self.engine().retrieve(parsed_id)?;
+
+        query.ok_or_else(|| {
+            Error::new(
                 DbError::Unprepared {
-                    statement_id: Bytes::copy_from_slice(execute.id),
+                    statement_id: Bytes::copy_from_slice(id),
                 },
                 "Unprepared query id",
-            ));
+            )
+        })
+    }
+
+    pub fn prepare_in(
+        &self,
+        connection: &ConnectionState,
+        prepare: Prepare<'_>,
+    ) -> Result<QueryResult, Error> {
+        let id = self
+            .id_provider()
+            .statement_id(connection.keyspace(), prepare.raw_query);
+        self.prepare_with_id_in(connection, prepare.query, id)
+    }
+
+    /// Prepared statements are visible to every connection, so statements without explicit keyspace
+    /// are bound to the keyspace of the preparing connection.
+    #[instrument(level = Level::TRACE, skip(self, connection), err, ret)]
+    pub fn prepare_with_id_in(
+        &self,
+        connection: &ConnectionState,
+        mut query: QueryString,
+        id: u128,
+    ) -> Result<QueryResult, Error> {
+        if let Some(rewrite) = self.rewrite() {
+            rewrite.rewrite(&mut query);
+        }
+        self.policy().check(&query)?;
+
+        let mut engine = self.statement_engine_mut();
+        let (prepared_metadata, result_metadata) = Plan::prepare(
+            query.clone(),
+            connection.keyspace.clone(),
+            &*engine,
+            self.strict_mode(),
+        )
+        .map_err(|error| self.error_renderer().apply(error))?;
+        if let Some(keyspace) = connection.keyspace() {
+            query.qualify(keyspace);
+        }
+
+        engine.store(id, query)?;
+        self.shared
+            .result_metadata
+            .lock()
+            .unwrap()
+            .put(id, result_metadata.col_specs.clone());
+
+        let prepared = Prepared {
+            id,
+            prepared_metadata,
+            result_metadata,
         };
 
-        self.process(Query {
+        Ok(QueryResult::Prepared(prepared))
+    }
+
+    /// Plan the statement would run with, without running it, `EXPLAIN` statements render it as rows
+    pub fn explain_in(
+        &self,
+        connection: &ConnectionState,
+        mut query: Query,
+    ) -> Result<Plan, Error> {
+        if let Some(rewrite) = self.rewrite() {
+            rewrite.rewrite(&mut query.query);
+        }
+
+        Plan::build(
+            query.query,
+            query.parameters,
+            connection.keyspace.clone(),
+            &*self.engine(),
+            self.strict_mode(),
+        )
+    }
+
+    /// Statement prepared under `id`, `None` if it isn't prepared
+    pub fn prepared_statement(&self, id: &[u8]) -> Option<QueryString> {
+        self.retrieve(id).ok()
+    }
+
+    /// Bind markers of a statement, the same [`SessionHandle::prepare_in`] returns, without storing the statement
+    pub fn prepared_metadata(
+        &self,
+        query: QueryString,
+        keyspace: Option<&str>,
+    ) -> Result<PreparedMetadata, Error> {
+        let (metadata, _) = Plan::prepare(
             query,
-            raw_query: "",
-            parameters: execute.parameters,
-        })
+            keyspace.map(str::to_owned),
+            &*self.engine(),
+            self.strict_mode(),
+        )?;
+
+        Ok(metadata)
     }
 
-    #[instrument(level = Level::TRACE, skip(self), err, ret)]
-    pub fn process_batch(&mut self, batch: Batch<'_>) -> Result<QueryResult, Error> {
-        for statement in batch.statements {
-            let (query, values) = match statement {
-                BatchStatement::Query { query, values, .. } => (query, values),
-                BatchStatement::Prepared { id, values, .. } => {
-                    let parsed_id = u128::from_be_bytes(id.try_into().map_err(|_| {
-                        Error::new(DbError::Invalid, "Invalid id for prepared query")
-                    })?);
-                    let Some(query) = self.engine.retrieve(parsed_id)? else {
-                        return Err(Error::new(
-                            DbError::Unprepared {
-                                statement_id: Bytes::copy_from_slice(id),
-                            },
-                            "Unprepared query id",
-                        ));
-                    };
-                    (query, values)
-                }
-            };
+    /// Replaces data and schema with the ones of `other`, for every handle of the session
+    pub fn replace_with(&self, other: KassandraSession<E>) {
+        std::mem::swap(&mut *self.engine_mut(), &mut *other.engine_mut());
+    }
 
-            self.process(Query {
-                query,
-                raw_query: "",
-                parameters: QueryParameters {
-                    consistency: batch.consistency,
-                    flags: QueryFlags::VALUES,
-                    data: values,
-                    result_page_size: None,
-                    paging_state: None,
-                    serial_consistency: batch.serial_consistency,
-                    default_timestamp: batch.timestamp,
-                },
-            })?;
+    pub fn policy(&self) -> RwLockReadGuard<'_, StatementPolicy> {
+        self.shared.policy.read().unwrap()
+    }
+
+    pub fn policy_mut(&self) -> RwLockWriteGuard<'_, StatementPolicy> {
+        self.shared.policy.write().unwrap()
+    }
+
+    /// Ends the setup phase of the statement policy, see [`StatementPolicy::finish_setup`].
+    pub fn finish_setup(&self) {
+        self.policy_mut().finish_setup();
+    }
+
+    pub fn unimplemented_policy(&self) -> UnimplementedPolicy {
+        *self.shared.unimplemented.read().unwrap()
+    }
+
+    pub fn set_unimplemented_policy(&self, policy: UnimplementedPolicy) {
+        *self.shared.unimplemented.write().unwrap() = policy;
+    }
+
+    pub fn consistency_policy(&self) -> ConsistencyPolicy {
+        self.shared.consistency.read().unwrap().clone()
+    }
+
+    /// Failures simulated for statements of the consistency levels, see [`ConsistencyPolicy`]
+    pub fn set_consistency_policy(&self, policy: ConsistencyPolicy) {
+        *self.shared.consistency.write().unwrap() = policy;
+    }
+
+    pub fn latency_policy(&self) -> LatencyPolicy {
+        self.shared.latency.read().unwrap().clone()
+    }
+
+    /// Artificial latency servers of the session add to responses, see [`LatencyPolicy`]
+    pub fn set_latency_policy(&self, policy: LatencyPolicy) {
+        *self.shared.latency.write().unwrap() = policy;
+    }
+
+    pub fn batch_size_policy(&self) -> BatchSizePolicy {
+        *self.shared.batch_size.read().unwrap()
+    }
+
+    /// Sizes of batches which warn or fail, see [`BatchSizePolicy`]
+    pub fn set_batch_size_policy(&self, policy: BatchSizePolicy) {
+        *self.shared.batch_size.write().unwrap() = policy;
+    }
+
+    pub fn compatibility_profile(&self) -> Option<CompatibilityProfile> {
+        self.shared.features.read().unwrap().profile
+    }
+
+    /// Server the session pretends to be in `OPTIONS` responses and behaviors, see [`CompatibilityProfile`].
+    ///
+    /// Versions in `system.local` are written when the session is created, see [`SessionConfig::with_profile`].
+    pub fn set_compatibility_profile(&self, profile: Option<CompatibilityProfile>) {
+        self.shared.features.write().unwrap().profile = profile;
+    }
+
+    pub fn features(&self) -> Features {
+        self.shared.features.read().unwrap().clone()
+    }
+
+    /// Features advertised in `OPTIONS` responses, see [`Features`]
+    pub fn set_features(&self, features: Features) {
+        *self.shared.features.write().unwrap() = features;
+    }
+
+    pub fn limits_policy(&self) -> LimitsPolicy {
+        *self.shared.limits.read().unwrap()
+    }
+
+    /// Limits of names and values statements are checked against, see [`LimitsPolicy`]
+    pub fn set_limits_policy(&self, policy: LimitsPolicy) {
+        *self.shared.limits.write().unwrap() = policy;
+    }
+
+    pub fn scan_limit(&self) -> Option<NonZeroUsize> {
+        *self.shared.scan_limit.read().unwrap()
+    }
+
+    /// Scans without paging returning more rows fail, rather than building responses of any size,
+    /// `None` lets them return every row.
+    pub fn set_scan_limit(&self, limit: Option<NonZeroUsize>) {
+        *self.shared.scan_limit.write().unwrap() = limit;
+    }
+
+    /// Time servers wait before responding to the request, it has to be asked before the request is run.
+    ///
+    /// Executions of statements which aren't prepared are not delayed, they fail right away.
+    pub fn latency(&self, connection: &ConnectionState, request: &Request<'_>) -> Duration {
+        let policy = self.shared.latency.read().unwrap();
+        if policy.is_empty() {
+            return Duration::ZERO;
         }
 
-        Ok(QueryResult::Void)
+        let query = match request {
+            Request::Query(query) => query.query.clone(),
+            Request::Execute(execute) => match self.retrieve(execute.id) {
+                Ok(query) => query,
+                Err(_) => return Duration::ZERO,
+            },
+            Request::Batch(batch) => QueryString::Batch(BatchQuery {
+                batch_type: batch.batch_type,
+                using: None,
+                statements: batch
+                    .statements
+                    .iter()
+                    .filter_map(|statement| match statement {
+                        BatchStatement::Query { query, .. } => Some(*query.clone()),
+                        BatchStatement::Prepared { id, .. } => self.retrieve(id).ok(),
+                    })
+                    .collect(),
+            }),
+            _ => return Duration::ZERO,
+        };
+
+        policy.delay(&query, connection.keyspace())
     }
 
-    #[instrument(level = Level::TRACE, skip(self), err, ret)]
-    pub fn prepare(&mut self, query: QueryString) -> Result<QueryResult, Error> {
-        self.prepare_with_id(query, ulid::Ulid::new().0)
+    pub fn strict_mode(&self) -> bool {
+        self.shared.strict.load(Ordering::Relaxed)
     }
 
-    #[instrument(level = Level::TRACE, skip(self), err, ret)]
-    pub fn prepare_with_id(&mut self, query: QueryString, id: u128) -> Result<QueryResult, Error> {
-        let (prepared_metadata, result_metadata) =
-            Plan::prepare(query.clone(), self.use_keyspace.clone(), &mut self.engine)?;
+    /// In strict mode writes missing parts of the primary key are rejected with the errors cassandra gives,
+    /// otherwise they fail with generic errors or are let through where kassandra doesn't need the key.
+    pub fn set_strict_mode(&self, strict: bool) {
+        self.shared.strict.store(strict, Ordering::Relaxed);
+    }
 
-        self.engine.store(id, query)?;
+    pub fn authentication(&self) -> bool {
+        self.shared.authentication.load(Ordering::Relaxed)
+    }
 
-        let prepared = Prepared {
-            id,
-            prepared_metadata,
-            result_metadata,
+    /// With authentication clients log in with a password, then statements are checked against the permissions of their role.
+    ///
+    /// Roles are managed with `CREATE ROLE`, `GRANT` and `REVOKE`, sessions start with the `cassandra` superuser.
+    pub fn set_authentication(&self, authentication: bool) {
+        self.shared
+            .authentication
+            .store(authentication, Ordering::Relaxed);
+    }
+
+    pub fn error_renderer(&self) -> ErrorRenderer {
+        *self.shared.errors.read().unwrap()
+    }
+
+    /// Selects the messages of errors drivers and applications are known to match on
+    pub fn set_error_renderer(&self, renderer: ErrorRenderer) {
+        *self.shared.errors.write().unwrap() = renderer;
+    }
+
+    pub fn rewrite(&self) -> Option<Arc<dyn Rewrite>> {
+        self.shared.rewrite.read().unwrap().clone()
+    }
+
+    /// Rewrites every statement the session receives before it is prepared or executed,
+    /// prepared statements are stored rewritten and aren't rewritten again when executed.
+    pub fn set_rewrite(&self, rewrite: Option<Arc<dyn Rewrite>>) {
+        *self.shared.rewrite.write().unwrap() = rewrite;
+    }
+
+    pub fn middleware(&self) -> Vec<Arc<dyn Middleware>> {
+        self.shared.middleware.read().unwrap().clone()
+    }
+
+    /// Runs every request through the middleware after the already added ones, see [`Middleware`]
+    pub fn add_middleware(&self, middleware: impl Middleware + 'static) {
+        self.shared
+            .middleware
+            .write()
+            .unwrap()
+            .push(Arc::new(middleware));
+    }
+
+    pub fn set_middleware(&self, middleware: Vec<Arc<dyn Middleware>>) {
+        *self.shared.middleware.write().unwrap() = middleware;
+    }
+
+    pub fn opcode_handlers(&self) -> HashMap<u8, Arc<dyn OpcodeHandler>> {
+        self.shared.opcodes.read().unwrap().clone()
+    }
+
+    /// Answers requests with the opcode by the handler, replacing the one registered before, see [`OpcodeHandler`]
+    pub fn register_opcode(&self, opcode: u8, handler: impl OpcodeHandler + 'static) {
+        self.shared
+            .opcodes
+            .write()
+            .unwrap()
+            .insert(opcode, Arc::new(handler));
+    }
+
+    pub fn set_opcode_handlers(&self, handlers: HashMap<u8, Arc<dyn OpcodeHandler>>) {
+        *self.shared.opcodes.write().unwrap() = handlers;
+    }
+
+    pub fn time_provider(&self) -> Arc<dyn TimeProvider> {
+        self.shared.time.read().unwrap().clone()
+    }
+
+    /// Time writes without a timestamp are made at and tombstones expire by
+    pub fn set_time_provider(&self, time: Arc<dyn TimeProvider>) {
+        self.engine_mut().set_time_provider(time.clone());
+        *self.shared.time.write().unwrap() = time;
+    }
+
+    pub fn id_provider(&self) -> Arc<dyn IdProvider> {
+        self.shared.ids.read().unwrap().clone()
+    }
+
+    /// Ids of statements prepared from now on, the ones prepared before keep their ids
+    pub fn set_id_provider(&self, ids: Arc<dyn IdProvider>) {
+        *self.shared.ids.write().unwrap() = ids;
+    }
+
+    /// Replication factor of the keyspace, or of the one in use, the largest one of its datacenters.
+    /// Keyspaces which can't be found are replicated once
+    fn replication_factor(&self, connection: &ConnectionState, keyspace: Option<&str>) -> usize {
+        let Some(keyspace) = keyspace.or(connection.keyspace()) else {
+            return 1;
         };
 
-        Ok(QueryResult::Prepared(prepared))
+        match self.engine().get_keyspace(keyspace).map(|it| &it.strategy) {
+            Some(Strategy::SimpleStrategy { replication_factor }) => *replication_factor,
+            Some(Strategy::NetworkTopologyStrategy {
+                datacenter_repfactors,
+            }) => datacenter_repfactors.values().copied().max().unwrap_or(1),
+            _ => 1,
+        }
     }
 
-    pub fn use_keyspace(&mut self, ks: impl Into<String>) {
-        self.use_keyspace = Some(ks.into());
+    /// Timestamp of writes which don't specify one
+    fn write_timestamp(&self) -> i64 {
+        self.shared
+            .timestamps
+            .next(self.time_provider().now_micros())
+    }
+
+    /// Changes applied by statements of the session from now on, of every handle of it.
+    ///
+    /// Statements which fail report nothing.
+    pub fn subscribe(&self) -> impl Stream<Item = ChangeEvent> {
+        self.shared.subscribers.subscribe()
+    }
+
+    /// Statistics of the statements run so far by their shape, see [`statement_shape`]
+    pub fn query_stats(&self) -> BTreeMap<String, QueryStats> {
+        self.shared.stats.stats()
+    }
+
+    pub fn reset_query_stats(&self) {
+        self.shared.stats.reset();
+    }
+
+    pub fn slow_query_threshold(&self) -> Option<Duration> {
+        self.shared.stats.slow_query_threshold()
+    }
+
+    /// Statements running at least as long as the threshold are logged with a `Slow query` warning
+    pub fn set_slow_query_threshold(&self, threshold: Option<Duration>) {
+        self.shared.stats.set_slow_query_threshold(threshold);
+    }
+
+    /// Statements skipped by [`UnimplementedPolicy::WarnAndIgnore`]
+    pub fn skipped_statements(&self) -> Vec<SkippedStatement> {
+        self.shared.skipped.lock().unwrap().clone()
+    }
+
+    /// Keyspaces of an isolated session with the prefix stripped, other sessions own every keyspace
+    fn own_keyspaces<T>(&self, keyspaces: BTreeMap<String, T>) -> BTreeMap<String, T> {
+        let Some(prefix) = &self.shared.isolation else {
+            return keyspaces;
+        };
+
+        keyspaces
+            .into_iter()
+            .filter_map(|(name, it)| Some((name.strip_prefix(prefix.as_str())?.to_owned(), it)))
+            .collect()
+    }
+
+    pub(crate) fn engine(&self) -> RwLockReadGuard<'_, E> {
+        self.shared.engine.read().unwrap()
+    }
+
+    /// Exclusive access to the engine for changes made outside of statements, which drops all cached rows
+    fn engine_mut(&self) -> RwLockWriteGuard<'_, E> {
+        let engine = self.shared.engine.write().unwrap();
+        self.shared.row_cache.clear();
+        engine
+    }
+
+    /// Exclusive access to the engine for statements, which invalidate the cached rows they change
+    fn statement_engine_mut(&self) -> RwLockWriteGuard<'_, E> {
+        self.shared.engine.write().unwrap()
+    }
+}
+
+/// Prefixes keyspaces of the statements of an isolated session
+struct Isolation<'a> {
+    prefix: &'a str,
+}
+
+impl VisitMut for Isolation<'_> {
+    fn visit_keyspace(&mut self, keyspace: &mut String) {
+        if !is_system_keyspace(keyspace) {
+            keyspace.insert_str(0, self.prefix);
+        }
+    }
+}
+
+/// Rows are read at once, one row past the limit tells scans over it apart
+fn limited_scan<E: cql::Engine>(
+    mut node: ScanNode,
+    max: NonZeroUsize,
+    engine: &E,
+) -> Result<QueryResult, Error> {
+    let table = format!("{}.{}", node.keyspace, node.table);
+    node.result_page_size = max.get() + 1;
+
+    match Plan::Scan(node).read(engine)? {
+        QueryResult::Rows(rows) if rows.rows.len() > max.get() => Err(Error::new(
+            DbError::Invalid,
+            format!(
+                "Scan of {table} without paging returns more than {max} rows, \
+                 page the query or raise the scan limit"
+            ),
+        )),
+        result => Ok(result),
+    }
+}
+
+/// Reads a page chunk by chunk, the engine is only locked while a chunk is read.
+///
+/// Writes can be applied between chunks, so a page isn't a snapshot: rows of later chunks
+/// can show writes made after earlier chunks were read, as pages of Cassandra can.
+struct PageChunks<E: cql::Engine> {
+    handle: SessionHandle<E>,
+    reader: Box<dyn ChunkedReader<E>>,
+}
+
+impl<E: cql::Engine> RowChunks for PageChunks<E> {
+    fn next_chunk(&mut self) -> Result<Vec<Row>, Error> {
+        let engine = self.handle.engine();
+        self.reader.next_chunk(&engine, ROWS_PER_CHUNK)
     }
+
+    fn end_page(&mut self) -> Result<(), Error> {
+        let engine = self.handle.engine();
+        self.reader.end_page(&engine)
+    }
+
+    fn metadata(&self) -> ResultMetadata {
+        self.reader.metadata()
+    }
+}
+
+/// Layout version of saved states, bumped whenever states of the previous one can't be loaded as they are.
+///
+/// Version 1 keys partitions of tables by their token, unversioned states predate it
+/// and are migrated as they are loaded.
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SavedState<'a, E> {
+    version: u32,
+    engine: &'a E,
+}
+
+/// Saved state of the current version, whose version is already checked
+#[derive(Deserialize)]
+struct LoadedState<E> {
+    engine: E,
+}
+
+/// Version of a saved state, read before the engine so states of other versions are reported as such,
+/// unversioned states read as 0
+#[derive(Deserialize)]
+struct StateVersion {
+    #[serde(default)]
+    version: u32,
 }
 
 impl KassandraSession<KvEngine<memory::Memory>> {
     pub fn load_state(data: &[u8]) -> eyre::Result<Self> {
-        let engine = ron::de::from_bytes(data)?;
+        let StateVersion { version } = ron::de::from_bytes(data)?;
+        match version {
+            STATE_VERSION => {}
+            0 => return Self::load_unversioned_state(data),
+            version => eyre::bail!(
+                "State has layout version {version}, this kassandra reads version {STATE_VERSION}"
+            ),
+        }
+        let LoadedState { engine } = ron::de::from_bytes(data)?;
 
-        Ok(Self {
-            use_keyspace: None,
-            engine,
-        })
+        Ok(Self::with_engine(engine))
+    }
+
+    /// User keyspaces of the state are imported into a new session, keying their partitions by token,
+    /// system keyspaces are the ones of the new session
+    fn load_unversioned_state(data: &[u8]) -> eyre::Result<Self> {
+        let state: UnversionedEngine = ron::de::from_bytes(data)?;
+        let session = Self::new();
+        session.engine_mut().import_unversioned(state)?;
+
+        Ok(session)
     }
+}
 
+impl SessionHandle<KvEngine<memory::Memory>> {
+    /// Engine along with [`STATE_VERSION`], see [`KassandraSession::load_state`]
     pub fn save_state(&self) -> Vec<u8> {
-        ron::ser::to_string_pretty(&self.engine, Default::default())
+        let state = SavedState {
+            version: STATE_VERSION,
+            engine: &*self.engine(),
+        };
+        ron::ser::to_string_pretty(&state, Default::default())
             .unwrap()
             .into_bytes()
     }
 
+    /// User keyspaces with their data, see [`KeyspaceDump`]
+    pub fn export_keyspaces(&self) -> Vec<KeyspaceDump> {
+        self.engine().export_keyspaces()
+    }
+
+    /// Replaces the keyspace of the same name with the dumped one
+    pub fn import_keyspace(&self, dump: KeyspaceDump) -> Result<(), Error> {
+        self.engine_mut().import_keyspace(dump)
+    }
+
     pub fn data_snapshot(&self) -> DataSnapshots {
-        self.engine.data.snapshot()
+        DataSnapshots(self.own_keyspaces(self.engine().data.snapshot().0))
+    }
+
+    pub fn data_snapshot_with_tombstones(&self) -> DataSnapshots {
+        DataSnapshots(self.own_keyspaces(self.engine().data.snapshot_with_tombstones().0))
+    }
+
+    /// Hash of the schema and data of user keyspaces, equal for sessions with the same snapshots,
+    /// so states of two runs can be compared without keeping snapshots of them, see [`state_digest`]
+    pub fn state_digest(&self) -> [u8; 32] {
+        state_digest(
+            &self.schema_snapshot(),
+            &self.data_snapshot_with_tombstones(),
+        )
+    }
+
+    pub fn stats_snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot(self.own_keyspaces(self.engine().data.stats().0))
+    }
+
+    /// Schema of user keyspaces
+    pub fn schema_snapshot(&self) -> Schema {
+        let keyspaces = self
+            .engine()
+            .schema()
+            .iter()
+            .filter(|(name, _)| !is_system_keyspace(name))
+            .map(|(name, keyspace)| (name.clone(), keyspace.clone()))
+            .collect();
+
+        Schema(
+            self.own_keyspaces(keyspaces)
+                .into_iter()
+                .map(|(name, mut keyspace)| {
+                    keyspace.name.clone_from(&name);
+                    for table in keyspace.tables.values_mut() {
+                        table.keyspace.clone_from(&name);
+                    }
+                    for ty in keyspace.user_defined_types.values_mut() {
+                        ty.keyspace.clone_from(&name);
+                    }
+                    (name, keyspace)
+                })
+                .collect(),
+        )
+    }
+
+    pub fn set_tombstone_retention(&self, retention: Duration) {
+        self.engine_mut().data.set_tombstone_retention(retention);
+    }
+
+    pub fn set_prepared_statements_capacity(&self, capacity: NonZeroUsize) {
+        self.engine_mut().set_prepared_statements_capacity(capacity);
+        self.shared.result_metadata.lock().unwrap().resize(capacity);
+    }
+
+    pub fn system_views(&self) -> SystemViews {
+        self.engine().system_views().clone()
+    }
+
+    /// Server state `system_views` tables are answered from
+    pub fn set_system_views(&self, views: SystemViews) {
+        self.engine_mut().set_system_views(views);
+    }
+
+    /// Purges tombstones older than the retention period, returns how many of them were purged.
+    pub fn compact(&self) -> usize {
+        self.engine_mut()
+            .data
+            .compact(self.time_provider().now_micros())
+            .expect("In-memory compaction is infallible")
     }
 }
 
-fn init_session() -> Plan {
-    Plan::Insert(InsertNode {
+fn init_session(config: &SessionConfig) -> Vec<Plan> {
+    let topology = &config.topology;
+    let local = Plan::Insert(InsertNode {
         keyspace: "system".to_string(),
         table: "local".to_string(),
-        partition_key: PartitionKeyValue::Simple(CqlValue::Text("local".to_owned())),
+        partition_key: PartitionKeyValue::Simple(CqlValue::Text("local".into())),
         clustering_key: ClusteringKeyValue::Empty,
-        values: vec![
+        timestamp: write_timestamp(),
+        values: cells([
             ("key".to_owned(), "local".to_owned().into()),
             ("bootstrapped".to_owned(), "COMPLETED".to_owned().into()),
             (
                 "broadcast_address".to_owned(),
-                CqlValue::Inet(IpAddr::from([127, 0, 0, 1])),
+                CqlValue::Inet(topology.address(0)),
+            ),
+            (
+                "cluster_name".to_owned(),
+                config.cluster_name.clone().into(),
             ),
-            ("cluster_name".to_owned(), "Test Cluster".to_owned().into()),
-            ("data_center".to_owned(), "datacenter1".to_owned().into()),
+            ("data_center".to_owned(), config.datacenter.clone().into()),
             ("gossip_generation".to_owned(), CqlValue::Int(1683509222)),
             (
                 "listen_address".to_owned(),
-                CqlValue::Inet(IpAddr::from([127, 0, 0, 1])),
+                CqlValue::Inet(topology.address(0)),
             ),
             ("native_protocol_version".to_owned(), "4".to_owned().into()),
-            ("rack".to_owned(), "rack".to_owned().into()),
-            ("release_version".to_owned(), "3.0.0".to_owned().into()),
-            ("cql_version".to_owned(), "4.1.0".to_owned().into()),
+            ("rack".to_owned(), config.rack.clone().into()),
             (
-                "host_id".to_owned(),
-                CqlValue::Uuid(uuid! {"aa1f1ae0-469d-4abf-ae3f-ecb7a17132fe"}),
+                "release_version".to_owned(),
+                config.release_version.clone().into(),
             ),
             (
-                "schema_version".to_owned(),
-                CqlValue::Uuid(uuid! {"0b1c3252-f787-4099-8594-157323b71789"}),
+                "cql_version".to_owned(),
+                config
+                    .profile
+                    .map_or("4.1.0", CompatibilityProfile::cql_version)
+                    .to_owned()
+                    .into(),
             ),
+            ("host_id".to_owned(), CqlValue::Uuid(config.host_id(0))),
+            ("schema_version".to_owned(), CqlValue::Uuid(SCHEMA_VERSION)),
             (
                 "rpc_address".to_owned(),
-                CqlValue::Inet(IpAddr::from([127, 0, 0, 1])),
+                CqlValue::Inet(topology.address(0)),
+            ),
+            (
+                "partitioner".to_owned(),
+                Murmur3Partitioner::NAME.to_owned().into(),
             ),
+            ("tokens".to_owned(), tokens(config, 0)),
+        ]),
+    });
+
+    let peers = (1..=topology.peers).flat_map(|node| {
+        let address = CqlValue::Inet(topology.address(node));
+        let common = [
+            ("data_center".to_owned(), config.datacenter.clone().into()),
+            ("host_id".to_owned(), CqlValue::Uuid(config.host_id(node))),
+            ("preferred_ip".to_owned(), address.clone()),
+            ("rack".to_owned(), config.rack.clone().into()),
             (
-                "tokens".to_owned(),
-                CqlValue::Set(vec!["hello".to_owned().into()]),
+                "release_version".to_owned(),
+                config.release_version.clone().into(),
             ),
-        ],
-    })
+            ("schema_version".to_owned(), CqlValue::Uuid(SCHEMA_VERSION)),
+            ("tokens".to_owned(), tokens(config, node)),
+        ];
+
+        let peer = Plan::Insert(InsertNode {
+            keyspace: "system".to_string(),
+            table: "peers".to_string(),
+            partition_key: PartitionKeyValue::Simple(address.clone()),
+            clustering_key: ClusteringKeyValue::Empty,
+            timestamp: write_timestamp(),
+            values: cells(common.iter().cloned().chain([
+                ("peer".to_owned(), address.clone()),
+                ("rpc_address".to_owned(), address.clone()),
+            ])),
+        });
+        let peer_v2 = Plan::Insert(InsertNode {
+            keyspace: "system".to_string(),
+            table: "peers_v2".to_string(),
+            partition_key: PartitionKeyValue::Simple(address.clone()),
+            clustering_key: ClusteringKeyValue::Simple(Some(CqlValue::Int(7000))),
+            timestamp: write_timestamp(),
+            values: cells(common.into_iter().chain([
+                ("peer".to_owned(), address.clone()),
+                ("peer_port".to_owned(), CqlValue::Int(7000)),
+                ("preferred_port".to_owned(), CqlValue::Int(7000)),
+                ("native_address".to_owned(), address.clone()),
+                (
+                    "native_port".to_owned(),
+                    CqlValue::Int(topology.native_port.into()),
+                ),
+            ])),
+        });
+
+        [peer, peer_v2]
+    });
+
+    let superuser = Plan::Auth(AuthNode::CreateRole {
+        role: DEFAULT_ROLE.to_owned(),
+        ignore_existence: true,
+        login: true,
+        superuser: true,
+        salted_hash: Some(auth::hash_password(DEFAULT_ROLE)),
+        timestamp: write_timestamp(),
+    });
+
+    std::iter::once(local)
+        .chain(peers)
+        .chain(std::iter::once(superuser))
+        .collect()
+}
+
+fn cells(values: impl IntoIterator<Item = (String, CqlValue)>) -> Vec<(String, Option<CqlValue>)> {
+    values
+        .into_iter()
+        .map(|(column, value)| (column, Some(value)))
+        .collect()
+}
+
+fn tokens(config: &SessionConfig, node: usize) -> CqlValue {
+    CqlValue::Set(
+        config
+            .tokens(node)
+            .iter()
+            .map(|it| it.to_string().into())
+            .collect(),
+    )
 }
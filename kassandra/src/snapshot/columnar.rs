@@ -0,0 +1,254 @@
+//! Arrow record batches and parquet files of table snapshots, for inspecting captured data with analytics tools.
+//!
+//! Column types are told by the values of a column, every value has to be of the same kind:
+//! scalars map onto the matching arrow types, while decimals, varints, uuids and inets are written as strings
+//! and collections, tuples, user defined types or columns mixing kinds as json strings.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{
+    types::IntervalMonthDayNanoType, ArrayRef, BinaryArray, BooleanArray, Date32Array,
+    Float32Array, Float64Array, Int16Array, Int32Array, Int64Array, Int8Array,
+    IntervalMonthDayNanoArray, RecordBatch, RecordBatchOptions, StringArray, Time64NanosecondArray,
+    TimestampMillisecondArray,
+};
+use arrow_schema::{ArrowError, DataType, Field, IntervalUnit, Schema, TimeUnit};
+use eyre::Context;
+use parquet::arrow::ArrowWriter;
+
+use super::{DataSnapshots, TableDataSnapshot, ValueSnapshot};
+use crate::cql::{json, value::CqlValue};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Utf8,
+    Boolean,
+    Binary,
+    Int64,
+    Int32,
+    Int16,
+    Int8,
+    Float64,
+    Float32,
+    Date,
+    Timestamp,
+    Time,
+    Duration,
+    Json,
+}
+
+impl Kind {
+    /// `None` for nulls and empty values, they fit any kind
+    fn of(value: &ValueSnapshot) -> Option<Self> {
+        Some(match value {
+            ValueSnapshot::Ascii(_)
+            | ValueSnapshot::Text(_)
+            | ValueSnapshot::Decimal(_)
+            | ValueSnapshot::Varint(_)
+            | ValueSnapshot::Inet(_)
+            | ValueSnapshot::Uuid(_)
+            | ValueSnapshot::Timeuuid(_) => Kind::Utf8,
+            ValueSnapshot::Boolean(_) => Kind::Boolean,
+            ValueSnapshot::Blob(_) => Kind::Binary,
+            ValueSnapshot::Counter(_) | ValueSnapshot::BigInt(_) => Kind::Int64,
+            ValueSnapshot::Int(_) => Kind::Int32,
+            ValueSnapshot::SmallInt(_) => Kind::Int16,
+            ValueSnapshot::TinyInt(_) => Kind::Int8,
+            ValueSnapshot::Double(_) => Kind::Float64,
+            ValueSnapshot::Float(_) => Kind::Float32,
+            ValueSnapshot::Date(_) => Kind::Date,
+            ValueSnapshot::Timestamp(_) => Kind::Timestamp,
+            ValueSnapshot::Time(_) => Kind::Time,
+            ValueSnapshot::Duration(_) => Kind::Duration,
+            ValueSnapshot::List(_)
+            | ValueSnapshot::Set(_)
+            | ValueSnapshot::Map(_)
+            | ValueSnapshot::Tuple(_)
+            | ValueSnapshot::UserDefinedType { .. } => Kind::Json,
+            ValueSnapshot::Empty | ValueSnapshot::Null => return None,
+        })
+    }
+
+    fn data_type(self) -> DataType {
+        match self {
+            Kind::Utf8 | Kind::Json => DataType::Utf8,
+            Kind::Boolean => DataType::Boolean,
+            Kind::Binary => DataType::Binary,
+            Kind::Int64 => DataType::Int64,
+            Kind::Int32 => DataType::Int32,
+            Kind::Int16 => DataType::Int16,
+            Kind::Int8 => DataType::Int8,
+            Kind::Float64 => DataType::Float64,
+            Kind::Float32 => DataType::Float32,
+            Kind::Date => DataType::Date32,
+            Kind::Timestamp => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            Kind::Time => DataType::Time64(TimeUnit::Nanosecond),
+            Kind::Duration => DataType::Interval(IntervalUnit::MonthDayNano),
+        }
+    }
+}
+
+impl TableDataSnapshot {
+    /// Rows as a record batch with a nullable column per cql column, in the order of their names.
+    ///
+    /// Columns without a single value are left as nulls of `Utf8`.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        let mut names = self
+            .rows
+            .iter()
+            .flat_map(|row| row.data.keys())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        let mut fields = vec![];
+        let mut columns = vec![];
+        for name in names {
+            let values = self
+                .rows
+                .iter()
+                .map(|row| row.data.get(name).unwrap_or(&ValueSnapshot::Null))
+                .collect::<Vec<_>>();
+            let mut kinds = values.iter().filter_map(|it| Kind::of(it));
+            let kind = match kinds.next() {
+                Some(kind) if kinds.all(|it| it == kind) => kind,
+                Some(_) => Kind::Json,
+                None => Kind::Utf8,
+            };
+
+            fields.push(Field::new(name, kind.data_type(), true));
+            columns.push(column(kind, &values)?);
+        }
+
+        let options = RecordBatchOptions::new().with_row_count(Some(self.rows.len()));
+        RecordBatch::try_new_with_options(Arc::new(Schema::new(fields)), columns, &options)
+    }
+}
+
+impl DataSnapshots {
+    /// Writes every table with rows into `<dir>/<keyspace>/<table>.parquet`, returns the files written
+    pub fn to_parquet(&self, dir: impl AsRef<Path>) -> eyre::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        let mut written = vec![];
+
+        for (keyspace, snapshot) in &self.0 {
+            let tables = snapshot
+                .tables
+                .iter()
+                .filter(|(_, table)| !table.rows.is_empty());
+
+            for (table, data) in tables {
+                let keyspace_dir = dir.join(keyspace);
+                std::fs::create_dir_all(&keyspace_dir)
+                    .with_context(|| format!("creating {}", keyspace_dir.display()))?;
+
+                let path = keyspace_dir.join(format!("{table}.parquet"));
+                let batch = data
+                    .to_record_batch()
+                    .with_context(|| format!("converting {keyspace}.{table}"))?;
+                let file =
+                    File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+
+                let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+                writer.write(&batch)?;
+                writer.close()?;
+
+                written.push(path);
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+fn column(kind: Kind, values: &[&ValueSnapshot]) -> Result<ArrayRef, ArrowError> {
+    // values are of the column kind, or nulls and empty values
+    macro_rules! array {
+        ($array:ty, |$value:ident| $convert:expr) => {
+            values.iter().map(|$value| $convert).collect::<$array>()
+        };
+    }
+
+    Ok(match kind {
+        Kind::Utf8 => Arc::new(array!(StringArray, |value| match value {
+            ValueSnapshot::Ascii(v) | ValueSnapshot::Text(v) => Some(v.clone()),
+            ValueSnapshot::Decimal(v) => Some(v.to_string()),
+            ValueSnapshot::Varint(v) => Some(v.to_string()),
+            ValueSnapshot::Inet(v) => Some(v.to_string()),
+            ValueSnapshot::Uuid(v) | ValueSnapshot::Timeuuid(v) => Some(v.to_string()),
+            ValueSnapshot::Empty => Some(String::new()),
+            _ => None,
+        })),
+        Kind::Json => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    ValueSnapshot::Null => None,
+                    value => Some(json::to_json(&CqlValue::from((*value).clone()))),
+                })
+                .collect::<Vec<_>>();
+            Arc::new(StringArray::from(values))
+        }
+        Kind::Boolean => Arc::new(array!(BooleanArray, |value| match value {
+            ValueSnapshot::Boolean(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Binary => Arc::new(array!(BinaryArray, |value| match value {
+            ValueSnapshot::Blob(v) => Some(v.as_slice()),
+            ValueSnapshot::Empty => Some(&[][..]),
+            _ => None,
+        })),
+        Kind::Int64 => Arc::new(array!(Int64Array, |value| match value {
+            ValueSnapshot::Counter(v) | ValueSnapshot::BigInt(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Int32 => Arc::new(array!(Int32Array, |value| match value {
+            ValueSnapshot::Int(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Int16 => Arc::new(array!(Int16Array, |value| match value {
+            ValueSnapshot::SmallInt(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Int8 => Arc::new(array!(Int8Array, |value| match value {
+            ValueSnapshot::TinyInt(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Float64 => Arc::new(array!(Float64Array, |value| match value {
+            ValueSnapshot::Double(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Float32 => Arc::new(array!(Float32Array, |value| match value {
+            ValueSnapshot::Float(v) => Some(*v),
+            _ => None,
+        })),
+        // cql dates count days from 2^31 rather than from the epoch
+        Kind::Date => Arc::new(array!(Date32Array, |value| match value {
+            ValueSnapshot::Date(v) => Some((i64::from(*v) - (1 << 31)) as i32),
+            _ => None,
+        })),
+        Kind::Timestamp => {
+            let array = array!(TimestampMillisecondArray, |value| match value {
+                ValueSnapshot::Timestamp(v) => Some(*v),
+                _ => None,
+            });
+            Arc::new(array.with_timezone("UTC"))
+        }
+        Kind::Time => Arc::new(array!(Time64NanosecondArray, |value| match value {
+            ValueSnapshot::Time(v) => Some(*v),
+            _ => None,
+        })),
+        Kind::Duration => Arc::new(array!(IntervalMonthDayNanoArray, |value| match value {
+            ValueSnapshot::Duration(v) => Some(IntervalMonthDayNanoType::make_value(
+                v.months,
+                v.days,
+                v.nanoseconds
+            )),
+            _ => None,
+        })),
+    })
+}
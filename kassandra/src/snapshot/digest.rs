@@ -0,0 +1,58 @@
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{cql::schema::Schema, snapshot::DataSnapshots};
+
+/// Tags every digest, it changes whenever the canonical form does, so digests of different forms never match
+const DIGEST_VERSION: &str = "kassandra-state-digest/1";
+
+/// SHA-256 of the schema and data in a canonical form: maps are ordered by their keys,
+/// whatever order they are kept in, and write times are left out, like they are from snapshots.
+pub fn state_digest(schema: &Schema, data: &DataSnapshots) -> [u8; 32] {
+    #[derive(Serialize)]
+    struct State<'a> {
+        schema: &'a Schema,
+        data: &'a DataSnapshots,
+    }
+
+    let state = serde_json::to_value(State { schema, data }).expect("Snapshots serialize to json");
+    let mut hasher = Sha256::new();
+    hasher.update(DIGEST_VERSION);
+    hasher.update([0]);
+    canonical(&state, &mut hasher);
+
+    hasher.finalize().into()
+}
+
+/// Feeds the value as json with object keys sorted
+fn canonical(value: &Value, hasher: &mut Sha256) {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+
+            hasher.update("{");
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    hasher.update(",");
+                }
+                hasher.update(Value::from(key.as_str()).to_string());
+                hasher.update(":");
+                canonical(value, hasher);
+            }
+            hasher.update("}");
+        }
+        Value::Array(items) => {
+            hasher.update("[");
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    hasher.update(",");
+                }
+                canonical(item, hasher);
+            }
+            hasher.update("]");
+        }
+        scalar => hasher.update(scalar.to_string()),
+    }
+}
@@ -2,10 +2,19 @@ use std::collections::BTreeMap;
 
 use serde::Serialize;
 
-use crate::storage::memory::{Keyspace, Table};
+use crate::{
+    cql::schema::system::is_system_keyspace,
+    storage::memory::{Keyspace, KeyspaceTombstones, Table, Tombstones},
+};
 
+#[cfg(feature = "parquet")]
+mod columnar;
+mod digest;
+mod stats;
 mod value;
 
+pub use digest::state_digest;
+pub use stats::{KeyspaceStats, StatsSnapshot, TableStats};
 pub use value::ValueSnapshot;
 
 #[derive(Debug, Serialize)]
@@ -19,11 +28,40 @@ impl DataSnapshots {
         Self(
             keyspaces
                 .into_iter()
-                .filter(|(name, _)| name.as_str() != "system" && name.as_str() != "system_schema")
+                .filter(|(name, _)| !is_system_keyspace(name))
                 .map(|(name, keyspace)| (name.clone(), keyspace.into()))
                 .collect(),
         )
     }
+
+    /// Adds tombstones to table snapshots, tables which only have tombstones left are included as well.
+    pub fn with_tombstones<'a>(
+        mut self,
+        keyspaces: impl IntoIterator<Item = (&'a String, &'a KeyspaceTombstones)>,
+    ) -> Self {
+        let keyspaces = keyspaces
+            .into_iter()
+            .filter(|(name, _)| !is_system_keyspace(name));
+
+        for (name, tables) in keyspaces {
+            for (table, tombstones) in tables.iter().filter(|(_, it)| !it.is_empty()) {
+                self.0
+                    .entry(name.clone())
+                    .or_insert_with(|| KeyspaceSnapshot {
+                        tables: BTreeMap::new(),
+                    })
+                    .tables
+                    .entry(table.clone())
+                    .or_insert_with(|| TableDataSnapshot {
+                        rows: vec![],
+                        tombstones: vec![],
+                    })
+                    .tombstones = tombstones_snapshot(tombstones);
+            }
+        }
+
+        self
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -37,7 +75,7 @@ impl<'a> From<&'a Keyspace> for KeyspaceSnapshot {
             tables: value
                 .iter()
                 .filter(|(_, table)| !table.is_empty())
-                .map(|(key, table)| (key.clone(), table.into()))
+                .map(|(key, table)| (key.clone(), (&**table).into()))
                 .collect(),
         }
     }
@@ -46,13 +84,22 @@ impl<'a> From<&'a Keyspace> for KeyspaceSnapshot {
 #[derive(Debug, Serialize)]
 pub struct TableDataSnapshot {
     pub rows: Vec<Row>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tombstones: Vec<TombstoneSnapshot>,
 }
 
 impl<'a> From<&'a Table> for TableDataSnapshot {
     fn from(value: &'a Table) -> Self {
         let mut rows = Vec::new();
 
-        for (partition_key, entries) in value.iter() {
+        // snapshots are ordered by partition keys rather than tokens to keep them readable
+        let partitions = value
+            .partitions
+            .values()
+            .flatten()
+            .collect::<BTreeMap<_, _>>();
+
+        for (partition_key, entries) in partitions {
             for (clustering_key, data) in entries {
                 let partition_key = partition_key.clone().into();
                 let clustering_key = clustering_key.clone().into();
@@ -60,9 +107,9 @@ impl<'a> From<&'a Table> for TableDataSnapshot {
                 let row = Row {
                     partition_key,
                     clustering_key,
-                    data: data
-                        .iter()
-                        .map(|(k, v)| (k.clone(), v.clone().into()))
+                    data: value
+                        .cells(data)
+                        .map(|(k, cell)| (k.clone(), cell.value.clone().into()))
                         .collect(),
                 };
 
@@ -70,13 +117,33 @@ impl<'a> From<&'a Table> for TableDataSnapshot {
             }
         }
 
-        Self { rows }
+        Self {
+            rows,
+            tombstones: vec![],
+        }
     }
 }
 
+// deletion time is left out, so snapshots stay the same between runs
+fn tombstones_snapshot(tombstones: &Tombstones) -> Vec<TombstoneSnapshot> {
+    tombstones
+        .keys()
+        .map(|(partition_key, clustering_key)| TombstoneSnapshot {
+            partition_key: partition_key.clone().into(),
+            clustering_key: clustering_key.clone().into(),
+        })
+        .collect()
+}
+
 #[derive(Debug, Serialize)]
 pub struct Row {
     pub partition_key: ValueSnapshot,
     pub clustering_key: ValueSnapshot,
     pub data: BTreeMap<String, ValueSnapshot>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct TombstoneSnapshot {
+    pub partition_key: ValueSnapshot,
+    pub clustering_key: ValueSnapshot,
+}
@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    cql::schema::system::is_system_keyspace,
+    frame::write,
+    storage::memory::{Keyspace, Table},
+};
+
+/// Usage report of the stored data, sizes are approximated by the serialized size of cells.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct StatsSnapshot(pub BTreeMap<String, KeyspaceStats>);
+
+impl StatsSnapshot {
+    pub fn from_keyspaces<'a>(
+        keyspaces: impl IntoIterator<Item = (&'a String, &'a Keyspace)>,
+    ) -> Self {
+        Self(
+            keyspaces
+                .into_iter()
+                .filter(|(name, _)| !is_system_keyspace(name))
+                .map(|(name, keyspace)| (name.clone(), keyspace.into()))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct KeyspaceStats {
+    pub partitions: usize,
+    pub rows: usize,
+    pub bytes: usize,
+    pub tables: BTreeMap<String, TableStats>,
+}
+
+impl<'a> From<&'a Keyspace> for KeyspaceStats {
+    fn from(value: &'a Keyspace) -> Self {
+        let tables = value
+            .iter()
+            .map(|(name, table)| (name.clone(), TableStats::from(&**table)))
+            .collect::<BTreeMap<_, _>>();
+
+        Self {
+            partitions: tables.values().map(|it| it.partitions).sum(),
+            rows: tables.values().map(|it| it.rows).sum(),
+            bytes: tables.values().map(|it| it.bytes).sum(),
+            tables,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TableStats {
+    pub partitions: usize,
+    pub rows: usize,
+    pub bytes: usize,
+}
+
+impl<'a> From<&'a Table> for TableStats {
+    fn from(value: &'a Table) -> Self {
+        let mut stats = Self::default();
+
+        for partition in value.partitions.values().flat_map(|it| it.values()) {
+            stats.partitions += 1;
+            stats.rows += partition.len();
+            stats.bytes += partition
+                .values()
+                .flat_map(|row| row.values())
+                .map(|cell| write::opt_cql_value_size(cell.value.as_ref()))
+                .sum::<usize>();
+        }
+
+        stats
+    }
+}
@@ -29,8 +29,10 @@ pub enum ValueSnapshot {
     Int(i32),
     BigInt(i64),
     Text(String),
+    /// Milliseconds since unix epoch, written as rfc3339 date
     #[from(ignore)]
-    Timestamp(String),
+    #[serde(serialize_with = "rfc3339")]
+    Timestamp(i64),
     Inet(IpAddr),
     List(Vec<ValueSnapshot>),
     Map(#[serde(serialize_with = "as_map")] Vec<(ValueSnapshot, ValueSnapshot)>),
@@ -51,16 +53,36 @@ pub enum ValueSnapshot {
     Tuple(Vec<ValueSnapshot>),
     Uuid(Uuid),
     Varint(BigInt),
-    #[from(types(()))]
+    /// Zero-length value, rendered as an empty string so it stays apart from nulls
+    #[serde(serialize_with = "empty")]
     Empty,
+    #[from(types(()))]
+    Null,
+}
+
+fn empty<S: serde::Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("")
+}
+
+fn rfc3339<S: serde::Serializer>(millis: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    match chrono::DateTime::from_timestamp_millis(*millis) {
+        Some(timestamp) => serializer.serialize_str(&timestamp.to_rfc3339()),
+        None => serializer.serialize_i64(*millis),
+    }
+}
+
+impl From<Option<CqlValue>> for ValueSnapshot {
+    fn from(value: Option<CqlValue>) -> Self {
+        value.map(Into::into).unwrap_or(ValueSnapshot::Null)
+    }
 }
 
 impl From<CqlValue> for ValueSnapshot {
     fn from(value: CqlValue) -> Self {
         match value {
-            CqlValue::Ascii(v) => ValueSnapshot::Ascii(v),
+            CqlValue::Ascii(v) => ValueSnapshot::Ascii(v.into()),
             CqlValue::Boolean(v) => ValueSnapshot::Boolean(v),
-            CqlValue::Blob(v) => ValueSnapshot::Blob(v),
+            CqlValue::Blob(v) => ValueSnapshot::Blob(v.into()),
             CqlValue::Counter(v) => ValueSnapshot::Counter(v),
             CqlValue::Decimal(v) => ValueSnapshot::Decimal(v),
             CqlValue::Date(v) => ValueSnapshot::Date(v),
@@ -69,13 +91,8 @@ impl From<CqlValue> for ValueSnapshot {
             CqlValue::Float(v) => ValueSnapshot::Float(f32::from_be_bytes(v.to_be_bytes())),
             CqlValue::Int(v) => ValueSnapshot::Int(v),
             CqlValue::BigInt(v) => ValueSnapshot::BigInt(v),
-            CqlValue::Text(v) => ValueSnapshot::Text(v),
-            CqlValue::Timestamp(v) => ValueSnapshot::Timestamp(
-                chrono::NaiveDateTime::from_timestamp_millis(v)
-                    .unwrap()
-                    .and_utc()
-                    .to_rfc3339(),
-            ),
+            CqlValue::Text(v) => ValueSnapshot::Text(v.into()),
+            CqlValue::Timestamp(v) => ValueSnapshot::Timestamp(v),
             CqlValue::Inet(v) => ValueSnapshot::Inet(v),
             CqlValue::List(v) => {
                 ValueSnapshot::List(v.into_iter().map(ValueSnapshot::from).collect())
@@ -92,9 +109,15 @@ impl From<CqlValue> for ValueSnapshot {
             CqlValue::TinyInt(v) => ValueSnapshot::TinyInt(v),
             CqlValue::Time(v) => ValueSnapshot::Time(v),
             CqlValue::Timeuuid(v) => ValueSnapshot::Timeuuid(v),
-            CqlValue::Tuple(v) => {
-                ValueSnapshot::Tuple(v.into_iter().map(ValueSnapshot::from).collect())
-            }
+            // empty tuple elements are nulls
+            CqlValue::Tuple(v) => ValueSnapshot::Tuple(
+                v.into_iter()
+                    .map(|it| match it {
+                        CqlValue::Empty => ValueSnapshot::Null,
+                        it => it.into(),
+                    })
+                    .collect(),
+            ),
             CqlValue::Uuid(v) => ValueSnapshot::Uuid(v),
             CqlValue::Varint(v) => ValueSnapshot::Varint(v),
             CqlValue::UserDefinedType {
@@ -114,16 +137,64 @@ impl From<CqlValue> for ValueSnapshot {
     }
 }
 
-impl From<ClusteringKeyValue> for ValueSnapshot {
-    fn from(value: ClusteringKeyValue) -> Self {
+/// Snapshots keep everything of values, they can be turned back into them
+impl From<ValueSnapshot> for CqlValue {
+    fn from(value: ValueSnapshot) -> Self {
+        let values = |values: Vec<ValueSnapshot>| values.into_iter().map(CqlValue::from).collect();
         match value {
-            ClusteringKeyValue::Simple(v) => v.map(Into::into).unwrap_or(ValueSnapshot::Empty),
-            ClusteringKeyValue::Composite(vs) => ValueSnapshot::Tuple(
-                vs.into_iter()
-                    .map(|it| it.map(Into::into).unwrap_or(ValueSnapshot::Empty))
+            ValueSnapshot::Ascii(v) => CqlValue::Ascii(v.into()),
+            ValueSnapshot::Boolean(v) => CqlValue::Boolean(v),
+            ValueSnapshot::Blob(v) => CqlValue::Blob(v.into()),
+            ValueSnapshot::Counter(v) => CqlValue::Counter(v),
+            ValueSnapshot::Decimal(v) => CqlValue::Decimal(v),
+            ValueSnapshot::Date(v) => CqlValue::Date(v),
+            ValueSnapshot::Double(v) => CqlValue::Double(v.to_bits()),
+            ValueSnapshot::Duration(v) => CqlValue::Duration(v),
+            ValueSnapshot::Float(v) => CqlValue::Float(v.to_bits()),
+            ValueSnapshot::Int(v) => CqlValue::Int(v),
+            ValueSnapshot::BigInt(v) => CqlValue::BigInt(v),
+            ValueSnapshot::Text(v) => CqlValue::Text(v.into()),
+            ValueSnapshot::Timestamp(v) => CqlValue::Timestamp(v),
+            ValueSnapshot::Inet(v) => CqlValue::Inet(v),
+            ValueSnapshot::List(v) => CqlValue::List(values(v)),
+            ValueSnapshot::Map(v) => CqlValue::Map(
+                v.into_iter()
+                    .map(|(k, v)| (CqlValue::from(k), CqlValue::from(v)))
                     .collect(),
             ),
-            ClusteringKeyValue::Empty => ValueSnapshot::Empty,
+            ValueSnapshot::Set(v) => CqlValue::Set(values(v)),
+            ValueSnapshot::UserDefinedType {
+                keyspace,
+                type_name,
+                fields,
+            } => CqlValue::UserDefinedType {
+                keyspace,
+                type_name,
+                fields: fields
+                    .into_iter()
+                    .map(|(n, v)| (n, v.map(CqlValue::from)))
+                    .collect(),
+            },
+            ValueSnapshot::SmallInt(v) => CqlValue::SmallInt(v),
+            ValueSnapshot::TinyInt(v) => CqlValue::TinyInt(v),
+            ValueSnapshot::Time(v) => CqlValue::Time(v),
+            ValueSnapshot::Timeuuid(v) => CqlValue::Timeuuid(v),
+            ValueSnapshot::Tuple(v) => CqlValue::Tuple(values(v)),
+            ValueSnapshot::Uuid(v) => CqlValue::Uuid(v),
+            ValueSnapshot::Varint(v) => CqlValue::Varint(v),
+            ValueSnapshot::Empty | ValueSnapshot::Null => CqlValue::Empty,
+        }
+    }
+}
+
+impl From<ClusteringKeyValue> for ValueSnapshot {
+    fn from(value: ClusteringKeyValue) -> Self {
+        match value {
+            ClusteringKeyValue::Simple(v) => v.into(),
+            ClusteringKeyValue::Composite(vs) => {
+                ValueSnapshot::Tuple(vs.into_iter().map(Into::into).collect())
+            }
+            ClusteringKeyValue::Empty => ValueSnapshot::Null,
         }
     }
 }
@@ -135,7 +206,7 @@ impl From<PartitionKeyValue> for ValueSnapshot {
             PartitionKeyValue::Composite(vs) => {
                 ValueSnapshot::Tuple(vs.into_iter().map(|it| it.into()).collect())
             }
-            PartitionKeyValue::Empty => ValueSnapshot::Empty,
+            PartitionKeyValue::Empty => ValueSnapshot::Null,
         }
     }
 }
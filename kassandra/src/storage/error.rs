@@ -0,0 +1,50 @@
+use thiserror::Error;
+
+use crate::{error::DbError, frame::response::error::Error as CqlError};
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Keyspace {0} does not exist")]
+    KeyspaceDoesNotExist(String),
+
+    #[error("Table {keyspace}.{table} does not exist")]
+    TableDoesNotExist { keyspace: String, table: String },
+
+    #[error("Storage is corrupted: {0}")]
+    Corrupted(String),
+
+    #[error("Storage io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl StorageError {
+    pub fn table_does_not_exist(keyspace: &str, table: &str) -> Self {
+        Self::TableDoesNotExist {
+            keyspace: keyspace.to_owned(),
+            table: table.to_owned(),
+        }
+    }
+}
+
+impl From<&StorageError> for DbError {
+    fn from(value: &StorageError) -> Self {
+        match value {
+            StorageError::KeyspaceDoesNotExist(_) | StorageError::TableDoesNotExist { .. } => {
+                DbError::Invalid
+            }
+            StorageError::Corrupted(_) | StorageError::Io(_) => DbError::ServerError,
+        }
+    }
+}
+
+impl From<StorageError> for DbError {
+    fn from(value: StorageError) -> Self {
+        DbError::from(&value)
+    }
+}
+
+impl From<StorageError> for CqlError {
+    fn from(value: StorageError) -> Self {
+        CqlError::new(DbError::from(&value), value.to_string())
+    }
+}
@@ -1,69 +1,616 @@
 use std::{
+    cmp::Ordering,
     collections::{BTreeMap, HashMap},
-    ops::RangeBounds,
+    ops::Bound,
+    sync::Arc,
+    time::Duration,
 };
 
-use eyre::eyre;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use smallvec::SmallVec;
 
-use super::RowEntry;
+use super::{Predicate, Result, RowEntry, StorageError, Tombstone};
 use crate::{
-    cql::value::{ClusteringKeyValue, CqlValue, PartitionKeyValue},
-    snapshot::DataSnapshots,
+    cql::{
+        partitioner::{Murmur3Partitioner, Partitioner},
+        value::{
+            ClusteringKeyValue, ClusteringKeyValueRange, CqlValue, PartitionKeyValue,
+            PartitionKeyValueRange,
+        },
+    },
+    frame::write,
+    snapshot::{DataSnapshots, StatsSnapshot},
 };
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
-pub struct Memory {
+/// Same as the default `gc_grace_seconds` in Cassandra.
+pub const DEFAULT_TOMBSTONE_RETENTION: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+
+/// Tables and their tombstones are shared by clones until one of them writes to the table,
+/// which copies it first, so cloning the storage is cheap however much data it holds.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Memory<P: Partitioner = Murmur3Partitioner> {
     pub(crate) data: HashMap<String, Keyspace>,
+    #[serde(default)]
+    pub(crate) tombstones: HashMap<String, KeyspaceTombstones>,
+    #[serde(skip, default = "default_tombstone_retention")]
+    tombstone_retention: Duration,
+    #[serde(skip)]
+    partitioner: P,
+}
+
+impl<P: Partitioner> Default for Memory<P> {
+    fn default() -> Self {
+        Self {
+            data: Default::default(),
+            tombstones: Default::default(),
+            tombstone_retention: DEFAULT_TOMBSTONE_RETENTION,
+            partitioner: Default::default(),
+        }
+    }
+}
+
+fn default_tombstone_retention() -> Duration {
+    DEFAULT_TOMBSTONE_RETENTION
+}
+
+pub(crate) type Keyspace = HashMap<String, Arc<Table>>;
+pub(crate) type Partition = BTreeMap<ClusteringKeyValue, RowValues>;
+/// Cells of a row keyed by their column names, as rows are persisted and dumped
+pub(crate) type NamedCells = BTreeMap<String, Cell>;
+pub(crate) type KeyspaceTombstones = HashMap<String, Arc<Tombstones>>;
+/// Empty clustering key marks a tombstone of the whole partition.
+pub(crate) type Tombstones = BTreeMap<(PartitionKeyValue, ClusteringKeyValue), Tombstone>;
+
+/// Column names are interned per table, rows keep their cells at the positions of their columns in `columns`,
+/// so a name is stored once rather than in every row.
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    columns: Vec<String>,
+    /// Partitions are grouped by token first, so tables are iterated in the token order.
+    pub(crate) partitions: BTreeMap<i64, BTreeMap<PartitionKeyValue, Partition>>,
+}
+
+/// Cells of a row indexed by column ids of its table, `None` for columns the row has no cell of
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RowValues(SmallVec<[Option<Cell>; 4]>);
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Cell {
+    /// Null cells are kept, so they shadow older writes
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "nullable")]
+    pub value: Option<CqlValue>,
+    /// Write timestamp in microseconds, used to resolve conflicting writes
+    pub timestamp: i64,
+}
+
+impl Cell {
+    /// Whether the cell wins over `other` written to the same column, the way Cassandra reconciles them:
+    /// the newer write wins, ties go to nulls and then to the greater serialized value,
+    /// so writes applied in any order end up the same.
+    fn supersedes(&self, other: &Cell) -> bool {
+        match self.timestamp.cmp(&other.timestamp) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => match (&self.value, &other.value) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(value), Some(other)) => serialized(value) >= serialized(other),
+            },
+        }
+    }
+}
+
+fn serialized(value: &CqlValue) -> Vec<u8> {
+    let mut buf = vec![];
+    write::opt_cql_value(&mut buf, Some(value));
+    // without the size prefix, values are compared by their bytes only
+    buf.split_off(4)
+}
+
+impl Table {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.partitions.is_empty()
+    }
+
+    /// Rows in the token order
+    pub(crate) fn rows(
+        &self,
+    ) -> impl Iterator<Item = (&PartitionKeyValue, &ClusteringKeyValue, &RowValues)> {
+        self.partitions
+            .values()
+            .flatten()
+            .flat_map(|(partition_key, partition)| {
+                partition
+                    .iter()
+                    .map(move |(clustering_key, row)| (partition_key, clustering_key, row))
+            })
+    }
+
+    pub(crate) fn cell<'a>(&self, row: &'a RowValues, column: &str) -> Option<&'a Cell> {
+        let id = self.columns.iter().position(|it| it == column)?;
+        row.get(id)
+    }
+
+    pub(crate) fn cells<'a>(&'a self, row: &'a RowValues) -> Cells<'a> {
+        Cells {
+            columns: &self.columns,
+            cells: row.0.iter().enumerate(),
+        }
+    }
+
+    pub(crate) fn named_cells(&self, row: &RowValues) -> NamedCells {
+        self.cells(row)
+            .map(|(column, cell)| (column.clone(), cell.clone()))
+            .collect()
+    }
+
+    fn insert(
+        &mut self,
+        token: i64,
+        partition_key: PartitionKeyValue,
+        clustering_key: ClusteringKeyValue,
+        cells: NamedCells,
+    ) {
+        let mut row = RowValues::default();
+        for (column, cell) in cells {
+            row.set(intern(&mut self.columns, column), cell);
+        }
+        self.partitions
+            .entry(token)
+            .or_default()
+            .entry(partition_key)
+            .or_default()
+            .insert(clustering_key, row);
+    }
 }
 
-pub(crate) type Keyspace = HashMap<String, Table>;
-pub(crate) type Table = BTreeMap<PartitionKeyValue, BTreeMap<ClusteringKeyValue, RowValues>>;
-pub(crate) type RowValues = BTreeMap<String, CqlValue>;
+/// Id of a column in a table, new columns get the next one
+fn intern(columns: &mut Vec<String>, column: String) -> usize {
+    match columns.iter().position(|it| *it == column) {
+        Some(id) => id,
+        None => {
+            columns.push(column);
+            columns.len() - 1
+        }
+    }
+}
+
+impl RowValues {
+    fn get(&self, id: usize) -> Option<&Cell> {
+        self.0.get(id)?.as_ref()
+    }
+
+    fn set(&mut self, id: usize, cell: Cell) {
+        if self.0.len() <= id {
+            self.0.resize(id + 1, None);
+        }
+        self.0[id] = Some(cell);
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Cell> {
+        self.0.iter().flatten()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(Option::is_none)
+    }
+}
+
+/// Cells of a row together with the names of their columns
+pub struct Cells<'a> {
+    columns: &'a [String],
+    cells: std::iter::Enumerate<std::slice::Iter<'a, Option<Cell>>>,
+}
+
+impl<'a> Iterator for Cells<'a> {
+    type Item = (&'a String, &'a Cell);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let columns = self.columns;
+        self.cells
+            .find_map(|(id, cell)| Some((&columns[id], cell.as_ref()?)))
+    }
+}
+
+/// Tables are persisted with column names in every row, as they were stored before names were interned
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.partitions.iter().map(|(token, partitions)| {
+            let partitions = partitions
+                .iter()
+                .map(|(partition_key, partition)| {
+                    let rows = partition
+                        .iter()
+                        .map(|(clustering_key, row)| (clustering_key, self.named_cells(row)))
+                        .collect::<BTreeMap<_, _>>();
+                    (partition_key, rows)
+                })
+                .collect::<BTreeMap<_, _>>();
+            (token, partitions)
+        }))
+    }
+}
+
+impl<'de> Deserialize<'de> for Table {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        type Partitions = BTreeMap<PartitionKeyValue, BTreeMap<ClusteringKeyValue, NamedCells>>;
+
+        let mut table = Table::default();
+        for (token, partitions) in BTreeMap::<i64, Partitions>::deserialize(deserializer)? {
+            for (partition_key, rows) in partitions {
+                for (clustering_key, cells) in rows {
+                    table.insert(token, partition_key.clone(), clustering_key, cells);
+                }
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+impl<P: Partitioner> Memory<P> {
+    pub fn with_tombstone_retention(mut self, retention: Duration) -> Self {
+        self.tombstone_retention = retention;
+        self
+    }
+
+    pub fn set_tombstone_retention(&mut self, retention: Duration) {
+        self.tombstone_retention = retention;
+    }
+
+    pub fn tombstone_retention(&self) -> Duration {
+        self.tombstone_retention
+    }
 
-impl Memory {
     pub fn snapshot(&self) -> DataSnapshots {
         DataSnapshots::from_keyspaces(self.data.iter())
     }
+
+    pub fn snapshot_with_tombstones(&self) -> DataSnapshots {
+        self.snapshot().with_tombstones(self.tombstones.iter())
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        StatsSnapshot::from_keyspaces(self.data.iter())
+    }
+
+    /// Tables of a keyspace with rows and tombstones listed in order
+    pub(crate) fn dump_keyspace(&self, keyspace: &str) -> BTreeMap<String, TableDump> {
+        let mut tables = BTreeMap::<String, TableDump>::new();
+
+        for (name, table) in self.data.get(keyspace).into_iter().flatten() {
+            let rows = table
+                .rows()
+                .map(|(partition_key, clustering_key, row)| RowDump {
+                    partition_key: partition_key.clone(),
+                    clustering_key: clustering_key.clone(),
+                    cells: table.named_cells(row),
+                });
+            tables.entry(name.clone()).or_default().rows.extend(rows);
+        }
+        for (name, tombstones) in self.tombstones.get(keyspace).into_iter().flatten() {
+            let tombstones =
+                tombstones
+                    .iter()
+                    .map(
+                        |((partition_key, clustering_key), tombstone)| TombstoneDump {
+                            partition_key: partition_key.clone(),
+                            clustering_key: clustering_key.clone(),
+                            deleted_at: tombstone.deleted_at,
+                            local_deletion_time: Some(tombstone.local_deletion_time),
+                        },
+                    );
+            tables
+                .entry(name.clone())
+                .or_default()
+                .tombstones
+                .extend(tombstones);
+        }
+
+        tables
+    }
+
+    /// Replaces data of a keyspace with the dumped one
+    pub(crate) fn restore_keyspace(&mut self, keyspace: &str, tables: BTreeMap<String, TableDump>) {
+        let data = self.data.entry(keyspace.to_owned()).or_default();
+        let tombstones = self.tombstones.entry(keyspace.to_owned()).or_default();
+        data.clear();
+        tombstones.clear();
+
+        for (name, dump) in tables {
+            let table = Arc::make_mut(data.entry(name.clone()).or_default());
+            for row in dump.rows {
+                let token = self.partitioner.token(&row.partition_key);
+                table.insert(token, row.partition_key, row.clustering_key, row.cells);
+            }
+
+            let table_tombstones = Arc::make_mut(tombstones.entry(name).or_default());
+            for tombstone in dump.tombstones {
+                table_tombstones.insert(
+                    (tombstone.partition_key, tombstone.clustering_key),
+                    Tombstone {
+                        deleted_at: tombstone.deleted_at,
+                        local_deletion_time: tombstone
+                            .local_deletion_time
+                            .unwrap_or(tombstone.deleted_at),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Data of a table as [`Memory::dump_keyspace`] lists it, keys are values rather than map keys,
+/// so the dump can be stored in formats which only have string keys, like json.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TableDump {
+    #[serde(default)]
+    rows: Vec<RowDump>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tombstones: Vec<TombstoneDump>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct RowDump {
+    partition_key: PartitionKeyValue,
+    clustering_key: ClusteringKeyValue,
+    cells: NamedCells,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TombstoneDump {
+    partition_key: PartitionKeyValue,
+    clustering_key: ClusteringKeyValue,
+    deleted_at: i64,
+    /// Dumps made before the local deletion time was kept expire by `deleted_at`
+    #[serde(default)]
+    local_deletion_time: Option<i64>,
+}
+
+/// Table of a state saved before states had a layout version,
+/// partitions are keyed by their key rather than their token and cells have no timestamps
+pub(crate) type UnversionedTable =
+    BTreeMap<PartitionKeyValue, BTreeMap<ClusteringKeyValue, BTreeMap<String, CqlValue>>>;
+
+impl TableDump {
+    /// Rows of an unversioned table, tokens are assigned as the dump is restored.
+    ///
+    /// Cells are written at timestamp 0, so they are older than any write made after loading.
+    pub(crate) fn from_unversioned(table: UnversionedTable) -> Self {
+        let rows = table
+            .into_iter()
+            .flat_map(|(partition_key, rows)| {
+                rows.into_iter()
+                    .map(move |(clustering_key, cells)| RowDump {
+                        partition_key: partition_key.clone(),
+                        clustering_key,
+                        cells: cells
+                            .into_iter()
+                            .map(|(column, value)| {
+                                let cell = Cell {
+                                    value: Some(value),
+                                    timestamp: 0,
+                                };
+                                (column, cell)
+                            })
+                            .collect(),
+                    })
+            })
+            .collect();
+
+        Self {
+            rows,
+            tombstones: vec![],
+        }
+    }
 }
 
-impl super::Storage for Memory {
-    type RowIterator<'a> = std::collections::btree_map::Iter<'a, String, CqlValue>;
+impl<P: Partitioner> super::ReadStorage for Memory<P> {
+    type RowIterator<'a> = std::iter::FilterMap<
+        Cells<'a>,
+        fn((&'a String, &'a Cell)) -> Option<(&'a String, &'a CqlValue)>,
+    >;
 
-    fn create_keyspace(&mut self, keyspace: &str) -> eyre::Result<()> {
+    /// Rows are filtered and mapped by closures, which can't be named
+    type Rows<'a>
+        = Box<dyn Iterator<Item = RowEntry<'a, Self::RowIterator<'a>>> + 'a>
+    where
+        Self: 'a;
+
+    fn read<'a>(
+        &'a self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &'a PartitionKeyValue,
+        range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<Self::Rows<'a>> {
+        let token = self.partitioner.token(partition_key);
+        let Some(table) = self.data.get(keyspace).and_then(|it| it.get(table)) else {
+            return Ok(Box::new(std::iter::empty()));
+        };
+        let partition = table
+            .partitions
+            .get(&token)
+            .and_then(|partitions| partitions.get(partition_key));
+        let iter = partition
+            .into_iter()
+            .flat_map(move |partition_entry| partition_entry.range(range.clone()))
+            .filter(move |(_, row)| matches(&predicate, table, row))
+            .map(move |(clustering_key, row)| RowEntry {
+                row: table.cells(row).filter_map(cell_value as fn(_) -> _),
+                partition: partition_key,
+                clustering: clustering_key,
+            });
+        Ok(Box::new(iter))
+    }
+
+    fn scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<Self::Rows<'_>> {
+        let Some(table) = self.data.get(keyspace).and_then(|it| it.get(table)) else {
+            return Ok(Box::new(std::iter::empty()));
+        };
+
+        let iter = self
+            .partitions(table, range)
+            .flat_map(|(partition_key, values)| {
+                values
+                    .iter()
+                    .map(move |(clustering_key, row)| (partition_key, clustering_key, row))
+            })
+            .filter(move |(_, _, row)| matches(&predicate, table, row))
+            .map(|(partition_key, clustering_key, row)| RowEntry {
+                partition: partition_key,
+                clustering: clustering_key,
+                row: table.cells(row).filter_map(cell_value as fn(_) -> _),
+            });
+
+        Ok(Box::new(iter))
+    }
+
+    fn count(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize> {
+        let token = self.partitioner.token(partition_key);
+        let Some(table) = self.data.get(keyspace).and_then(|it| it.get(table)) else {
+            return Ok(0);
+        };
+        let Some(partition) = table
+            .partitions
+            .get(&token)
+            .and_then(|partitions| partitions.get(partition_key))
+        else {
+            return Ok(0);
+        };
+
+        Ok(partition
+            .range(range)
+            .filter(|(_, row)| matches(&predicate, table, row))
+            .count())
+    }
+
+    fn count_scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize> {
+        let Some(table) = self.data.get(keyspace).and_then(|it| it.get(table)) else {
+            return Ok(0);
+        };
+
+        Ok(self
+            .partitions(table, range)
+            .map(|(_, partition)| match predicate.is_empty() {
+                // unrestricted rows are counted by their partition, without looking at their cells
+                true => partition.len(),
+                false => partition
+                    .values()
+                    .filter(|row| matches(&predicate, table, row))
+                    .count(),
+            })
+            .sum())
+    }
+}
+
+impl<P: Partitioner> super::WriteStorage for Memory<P> {
+    fn create_keyspace(&mut self, keyspace: &str) -> Result<()> {
         self.data.insert(keyspace.to_owned(), Default::default());
         Ok(())
     }
 
-    fn create_table(&mut self, keyspace: &str, table: &str) -> eyre::Result<()> {
+    fn create_table(&mut self, keyspace: &str, table: &str) -> Result<()> {
         self.data
             .get_mut(keyspace)
-            .ok_or(eyre!("Keyspace does not exist"))?
+            .ok_or_else(|| StorageError::KeyspaceDoesNotExist(keyspace.to_owned()))?
             .insert(table.to_owned(), Default::default());
         Ok(())
     }
 
+    fn drop_keyspace(&mut self, keyspace: &str) -> Result<()> {
+        self.data.remove(keyspace);
+        self.tombstones.remove(keyspace);
+        Ok(())
+    }
+
+    fn drop_table(&mut self, keyspace: &str, table: &str) -> Result<()> {
+        if let Some(tables) = self.data.get_mut(keyspace) {
+            tables.remove(table);
+        }
+        if let Some(tombstones) = self.tombstones.get_mut(keyspace) {
+            tombstones.remove(table);
+        }
+        Ok(())
+    }
+
     fn write(
         &mut self,
         keyspace: &str,
         table: &str,
         partition_key: PartitionKeyValue,
         clustering_key: ClusteringKeyValue,
-        values: impl Iterator<Item = (String, CqlValue)>,
-    ) -> eyre::Result<()> {
-        let table = self
-            .data
-            .entry(keyspace.to_owned())
-            .or_default()
-            .entry(table.to_owned())
-            .or_default();
+        values: impl Iterator<Item = (String, impl Into<Option<CqlValue>>)>,
+        timestamp: i64,
+    ) -> Result<()> {
+        let deleted_at = self
+            .tombstones
+            .get(keyspace)
+            .and_then(|it| it.get(table))
+            .filter(|it| !it.is_empty())
+            .and_then(|tombstones| {
+                let partition = tombstones.get(&(partition_key.clone(), ClusteringKeyValue::Empty));
+                let row = tombstones.get(&(partition_key.clone(), clustering_key.clone()));
+                partition
+                    .into_iter()
+                    .chain(row)
+                    .map(|it| it.deleted_at)
+                    .max()
+            });
+        if deleted_at.is_some_and(|deleted_at| timestamp <= deleted_at) {
+            return Ok(());
+        }
+
+        let token = self.partitioner.token(&partition_key);
+        let Table {
+            columns,
+            partitions,
+        } = Arc::make_mut(
+            self.data
+                .entry(keyspace.to_owned())
+                .or_default()
+                .entry(table.to_owned())
+                .or_default(),
+        );
 
-        table
+        let row = partitions
+            .entry(token)
+            .or_default()
             .entry(partition_key)
             .or_default()
             .entry(clustering_key)
-            .or_default()
-            .extend(values);
+            .or_default();
+
+        for (column, value) in values {
+            let id = intern(columns, column);
+            let cell = Cell {
+                value: value.into(),
+                timestamp,
+            };
+            if row.get(id).is_none_or(|old| cell.supersedes(old)) {
+                row.set(id, cell);
+            }
+        }
 
         Ok(())
     }
@@ -74,77 +621,176 @@ impl super::Storage for Memory {
         table: &str,
         partition_key: &PartitionKeyValue,
         clustering_key: &ClusteringKeyValue,
-    ) -> eyre::Result<()> {
-        let table = self
+        timestamp: i64,
+        now: i64,
+    ) -> Result<()> {
+        let tombstones = self
+            .tombstones
+            .entry(keyspace.to_owned())
+            .or_default()
+            .entry(table.to_owned())
+            .or_default();
+        let tombstone = Arc::make_mut(tombstones)
+            .entry((partition_key.clone(), clustering_key.clone()))
+            .or_insert(Tombstone {
+                deleted_at: timestamp,
+                local_deletion_time: now,
+            });
+        // the newest deletion wins, so its tombstone is retained from the time it was applied
+        if timestamp >= tombstone.deleted_at {
+            *tombstone = Tombstone {
+                deleted_at: timestamp,
+                local_deletion_time: now,
+            };
+        }
+
+        // tables are created by their first write, there are no rows to delete before it
+        let Some(table) = self
             .data
             .get_mut(keyspace)
-            .ok_or(eyre!("Keyspace does not exist"))?
-            .get_mut(table)
-            .ok_or(eyre!("Table does not exist"))?;
+            .and_then(|tables| tables.get_mut(table))
+        else {
+            return Ok(());
+        };
+        let token = self.partitioner.token(partition_key);
+        // the table is only copied when it has rows to delete
+        let written = table
+            .partitions
+            .get(&token)
+            .is_some_and(|partitions| partitions.contains_key(partition_key));
+        if !written {
+            return Ok(());
+        }
+        let table = Arc::make_mut(table);
+        let Some(partitions) = table.partitions.get_mut(&token) else {
+            return Ok(());
+        };
+        let Some(partition) = partitions.get_mut(partition_key) else {
+            return Ok(());
+        };
 
-        match clustering_key {
-            ClusteringKeyValue::Empty => {
-                table.remove(partition_key);
+        // cells written after the deletion survive it
+        let shadow = |row: &mut RowValues| {
+            for cell in &mut row.0 {
+                if cell
+                    .as_ref()
+                    .is_some_and(|cell| cell.timestamp <= timestamp)
+                {
+                    *cell = None;
+                }
             }
+            !row.is_empty()
+        };
+        match clustering_key {
+            ClusteringKeyValue::Empty => partition.retain(|_, row| shadow(row)),
             other => {
-                let Some(partition) = table.get_mut(partition_key) else {
-                    return Ok(());
-                };
-
-                partition.remove(other);
+                if let Some(row) = partition.get_mut(other) {
+                    if !shadow(row) {
+                        partition.remove(other);
+                    }
+                }
             }
         }
 
+        if partition.is_empty() {
+            partitions.remove(partition_key);
+        }
+        if partitions.is_empty() {
+            table.partitions.remove(&token);
+        }
+
         Ok(())
     }
 
-    fn read<'a, 'b: 'a>(
-        &'a mut self,
-        keyspace: &str,
-        table: &str,
-        partition_key: &'b PartitionKeyValue,
-        range: impl RangeBounds<ClusteringKeyValue> + Clone + 'static,
-    ) -> eyre::Result<Box<dyn Iterator<Item = RowEntry<'a, Self::RowIterator<'a>>> + 'a>> {
-        let partition = self
-            .data
-            .entry(keyspace.to_owned())
-            .or_default()
-            .entry(table.to_owned())
-            .or_default()
-            .get(partition_key);
-        let iter = partition.into_iter().flat_map(move |partition_entry| {
-            partition_entry
-                .range(range.clone())
-                .map(move |(clustering_key, row)| RowEntry {
-                    row: row.iter(),
-                    partition: partition_key,
-                    clustering: clustering_key,
-                })
-        });
-        Ok(Box::new(iter))
+    fn compact(&mut self, now: i64) -> Result<usize> {
+        let retention = self.tombstone_retention;
+        let mut purged = 0;
+
+        for tombstones in self.tombstones.values_mut().flat_map(|it| it.values_mut()) {
+            let expired = tombstones
+                .values()
+                .filter(|tombstone| tombstone.is_expired(retention, now))
+                .count();
+            if expired > 0 {
+                Arc::make_mut(tombstones)
+                    .retain(|_, tombstone| !tombstone.is_expired(retention, now));
+                purged += expired;
+            }
+        }
+
+        Ok(purged)
     }
+}
 
-    fn scan(
-        &mut self,
-        keyspace: &str,
-        table: &str,
-        range: impl RangeBounds<PartitionKeyValue> + Clone + 'static,
-    ) -> eyre::Result<Box<dyn Iterator<Item = RowEntry<'_, Self::RowIterator<'_>>> + '_>> {
-        let table = self
-            .data
-            .entry(keyspace.to_owned())
-            .or_default()
-            .entry(table.to_owned())
-            .or_default();
+impl<P: Partitioner> Memory<P> {
+    /// Partitions of the table in the token range, in the token order
+    fn partitions<'a>(
+        &self,
+        table: &'a Table,
+        range: PartitionKeyValueRange,
+    ) -> Box<dyn Iterator<Item = (&'a PartitionKeyValue, &'a Partition)> + 'a> {
+        let from = range
+            .from_key
+            .map(|key| (self.partitioner.token(&key), key));
+        let start = match &from {
+            // resumed partition is already known to be in range
+            Some((token, _)) => Bound::Included(*token),
+            None => range.start,
+        };
+        if (PartitionKeyValueRange::tokens(start, range.end)).is_empty() {
+            return Box::new(std::iter::empty());
+        }
 
-        let iter = table.range(range).flat_map(|(partition_key, values)| {
-            values.iter().map(|(clustering_key, row)| RowEntry {
-                partition: partition_key,
-                clustering: clustering_key,
-                row: row.iter(),
-            })
-        });
+        let iter =
+            table
+                .partitions
+                .range((start, range.end))
+                .flat_map(move |(token, partitions)| {
+                    let from = match &from {
+                        Some((from_token, key)) if from_token == token => Some(key.clone()),
+                        _ => None,
+                    };
+                    partitions
+                        .iter()
+                        .filter(move |(key, _)| from.as_ref().is_none_or(|from| *key >= from))
+                });
 
-        Ok(Box::new(iter))
+        Box::new(iter)
+    }
+}
+
+fn matches(predicate: &Predicate, table: &Table, row: &RowValues) -> bool {
+    predicate.matches(|column| table.cell(row, column)?.value.as_ref())
+}
+
+/// Null cells are not read
+fn cell_value<'a>((column, cell): (&'a String, &'a Cell)) -> Option<(&'a String, &'a CqlValue)> {
+    Some((column, cell.value.as_ref()?))
+}
+
+/// Null cells are left out of the persisted state,
+/// states saved before nulls were kept apart from empty values store them as `Empty`
+mod nullable {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::cql::value::CqlValue;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<CqlValue>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => value.serialize(serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<CqlValue>, D::Error> {
+        Ok(match CqlValue::deserialize(deserializer)? {
+            CqlValue::Empty => None,
+            value => Some(value),
+        })
     }
 }
@@ -2,13 +2,25 @@
 // pub mod system;
 // pub mod table;
 
+pub mod error;
 pub mod memory;
 
 pub type Entries = Vec<(String, CqlValue)>;
 
-use std::ops::RangeBounds;
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use crate::cql::value::{ClusteringKeyValue, CqlValue, PartitionKeyValue};
+use serde::{Deserialize, Deserializer, Serialize};
+
+pub use self::error::StorageError;
+use crate::cql::value::{
+    ClusteringKeyValue, ClusteringKeyValueRange, CqlValue, PartitionKeyValue,
+    PartitionKeyValueRange,
+};
+
+pub type Result<T, E = StorageError> = std::result::Result<T, E>;
 
 pub struct RowEntry<'a, I: 'a> {
     pub partition: &'a PartitionKeyValue,
@@ -16,13 +28,248 @@ pub struct RowEntry<'a, I: 'a> {
     pub row: I,
 }
 
-pub trait Storage: std::fmt::Debug + Send + 'static {
+/// Restrictions rows are filtered with while they are iterated by storage,
+/// so rows which don't match are never copied out of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct Predicate {
+    /// Cells which have to be equal to the values
+    pub equals: Vec<(String, CqlValue)>,
+    /// Text cells which have to match the patterns
+    pub likes: Vec<(String, Like)>,
+}
+
+impl Predicate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eq(mut self, column: impl Into<String>, value: CqlValue) -> Self {
+        self.equals.push((column.into(), value));
+        self
+    }
+
+    pub fn like(mut self, column: impl Into<String>, pattern: Like) -> Self {
+        self.likes.push((column.into(), pattern));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.equals.is_empty() && self.likes.is_empty()
+    }
+
+    /// Whether the row with the cells given by `cell` matches, missing and null cells never match
+    pub fn matches<'a>(&self, cell: impl Fn(&str) -> Option<&'a CqlValue>) -> bool {
+        self.equals
+            .iter()
+            .all(|(column, value)| cell(column) == Some(value))
+            && self
+                .likes
+                .iter()
+                .all(|(column, pattern)| match cell(column) {
+                    Some(CqlValue::Text(text) | CqlValue::Ascii(text)) => pattern.matches(text),
+                    _ => false,
+                })
+    }
+}
+
+/// Pattern of a `LIKE` restriction, the way SASI indexes match them:
+/// `%` is a wildcard at the start or the end of the pattern, and matches anything else literally.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum Like {
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+    Contains(String),
+}
+
+impl Like {
+    /// `None` for patterns which are empty without their wildcards
+    pub fn new(pattern: &str) -> Option<Self> {
+        let (prefix, start) = match pattern.strip_suffix('%') {
+            Some(start) => (true, start),
+            None => (false, pattern),
+        };
+        let (suffix, text) = match start.strip_prefix('%') {
+            Some(text) => (true, text),
+            None => (false, start),
+        };
+        if text.is_empty() {
+            return None;
+        }
+
+        let text = text.to_owned();
+        Some(match (prefix, suffix) {
+            (false, false) => Self::Exact(text),
+            (true, false) => Self::Prefix(text),
+            (false, true) => Self::Suffix(text),
+            (true, true) => Self::Contains(text),
+        })
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            Like::Exact(text) => value == text,
+            Like::Prefix(text) => value.starts_with(text.as_str()),
+            Like::Suffix(text) => value.ends_with(text.as_str()),
+            Like::Contains(text) => value.contains(text.as_str()),
+        }
+    }
+}
+
+/// Marker left behind by a deleted row or partition until it is purged by [`WriteStorage::compact`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "PersistedTombstone")]
+pub struct Tombstone {
+    /// Write timestamp of the deletion in microseconds since unix epoch, same as cql write timestamps
+    pub deleted_at: i64,
+    /// Time the deletion was applied at, the way Cassandra keeps it for `gc_grace_seconds`.
+    ///
+    /// Retention is measured from it rather than from `deleted_at`, which clients may set to any value.
+    pub local_deletion_time: i64,
+}
+
+impl Tombstone {
+    pub fn is_expired(&self, retention: Duration, now: i64) -> bool {
+        self.local_deletion_time
+            .saturating_add(retention.as_micros() as i64)
+            <= now
+    }
+}
+
+/// Tombstones saved before the local deletion time was kept expire by their write timestamp
+#[derive(Deserialize)]
+struct PersistedTombstone {
+    deleted_at: i64,
+    #[serde(default, deserialize_with = "present")]
+    local_deletion_time: Option<i64>,
+}
+
+/// Tombstones are written with a plain local deletion time, it's only optional when reading
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    i64::deserialize(deserializer).map(Some)
+}
+
+impl From<PersistedTombstone> for Tombstone {
+    fn from(value: PersistedTombstone) -> Self {
+        Self {
+            deleted_at: value.deleted_at,
+            local_deletion_time: value.local_deletion_time.unwrap_or(value.deleted_at),
+        }
+    }
+}
+
+pub(crate) fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64
+}
+
+/// Timestamp for writes that don't specify one, see [`Timestamps`]
+pub fn write_timestamp() -> i64 {
+    static LAST: Timestamps = Timestamps::new();
+
+    LAST.next(now_micros())
+}
+
+/// Strictly increasing write timestamps, the same way Cassandra makes timestamps of a client unique,
+/// so quick delete and insert pairs don't shadow each other.
+#[derive(Debug)]
+pub(crate) struct Timestamps {
+    last: AtomicI64,
+}
+
+impl Timestamps {
+    pub(crate) const fn new() -> Self {
+        Self {
+            last: AtomicI64::new(i64::MIN),
+        }
+    }
+
+    /// Timestamp at `now`, or right after the last one if the clock didn't move past it
+    pub(crate) fn next(&self, now: i64) -> i64 {
+        let last = self
+            .last
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .expect("update closure always returns a value");
+
+        now.max(last + 1)
+    }
+}
+
+/// Read half of a storage, rows are iterated over shared borrows of it,
+/// so backends can hand out references into their data rather than copies of it.
+pub trait ReadStorage: std::fmt::Debug {
+    /// Cells of a row, null cells are left out
     type RowIterator<'a>: Iterator<Item = (&'a String, &'a CqlValue)>
     where
         Self: 'a;
 
-    fn create_keyspace(&mut self, keyspace: &str) -> eyre::Result<()>;
-    fn create_table(&mut self, keyspace: &str, table: &str) -> eyre::Result<()>;
+    /// Rows of [`ReadStorage::read`] and [`ReadStorage::scan`]
+    type Rows<'a>: Iterator<Item = RowEntry<'a, Self::RowIterator<'a>>>
+    where
+        Self: 'a;
+
+    /// Rows of the partition in the clustering range, which match the predicate
+    fn read<'a>(
+        &'a self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &'a PartitionKeyValue,
+        range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<Self::Rows<'a>>;
+
+    /// Rows of partitions in the token range, which match the predicate
+    fn scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<Self::Rows<'_>>;
+
+    /// Number of rows [`ReadStorage::read`] returns, counted without reading their cells
+    fn count(
+        &self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &PartitionKeyValue,
+        range: ClusteringKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize> {
+        Ok(self
+            .read(keyspace, table, partition_key, range, predicate)?
+            .count())
+    }
+
+    /// Number of rows [`ReadStorage::scan`] returns, counted without reading their cells
+    fn count_scan(
+        &self,
+        keyspace: &str,
+        table: &str,
+        range: PartitionKeyValueRange,
+        predicate: Predicate,
+    ) -> Result<usize> {
+        Ok(self.scan(keyspace, table, range, predicate)?.count())
+    }
+}
+
+/// Write half of a storage.
+///
+/// Writes and deletes are resolved by their timestamps (last write wins):
+/// cells are only overwritten by writes with the same or newer timestamp,
+/// and deletes shadow everything written before or at the same timestamp.
+pub trait WriteStorage: ReadStorage {
+    fn create_keyspace(&mut self, keyspace: &str) -> Result<()>;
+    fn create_table(&mut self, keyspace: &str, table: &str) -> Result<()>;
+
+    /// Removes everything written to the keyspace, tombstones included.
+    fn drop_keyspace(&mut self, keyspace: &str) -> Result<()>;
+    /// Removes everything written to the table, tombstones included.
+    fn drop_table(&mut self, keyspace: &str, table: &str) -> Result<()>;
 
     fn write(
         &mut self,
@@ -30,29 +277,29 @@ pub trait Storage: std::fmt::Debug + Send + 'static {
         table: &str,
         partition_key: PartitionKeyValue,
         clustering_key: ClusteringKeyValue,
-        values: impl Iterator<Item = (String, CqlValue)>,
-    ) -> eyre::Result<()>;
+        values: impl Iterator<Item = (String, impl Into<Option<CqlValue>>)>,
+        timestamp: i64,
+    ) -> Result<()>;
 
+    /// Removes the row, or the whole partition for an empty clustering key, leaving a [`Tombstone`].
+    ///
+    /// `now` is the local deletion time of the tombstone, in the same clock [`WriteStorage::compact`] is given.
     fn delete(
         &mut self,
         keyspace: &str,
         table: &str,
         partition_key: &PartitionKeyValue,
         clustering_key: &ClusteringKeyValue,
-    ) -> eyre::Result<()>;
-
-    fn read<'a, 'b: 'a>(
-        &'a mut self,
-        keyspace: &str,
-        table: &str,
-        partition_key: &'b PartitionKeyValue,
-        range: impl RangeBounds<ClusteringKeyValue> + Clone + 'static,
-    ) -> eyre::Result<Box<dyn Iterator<Item = RowEntry<Self::RowIterator<'a>>> + 'a>>;
+        timestamp: i64,
+        now: i64,
+    ) -> Result<()>;
 
-    fn scan(
-        &mut self,
-        keyspace: &str,
-        table: &str,
-        range: impl RangeBounds<PartitionKeyValue> + Clone + 'static,
-    ) -> eyre::Result<Box<dyn Iterator<Item = RowEntry<Self::RowIterator<'_>>> + '_>>;
+    /// Purges tombstones older than the retention period at `now` (microseconds since unix epoch),
+    /// returns how many of them were purged.
+    fn compact(&mut self, now: i64) -> Result<usize>;
 }
+
+/// Storage which can be both read and written
+pub trait Storage: ReadStorage + WriteStorage {}
+
+impl<S: ReadStorage + WriteStorage> Storage for S {}
@@ -0,0 +1,449 @@
+//! Random schemas and statement sequences valid for them, for property tests of kassandra
+//! and of data layers built over it.
+//!
+//! A [`Workload`] is a keyspace with tables and statements writing and reading them,
+//! proptest shrinks a failing workload to fewer statements and simpler values before it is reported.
+//! Keys are drawn from small domains, so statements keep hitting rows written before them.
+
+use std::fmt;
+
+use bytes::Bytes;
+pub use proptest;
+use proptest::{
+    arbitrary::Arbitrary,
+    collection::vec,
+    prelude::*,
+    sample::{select, subsequence},
+    strategy::BoxedStrategy,
+};
+use uuid::Uuid;
+
+use crate::{
+    cql::{self, parser, query::QueryString, schema::ColumnType, value::CqlValue},
+    export,
+    frame::{
+        request::query::Query,
+        response::{error::Error, result::QueryResult},
+    },
+    KassandraSession,
+};
+
+/// Keyspace generated tables are created in
+pub const KEYSPACE: &str = "testgen";
+
+#[derive(Debug, Clone)]
+pub struct GeneratedTable {
+    pub name: String,
+    pub partition_key: Vec<(String, ColumnType)>,
+    pub clustering_key: Vec<(String, ColumnType)>,
+    /// Regular columns, there is at least one of them
+    pub columns: Vec<(String, ColumnType)>,
+}
+
+impl GeneratedTable {
+    pub fn create_statement(&self, keyspace: &str) -> String {
+        let columns = self
+            .primary_key()
+            .chain(&self.columns)
+            .map(|(name, ty)| format!("{name} {ty}"))
+            .collect::<Vec<_>>();
+        let names = |columns: &[(String, ColumnType)]| {
+            columns
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let primary_key = match self.clustering_key.is_empty() {
+            true => format!("({})", names(&self.partition_key)),
+            false => format!(
+                "({}), {}",
+                names(&self.partition_key),
+                names(&self.clustering_key)
+            ),
+        };
+
+        format!(
+            "CREATE TABLE {keyspace}.{} ({}, PRIMARY KEY ({primary_key}))",
+            self.name,
+            columns.join(", ")
+        )
+    }
+
+    fn primary_key(&self) -> impl Iterator<Item = &(String, ColumnType)> {
+        self.partition_key.iter().chain(&self.clustering_key)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratedSchema {
+    pub keyspace: String,
+    pub tables: Vec<GeneratedTable>,
+}
+
+/// Single key value table, statements generated without a schema of their own target it
+impl Default for GeneratedSchema {
+    fn default() -> Self {
+        Self {
+            keyspace: KEYSPACE.to_owned(),
+            tables: vec![GeneratedTable {
+                name: "kv".to_owned(),
+                partition_key: vec![("key".to_owned(), ColumnType::Int)],
+                clustering_key: vec![],
+                columns: vec![("value".to_owned(), ColumnType::Text)],
+            }],
+        }
+    }
+}
+
+impl GeneratedSchema {
+    /// Statements creating the keyspace and its tables
+    pub fn statements(&self) -> Vec<QueryString> {
+        let keyspace = format!(
+            "CREATE KEYSPACE {} WITH REPLICATION = {{'class': 'SimpleStrategy', 'replication_factor': 1}}",
+            self.keyspace
+        );
+
+        std::iter::once(keyspace)
+            .chain(
+                self.tables
+                    .iter()
+                    .map(|table| table.create_statement(&self.keyspace)),
+            )
+            .map(|statement| parse(&statement))
+            .collect()
+    }
+}
+
+/// Schema along with statements to run against it
+#[derive(Debug, Clone)]
+pub struct Workload {
+    pub schema: GeneratedSchema,
+    pub statements: Vec<QueryString>,
+}
+
+impl Workload {
+    /// Creates the schema, then runs the statements in order, stopping at the first one which fails
+    pub fn apply<E: cql::Engine>(
+        &self,
+        session: &mut KassandraSession<E>,
+    ) -> Result<Vec<QueryResult>, Error> {
+        self.schema
+            .statements()
+            .into_iter()
+            .chain(self.statements.iter().cloned())
+            .map(|query| {
+                session.process(Query {
+                    query,
+                    raw_query: "",
+                    parameters: Default::default(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The workload as a cql script, the way failures are reported
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for statement in self.schema.statements().iter().chain(&self.statements) {
+            writeln!(f, "{statement};")?;
+        }
+        Ok(())
+    }
+}
+
+/// Types of regular columns: native types, lists of them and maps keyed by text.
+///
+/// Only types with literals kassandra parses are generated, sets and blobs are left out.
+pub fn column_type() -> BoxedStrategy<ColumnType> {
+    prop_oneof![
+        4 => native_type(),
+        1 => native_type().prop_map(|it| ColumnType::List(Box::new(it))),
+        1 => native_type()
+            .prop_map(|value| ColumnType::Map(Box::new(ColumnType::Text), Box::new(value))),
+    ]
+    .boxed()
+}
+
+/// Types of primary key columns
+pub fn key_type() -> impl Strategy<Value = ColumnType> {
+    select(vec![
+        ColumnType::Int,
+        ColumnType::BigInt,
+        ColumnType::Text,
+        ColumnType::Uuid,
+    ])
+}
+
+fn native_type() -> impl Strategy<Value = ColumnType> {
+    select(vec![
+        ColumnType::Int,
+        ColumnType::BigInt,
+        ColumnType::Text,
+        ColumnType::Boolean,
+        ColumnType::Double,
+        ColumnType::Timestamp,
+        ColumnType::Uuid,
+    ])
+}
+
+/// Values of the type, collections hold a few distinct elements
+pub fn value(ty: &ColumnType) -> BoxedStrategy<CqlValue> {
+    match ty {
+        ColumnType::Int => any::<i32>().prop_map(CqlValue::Int).boxed(),
+        ColumnType::BigInt => any::<i64>().prop_map(CqlValue::BigInt).boxed(),
+        ColumnType::Text => "[a-zA-Z0-9 ']{0,12}"
+            .prop_map(|it| CqlValue::Text(it.into()))
+            .boxed(),
+        ColumnType::Boolean => any::<bool>().prop_map(CqlValue::Boolean).boxed(),
+        // quarters are written exactly by decimal literals
+        ColumnType::Double => (-4_000_000i32..4_000_000)
+            .prop_map(|it| CqlValue::Double((f64::from(it) / 4.0).to_bits()))
+            .boxed(),
+        ColumnType::Timestamp => (0i64..4_102_444_800_000)
+            .prop_map(CqlValue::Timestamp)
+            .boxed(),
+        ColumnType::Uuid => any::<u128>()
+            .prop_map(|it| CqlValue::Uuid(Uuid::from_u128(it)))
+            .boxed(),
+        ColumnType::Blob => vec(any::<u8>(), 0..8)
+            .prop_map(|it| CqlValue::Blob(Bytes::from(it)))
+            .boxed(),
+        ColumnType::List(item) => vec(value(item), 0..4).prop_map(CqlValue::List).boxed(),
+        ColumnType::Set(item) => vec(value(item), 0..4)
+            .prop_map(|mut items| {
+                items.sort();
+                items.dedup();
+                CqlValue::Set(items)
+            })
+            .boxed(),
+        ColumnType::Map(key, item) => vec((value(key), value(item)), 0..4)
+            .prop_map(|mut entries| {
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries.dedup_by(|a, b| a.0 == b.0);
+                CqlValue::Map(entries)
+            })
+            .boxed(),
+        other => panic!("Values of {other} are not generated"),
+    }
+}
+
+/// Values of key columns, from domains small enough for statements to share keys
+fn key_value(ty: &ColumnType) -> BoxedStrategy<CqlValue> {
+    match ty {
+        ColumnType::Int => (0..4).prop_map(CqlValue::Int).boxed(),
+        ColumnType::BigInt => (0..4i64).prop_map(CqlValue::BigInt).boxed(),
+        ColumnType::Text => "[a-c]".prop_map(|it| CqlValue::Text(it.into())).boxed(),
+        ColumnType::Uuid => (0..4u128)
+            .prop_map(|it| CqlValue::Uuid(Uuid::from_u128(it)))
+            .boxed(),
+        ColumnType::Blob => (1..4u8)
+            .prop_map(|it| CqlValue::Blob(Bytes::from(vec![it])))
+            .boxed(),
+        other => value(other),
+    }
+}
+
+/// Keyspace with up to three tables, tables have compound keys and columns of any generated type
+pub fn schema() -> impl Strategy<Value = GeneratedSchema> {
+    let table = (
+        vec(key_type(), 1..3),
+        vec(key_type(), 0..3),
+        vec(column_type(), 1..5),
+    );
+
+    vec(table, 1..4).prop_map(|tables| GeneratedSchema {
+        keyspace: KEYSPACE.to_owned(),
+        tables: tables
+            .into_iter()
+            .enumerate()
+            .map(|(id, (partition_key, clustering_key, columns))| {
+                let named = |prefix: &str, types: Vec<ColumnType>| {
+                    types
+                        .into_iter()
+                        .enumerate()
+                        .map(|(id, ty)| (format!("{prefix}{id}"), ty))
+                        .collect()
+                };
+                GeneratedTable {
+                    name: format!("t{id}"),
+                    partition_key: named("pk", partition_key),
+                    clustering_key: named("ck", clustering_key),
+                    columns: named("c", columns),
+                }
+            })
+            .collect(),
+    })
+}
+
+/// `INSERT`, `UPDATE`, `DELETE` or `SELECT` of a table of the schema
+pub fn statement(schema: &GeneratedSchema) -> BoxedStrategy<QueryString> {
+    let keyspace = schema.keyspace.clone();
+
+    select(schema.tables.clone())
+        .prop_flat_map(move |table| {
+            let target = format!("{keyspace}.{}", table.name);
+            prop_oneof![
+                3 => insert(&target, &table),
+                2 => update(&target, &table),
+                1 => delete(&target, &table),
+                1 => read(&target, &table),
+            ]
+        })
+        .prop_map(|statement| parse(&statement))
+        .boxed()
+}
+
+/// Schema with up to 32 statements against it
+pub fn workload() -> impl Strategy<Value = Workload> {
+    schema().prop_flat_map(|schema| {
+        vec(statement(&schema), 0..32).prop_map(move |statements| Workload {
+            schema: schema.clone(),
+            statements,
+        })
+    })
+}
+
+fn insert(target: &str, table: &GeneratedTable) -> BoxedStrategy<String> {
+    let target = target.to_owned();
+    let keys = table.primary_key().cloned().collect::<Vec<_>>();
+
+    (
+        literals(&keys, key_value),
+        subsequence(table.columns.clone(), 0..=table.columns.len())
+            .prop_flat_map(|columns| (Just(columns.clone()), literals(&columns, value))),
+    )
+        .prop_map(move |(key_literals, (columns, literals))| {
+            let names = keys
+                .iter()
+                .chain(&columns)
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            format!(
+                "INSERT INTO {target} ({}) VALUES ({})",
+                names.join(", "),
+                [key_literals, literals].concat().join(", ")
+            )
+        })
+        .boxed()
+}
+
+fn update(target: &str, table: &GeneratedTable) -> BoxedStrategy<String> {
+    let target = target.to_owned();
+    let keys = table.primary_key().cloned().collect::<Vec<_>>();
+
+    (
+        restrictions(&keys),
+        subsequence(table.columns.clone(), 1..=table.columns.len())
+            .prop_flat_map(|columns| (Just(columns.clone()), literals(&columns, value))),
+    )
+        .prop_map(move |(restrictions, (columns, literals))| {
+            let assignments = columns
+                .iter()
+                .zip(literals)
+                .map(|((name, _), literal)| format!("{name} = {literal}"))
+                .collect::<Vec<_>>();
+            format!(
+                "UPDATE {target} SET {} WHERE {restrictions}",
+                assignments.join(", ")
+            )
+        })
+        .boxed()
+}
+
+/// Deletes a row, or the whole partition
+fn delete(target: &str, table: &GeneratedTable) -> BoxedStrategy<String> {
+    let target = target.to_owned();
+    let partition = restrictions(&table.partition_key);
+    let row = restrictions(&table.primary_key().cloned().collect::<Vec<_>>());
+
+    prop_oneof![partition, row]
+        .prop_map(move |restrictions| format!("DELETE FROM {target} WHERE {restrictions}"))
+        .boxed()
+}
+
+/// Reads a partition, or scans the whole table
+fn read(target: &str, table: &GeneratedTable) -> BoxedStrategy<String> {
+    let target = target.to_owned();
+    let scan = format!("SELECT * FROM {target}");
+
+    prop_oneof![
+        restrictions(&table.partition_key)
+            .prop_map(move |restrictions| format!("SELECT * FROM {target} WHERE {restrictions}")),
+        Just(scan),
+    ]
+    .boxed()
+}
+
+fn literals(
+    columns: &[(String, ColumnType)],
+    values: fn(&ColumnType) -> BoxedStrategy<CqlValue>,
+) -> BoxedStrategy<Vec<String>> {
+    columns
+        .iter()
+        .map(|(_, ty)| {
+            let ty = ty.clone();
+            values(&ty)
+                .prop_map(move |value| export::literal(&value, &ty))
+                .boxed()
+        })
+        .collect::<Vec<_>>()
+        .boxed()
+}
+
+/// `column = literal` of every column, joined by `AND`
+fn restrictions(columns: &[(String, ColumnType)]) -> BoxedStrategy<String> {
+    let names = columns
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    literals(columns, key_value)
+        .prop_map(move |literals| {
+            names
+                .iter()
+                .zip(literals)
+                .map(|(name, literal)| format!("{name} = {literal}"))
+                .collect::<Vec<_>>()
+                .join(" AND ")
+        })
+        .boxed()
+}
+
+fn parse(statement: &str) -> QueryString {
+    parser::query(statement)
+        .unwrap_or_else(|error| panic!("Generated `{statement}` is invalid: {}", error.reason))
+}
+
+/// Values of a column type, any generated type when no type is given
+impl Arbitrary for CqlValue {
+    type Parameters = Option<ColumnType>;
+    type Strategy = BoxedStrategy<CqlValue>;
+
+    fn arbitrary_with(ty: Self::Parameters) -> Self::Strategy {
+        match ty {
+            Some(ty) => value(&ty),
+            None => column_type().prop_flat_map(|ty| value(&ty)).boxed(),
+        }
+    }
+}
+
+/// Statements valid for the schema
+impl Arbitrary for QueryString {
+    type Parameters = GeneratedSchema;
+    type Strategy = BoxedStrategy<QueryString>;
+
+    fn arbitrary_with(schema: Self::Parameters) -> Self::Strategy {
+        statement(&schema)
+    }
+}
+
+impl Arbitrary for Workload {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Workload>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        workload().boxed()
+    }
+}
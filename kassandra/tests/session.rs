@@ -1,6 +1,47 @@
+use std::{
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{FutureExt, StreamExt};
 use insta::assert_debug_snapshot;
 use kassandra::{
-    frame::{request::query::Query, response::result::QueryResult},
+    changes::ChangeEvent,
+    clock::{ManualClock, SequentialIds},
+    compat::CompatibilityProfile,
+    cql::{
+        engine::views::SystemViews,
+        partitioner::{Murmur3Partitioner, Partitioner},
+        plan::Plan,
+        query::QueryString,
+        value::{ClusteringKeyValue, CqlDuration, CqlValue, PartitionKeyValue},
+        visit::VisitMut,
+    },
+    error::DbError,
+    frame::{
+        consistency::Consistency,
+        request::{
+            execute::Execute, prepare::Prepare, query::Query, QueryFlags, QueryParameters, Request,
+        },
+        response::{
+            error::{Error, ErrorRenderer},
+            event::{SchemaChangeEvent, SchemaChangeType},
+            result::{PreparedMetadata, QueryResult, Row},
+            Response,
+        },
+        value::FrameValue,
+    },
+    middleware::Context,
+    policy::{
+        BatchSizePolicy, ConsistencyFailure, ConsistencyPolicy, LimitsPolicy, UnimplementedPolicy,
+    },
+    session::{self, ConnectionState, STATE_VERSION},
+    snapshot::ValueSnapshot,
     KassandraSession,
 };
 
@@ -101,3 +142,3817 @@ fn select_from_system_schema() {
     };
     assert_eq!(rows.rows.len(), 1);
 }
+
+#[test]
+fn clustering_order_desc() {
+    let mut session = session();
+    let _ = exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race_id int,
+                       race_time int,
+                       rider text,
+                       PRIMARY KEY (race_id, race_time))
+                       WITH CLUSTERING ORDER BY (race_time DESC);"
+    );
+    for (time, rider) in [(3, "john"), (1, "smith"), (2, "jane")] {
+        let query = &format!(
+            "insert into cycling.race_times (race_id, race_time, rider) values (1, {time}, '{rider}');"
+        );
+        let _ = exec!(session, query);
+    }
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "select race_time from cycling.race_times where race_id = 1;"
+    ) else {
+        panic!("invalid return type");
+    };
+    let times: Vec<_> = rows.rows.into_iter().map(|it| it.columns).collect();
+    assert_eq!(
+        times,
+        [3, 2, 1].map(|it| vec![Some(CqlValue::Int(it))]).to_vec()
+    );
+
+    let QueryResult::Rows(rows) = exec!(session, "select race_time from cycling.race_times;")
+    else {
+        panic!("invalid return type");
+    };
+    let times: Vec<_> = rows.rows.into_iter().map(|it| it.columns).collect();
+    assert_eq!(
+        times,
+        [3, 2, 1].map(|it| vec![Some(CqlValue::Int(it))]).to_vec()
+    );
+}
+
+#[test]
+fn size_estimates_follow_data() {
+    let mut session = session();
+    for id in 0..10 {
+        let query = &format!(
+            "insert into cycling.cyclist_name (id, lastname, firstname) values ({id}, 'john', 'johnson');"
+        );
+        let _ = exec!(session, query);
+    }
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "select partitions_count from system.size_estimates where keyspace_name = 'cycling';"
+    ) else {
+        panic!("invalid return type");
+    };
+
+    assert_eq!(rows.rows.len(), session::DEFAULT_NUM_TOKENS);
+    let partitions: i64 = rows
+        .rows
+        .into_iter()
+        .map(|it| match it.columns[0] {
+            Some(CqlValue::BigInt(count)) => count,
+            _ => panic!("invalid partitions count"),
+        })
+        .sum();
+    assert_eq!(partitions, 10);
+}
+
+#[test]
+fn scan_in_token_order() {
+    let mut session = session();
+    for id in 0..10 {
+        let query = &format!(
+            "insert into cycling.cyclist_name (id, lastname, firstname) values ({id}, 'john', 'johnson');"
+        );
+        let _ = exec!(session, query);
+    }
+    let token = |id| Murmur3Partitioner.token(&PartitionKeyValue::Simple(CqlValue::Int(id)));
+    let ids = |rows: Vec<Row>| {
+        rows.into_iter()
+            .map(|it| match it.columns[0] {
+                Some(CqlValue::Int(id)) => id,
+                _ => panic!("invalid id"),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let QueryResult::Rows(rows) = exec!(session, "select id from cycling.cyclist_name;") else {
+        panic!("invalid return type");
+    };
+    let mut expected = (0..10).collect::<Vec<_>>();
+    expected.sort_by_key(|id| token(*id));
+    assert_eq!(ids(rows.rows), expected);
+
+    let query = &format!(
+        "select id from cycling.cyclist_name where token(id) > {} and token(id) <= {};",
+        token(expected[2]),
+        token(expected[5])
+    );
+    let QueryResult::Rows(rows) = exec!(session, query) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(ids(rows.rows), expected[3..=5]);
+}
+
+#[test]
+fn aliases_name_selected_columns() {
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname, records) VALUES (1, 'VOS', {'2019': 'first'})"
+    );
+
+    for query in [
+        r#"SELECT id AS "Id", toJson(records), lastname AS a, lastname AS b FROM cycling.cyclist_name WHERE id = 1"#,
+        r#"SELECT id AS "Id", toJson(records), lastname AS a, lastname AS b FROM cycling.cyclist_name"#,
+    ] {
+        let QueryResult::Rows(rows) = exec!(session, query) else {
+            panic!("invalid return type");
+        };
+        let names = rows.metadata.col_specs.iter().map(|it| it.name.as_str());
+        assert_eq!(
+            names.collect::<Vec<_>>(),
+            ["Id", "system.tojson(records)", "a", "b"],
+            "{query}"
+        );
+        assert_eq!(
+            rows.rows[0].columns,
+            vec![
+                Some(CqlValue::Int(1)),
+                Some(CqlValue::Text(r#"{"2019": "first"}"#.into())),
+                Some(CqlValue::Text("VOS".into())),
+                Some(CqlValue::Text("VOS".into())),
+            ],
+            "{query}"
+        );
+    }
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        r#"SELECT JSON id AS "Key", lastname AS name FROM cycling.cyclist_name WHERE id = 1"#
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![Some(CqlValue::Text(
+            r#"{"\"Key\"": 1, "name": "VOS"}"#.into()
+        ))]
+    );
+
+    let error = session
+        .process(Query::simple("SELECT id AS key FROM cycling.cyclist_name WHERE key = 1").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+    assert_eq!(
+        error.reason,
+        "Aliases aren't allowed in the where clause ('key = 1')"
+    );
+}
+
+#[test]
+fn like_restrictions_match_text() {
+    let mut session = session();
+    for (id, lastname) in [(1, "VOS"), (2, "VOSKAMP"), (3, "BRAND"), (4, "KOPECKY")] {
+        let query = &format!(
+            "INSERT INTO cycling.cyclist_name (id, lastname) VALUES ({id}, '{lastname}');"
+        );
+        exec!(session, query);
+    }
+    let mut ids = |query: &str| {
+        let QueryResult::Rows(rows) = session.process(Query::simple(query)?)? else {
+            panic!("invalid return type");
+        };
+        let mut ids = rows
+            .rows
+            .into_iter()
+            .map(|it| match it.columns[0] {
+                Some(CqlValue::Int(id)) => id,
+                _ => panic!("invalid id"),
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        Ok::<_, kassandra::frame::response::error::Error>(ids)
+    };
+
+    let like = |pattern: &str| {
+        format!("SELECT id FROM cycling.cyclist_name WHERE lastname LIKE '{pattern}'")
+    };
+    assert_eq!(ids(&like("VOS%")).unwrap(), [1, 2]);
+    assert_eq!(ids(&like("%AND")).unwrap(), [3]);
+    assert_eq!(ids(&like("%O%")).unwrap(), [1, 2, 4]);
+    assert_eq!(ids(&like("VOS")).unwrap(), [1]);
+    assert_eq!(
+        ids("SELECT id FROM cycling.cyclist_name WHERE id = 2 AND lastname LIKE 'VOS%'").unwrap(),
+        [2]
+    );
+
+    for invalid in [
+        like("%"),
+        "SELECT id FROM cycling.cyclist_name WHERE id LIKE '1%'".to_owned(),
+    ] {
+        assert_eq!(ids(&invalid).unwrap_err().error, DbError::Invalid);
+    }
+
+    let QueryResult::Prepared(prepared) = session
+        .prepare(
+            Prepare::simple("SELECT id FROM cycling.cyclist_name WHERE lastname LIKE ?").unwrap(),
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(prepared.prepared_metadata.col_specs[0].name, "lastname");
+    let id = prepared.id.to_be_bytes();
+    let QueryResult::Rows(rows) = session
+        .execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data: vec![FrameValue::Some(b"%KAMP")],
+                ..Default::default()
+            },
+        })
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Int(2)));
+}
+
+#[test]
+fn select_clustering_slices() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.stage_results (
+                       race text,
+                       stage int,
+                       rank int,
+                       PRIMARY KEY (race, stage, rank));"
+    );
+    for stage in 1..=3 {
+        for rank in 1..=3 {
+            let query = &format!(
+                "INSERT INTO cycling.stage_results (race, stage, rank) VALUES ('Tour', {stage}, {rank});"
+            );
+            exec!(session, query);
+        }
+    }
+    let keys = |rows: Vec<Row>| {
+        rows.into_iter()
+            .map(|it| match it.columns[..] {
+                [Some(CqlValue::Int(stage)), Some(CqlValue::Int(rank))] => (stage, rank),
+                _ => panic!("invalid row"),
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut select = |restriction: &str| {
+        let query = format!(
+            "SELECT stage, rank FROM cycling.stage_results WHERE race = 'Tour' AND {restriction}"
+        );
+        match session.process(Query::simple(&query)?)? {
+            QueryResult::Rows(rows) => {
+                Ok::<_, kassandra::frame::response::error::Error>(keys(rows.rows))
+            }
+            _ => panic!("invalid return type"),
+        }
+    };
+
+    assert_eq!(select("stage = 2 AND rank > 1").unwrap(), [(2, 2), (2, 3)]);
+    assert_eq!(
+        select("stage > 1 AND stage <= 2").unwrap(),
+        [(2, 1), (2, 2), (2, 3)]
+    );
+    assert_eq!(
+        select("(stage, rank) > (2, 2)").unwrap(),
+        [(2, 3), (3, 1), (3, 2), (3, 3)]
+    );
+    assert_eq!(
+        select("(stage, rank) >= (1, 3) AND (stage, rank) < (3, 1)").unwrap(),
+        [(1, 3), (2, 1), (2, 2), (2, 3)]
+    );
+    assert_eq!(select("(stage) = (3)").unwrap(), [(3, 1), (3, 2), (3, 3)]);
+
+    for invalid in ["rank > 1", "(rank, stage) > (1, 1)", "(stage, rank) > (1)"] {
+        assert_eq!(select(invalid).unwrap_err().error, DbError::Invalid);
+    }
+    assert_eq!(
+        select("stage < ? AND race = ?").unwrap_err().error,
+        DbError::SyntaxError
+    );
+
+    // manual paging continues after the last clustering key of the previous page
+    let QueryResult::Prepared(prepared) = session
+        .prepare(
+            Prepare::simple(
+                "SELECT stage, rank FROM cycling.stage_results \
+                 WHERE race = ? AND (stage, rank) > (?, ?) LIMIT 2",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let names = prepared
+        .prepared_metadata
+        .col_specs
+        .iter()
+        .map(|it| it.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["race", "stage", "rank"]);
+
+    let id = prepared.id.to_be_bytes();
+    let mut pages = vec![];
+    let mut last = (0_i32, 0_i32);
+    loop {
+        let (stage, rank) = (last.0.to_be_bytes(), last.1.to_be_bytes());
+        let QueryResult::Rows(rows) = session
+            .execute(Execute {
+                id: &id,
+                parameters: QueryParameters {
+                    data: vec![
+                        FrameValue::Some(b"Tour"),
+                        FrameValue::Some(&stage),
+                        FrameValue::Some(&rank),
+                    ],
+                    ..Default::default()
+                },
+            })
+            .unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        let page = keys(rows.rows);
+        let Some(&next) = page.last() else {
+            break;
+        };
+        last = next;
+        pages.push(page);
+    }
+    assert_eq!(
+        pages,
+        [
+            vec![(1, 1), (1, 2)],
+            vec![(1, 3), (2, 1)],
+            vec![(2, 2), (2, 3)],
+            vec![(3, 1), (3, 2)],
+            vec![(3, 3)],
+        ]
+    );
+}
+
+#[test]
+fn allow_filtering_restricts_rows() {
+    let mut session = session();
+    let _ = exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race_id int,
+                       race_time int,
+                       rider text,
+                       PRIMARY KEY (race_id, race_time));"
+    );
+    for (race, time, rider) in [
+        (1, 3, "john"),
+        (1, 1, "smith"),
+        (2, 2, "john"),
+        (3, 5, "jane"),
+    ] {
+        let query = &format!(
+            "INSERT INTO cycling.race_times (race_id, race_time, rider) VALUES ({race}, {time}, '{rider}');"
+        );
+        let _ = exec!(session, query);
+    }
+    let mut times = |query: &str| {
+        let QueryResult::Rows(rows) = session.process(Query::simple(query)?)? else {
+            panic!("invalid return type");
+        };
+        let mut times = rows
+            .rows
+            .into_iter()
+            .map(|it| match it.columns[0] {
+                Some(CqlValue::Int(time)) => time,
+                _ => panic!("invalid time"),
+            })
+            .collect::<Vec<_>>();
+        times.sort();
+        Ok::<_, kassandra::frame::response::error::Error>(times)
+    };
+
+    assert_eq!(
+        times("SELECT race_time FROM cycling.race_times WHERE rider = 'john' ALLOW FILTERING")
+            .unwrap(),
+        vec![2, 3]
+    );
+    assert_eq!(
+        times("SELECT race_time FROM cycling.race_times WHERE race_time = 5 ALLOW FILTERING")
+            .unwrap(),
+        vec![5]
+    );
+    assert_eq!(
+        times("SELECT race_time FROM cycling.race_times WHERE race_id = 1 AND race_time = 1")
+            .unwrap(),
+        vec![1]
+    );
+    assert_eq!(
+        times(
+            "SELECT race_time FROM cycling.race_times WHERE race_id = 1 AND rider = 'smith' \
+             ALLOW FILTERING"
+        )
+        .unwrap(),
+        vec![1]
+    );
+
+    let error =
+        times("SELECT race_time FROM cycling.race_times WHERE race_id = 1 AND rider = 'smith'")
+            .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+    assert!(error.reason.contains("ALLOW FILTERING"));
+}
+
+#[test]
+fn count_rows() {
+    let mut session = session();
+    let _ = exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race_id int,
+                       race_time int,
+                       rider text,
+                       PRIMARY KEY (race_id, race_time));"
+    );
+    for (race, time, rider) in [
+        (1, 3, "john"),
+        (1, 1, "smith"),
+        (2, 2, "john"),
+        (3, 5, "jane"),
+    ] {
+        let query = &format!(
+            "INSERT INTO cycling.race_times (race_id, race_time, rider) VALUES ({race}, {time}, '{rider}');"
+        );
+        let _ = exec!(session, query);
+    }
+    let mut count = |query: &str| {
+        let QueryResult::Rows(rows) = exec!(session, query) else {
+            panic!("invalid return type");
+        };
+        let names = rows.metadata.col_specs.iter().map(|it| it.name.clone());
+        let [Row { columns }] = &rows.rows[..] else {
+            panic!("count is a single row");
+        };
+        (names.collect::<Vec<_>>(), columns[0].clone())
+    };
+
+    assert_eq!(
+        count("SELECT COUNT(*) FROM cycling.race_times"),
+        (vec!["count".to_owned()], Some(CqlValue::BigInt(4)))
+    );
+    assert_eq!(
+        count("SELECT count(1) AS riders FROM cycling.race_times WHERE race_id = 1"),
+        (vec!["riders".to_owned()], Some(CqlValue::BigInt(2)))
+    );
+    assert_eq!(
+        count(
+            "SELECT COUNT(*) FROM cycling.race_times WHERE race_id = 1 AND race_time > 1 LIMIT 1"
+        )
+        .1,
+        Some(CqlValue::BigInt(1))
+    );
+    assert_eq!(
+        count("SELECT COUNT(*) FROM cycling.race_times WHERE rider = 'john' ALLOW FILTERING").1,
+        Some(CqlValue::BigInt(2))
+    );
+    assert_eq!(
+        count("SELECT COUNT(*) FROM cycling.race_times WHERE race_id = 4").1,
+        Some(CqlValue::BigInt(0))
+    );
+    assert_eq!(
+        count("SELECT JSON COUNT(*) FROM cycling.race_times WHERE race_id = 3").1,
+        Some(CqlValue::Text(r#"{"count": 1}"#.into()))
+    );
+}
+
+#[test]
+fn scans_are_not_truncated() {
+    let mut session = session();
+    for id in 0..1200 {
+        let query =
+            &format!("INSERT INTO cycling.cyclist_name (id, lastname) VALUES ({id}, 'VOS');");
+        let _ = exec!(session, query);
+    }
+    let scan = || Query::simple("SELECT id FROM cycling.cyclist_name").unwrap();
+
+    let QueryResult::Rows(rows) = session.process(scan()).unwrap() else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1200);
+    assert!(rows.metadata.paging_state.is_none());
+
+    session.set_scan_limit(NonZeroUsize::new(1000));
+    let error = session.process(scan()).unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    // pages are limited by their size already
+    let mut query = scan();
+    query.parameters.result_page_size = Some(700);
+    let QueryResult::Rows(rows) = session.process(query).unwrap() else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 700);
+    assert!(rows.metadata.paging_state.is_some());
+}
+
+#[test]
+fn tombstones_are_kept_until_compacted() {
+    let mut session = session();
+    for id in 0..3 {
+        let query = &format!(
+            "insert into cycling.cyclist_name (id, lastname, firstname) values ({id}, 'john', 'johnson');"
+        );
+        let _ = exec!(session, query);
+    }
+    let _ = exec!(session, "delete from cycling.cyclist_name where id = 1;");
+    let _ = exec!(session, "delete from cycling.cyclist_name where id = 2;");
+    let _ = exec!(
+        session,
+        "insert into cycling.cyclist_name (id, lastname, firstname) values (2, 'john', 'johnson');"
+    );
+
+    let QueryResult::Rows(rows) =
+        exec!(session, "select id from cycling.cyclist_name where id = 1;")
+    else {
+        panic!("invalid return type");
+    };
+    assert!(rows.rows.is_empty());
+
+    let tombstones = |session: &KassandraSession| {
+        session
+            .data_snapshot_with_tombstones()
+            .0
+            .get("cycling")
+            .map(|it| it.tables["cyclist_name"].tombstones.len())
+            .unwrap_or_default()
+    };
+    // tombstone of a re-inserted row stays to shadow late writes
+    assert_eq!(tombstones(&session), 2);
+    assert!(session.data_snapshot().0["cycling"].tables["cyclist_name"]
+        .tombstones
+        .is_empty());
+
+    assert_eq!(session.compact(), 0);
+    assert_eq!(tombstones(&session), 2);
+
+    session.set_tombstone_retention(Duration::ZERO);
+    assert_eq!(session.compact(), 2);
+    assert_eq!(tombstones(&session), 0);
+}
+
+#[test]
+fn stats_snapshot() {
+    let mut session = session();
+    for id in 0..3 {
+        let query = &format!(
+            "insert into cycling.cyclist_name (id, lastname, firstname) values ({id}, 'john', 'johnson');"
+        );
+        let _ = exec!(session, query);
+    }
+
+    let stats = session.stats_snapshot();
+    let keyspace = &stats.0["cycling"];
+    let table = keyspace.tables["cyclist_name"];
+
+    assert_eq!(table.partitions, 3);
+    assert_eq!(table.rows, 3);
+    // id int (4 + 4), lastname (4 + 4), firstname (4 + 7)
+    assert_eq!(table.bytes, 3 * 27);
+    assert_eq!(keyspace.rows, 3);
+    assert_eq!(keyspace.bytes, table.bytes);
+}
+
+#[test]
+fn stats_snapshot_of_numbers_durations_and_udts() {
+    let mut session = session();
+    for statement in [
+        "CREATE TYPE cycling.address (street text, zip int);",
+        "CREATE TABLE cycling.measures (
+            id int PRIMARY KEY,
+            price decimal,
+            total varint,
+            took duration,
+            home frozen<address>);",
+        r#"INSERT INTO cycling.measures JSON '{"id": 1, "price": 12.34, "total": -128,
+            "took": "1mo2d3ns", "home": {"street": "Main", "zip": 1}}';"#,
+    ] {
+        exec!(session, statement);
+    }
+
+    let table = session.stats_snapshot().0["cycling"].tables["measures"];
+
+    assert_eq!(table.rows, 1);
+    // id (4 + 4), price (4 + 4 + 2), total (4 + 1), took (4 + 3), home (4 + 4 + 4 + 4 + 4)
+    assert_eq!(table.bytes, 50);
+}
+
+#[test]
+fn last_write_wins() {
+    let mut session = session();
+    let lastname = |session: &mut KassandraSession| {
+        let QueryResult::Rows(rows) = exec!(
+            session,
+            "select lastname from cycling.cyclist_name where id = 1;"
+        ) else {
+            panic!("invalid return type");
+        };
+        rows.rows.first().map(|it| it.columns[0].clone())
+    };
+
+    let _ = exec!(
+        session,
+        "insert into cycling.cyclist_name (id, lastname) values (1, 'second') using timestamp 200;"
+    );
+    let _ = exec!(
+        session,
+        "insert into cycling.cyclist_name (id, lastname) values (1, 'first') using timestamp 100;"
+    );
+    assert_eq!(
+        lastname(&mut session),
+        Some(Some(CqlValue::Text("second".into())))
+    );
+
+    let mut query =
+        Query::simple("update cycling.cyclist_name set lastname = 'third' where id = 1;").unwrap();
+    query.parameters.default_timestamp = Some(300);
+    session.process(query).unwrap();
+    assert_eq!(
+        lastname(&mut session),
+        Some(Some(CqlValue::Text("third".into())))
+    );
+
+    let _ = exec!(
+        session,
+        "delete from cycling.cyclist_name using timestamp 400 where id = 1;"
+    );
+    let _ = exec!(
+        session,
+        "update cycling.cyclist_name using timestamp 350 set lastname = 'late' where id = 1;"
+    );
+    assert_eq!(lastname(&mut session), None);
+}
+
+#[test]
+fn unimplemented_statements_policy() {
+    let mut session = session();
+    let statement = "create index on cycling.cyclist_name (lastname);";
+
+    let error = session.process_cql(statement).unwrap_err();
+    assert_eq!(error.error, DbError::Unimplemented);
+
+    session.set_unimplemented_policy(UnimplementedPolicy::WarnAndIgnore);
+    assert!(matches!(
+        session.process_cql(statement),
+        Ok(QueryResult::Void)
+    ));
+    assert!(matches!(
+        session.process_cql("drop index cycling.lastname_idx;"),
+        Ok(QueryResult::Void)
+    ));
+    assert_eq!(session.skipped_statements().len(), 2);
+    assert_eq!(session.skipped_statements()[0].statement, statement);
+
+    // not an unimplemented feature, so still an error
+    assert!(session.process_cql("select from nowhere;").is_err());
+}
+
+#[test]
+fn consistency_policy_fails_statements() {
+    let mut session = session().with_consistency_policy(
+        ConsistencyPolicy::new()
+            .fail_reads(Consistency::Quorum, ConsistencyFailure::Unavailable)
+            .fail_writes(Consistency::All, ConsistencyFailure::Timeout),
+    );
+    let mut at = |statement: &str, consistency| {
+        let mut query = Query::simple(statement).unwrap();
+        query.parameters.consistency = consistency;
+        session.process(query)
+    };
+
+    let error = at("SELECT * FROM cycling.cyclist_name", Consistency::Quorum).unwrap_err();
+    assert!(matches!(
+        error.error,
+        DbError::Unavailable {
+            required: 1,
+            alive: 0,
+            ..
+        }
+    ));
+    assert!(at("SELECT * FROM cycling.cyclist_name", Consistency::One).is_ok());
+
+    let insert = "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS')";
+    assert!(matches!(
+        at(insert, Consistency::All).unwrap_err().error,
+        DbError::WriteTimeout { .. }
+    ));
+    assert!(at(insert, Consistency::Quorum).is_ok());
+}
+
+#[test]
+fn virtual_peers() {
+    let topology = session::Topology::new(4).with_peers(2);
+    let mut session: KassandraSession = KassandraSession::with_topology(topology);
+
+    let QueryResult::Rows(rows) = exec!(session, "select peer, host_id from system.peers;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 2);
+
+    let QueryResult::Rows(rows) = exec!(session, "select peer from system.peers_v2;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 2);
+
+    let mut tokens = (0..3)
+        .flat_map(|node| topology.tokens(node))
+        .collect::<Vec<_>>();
+    tokens.sort();
+    tokens.dedup();
+    assert_eq!(tokens.len(), 12);
+    assert_eq!(topology.tokens(0).len(), 4);
+    assert_ne!(topology.host_id(1), topology.host_id(2));
+}
+
+#[test]
+fn session_config() {
+    let host_id = uuid::uuid!("5f3c6ad2-1d38-4c4e-8d1b-3c9e2f0b7a10");
+    let config = session::SessionConfig::new()
+        .with_topology(session::Topology::new(4).with_peers(1))
+        .with_cluster_name("Production")
+        .with_datacenter("eu-west")
+        .with_rack("rack-b")
+        .with_release_version("4.1.3")
+        .with_tokens(vec![0, 42])
+        .with_host_id(host_id);
+    let mut session: KassandraSession = KassandraSession::with_config(config.clone());
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT cluster_name, data_center, rack, release_version, tokens, host_id FROM system.local"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![
+            Some(CqlValue::Text("Production".into())),
+            Some(CqlValue::Text("eu-west".into())),
+            Some(CqlValue::Text("rack-b".into())),
+            Some(CqlValue::Text("4.1.3".into())),
+            Some(CqlValue::Set(vec![
+                CqlValue::Text("0".into()),
+                CqlValue::Text("42".into())
+            ])),
+            Some(CqlValue::Uuid(host_id)),
+        ]
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT data_center, rack, host_id FROM system.peers"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![
+            Some(CqlValue::Text("eu-west".into())),
+            Some(CqlValue::Text("rack-b".into())),
+            Some(CqlValue::Uuid(config.host_id(1))),
+        ]
+    );
+    assert!(!config.tokens(1).contains(&0));
+}
+
+#[test]
+fn compatibility_profile() {
+    let config = session::SessionConfig::new().with_profile(CompatibilityProfile::Scylla5);
+    let mut session: KassandraSession = KassandraSession::with_config(config);
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT release_version, cql_version FROM system.local"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![
+            Some(CqlValue::Text("3.0.8".into())),
+            Some(CqlValue::Text("3.3.1".into())),
+        ]
+    );
+    let Response::Supported(supported) = session.request(Request::Options) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(supported.options["CQL_VERSION"], vec!["3.3.1".to_owned()]);
+    assert_eq!(
+        supported.options["PROTOCOL_VERSIONS"],
+        vec!["4/v4".to_owned()]
+    );
+
+    let error = session
+        .process(Query::simple("SELECT * FROM system_views.settings").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    session.set_compatibility_profile(Some(CompatibilityProfile::Cassandra4));
+    exec!(session, "SELECT * FROM system_views.settings");
+}
+
+#[test]
+fn keyspace_is_used_per_connection() {
+    let session = session();
+    let mut cycling = ConnectionState::new();
+    let mut other = ConnectionState::new();
+
+    let result = session
+        .process_in(&mut cycling, Query::simple("USE cycling").unwrap())
+        .unwrap();
+    assert!(matches! { result, QueryResult::SetKeyspace(_) });
+    assert_eq!(cycling.keyspace(), Some("cycling"));
+    assert_eq!(other.keyspace(), None);
+
+    let insert =
+        "insert into cyclist_name (id, lastname, firstname) values (1, 'john', 'johnson');";
+    session
+        .process_in(&mut cycling, Query::simple(insert).unwrap())
+        .unwrap();
+    let error = session
+        .process_in(&mut other, Query::simple(insert).unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    // prepared statement keeps the keyspace of the connection it was prepared on
+    let QueryResult::Prepared(prepared) = session
+        .prepare_in(
+            &cycling,
+            Prepare::simple("select * from cyclist_name").unwrap(),
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let id = prepared.id.to_be_bytes();
+    let QueryResult::Rows(rows) = session
+        .execute_in(
+            &mut other,
+            Execute {
+                id: &id,
+                parameters: Default::default(),
+            },
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+}
+
+#[test]
+fn handles_share_session_across_threads() {
+    let mut session = session();
+    let threads = (0..4)
+        .map(|thread| {
+            let handle = session.handle();
+            std::thread::spawn(move || {
+                let mut connection = ConnectionState::new();
+                for id in 0..25 {
+                    let insert = format!(
+                        "insert into cycling.cyclist_name (id, lastname) values ({}, 'rider');",
+                        thread * 100 + id
+                    );
+                    handle
+                        .process_in(&mut connection, Query::simple(&insert).unwrap())
+                        .unwrap();
+                    let select = "select * from cycling.cyclist_name;";
+                    handle
+                        .process_in(&mut connection, Query::simple(select).unwrap())
+                        .unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let QueryResult::Rows(rows) = exec!(session, "select * from cycling.cyclist_name;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 100);
+}
+
+#[test]
+fn streamed_rows_match_materialized_rows() {
+    let session = session();
+    let mut connection = ConnectionState::new();
+    for id in 0..2500 {
+        let insert =
+            format!("insert into cycling.cyclist_name (id, lastname) values ({id}, 'rider');");
+        session
+            .process_in(&mut connection, Query::simple(&insert).unwrap())
+            .unwrap();
+    }
+
+    let select = |connection: &mut ConnectionState| {
+        let mut query = Query::simple("select * from cycling.cyclist_name;").unwrap();
+        query.parameters.result_page_size = Some(2000);
+        session.process_in(connection, query).unwrap()
+    };
+    let materialized = select(&mut connection);
+    let streamed = select(&mut ConnectionState::new().with_streamed_rows());
+    let QueryResult::RowStream(stream) = &streamed else {
+        panic!("invalid return type");
+    };
+    let rows_written = stream.rows_written();
+
+    let mut expected = Vec::new();
+    materialized.serialize(&mut expected).unwrap();
+    let mut actual = Vec::new();
+    streamed.serialize(&mut actual).unwrap();
+    assert_eq!(actual, expected);
+    assert_eq!(
+        rows_written.load(std::sync::atomic::Ordering::Relaxed),
+        2000
+    );
+}
+
+#[test]
+fn streamed_partition_rows_match_materialized_rows() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.stages (race int, stage int, winner text, PRIMARY KEY (race, stage))
+           WITH CLUSTERING ORDER BY (stage DESC);"
+    );
+    let mut connection = ConnectionState::new();
+    for stage in 0..2500 {
+        let insert = format!(
+            "insert into cycling.stages (race, stage, winner) values (1, {stage}, 'rider');"
+        );
+        session
+            .process_in(&mut connection, Query::simple(&insert).unwrap())
+            .unwrap();
+    }
+
+    let select = |connection: &mut ConnectionState| {
+        let mut query = Query::simple("select * from cycling.stages where race = 1;").unwrap();
+        query.parameters.result_page_size = Some(2000);
+        session.process_in(connection, query).unwrap()
+    };
+    let materialized = select(&mut connection);
+    let streamed = select(&mut ConnectionState::new().with_streamed_rows());
+    let QueryResult::RowStream(stream) = &streamed else {
+        panic!("invalid return type");
+    };
+    let rows_written = stream.rows_written();
+
+    let mut expected = Vec::new();
+    materialized.serialize(&mut expected).unwrap();
+    let mut actual = Vec::new();
+    streamed.serialize(&mut actual).unwrap();
+    assert_eq!(actual, expected);
+    assert_eq!(
+        rows_written.load(std::sync::atomic::Ordering::Relaxed),
+        2000
+    );
+}
+
+#[test]
+fn decimal_varint_duration_and_udt_values_are_bound_and_paged() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TYPE cycling.sponsor (name text, amount varint);"
+    );
+    exec!(
+        session,
+        "CREATE TABLE cycling.payouts (
+           race int, fee decimal, prize varint, sponsor frozen<sponsor>, delay duration,
+           PRIMARY KEY (race, fee, prize, sponsor));"
+    );
+    let prepare = |session: &mut KassandraSession, query| {
+        let QueryResult::Prepared(prepared) =
+            session.prepare(Prepare::simple(query).unwrap()).unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        prepared.id.to_be_bytes()
+    };
+
+    let insert = prepare(
+        &mut session,
+        "INSERT INTO cycling.payouts (race, fee, prize, sponsor, delay) VALUES (1, ?, ?, ?, ?)",
+    );
+    for i in 0..5_u8 {
+        // scale 1, unscaled `i`
+        let fee = [0, 0, 0, 1, i];
+        let prize = [0x01, i];
+        // `name` is left null, `amount` is `-i`
+        let sponsor = [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1, i.wrapping_neg()];
+        // zigzag encoded months, days and nanoseconds
+        let delay = [2, 4, 2 * i];
+        session
+            .execute(Execute {
+                id: &insert,
+                parameters: QueryParameters {
+                    data: vec![
+                        FrameValue::Some(&fee),
+                        FrameValue::Some(&prize),
+                        FrameValue::Some(&sponsor),
+                        FrameValue::Some(&delay),
+                    ],
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+    }
+    let truncated = session.execute(Execute {
+        id: &insert,
+        parameters: QueryParameters {
+            data: vec![
+                FrameValue::Some(&[0, 0, 1]),
+                FrameValue::Some(&[1]),
+                FrameValue::Some(&[]),
+                FrameValue::Some(&[2, 4]),
+            ],
+            ..Default::default()
+        },
+    });
+    assert_eq!(truncated.unwrap_err().error, DbError::Invalid);
+
+    let select = prepare(
+        &mut session,
+        "SELECT fee, prize, sponsor, delay FROM cycling.payouts WHERE race = ?",
+    );
+    let mut pages = vec![];
+    let mut paging_state = None;
+    loop {
+        let QueryResult::Rows(rows) = session
+            .execute(Execute {
+                id: &select,
+                parameters: QueryParameters {
+                    data: vec![FrameValue::Some(&[0, 0, 0, 1])],
+                    result_page_size: Some(2),
+                    paging_state,
+                    ..Default::default()
+                },
+            })
+            .unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        pages.push(rows.rows.len());
+        for row in rows.rows {
+            assert_eq!(row.columns.len(), 4);
+            let [fee, prize, sponsor, delay] = &row.columns[..] else {
+                unreachable!()
+            };
+            let i = match fee {
+                Some(CqlValue::Decimal(fee)) => {
+                    let (unscaled, scale) = fee.as_bigint_and_exponent();
+                    assert_eq!(scale, 1);
+                    u8::try_from(unscaled).unwrap()
+                }
+                other => panic!("unexpected fee {other:?}"),
+            };
+            assert_eq!(
+                prize,
+                &Some(CqlValue::Varint(num_bigint::BigInt::from(256 + i as i32)))
+            );
+            assert_eq!(
+                sponsor,
+                &Some(CqlValue::UserDefinedType {
+                    keyspace: "cycling".to_owned(),
+                    type_name: "sponsor".to_owned(),
+                    fields: vec![
+                        ("name".to_owned(), None),
+                        (
+                            "amount".to_owned(),
+                            Some(CqlValue::Varint(num_bigint::BigInt::from(
+                                i.wrapping_neg() as i8
+                            )))
+                        ),
+                    ],
+                })
+            );
+            assert_eq!(
+                delay,
+                &Some(CqlValue::Duration(CqlDuration {
+                    months: 1,
+                    days: 2,
+                    nanoseconds: i as i64,
+                }))
+            );
+        }
+        paging_state = rows.metadata.paging_state;
+        if paging_state.is_none() {
+            break;
+        }
+    }
+    assert_eq!(pages, [2, 2, 1]);
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT prize FROM cycling.payouts WHERE race = 1 AND fee = 0.3 AND prize = 259"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+}
+
+#[test]
+fn prepared_statements_are_deduplicated_and_evicted() {
+    let session = session();
+    session.set_prepared_statements_capacity(NonZeroUsize::new(1).unwrap());
+    let connection = ConnectionState::new();
+    let prepare = |query| {
+        let QueryResult::Prepared(prepared) = session
+            .prepare_in(&connection, Prepare::simple(query).unwrap())
+            .unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        prepared.id.to_be_bytes()
+    };
+
+    let select = prepare("select * from cycling.cyclist_name");
+    assert_eq!(select, prepare("select * from cycling.cyclist_name"));
+
+    let other = prepare("select id from cycling.cyclist_name");
+    assert_ne!(select, other);
+
+    let execute = |id| {
+        session.execute_in(
+            &mut ConnectionState::new(),
+            Execute {
+                id,
+                parameters: Default::default(),
+            },
+        )
+    };
+    assert!(execute(&other).is_ok());
+    let error = execute(&select).unwrap_err();
+    assert!(
+        matches! { error.error, DbError::Unprepared { statement_id } if statement_id == select[..] }
+    );
+}
+
+#[test]
+fn prepared_statements_are_looked_up_by_id() {
+    let mut session = session();
+    let QueryResult::Prepared(prepared) = session
+        .prepare(Prepare::simple("/* by id */select * from cycling.cyclist_name").unwrap())
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+
+    assert!(matches!(
+        session.prepared_statement(&prepared.id.to_be_bytes()),
+        Some(QueryString::Select(select)) if select.table == "cyclist_name"
+    ));
+    assert!(session.prepared_statement(&7u128.to_be_bytes()).is_none());
+    assert!(session.prepared_statement(&[1, 2]).is_none());
+}
+
+#[test]
+fn states_of_other_layouts_are_rejected() {
+    let session = session();
+    let state = String::from_utf8(session.save_state()).unwrap();
+    assert!(state.starts_with(&format!("(\n    version: {STATE_VERSION},")));
+
+    let newer = state.replacen(
+        &format!("version: {STATE_VERSION}"),
+        &format!("version: {}", STATE_VERSION + 1),
+        1,
+    );
+    let error = KassandraSession::load_state(newer.as_bytes()).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "State has layout version {}, this kassandra reads version {STATE_VERSION}",
+            STATE_VERSION + 1
+        )
+    );
+}
+
+/// State saved before states had a layout version, trimmed down to a row of `system.local`,
+/// a system table and a user table
+const UNVERSIONED_STATE: &str = r#"(
+    data: (
+        data: {
+            "system": {
+                "local": {
+                    Simple(Text("local")): {
+                        Empty: {
+                            "cluster_name": Text("Test Cluster"),
+                            "key": Text("local"),
+                        },
+                    },
+                },
+            },
+            "ks": {
+                "t": {
+                    Simple(Int(1)): {
+                        Simple(Some(Int(1))): {
+                            "c": Int(1),
+                            "k": Int(1),
+                            "v": Text("one"),
+                        },
+                    },
+                    Simple(Int(2)): {
+                        Simple(Some(Int(1))): {
+                            "c": Int(1),
+                            "k": Int(2),
+                            "v": Text("two"),
+                        },
+                    },
+                },
+            },
+        },
+    ),
+    schema: (
+        schema: {
+            "ks": (
+                name: "ks",
+                strategy: LocalStrategy,
+                tables: {
+                    "t": (
+                        keyspace: "ks",
+                        name: "t",
+                        schema: (
+                            columns: {
+                                "k": (ty: Int, kind: PartitionKey),
+                                "c": (ty: Int, kind: Clustering),
+                                "v": (ty: Text, kind: Regular),
+                            },
+                            partition_key: Simple("k"),
+                            clustering_key: Simple("c"),
+                            partitioner: None,
+                        ),
+                    ),
+                },
+                user_defined_types: {},
+            ),
+            "system": (
+                name: "system",
+                strategy: LocalStrategy,
+                tables: {
+                    "available_ranges": (
+                        keyspace: "system",
+                        name: "available_ranges",
+                        schema: (
+                            columns: {
+                                "keyspace_name": (ty: Text, kind: PartitionKey),
+                                "ranges": (ty: Set(Blob), kind: Regular),
+                            },
+                            partition_key: Simple("keyspace_name"),
+                            clustering_key: Empty,
+                            partitioner: None,
+                        ),
+                    ),
+                },
+                user_defined_types: {},
+            ),
+        },
+    ),
+)"#;
+
+#[test]
+fn unversioned_states_are_migrated() {
+    let mut session = KassandraSession::load_state(UNVERSIONED_STATE.as_bytes()).unwrap();
+
+    // partitions are keyed by their token again, so they are scanned in the token order
+    let QueryResult::Rows(rows) = exec!(session, "SELECT k, v FROM ks.t;") else {
+        panic!("invalid return type");
+    };
+    let partitioner = Murmur3Partitioner;
+    let mut expected = [(1, "one"), (2, "two")];
+    expected.sort_by_key(|(k, _)| partitioner.token(&PartitionKeyValue::Simple(CqlValue::Int(*k))));
+    assert_eq!(
+        rows.rows
+            .into_iter()
+            .map(|row| row.columns)
+            .collect::<Vec<_>>(),
+        expected
+            .map(|(k, v)| vec![Some(CqlValue::Int(k)), Some(CqlValue::Text(v.into()))])
+            .to_vec()
+    );
+
+    // migrated cells are older than any write made afterwards
+    exec!(
+        session,
+        "UPDATE ks.t USING TIMESTAMP 1 SET v = 'new' WHERE k = 2 AND c = 1;"
+    );
+    let QueryResult::Rows(rows) = exec!(session, "SELECT v FROM ks.t WHERE k = 2;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![Some(CqlValue::Text("new".into()))]
+    );
+
+    // system keyspaces are the ones of a new session
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT keyspace_name FROM system_schema.keyspaces WHERE keyspace_name = 'system_auth';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+
+    let state = String::from_utf8(session.save_state()).unwrap();
+    assert!(state.starts_with(&format!("(\n    version: {STATE_VERSION},")));
+}
+
+#[test]
+fn prepared_statements_are_kept_in_state() {
+    let mut session = session();
+    let QueryResult::Prepared(prepared) = session
+        .prepare(Prepare::simple("select * from cycling.cyclist_name").unwrap())
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let id = prepared.id.to_be_bytes();
+
+    let mut loaded = KassandraSession::load_state(&session.save_state()).unwrap();
+    let execute = Execute {
+        id: &id,
+        parameters: Default::default(),
+    };
+    assert!(matches! { loaded.execute(execute).unwrap(), QueryResult::Rows(_) });
+
+    let QueryResult::Rows(rows) = exec!(
+        loaded,
+        "select prepared_id, logged_keyspace from system.prepared_statements;"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![
+            Some(CqlValue::Blob(id.to_vec().into())),
+            Some(CqlValue::Text("cycling".into()))
+        ]
+    );
+}
+
+#[test]
+fn update_rejects_primary_key_assignments() {
+    let mut session = session();
+    let error = session
+        .process(Query::simple("update cycling.cyclist_name set id = 2 where id = 1;").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    let QueryResult::Prepared(prepared) = session
+        .prepare(
+            Prepare::simple("update cycling.cyclist_name set lastname = ? where id = ?").unwrap(),
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(prepared.prepared_metadata.col_specs.len(), 2);
+    assert_eq!(prepared.prepared_metadata.pk_indexes[0].index, 1);
+}
+
+#[test]
+fn prepared_statements_mix_literals_and_bind_markers() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race text,
+                       year int,
+                       rank int,
+                       cyclist text,
+                       PRIMARY KEY ((race, year), rank));"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.race_times (race, year, rank, cyclist) VALUES ('Tour', 2019, 1, 'Egan');"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.race_times (race, year, rank, cyclist) VALUES ('Tour', 2019, 2, 'Geraint');"
+    );
+
+    let mut prepare = |query: &str| {
+        let QueryResult::Prepared(prepared) =
+            session.prepare(Prepare::simple(query).unwrap()).unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        prepared
+    };
+    let update = prepare(
+        "UPDATE cycling.race_times SET cyclist = ? WHERE race = ? AND year = ? AND rank = 2",
+    );
+    let select = prepare(
+        "SELECT cyclist FROM cycling.race_times WHERE race = 'Tour' AND year = ? AND rank = ?",
+    );
+
+    let names = |metadata: &PreparedMetadata| {
+        metadata
+            .col_specs
+            .iter()
+            .map(|it| it.name.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        names(&update.prepared_metadata),
+        ["cyclist", "race", "year"]
+    );
+    let indexes = update
+        .prepared_metadata
+        .pk_indexes
+        .iter()
+        .map(|it| (it.index, it.sequence))
+        .collect::<Vec<_>>();
+    assert_eq!(indexes, [(1, 0), (2, 1)]);
+    assert_eq!(names(&select.prepared_metadata), ["year", "rank"]);
+    // `race` is a literal, so the partition key can't be computed from bound values alone
+    assert!(select.prepared_metadata.pk_indexes.is_empty());
+
+    let id = update.id.to_be_bytes();
+    session
+        .execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data: vec![
+                    FrameValue::Some(b"Thomas"),
+                    FrameValue::Some(b"Tour"),
+                    FrameValue::Some(&2019_i32.to_be_bytes()),
+                ],
+                ..Default::default()
+            },
+        })
+        .unwrap();
+
+    let id = select.id.to_be_bytes();
+    let QueryResult::Rows(rows) = session
+        .execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data: vec![
+                    FrameValue::Some(&2019_i32.to_be_bytes()),
+                    FrameValue::Some(&2_i32.to_be_bytes()),
+                ],
+                ..Default::default()
+            },
+        })
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+    assert_eq!(
+        rows.rows[0].columns[0],
+        Some(CqlValue::Text("Thomas".into()))
+    );
+}
+
+#[test]
+fn bound_values_are_checked_against_column_types() {
+    let mut session = session();
+    let QueryResult::Prepared(prepared) = session
+        .prepare(
+            Prepare::simple(
+                "insert into cycling.cyclist_name (id, lastname, records) values (?, ?, ?)",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let id = prepared.id.to_be_bytes();
+    let mut execute = |data: Vec<FrameValue<'static>>| {
+        session.execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data,
+                ..Default::default()
+            },
+        })
+    };
+
+    // one entry: key `f1` and a value which claims more bytes than there are
+    let truncated_map = b"\0\0\0\x01\0\0\0\x02f1\0\0\0\x09120";
+    let cases: [(Vec<FrameValue<'static>>, &str); 3] = [
+        (
+            vec![FrameValue::Some(b"one"), FrameValue::Null, FrameValue::Null],
+            "`id`: expected 4 bytes for int, got 3",
+        ),
+        (
+            vec![
+                FrameValue::Some(&[0, 0, 0, 1]),
+                FrameValue::Some(&[0xff, 0xfe]),
+                FrameValue::Null,
+            ],
+            "`lastname`: invalid utf-8",
+        ),
+        (
+            vec![
+                FrameValue::Some(&[0, 0, 0, 1]),
+                FrameValue::Null,
+                FrameValue::Some(truncated_map),
+            ],
+            "`records`: truncated",
+        ),
+    ];
+    for (data, message) in cases {
+        let error = execute(data).unwrap_err();
+        assert_eq!(error.error, DbError::Invalid);
+        assert!(error.reason.contains(message), "{}", error.reason);
+    }
+
+    let result = execute(vec![
+        FrameValue::Some(&[0, 0, 0, 1]),
+        FrameValue::Some(b"john"),
+        FrameValue::Some(b"\0\0\0\x01\0\0\0\x02f1\0\0\0\x03120"),
+    ])
+    .unwrap();
+    assert!(matches!(result, QueryResult::Void));
+}
+
+#[test]
+fn undefined_columns_and_missing_primary_key_parts_are_rejected() {
+    let mut session = session();
+    for query in [
+        "insert into cycling.cyclist_name (id, nickname) values (1, 'jj');",
+        "update cycling.cyclist_name set nickname = 'jj' where id = 1;",
+        "select nickname from cycling.cyclist_name;",
+        "delete nickname from cycling.cyclist_name where id = 1;",
+    ] {
+        let error = session.process(Query::simple(query).unwrap()).unwrap_err();
+        assert_eq!(error.error, DbError::Invalid);
+        assert_eq!(error.reason, "Undefined column name nickname", "{query}");
+    }
+
+    exec!(
+        session,
+        "CREATE TABLE cycling.stages (race int, stage int, winner text, PRIMARY KEY (race, stage));"
+    );
+    let delete_winner = "delete winner from cycling.stages where race = 1;";
+    exec!(session, delete_winner);
+
+    session.set_strict_mode(true);
+    for (query, reason) in [
+        (
+            "insert into cycling.stages (race, winner) values (1, 'john');",
+            "Some clustering keys are missing: stage",
+        ),
+        (
+            "update cycling.stages set winner = 'john' where stage = 1;",
+            "Some partition key parts are missing: race",
+        ),
+        (delete_winner, "Some clustering keys are missing: stage"),
+    ] {
+        let error = session.process(Query::simple(query).unwrap()).unwrap_err();
+        assert_eq!(error.error, DbError::Invalid);
+        assert_eq!(error.reason, reason, "{query}");
+    }
+
+    let error = session
+        .prepare(Prepare::simple("insert into cycling.stages (stage) values (?)").unwrap())
+        .unwrap_err();
+    assert_eq!(error.reason, "Some partition key parts are missing: race");
+}
+
+#[test]
+fn error_messages_can_match_cassandra() {
+    let mut session = session();
+    let queries = [
+        ("select * from cycling.stages;", Consistency::One),
+        ("select * from racing.stages;", Consistency::One),
+        ("select * from cycling.cyclist_name;", Consistency::Any),
+    ];
+    let renderers = [
+        (
+            ErrorRenderer::Kassandra,
+            [
+                "Table cycling.stages does not exist",
+                "Keyspace racing does not exist",
+                "Any consistency can only be used for writes",
+            ],
+        ),
+        (
+            ErrorRenderer::Cassandra4,
+            [
+                "unconfigured table stages",
+                "keyspace racing does not exist",
+                "ANY ConsistencyLevel is only supported for writes",
+            ],
+        ),
+    ];
+    for (renderer, reasons) in renderers {
+        session.set_error_renderer(renderer);
+        for ((query, consistency), reason) in queries.into_iter().zip(reasons) {
+            let mut query = Query::simple(query).unwrap();
+            query.parameters.consistency = consistency;
+            let error = session.process(query).unwrap_err();
+            assert_eq!(error.error, DbError::Invalid);
+            assert_eq!(error.reason, reason);
+        }
+    }
+
+    let error = session
+        .prepare(Prepare::simple("insert into cycling.stages (id) values (?)").unwrap())
+        .unwrap_err();
+    assert_eq!(error.reason, "unconfigured table stages");
+}
+
+#[test]
+fn unset_values_leave_cells_untouched() {
+    let mut session = session();
+    exec!(
+        session,
+        "insert into cycling.cyclist_name (id, lastname, firstname) values (1, 'johnson', 'john');"
+    );
+    let QueryResult::Prepared(prepared) = session
+        .prepare(
+            Prepare::simple(
+                "insert into cycling.cyclist_name (id, lastname, firstname) values (?, ?, ?)",
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let id = prepared.id.to_be_bytes();
+    let mut execute = |data: Vec<FrameValue<'static>>| {
+        session.execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data,
+                ..Default::default()
+            },
+        })
+    };
+
+    execute(vec![
+        FrameValue::Some(&[0, 0, 0, 1]),
+        FrameValue::Null,
+        FrameValue::NotSet,
+    ])
+    .unwrap();
+    let error = execute(vec![
+        FrameValue::NotSet,
+        FrameValue::Null,
+        FrameValue::NotSet,
+    ])
+    .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+    assert_eq!(error.reason, "Invalid unset value for column id");
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "select lastname, firstname from cycling.cyclist_name where id = 1;"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![None, Some(CqlValue::Text("john".into()))]
+    );
+}
+
+#[test]
+fn insert_json_and_from_json() {
+    let mut session = session();
+    for statement in [
+        "CREATE TYPE cycling.address (street text, zip int);",
+        "CREATE TABLE cycling.profiles (
+            id int PRIMARY KEY,
+            name text,
+            tags set<int>,
+            home frozen<address>,
+            scores map<int, text>,
+            seen timestamp);",
+    ] {
+        exec!(session, statement);
+    }
+
+    exec!(
+        session,
+        r#"INSERT INTO cycling.profiles JSON '{"id": 1, "name": "VOS", "tags": [3, 1, 3],
+            "home": {"street": "Main"}, "scores": {"10": "a", "9": "b"},
+            "seen": "2017-04-01 10:15:00+0000"}';"#
+    );
+    let select = |session: &mut kassandra::KassandraSession, id: i32| {
+        let QueryResult::Rows(rows) = session
+            .process(
+                Query::simple(&format!(
+                    "SELECT name, tags, home, scores, seen FROM cycling.profiles WHERE id = {id}"
+                ))
+                .unwrap(),
+            )
+            .unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        rows.rows[0].columns.clone()
+    };
+    assert_eq!(
+        select(&mut session, 1),
+        vec![
+            Some(CqlValue::Text("VOS".into())),
+            Some(CqlValue::Set(vec![CqlValue::Int(1), CqlValue::Int(3)])),
+            Some(CqlValue::UserDefinedType {
+                keyspace: "cycling".to_owned(),
+                type_name: "address".to_owned(),
+                fields: vec![
+                    ("street".to_owned(), Some(CqlValue::Text("Main".into()))),
+                    ("zip".to_owned(), None),
+                ],
+            }),
+            Some(CqlValue::Map(vec![
+                (CqlValue::Int(9), CqlValue::Text("b".into())),
+                (CqlValue::Int(10), CqlValue::Text("a".into())),
+            ])),
+            Some(CqlValue::Timestamp(1491041700000)),
+        ]
+    );
+
+    // missing columns are left untouched with DEFAULT UNSET and cleared otherwise
+    exec!(
+        session,
+        r#"INSERT INTO cycling.profiles JSON '{"ID": 1, "tags": null}' DEFAULT UNSET"#
+    );
+    let row = select(&mut session, 1);
+    assert_eq!(row[0], Some(CqlValue::Text("VOS".into())));
+    assert_eq!(row[1], None);
+    exec!(session, r#"INSERT INTO cycling.profiles JSON '{"id": 1}'"#);
+    assert_eq!(select(&mut session, 1), vec![None; 5]);
+
+    exec!(
+        session,
+        r#"INSERT INTO cycling.profiles (id, tags) VALUES (2, fromJson('["2", 1]'))"#
+    );
+    exec!(
+        session,
+        r#"UPDATE cycling.profiles SET seen = fromJson('1000'), name = fromJson('"x"') WHERE id = 2"#
+    );
+    assert_eq!(
+        select(&mut session, 2),
+        vec![
+            Some(CqlValue::Text("x".into())),
+            Some(CqlValue::Set(vec![CqlValue::Int(1), CqlValue::Int(2)])),
+            None,
+            None,
+            Some(CqlValue::Timestamp(1000)),
+        ]
+    );
+
+    let mut prepare = |query: &str| {
+        let QueryResult::Prepared(prepared) =
+            session.prepare(Prepare::simple(query).unwrap()).unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        prepared
+    };
+    let insert = prepare("INSERT INTO cycling.profiles JSON ?");
+    let names = insert
+        .prepared_metadata
+        .col_specs
+        .iter()
+        .map(|it| (&*it.name, it.typ.to_string()));
+    assert_eq!(names.collect::<Vec<_>>(), [("[json]", "text".to_owned())]);
+    let update = prepare("UPDATE cycling.profiles SET tags = fromJson(?) WHERE id = ?");
+    let names = update
+        .prepared_metadata
+        .col_specs
+        .iter()
+        .map(|it| (&*it.name, it.typ.to_string()));
+    assert_eq!(
+        names.collect::<Vec<_>>(),
+        [("tags", "text".to_owned()), ("id", "int".to_owned())]
+    );
+
+    let mut execute = |id: u128, data: Vec<FrameValue<'static>>| {
+        session.execute(Execute {
+            id: &id.to_be_bytes(),
+            parameters: QueryParameters {
+                data,
+                ..Default::default()
+            },
+        })
+    };
+    execute(
+        insert.id,
+        vec![FrameValue::Some(br#"{"id": 3, "name": "bound"}"#)],
+    )
+    .unwrap();
+    execute(
+        update.id,
+        vec![FrameValue::Some(b"[5]"), FrameValue::Some(&[0, 0, 0, 3])],
+    )
+    .unwrap();
+    let row = select(&mut session, 3);
+    assert_eq!(row[0], Some(CqlValue::Text("bound".into())));
+    assert_eq!(row[1], Some(CqlValue::Set(vec![CqlValue::Int(5)])));
+
+    for (invalid, reason) in [
+        (
+            r#"INSERT INTO cycling.profiles JSON '{"id": 4, "nickname": "x"}'"#,
+            "Undefined column name nickname",
+        ),
+        (
+            r#"INSERT INTO cycling.profiles JSON '[1]'"#,
+            "Could not decode JSON string as a map: [1]",
+        ),
+        (
+            r#"INSERT INTO cycling.profiles JSON '{"id": 4, "home": {"city": "x"}}'"#,
+            "Unknown field 'city' in value of user defined type address",
+        ),
+        (
+            r#"INSERT INTO cycling.profiles JSON '{"id": "four"}'"#,
+            "Unable to make int from \"four\", expected a number or a numeric string",
+        ),
+        (
+            "INSERT INTO cycling.profiles (id, tags) VALUES (4, fromJson(1))",
+            "fromJson() of column tags only accepts json strings",
+        ),
+    ] {
+        let error = session
+            .process(Query::simple(invalid).unwrap())
+            .unwrap_err();
+        assert_eq!(error.error, DbError::Invalid, "{invalid}");
+        assert_eq!(error.reason, reason, "{invalid}");
+    }
+    let error = session
+        .process(Query::simple(r#"INSERT INTO cycling.profiles JSON '{"name": "x"}'"#).unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+}
+
+#[test]
+fn nulls_are_kept_apart_from_empty_values() {
+    let mut session = session();
+    exec!(
+        session,
+        "insert into cycling.cyclist_name (id, lastname, firstname, records) values (1, '', null, {'f1': '120'});"
+    );
+    exec!(
+        session,
+        "delete records from cycling.cyclist_name where id = 1;"
+    );
+
+    let mut loaded = KassandraSession::load_state(&session.save_state()).unwrap();
+    for session in [&mut session, &mut loaded] {
+        let QueryResult::Rows(rows) = exec!(
+            session,
+            "select lastname, firstname, records from cycling.cyclist_name where id = 1;"
+        ) else {
+            panic!("invalid return type");
+        };
+        assert_eq!(
+            rows.rows[0].columns,
+            vec![Some(CqlValue::Text("".into())), None, None]
+        );
+    }
+
+    let snapshot = session.data_snapshot();
+    let row = &snapshot.0["cycling"].tables["cyclist_name"].rows[0];
+    assert_eq!(row.data["lastname"], ValueSnapshot::Text("".to_owned()));
+    assert_eq!(row.data["firstname"], ValueSnapshot::Null);
+    assert_eq!(row.data["records"], ValueSnapshot::Null);
+    assert_eq!(
+        serde_json::to_string(&[ValueSnapshot::Empty, ValueSnapshot::Null]).unwrap(),
+        r#"["",null]"#
+    );
+}
+
+#[test]
+fn describe_renders_schema() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race text,
+                       year int,
+                       rank int,
+                       cyclist text,
+                       PRIMARY KEY ((race, year), rank))
+                       WITH CLUSTERING ORDER BY (rank DESC);"
+    );
+
+    let QueryResult::Rows(rows) = exec!(session, "DESCRIBE KEYSPACES;") else {
+        panic!("invalid return type");
+    };
+    let keyspaces = rows
+        .rows
+        .iter()
+        .map(|row| row.columns[2].clone().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        keyspaces,
+        [
+            "cycling",
+            "system",
+            "system_auth",
+            "system_schema",
+            "system_views"
+        ]
+        .map(|it| CqlValue::Text(it.into()))
+    );
+
+    let QueryResult::Rows(rows) = exec!(session, "DESC TABLE cycling.race_times") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns[3],
+        Some(CqlValue::Text(
+            "CREATE TABLE cycling.race_times (
+    race text,
+    year int,
+    rank int,
+    cyclist text,
+    PRIMARY KEY ((race, year), rank)
+) WITH CLUSTERING ORDER BY (rank DESC);"
+                .into()
+        ))
+    );
+
+    session.use_keyspace("cycling");
+    let QueryResult::Rows(rows) = exec!(session, "DESCRIBE KEYSPACE") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 3);
+    assert_eq!(
+        rows.rows[2].columns[3],
+        Some(CqlValue::Text(
+            "CREATE TABLE cycling.race_times (
+    race text,
+    year int,
+    rank int,
+    cyclist text,
+    PRIMARY KEY ((race, year), rank)
+) WITH CLUSTERING ORDER BY (rank DESC);"
+                .into()
+        ))
+    );
+
+    let error = session
+        .process(Query::simple("DESCRIBE TABLE cycling.missing").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+}
+
+#[test]
+fn system_schema_reflects_catalog() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race text,
+                       year int,
+                       rank int,
+                       cyclist text,
+                       PRIMARY KEY ((year, race), rank))
+                       WITH CLUSTERING ORDER BY (rank DESC);"
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT column_name, kind, position, clustering_order, type FROM system_schema.columns \
+         WHERE keyspace_name = 'cycling' AND table_name = 'race_times';"
+    ) else {
+        panic!("invalid return type");
+    };
+    let columns = rows
+        .rows
+        .into_iter()
+        .map(|row| {
+            row.columns
+                .into_iter()
+                .map(Option::unwrap)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        columns,
+        [
+            ("cyclist", "regular", -1, "none", "text"),
+            ("race", "partition_key", 1, "none", "text"),
+            ("rank", "clustering", 0, "desc", "int"),
+            ("year", "partition_key", 0, "none", "int"),
+        ]
+        .map(|(name, kind, position, order, ty)| vec![
+            CqlValue::Text(name.into()),
+            CqlValue::Text(kind.into()),
+            CqlValue::Int(position),
+            CqlValue::Text(order.into()),
+            CqlValue::Text(ty.into()),
+        ])
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT durable_writes FROM system_schema.keyspaces WHERE keyspace_name = 'cycling';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Boolean(true)));
+}
+
+#[test]
+fn system_views_expose_server_state() {
+    let mut session = session();
+    let views = SystemViews::new();
+    views.set_setting("num_tokens", 16);
+    session.set_system_views(views.clone());
+
+    let client = views.connect("127.0.0.1:4242".parse().unwrap(), false);
+    client.startup(
+        4,
+        &[("DRIVER_NAME".to_owned(), "test driver".to_owned())].into(),
+    );
+    client.request(Some("cycling"));
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT port, driver_name, keyspace_name, request_count FROM system_views.clients;"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        [
+            Some(CqlValue::Int(4242)),
+            Some(CqlValue::Text("test driver".into())),
+            Some(CqlValue::Text("cycling".into())),
+            Some(CqlValue::BigInt(1)),
+        ]
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT value FROM system_views.settings WHERE name = 'num_tokens';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Text("16".into())));
+
+    drop(client);
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT value FROM system_views.metrics WHERE name = 'connected_clients';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::BigInt(0)));
+}
+
+#[test]
+fn keyspace_replication_is_parsed() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE KEYSPACE tour WITH REPLICATION = {
+           'class' : 'org.apache.cassandra.locator.NetworkTopologyStrategy',
+           'replication_factor' : 3,
+           'dc2' : '2'
+          };"
+    );
+
+    let replication = |session: &mut KassandraSession, keyspace: &str| {
+        let query = format!(
+            "SELECT replication FROM system_schema.keyspaces WHERE keyspace_name = '{keyspace}';"
+        );
+        let QueryResult::Rows(rows) = session.process(Query::simple(&query).unwrap()).unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        let Some(CqlValue::Map(replication)) = rows.rows[0].columns[0].clone() else {
+            panic!("replication is not a map");
+        };
+        replication
+            .into_iter()
+            .map(|(key, value)| match (key, value) {
+                (CqlValue::Text(key), CqlValue::Text(value)) => {
+                    (key.to_string(), value.to_string())
+                }
+                other => panic!("unexpected replication entry {other:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+    let expected = |entries: &[(&str, &str)]| {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        replication(&mut session, "cycling"),
+        expected(&[
+            ("class", "org.apache.cassandra.locator.SimpleStrategy"),
+            ("replication_factor", "1"),
+        ])
+    );
+    assert_eq!(
+        replication(&mut session, "tour"),
+        expected(&[
+            (
+                "class",
+                "org.apache.cassandra.locator.NetworkTopologyStrategy"
+            ),
+            ("datacenter1", "3"),
+            ("dc2", "2"),
+        ])
+    );
+
+    for (replication, message) in [
+        (
+            "{'replication_factor': 1}",
+            "Missing replication strategy class",
+        ),
+        (
+            "{'class': 'SimpleStrategy'}",
+            "SimpleStrategy requires a replication_factor strategy option.",
+        ),
+        (
+            "{'class': 'SimpleStrategy', 'replication_factor': -1}",
+            "Replication factor must be non-negative; found -1",
+        ),
+        (
+            "{'class': 'SimpleStrategy', 'replication_factor': 'many'}",
+            "Replication factor must be numeric; found 'many'",
+        ),
+    ] {
+        let error = session
+            .process(
+                Query::simple(&format!(
+                    "CREATE KEYSPACE invalid WITH REPLICATION = {replication};"
+                ))
+                .unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(error.error, DbError::ConfigError);
+        assert_eq!(error.reason, message);
+    }
+}
+
+#[test]
+fn alter_keyspace() {
+    let mut session = session();
+    let result = exec!(
+        session,
+        "ALTER KEYSPACE cycling WITH replication = {'class': 'NetworkTopologyStrategy', 'datacenter1': 3} AND durable_writes = false;"
+    );
+    let QueryResult::SchemaChange(change) = result else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        change.event,
+        SchemaChangeEvent::KeyspaceChange {
+            change_type: SchemaChangeType::Updated,
+            keyspace_name: "cycling".to_owned(),
+        }
+    );
+
+    let QueryResult::Rows(rows) = exec!(session, "DESCRIBE KEYSPACE cycling;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns[3],
+        Some(CqlValue::Text(
+            "CREATE KEYSPACE cycling WITH replication = {'class': 'org.apache.cassandra.locator.NetworkTopologyStrategy', 'datacenter1': '3'}  AND durable_writes = false;".into()
+        ))
+    );
+
+    // tables of the keyspace are kept
+    let _ = exec!(session, "SELECT * FROM cycling.cyclist_name;");
+
+    let error = session
+        .process(Query::simple("ALTER KEYSPACE tour WITH durable_writes = false;").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    let error = session
+        .process(Query::simple("ALTER KEYSPACE system WITH durable_writes = false;").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Unauthorized);
+}
+
+#[test]
+fn user_types_are_resolved_in_table_columns() {
+    let mut session = session();
+    for statement in [
+        "CREATE TYPE cycling.address (street text, zip int);",
+        "CREATE TYPE cycling.team (name text, addresses frozen<map<text, frozen<address>>>);",
+        "CREATE TABLE cycling.profiles (
+            id int PRIMARY KEY,
+            home frozen<cycling.address>,
+            teams list<frozen<team>>,
+            location frozen<tuple<int, frozen<address>>>);",
+    ] {
+        let result = exec!(session, statement);
+        assert!(matches!(result, QueryResult::SchemaChange(_)));
+    }
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT home, teams, location FROM cycling.profiles"
+    ) else {
+        panic!("invalid return type");
+    };
+    let types = rows
+        .metadata
+        .col_specs
+        .iter()
+        .map(|it| it.typ.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        types,
+        [
+            "frozen<address>",
+            "list<frozen<team>>",
+            "frozen<tuple<int, frozen<address>>>"
+        ]
+    );
+
+    for invalid in [
+        "CREATE TABLE cycling.t (id int PRIMARY KEY, home frozen<unknown>);",
+        "CREATE TABLE cycling.t (id int PRIMARY KEY, home frozen<system.address>);",
+        "CREATE TYPE cycling.club (members list<frozen<member>>);",
+        // the type is used by tables
+        "DROP TYPE cycling.address;",
+    ] {
+        let error = session
+            .process(Query::simple(invalid).unwrap())
+            .unwrap_err();
+        assert_eq!(error.error, DbError::Invalid, "{invalid}");
+    }
+}
+
+#[test]
+fn illegal_type_nestings_are_rejected() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TYPE cycling.address (street text, zip int);"
+    );
+
+    for valid in [
+        "CREATE TABLE cycling.a (id int PRIMARY KEY, v list<frozen<set<int>>>);",
+        "CREATE TABLE cycling.b (id int PRIMARY KEY, v frozen<map<int, list<int>>>);",
+        "CREATE TABLE cycling.c (id int PRIMARY KEY, v tuple<int, list<int>>);",
+        "CREATE TABLE cycling.d (id int PRIMARY KEY, v frozen<list<address>>);",
+        "CREATE TYPE cycling.e (v list<int>, home frozen<address>);",
+    ] {
+        let result = exec!(session, valid);
+        assert!(matches!(result, QueryResult::SchemaChange(_)), "{valid}");
+    }
+
+    for (invalid, message) in [
+        (
+            "CREATE TABLE cycling.t (id int PRIMARY KEY, v list<set<int>>);",
+            "Non-frozen collections are not allowed inside collections: set<int>",
+        ),
+        (
+            "CREATE TABLE cycling.t (id int PRIMARY KEY, v map<int, address>);",
+            "Non-frozen UDTs are not allowed inside collections: address",
+        ),
+        (
+            "CREATE TABLE cycling.t (id int PRIMARY KEY, v set<counter>);",
+            "Counters are not allowed inside collections: counter",
+        ),
+        (
+            "CREATE TABLE cycling.t (id int PRIMARY KEY, v tuple<int, counter>);",
+            "Counters are not allowed inside tuples: counter",
+        ),
+        (
+            "CREATE TYPE cycling.t (home address);",
+            "A user type cannot contain non-frozen UDTs",
+        ),
+        (
+            "CREATE TYPE cycling.t (hits counter);",
+            "A user type cannot contain counters",
+        ),
+    ] {
+        let error = session
+            .process(Query::simple(invalid).unwrap())
+            .unwrap_err();
+        assert_eq!(error.error, DbError::Invalid, "{invalid}");
+        assert_eq!(error.reason, message, "{invalid}");
+    }
+}
+
+#[test]
+fn existence_clauses() {
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (1, 'VOS', 'Marianne');"
+    );
+
+    let migration = [
+        "CREATE KEYSPACE IF NOT EXISTS cycling WITH replication = {'class': 'SimpleStrategy', 'replication_factor': 1};",
+        "CREATE TYPE IF NOT EXISTS cycling.basic_info (birthday timestamp, nationality text, teams list<text>);",
+        "CREATE TABLE IF NOT EXISTS cycling.cyclist_name (id int PRIMARY KEY, lastname text, firstname text);",
+        "DROP TABLE IF EXISTS cycling.race_winners;",
+        "DROP TYPE IF EXISTS cycling.race_info;",
+        "DROP KEYSPACE IF EXISTS tour;",
+    ];
+    for _ in 0..2 {
+        for statement in migration {
+            let _ = exec!(session, statement);
+        }
+    }
+    assert!(matches!(
+        exec!(session, "DROP TABLE IF EXISTS cycling.race_winners;"),
+        QueryResult::Void
+    ));
+
+    let error = session
+        .process(Query::simple("CREATE TYPE cycling.basic_info (birthday timestamp);").unwrap())
+        .unwrap_err();
+    assert!(matches!(error.error, DbError::AlreadyExists { .. }));
+
+    let QueryResult::SchemaChange(change) = exec!(session, "DROP TABLE cycling.cyclist_name;")
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        change.event,
+        SchemaChangeEvent::TableChange {
+            change_type: SchemaChangeType::Dropped,
+            keyspace_name: "cycling".to_owned(),
+            object_name: "cyclist_name".to_owned(),
+        }
+    );
+    let error = session
+        .process(Query::simple("DROP TABLE cycling.cyclist_name;").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    // data of the dropped table doesn't come back with the new one
+    exec!(
+        session,
+        "CREATE TABLE cycling.cyclist_name (id int PRIMARY KEY, lastname text, firstname text);"
+    );
+    let QueryResult::Rows(rows) = exec!(session, "SELECT * FROM cycling.cyclist_name;") else {
+        panic!("invalid return type");
+    };
+    assert!(rows.rows.is_empty());
+
+    let QueryResult::SchemaChange(change) = exec!(session, "DROP TYPE cycling.basic_info;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        change.event,
+        SchemaChangeEvent::TypeChange {
+            change_type: SchemaChangeType::Dropped,
+            keyspace_name: "cycling".to_owned(),
+            type_name: "basic_info".to_owned(),
+        }
+    );
+
+    let QueryResult::SchemaChange(change) = exec!(session, "DROP KEYSPACE cycling;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        change.event,
+        SchemaChangeEvent::KeyspaceChange {
+            change_type: SchemaChangeType::Dropped,
+            keyspace_name: "cycling".to_owned(),
+        }
+    );
+    let error = session
+        .process(Query::simple("SELECT * FROM cycling.cyclist_name;").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    let error = session
+        .process(Query::simple("DROP KEYSPACE IF EXISTS system_schema;").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Unauthorized);
+}
+
+#[test]
+fn textual_batch() {
+    let mut session = session();
+    let result = exec!(
+        session,
+        "BEGIN BATCH
+            INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (1, 'VOS', 'Marianne');
+            INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (2, 'VAN DER BREGGEN', 'Anna');
+            DELETE FROM cycling.cyclist_name WHERE id = 2;
+        APPLY BATCH;"
+    );
+    assert!(matches!(result, QueryResult::Void));
+
+    let QueryResult::Rows(rows) = exec!(session, "SELECT id FROM cycling.cyclist_name;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+
+    // values are handed out to the markers of the batch in order
+    let mut query = Query::simple(
+        "BEGIN UNLOGGED BATCH USING TIMESTAMP ?
+            UPDATE cycling.cyclist_name SET lastname = ? WHERE id = ?;
+            UPDATE cycling.cyclist_name SET firstname = ? WHERE id = ?;
+        APPLY BATCH",
+    )
+    .unwrap();
+    let timestamp = 1000i64.to_be_bytes();
+    query.parameters.data = vec![
+        FrameValue::Some(&timestamp),
+        FrameValue::Some(b"FERRAND-PREVOT"),
+        FrameValue::Some(&[0, 0, 0, 3]),
+        FrameValue::Some(b"Pauline"),
+        FrameValue::Some(&[0, 0, 0, 3]),
+    ];
+    session.process(query).unwrap();
+
+    exec!(
+        session,
+        "UPDATE cycling.cyclist_name USING TIMESTAMP 500 SET lastname = 'late' WHERE id = 3;"
+    );
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT lastname, firstname FROM cycling.cyclist_name WHERE id = 3;"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        vec![
+            Some(CqlValue::Text("FERRAND-PREVOT".into())),
+            Some(CqlValue::Text("Pauline".into()))
+        ]
+    );
+
+    let mut query =
+        Query::simple("BEGIN BATCH DELETE FROM cycling.cyclist_name WHERE id = ?; APPLY BATCH")
+            .unwrap();
+    query.parameters.data = vec![
+        FrameValue::Some(&[0, 0, 0, 1]),
+        FrameValue::Some(&[0, 0, 0, 3]),
+    ];
+    let error = session.process(query).unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+}
+
+#[test]
+fn batch_statements_share_timestamp() {
+    use kassandra::frame::request::batch::Batch;
+
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'old');"
+    );
+    let QueryString::Batch(batch) = Query::simple(
+        "BEGIN BATCH
+            DELETE FROM cycling.cyclist_name WHERE id = 1;
+            INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'new');
+        APPLY BATCH",
+    )
+    .unwrap()
+    .query
+    else {
+        panic!("invalid query type");
+    };
+    // sent without a client timestamp, the tombstone wins the tie with the insert
+    let batch = Batch::from_query(batch, Default::default()).unwrap();
+    assert_eq!(batch.timestamp, None);
+    session.process_batch(batch).unwrap();
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT lastname FROM cycling.cyclist_name WHERE id = 1;"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert!(rows.rows.is_empty());
+}
+
+#[test]
+fn failed_batch_writes_nothing() {
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS');"
+    );
+    let before = format!("{:?}", session.data_snapshot_with_tombstones());
+
+    // statements before the failing one are not applied either
+    let error = session
+        .process(
+            Query::simple(
+                "BEGIN BATCH
+                    INSERT INTO cycling.cyclist_name (id, lastname) VALUES (2, 'VAN DER BREGGEN');
+                    DELETE FROM cycling.cyclist_name WHERE id = 1;
+                    INSERT INTO cycling.cyclist_name (id, unknown) VALUES (3, 'FERRAND-PREVOT');
+                APPLY BATCH;",
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+    assert_eq!(
+        format!("{:?}", session.data_snapshot_with_tombstones()),
+        before
+    );
+
+    let error = session
+        .process(
+            Query::simple(
+                "BEGIN BATCH
+                    INSERT INTO cycling.cyclist_name (id, lastname) VALUES (2, 'VAN DER BREGGEN');
+                    INSERT INTO cycling.missing (id) VALUES (3);
+                APPLY BATCH;",
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+    assert_eq!(
+        format!("{:?}", session.data_snapshot_with_tombstones()),
+        before
+    );
+}
+
+#[test]
+fn batch_size_thresholds() {
+    let mut session =
+        session().with_batch_size_policy(BatchSizePolicy::new().warn_above(200).fail_above(400));
+    let batch = |rows: usize| {
+        let inserts = (0..rows).map(|id| {
+            format!("INSERT INTO cycling.cyclist_name (id, lastname) VALUES ({id}, 'VOS');")
+        });
+        format!("BEGIN BATCH {} APPLY BATCH", inserts.collect::<String>())
+    };
+
+    session.process(Query::simple(&batch(1)).unwrap()).unwrap();
+    assert!(session.take_warnings().is_empty());
+
+    session.process(Query::simple(&batch(4)).unwrap()).unwrap();
+    let warnings = session.take_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("Batch for [cycling.cyclist_name] is of size"));
+    assert!(session.take_warnings().is_empty());
+
+    let error = session
+        .process(Query::simple(&batch(8)).unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+    assert_eq!(error.reason, "Batch too large");
+    let QueryResult::Rows(rows) = exec!(session, "SELECT id FROM cycling.cyclist_name;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 4);
+}
+
+#[test]
+fn table_options_are_kept() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.race_times (
+                       race text PRIMARY KEY,
+                       rider text)
+                       WITH comment = 'fastest riders'
+                       AND default_time_to_live = 3600
+                       AND gc_grace_seconds = 0
+                       AND bloom_filter_fp_chance = 0.1
+                       AND compaction = {'class': 'LeveledCompactionStrategy', 'sstable_size_in_mb': 160};"
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT comment, default_time_to_live, gc_grace_seconds, bloom_filter_fp_chance, compaction
+         FROM system_schema.tables WHERE keyspace_name = 'cycling' AND table_name = 'race_times';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns,
+        [
+            Some(CqlValue::Text("fastest riders".into())),
+            Some(CqlValue::Int(3600)),
+            Some(CqlValue::Int(0)),
+            Some(CqlValue::Double(0.1f64.to_bits())),
+            Some(CqlValue::Map(vec![
+                (
+                    CqlValue::Text("class".into()),
+                    CqlValue::Text(
+                        "org.apache.cassandra.db.compaction.LeveledCompactionStrategy".into()
+                    )
+                ),
+                (
+                    CqlValue::Text("sstable_size_in_mb".into()),
+                    CqlValue::Text("160".into())
+                ),
+            ])),
+        ]
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "SELECT default_time_to_live FROM system_schema.tables
+         WHERE keyspace_name = 'cycling' AND table_name = 'cyclist_name';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Int(0)));
+
+    let error = session
+        .process(
+            Query::simple(
+                "CREATE TABLE cycling.invalid (id int PRIMARY KEY) WITH default_time_to_live = -1;",
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+    assert_eq!(error.error, DbError::ConfigError);
+    assert_eq!(
+        error.reason,
+        "default_time_to_live must be greater than or equal to 0 (got -1)"
+    );
+}
+
+#[test]
+fn statements_are_rewritten() {
+    struct Prefix;
+
+    impl VisitMut for Prefix {
+        fn visit_keyspace(&mut self, keyspace: &mut String) {
+            *keyspace = format!("t1_{keyspace}");
+        }
+    }
+
+    let mut session =
+        KassandraSession::new().with_rewrite(|query: &mut QueryString| Prefix.visit_query(query));
+    exec!(
+        session,
+        "CREATE KEYSPACE app WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1};"
+    );
+    exec!(
+        session,
+        "CREATE TABLE app.users (id int PRIMARY KEY, name text);"
+    );
+    assert!(session.schema_snapshot().0.contains_key("t1_app"));
+
+    // prepared statements are stored rewritten, executing them doesn't rewrite them again
+    let QueryResult::Prepared(prepared) = session
+        .prepare(Prepare::simple("INSERT INTO app.users (id, name) VALUES (?, ?)").unwrap())
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let id = prepared.id.to_be_bytes();
+    session
+        .execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data: vec![FrameValue::Some(&[0, 0, 0, 1]), FrameValue::Some(b"Anna")],
+                ..Default::default()
+            },
+        })
+        .unwrap();
+    exec!(
+        session,
+        "BEGIN BATCH INSERT INTO app.users (id, name) VALUES (2, 'Marianne'); APPLY BATCH"
+    );
+
+    let QueryResult::SetKeyspace(keyspace) = exec!(session, "USE app;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(keyspace.keyspace_name, "t1_app");
+    let QueryResult::Rows(rows) = exec!(session, "SELECT name FROM users;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 2);
+}
+
+#[test]
+fn isolated_sessions() {
+    let shared = KassandraSession::new();
+    let mut sessions = [shared.isolated("first"), shared.isolated("second")];
+
+    for (id, session) in sessions.iter_mut().enumerate() {
+        exec!(
+            session,
+            "CREATE KEYSPACE cycling WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1};"
+        );
+        exec!(session, "USE cycling;");
+        exec!(
+            session,
+            "CREATE TABLE riders (id int PRIMARY KEY, name text);"
+        );
+        session
+            .process_cql(&format!(
+                "INSERT INTO cycling.riders (id, name) VALUES ({id}, 'rider');"
+            ))
+            .unwrap();
+    }
+
+    for (id, session) in sessions.iter_mut().enumerate() {
+        let QueryResult::Rows(rows) = exec!(session, "SELECT id FROM cycling.riders;") else {
+            panic!("invalid return type");
+        };
+        assert_eq!(rows.rows.len(), 1);
+        assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Int(id as i32)));
+
+        let schema = session.schema_snapshot();
+        assert_eq!(schema.0.keys().collect::<Vec<_>>(), ["cycling"]);
+        assert_eq!(schema.0["cycling"].tables["riders"].keyspace, "cycling");
+        let data = session.data_snapshot();
+        assert_eq!(data.0["cycling"].tables["riders"].rows.len(), 1);
+    }
+
+    // the shared session sees keyspaces of every isolated session with their prefixes
+    assert_eq!(shared.schema_snapshot().0.len(), 2);
+    let QueryResult::Rows(rows) = sessions[0]
+        .process_cql("SELECT keyspace_name FROM system_schema.keyspaces;")
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    assert!(rows.rows.len() > 2);
+}
+
+#[test]
+fn injected_clock_and_ids() {
+    let clock = ManualClock::new(1_000_000);
+    let mut session = KassandraSession::new()
+        .with_time_provider(clock.clone())
+        .with_id_provider(SequentialIds::default());
+    exec!(
+        session,
+        "CREATE KEYSPACE cycling WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1};"
+    );
+    exec!(
+        session,
+        "CREATE TABLE cycling.riders (id int PRIMARY KEY, name text);"
+    );
+
+    // the clock doesn't move, still every write comes after the previous one
+    exec!(
+        session,
+        "INSERT INTO cycling.riders (id, name) VALUES (1, 'Marianne');"
+    );
+    exec!(session, "DELETE FROM cycling.riders WHERE id = 1;");
+    exec!(
+        session,
+        "INSERT INTO cycling.riders (id, name) VALUES (1, 'Anna');"
+    );
+    let QueryResult::Rows(rows) = exec!(session, "SELECT id FROM cycling.riders;") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+
+    session.set_tombstone_retention(Duration::from_secs(3600));
+    assert_eq!(session.compact(), 0);
+    clock.advance(Duration::from_secs(2 * 3600));
+    assert_eq!(session.compact(), 1);
+
+    let mut prepare = |query: &str| {
+        let QueryResult::Prepared(prepared) =
+            session.prepare(Prepare::simple(query).unwrap()).unwrap()
+        else {
+            panic!("invalid return type");
+        };
+        prepared.id
+    };
+    assert_eq!(prepare("SELECT * FROM cycling.riders"), 1);
+    assert_eq!(prepare("SELECT name FROM cycling.riders"), 2);
+    assert_eq!(prepare("SELECT * FROM cycling.riders"), 1);
+}
+
+#[test]
+fn tombstones_are_retained_from_their_local_deletion_time() {
+    let clock = ManualClock::new(1_700_000_000_000_000);
+    let mut session = session().with_time_provider(clock.clone());
+    session.set_tombstone_retention(Duration::from_secs(3600));
+
+    // the deletion is timestamped long before it is applied
+    exec!(
+        session,
+        "DELETE FROM cycling.cyclist_name USING TIMESTAMP 10 WHERE id = 1;"
+    );
+    assert_eq!(session.compact(), 0);
+
+    // a delayed write older than the deletion stays shadowed
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS') USING TIMESTAMP 5;"
+    );
+    let QueryResult::Rows(rows) =
+        exec!(session, "SELECT id FROM cycling.cyclist_name WHERE id = 1;")
+    else {
+        panic!("invalid return type");
+    };
+    assert!(rows.rows.is_empty());
+
+    clock.advance(Duration::from_secs(2 * 3600));
+    assert_eq!(session.compact(), 1);
+}
+
+#[test]
+fn writes_with_equal_timestamps_are_reconciled_in_any_order() {
+    let lastname = |writes: [&str; 2]| {
+        let mut session = session();
+        for write in writes {
+            exec!(session, write);
+        }
+        let QueryResult::Rows(rows) = exec!(
+            session,
+            "SELECT lastname FROM cycling.cyclist_name WHERE id = 1;"
+        ) else {
+            panic!("invalid return type");
+        };
+        rows.rows[0].columns[0].clone()
+    };
+    let vos =
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS') USING TIMESTAMP 5;";
+    let bos =
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'BOS') USING TIMESTAMP 5;";
+    let null =
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, null) USING TIMESTAMP 5;";
+
+    // the greater value wins
+    assert_eq!(lastname([vos, bos]), Some(CqlValue::Text("VOS".into())));
+    assert_eq!(lastname([bos, vos]), Some(CqlValue::Text("VOS".into())));
+    // and a null wins over any value
+    assert_eq!(lastname([vos, null]), None);
+    assert_eq!(lastname([null, vos]), None);
+}
+
+#[test]
+fn tombstones_are_kept_in_state() {
+    let clock = ManualClock::new(1_700_000_000_000_000);
+    let mut session = session().with_time_provider(clock.clone());
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS');"
+    );
+    exec!(
+        session,
+        "DELETE FROM cycling.cyclist_name USING TIMESTAMP 10 WHERE id = 2;"
+    );
+
+    let loaded = KassandraSession::load_state(&session.save_state())
+        .unwrap()
+        .with_time_provider(clock.clone());
+    assert_eq!(
+        format!("{:?}", loaded.data_snapshot_with_tombstones()),
+        format!("{:?}", session.data_snapshot_with_tombstones())
+    );
+
+    // the tombstone is still retained from the time it was applied, not from its timestamp
+    loaded.set_tombstone_retention(Duration::from_secs(3600));
+    assert_eq!(loaded.compact(), 0);
+    clock.advance(Duration::from_secs(2 * 3600));
+    assert_eq!(loaded.compact(), 1);
+}
+
+#[test]
+fn keyspaces_are_exported_and_imported() {
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (1, 'VOS', 'Marianne');"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (2, 'VAN DER BREGGEN', 'Anna');"
+    );
+    exec!(session, "DELETE FROM cycling.cyclist_name WHERE id = 2;");
+
+    let dumps = session.export_keyspaces();
+    assert_eq!(dumps.len(), 1);
+    let dump = serde_json::to_string(&dumps[0]).unwrap();
+
+    let mut restored = KassandraSession::new();
+    restored
+        .import_keyspace(serde_json::from_str(&dump).unwrap())
+        .unwrap();
+    assert_eq!(
+        format!("{:?}", restored.data_snapshot_with_tombstones()),
+        format!("{:?}", session.data_snapshot_with_tombstones())
+    );
+
+    let QueryResult::Rows(rows) = exec!(
+        restored,
+        "SELECT table_name FROM system_schema.tables WHERE keyspace_name = 'cycling';"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows.len(), 1);
+    let QueryResult::Rows(rows) = exec!(restored, "SELECT lastname FROM cycling.cyclist_name;")
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Text("VOS".into())));
+
+    let mut system = dumps[0].clone();
+    system.schema.name = "system".to_owned();
+    let error = restored.import_keyspace(system).unwrap_err();
+    assert_eq!(error.error, DbError::Unauthorized);
+}
+
+#[test]
+fn forked_state_is_restored() {
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS');"
+    );
+    let baseline = session.fork();
+    let seeded = format!("{:?}", session.data_snapshot_with_tombstones());
+
+    // every branch starts from the seeded data
+    for id in 2..4 {
+        exec!(
+            session,
+            "CREATE TABLE cycling.rank (race text PRIMARY KEY, rider text);"
+        );
+        session
+            .process(
+                Query::simple(&format!(
+                    "INSERT INTO cycling.cyclist_name (id, lastname) VALUES ({id}, 'VAN DER BREGGEN');"
+                ))
+                .unwrap(),
+            )
+            .unwrap();
+        exec!(session, "DELETE FROM cycling.cyclist_name WHERE id = 1;");
+        let QueryResult::Rows(rows) = exec!(session, "SELECT id FROM cycling.cyclist_name;") else {
+            panic!("invalid return type");
+        };
+        assert_eq!(rows.rows.len(), 1);
+
+        session.restore(&baseline);
+        assert_eq!(
+            format!("{:?}", session.data_snapshot_with_tombstones()),
+            seeded
+        );
+        assert!(!session.schema_snapshot().0["cycling"]
+            .tables
+            .contains_key("rank"));
+    }
+
+    // the savepoint isn't changed by writes of the session
+    let QueryResult::Rows(rows) = exec!(session, "SELECT lastname FROM cycling.cyclist_name;")
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns[0], Some(CqlValue::Text("VOS".into())));
+}
+
+#[test]
+fn state_digest_compares_replays() {
+    let replay = |statements: &[&str]| {
+        let mut session = session();
+        for statement in statements {
+            session.process(Query::simple(statement).unwrap()).unwrap();
+        }
+        session.state_digest()
+    };
+    let vos = "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS');";
+    let anna = "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (2, 'VAN DER BREGGEN', 'Anna');";
+
+    // write times are not part of the state
+    assert_eq!(replay(&[vos, anna]), replay(&[anna, vos]));
+    assert_ne!(replay(&[vos, anna]), replay(&[vos]));
+    assert_ne!(
+        replay(&[vos]),
+        replay(&[vos, anna, "DELETE FROM cycling.cyclist_name WHERE id = 2;"])
+    );
+    assert_ne!(
+        replay(&[vos]),
+        replay(&[
+            vos,
+            "CREATE TABLE cycling.rank (race text PRIMARY KEY, rider text);"
+        ])
+    );
+}
+
+#[test]
+fn state_is_exported_as_cql() {
+    let mut session = session();
+    exec!(
+        session,
+        "CREATE TABLE cycling.rank (race text, year int, rank int, rider text, scores map<text, int>, PRIMARY KEY ((race, year), rank)) WITH CLUSTERING ORDER BY (rank DESC);"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.rank (race, year, rank, rider, scores) VALUES ('Tour', 2019, 1, 'O''Grady', {'x': 1});"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.rank (race, year, rank, rider) VALUES ('Tour', 2019, 2, 'Vos');"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.rank (race, year, rank) VALUES ('Giro', 2020, 1);"
+    );
+    exec!(
+        session,
+        "DELETE FROM cycling.rank WHERE race = 'Tour' AND year = 2019 AND rank = 2;"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname) VALUES (1, 'VOS', 'Marianne');"
+    );
+
+    let script = kassandra::export::to_cql_script(&session);
+    assert!(script.contains(
+        "INSERT INTO cycling.rank (race, year, rank, rider, scores) VALUES ('Tour', 2019, 1, 'O''Grady', {'x': 1});"
+    ));
+    assert!(
+        script.contains("INSERT INTO cycling.rank (race, year, rank) VALUES ('Giro', 2020, 1);")
+    );
+
+    let mut restored = KassandraSession::new();
+    for statement in script.split_inclusive(";\n") {
+        restored.process_cql(statement).unwrap();
+    }
+    assert_eq!(
+        format!("{:?}", restored.data_snapshot()),
+        format!("{:?}", session.data_snapshot())
+    );
+    assert_eq!(
+        format!("{:?}", restored.schema_snapshot()),
+        format!("{:?}", session.schema_snapshot())
+    );
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn data_is_exported_to_parquet() {
+    use arrow_schema::DataType;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname, firstname, records) VALUES (1, 'VOS', 'Marianne', {'tour': '1st'});"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (2, 'VAN DER BREGGEN');"
+    );
+
+    let dir = std::env::temp_dir().join(format!("kassandra-parquet-{}", std::process::id()));
+    let written = session.data_snapshot().to_parquet(&dir).unwrap();
+    assert_eq!(
+        written,
+        vec![dir.join("cycling").join("cyclist_name.parquet")]
+    );
+
+    let file = std::fs::File::open(&written[0]).unwrap();
+    let batches = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let batch = &batches[0];
+    assert_eq!(batch.num_rows(), 2);
+    let schema = batch.schema();
+    assert_eq!(
+        schema.field_with_name("id").unwrap().data_type(),
+        &DataType::Int32
+    );
+    assert_eq!(
+        schema.field_with_name("lastname").unwrap().data_type(),
+        &DataType::Utf8
+    );
+    assert_eq!(
+        schema.field_with_name("records").unwrap().data_type(),
+        &DataType::Utf8
+    );
+    assert_eq!(batch.column_by_name("firstname").unwrap().null_count(), 1);
+}
+
+#[cfg(feature = "testgen")]
+#[test]
+fn generated_workloads_are_applied() {
+    use kassandra::testgen::{workload, Workload};
+    use proptest::{
+        prop_assert_eq,
+        test_runner::{Config, TestCaseError, TestRunner},
+    };
+
+    let mut runner = TestRunner::new(Config {
+        cases: 64,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    let result = runner.run(&workload(), |workload: Workload| {
+        let mut session = KassandraSession::new();
+        workload
+            .apply(&mut session)
+            .map_err(|error| TestCaseError::fail(format!("{}\n{workload}", error.reason)))?;
+
+        let restored = KassandraSession::load_state(&session.save_state()).unwrap();
+        prop_assert_eq!(restored.state_digest(), session.state_digest());
+        Ok(())
+    });
+    if let Err(error) = result {
+        panic!("{error}");
+    }
+}
+
+#[test]
+fn replay_log_round_trip() {
+    use kassandra::{
+        cql::query_cache,
+        frame::request::{batch::Batch, Request},
+        replay::ReplayLog,
+    };
+
+    let query = |statement: &'static str| Request::Query(Query::simple(statement).unwrap());
+    let mut log = ReplayLog::new();
+    log.record(
+        &query("CREATE KEYSPACE cycling WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1};"),
+        None,
+        1,
+    );
+    log.record(&query("USE cycling;"), None, 2);
+    log.record(
+        &query("CREATE TABLE riders (id int PRIMARY KEY, name text);"),
+        None,
+        3,
+    );
+    log.record(
+        &Request::Prepare(Prepare::simple("INSERT INTO riders (id, name) VALUES (?, ?)").unwrap()),
+        Some("cycling"),
+        4,
+    );
+    let id = query_cache::statement_id(
+        Some("cycling"),
+        "INSERT INTO riders (id, name) VALUES (?, ?)",
+    );
+    let id = id.to_be_bytes();
+    let values = vec![
+        FrameValue::Some(&[0, 0, 0, 1]),
+        FrameValue::Some(b"Marianne"),
+    ];
+    log.record(
+        &Request::Execute(Execute {
+            id: &id,
+            parameters: QueryParameters {
+                data: values.clone(),
+                ..Default::default()
+            },
+        }),
+        None,
+        20,
+    );
+    // the deletion happened before the insert, the row stays
+    log.record(&query("DELETE FROM riders WHERE id = 1;"), None, 10);
+    let QueryString::Batch(batch) = Query::simple(
+        "BEGIN BATCH INSERT INTO riders (id, name) VALUES (2, 'Anna'); INSERT INTO riders (id, name) VALUES (3, 'Lotte'); APPLY BATCH;",
+    )
+    .unwrap()
+    .query
+    else {
+        panic!("invalid query type");
+    };
+    log.record(
+        &Request::Batch(Batch::from_query(batch, Default::default()).unwrap()),
+        None,
+        30,
+    );
+    log.record(&Request::Options, None, 40);
+    assert_eq!(log.entries.len(), 7);
+
+    let json = serde_json::to_string(&log).unwrap();
+    assert!(json.contains(r#""values":[{"value":"00000001"},{"value":"4d617269616e6e65"}]"#));
+    let log: ReplayLog = serde_json::from_str(&json).unwrap();
+
+    let replay = || {
+        let mut session = KassandraSession::new();
+        log.apply(&mut session).unwrap();
+        format!("{:?}", session.data_snapshot_with_tombstones())
+    };
+    assert_eq!(replay(), replay());
+
+    let mut session = KassandraSession::new();
+    log.apply(&mut session).unwrap();
+    let rows = &session.data_snapshot().0["cycling"].tables["riders"].rows;
+    assert_eq!(rows.len(), 3);
+    assert_eq!(
+        rows[0].data["name"],
+        ValueSnapshot::Text("Marianne".to_owned())
+    );
+}
+
+#[test]
+fn prepared_metadata_of_bind_markers() {
+    let session = session();
+    let query = Query::simple("SELECT * FROM cyclist_name WHERE id = ?")
+        .unwrap()
+        .query;
+
+    let metadata = session
+        .prepared_metadata(query.clone(), Some("cycling"))
+        .unwrap();
+    assert_eq!(metadata.col_specs.len(), 1);
+    assert_eq!(metadata.col_specs[0].name, "id");
+    // unqualified tables need a keyspace to be resolved
+    assert!(session.prepared_metadata(query, None).is_err());
+}
+
+#[test]
+fn change_events() {
+    let mut session = session();
+    let mut changes = session.subscribe();
+    let mut other = session.subscribe();
+    drop(other);
+    other = session.subscribe();
+
+    exec!(
+        session,
+        "CREATE TABLE cycling.events (id int, ck int, name text, PRIMARY KEY (id, ck))"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.events (id, ck, name) VALUES (1, 2, 'one') USING TIMESTAMP 10"
+    );
+    exec!(
+        session,
+        "UPDATE cycling.events USING TIMESTAMP 11 SET name = null WHERE id = 1 AND ck = 2"
+    );
+    exec!(
+        session,
+        "DELETE FROM cycling.events USING TIMESTAMP 12 WHERE id = 1"
+    );
+    exec!(session, "SELECT * FROM cycling.events");
+    assert!(session
+        .process(Query::simple("INSERT INTO cycling.missing (id) VALUES (1)").unwrap())
+        .is_err());
+
+    let mut events =
+        std::iter::from_fn(|| changes.next().now_or_never().flatten()).collect::<Vec<_>>();
+    for event in &mut events {
+        if let ChangeEvent::Insert { values, .. } = event {
+            values.sort();
+        }
+    }
+    assert_eq!(
+        events,
+        vec![
+            ChangeEvent::Schema(SchemaChangeEvent::TableChange {
+                change_type: SchemaChangeType::Created,
+                keyspace_name: "cycling".to_owned(),
+                object_name: "events".to_owned(),
+            }),
+            ChangeEvent::Insert {
+                keyspace: "cycling".to_owned(),
+                table: "events".to_owned(),
+                partition_key: PartitionKeyValue::Simple(CqlValue::Int(1)),
+                clustering_key: ClusteringKeyValue::Simple(Some(CqlValue::Int(2))),
+                values: vec![
+                    ("ck".to_owned(), Some(CqlValue::Int(2))),
+                    ("id".to_owned(), Some(CqlValue::Int(1))),
+                    ("name".to_owned(), Some(CqlValue::Text("one".into()))),
+                ],
+                timestamp: 10,
+            },
+            ChangeEvent::Insert {
+                keyspace: "cycling".to_owned(),
+                table: "events".to_owned(),
+                partition_key: PartitionKeyValue::Simple(CqlValue::Int(1)),
+                clustering_key: ClusteringKeyValue::Simple(Some(CqlValue::Int(2))),
+                values: vec![
+                    ("ck".to_owned(), Some(CqlValue::Int(2))),
+                    ("id".to_owned(), Some(CqlValue::Int(1))),
+                    ("name".to_owned(), None),
+                ],
+                timestamp: 11,
+            },
+            ChangeEvent::Delete {
+                keyspace: "cycling".to_owned(),
+                table: "events".to_owned(),
+                partition_key: PartitionKeyValue::Simple(CqlValue::Int(1)),
+                clustering_key: ClusteringKeyValue::Empty,
+                timestamp: 12,
+            },
+        ]
+    );
+
+    let events = std::iter::from_fn(|| other.next().now_or_never().flatten()).count();
+    assert_eq!(events, 4);
+}
+
+#[test]
+fn middleware() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let mut session = session()
+        .with_middleware(move |_: &mut Request<'_>, _: &mut Context<'_>| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            ControlFlow::Continue(())
+        })
+        .with_middleware(|request: &mut Request<'_>, context: &mut Context<'_>| {
+            let Request::Query(query) = request else {
+                return ControlFlow::Continue(());
+            };
+            match query.raw_query {
+                "SELECT * FROM cycling.forbidden" => ControlFlow::Break(Response::Error(
+                    Error::new(DbError::Unauthorized, "forbidden"),
+                )),
+                "SELECT * FROM cycling.renamed" => {
+                    *query = Query::simple("SELECT cluster_name FROM system.local").unwrap();
+                    context.connection.use_keyspace("cycling");
+                    ControlFlow::Continue(())
+                }
+                _ => ControlFlow::Continue(()),
+            }
+        });
+
+    let response = session.request(Request::Query(
+        Query::simple("SELECT * FROM cycling.forbidden").unwrap(),
+    ));
+    assert!(matches!(
+        response,
+        Response::Error(Error {
+            error: DbError::Unauthorized,
+            ..
+        })
+    ));
+
+    let Response::Result(QueryResult::Rows(rows)) = session.request(Request::Query(
+        Query::simple("SELECT * FROM cycling.renamed").unwrap(),
+    )) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.metadata.col_specs[0].name, "cluster_name");
+    assert_eq!(session.keyspace(), Some("cycling"));
+
+    assert!(matches!(
+        session.request(Request::Options),
+        Response::Supported(_)
+    ));
+    // statements processed directly don't go through middleware
+    exec!(session, "SELECT * FROM cycling.cyclist_name");
+    assert_eq!(requests.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn explain() {
+    let mut session = session();
+
+    let plan = session
+        .explain(Query::simple("SELECT * FROM cycling.cyclist_name WHERE id = 1").unwrap())
+        .unwrap();
+    assert!(matches!(plan, Plan::Select(_)));
+    let plan = session
+        .explain(Query::simple("SELECT * FROM cycling.cyclist_name").unwrap())
+        .unwrap();
+    assert!(matches!(plan, Plan::Scan(_)));
+
+    let QueryResult::Rows(rows) = exec!(
+        session,
+        "EXPLAIN SELECT COUNT(*) FROM cycling.cyclist_name WHERE firstname = 'Alex' ALLOW FILTERING"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_debug_snapshot!(rows);
+
+    let details = |session: &mut KassandraSession, statement: &str| {
+        let QueryResult::Rows(rows) = exec!(session, statement) else {
+            panic!("invalid return type");
+        };
+        rows.rows[0].columns[2].clone()
+    };
+    assert_eq!(
+        details(
+            &mut session,
+            "EXPLAIN SELECT firstname FROM cycling.cyclist_name WHERE id = 1 LIMIT 5"
+        ),
+        Some(CqlValue::Text(
+            r#"{"columns":["firstname"],"limit":5,"partition_key":[1],"table":"cycling.cyclist_name"}"#
+                .into()
+        ))
+    );
+    // explained writes are not applied
+    assert_eq!(
+        details(
+            &mut session,
+            "EXPLAIN INSERT INTO cycling.cyclist_name (id, firstname) VALUES (1, 'Alex')"
+        ),
+        Some(CqlValue::Text(
+            r#"{"partition_key":[1],"table":"cycling.cyclist_name","values":{"firstname":"Alex","id":1}}"#
+                .into()
+        ))
+    );
+    let QueryResult::Rows(rows) = exec!(session, "SELECT COUNT(*) FROM cycling.cyclist_name")
+    else {
+        panic!("invalid return type");
+    };
+    assert_eq!(rows.rows[0].columns, vec![Some(CqlValue::BigInt(0))]);
+}
+
+#[test]
+fn query_stats() {
+    let mut session = session().with_slow_query_threshold(Duration::from_secs(60));
+    assert_eq!(
+        session.slow_query_threshold(),
+        Some(Duration::from_secs(60))
+    );
+
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, firstname) VALUES (1, 'Alex')"
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, firstname) VALUES (2, 'Bob')"
+    );
+    exec!(session, "SELECT * FROM cycling.cyclist_name");
+    assert!(session
+        .process(Query::simple("SELECT * FROM cycling.missing").unwrap())
+        .is_err());
+
+    let stats = session.query_stats();
+    let inserts = &stats["INSERT INTO cycling.cyclist_name (id, firstname) VALUES (?, ?)"];
+    assert_eq!(inserts.count, 2);
+    assert_eq!(inserts.errors, 0);
+    assert!(inserts.max_latency <= inserts.total_latency);
+    assert_eq!(stats["SELECT * FROM cycling.cyclist_name"].rows, 2);
+    assert_eq!(stats["SELECT * FROM cycling.missing"].errors, 1);
+
+    session.reset_query_stats();
+    assert!(session.query_stats().is_empty());
+}
+
+#[test]
+fn limits() {
+    let mut session = session().with_limits_policy(
+        LimitsPolicy::cassandra()
+            .max_collection_size(2)
+            .max_cell_size(8),
+    );
+    let invalid = |session: &mut KassandraSession, query: &str| {
+        let error = session.process(Query::simple(query).unwrap()).unwrap_err();
+        assert_eq!(error.error, DbError::Invalid, "{query}");
+        error.reason
+    };
+
+    let name = "a".repeat(49);
+    assert_eq!(
+        invalid(
+            &mut session,
+            &format!("CREATE TABLE cycling.{name} (id int PRIMARY KEY)")
+        ),
+        format!("Table name must not be more than 48 characters long (got 49 characters for \"{name}\")")
+    );
+    invalid(
+        &mut session,
+        &format!("CREATE KEYSPACE {name} WITH REPLICATION = {{'class': 'SimpleStrategy', 'replication_factor': 1}}"),
+    );
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, firstname, records) VALUES (1, 'Alex', {'a': 'b', 'c': 'd'})"
+    );
+    invalid(
+        &mut session,
+        "INSERT INTO cycling.cyclist_name (id, records) VALUES (1, {'a': 'b', 'c': 'd', 'e': 'f'})",
+    );
+    invalid(
+        &mut session,
+        "UPDATE cycling.cyclist_name SET firstname = 'Alexander' WHERE id = 1",
+    );
+
+    // prefixes of isolated sessions don't count into the names
+    let mut isolated = session.isolated("a_rather_long_prefix_of_the_isolated_session");
+    exec!(
+        isolated,
+        "CREATE KEYSPACE cycling WITH REPLICATION = {'class': 'SimpleStrategy', 'replication_factor': 1}"
+    );
+}
+
+#[test]
+fn startup_options() {
+    let mut session = session();
+    let startup = |session: &mut KassandraSession, options: &[(&str, &str)]| {
+        let options = options
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        match session.request(Request::StartUp(options)) {
+            Response::Ready => Ok(()),
+            Response::Error(error) => Err(error),
+            _ => panic!("invalid return type"),
+        }
+    };
+
+    let error = startup(&mut session, &[]).unwrap_err();
+    assert_eq!(error.error, DbError::ProtocolError);
+    let error = startup(
+        &mut session,
+        &[("CQL_VERSION", "3.0.0"), ("COMPRESSION", "lz4")],
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.reason,
+        "Unknown compression algorithm: lz4, supported: []"
+    );
+    assert!(startup(
+        &mut session,
+        &[("CQL_VERSION", "3.0.0"), ("THROW_ON_OVERLOAD", "yes")],
+    )
+    .is_err());
+
+    let mut connection = ConnectionState::new();
+    session
+        .startup_in(
+            &mut connection,
+            [
+                ("CQL_VERSION", "3.0.0"),
+                ("NO_COMPACT", "false"),
+                ("THROW_ON_OVERLOAD", "true"),
+            ]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .into(),
+        )
+        .unwrap();
+    assert!(connection.throw_on_overload());
+}
+
+#[test]
+fn access_control() {
+    let mut admin = session().with_authentication();
+    let handle = admin.handle();
+    let mut alice = ConnectionState::new();
+    let run = |connection: &mut ConnectionState, query: &str| {
+        handle.process_in(connection, Query::simple(query).unwrap())
+    };
+    let unauthorized = |result: Result<QueryResult, Error>| {
+        let error = result.unwrap_err();
+        assert_eq!(error.error, DbError::Unauthorized);
+        error.reason
+    };
+
+    assert_eq!(
+        unauthorized(run(&mut alice, "SELECT * FROM cycling.cyclist_name")),
+        "You have not logged in"
+    );
+
+    admin.login("cassandra", "cassandra").unwrap();
+    exec!(
+        admin,
+        "CREATE ROLE alice WITH PASSWORD = 'secret' AND LOGIN = true"
+    );
+    let error = admin
+        .process(Query::simple("CREATE ROLE alice").unwrap())
+        .unwrap_err();
+    assert_eq!(error.reason, "alice already exists");
+
+    // drivers log in with PLAIN credentials after STARTUP
+    let startup = Request::StartUp([("CQL_VERSION".to_owned(), "3.0.0".to_owned())].into());
+    assert!(matches!(
+        handle.request_in(&mut alice, startup),
+        Response::Authenticate(_)
+    ));
+    let response = handle.request_in(
+        &mut alice,
+        Request::AuthResponse {
+            token: Some(b"\0alice\0wrong"),
+        },
+    );
+    assert!(matches!(
+        response,
+        Response::Error(Error {
+            error: DbError::AuthenticationError,
+            ..
+        })
+    ));
+    let response = handle.request_in(
+        &mut alice,
+        Request::AuthResponse {
+            token: Some(b"\0alice\0secret"),
+        },
+    );
+    assert!(matches!(response, Response::AuthSuccess(_)));
+    assert_eq!(alice.role(), Some("alice"));
+
+    assert_eq!(
+        unauthorized(run(&mut alice, "SELECT * FROM cycling.cyclist_name")),
+        "User alice has no SELECT permission on <table cycling.cyclist_name> or any of its parents"
+    );
+    run(&mut alice, "SELECT * FROM system.local").unwrap();
+    run(&mut alice, "SELECT * FROM system_schema.keyspaces").unwrap();
+    // password hashes are only readable with a permission
+    assert_eq!(
+        unauthorized(run(&mut alice, "SELECT salted_hash FROM system_auth.roles")),
+        "User alice has no SELECT permission on <table system_auth.roles> or any of its parents"
+    );
+
+    exec!(admin, "GRANT SELECT ON KEYSPACE cycling TO alice");
+    run(&mut alice, "SELECT * FROM cycling.cyclist_name").unwrap();
+    assert_eq!(
+        unauthorized(run(
+            &mut alice,
+            "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS')"
+        )),
+        "User alice has no MODIFY permission on <table cycling.cyclist_name> or any of its parents"
+    );
+    assert_eq!(
+        unauthorized(run(&mut alice, "CREATE ROLE bob")),
+        "User alice does not have sufficient privileges to perform the requested operation"
+    );
+
+    exec!(
+        admin,
+        "GRANT ALL PERMISSIONS ON TABLE cycling.cyclist_name TO alice"
+    );
+    run(
+        &mut alice,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS')",
+    )
+    .unwrap();
+    let error = admin
+        .process(Query::simple("GRANT CREATE ON TABLE cycling.cyclist_name TO alice").unwrap())
+        .unwrap_err();
+    assert_eq!(error.error, DbError::Invalid);
+
+    // permissions on all keyspaces don't make system keyspaces writable
+    exec!(admin, "GRANT ALL PERMISSIONS ON ALL KEYSPACES TO alice");
+    run(&mut alice, "SELECT role FROM system_auth.roles").unwrap();
+    assert_eq!(
+        unauthorized(run(
+            &mut alice,
+            "UPDATE system_auth.roles SET is_superuser = true WHERE role = 'alice'"
+        )),
+        "system_auth keyspace is not user-modifiable."
+    );
+    assert_eq!(
+        unauthorized(run(
+            &mut alice,
+            "DELETE FROM system.local WHERE key = 'local'"
+        )),
+        "system keyspace is not user-modifiable."
+    );
+    exec!(admin, "REVOKE ALL PERMISSIONS ON ALL KEYSPACES FROM alice");
+
+    exec!(admin, "REVOKE SELECT ON KEYSPACE cycling FROM alice");
+    exec!(admin, "REVOKE SELECT ON cycling.cyclist_name FROM alice");
+    assert_eq!(
+        unauthorized(run(&mut alice, "SELECT * FROM cycling.cyclist_name")),
+        "User alice has no SELECT permission on <table cycling.cyclist_name> or any of its parents"
+    );
+    let QueryResult::Rows(permissions) = exec!(
+        admin,
+        "SELECT permissions FROM system_auth.role_permissions WHERE role = 'alice'"
+    ) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        permissions.rows[0].columns[0],
+        Some(CqlValue::Set(
+            ["ALTER", "AUTHORIZE", "DROP", "MODIFY"]
+                .map(|it| CqlValue::Text(it.into()))
+                .into()
+        ))
+    );
+
+    exec!(admin, "DROP ROLE alice");
+    assert_eq!(
+        unauthorized(run(&mut alice, "SELECT * FROM system.local")),
+        "Role alice doesn't exist"
+    );
+}
+
+#[test]
+fn skip_metadata_leaves_out_prepared_column_specs() {
+    let mut session = session();
+    exec!(
+        session,
+        "INSERT INTO cycling.cyclist_name (id, lastname) VALUES (1, 'VOS')"
+    );
+    let QueryResult::Prepared(prepared) = session
+        .prepare(Prepare::simple("SELECT * FROM cycling.cyclist_name").unwrap())
+        .unwrap()
+    else {
+        panic!("invalid return type");
+    };
+    let id = prepared.id.to_be_bytes();
+    let execute = |session: &mut KassandraSession, flags| {
+        let result = session
+            .execute(Execute {
+                id: &id,
+                parameters: QueryParameters {
+                    flags,
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        let mut buf = vec![];
+        result.serialize(&mut buf).unwrap();
+        // <kind><flags><columns_count>...
+        let flags = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+        let columns = u32::from_be_bytes(buf[8..12].try_into().unwrap());
+        (flags & 0x4 != 0, columns, buf.len())
+    };
+
+    let (no_metadata, columns, full) = execute(&mut session, QueryFlags::empty());
+    assert!(!no_metadata);
+    assert_eq!(columns, 4);
+    let (no_metadata, columns, skipped) = execute(&mut session, QueryFlags::SKIP_METADATA);
+    assert!(no_metadata);
+    assert_eq!(columns, 4);
+    assert!(skipped < full);
+
+    // specs clients prepared the statement with are outdated once the table changes
+    exec!(session, "DROP TABLE cycling.cyclist_name");
+    exec!(
+        session,
+        "CREATE TABLE cycling.cyclist_name (id int PRIMARY KEY, nickname text)"
+    );
+    let (no_metadata, columns, _) = execute(&mut session, QueryFlags::SKIP_METADATA);
+    assert!(!no_metadata);
+    assert_eq!(columns, 2);
+}
+
+#[test]
+fn cached_system_rows_follow_writes() {
+    let mut session = session();
+    let tables = |session: &mut KassandraSession| {
+        let QueryResult::Rows(rows) = exec!(
+            session,
+            "SELECT table_name FROM system_schema.tables WHERE keyspace_name = 'cycling'"
+        ) else {
+            panic!("invalid return type");
+        };
+        rows.rows.len()
+    };
+    let serialized = |result: QueryResult| {
+        let mut buf = vec![];
+        result.serialize(&mut buf).unwrap();
+        buf
+    };
+
+    let local = exec!(session, "SELECT release_version, tokens FROM system.local");
+    let cached = exec!(session, "SELECT release_version, tokens FROM system.local");
+    assert_eq!(serialized(local), serialized(cached));
+
+    assert_eq!(tables(&mut session), 1);
+    exec!(
+        session,
+        "CREATE TABLE cycling.cyclist_id (id int PRIMARY KEY, lastname text)"
+    );
+    assert_eq!(tables(&mut session), 2);
+
+    // isolated sessions change the same system tables
+    let mut isolated = session.isolated("other");
+    exec!(
+        isolated,
+        "UPDATE system.local SET release_version = '5.0.0' WHERE key = 'local'"
+    );
+    let QueryResult::Rows(rows) = exec!(session, "SELECT release_version FROM system.local") else {
+        panic!("invalid return type");
+    };
+    assert_eq!(
+        rows.rows[0].columns[0],
+        Some(CqlValue::Text("5.0.0".into()))
+    );
+}
+
+#[test]
+fn unknown_opcodes_get_protocol_errors_unless_registered() {
+    let mut session = session();
+    let other = |session: &mut KassandraSession| {
+        session.request(Request::Other {
+            opcode: 0x20,
+            body: b"ping",
+        })
+    };
+
+    let Response::Error(error) = other(&mut session) else {
+        panic!("invalid return type");
+    };
+    assert_eq!(error.error, DbError::ProtocolError);
+    assert_eq!(error.reason, "Unknown opcode 0x20");
+
+    session.register_opcode(0x20, |body: &[u8], context: &mut Context<'_>| {
+        assert_eq!(body, b"ping");
+        context.connection.use_keyspace("cycling");
+        Response::Ready
+    });
+    assert!(matches!(other(&mut session), Response::Ready));
+    assert_eq!(session.keyspace(), Some("cycling"));
+}